@@ -1,3 +1,5 @@
+#![allow(deprecated)]
+
 #[macro_use]
 extern crate criterion;
 
@@ -5,6 +7,8 @@ use criterion::{Benchmark, Criterion, Throughput};
 use snow::{params::*, *};
 
 const MSG_SIZE: usize = 4096;
+/// The largest Noise message (65535 bytes) less the AEAD tag.
+const MAX_PAYLOAD_SIZE: usize = 65535 - 16;
 
 fn benchmarks(c: &mut Criterion) {
     c.bench(
@@ -110,6 +114,36 @@ fn benchmarks(c: &mut Criterion) {
         );
     }
 
+    if cfg!(feature = "cipher-aegis") {
+        c.bench(
+            "transport",
+            Benchmark::new("AEGIS128L_SHA256 throughput", |b| {
+                static PATTERN: &str = "Noise_NN_25519_AEGIS128L_SHA256";
+
+                let mut h_i = Builder::new(PATTERN.parse().unwrap()).build_initiator().unwrap();
+                let mut h_r = Builder::new(PATTERN.parse().unwrap()).build_responder().unwrap();
+
+                let mut buffer_msg = [0u8; MSG_SIZE * 2];
+                let mut buffer_out = [0u8; MSG_SIZE * 2];
+
+                // get the handshaking out of the way for even testing
+                let len = h_i.write_message(&[0u8; 0], &mut buffer_msg).unwrap();
+                h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+                let len = h_r.write_message(&[0u8; 0], &mut buffer_msg).unwrap();
+                h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+                let mut h_i = h_i.into_transport_mode().unwrap();
+                let mut h_r = h_r.into_transport_mode().unwrap();
+
+                b.iter(move || {
+                    let len = h_i.write_message(&buffer_msg[..MSG_SIZE], &mut buffer_out).unwrap();
+                    let _ = h_r.read_message(&buffer_out[..len], &mut buffer_msg).unwrap();
+                })
+            })
+            .throughput(Throughput::Bytes(MSG_SIZE as u64 * 2)),
+        );
+    }
+
     c.bench(
         "transport",
         Benchmark::new("ChaChaPoly_BLAKE2s throughput", |b| {
@@ -137,6 +171,35 @@ fn benchmarks(c: &mut Criterion) {
         })
         .throughput(Throughput::Bytes(MSG_SIZE as u64 * 2)),
     );
+
+    c.bench(
+        "transport",
+        Benchmark::new("ChaChaPoly_BLAKE2s max-size throughput", |b| {
+            static PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+
+            let mut h_i = Builder::new(PATTERN.parse().unwrap()).build_initiator().unwrap();
+            let mut h_r = Builder::new(PATTERN.parse().unwrap()).build_responder().unwrap();
+
+            let mut buffer_msg = vec![0u8; MAX_PAYLOAD_SIZE + 16];
+            let mut buffer_out = vec![0u8; MAX_PAYLOAD_SIZE + 16];
+
+            // get the handshaking out of the way for even testing
+            let len = h_i.write_message(&[0u8; 0], &mut buffer_msg).unwrap();
+            h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+            let len = h_r.write_message(&[0u8; 0], &mut buffer_msg).unwrap();
+            h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+            let mut h_i = h_i.into_transport_mode().unwrap();
+            let mut h_r = h_r.into_transport_mode().unwrap();
+
+            b.iter(move || {
+                let len =
+                    h_i.write_message(&buffer_msg[..MAX_PAYLOAD_SIZE], &mut buffer_out).unwrap();
+                let _ = h_r.read_message(&buffer_out[..len], &mut buffer_msg).unwrap();
+            })
+        })
+        .throughput(Throughput::Bytes(MAX_PAYLOAD_SIZE as u64 * 2)),
+    );
 }
 
 criterion_group!(benches, benchmarks);
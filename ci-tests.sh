@@ -2,15 +2,23 @@
 set -e
 TARGET="$([ -n "$1" ] && echo "--target $1" || echo "")"
 
-COMMON_FEATURES="xchachapoly vector-tests"
+COMMON_FEATURES="xchachapoly vector-tests ffi rayon bytes argon2 armv8-resolver verified-resolver tracing x509 futures-io embedded hardware-resolver dh-secp256k1"
 
 set -x
 cargo check --benches
 cargo test $TARGET --no-default-features
+cargo test $TARGET --no-default-features --features "cipher-chachapoly hash-blake2 dh-25519"
+# FIPS mode rejects most protocol names, so only the unit tests apply.
+cargo test $TARGET --no-default-features --features "fips" --lib
 cargo test $TARGET --features "$COMMON_FEATURES"
+cargo test $TARGET --features "forbid-unsafe xchachapoly vector-tests rayon bytes argon2 verified-resolver futures-io embedded hardware-resolver"
 cargo test $TARGET --features "ring-resolver $COMMON_FEATURES"
 cargo test $TARGET --features "ring-accelerated $COMMON_FEATURES"
-cargo test $TARGET --features "hfs pqclean_kyber1024 $COMMON_FEATURES"
-cargo test $TARGET --features "ring-resolver hfs pqclean_kyber1024 $COMMON_FEATURES"
+cargo test $TARGET --features "hfs pqclean_kyber $COMMON_FEATURES"
+# ML-KEM needs OpenSSL 3.5 or later.
+cargo test $TARGET --features "mlkem $COMMON_FEATURES"
+cargo test $TARGET --features "disco $COMMON_FEATURES"
+cargo test $TARGET --features "mobile $COMMON_FEATURES"
+cargo test $TARGET --features "ring-resolver hfs pqclean_kyber $COMMON_FEATURES"
 cargo test $TARGET --features "libsodium-resolver $COMMON_FEATURES"
 cargo test $TARGET --features "libsodium-accelerated $COMMON_FEATURES"
@@ -0,0 +1,88 @@
+/*
+ * C interface to snow, built with the `ffi` feature:
+ *
+ *     cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * See the documentation of the `snow::ffi` module for the conventions shared by every function.
+ */
+
+#ifndef SCREECH_H
+#define SCREECH_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum ScreechError {
+    SCREECH_OK = 0,
+    SCREECH_ERROR_NULL_POINTER = 1,
+    SCREECH_ERROR_PATTERN = 2,
+    SCREECH_ERROR_INIT = 3,
+    SCREECH_ERROR_PREREQ = 4,
+    SCREECH_ERROR_STATE = 5,
+    SCREECH_ERROR_INPUT = 6,
+    SCREECH_ERROR_BUFFER_TOO_SMALL = 7,
+    SCREECH_ERROR_RNG = 8,
+    SCREECH_ERROR_DH = 9,
+    SCREECH_ERROR_DECRYPT = 10,
+    SCREECH_ERROR_TRUNCATED_MESSAGE = 11,
+    SCREECH_ERROR_SIG = 12,
+    SCREECH_ERROR_PROLOGUE = 13,
+    SCREECH_ERROR_KEM = 14,
+    SCREECH_ERROR_MESSAGE_TOO_LARGE = 15,
+    SCREECH_ERROR_PANIC = 16,
+} ScreechError;
+
+typedef struct ScreechBuilder ScreechBuilder;
+typedef struct ScreechHandshake ScreechHandshake;
+typedef struct ScreechTransport ScreechTransport;
+
+ScreechError screech_builder_new(const char *name, ScreechBuilder **out);
+void screech_builder_free(ScreechBuilder *builder);
+ScreechError screech_builder_local_private_key(ScreechBuilder *builder, const uint8_t *key,
+                                               size_t key_len);
+ScreechError screech_builder_remote_public_key(ScreechBuilder *builder, const uint8_t *key,
+                                               size_t key_len);
+ScreechError screech_builder_prologue(ScreechBuilder *builder, const uint8_t *prologue,
+                                      size_t prologue_len);
+ScreechError screech_builder_psk(ScreechBuilder *builder, uint8_t location, const uint8_t *key,
+                                 size_t key_len);
+ScreechError screech_builder_generate_keypair(const ScreechBuilder *builder, uint8_t *private_key,
+                                              size_t private_cap, size_t *private_len,
+                                              uint8_t *public_key, size_t public_cap,
+                                              size_t *public_len);
+ScreechError screech_builder_build_initiator(const ScreechBuilder *builder, ScreechHandshake **out);
+ScreechError screech_builder_build_responder(const ScreechBuilder *builder, ScreechHandshake **out);
+
+void screech_handshake_free(ScreechHandshake *handshake);
+ScreechError screech_handshake_write_message(ScreechHandshake *handshake, const uint8_t *payload,
+                                             size_t payload_len, uint8_t *message,
+                                             size_t message_cap, size_t *out_len);
+ScreechError screech_handshake_read_message(ScreechHandshake *handshake, const uint8_t *message,
+                                            size_t message_len, uint8_t *payload,
+                                            size_t payload_cap, size_t *out_len);
+int screech_handshake_is_finished(const ScreechHandshake *handshake);
+int screech_handshake_is_my_turn(const ScreechHandshake *handshake);
+ScreechError screech_handshake_get_remote_static(const ScreechHandshake *handshake, uint8_t *out,
+                                                 size_t out_cap, size_t *out_len);
+/* Consumes `handshake`, even on failure. */
+ScreechError screech_handshake_into_transport(ScreechHandshake *handshake, ScreechTransport **out);
+
+void screech_transport_free(ScreechTransport *transport);
+ScreechError screech_transport_write_message(ScreechTransport *transport, const uint8_t *payload,
+                                             size_t payload_len, uint8_t *message,
+                                             size_t message_cap, size_t *out_len);
+ScreechError screech_transport_read_message(ScreechTransport *transport, const uint8_t *message,
+                                            size_t message_len, uint8_t *payload,
+                                            size_t payload_cap, size_t *out_len);
+ScreechError screech_transport_rekey_outgoing(ScreechTransport *transport);
+ScreechError screech_transport_rekey_incoming(ScreechTransport *transport);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SCREECH_H */
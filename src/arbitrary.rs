@@ -0,0 +1,318 @@
+//! [`Arbitrary`] implementations, for property-testing code built on this crate against randomly
+//! chosen Noise configurations.
+//!
+//! [`NoiseParams`] are drawn from the protocols the default resolver runs with default features:
+//! any handshake pattern, with or without PSK modifiers, over Curve25519 and any cipher and hash.
+//! A [`Scenario`] adds the [`Keys`] both sides need and a [`Schedule`] of payloads to exchange,
+//! and [`Scenario::run()`] plays it through in memory.
+//!
+//! Requires the `arbitrary` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::arbitrary::Scenario;
+//! # use arbitrary::{Arbitrary, Unstructured};
+//! # #[cfg(feature = "default-resolver")]
+//! # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+//! // With a fuzzer or a property-testing crate, the bytes would be random.
+//! let bytes = [7u8; 256];
+//! let scenario = Scenario::arbitrary(&mut Unstructured::new(&bytes))?;
+//!
+//! // Whatever the configuration, every payload should arrive intact.
+//! let received = scenario.run()?;
+//! assert_eq!(received, scenario.expected());
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(feature = "default-resolver"))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::{MAXMSGLEN, PSKLEN, TAGLEN},
+    params::{
+        HandshakeChoice, HandshakeModifierList, HandshakeTokens, NoiseParams,
+        SUPPORTED_HANDSHAKE_PATTERNS,
+    },
+};
+#[cfg(feature = "default-resolver")]
+use crate::{error::Error, params::HandshakeModifier, Builder, HandshakeState};
+use ::arbitrary::{Arbitrary, Result, Unstructured};
+use std::convert::TryFrom;
+
+const CIPHERS: &[&str] = &["ChaChaPoly", "AESGCM"];
+const HASHES: &[&str] = &["SHA256", "SHA384", "SHA512", "BLAKE2s", "BLAKE2b"];
+
+/// The longest payload an arbitrary handshake message carries.
+const MAX_HANDSHAKE_PAYLOAD: usize = 1024;
+
+/// The longest payload an arbitrary transport message carries.
+const MAX_TRANSPORT_PAYLOAD: usize = MAXMSGLEN - TAGLEN;
+
+impl<'a> Arbitrary<'a> for NoiseParams {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let pattern = *u.choose(SUPPORTED_HANDSHAKE_PATTERNS)?;
+        let handshake =
+            HandshakeChoice { pattern, modifiers: HandshakeModifierList { list: vec![] } };
+        let messages = HandshakeTokens::try_from(&handshake)
+            .map_err(|_| ::arbitrary::Error::IncorrectFormat)?
+            .msg_patterns
+            .len();
+
+        // Each message, and the start of the first one, can take a PSK. Once the input runs out,
+        // `ratio()` is always true, so it's negated to leave out the PSKs.
+        let mut modifiers = String::new();
+        for location in 0..=messages {
+            if !u.ratio(3, 4)? {
+                if !modifiers.is_empty() {
+                    modifiers.push('+');
+                }
+                modifiers.push_str(&format!("psk{}", location));
+            }
+        }
+        let name = format!(
+            "Noise_{}{}_25519_{}_{}",
+            pattern.as_str(),
+            modifiers,
+            u.choose(CIPHERS)?,
+            u.choose(HASHES)?
+        );
+        name.parse().map_err(|_| ::arbitrary::Error::IncorrectFormat)
+    }
+}
+
+/// Everything both sides of a handshake need besides the protocol.
+///
+/// Any 32 bytes are a valid Curve25519 private key, so the keys are just random bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keys {
+    /// The initiator's static private key.
+    pub initiator_private: [u8; 32],
+    /// The responder's static private key.
+    pub responder_private: [u8; 32],
+    /// The PSK for every PSK modifier.
+    pub psk:               [u8; PSKLEN],
+    /// The prologue both sides use.
+    pub prologue:          Vec<u8>,
+}
+
+impl<'a> Arbitrary<'a> for Keys {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Keys {
+            initiator_private: u.arbitrary()?,
+            responder_private: u.arbitrary()?,
+            psk:               u.arbitrary()?,
+            prologue:          u.arbitrary()?,
+        })
+    }
+}
+
+#[cfg(feature = "default-resolver")]
+impl Keys {
+    /// Build the initiator of `params` with these keys, giving it the responder's static public
+    /// key if the pattern needs it.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Builder::build_initiator()`].
+    pub fn initiator(&self, params: &NoiseParams) -> std::result::Result<HandshakeState, Error> {
+        self.build(params, true)
+    }
+
+    /// Build the responder of `params` with these keys, giving it the initiator's static public
+    /// key if the pattern needs it.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Builder::build_responder()`].
+    pub fn responder(&self, params: &NoiseParams) -> std::result::Result<HandshakeState, Error> {
+        self.build(params, false)
+    }
+
+    fn build(
+        &self,
+        params: &NoiseParams,
+        initiator: bool,
+    ) -> std::result::Result<HandshakeState, Error> {
+        let (local, remote) = if initiator {
+            (&self.initiator_private, &self.responder_private)
+        } else {
+            (&self.responder_private, &self.initiator_private)
+        };
+        let remote_public = x25519_dalek::x25519(*remote, x25519_dalek::X25519_BASEPOINT_BYTES);
+        let pattern = params.handshake.pattern;
+        let mut builder = Builder::new(params.clone()).prologue(&self.prologue);
+        if pattern.needs_local_static_key(initiator) {
+            builder = builder.local_private_key(local);
+        }
+        if pattern.need_known_remote_pubkey(initiator) {
+            builder = builder.remote_public_key(&remote_public);
+        }
+        for modifier in &params.handshake.modifiers.list {
+            if let HandshakeModifier::Psk(location) = *modifier {
+                builder = builder.psk(location, &self.psk);
+            }
+        }
+        if initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        }
+    }
+}
+
+/// The payloads to exchange over a session.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Schedule {
+    /// The payload of each handshake message, in order. Messages past the end of the list carry
+    /// empty payloads, and payloads past the end of the handshake are left out.
+    pub handshake: Vec<Vec<u8>>,
+    /// The transport messages to send once the handshake is done.
+    pub transport: Vec<Transfer>,
+}
+
+/// One transport message in a [`Schedule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    /// Whether the initiator sends it, rather than the responder.
+    pub from_initiator: bool,
+    /// The plaintext.
+    pub payload:        Vec<u8>,
+}
+
+impl<'a> Arbitrary<'a> for Schedule {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut handshake = Vec::new();
+        u.arbitrary_loop(None, Some(8), |u| {
+            handshake.push(payload(u, MAX_HANDSHAKE_PAYLOAD)?);
+            Ok(std::ops::ControlFlow::Continue(()))
+        })?;
+        let mut transport = Vec::new();
+        u.arbitrary_loop(None, Some(16), |u| {
+            let from_initiator = u.arbitrary()?;
+            transport
+                .push(Transfer { from_initiator, payload: payload(u, MAX_TRANSPORT_PAYLOAD)? });
+            Ok(std::ops::ControlFlow::Continue(()))
+        })?;
+        Ok(Schedule { handshake, transport })
+    }
+}
+
+/// Up to `max` bytes, mostly short ones.
+fn payload(u: &mut Unstructured<'_>, max: usize) -> Result<Vec<u8>> {
+    let len = if u.ratio(1, 16)? { u.int_in_range(0..=max)? } else { u.int_in_range(0..=64)? };
+    Ok(u.bytes(len.min(u.len()))?.to_vec())
+}
+
+/// A protocol, keys for both sides and payloads to exchange over it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    /// The protocol.
+    pub params:   NoiseParams,
+    /// The keys of both sides.
+    pub keys:     Keys,
+    /// The payloads to exchange. For one-way patterns, only the initiator sends transport
+    /// messages.
+    pub schedule: Schedule,
+}
+
+impl<'a> Arbitrary<'a> for Scenario {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let params: NoiseParams = u.arbitrary()?;
+        let keys = u.arbitrary()?;
+        let mut schedule: Schedule = u.arbitrary()?;
+        if params.handshake.pattern.is_oneway() {
+            for transfer in &mut schedule.transport {
+                transfer.from_initiator = true;
+            }
+        }
+        Ok(Scenario { params, keys, schedule })
+    }
+}
+
+impl Scenario {
+    /// The payloads [`run()`](Self::run) should return: those of the handshake messages the
+    /// pattern has, then those of the transport messages.
+    pub fn expected(&self) -> Vec<Vec<u8>> {
+        let messages = HandshakeTokens::try_from(&self.params.handshake)
+            .map_or(0, |tokens| tokens.msg_patterns.len());
+        let handshake = (0..messages).map(|i| self.handshake_payload(i).to_vec());
+        handshake.chain(self.schedule.transport.iter().map(|t| t.payload.clone())).collect()
+    }
+
+    /// Run the handshake and then the transport messages between two sessions in memory,
+    /// returning every payload as the receiving side decrypted it.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the first error from building either side or from a message.
+    #[cfg(feature = "default-resolver")]
+    pub fn run(&self) -> std::result::Result<Vec<Vec<u8>>, Error> {
+        let mut initiator = self.keys.initiator(&self.params)?;
+        let mut responder = self.keys.responder(&self.params)?;
+        let mut message = vec![0u8; MAXMSGLEN];
+        let mut payload = vec![0u8; MAXMSGLEN];
+        let mut received = Vec::new();
+
+        while !(initiator.is_handshake_finished() && responder.is_handshake_finished()) {
+            let (writer, reader) = if initiator.is_my_turn() {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            let len = writer.write_message(self.handshake_payload(received.len()), &mut message)?;
+            let len = reader.read_message(&message[..len], &mut payload)?;
+            received.push(payload[..len].to_vec());
+        }
+
+        let mut initiator = initiator.into_transport_mode()?;
+        let mut responder = responder.into_transport_mode()?;
+        for transfer in &self.schedule.transport {
+            let (writer, reader) = if transfer.from_initiator {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            let len = writer.write_message(&transfer.payload, &mut message)?;
+            let len = reader.read_message(&message[..len], &mut payload)?;
+            received.push(payload[..len].to_vec());
+        }
+        Ok(received)
+    }
+
+    fn handshake_payload(&self, index: usize) -> &[u8] {
+        self.schedule.handshake.get(index).map_or(&[], |payload| &payload[..])
+    }
+}
+
+#[cfg(all(test, feature = "default-resolver"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitrary_scenarios_run() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut psks = 0;
+        for _ in 0..200 {
+            let bytes: Vec<u8> = (0..2048)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    seed as u8
+                })
+                .collect();
+            let scenario = Scenario::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            psks += usize::from(scenario.params.handshake.is_psk());
+            assert_eq!(scenario.run().unwrap(), scenario.expected(), "{:?}", scenario.params);
+        }
+        assert!(psks > 0);
+    }
+
+    #[test]
+    fn test_params_from_empty_input() {
+        let params = NoiseParams::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert_eq!(params.name, "Noise_N_25519_ChaChaPoly_SHA256");
+    }
+}
@@ -0,0 +1,11 @@
+//! Generates Kotlin and Swift bindings for the `mobile` feature, e.g.:
+//!
+//! ```sh
+//! cargo rustc --release --features mobile --crate-type cdylib
+//! cargo run --features mobile --bin uniffi-bindgen -- generate \
+//!     --library target/release/libsnow.so --language kotlin --out-dir out
+//! ```
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
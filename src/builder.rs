@@ -1,19 +1,51 @@
+#[cfg(any(feature = "disco", feature = "legacy-psk"))]
+use crate::params::BaseChoice;
 #[cfg(feature = "hfs")]
 use crate::params::HandshakeModifier;
 use crate::{
     cipherstate::{CipherState, CipherStates},
-    constants::{MAXDHLEN, PSKLEN},
-    error::{Error, InitStage, Prerequisite},
-    handshakestate::HandshakeState,
-    params::NoiseParams,
+    constants::{MAXDHLEN, MAXMSGLEN, MAXPROTOCOLNAMELEN, MAXSTATICLEN, PSKLEN},
+    dh_cache::StaticDhCache,
+    ephemeral::EphemeralPool,
+    error::{Error, InitStage, PatternProblem, Prerequisite},
+    handshakestate::{CorePrimitives, EphemeralSource, HandshakeState, HandshakeStateCore},
+    key_usage::KeyUsageTracker,
+    observer::SessionObserver,
+    padding::PaddingPolicy,
+    params::{HandshakeTokens, NoiseParams, Token},
+    pinning::{PinPolicy, PinStore, Pinning},
     resolvers::{BoxedCryptoResolver, CryptoResolver},
+    selector::StaticKeySelector,
+    symmetricstate::SymmetricState,
+    transcript::Transcript,
+    transportstate::TransportState,
+    types::{Cipher, Dh, Hash, Random},
+    typestate::{Handshake, Reading, Writing},
     utils::Toggle,
 };
+#[cfg(feature = "argon2")]
+use crate::{
+    constants::TAGLEN,
+    psk::{derive_psk, PassphraseCost},
+};
+use std::{convert::TryFrom, fmt, sync::Arc};
 use subtle::ConstantTimeEq;
+#[cfg(feature = "argon2")]
+use zeroize::Zeroizing;
+
+/// Version byte leading a sealed session.
+#[cfg(feature = "argon2")]
+const SEALED_VERSION: u8 = 1;
+
+/// Length of a sealed session's header: the version, the Argon2id cost and the salt.
+#[cfg(feature = "argon2")]
+const SEALED_HEADER_LEN: usize = 1 + 3 * 4 + 16;
 
 /// A keypair object returned by [`Builder::generate_keypair()`]
 ///
 /// [`generate_keypair()`]: #method.generate_keypair
+#[derive(Clone)]
+#[cfg_attr(feature = "mobile", derive(uniffi::Record))]
 pub struct Keypair {
     /// The private asymmetric key
     pub private: Vec<u8>,
@@ -48,29 +80,48 @@ impl PartialEq for Keypair {
 ///     .unwrap();
 /// ```
 pub struct Builder<'builder> {
-    params:   NoiseParams,
-    resolver: BoxedCryptoResolver,
-    s:        Option<&'builder [u8]>,
-    e_fixed:  Option<&'builder [u8]>,
-    rs:       Option<&'builder [u8]>,
-    psks:     [Option<&'builder [u8]>; 10],
-    plog:     Option<&'builder [u8]>,
+    params:     NoiseParams,
+    resolver:   BoxedCryptoResolver,
+    s:          Option<&'builder [u8]>,
+    s_selector: Option<Arc<dyn StaticKeySelector>>,
+    e_fixed:    Option<&'builder [u8]>,
+    e_given:    Option<&'builder Keypair>,
+    e_pool:     Option<&'builder EphemeralPool>,
+    ss:         Option<&'builder [u8]>,
+    ss_cache:   Option<Arc<StaticDhCache>>,
+    key_usage:  Option<Arc<KeyUsageTracker>>,
+    rs:         Option<&'builder [u8]>,
+    psks:       Vec<(u8, &'builder [u8])>,
+    plog:       Option<&'builder [u8]>,
+    padding:    Option<PaddingPolicy>,
+    rng:        Option<Box<dyn Random>>,
+    observer:   Option<Arc<dyn SessionObserver>>,
+    pinning:    Option<Pinning>,
+    max_len:    Option<usize>,
+    transcript: bool,
 }
 
 impl<'builder> Builder<'builder> {
     /// Create a Builder with the default crypto resolver.
     #[cfg(all(
-        feature = "default-resolver",
-        not(any(feature = "ring-accelerated", feature = "libsodium-accelerated"))
+        feature = "default-resolver-core",
+        not(any(
+            feature = "ring-accelerated",
+            feature = "libsodium-accelerated",
+            feature = "fips"
+        ))
     ))]
     pub fn new(params: NoiseParams) -> Self {
         use crate::resolvers::DefaultResolver;
 
-        Self::with_resolver(params, Box::new(DefaultResolver::default()))
+        Self::with_resolver(params, Box::new(DefaultResolver))
     }
 
     /// Create a Builder with the ring resolver and default resolver as a fallback.
-    #[cfg(all(not(feature = "libsodium-accelerated"), feature = "ring-accelerated"))]
+    #[cfg(all(
+        not(any(feature = "libsodium-accelerated", feature = "fips")),
+        feature = "ring-accelerated"
+    ))]
     pub fn new(params: NoiseParams) -> Self {
         use crate::resolvers::{DefaultResolver, FallbackResolver, RingResolver};
 
@@ -81,7 +132,10 @@ impl<'builder> Builder<'builder> {
     }
 
     /// Create a Builder with the ring resolver and default resolver as a fallback.
-    #[cfg(all(not(feature = "ring-accelerated"), feature = "libsodium-accelerated"))]
+    #[cfg(all(
+        not(any(feature = "ring-accelerated", feature = "fips")),
+        feature = "libsodium-accelerated"
+    ))]
     pub fn new(params: NoiseParams) -> Self {
         use crate::resolvers::{DefaultResolver, FallbackResolver, SodiumResolver};
 
@@ -91,14 +145,50 @@ impl<'builder> Builder<'builder> {
         )
     }
 
+    /// Create a Builder with the FIPS resolver, and nothing to fall back to.
+    #[cfg(feature = "fips")]
+    pub fn new(params: NoiseParams) -> Self {
+        use crate::resolvers::FipsResolver;
+
+        Self::with_resolver(params, Box::new(FipsResolver::default()))
+    }
+
     /// Create a Builder with a custom crypto resolver.
     pub fn with_resolver(params: NoiseParams, resolver: BoxedCryptoResolver) -> Self {
-        Builder { params, resolver, s: None, e_fixed: None, rs: None, plog: None, psks: [None; 10] }
+        Builder {
+            params,
+            resolver,
+            s: None,
+            s_selector: None,
+            e_fixed: None,
+            e_given: None,
+            e_pool: None,
+            ss: None,
+            ss_cache: None,
+            key_usage: None,
+            rs: None,
+            plog: None,
+            psks: vec![],
+            padding: None,
+            rng: None,
+            observer: None,
+            pinning: None,
+            max_len: None,
+            transcript: false,
+        }
     }
 
-    /// Specify a PSK (only used with `NoisePSK` base parameter)
+    /// Specify the PSK mixed in by the pattern's `pskN` modifier at `location`.
+    ///
+    /// Building fails with `InitStage::ValidatePskPosition` if the pattern has no such modifier.
+    /// The legacy `NoisePSK` base takes its PSK at location 0.
+    ///
+    /// Either every PSK the pattern uses is given here, or none of them are and they're set with
+    /// [`HandshakeState::set_psk()`] before the handshake reaches them; building with only some
+    /// of them fails with `Error::PatternPrereq`.
     pub fn psk(mut self, location: u8, key: &'builder [u8]) -> Self {
-        self.psks[location as usize] = Some(key);
+        self.psks.retain(|(l, _)| *l != location);
+        self.psks.push((location, key));
         self
     }
 
@@ -110,32 +200,173 @@ impl<'builder> Builder<'builder> {
         self
     }
 
+    /// Pick the responder's static key once the first message has been read, from its payload
+    /// and the prologue, instead of giving it up front (see the [`selector`] module).
+    ///
+    /// Only used if no [`local_private_key()`](Self::local_private_key) is given. Building fails
+    /// with `Prerequisite::LocalPrivateKey` if the key is needed before the first message is
+    /// read, i.e. for initiators and for patterns where the initiator knows the responder's key
+    /// beforehand, and for the `sig` modifier.
+    ///
+    /// [`selector`]: crate::selector
+    pub fn local_key_selector(mut self, selector: Arc<dyn StaticKeySelector>) -> Self {
+        self.s_selector = Some(selector);
+        self
+    }
+
     #[doc(hidden)]
     pub fn fixed_ephemeral_key_for_testing_only(mut self, key: &'builder [u8]) -> Self {
         self.e_fixed = Some(key);
         self
     }
 
+    /// Use `keypair` as the ephemeral key, for protocols that derive their ephemeral keys
+    /// deterministically (e.g. from a ratchet or a VRF) instead of generating them randomly.
+    ///
+    /// The keypair is only used for this handshake: after a [`reset()`], a fresh ephemeral key is
+    /// generated, since reusing one would break the handshake's security. Building fails with
+    /// `InitStage::ValidateEphemeralKeypair` if the public key doesn't belong to the private key.
+    ///
+    /// [`reset()`]: crate::HandshakeState::reset
+    pub fn fixed_ephemeral(mut self, keypair: &'builder Keypair) -> Self {
+        self.e_given = Some(keypair);
+        self
+    }
+
+    /// Draw the ephemeral key from a pool of pre-generated keys, if one is ready (see
+    /// [`EphemeralPool`]).
+    pub fn ephemeral_pool(mut self, pool: &'builder EphemeralPool) -> Self {
+        self.e_pool = Some(pool);
+        self
+    }
+
+    /// The result of the `ss` DH between the local static key and the remote static key given to
+    /// [`remote_public_key()`], computed ahead of time.
+    ///
+    /// If it doesn't match the keys, the handshake will fail.
+    ///
+    /// [`remote_public_key()`]: #method.remote_public_key
+    pub fn precomputed_ss(mut self, ss: &'builder [u8]) -> Self {
+        self.ss = Some(ss);
+        self
+    }
+
+    /// Look up (and store) the result of the `ss` DH in a cache shared between handshakes (see
+    /// [`StaticDhCache`]).
+    pub fn static_dh_cache(mut self, cache: Arc<StaticDhCache>) -> Self {
+        self.ss_cache = Some(cache);
+        self
+    }
+
+    /// Record the role the local static key is built for in a tracker shared between builders,
+    /// failing with `StateProblem::KeyUsedInBothRoles` if it was already built for the other
+    /// role of the same protocol (see the [`key_usage`] module).
+    ///
+    /// [`key_usage`]: crate::key_usage
+    pub fn key_usage_tracker(mut self, tracker: Arc<KeyUsageTracker>) -> Self {
+        self.key_usage = Some(tracker);
+        self
+    }
+
     /// Arbitrary data to be hashed in to the handshake hash value.
     pub fn prologue(mut self, key: &'builder [u8]) -> Self {
         self.plog = Some(key);
         self
     }
 
+    /// The prologue set with [`prologue()`](Self::prologue), if any.
+    pub(crate) fn get_prologue(&self) -> Option<&'builder [u8]> {
+        self.plog
+    }
+
     /// The responder's static public key.
     pub fn remote_public_key(mut self, pub_key: &'builder [u8]) -> Self {
         self.rs = Some(pub_key);
         self
     }
 
+    /// Length-hiding padding to apply to transport messages (see [`PaddingPolicy`]).
+    ///
+    /// Both sides must enable padding for the session to interoperate, since it changes
+    /// the framing of every transport payload. Sizes larger than a Noise message are capped
+    /// at the largest plaintext that fits in one.
+    ///
+    /// [`PaddingPolicy`]: crate::padding::PaddingPolicy
+    pub fn padding(mut self, policy: PaddingPolicy) -> Self {
+        self.padding = Some(policy.clamped());
+        self
+    }
+
+    /// The RNG to use for this session's ephemeral keys and padding, instead of the one from
+    /// the resolver.
+    ///
+    /// A seeded CSPRNG makes whole handshakes reproducible, for generating test vectors or
+    /// comparing against other implementations. Don't use a predictable RNG in production.
+    pub fn rng(mut self, rng: Box<dyn Random>) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    /// An observer to report this session's events to, e.g. for metrics (see
+    /// [`SessionObserver`]). It's carried over into transport mode.
+    ///
+    /// [`SessionObserver`]: crate::observer::SessionObserver
+    pub fn observer(mut self, observer: Arc<dyn SessionObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// The longest message, handshake or transport, that this side will read, for receivers
+    /// that can't afford buffers for the protocol's 65535-byte maximum.
+    ///
+    /// Longer messages are rejected with `Error::MessageTooLarge` before any decryption or
+    /// allocation. Messages written by this side aren't limited; the peer has to be told to
+    /// keep its messages short some other way.
+    pub fn max_message_len(mut self, len: usize) -> Self {
+        self.max_len = Some(len.min(MAXMSGLEN));
+        self
+    }
+
+    /// Keep a [`Transcript`] of the handshake's messages, tokens and hashes, for audits and for
+    /// debugging interop failures (see the [`transcript`] module).
+    ///
+    /// [`Transcript`]: crate::transcript::Transcript
+    /// [`transcript`]: crate::transcript
+    pub fn record_transcript(mut self) -> Self {
+        self.transcript = true;
+        self
+    }
+
+    /// Check the remote static key the peer sends against the one pinned for `peer` in
+    /// `store`, following `policy` if none is pinned yet (see the [`pinning`] module).
+    ///
+    /// [`pinning`]: crate::pinning
+    pub fn pinning(mut self, store: Arc<dyn PinStore>, peer: &str, policy: PinPolicy) -> Self {
+        self.pinning = Some(Pinning { store, peer: peer.to_owned(), policy });
+        self
+    }
+
     // TODO: performance issue w/ creating a new RNG and DH instance per call.
     /// Generate a new asymmetric keypair (for use as a static key).
+    ///
+    /// With the `sig` modifier, this is a signing keypair for the chosen signature algorithm.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Rng` if the RNG fails to provide entropy.
     pub fn generate_keypair(&self) -> Result<Keypair, Error> {
         let mut rng = self.resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
+        if let Some(sig) = &self.params.sig {
+            let mut signer = self.resolver.resolve_sig(sig).ok_or(InitStage::GetSigImpl)?;
+            signer.generate(&mut *rng).map_err(|_| Error::Rng)?;
+            let private = signer.privkey().to_vec();
+            let public = signer.pubkey().to_vec();
+            return Ok(Keypair { private, public });
+        }
         let mut dh = self.resolver.resolve_dh(&self.params.dh).ok_or(InitStage::GetDhImpl)?;
         let mut private = vec![0u8; dh.priv_len()];
         let mut public = vec![0u8; dh.pub_len()];
-        dh.generate(&mut *rng);
+        dh.generate(&mut *rng).map_err(|_| Error::Rng)?;
 
         private.copy_from_slice(dh.privkey());
         public.copy_from_slice(dh.pubkey());
@@ -153,43 +384,336 @@ impl<'builder> Builder<'builder> {
         self.build(false)
     }
 
-    fn build(self, initiator: bool) -> Result<HandshakeState, Error> {
-        if self.s.is_none() && self.params.handshake.pattern.needs_local_static_key(initiator) {
-            bail!(Prerequisite::LocalPrivateKey);
-        }
+    /// Build a [`Handshake`] that tracks its turn in the type, for the side who will initiate
+    /// the handshake. See the [`typestate`](crate::typestate) module.
+    pub fn build_typed_initiator(self) -> Result<Handshake<Writing>, Error> {
+        self.build(true).map(Handshake::new)
+    }
+
+    /// Build a [`Handshake`] that tracks its turn in the type, for the side who will respond.
+    /// See the [`typestate`](crate::typestate) module.
+    pub fn build_typed_responder(self) -> Result<Handshake<Reading>, Error> {
+        self.build(false).map(Handshake::new)
+    }
 
-        if self.rs.is_none() && self.params.handshake.pattern.need_known_remote_pubkey(initiator) {
-            bail!(Prerequisite::RemotePublicKey);
+    /// Pick up a session exported with
+    /// [`TransportState::export_session()`](crate::TransportState::export_session).
+    ///
+    /// The `Builder` has to be for the same protocol the session was exported from. Its
+    /// padding policy, RNG and observer are used for the imported session; keys, PSKs and the
+    /// prologue are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the export is malformed or from a different protocol.
+    ///
+    /// Will result in `Error::State(StateProblem::UnexportableSession)` for Disco protocols.
+    pub fn import_session(mut self, exported: &[u8]) -> Result<TransportState, Error> {
+        #[cfg(feature = "disco")]
+        if self.params.base == BaseChoice::NoiseDisco {
+            bail!(crate::error::StateProblem::UnexportableSession);
         }
 
-        let rng = self.resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
-        let cipher =
+        let rng = match self.rng.take() {
+            Some(rng) => rng,
+            None => self.resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?,
+        };
+        let initiator_cipher =
             self.resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?;
-        let hash = self.resolver.resolve_hash(&self.params.hash).ok_or(InitStage::GetHashImpl)?;
-        let mut s_dh = self.resolver.resolve_dh(&self.params.dh).ok_or(InitStage::GetDhImpl)?;
-        let mut e_dh = self.resolver.resolve_dh(&self.params.dh).ok_or(InitStage::GetDhImpl)?;
-        let cipher1 =
+        let responder_cipher =
             self.resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?;
-        let cipher2 =
+        TransportState::import(
+            self.params,
+            (initiator_cipher, responder_cipher),
+            rng,
+            self.padding,
+            self.observer,
+            self.max_len,
+            exported,
+        )
+    }
+
+    /// Export `transport` like [`TransportState::export_session()`](crate::TransportState::export_session),
+    /// encrypted under a key derived from `passphrase`, for persisting sessions at rest.
+    ///
+    /// The key is stretched from the passphrase with Argon2id at the given `cost`, under a
+    /// fresh random salt; both are stored in the sealed session, so only the passphrase (and
+    /// a cost at least as high) is needed to [`unseal_session()`](Self::unseal_session) it.
+    /// The export is encrypted with
+    /// this `Builder`'s cipher, and the protocol name is authenticated along with it.
+    ///
+    /// Requires the `argon2` feature.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the `Builder` is for a different protocol than the
+    /// session, or the cost is outside of what Argon2 allows.
+    ///
+    /// Will result in `Error::State(StateProblem::UnexportableSession)` for sessions
+    /// [`TransportState::export_session()`] can't export.
+    #[cfg(feature = "argon2")]
+    pub fn seal_session(
+        &self,
+        transport: &TransportState,
+        passphrase: &[u8],
+        cost: PassphraseCost,
+    ) -> Result<Vec<u8>, Error> {
+        if transport.params.name != self.params.name {
+            bail!(Error::Input);
+        }
+        let exported = Zeroizing::new(transport.export_session()?);
+
+        let mut rng = self.resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
+        let mut header = [0u8; SEALED_HEADER_LEN];
+        header[0] = SEALED_VERSION;
+        header[1..5].copy_from_slice(&cost.memory_kib.to_be_bytes());
+        header[5..9].copy_from_slice(&cost.iterations.to_be_bytes());
+        header[9..13].copy_from_slice(&cost.parallelism.to_be_bytes());
+        rng.try_fill_bytes(&mut header[13..]).map_err(|_| Error::Rng)?;
+
+        let cipher = self.sealing_cipher(passphrase, &header, cost)?;
+        let mut sealed = vec![0u8; SEALED_HEADER_LEN + exported.len() + TAGLEN];
+        sealed[..SEALED_HEADER_LEN].copy_from_slice(&header);
+        let authtext = [&header[..], self.params.name.as_bytes()].concat();
+        cipher.encrypt(0, &authtext, &exported, &mut sealed[SEALED_HEADER_LEN..]);
+        Ok(sealed)
+    }
+
+    /// Pick up a session sealed with [`seal_session()`](Self::seal_session), like
+    /// [`import_session()`](Self::import_session) does for a plain export.
+    ///
+    /// The Argon2id cost is read from the sealed session, which anyone who can write to it
+    /// controls, so it's only spent if none of its parameters exceed `max_cost`'s. Pass the
+    /// cost the session was sealed with, or the most this process can afford.
+    ///
+    /// Requires the `argon2` feature.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Decrypt` if the passphrase is wrong, the sealed session was
+    /// tampered with, or it was sealed for a different protocol.
+    ///
+    /// Will result in `Error::Input` if the sealed session is malformed, or its cost exceeds
+    /// `max_cost`.
+    #[cfg(feature = "argon2")]
+    pub fn unseal_session(
+        self,
+        sealed: &[u8],
+        passphrase: &[u8],
+        max_cost: PassphraseCost,
+    ) -> Result<TransportState, Error> {
+        if sealed.len() < SEALED_HEADER_LEN + TAGLEN || sealed[0] != SEALED_VERSION {
+            bail!(Error::Input);
+        }
+        let (header, ciphertext) = sealed.split_at(SEALED_HEADER_LEN);
+        let field = |i: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&header[i..i + 4]);
+            u32::from_be_bytes(bytes)
+        };
+        let cost =
+            PassphraseCost { memory_kib: field(1), iterations: field(5), parallelism: field(9) };
+        if cost.memory_kib > max_cost.memory_kib
+            || cost.iterations > max_cost.iterations
+            || cost.parallelism > max_cost.parallelism
+        {
+            bail!(Error::Input);
+        }
+        let cipher = self.sealing_cipher(passphrase, header, cost)?;
+        let mut exported = Zeroizing::new(vec![0u8; ciphertext.len() - TAGLEN]);
+        let authtext = [header, self.params.name.as_bytes()].concat();
+        cipher.decrypt(0, &authtext, ciphertext, &mut exported).map_err(|_| Error::Decrypt)?;
+        self.import_session(&exported)
+    }
+
+    /// Key a cipher from `passphrase` with `cost` and the salt in a sealed session's `header`.
+    #[cfg(feature = "argon2")]
+    fn sealing_cipher(
+        &self,
+        passphrase: &[u8],
+        header: &[u8],
+        cost: PassphraseCost,
+    ) -> Result<Box<dyn Cipher>, Error> {
+        let key = Zeroizing::new(derive_psk(passphrase, &header[13..], cost)?);
+        let mut cipher =
             self.resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?;
-        let handshake_cipherstate = CipherState::new(cipher);
-        let cipherstates = CipherStates::new(CipherState::new(cipher1), CipherState::new(cipher2))?;
+        cipher.set(&key[..]);
+        Ok(cipher)
+    }
 
-        let s = match self.s {
-            Some(k) => {
-                (&mut *s_dh).set(k);
-                Toggle::on(s_dh)
-            },
-            None => Toggle::off(s_dh),
+    /// Build a [`HandshakeStateCore`] for the initiator over the given primitive instances,
+    /// rather than the boxed ones the resolver would hand out. The resolver still provides the
+    /// RNG, and the signature and KEM primitives for the `sig` and `hfs` modifiers.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`build_initiator()`](Self::build_initiator), and with `Error::Input` if a
+    /// primitive doesn't match the one named in the params, or
+    /// `Error::Pattern(PatternProblem::UnsupportedBaseType)` for Disco, which brings its own.
+    pub fn build_core_initiator<D: Dh, C: Cipher, H: Hash>(
+        self,
+        primitives: CorePrimitives<D, C, H>,
+    ) -> Result<HandshakeStateCore<D, C, H>, Error> {
+        self.build_core(true, primitives)
+    }
+
+    /// Build a [`HandshakeStateCore`] for the responder over the given primitive instances. See
+    /// [`build_core_initiator()`](Self::build_core_initiator).
+    ///
+    /// # Errors
+    ///
+    /// Same as `build_core_initiator()`.
+    pub fn build_core_responder<D: Dh, C: Cipher, H: Hash>(
+        self,
+        primitives: CorePrimitives<D, C, H>,
+    ) -> Result<HandshakeStateCore<D, C, H>, Error> {
+        self.build_core(false, primitives)
+    }
+
+    fn build_core<D: Dh, C: Cipher, H: Hash>(
+        self,
+        initiator: bool,
+        primitives: CorePrimitives<D, C, H>,
+    ) -> Result<HandshakeStateCore<D, C, H>, Error> {
+        #[cfg(any(feature = "disco", feature = "legacy-psk"))]
+        if self.params.base != BaseChoice::Noise {
+            bail!(PatternProblem::UnsupportedBaseType);
+        }
+        let CorePrimitives { local_static, ephemeral, ciphers, hash } = primitives;
+        let dh_name = self.params.dh.to_string();
+        let cipher_name = self.params.cipher.to_string();
+        if local_static.name() != dh_name
+            || ephemeral.name() != dh_name
+            || ciphers.iter().any(|cipher| cipher.name() != cipher_name)
+            || hash.name() != self.params.hash.to_string()
+        {
+            bail!(Error::Input);
+        }
+
+        let [cipher, cipher1, cipher2] = ciphers;
+        self.build_with(initiator, move |_| {
+            let symmetricstate = SymmetricState::new(CipherState::new(cipher), hash);
+            let cipherstates =
+                CipherStates::new(CipherState::new(cipher1), CipherState::new(cipher2))?;
+            Ok(Parts { s: local_static, e: ephemeral, symmetricstate, cipherstates })
+        })
+    }
+
+    fn build(self, initiator: bool) -> Result<HandshakeState, Error> {
+        self.build_with(initiator, |builder| {
+            let s = builder.resolver.resolve_dh(&builder.params.dh).ok_or(InitStage::GetDhImpl)?;
+            let e = builder.resolver.resolve_dh(&builder.params.dh).ok_or(InitStage::GetDhImpl)?;
+            let (symmetricstate, cipherstates) = builder.resolve_symmetric()?;
+            Ok(Parts { s, e, symmetricstate, cipherstates })
+        })
+    }
+
+    /// Check the options and put a handshake together, with `parts` providing the primitives
+    /// once the RNG has been resolved.
+    fn build_with<D: Dh, C: Cipher, H: Hash>(
+        mut self,
+        initiator: bool,
+        parts: impl FnOnce(&Self) -> Result<Parts<D, C, H>, Error>,
+    ) -> Result<HandshakeStateCore<D, C, H>, Error> {
+        // Parsed names are already checked, but `NoiseParams::new()` takes any name.
+        if self.params.name.len() > MAXPROTOCOLNAMELEN {
+            bail!(PatternProblem::ProtocolNameTooLong);
+        }
+        // Check against the modified tokens rather than the base pattern, since modifiers
+        // like fallback move static keys around.
+        let tokens = HandshakeTokens::try_from(&self.params.handshake)?;
+        let needs_s = self.s.is_none() && tokens.needs_local_static(initiator);
+        // A responder can leave picking its static key until it has read the first message.
+        let selects_s = needs_s
+            && self.s_selector.is_some()
+            && !initiator
+            && self.params.sig.is_none()
+            && !tokens.needs_responder_static_upfront();
+        if needs_s && !selects_s {
+            bail!(Prerequisite::LocalPrivateKey.for_pattern(&self.params.handshake, initiator));
+        }
+
+        if self.rs.is_none() && tokens.needs_remote_static(initiator) {
+            bail!(Prerequisite::RemotePublicKey.for_pattern(&self.params.handshake, initiator));
+        }
+
+        let rng = match self.rng.take() {
+            Some(rng) => rng,
+            None => self.resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?,
         };
+        let Parts { s: mut s_dh, e: mut e_dh, symmetricstate, cipherstates } = parts(&self)?;
 
-        if let Some(fixed_k) = self.e_fixed {
-            (&mut *e_dh).set(fixed_k);
+        // With the sig modifier, the local private key is a signing key instead of a DH key.
+        let signer = match &self.params.sig {
+            Some(sig) => Some(self.resolver.resolve_sig(sig).ok_or(InitStage::GetSigImpl)?),
+            None => None,
+        };
+
+        let s_len = signer.as_ref().map_or(s_dh.priv_len(), |signer| signer.priv_len());
+        if self.s.is_some_and(|k| k.len() != s_len) {
+            bail!(InitStage::ValidateKeyLengths);
         }
+        let (s, signer) = match (self.s, signer) {
+            (Some(k), Some(mut signer)) => {
+                signer.set(k);
+                (Toggle::off(s_dh), Some(Toggle::on(signer)))
+            },
+            (None, Some(signer)) => (Toggle::off(s_dh), Some(Toggle::off(signer))),
+            (Some(k), None) => {
+                s_dh.set(k);
+                (Toggle::on(s_dh), None)
+            },
+            (None, None) => (Toggle::off(s_dh), None),
+        };
+        if let Some(tracker) = &self.key_usage {
+            let public_key = match (s.get(), &signer) {
+                (Some(s), _) => Some(s.pubkey()),
+                (None, Some(signer)) => signer.get().map(|signer| signer.pubkey()),
+                (None, None) => None,
+            };
+            if let Some(public_key) = public_key {
+                tracker.record(&self.params.name, public_key, initiator)?;
+            }
+        }
+
+        let e_source = match (self.e_fixed, self.e_given, self.e_pool) {
+            (Some(fixed_k), ..) => {
+                if fixed_k.len() != e_dh.priv_len() {
+                    bail!(InitStage::ValidateKeyLengths);
+                }
+                e_dh.set(fixed_k);
+                EphemeralSource::Fixed
+            },
+            (None, Some(keypair), _) => {
+                if keypair.private.len() != e_dh.priv_len() {
+                    bail!(InitStage::ValidateKeyLengths);
+                }
+                e_dh.set(&keypair.private);
+                if !bool::from(e_dh.pubkey().ct_eq(&keypair.public)) {
+                    bail!(InitStage::ValidateEphemeralKeypair);
+                }
+                EphemeralSource::Pregenerated
+            },
+            (None, None, Some(pool)) => {
+                if pool.dh() != self.params.dh {
+                    bail!(InitStage::ValidateEphemeralPool);
+                }
+                match pool.take() {
+                    Some(keypair) => {
+                        e_dh.set_keypair(&keypair.private, &keypair.public);
+                        EphemeralSource::Pregenerated
+                    },
+                    None => EphemeralSource::Generate,
+                }
+            },
+            (None, None, None) => EphemeralSource::Generate,
+        };
+        let shared_len = e_dh.shared_len();
         let e = Toggle::off(e_dh);
 
-        let mut rs_buf = [0u8; MAXDHLEN];
+        let mut rs_buf = [0u8; MAXSTATICLEN];
         let rs = match self.rs {
+            Some(v) if v.len() > MAXSTATICLEN => bail!(InitStage::ValidateKeyLengths),
             Some(v) => {
                 rs_buf[..v.len()].copy_from_slice(v);
                 Toggle::on(rs_buf)
@@ -199,47 +723,110 @@ impl<'builder> Builder<'builder> {
 
         let re = Toggle::off([0u8; MAXDHLEN]);
 
-        let mut psks = [None::<[u8; PSKLEN]>; 10];
-        for (i, psk) in self.psks.iter().enumerate() {
-            if let Some(key) = *psk {
-                if key.len() != PSKLEN {
-                    bail!(InitStage::ValidatePskLengths);
-                }
-                let mut k = [0u8; PSKLEN];
-                k.copy_from_slice(key);
-                psks[i] = Some(k);
+        let mut psks = Vec::with_capacity(self.psks.len());
+        for &(location, key) in &self.psks {
+            if !self.params.takes_psk(&tokens, location) {
+                bail!(InitStage::ValidatePskPosition);
+            }
+            if key.len() != PSKLEN {
+                bail!(InitStage::ValidatePskLengths);
+            }
+            let mut k = [0u8; PSKLEN];
+            k.copy_from_slice(key);
+            psks.push((location, k));
+        }
+
+        // PSKs can all be left for `set_psk()`, but giving only some of them is a mistake.
+        if !self.psks.is_empty() {
+            let missing = tokens.msg_patterns.iter().flatten().find_map(|token| match token {
+                Token::Psk(n) if !self.psks.iter().any(|(location, _)| location == n) => Some(*n),
+                _ => None,
+            });
+            if let Some(n) = missing {
+                bail!(Prerequisite::Psk(n).for_pattern(&self.params.handshake, initiator));
             }
         }
 
-        let mut hs = HandshakeState::new(
+        let precomputed_ss = match self.ss {
+            Some(ss) if ss.len() != shared_len => bail!(InitStage::ValidateKeyLengths),
+            Some(ss) => {
+                let mut buf = [0u8; MAXDHLEN];
+                buf[..ss.len()].copy_from_slice(ss);
+                Some(buf)
+            },
+            None => None,
+        };
+
+        let prologue = self.plog.unwrap_or(&[]);
+        let mut hs = HandshakeStateCore::new(
             rng,
-            handshake_cipherstate,
-            hash,
+            symmetricstate,
             s,
             e,
-            self.e_fixed.is_some(),
+            e_source,
+            signer,
             rs,
             re,
             initiator,
             self.params,
             psks,
-            self.plog.unwrap_or_else(|| &[0u8; 0]),
+            prologue,
             cipherstates,
+            self.padding,
         )?;
+        hs.precomputed_ss = precomputed_ss;
+        hs.ss_cache = self.ss_cache;
+        hs.key_usage = self.key_usage;
         Self::resolve_kem(self.resolver, &mut hs)?;
+        if let Some(observer) = &self.observer {
+            observer.handshake_started(&hs.params, hs.initiator);
+        }
+        hs.observer = self.observer;
+        hs.pinning = self.pinning;
+        if selects_s {
+            hs.s_selector = self.s_selector.map(|selector| (selector, prologue.to_vec()));
+        }
+        hs.max_message_len = self.max_len;
+        if self.transcript {
+            let initial_hash = hs.symmetricstate.handshake_hash();
+            hs.transcript = Some(Transcript::new(&hs.params, hs.initiator, initial_hash));
+        }
         Ok(hs)
     }
 
+    fn resolve_symmetric(&self) -> Result<(SymmetricState, CipherStates), Error> {
+        #[cfg(feature = "disco")]
+        if self.params.base == BaseChoice::NoiseDisco {
+            let cipherstates =
+                CipherStates::new(CipherState::new_strobe(), CipherState::new_strobe())?;
+            return Ok((SymmetricState::new_disco(), cipherstates));
+        }
+
+        let cipher =
+            self.resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?;
+        let hash = self.resolver.resolve_hash(&self.params.hash).ok_or(InitStage::GetHashImpl)?;
+        let cipher1 =
+            self.resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?;
+        let cipher2 =
+            self.resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?;
+        let symmetricstate = SymmetricState::new(CipherState::new(cipher), hash);
+        let cipherstates = CipherStates::new(CipherState::new(cipher1), CipherState::new(cipher2))?;
+        Ok((symmetricstate, cipherstates))
+    }
+
     #[cfg(not(feature = "hfs"))]
-    fn resolve_kem(_: Box<dyn CryptoResolver>, _: &mut HandshakeState) -> Result<(), Error> {
+    fn resolve_kem<D: Dh, C: Cipher, H: Hash>(
+        _: Box<dyn CryptoResolver>,
+        _: &mut HandshakeStateCore<D, C, H>,
+    ) -> Result<(), Error> {
         // HFS is disabled, return nothing
         Ok(())
     }
 
     #[cfg(feature = "hfs")]
-    fn resolve_kem(
+    fn resolve_kem<D: Dh, C: Cipher, H: Hash>(
         resolver: Box<dyn CryptoResolver>,
-        hs: &mut HandshakeState,
+        hs: &mut HandshakeStateCore<D, C, H>,
     ) -> Result<(), Error> {
         if hs.params.handshake.modifiers.list.contains(&HandshakeModifier::Hfs) {
             if let Some(kem_choice) = hs.params.kem {
@@ -253,6 +840,41 @@ impl<'builder> Builder<'builder> {
     }
 }
 
+/// The primitives a handshake is put together from, resolved or given.
+struct Parts<D, C, H> {
+    s:              D,
+    e:              D,
+    symmetricstate: SymmetricState<C, H>,
+    cipherstates:   CipherStates<C>,
+}
+
+/// Shows which options are set, but none of the keys.
+impl<'builder> fmt::Debug for Builder<'builder> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let psks: Vec<u8> = self.psks.iter().map(|(location, _)| *location).collect();
+        fmt.debug_struct("Builder")
+            .field("params", &self.params.name)
+            .field("s", &self.s.is_some())
+            .field("s_selector", &self.s_selector.is_some())
+            .field("e_fixed", &self.e_fixed.is_some())
+            .field("e_given", &self.e_given.is_some())
+            .field("e_pool", &self.e_pool.is_some())
+            .field("ss", &self.ss.is_some())
+            .field("ss_cache", &self.ss_cache.is_some())
+            .field("key_usage", &self.key_usage.is_some())
+            .field("rs", &self.rs.is_some())
+            .field("psks", &psks)
+            .field("prologue", &self.plog.is_some())
+            .field("padding", &self.padding)
+            .field("rng", &self.rng.is_some())
+            .field("observer", &self.observer.is_some())
+            .field("pinning", &self.pinning)
+            .field("max_message_len", &self.max_len)
+            .field("transcript", &self.transcript)
+            .finish_non_exhaustive()
+    }
+}
+
 #[cfg(test)]
 #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
 mod tests {
@@ -285,6 +907,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_builder_ephemeral_pool() {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+        let pool = EphemeralPool::new(params.dh, 4).unwrap();
+        let mut ephemerals = vec![];
+        for _ in 0..8 {
+            let mut h_i =
+                Builder::new(params.clone()).ephemeral_pool(&pool).build_initiator().unwrap();
+            let mut h_r =
+                Builder::new(params.clone()).ephemeral_pool(&pool).build_responder().unwrap();
+            let (mut msg, mut buf) = ([0u8; 128], [0u8; 128]);
+            let len = h_i.write_message(&[], &mut msg).unwrap();
+            ephemerals.push(msg[..32].to_vec());
+            h_r.read_message(&msg[..len], &mut buf).unwrap();
+            let len = h_r.write_message(&[], &mut msg).unwrap();
+            ephemerals.push(msg[..32].to_vec());
+            h_i.read_message(&msg[..len], &mut buf).unwrap();
+            assert!(h_i.into_transport_mode().is_ok());
+        }
+        ephemerals.sort();
+        ephemerals.dedup();
+        assert_eq!(ephemerals.len(), 16);
+    }
+
+    #[test]
+    fn test_builder_fixed_ephemeral() {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+        let keypair = Builder::new(params.clone()).generate_keypair().unwrap();
+        let mut h_i =
+            Builder::new(params.clone()).fixed_ephemeral(&keypair).build_initiator().unwrap();
+        let mut msg = [0u8; 128];
+        h_i.write_message(&[], &mut msg).unwrap();
+        assert_eq!(&msg[..32], &keypair.public[..]);
+
+        // The keypair is used for one handshake only.
+        h_i.reset(true, &[], None).unwrap();
+        h_i.write_message(&[], &mut msg).unwrap();
+        assert_ne!(&msg[..32], &keypair.public[..]);
+
+        let other = Builder::new(params.clone()).generate_keypair().unwrap();
+        let mismatched = Keypair { private: keypair.private.clone(), public: other.public };
+        assert!(matches!(
+            Builder::new(params.clone()).fixed_ephemeral(&mismatched).build_initiator(),
+            Err(Error::Init(InitStage::ValidateEphemeralKeypair))
+        ));
+        let short = Keypair { private: vec![1; 16], public: keypair.public.clone() };
+        assert!(matches!(
+            Builder::new(params).fixed_ephemeral(&short).build_initiator(),
+            Err(Error::Init(InitStage::ValidateKeyLengths))
+        ));
+    }
+
     #[test]
     fn test_builder_missing_prereqs() {
         let noise = Builder::new("Noise_NK_25519_ChaChaPoly_SHA256".parse().unwrap())
@@ -297,6 +971,78 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_builder_precise_prereqs() {
+        let ik = "Noise_IK_25519_ChaChaPoly_SHA256";
+        let err = Builder::new(ik.parse().unwrap())
+            .local_private_key(&[0u8; 32])
+            .build_initiator()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::PatternPrereq { missing: Prerequisite::RemotePublicKey, initiator: true, .. }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "missing prerequisite: pattern IK requires remote_public_key for the initiator"
+        );
+
+        let err = Builder::new(ik.parse().unwrap()).build_responder().unwrap_err();
+        assert!(matches!(
+            err,
+            Error::PatternPrereq { missing: Prerequisite::LocalPrivateKey, initiator: false, .. }
+        ));
+
+        // PSKs may all be set after building, so they're then only checked when needed.
+        let mut noise = Builder::new("Noise_NNpsk0_25519_ChaChaPoly_SHA256".parse().unwrap())
+            .build_initiator()
+            .unwrap();
+        let err = noise.write_message(&[], &mut [0u8; 1024]).unwrap_err();
+        match &err {
+            Error::Handshake { message: 0, token: Token::Psk(0), source } => {
+                assert!(matches!(**source, Error::Prereq(Prerequisite::Psk(0))))
+            },
+            _ => panic!("unexpected error {:?}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            "handshake message 0 failed at token psk0: missing prerequisite: psk0"
+        );
+
+        // Giving some but not all of them fails the build.
+        let key = [0u8; 32];
+        let err = Builder::new("Noise_NNpsk0+psk2_25519_ChaChaPoly_SHA256".parse().unwrap())
+            .psk(0, &key)
+            .build_responder()
+            .unwrap_err();
+        assert!(matches!(err, Error::PatternPrereq { missing: Prerequisite::Psk(2), .. }));
+        assert_eq!(
+            err.to_string(),
+            "missing prerequisite: pattern NNpsk0+psk2 requires psk2 for the responder"
+        );
+    }
+
+    #[test]
+    fn test_builder_psk_positions() {
+        let params: NoiseParams = "Noise_XXpsk3_25519_ChaChaPoly_SHA256".parse().unwrap();
+        let key = [0u8; 32];
+        let builder = || Builder::new(params.clone()).local_private_key(&key);
+        assert!(builder().psk(3, &key).build_responder().is_ok());
+
+        for location in &[0, 10, 255] {
+            let err = builder().psk(*location, &key).build_responder();
+            assert!(matches!(err.unwrap_err(), Error::Init(InitStage::ValidatePskPosition)));
+        }
+
+        let mut noise = builder().build_initiator().unwrap();
+        assert!(noise.set_psk(3, &key).is_ok());
+        for location in &[0, 10, 255, 256] {
+            let err = noise.set_psk(*location, &key);
+            assert!(matches!(err.unwrap_err(), Error::Init(InitStage::ValidatePskPosition)));
+        }
+        assert!(matches!(noise.set_psk(3, &key[..31]), Err(Error::Input)));
+    }
+
     #[test]
     fn test_partialeq_impl() {
         let keypair_1 = Keypair { private: vec![0x01; 32], public: vec![0x01; 32] };
@@ -304,17 +1050,17 @@ mod tests {
         let mut keypair_2 = Keypair { private: vec![0x01; 32], public: vec![0x01; 32] };
 
         // If both private and public are the same, return true
-        assert_eq!(keypair_1 == keypair_2, true);
+        assert!(keypair_1 == keypair_2);
 
         // If either public or private are different, return false
 
         // Wrong private
         keypair_2.private = vec![0x50; 32];
-        assert_eq!(keypair_1 == keypair_2, false);
+        assert!(keypair_1 != keypair_2);
         // Reset to original
         keypair_2.private = vec![0x01; 32];
         // Wrong public
         keypair_2.public = vec![0x50; 32];
-        assert_eq!(keypair_1 == keypair_2, false);
+        assert!(keypair_1 != keypair_2);
     }
 }
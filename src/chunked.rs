@@ -0,0 +1,462 @@
+//! Encrypting very large payloads as a stream of fixed-size chunks, for transfers that don't fit
+//! in transport messages.
+//!
+//! A transport message carries at most 65535 bytes, and the application has to notice on its own
+//! if the peer's last messages never arrived. A [`StreamWriter`] instead takes any amount of data
+//! through [`std::io::Write`], cuts it into chunks and seals each under its own nonce, like the
+//! STREAM construction of Hoang, Reyhanitabar, Rogaway and Vizár. The last chunk is sealed with
+//! a flag in its nonce, so a [`StreamReader`] reports an error instead of a clean end of file if
+//! the stream is cut short, even at a chunk boundary, and chunks can't be reordered, dropped or
+//! spliced in from another stream.
+//!
+//! Each stream is keyed from the final chaining key of a finished handshake, independently of the
+//! transport keys, its direction, and a stream ID the application picks, so streams run next to
+//! normal transport messages rather than replacing them. Streams in the same direction must use
+//! different IDs. As with [`resumption_psk()`](crate::HandshakeState::resumption_psk), the keys
+//! have to be derived before the handshake is turned into a transport.
+//!
+//! Every chunk but the last holds [`DEFAULT_CHUNK_LEN`] bytes of plaintext (or the length set with
+//! [`StreamWriter::chunk_len()`], which the reader has to match), followed by the cipher's tag.
+//! The last chunk holds whatever is left, and is empty if the plaintext filled the chunk before
+//! it. The reader expects the stream to end where its inner reader does, so give each stream a
+//! connection or file of its own, or bound it with [`Read::take()`](std::io::Read::take).
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::chunked::*;
+//! # use std::io::{Read, Write};
+//! # #[cfg(feature = "default-resolver")]
+//! # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let (mut initiator, mut responder) = snow::doctest::nn_handshake()?;
+//! let payload = vec![7u8; 200_000];
+//!
+//! // The initiator encrypts stream 0 into any `Write`...
+//! let mut writer = StreamWriter::new(&mut initiator, 0, Vec::new())?;
+//! writer.write_all(&payload)?;
+//! let sealed = writer.finish()?;
+//!
+//! // ...and the responder decrypts it from any `Read`.
+//! let mut reader = StreamReader::new(&mut responder, 0, &sealed[..])?;
+//! let mut opened = Vec::new();
+//! reader.read_to_end(&mut opened)?;
+//! assert_eq!(opened, payload);
+//!
+//! // Both sides can still go on to exchange transport messages.
+//! let initiator = initiator.into_transport_mode()?;
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(feature = "default-resolver"))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::{CIPHERKEYLEN, MAXHASHLEN, TAGLEN},
+    error::{Error, InitStage, StateProblem},
+    handshakestate::HandshakeState,
+    resolvers::BoxedCryptoResolver,
+    types::Cipher,
+};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+/// How much plaintext each chunk holds, unless configured otherwise.
+pub const DEFAULT_CHUNK_LEN: usize = 64 * 1024;
+
+const STREAM_LABEL: &[u8] = b"snow chunked stream";
+
+/// The nonce bit that marks the last chunk of a stream.
+const LAST_CHUNK: u64 = 1 << 63;
+
+/// Encrypts everything written to it into a chunked stream on the inner writer.
+///
+/// Nothing marks the end of the stream until [`finish()`](Self::finish) is called; a writer
+/// that's dropped before that leaves a stream the reader will reject as truncated.
+pub struct StreamWriter<W: Write> {
+    inner:     W,
+    cipher:    Box<dyn Cipher>,
+    buf:       Vec<u8>,
+    chunk_len: usize,
+    chunks:    u64,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Start stream `stream_id` from this side of a finished handshake, using the default
+    /// crypto resolver.
+    #[cfg(feature = "default-resolver")]
+    pub fn new(handshake: &mut HandshakeState, stream_id: u64, inner: W) -> Result<Self, Error> {
+        Self::with_resolver(
+            handshake,
+            stream_id,
+            inner,
+            Box::new(crate::resolvers::DefaultResolver),
+        )
+    }
+
+    /// Start stream `stream_id` from this side of a finished handshake, using a custom crypto
+    /// resolver.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State` if the handshake isn't finished, and `Error::Init` if the
+    /// resolver doesn't support the handshake's cipher or hash function.
+    pub fn with_resolver(
+        handshake: &mut HandshakeState,
+        stream_id: u64,
+        inner: W,
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        let cipher = stream_cipher(handshake, stream_id, true, resolver)?;
+        Ok(StreamWriter {
+            inner,
+            cipher,
+            buf: Vec::with_capacity(DEFAULT_CHUNK_LEN + TAGLEN),
+            chunk_len: DEFAULT_CHUNK_LEN,
+            chunks: 0,
+        })
+    }
+
+    /// Set how much plaintext each chunk holds. The reader has to be set up with the same
+    /// length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is zero.
+    pub fn chunk_len(mut self, len: usize) -> Self {
+        assert!(len > 0, "chunks have to hold some plaintext");
+        self.chunk_len = len;
+        self
+    }
+
+    /// The inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Seal what's left as the last chunk, flush it out, and return the inner writer.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the inner writer's I/O errors.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        if self.chunks >= LAST_CHUNK {
+            return Err(invalid(StateProblem::NonceExhausted.into()));
+        }
+        let nonce = if last { self.chunks | LAST_CHUNK } else { self.chunks };
+        let len = self.buf.len();
+        self.buf.resize(len + TAGLEN, 0);
+        self.cipher.encrypt_in_place(nonce, &[], &mut self.buf, len);
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        self.chunks += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = data.len().min(self.chunk_len - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        // Only the last chunk is ever short, so a full one can go out right away.
+        if self.buf.len() == self.chunk_len {
+            self.seal_chunk(false)?;
+        }
+        Ok(len)
+    }
+
+    /// Flushes the inner writer. The chunk being filled can't be sent before it's full, so
+    /// this doesn't make buffered data readable on the other end.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> fmt::Debug for StreamWriter<W> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("StreamWriter")
+            .field("chunk_len", &self.chunk_len)
+            .field("chunks", &self.chunks)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Decrypts a chunked stream from the inner reader.
+///
+/// Reads fail with `ErrorKind::InvalidData` if a chunk doesn't decrypt or data follows the last
+/// chunk, and with `ErrorKind::UnexpectedEof` if the inner reader ends before the last chunk. In
+/// both cases the error wraps the [`Error`]. Plaintext is only returned once its whole chunk has
+/// been authenticated, but a stream that later turns out to be truncated has still been read up
+/// to that point, so don't act on the data before reaching the end.
+pub struct StreamReader<R: Read> {
+    inner:      R,
+    cipher:     Box<dyn Cipher>,
+    ciphertext: Vec<u8>,
+    plaintext:  Vec<u8>,
+    pos:        usize,
+    chunk_len:  usize,
+    chunks:     u64,
+    finished:   bool,
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Read stream `stream_id` of the peer of a finished handshake, using the default crypto
+    /// resolver.
+    #[cfg(feature = "default-resolver")]
+    pub fn new(handshake: &mut HandshakeState, stream_id: u64, inner: R) -> Result<Self, Error> {
+        Self::with_resolver(
+            handshake,
+            stream_id,
+            inner,
+            Box::new(crate::resolvers::DefaultResolver),
+        )
+    }
+
+    /// Read stream `stream_id` of the peer of a finished handshake, using a custom crypto
+    /// resolver.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State` if the handshake isn't finished, and `Error::Init` if the
+    /// resolver doesn't support the handshake's cipher or hash function.
+    pub fn with_resolver(
+        handshake: &mut HandshakeState,
+        stream_id: u64,
+        inner: R,
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        let cipher = stream_cipher(handshake, stream_id, false, resolver)?;
+        Ok(StreamReader {
+            inner,
+            cipher,
+            ciphertext: Vec::new(),
+            plaintext: Vec::new(),
+            pos: 0,
+            chunk_len: DEFAULT_CHUNK_LEN,
+            chunks: 0,
+            finished: false,
+        })
+    }
+
+    /// Set how much plaintext each chunk holds, as the writer did.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is zero.
+    pub fn chunk_len(mut self, len: usize) -> Self {
+        assert!(len > 0, "chunks have to hold some plaintext");
+        self.chunk_len = len;
+        self
+    }
+
+    /// The inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Whether the last chunk has been read and authenticated, i.e. the stream wasn't
+    /// truncated.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Read and decrypt the next chunk into `self.plaintext`.
+    fn open_chunk(&mut self) -> io::Result<()> {
+        let full = self.chunk_len + TAGLEN;
+        self.ciphertext.resize(full, 0);
+        let mut filled = 0;
+        while filled < full {
+            match self.inner.read(&mut self.ciphertext[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        if filled == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                Error::TruncatedMessage { needed: TAGLEN, got: 0 },
+            ));
+        }
+
+        // Only the last chunk is short.
+        let last = filled < full;
+        let nonce = if last { self.chunks | LAST_CHUNK } else { self.chunks };
+        self.plaintext.resize(filled.saturating_sub(TAGLEN), 0);
+        let len = self
+            .cipher
+            .decrypt(nonce, &[], &self.ciphertext[..filled], &mut self.plaintext)
+            .map_err(|_| invalid(Error::Decrypt))?;
+        self.plaintext.truncate(len);
+        self.pos = 0;
+        self.chunks += 1;
+
+        if last {
+            let mut trailing = [0u8; 1];
+            loop {
+                match self.inner.read(&mut trailing) {
+                    Ok(0) => break,
+                    Ok(_) => return Err(invalid(Error::Input)),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plaintext.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.open_chunk()?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.pos);
+        buf[..len].copy_from_slice(&self.plaintext[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl<R: Read> fmt::Debug for StreamReader<R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("StreamReader")
+            .field("chunk_len", &self.chunk_len)
+            .field("chunks", &self.chunks)
+            .field("finished", &self.finished)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Key a cipher for stream `stream_id` in the given direction, with an HMAC over the direction
+/// and stream ID under a secret derived from the chaining key.
+fn stream_cipher(
+    handshake: &mut HandshakeState,
+    stream_id: u64,
+    sending: bool,
+    resolver: BoxedCryptoResolver,
+) -> Result<Box<dyn Cipher>, Error> {
+    if !handshake.is_handshake_finished() {
+        bail!(StateProblem::HandshakeNotFinished);
+    }
+    let mut hash = resolver.resolve_hash(&handshake.params.hash).ok_or(InitStage::GetHashImpl)?;
+    let mut cipher =
+        resolver.resolve_cipher(&handshake.params.cipher).ok_or(InitStage::GetCipherImpl)?;
+
+    let hash_len = hash.hash_len();
+    let mut root = [0u8; MAXHASHLEN];
+    handshake.symmetricstate.derive_secret(STREAM_LABEL, &mut root[..hash_len]);
+
+    // The first byte is set for streams from the initiator to the responder.
+    let mut info = [0u8; 9];
+    info[0] = u8::from(handshake.is_initiator() == sending);
+    info[1..].copy_from_slice(&stream_id.to_be_bytes());
+    let mut key = [0u8; MAXHASHLEN];
+    hash.hmac(&root[..hash_len], &info, &mut key);
+    cipher.set(&key[..CIPHERKEYLEN]);
+    Ok(cipher)
+}
+
+pub(crate) fn invalid(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(all(test, feature = "default-resolver"))]
+mod tests {
+    use super::*;
+    use crate::doctest::nn_handshake;
+
+    fn handshake() -> (HandshakeState, HandshakeState) {
+        nn_handshake().unwrap()
+    }
+
+    fn seal(hs: &mut HandshakeState, id: u64, chunk_len: usize, data: &[u8]) -> Vec<u8> {
+        let mut writer = StreamWriter::new(hs, id, Vec::new()).unwrap().chunk_len(chunk_len);
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn open(
+        hs: &mut HandshakeState,
+        id: u64,
+        chunk_len: usize,
+        sealed: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let mut reader = StreamReader::new(hs, id, sealed).unwrap().chunk_len(chunk_len);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out)?;
+        assert!(reader.is_finished());
+        Ok(out)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (mut i, mut r) = handshake();
+        for len in [0, 1, 99, 100, 101, 1000] {
+            let data: Vec<u8> = (0..len).map(|b| b as u8).collect();
+            let sealed = seal(&mut i, 0, 100, &data);
+            assert_eq!(sealed.len(), len + (len / 100 + 1) * TAGLEN);
+            assert_eq!(open(&mut r, 0, 100, &sealed).unwrap(), data);
+
+            let sealed = seal(&mut r, 0, 100, &data);
+            assert_eq!(open(&mut i, 0, 100, &sealed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_truncation_detected() {
+        let (mut i, mut r) = handshake();
+        let sealed = seal(&mut i, 0, 100, &[1u8; 250]);
+
+        // Cut at a chunk boundary.
+        let err = open(&mut r, 0, 100, &sealed[..2 * (100 + TAGLEN)]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        // Cut inside a chunk.
+        let err = open(&mut r, 0, 100, &sealed[..150]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // Data after the last chunk.
+        let mut extended = sealed.clone();
+        extended.push(0);
+        assert!(open(&mut r, 0, 100, &extended).is_err());
+
+        // A writer dropped without finishing.
+        let mut writer = StreamWriter::new(&mut i, 1, Vec::new()).unwrap().chunk_len(100);
+        writer.write_all(&[1u8; 250]).unwrap();
+        let unfinished = writer.get_ref().clone();
+        assert!(open(&mut r, 1, 100, &unfinished).is_err());
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let (mut i, mut r) = handshake();
+        let sealed = seal(&mut i, 0, 100, &[1u8; 250]);
+        let chunk = 100 + TAGLEN;
+
+        let mut flipped = sealed.clone();
+        flipped[chunk + 5] ^= 1;
+        assert!(open(&mut r, 0, 100, &flipped).is_err());
+
+        let mut swapped = sealed[chunk..2 * chunk].to_vec();
+        swapped.extend_from_slice(&sealed[..chunk]);
+        swapped.extend_from_slice(&sealed[2 * chunk..]);
+        assert!(open(&mut r, 0, 100, &swapped).is_err());
+
+        // Other stream IDs, directions and chunk lengths are keyed or framed differently.
+        assert!(open(&mut r, 1, 100, &sealed).is_err());
+        assert!(open(&mut i, 0, 100, &sealed).is_err());
+        assert!(open(&mut r, 0, 50, &sealed).is_err());
+    }
+}
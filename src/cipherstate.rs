@@ -1,30 +1,76 @@
+#[cfg(feature = "disco")]
+use crate::strobe::{Strobe, STROBE_VERSION};
 use crate::{
-    constants::TAGLEN,
+    constants::{CIPHERKEYLEN, TAGLEN},
     error::{Error, InitStage, StateProblem},
     types::Cipher,
 };
+use zeroize::Zeroizing;
 
-pub(crate) struct CipherState {
-    cipher:  Box<dyn Cipher>,
+/// The primitive behind a `CipherState`.
+enum CipherImpl<C> {
+    Aead(C),
+
+    /// Disco's keyed Strobe duplex, where the nonce is implicit in the duplex state.
+    #[cfg(feature = "disco")]
+    Strobe(Box<Strobe>),
+}
+
+/// What `CipherState::restore()` needs to put a cipher back the way it was.
+pub(crate) struct CipherCheckpoint {
+    key:     Option<Zeroizing<[u8; CIPHERKEYLEN]>>,
     n:       u64,
     has_key: bool,
 }
 
+pub(crate) struct CipherState<C = Box<dyn Cipher>> {
+    cipher:  CipherImpl<C>,
+    /// The current key, tracked so the transport state can be exported, or `None` if there's
+    /// no key or the cipher rekeyed without handing it back.
+    key:     Option<Zeroizing<[u8; CIPHERKEYLEN]>>,
+    n:       u64,
+    has_key: bool,
+}
+
+#[cfg(feature = "disco")]
 impl CipherState {
-    pub fn new(cipher: Box<dyn Cipher>) -> Self {
-        Self { cipher, n: 0, has_key: false }
+    pub fn new_strobe() -> Self {
+        Self {
+            cipher:  CipherImpl::Strobe(Box::new(Strobe::new(&[]))),
+            key:     None,
+            n:       0,
+            has_key: false,
+        }
+    }
+}
+
+impl<C: Cipher> CipherState<C> {
+    pub fn new(cipher: C) -> Self {
+        Self { cipher: CipherImpl::Aead(cipher), key: None, n: 0, has_key: false }
     }
 
     pub fn name(&self) -> &'static str {
-        self.cipher.name()
+        match &self.cipher {
+            CipherImpl::Aead(cipher) => cipher.name(),
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(_) => STROBE_VERSION,
+        }
     }
 
     pub fn set(&mut self, key: &[u8], n: u64) {
-        self.cipher.set(key);
+        self.rekey_manually(key);
         self.n = n;
         self.has_key = true;
     }
 
+    /// Key this `CipherState` with a Strobe duplex split from a Disco handshake.
+    #[cfg(feature = "disco")]
+    pub fn set_strobe(&mut self, strobe: Strobe) {
+        self.cipher = CipherImpl::Strobe(Box::new(strobe));
+        self.n = 0;
+        self.has_key = true;
+    }
+
     pub fn encrypt_ad(
         &mut self,
         authtext: &[u8],
@@ -35,7 +81,43 @@ impl CipherState {
             bail!(StateProblem::MissingKeyMaterial);
         }
 
-        let len = self.cipher.encrypt(self.n, authtext, plaintext, out);
+        let len = match &mut self.cipher {
+            CipherImpl::Aead(cipher) => cipher.encrypt(self.n, authtext, plaintext, out),
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(strobe) => {
+                if !authtext.is_empty() {
+                    strobe.ad(authtext);
+                }
+                let len = plaintext.len();
+                out[..len].copy_from_slice(plaintext);
+                strobe.send_enc(&mut out[..len]);
+                strobe.send_mac(&mut out[len..len + TAGLEN]);
+                len + TAGLEN
+            },
+        };
+        self.n = self.n.checked_add(1).unwrap();
+        Ok(len)
+    }
+
+    /// Encrypt the first `plaintext_len` bytes of `in_out` in place, appending the tag.
+    pub fn encrypt_in_place(
+        &mut self,
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> Result<usize, Error> {
+        if !self.has_key {
+            bail!(StateProblem::MissingKeyMaterial);
+        }
+
+        let len = match &mut self.cipher {
+            CipherImpl::Aead(cipher) => cipher.encrypt_in_place(self.n, &[], in_out, plaintext_len),
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(strobe) => {
+                strobe.send_enc(&mut in_out[..plaintext_len]);
+                strobe.send_mac(&mut in_out[plaintext_len..plaintext_len + TAGLEN]);
+                plaintext_len + TAGLEN
+            },
+        };
         self.n = self.n.checked_add(1).unwrap();
         Ok(len)
     }
@@ -51,40 +133,133 @@ impl CipherState {
             return Err(());
         }
 
-        let len = self.cipher.decrypt(self.n, authtext, ciphertext, out);
+        let len = match &mut self.cipher {
+            CipherImpl::Aead(cipher) => cipher.decrypt(self.n, authtext, ciphertext, out),
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(strobe) => {
+                if !authtext.is_empty() {
+                    strobe.ad(authtext);
+                }
+                let len = ciphertext.len() - TAGLEN;
+                out[..len].copy_from_slice(&ciphertext[..len]);
+                strobe.recv_enc(&mut out[..len]);
+                strobe.recv_mac(&ciphertext[len..]).map(|_| len)
+            },
+        };
         self.n = self.n.checked_add(1).unwrap();
         len
     }
 
-    pub fn encrypt(&mut self, plaintext: &[u8], out: &mut [u8]) -> Result<usize, Error> {
-        self.encrypt_ad(&[0u8; 0], plaintext, out)
-    }
+    /// Decrypt the first `ciphertext_len` bytes of `in_out` in place, leaving the plaintext at
+    /// the front.
+    pub fn decrypt_in_place(
+        &mut self,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        if ciphertext_len < TAGLEN || ciphertext_len > in_out.len() || !self.has_key {
+            return Err(());
+        }
 
-    pub fn decrypt(&mut self, ciphertext: &[u8], out: &mut [u8]) -> Result<usize, ()> {
-        self.decrypt_ad(&[0u8; 0], ciphertext, out)
+        let len = match &mut self.cipher {
+            CipherImpl::Aead(cipher) => {
+                cipher.decrypt_in_place(self.n, authtext, in_out, ciphertext_len)
+            },
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(strobe) => {
+                if !authtext.is_empty() {
+                    strobe.ad(authtext);
+                }
+                let len = ciphertext_len - TAGLEN;
+                let (plaintext, tag) = in_out[..ciphertext_len].split_at_mut(len);
+                strobe.recv_enc(plaintext);
+                strobe.recv_mac(tag).map(|_| len)
+            },
+        };
+        self.n = self.n.checked_add(1).unwrap();
+        len
     }
 
     pub fn rekey(&mut self) {
-        self.cipher.rekey();
+        match &mut self.cipher {
+            CipherImpl::Aead(cipher) => self.key = cipher.rekey_returning_key().map(Zeroizing::new),
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(strobe) => strobe.ratchet(TAGLEN),
+        }
     }
 
     pub fn rekey_manually(&mut self, key: &[u8]) {
-        self.cipher.set(key);
+        match &mut self.cipher {
+            CipherImpl::Aead(cipher) => {
+                let mut copy = Zeroizing::new([0u8; CIPHERKEYLEN]);
+                copy_slices!(key, &mut *copy);
+                self.key = Some(copy);
+                cipher.set(key);
+            },
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(strobe) => strobe.meta_ad(key),
+        }
+    }
+
+    /// Whether this `CipherState` depends on messages being processed in order, so it can't be
+    /// used with an explicit nonce.
+    pub fn is_stateful(&self) -> bool {
+        match self.cipher {
+            CipherImpl::Aead(_) => false,
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(_) => true,
+        }
     }
 
     pub fn nonce(&self) -> u64 {
         self.n
     }
 
+    /// Whether [`key()`](Self::key) covers this `CipherState`: there's no key, or the current
+    /// one is known.
+    pub fn is_exportable(&self) -> bool {
+        !self.is_stateful() && (!self.has_key || self.key.is_some())
+    }
+
+    /// The current key, if there is one and it can be exported.
+    pub fn key(&self) -> Option<&[u8; CIPHERKEYLEN]> {
+        match self.cipher {
+            CipherImpl::Aead(_) if self.has_key => self.key.as_deref(),
+            _ => None,
+        }
+    }
+
     pub fn set_nonce(&mut self, nonce: u64) {
         self.n = nonce;
     }
+
+    /// Take a copy of the key and nonce, so a failed handshake message can be rolled back.
+    ///
+    /// A Strobe duplex isn't covered, since Disco keeps its handshake state elsewhere.
+    pub fn checkpoint(&self) -> CipherCheckpoint {
+        CipherCheckpoint { key: self.key.clone(), n: self.n, has_key: self.has_key }
+    }
+
+    pub fn restore(&mut self, checkpoint: CipherCheckpoint) {
+        match &mut self.cipher {
+            CipherImpl::Aead(cipher) if checkpoint.has_key => {
+                if let Some(key) = &checkpoint.key {
+                    cipher.set(&key[..]);
+                }
+                self.key = checkpoint.key;
+            },
+            _ => {},
+        }
+        self.n = checkpoint.n;
+        self.has_key = checkpoint.has_key;
+    }
 }
 
-pub(crate) struct CipherStates(pub CipherState, pub CipherState);
+pub(crate) struct CipherStates<C = Box<dyn Cipher>>(pub CipherState<C>, pub CipherState<C>);
 
-impl CipherStates {
-    pub fn new(initiator: CipherState, responder: CipherState) -> Result<Self, Error> {
+impl<C: Cipher> CipherStates<C> {
+    pub fn new(initiator: CipherState<C>, responder: CipherState<C>) -> Result<Self, Error> {
         if initiator.name() != responder.name() {
             bail!(InitStage::ValidateCipherTypes);
         }
@@ -129,6 +304,19 @@ impl StatelessCipherState {
         Ok(self.cipher.encrypt(nonce, authtext, plaintext, out))
     }
 
+    /// Encrypt the first `plaintext_len` bytes of `in_out` in place, appending the tag.
+    pub fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> Result<usize, Error> {
+        if !self.has_key {
+            bail!(StateProblem::MissingKeyMaterial);
+        }
+        Ok(self.cipher.encrypt_in_place(nonce, &[], in_out, plaintext_len))
+    }
+
     pub fn decrypt_ad(
         &self,
         nonce: u64,
@@ -144,12 +332,19 @@ impl StatelessCipherState {
         self.cipher.decrypt(nonce, authtext, ciphertext, out)
     }
 
-    pub fn encrypt(&self, nonce: u64, plaintext: &[u8], out: &mut [u8]) -> Result<usize, Error> {
-        self.encrypt_ad(nonce, &[], plaintext, out)
-    }
+    /// Decrypt the first `ciphertext_len` bytes of `in_out` in place, leaving the plaintext at
+    /// the front.
+    pub fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        if ciphertext_len < TAGLEN || ciphertext_len > in_out.len() || !self.has_key {
+            return Err(());
+        }
 
-    pub fn decrypt(&self, nonce: u64, ciphertext: &[u8], out: &mut [u8]) -> Result<usize, ()> {
-        self.decrypt_ad(nonce, &[], ciphertext, out)
+        self.cipher.decrypt_in_place(nonce, &[], in_out, ciphertext_len)
     }
 
     pub fn rekey(&mut self) {
@@ -163,7 +358,11 @@ impl StatelessCipherState {
 
 impl From<CipherState> for StatelessCipherState {
     fn from(other: CipherState) -> Self {
-        Self { cipher: other.cipher, has_key: other.has_key }
+        match other.cipher {
+            CipherImpl::Aead(cipher) => Self { cipher, has_key: other.has_key },
+            #[cfg(feature = "disco")]
+            CipherImpl::Strobe(_) => unreachable!("Strobe cipherstates can't be made stateless"),
+        }
     }
 }
 
@@ -0,0 +1,211 @@
+//! Encrypted close-notify messages, so a clean shutdown can be told apart from an attacker
+//! cutting the connection short.
+//!
+//! Like TLS 1.3's `close_notify`, the convention tags the plaintext of every transport message
+//! with a trailing content type byte: [`DATA`] for application data and [`CLOSE_NOTIFY`] for the
+//! last message a side sends. The tag is encrypted and authenticated along with the payload, so
+//! nobody but the peer can forge a close, and a stream that ends without one was truncated.
+//!
+//! A [`ClosingTransport`] wraps a [`TransportState`] and applies the convention on both sides,
+//! which both peers have to agree on: its messages aren't readable by a plain `TransportState`,
+//! and the other way around.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::close::*;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let (mut i, mut r) = snow::doctest::nn_handshake()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! let mut initiator = ClosingTransport::new(i.into_transport_mode()?);
+//! let mut responder = ClosingTransport::new(r.into_transport_mode()?);
+//!
+//! let len = initiator.write_message(b"goodbye", &mut msg)?;
+//! assert_eq!(responder.read_message(&msg[..len], &mut buf)?, Event::Data(7));
+//!
+//! let len = initiator.send_close(&mut msg)?;
+//! assert_eq!(responder.read_message(&msg[..len], &mut buf)?, Event::PeerClosed);
+//!
+//! // When the connection ends, make sure it ended on purpose.
+//! responder.end_of_stream()?;
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{error::StateProblem, Error, TransportState};
+use std::io::IoSlice;
+
+/// The content type of messages carrying application data.
+pub const DATA: u8 = 0;
+
+/// The content type of the message that closes a side of the session.
+pub const CLOSE_NOTIFY: u8 = 1;
+
+/// What [`ClosingTransport::read_message()`] made of an incoming message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// Application data, whose length is given.
+    Data(usize),
+
+    /// The peer closed its side of the session, and won't send anything more.
+    PeerClosed,
+}
+
+/// A [`TransportState`] that sends and recognizes close-notify messages.
+#[derive(Debug)]
+pub struct ClosingTransport {
+    transport:   TransportState,
+    closed:      bool,
+    peer_closed: bool,
+}
+
+impl ClosingTransport {
+    /// Wrap `transport`, which hasn't carried any messages without the content type.
+    pub fn new(transport: TransportState) -> Self {
+        ClosingTransport { transport, closed: false, peer_closed: false }
+    }
+
+    /// The wrapped transport.
+    pub fn transport(&self) -> &TransportState {
+        &self.transport
+    }
+
+    /// Encrypt application data, which takes one more byte than
+    /// [`TransportState::write_message()`] for the content type.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::Closed)` once this side has sent its close,
+    /// and otherwise fails like `TransportState::write_message()`.
+    pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        self.write(payload, DATA, message)
+    }
+
+    /// Write the close-notify message, after which this side can't send anything more.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::Closed)` if the close was already sent, and
+    /// otherwise fails like [`TransportState::write_message()`].
+    pub fn send_close(&mut self, message: &mut [u8]) -> Result<usize, Error> {
+        let len = self.write(&[], CLOSE_NOTIFY, message)?;
+        self.closed = true;
+        Ok(len)
+    }
+
+    fn write(
+        &mut self,
+        payload: &[u8],
+        content_type: u8,
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        if self.closed {
+            bail!(StateProblem::Closed);
+        }
+        let content_type = [content_type];
+        let payload = [IoSlice::new(payload), IoSlice::new(&content_type)];
+        self.transport.write_message_vectored(&payload, message)
+    }
+
+    /// Decrypt an incoming message, telling data apart from the peer's close. `payload` needs
+    /// room for the content type, one byte past the data.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::Closed)` if the peer already closed, since
+    /// nothing it sends afterwards can be genuine, `Error::Input` if the message has no
+    /// content type or an unknown one, and otherwise fails like
+    /// [`TransportState::read_message()`].
+    pub fn read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<Event, Error> {
+        if self.peer_closed {
+            bail!(StateProblem::Closed);
+        }
+        let len = self.transport.read_message(message, payload)?;
+        match len.checked_sub(1).map(|len| (len, payload[len])) {
+            Some((len, DATA)) => Ok(Event::Data(len)),
+            Some((0, CLOSE_NOTIFY)) => {
+                self.peer_closed = true;
+                Ok(Event::PeerClosed)
+            },
+            _ => bail!(Error::Input),
+        }
+    }
+
+    /// Whether this side has sent its close.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Whether the peer has sent its close.
+    pub fn is_peer_closed(&self) -> bool {
+        self.peer_closed
+    }
+
+    /// Check that the peer closed the session, once the underlying connection has ended.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::Truncated)` if the peer never sent its close,
+    /// so whatever was received may be missing its end.
+    pub fn end_of_stream(&self) -> Result<(), Error> {
+        if !self.peer_closed {
+            bail!(StateProblem::Truncated);
+        }
+        Ok(())
+    }
+
+    /// Give up the close tracking and get back the transport.
+    pub fn into_inner(self) -> TransportState {
+        self.transport
+    }
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::doctest;
+
+    fn transport_pair() -> (ClosingTransport, ClosingTransport) {
+        let (i, r) = doctest::transport_pair();
+        (ClosingTransport::new(i), ClosingTransport::new(r))
+    }
+
+    #[test]
+    fn test_close() {
+        let (mut i, mut r) = transport_pair();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+
+        let len = i.write_message(&[], &mut msg).unwrap();
+        assert_eq!(r.read_message(&msg[..len], &mut buf).unwrap(), Event::Data(0));
+        assert!(matches!(r.end_of_stream(), Err(Error::State(StateProblem::Truncated))));
+
+        let len = i.send_close(&mut msg).unwrap();
+        assert!(i.is_closed() && !r.is_peer_closed());
+        assert!(matches!(i.send_close(&mut msg), Err(Error::State(StateProblem::Closed))));
+        assert!(matches!(i.write_message(b"x", &mut msg), Err(Error::State(StateProblem::Closed))));
+        assert_eq!(r.read_message(&msg[..len], &mut buf).unwrap(), Event::PeerClosed);
+        r.end_of_stream().unwrap();
+
+        // The other direction stays open until the responder closes it too.
+        let len = r.write_message(b"last words", &mut msg).unwrap();
+        assert_eq!(i.read_message(&msg[..len], &mut buf).unwrap(), Event::Data(10));
+        assert_eq!(&buf[..10], b"last words");
+    }
+
+    #[test]
+    fn test_unknown_content_type() {
+        let (i, mut r) = transport_pair();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let mut i = i.into_inner();
+
+        let len = i.write_message(&[], &mut msg).unwrap();
+        assert!(matches!(r.read_message(&msg[..len], &mut buf), Err(Error::Input)));
+        let len = i.write_message(&[7, CLOSE_NOTIFY], &mut msg).unwrap();
+        assert!(matches!(r.read_message(&msg[..len], &mut buf), Err(Error::Input)));
+        assert!(!r.is_peer_closed());
+    }
+}
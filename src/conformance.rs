@@ -0,0 +1,552 @@
+//! An interoperability suite, for checking another Noise implementation against this one.
+//!
+//! A [`Suite`] is a matrix of [`Case`]s: each pairs a protocol with the role this side plays in
+//! it, and fixes everything else the two sides have to agree on (static keys, prologue, PSK and
+//! payloads). For every case, the suite tells the other implementation to set up the opposite
+//! role through a [`Remote`], exchanges the handshake messages and then one transport message
+//! each way, carrying each side's handshake hash. Whatever the remote rejects, or sends that
+//! doesn't match what this side expects, is collected as a [`Divergence`] in the [`Report`]
+//! instead of stopping the run.
+//!
+//! Writing the [`Remote`] is the only work needed to test against e.g. noise-c, cacophony or a
+//! Nim implementation: it typically forwards the case to a small driver program over a pipe or
+//! socket, and the driver runs the given role with its own implementation. See [`Case`] for what
+//! the remote has to send.
+//!
+//! Requires the `default-resolver` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use snow::conformance::*;
+//! # use std::io;
+//! struct NoiseC { /* e.g. a child process speaking some framing over stdin/stdout */ }
+//!
+//! impl Remote for NoiseC {
+//!     fn start(&mut self, case: &Case) -> io::Result<bool> {
+//!         // Tell the driver the protocol name, its role, keys, prologue and PSK, and return
+//!         // whether it supports the protocol.
+//! #       Ok(true)
+//!     }
+//!     fn send(&mut self, message: &[u8]) -> io::Result<()> {
+//! #       Ok(())
+//!     }
+//!     fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+//! #       Ok(None)
+//!     }
+//!     fn verdict(&mut self) -> io::Result<bool> {
+//! #       Ok(true)
+//!     }
+//! }
+//!
+//! let report = Suite::new().run(&mut NoiseC {})?;
+//! for divergence in &report.divergences {
+//!     eprintln!("{}", divergence);
+//! }
+//! assert!(report.divergences.is_empty());
+//! # Ok::<(), io::Error>(())
+//! ```
+
+use crate::{
+    error::Error,
+    params::{HandshakeModifier, NoiseParams},
+    resolvers::{CryptoResolver, DefaultResolver},
+    Builder, HandshakeState, Keypair,
+};
+use std::{fmt, io};
+
+/// The protocols of the default matrix, before picking a cipher and hash.
+const PATTERNS: &[&str] = &[
+    "N", "K", "X", "NN", "NK", "NX", "XN", "XK", "XX", "KN", "KK", "KX", "IN", "IK", "IX", "Npsk0",
+    "NNpsk0", "NNpsk2", "XXpsk3", "IKpsk2",
+];
+const CIPHERS: &[&str] = &["ChaChaPoly", "AESGCM"];
+const HASHES: &[&str] = &["SHA256", "SHA512", "BLAKE2s", "BLAKE2b"];
+
+const PROLOGUE: &[u8] = b"snow conformance";
+const PSK: [u8; 32] = [0x5a; 32];
+
+/// One protocol run between this side and the remote.
+///
+/// The remote plays the role this side doesn't, with the static key of that role (if the pattern
+/// has one), knowing the static public key of this side's role if the pattern requires it, and
+/// with [`prologue`](Self::prologue) and, at every PSK modifier, [`psk`](Self::psk). Every
+/// handshake message it writes has to carry [`handshake_payload()`](Self::handshake_payload),
+/// and once the handshake is done it sends its handshake hash as a transport message: the
+/// initiator first, then, unless the pattern is one-way, the responder.
+pub struct Case {
+    /// The protocol to run.
+    pub params:           NoiseParams,
+    /// Whether this side is the initiator.
+    pub initiator:        bool,
+    /// The initiator's static keypair.
+    pub initiator_static: Keypair,
+    /// The responder's static keypair.
+    pub responder_static: Keypair,
+    /// The prologue both sides use.
+    pub prologue:         Vec<u8>,
+    /// The PSK for every PSK modifier.
+    pub psk:              [u8; 32],
+}
+
+impl Case {
+    /// A case for `params`, with this side playing the initiator if `initiator` is set.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Init` if the default resolver doesn't support the protocol's DH.
+    pub fn new(params: NoiseParams, initiator: bool) -> Result<Self, Error> {
+        let (initiator_static, responder_static) = static_keys(&params)?;
+        Ok(Case {
+            params,
+            initiator,
+            initiator_static,
+            responder_static,
+            prologue: PROLOGUE.to_vec(),
+            psk: PSK,
+        })
+    }
+
+    /// The payload of the handshake message at `index`, counting from 0.
+    pub fn handshake_payload(&self, index: usize) -> Vec<u8> {
+        format!("handshake payload {}", index).into_bytes()
+    }
+
+    fn build(&self) -> Result<HandshakeState, Error> {
+        let pattern = self.params.handshake.pattern;
+        let (local, remote) = if self.initiator {
+            (&self.initiator_static, &self.responder_static)
+        } else {
+            (&self.responder_static, &self.initiator_static)
+        };
+        let mut builder = Builder::with_resolver(self.params.clone(), Box::new(DefaultResolver))
+            .prologue(&self.prologue);
+        if pattern.needs_local_static_key(self.initiator) {
+            builder = builder.local_private_key(&local.private);
+        }
+        if pattern.need_known_remote_pubkey(self.initiator) {
+            builder = builder.remote_public_key(&remote.public);
+        }
+        for modifier in &self.params.handshake.modifiers.list {
+            if let HandshakeModifier::Psk(location) = *modifier {
+                builder = builder.psk(location, &self.psk);
+            }
+        }
+        if self.initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        }
+    }
+
+    /// Run the case against `remote`, returning what diverged, if anything.
+    fn run<R: Remote + ?Sized>(&self, remote: &mut R) -> io::Result<Option<Problem>> {
+        let mut handshake = match self.build() {
+            Ok(handshake) => handshake,
+            Err(e) => return Ok(Some(Problem::Local(e))),
+        };
+        let mut buf = vec![0u8; crate::constants::MAXMSGLEN];
+        let mut index = 0;
+
+        while !handshake.is_handshake_finished() {
+            if handshake.is_my_turn() {
+                let len = match handshake.write_message(&self.handshake_payload(index), &mut buf) {
+                    Ok(len) => len,
+                    Err(e) => return Ok(Some(Problem::Local(e))),
+                };
+                remote.send(&buf[..len])?;
+            } else {
+                let message = match remote.receive()? {
+                    Some(message) => message,
+                    None => {
+                        return Ok(Some(Problem::Rejected { message: index.saturating_sub(1) }))
+                    },
+                };
+                let len = match handshake.read_message(&message, &mut buf) {
+                    Ok(len) => len,
+                    Err(error) => return Ok(Some(Problem::Unreadable { message: index, error })),
+                };
+                if buf[..len] != self.handshake_payload(index)[..] {
+                    return Ok(Some(Problem::PayloadMismatch { message: index }));
+                }
+            }
+            index += 1;
+        }
+
+        let hash = handshake.get_handshake_hash().to_vec();
+        let mut transport = match handshake.into_transport_mode() {
+            Ok(transport) => transport,
+            Err(e) => return Ok(Some(Problem::Local(e))),
+        };
+        let one_way = self.params.handshake.pattern.is_oneway();
+        for initiator_sends in [true, false] {
+            if one_way && !initiator_sends {
+                break;
+            }
+            if initiator_sends == self.initiator {
+                let len = match transport.write_message(&hash, &mut buf) {
+                    Ok(len) => len,
+                    Err(e) => return Ok(Some(Problem::Local(e))),
+                };
+                remote.send(&buf[..len])?;
+            } else {
+                let message = match remote.receive()? {
+                    Some(message) => message,
+                    None => {
+                        return Ok(Some(Problem::Rejected { message: index.saturating_sub(1) }))
+                    },
+                };
+                let len = match transport.read_message(&message, &mut buf) {
+                    Ok(len) => len,
+                    Err(error) => return Ok(Some(Problem::Unreadable { message: index, error })),
+                };
+                if buf[..len] != hash[..] {
+                    return Ok(Some(Problem::HandshakeHashMismatch));
+                }
+            }
+            index += 1;
+        }
+
+        if !remote.verdict()? {
+            return Ok(Some(Problem::Rejected { message: index.saturating_sub(1) }));
+        }
+        Ok(None)
+    }
+}
+
+impl fmt::Debug for Case {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Case")
+            .field("protocol", &self.params.name)
+            .field("initiator", &self.initiator)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The fixed static keypairs of the initiator and responder, for the protocol's DH.
+fn static_keys(params: &NoiseParams) -> Result<(Keypair, Keypair), Error> {
+    let mut dh =
+        DefaultResolver.resolve_dh(&params.dh).ok_or(crate::error::InitStage::GetDhImpl)?;
+    let mut keypair = |seed: u8| {
+        let private: Vec<u8> = (0..dh.priv_len()).map(|i| seed.wrapping_add(i as u8)).collect();
+        dh.set(&private);
+        Keypair { private, public: dh.pubkey().to_vec() }
+    };
+    Ok((keypair(0x10), keypair(0x80)))
+}
+
+/// The other implementation, as seen from the suite.
+pub trait Remote {
+    /// Set up a new run of `case`, playing the role opposite to `case.initiator`, and return
+    /// whether the remote supports it. Unsupported cases are skipped.
+    fn start(&mut self, case: &Case) -> io::Result<bool>;
+
+    /// Deliver a message from this side to the remote.
+    fn send(&mut self, message: &[u8]) -> io::Result<()>;
+
+    /// Get the next message the remote wrote, or `None` if it rejected a message it was sent
+    /// and gave up on the case.
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>>;
+
+    /// Called once every message of the case has been exchanged: whether the remote read all of
+    /// them, with the payloads and handshake hash it expected.
+    fn verdict(&mut self) -> io::Result<bool>;
+}
+
+/// A matrix of cases to run against a [`Remote`].
+#[derive(Debug)]
+pub struct Suite {
+    cases: Vec<Case>,
+}
+
+impl Suite {
+    /// The default matrix: the one-way and fundamental interactive patterns along with a few PSK
+    /// ones, over Curve25519 with every cipher and hash the spec defines (except SHA384, which
+    /// isn't in it), with this side playing each role in turn.
+    pub fn new() -> Self {
+        let mut cases = Vec::new();
+        for pattern in PATTERNS {
+            for cipher in CIPHERS {
+                for hash in HASHES {
+                    let name = format!("Noise_{}_25519_{}_{}", pattern, cipher, hash);
+                    let params: NoiseParams = name.parse().expect("valid protocol name");
+                    for initiator in [true, false] {
+                        cases.push(Case::new(params.clone(), initiator).expect("supported DH"));
+                    }
+                }
+            }
+        }
+        Suite { cases }
+    }
+
+    /// A suite of the given cases.
+    pub fn with_cases(cases: Vec<Case>) -> Self {
+        Suite { cases }
+    }
+
+    /// Keep only the cases for which `keep` returns true.
+    pub fn filter(mut self, keep: impl FnMut(&Case) -> bool) -> Self {
+        self.cases.retain(keep);
+        self
+    }
+
+    /// The cases in the suite.
+    pub fn cases(&self) -> &[Case] {
+        &self.cases
+    }
+
+    /// Run every case against `remote`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the first I/O error from `remote`, which stops the run. Protocol failures on
+    /// either side don't; they're reported as divergences.
+    pub fn run<R: Remote + ?Sized>(&self, remote: &mut R) -> io::Result<Report> {
+        let mut report = Report::default();
+        for case in &self.cases {
+            if !remote.start(case)? {
+                report.skipped += 1;
+                continue;
+            }
+            match case.run(remote)? {
+                None => report.passed += 1,
+                Some(problem) => report.divergences.push(Divergence {
+                    protocol: case.params.name.clone(),
+                    initiator: case.initiator,
+                    problem,
+                }),
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl Default for Suite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What came of a [`Suite::run()`].
+#[derive(Debug, Default)]
+pub struct Report {
+    /// How many cases ran through without divergence.
+    pub passed:      usize,
+    /// How many cases the remote doesn't support.
+    pub skipped:     usize,
+    /// The cases that diverged.
+    pub divergences: Vec<Divergence>,
+}
+
+/// A case on which the two implementations disagreed.
+#[derive(Debug)]
+pub struct Divergence {
+    /// The protocol name of the case.
+    pub protocol:  String,
+    /// Whether this side was the initiator.
+    pub initiator: bool,
+    /// What went wrong.
+    pub problem:   Problem,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let role = if self.initiator { "initiator" } else { "responder" };
+        write!(f, "{} (as {}): {}", self.protocol, role, self.problem)
+    }
+}
+
+/// How a case diverged. Messages are counted from 0 over the whole case, handshake messages
+/// first.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Problem {
+    /// This side couldn't read the remote's message.
+    Unreadable {
+        /// The message's index.
+        message: usize,
+        /// Why reading it failed.
+        error:   Error,
+    },
+    /// The remote rejected a message, at the latest the one at this index.
+    Rejected {
+        /// The message's index.
+        message: usize,
+    },
+    /// A handshake message from the remote carried an unexpected payload.
+    PayloadMismatch {
+        /// The message's index.
+        message: usize,
+    },
+    /// The remote's handshake hash differs from this side's.
+    HandshakeHashMismatch,
+    /// This side failed to run the case at all, e.g. because it doesn't support the protocol.
+    Local(Error),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unreadable { message, error } => {
+                write!(f, "couldn't read message {}: {}", message, error)
+            },
+            Problem::Rejected { message } => write!(f, "remote rejected message {}", message),
+            Problem::PayloadMismatch { message } => {
+                write!(f, "unexpected payload in message {}", message)
+            },
+            Problem::HandshakeHashMismatch => write!(f, "handshake hashes differ"),
+            Problem::Local(error) => write!(f, "couldn't run locally: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A remote running this crate on the other side, optionally with the wrong prologue.
+    struct Loopback {
+        bad_prologue: bool,
+        state:        Option<HandshakeState>,
+        transport:    Option<crate::TransportState>,
+        hash:         Vec<u8>,
+        case:         Option<Case>,
+        outbox:       VecDeque<Vec<u8>>,
+        index:        usize,
+        ok:           bool,
+    }
+
+    impl Loopback {
+        fn new(bad_prologue: bool) -> Self {
+            Loopback {
+                bad_prologue,
+                state: None,
+                transport: None,
+                hash: vec![],
+                case: None,
+                outbox: VecDeque::new(),
+                index: 0,
+                ok: true,
+            }
+        }
+
+        /// Write whatever the remote's turn calls for into the outbox.
+        fn advance(&mut self) {
+            let case = self.case.as_ref().unwrap();
+            let mut buf = vec![0u8; 65535];
+            if let Some(state) = &mut self.state {
+                if state.is_handshake_finished() {
+                    self.hash = state.get_handshake_hash().to_vec();
+                    self.transport =
+                        Some(self.state.take().unwrap().into_transport_mode().unwrap());
+                } else if state.is_my_turn() {
+                    let len =
+                        state.write_message(&case.handshake_payload(self.index), &mut buf).unwrap();
+                    self.outbox.push_back(buf[..len].to_vec());
+                    self.index += 1;
+                    return self.advance();
+                } else {
+                    return;
+                }
+            }
+            // The initiator sends its hash first, the responder once it has read it.
+            let transport = self.transport.as_mut().unwrap();
+            let one_way = case.params.handshake.pattern.is_oneway();
+            let sends = if transport.is_initiator() {
+                transport.sending_nonce() == 0
+            } else {
+                !one_way && transport.receiving_nonce() == 1 && transport.sending_nonce() == 0
+            };
+            if sends {
+                let len = transport.write_message(&self.hash, &mut buf).unwrap();
+                self.outbox.push_back(buf[..len].to_vec());
+            }
+        }
+    }
+
+    impl Remote for Loopback {
+        fn start(&mut self, case: &Case) -> io::Result<bool> {
+            let copy = |keypair: &Keypair| Keypair {
+                private: keypair.private.clone(),
+                public:  keypair.public.clone(),
+            };
+            let mut remote = Case {
+                params:           case.params.clone(),
+                initiator:        !case.initiator,
+                initiator_static: copy(&case.initiator_static),
+                responder_static: copy(&case.responder_static),
+                prologue:         case.prologue.clone(),
+                psk:              case.psk,
+            };
+            if self.bad_prologue {
+                remote.prologue = b"something else".to_vec();
+            }
+            self.state = Some(remote.build().unwrap());
+            self.transport = None;
+            self.case = Some(remote);
+            self.outbox.clear();
+            self.index = 0;
+            self.ok = true;
+            self.advance();
+            Ok(true)
+        }
+
+        fn send(&mut self, message: &[u8]) -> io::Result<()> {
+            let case = self.case.as_ref().unwrap();
+            let mut buf = vec![0u8; 65535];
+            let ok = match (&mut self.state, &mut self.transport) {
+                (Some(state), _) => match state.read_message(message, &mut buf) {
+                    Ok(len) => buf[..len] == case.handshake_payload(self.index)[..],
+                    Err(_) => false,
+                },
+                (None, Some(transport)) => match transport.read_message(message, &mut buf) {
+                    Ok(len) => buf[..len] == self.hash[..],
+                    Err(_) => false,
+                },
+                (None, None) => false,
+            };
+            self.index += 1;
+            if ok {
+                self.advance();
+            } else {
+                self.ok = false;
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+            Ok(if self.ok { self.outbox.pop_front() } else { None })
+        }
+
+        fn verdict(&mut self) -> io::Result<bool> {
+            Ok(self.ok)
+        }
+    }
+
+    #[test]
+    fn test_loopback_conforms() {
+        let suite = Suite::new();
+        let report = suite.run(&mut Loopback::new(false)).unwrap();
+        assert!(report.divergences.is_empty(), "{:?}", report.divergences);
+        assert_eq!(report.passed, suite.cases().len());
+    }
+
+    #[test]
+    fn test_divergence_reported() {
+        let suite =
+            Suite::new().filter(|case| case.params.cipher == crate::params::CipherChoice::AESGCM);
+        let report = suite.run(&mut Loopback::new(true)).unwrap();
+        assert_eq!(report.passed, 0);
+        assert_eq!(report.divergences.len(), suite.cases().len());
+        // As the initiator of a one-way pattern, the rejection only shows in the verdict.
+        let divergence = &report.divergences[0];
+        assert!(matches!(divergence.problem, Problem::Rejected { message: 1 }));
+        assert_eq!(
+            divergence.to_string(),
+            "Noise_N_25519_AESGCM_SHA256 (as initiator): remote rejected message 1"
+        );
+        // As the responder, this side can't read the first message.
+        assert!(matches!(report.divergences[1].problem, Problem::Unreadable { message: 0, .. }));
+    }
+}
@@ -5,7 +5,12 @@ pub const TAGLEN: usize = 16;
 pub const MAXHASHLEN: usize = 64;
 pub const MAXBLOCKLEN: usize = 128;
 pub const MAXDHLEN: usize = 56;
+pub const MAXSIGLEN: usize = 64;
+// Static keys are either DH keys or, with the sig modifier, Ed25519 signing keys.
+pub const MAXSTATICLEN: usize = MAXDHLEN;
 pub const MAXMSGLEN: usize = 65535;
+// Protocol names are at most 255 bytes, per section 8 of the spec.
+pub const MAXPROTOCOLNAMELEN: usize = 255;
 
 #[cfg(feature = "hfs")]
 pub const MAXKEMPUBLEN: usize = 4096;
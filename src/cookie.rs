@@ -0,0 +1,378 @@
+//! Stateless cookie-based DoS mitigation for responders on connectionless transports, modeled on
+//! [WireGuard's](https://www.wireguard.com/papers/wireguard.pdf) (section 5.4) MAC and cookie
+//! scheme.
+//!
+//! Every handshake message the initiator sends is followed by two MACs (see [`MACS_LEN`]):
+//!
+//! * `mac1` is keyed by the responder's static public key, so a responder can cheaply drop
+//!   messages from anyone who doesn't know who they're talking to, before doing any DH.
+//! * `mac2` is keyed by a *cookie*: a MAC of the initiator's source address under a secret only
+//!   the responder knows, which rotates every [`COOKIE_SECRET_LIFETIME`].
+//!
+//! When a responder is under load, it only accepts messages with a valid `mac2`, and answers the
+//! rest with a cookie reply instead of allocating a [`HandshakeState`](crate::HandshakeState).
+//! That way an attacker has to be able to receive packets at the address it claims before it
+//! can make the responder do any real work. The cookie is encrypted under a key derived from
+//! the responder's static public key and bound to the `mac1` it answers, so it can't be forged
+//! or replayed towards other initiators.
+//!
+//! The MACs are truncated HMACs with the protocol's hash function, and the cookie reply is
+//! encrypted with the protocol's cipher.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, cookie::*};
+//! # #[cfg(feature = "default-resolver")]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let params: snow::params::NoiseParams = "Noise_IK_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! # let keypair = Builder::new(params.clone()).generate_keypair()?;
+//! # let message = [0u8; 96];
+//! let mut generator = CookieGenerator::new(&params, &keypair.public)?;
+//! let mut consumer = CookieConsumer::new(&params, &keypair.public)?;
+//!
+//! // The initiator appends its MACs to a handshake message.
+//! let mut packet = [0u8; 128];
+//! packet[..96].copy_from_slice(&message);
+//! let len = consumer.seal(&mut packet, 96)?;
+//!
+//! // Under load, the responder asks for a cookie rather than handling the message...
+//! let source = b"198.51.100.7:51820";
+//! assert_eq!(generator.check(&packet[..len], source, true), CookieCheck::CookieRequired);
+//! let mut reply = [0u8; COOKIE_REPLY_LEN];
+//! generator.write_reply(&packet[..len], source, &mut reply)?;
+//!
+//! // ...which the initiator includes when it retries.
+//! consumer.read_reply(&reply)?;
+//! let len = consumer.seal(&mut packet, 96)?;
+//! assert_eq!(generator.check(&packet[..len], source, true), CookieCheck::Valid);
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(feature = "default-resolver"))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::{CIPHERKEYLEN, MAXHASHLEN, TAGLEN},
+    error::{Error, InitStage, StateProblem},
+    params::NoiseParams,
+    resolvers::BoxedCryptoResolver,
+    types::{Cipher, Hash, Random},
+};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+/// The length of each of `mac1` and `mac2`.
+pub const MAC_LEN: usize = 16;
+
+/// The number of bytes [`CookieConsumer::seal()`] appends to a message.
+pub const MACS_LEN: usize = 2 * MAC_LEN;
+
+/// The length of a cookie.
+pub const COOKIE_LEN: usize = 16;
+
+/// The length of the random salt a cookie reply's encryption key is derived from.
+pub const SALT_LEN: usize = 24;
+
+/// The length of a cookie reply: the salt followed by the encrypted cookie.
+pub const COOKIE_REPLY_LEN: usize = SALT_LEN + COOKIE_LEN + TAGLEN;
+
+/// How long a responder's cookie secret (and so every cookie made from it) stays valid.
+pub const COOKIE_SECRET_LIFETIME: Duration = Duration::from_secs(120);
+
+const SECRET_LEN: usize = 32;
+const LABEL_MAC1: &[u8] = b"mac1----";
+const LABEL_COOKIE: &[u8] = b"cookie--";
+
+/// The responder's verdict on a message's MACs, from [`CookieGenerator::check()`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CookieCheck {
+    /// The message can be handled.
+    Valid,
+
+    /// `mac1` is valid, but the responder is under load and `mac2` isn't. Answer with
+    /// [`CookieGenerator::write_reply()`] and drop the message.
+    CookieRequired,
+
+    /// `mac1` is invalid (or the message is too short to carry the MACs). Drop the message.
+    Invalid,
+}
+
+struct Keys {
+    hash:       Box<dyn Hash>,
+    cipher:     Box<dyn Cipher>,
+    mac1_key:   [u8; MAXHASHLEN],
+    cookie_key: [u8; MAXHASHLEN],
+}
+
+impl Keys {
+    fn new(
+        params: &NoiseParams,
+        responder_static: &[u8],
+        resolver: &BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        let mut hash = resolver.resolve_hash(&params.hash).ok_or(InitStage::GetHashImpl)?;
+        let cipher = resolver.resolve_cipher(&params.cipher).ok_or(InitStage::GetCipherImpl)?;
+        let mut mac1_key = [0u8; MAXHASHLEN];
+        let mut cookie_key = [0u8; MAXHASHLEN];
+        for (label, key) in [(LABEL_MAC1, &mut mac1_key), (LABEL_COOKIE, &mut cookie_key)] {
+            hash.reset();
+            hash.input(label);
+            hash.input(responder_static);
+            hash.result(key);
+        }
+        Ok(Keys { hash, cipher, mac1_key, cookie_key })
+    }
+
+    fn mac(&mut self, key: &[u8], data: &[&[u8]]) -> [u8; MAC_LEN] {
+        let data = data.concat();
+        let mut out = [0u8; MAXHASHLEN];
+        self.hash.hmac(key, &data, &mut out);
+        let mut mac = [0u8; MAC_LEN];
+        mac.copy_from_slice(&out[..MAC_LEN]);
+        mac
+    }
+
+    fn mac1(&mut self, message: &[u8]) -> [u8; MAC_LEN] {
+        let key = self.mac1_key;
+        self.mac(&key[..self.hash.hash_len()], &[message])
+    }
+
+    /// Key the cipher for a cookie reply with the given salt.
+    fn set_reply_key(&mut self, salt: &[u8]) {
+        let key = self.cookie_key;
+        let mut out = [0u8; MAXHASHLEN];
+        self.hash.hmac(&key[..self.hash.hash_len()], salt, &mut out);
+        self.cipher.set(&out[..CIPHERKEYLEN]);
+    }
+}
+
+fn split_macs(message: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let body_len = message.len().checked_sub(MACS_LEN)?;
+    let (body, macs) = message.split_at(body_len);
+    let (mac1, mac2) = macs.split_at(MAC_LEN);
+    Some((body, mac1, mac2))
+}
+
+/// The responder's side: checks the MACs on incoming messages and hands out cookies.
+pub struct CookieGenerator {
+    keys:           Keys,
+    rng:            Box<dyn Random>,
+    secret:         [u8; SECRET_LEN],
+    secret_created: Option<Instant>,
+}
+
+impl CookieGenerator {
+    /// Create a generator for a responder with the static public key `responder_static`, using
+    /// the default crypto resolver.
+    #[cfg(feature = "default-resolver")]
+    pub fn new(params: &NoiseParams, responder_static: &[u8]) -> Result<Self, Error> {
+        Self::with_resolver(params, responder_static, Box::new(crate::resolvers::DefaultResolver))
+    }
+
+    /// Create a generator for a responder with the static public key `responder_static`, using
+    /// a custom crypto resolver.
+    pub fn with_resolver(
+        params: &NoiseParams,
+        responder_static: &[u8],
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        let keys = Keys::new(params, responder_static, &resolver)?;
+        let rng = resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
+        Ok(CookieGenerator { keys, rng, secret: [0u8; SECRET_LEN], secret_created: None })
+    }
+
+    /// Check the MACs at the end of `message`, received from `source` (an encoding of the
+    /// initiator's address, e.g. its IP address and port).
+    ///
+    /// `mac2` is only required when `under_load` is true.
+    pub fn check(&mut self, message: &[u8], source: &[u8], under_load: bool) -> CookieCheck {
+        let (body, mac1, mac2) = match split_macs(message) {
+            Some(parts) => parts,
+            None => return CookieCheck::Invalid,
+        };
+        if !bool::from(self.keys.mac1(body).ct_eq(mac1)) {
+            return CookieCheck::Invalid;
+        }
+        if !under_load {
+            return CookieCheck::Valid;
+        }
+        match self.cookie(source) {
+            Ok(cookie) if bool::from(self.keys.mac(&cookie, &[body, mac1]).ct_eq(mac2)) => {
+                CookieCheck::Valid
+            },
+            _ => CookieCheck::CookieRequired,
+        }
+    }
+
+    /// Write a cookie reply to `message` (received from `source`) into `out`, returning its
+    /// length ([`COOKIE_REPLY_LEN`]).
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::TruncatedMessage` if `message` is too short to carry MACs,
+    /// `Error::BufferTooSmall` if `out` can't hold the reply, and `Error::Rng` if the RNG fails.
+    pub fn write_reply(
+        &mut self,
+        message: &[u8],
+        source: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        let (_, mac1, _) = split_macs(message)
+            .ok_or(Error::TruncatedMessage { needed: MACS_LEN, got: message.len() })?;
+        if out.len() < COOKIE_REPLY_LEN {
+            bail!(Error::BufferTooSmall { needed: COOKIE_REPLY_LEN, got: out.len() });
+        }
+        let cookie = self.cookie(source)?;
+        let (salt, ciphertext) = out[..COOKIE_REPLY_LEN].split_at_mut(SALT_LEN);
+        self.rng.try_fill_bytes(salt).map_err(|_| Error::Rng)?;
+        self.keys.set_reply_key(salt);
+        self.keys.cipher.encrypt(0, mac1, &cookie, ciphertext);
+        Ok(COOKIE_REPLY_LEN)
+    }
+
+    /// The cookie for `source`, rotating the secret first if it has expired.
+    fn cookie(&mut self, source: &[u8]) -> Result<[u8; COOKIE_LEN], Error> {
+        match self.secret_created {
+            Some(created) if created.elapsed() < COOKIE_SECRET_LIFETIME => {},
+            _ => {
+                self.rng.try_fill_bytes(&mut self.secret).map_err(|_| Error::Rng)?;
+                self.secret_created = Some(Instant::now());
+            },
+        }
+        let secret = self.secret;
+        Ok(self.keys.mac(&secret, &[source]))
+    }
+}
+
+/// The initiator's side: appends MACs to outgoing messages and remembers the cookies the
+/// responder hands out.
+pub struct CookieConsumer {
+    keys:      Keys,
+    last_mac1: Option<[u8; MAC_LEN]>,
+    cookie:    Option<([u8; COOKIE_LEN], Instant)>,
+}
+
+impl CookieConsumer {
+    /// Create a consumer for talking to a responder with the static public key
+    /// `responder_static`, using the default crypto resolver.
+    #[cfg(feature = "default-resolver")]
+    pub fn new(params: &NoiseParams, responder_static: &[u8]) -> Result<Self, Error> {
+        Self::with_resolver(params, responder_static, Box::new(crate::resolvers::DefaultResolver))
+    }
+
+    /// Create a consumer for talking to a responder with the static public key
+    /// `responder_static`, using a custom crypto resolver.
+    pub fn with_resolver(
+        params: &NoiseParams,
+        responder_static: &[u8],
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        let keys = Keys::new(params, responder_static, &resolver)?;
+        Ok(CookieConsumer { keys, last_mac1: None, cookie: None })
+    }
+
+    /// Append `mac1` and `mac2` to the `len`-byte message at the start of `buffer`, returning
+    /// the new length. `mac2` is all zeroes unless a cookie younger than
+    /// [`COOKIE_SECRET_LIFETIME`] has been received.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::BufferTooSmall` if `buffer` can't hold the MACs.
+    pub fn seal(&mut self, buffer: &mut [u8], len: usize) -> Result<usize, Error> {
+        let needed = len + MACS_LEN;
+        if buffer.len() < needed {
+            bail!(Error::BufferTooSmall { needed, got: buffer.len() });
+        }
+        let mac1 = self.keys.mac1(&buffer[..len]);
+        let mac2 = match self.cookie {
+            Some((cookie, received)) if received.elapsed() < COOKIE_SECRET_LIFETIME => {
+                self.keys.mac(&cookie, &[&buffer[..len], &mac1])
+            },
+            _ => [0u8; MAC_LEN],
+        };
+        buffer[len..len + MAC_LEN].copy_from_slice(&mac1);
+        buffer[len + MAC_LEN..needed].copy_from_slice(&mac2);
+        self.last_mac1 = Some(mac1);
+        Ok(needed)
+    }
+
+    /// Store the cookie from a responder's reply to the last message passed to
+    /// [`seal()`](Self::seal), to be used by the next call to it.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::TruncatedMessage` if `reply` is too short, `Error::State` if no
+    /// message has been sealed, and `Error::Decrypt` if the reply wasn't made by the responder
+    /// in answer to the last sealed message.
+    pub fn read_reply(&mut self, reply: &[u8]) -> Result<(), Error> {
+        if reply.len() < COOKIE_REPLY_LEN {
+            bail!(Error::TruncatedMessage { needed: COOKIE_REPLY_LEN, got: reply.len() });
+        }
+        let mac1 = self.last_mac1.ok_or(StateProblem::MissingKeyMaterial)?;
+        let (salt, ciphertext) = reply[..COOKIE_REPLY_LEN].split_at(SALT_LEN);
+        self.keys.set_reply_key(salt);
+        let mut cookie = [0u8; COOKIE_LEN];
+        self.keys.cipher.decrypt(0, &mac1, ciphertext, &mut cookie).map_err(|_| Error::Decrypt)?;
+        self.cookie = Some((cookie, Instant::now()));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "default-resolver"))]
+mod tests {
+    use super::*;
+
+    fn pair() -> (CookieGenerator, CookieConsumer) {
+        let params: NoiseParams = "Noise_IK_25519_AESGCM_SHA256".parse().unwrap();
+        let responder_static = [7u8; 32];
+        (
+            CookieGenerator::new(&params, &responder_static).unwrap(),
+            CookieConsumer::new(&params, &responder_static).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_mac1() {
+        let (mut generator, mut consumer) = pair();
+        let mut packet = [1u8; 64];
+        let len = consumer.seal(&mut packet, 32).unwrap();
+        assert_eq!(len, 32 + MACS_LEN);
+        assert_eq!(generator.check(&packet[..len], b"a", false), CookieCheck::Valid);
+        assert_eq!(generator.check(&packet[..len], b"a", true), CookieCheck::CookieRequired);
+        assert_eq!(generator.check(&packet[..MACS_LEN - 1], b"a", false), CookieCheck::Invalid);
+
+        packet[0] ^= 1;
+        assert_eq!(generator.check(&packet[..len], b"a", false), CookieCheck::Invalid);
+
+        let params: NoiseParams = "Noise_IK_25519_AESGCM_SHA256".parse().unwrap();
+        let mut stranger = CookieConsumer::new(&params, &[8u8; 32]).unwrap();
+        let len = stranger.seal(&mut packet, 32).unwrap();
+        assert_eq!(generator.check(&packet[..len], b"a", false), CookieCheck::Invalid);
+    }
+
+    #[test]
+    fn test_cookie_roundtrip() {
+        let (mut generator, mut consumer) = pair();
+        let mut packet = [1u8; 64];
+        let len = consumer.seal(&mut packet, 32).unwrap();
+        let mut reply = [0u8; COOKIE_REPLY_LEN];
+        generator.write_reply(&packet[..len], b"a", &mut reply).unwrap();
+
+        let mut tampered = reply;
+        tampered[SALT_LEN] ^= 1;
+        assert!(matches!(consumer.read_reply(&tampered), Err(Error::Decrypt)));
+        consumer.read_reply(&reply).unwrap();
+
+        let len = consumer.seal(&mut packet, 32).unwrap();
+        assert_eq!(generator.check(&packet[..len], b"a", true), CookieCheck::Valid);
+        // The cookie is bound to the address it was issued for.
+        assert_eq!(generator.check(&packet[..len], b"b", true), CookieCheck::CookieRequired);
+
+        // A reply can't be replayed against a different message.
+        packet[0] ^= 1;
+        consumer.seal(&mut packet, 32).unwrap();
+        assert!(matches!(consumer.read_reply(&reply), Err(Error::Decrypt)));
+    }
+}
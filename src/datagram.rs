@@ -0,0 +1,529 @@
+//! A ready-made wire format for datagram protocols: each message carries its own nonce.
+//!
+//! Over UDP and the like, messages get lost and reordered, so the receiver can't keep a nonce
+//! counter in step with the sender the way [`TransportState`](crate::TransportState) does.
+//! [`DatagramTransport`] wraps a [`StatelessTransportState`] instead, and frames each message as
+//! the 8-byte big-endian nonce followed by the ciphertext (the same layout WireGuard uses for its
+//! counter). The nonce is authenticated by the AEAD, since decrypting with any other nonce fails.
+//!
+//! Incoming nonces are checked against a [`ReplayWindow`], so a message is accepted at most once
+//! and messages that arrive up to [`REPLAY_WINDOW_LEN`] places out of order still get through.
+//!
+//! The receiver also keeps [`SequenceStats`] of the gaps, reorders and replays it saw in the
+//! incoming nonces, and can report each one as it happens to a [`SequenceObserver`], e.g. to
+//! feed loss estimates into congestion control or alerting.
+//!
+//! For servers that send on one session from many threads, [`SyncSender`] writes the same
+//! framing, handing out nonces atomically instead of through `&mut self`.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::datagram::*;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let (mut i, mut r) = snow::doctest::nn_handshake()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! let mut initiator = DatagramTransport::new(i.into_stateless_transport_mode()?);
+//! let mut responder = DatagramTransport::new(r.into_stateless_transport_mode()?);
+//!
+//! let (mut first, mut second) = ([0u8; 64], [0u8; 64]);
+//! let first_len = initiator.write_message(b"first", &mut first)?;
+//! let second_len = initiator.write_message(b"second", &mut second)?;
+//!
+//! // Out of order is fine...
+//! let len = responder.read_message(&second[..second_len], &mut buf)?;
+//! assert_eq!(&buf[..len], b"second");
+//! let len = responder.read_message(&first[..first_len], &mut buf)?;
+//! assert_eq!(&buf[..len], b"first");
+//!
+//! // ...but replays aren't.
+//! assert!(responder.read_message(&first[..first_len], &mut buf).is_err());
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    error::{Error, StateProblem},
+    StatelessTransportState,
+};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The length of the nonce prefixed to each message.
+pub const NONCE_LEN: usize = 8;
+
+/// How far behind the highest nonce seen a message may be and still be accepted.
+pub const REPLAY_WINDOW_LEN: u64 = 128;
+
+/// Tracks which nonces have been received, to reject replayed messages.
+///
+/// Remembers the highest nonce seen and which of the [`REPLAY_WINDOW_LEN`] nonces below it were
+/// seen too. Anything older than that is rejected, since it can't be told apart from a replay.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    seen:    u128,
+}
+
+impl ReplayWindow {
+    /// A window that hasn't seen any nonces yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a message with `nonce` would be new, without marking it as seen.
+    ///
+    /// Only mark a nonce with [`insert()`](Self::insert) once its message has been
+    /// authenticated, or forged messages could block genuine ones.
+    pub fn check(&self, nonce: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(highest) if nonce > highest => true,
+            Some(highest) => {
+                let age = highest - nonce;
+                age < REPLAY_WINDOW_LEN && self.seen & (1 << age) == 0
+            },
+        }
+    }
+
+    /// Mark `nonce` as seen, returning whether it was new.
+    pub fn insert(&mut self, nonce: u64) -> bool {
+        if !self.check(nonce) {
+            return false;
+        }
+        match self.highest {
+            Some(highest) if nonce <= highest => self.seen |= 1 << (highest - nonce),
+            Some(highest) => {
+                let shift = nonce - highest;
+                self.seen = if shift >= REPLAY_WINDOW_LEN { 1 } else { (self.seen << shift) | 1 };
+                self.highest = Some(nonce);
+            },
+            None => {
+                self.seen = 1;
+                self.highest = Some(nonce);
+            },
+        }
+        true
+    }
+
+    /// The highest nonce seen so far.
+    pub fn highest(&self) -> Option<u64> {
+        self.highest
+    }
+}
+
+/// Counts of what a [`DatagramTransport`] saw in the nonces of the messages it read.
+///
+/// Every nonce skipped by a gap either turns up later as a reorder or is lost, so
+/// `skipped - reordered` is how many messages are missing so far.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct SequenceStats {
+    /// Messages accepted.
+    pub accepted:  u64,
+    /// Times an accepted message skipped ahead of the next nonce expected.
+    pub gaps:      u64,
+    /// Nonces skipped by those gaps.
+    pub skipped:   u64,
+    /// Accepted messages whose nonce was below the highest one seen.
+    pub reordered: u64,
+    /// Messages rejected because their nonce was already accepted or had fallen out of the
+    /// replay window.
+    pub replayed:  u64,
+}
+
+/// Hears about the gaps, reorders and replays in a [`DatagramTransport`]'s incoming nonces as
+/// they happen.
+///
+/// Every method does nothing by default, so implementors only override what they care about.
+/// They're called inline from [`DatagramTransport::read_message()`], so they should be quick.
+pub trait SequenceObserver {
+    /// An accepted message skipped `count` nonces, starting from `first`.
+    fn gap(&mut self, _first: u64, _count: u64) {}
+
+    /// An accepted message with `nonce` arrived `behind` places below the highest nonce seen.
+    fn reordered(&mut self, _nonce: u64, _behind: u64) {}
+
+    /// A message with `nonce` was rejected as a replay.
+    ///
+    /// Replays are rejected before the message is authenticated, so anybody who can inject
+    /// packets can trigger this.
+    fn replayed(&mut self, _nonce: u64) {}
+}
+
+/// A [`StatelessTransportState`] that sends the nonce with every message and rejects replays.
+pub struct DatagramTransport {
+    transport:     StatelessTransportState,
+    sending_nonce: u64,
+    window:        ReplayWindow,
+    stats:         SequenceStats,
+    observer:      Option<Box<dyn SequenceObserver + Send>>,
+}
+
+impl fmt::Debug for DatagramTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatagramTransport")
+            .field("transport", &self.transport)
+            .field("sending_nonce", &self.sending_nonce)
+            .field("window", &self.window)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl DatagramTransport {
+    /// Frame messages for `transport`, starting from nonce 0 in both directions.
+    pub fn new(transport: StatelessTransportState) -> Self {
+        DatagramTransport {
+            transport,
+            sending_nonce: 0,
+            window: ReplayWindow::new(),
+            stats: SequenceStats::default(),
+            observer: None,
+        }
+    }
+
+    /// Report gaps, reorders and replays in the incoming nonces to `observer`.
+    pub fn with_sequence_observer(
+        mut self,
+        observer: impl SequenceObserver + Send + 'static,
+    ) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// The wrapped transport.
+    pub fn transport(&self) -> &StatelessTransportState {
+        &self.transport
+    }
+
+    /// The nonce the next message will be written with.
+    pub fn sending_nonce(&self) -> u64 {
+        self.sending_nonce
+    }
+
+    /// The nonces received so far.
+    pub fn replay_window(&self) -> &ReplayWindow {
+        &self.window
+    }
+
+    /// The gaps, reorders and replays seen in the incoming nonces so far.
+    pub fn sequence_stats(&self) -> SequenceStats {
+        self.stats
+    }
+
+    /// Encrypt `payload` under the next nonce and write the framed message to `message`.
+    ///
+    /// Returns the size of the framed message, nonce included.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::NonceExhausted)` if every nonce has been used,
+    /// and otherwise fails like [`StatelessTransportState::write_message()`], counting the nonce
+    /// towards the size of `message`.
+    pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        // The last nonce is reserved by the spec for rekeying.
+        if self.sending_nonce == u64::MAX {
+            bail!(StateProblem::NonceExhausted);
+        }
+        let len = write_framed(&self.transport, self.sending_nonce, payload, message)?;
+        self.sending_nonce += 1;
+        Ok(len)
+    }
+
+    /// Read a framed message, decrypting it into `payload` under the nonce it carries.
+    ///
+    /// Returns the size of the payload.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::TruncatedMessage` if `message` is too short to hold a nonce,
+    /// `Error::State(StateProblem::Replayed)` if its nonce was already accepted or has fallen
+    /// out of the replay window, and otherwise fails like
+    /// [`StatelessTransportState::read_message()`]. Failed messages don't affect the window.
+    pub fn read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<usize, Error> {
+        if message.len() < NONCE_LEN {
+            bail!(Error::TruncatedMessage { needed: NONCE_LEN, got: message.len() });
+        }
+        let (header, ciphertext) = message.split_at(NONCE_LEN);
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(header);
+        let nonce = u64::from_be_bytes(nonce);
+        if !self.window.check(nonce) {
+            self.stats.replayed += 1;
+            if let Some(observer) = &mut self.observer {
+                observer.replayed(nonce);
+            }
+            bail!(StateProblem::Replayed);
+        }
+        let len = self.transport.read_message(nonce, ciphertext, payload)?;
+        self.record_accepted(nonce);
+        self.window.insert(nonce);
+        Ok(len)
+    }
+
+    /// Count an authenticated message against the nonces seen before it.
+    fn record_accepted(&mut self, nonce: u64) {
+        self.stats.accepted += 1;
+        let expected = self.window.highest().map_or(0, |highest| highest + 1);
+        if nonce > expected {
+            self.stats.gaps += 1;
+            self.stats.skipped += nonce - expected;
+            if let Some(observer) = &mut self.observer {
+                observer.gap(expected, nonce - expected);
+            }
+        } else if nonce + 1 < expected {
+            self.stats.reordered += 1;
+            if let Some(observer) = &mut self.observer {
+                observer.reordered(nonce, expected - 1 - nonce);
+            }
+        }
+    }
+
+    /// Give up the framing and get back the transport.
+    pub fn into_inner(self) -> StatelessTransportState {
+        self.transport
+    }
+}
+
+/// Writes messages in the same framing as [`DatagramTransport`], from many threads at once.
+///
+/// Each message takes the next nonce from an atomic counter, so threads can encrypt
+/// concurrently through a shared `&SyncSender` (e.g. in an `Arc`) without serializing on a
+/// mutex around the whole write. Messages may then reach the wire in a different order than
+/// their nonces, which the receiver's [`ReplayWindow`] allows for up to [`REPLAY_WINDOW_LEN`]
+/// places.
+///
+/// A `SyncSender` only sends. To receive on the same session, read with the explicit nonce
+/// through [`transport()`](Self::transport), keeping a `ReplayWindow` of your own.
+#[derive(Debug)]
+pub struct SyncSender {
+    transport:     StatelessTransportState,
+    sending_nonce: AtomicU64,
+}
+
+impl SyncSender {
+    /// Send through `transport`, starting from nonce 0.
+    pub fn new(transport: StatelessTransportState) -> Self {
+        Self::starting_at(transport, 0)
+    }
+
+    /// Send through `transport`, starting from `nonce`, e.g. to take over from a
+    /// [`DatagramTransport`] that already sent some messages.
+    pub fn starting_at(transport: StatelessTransportState, nonce: u64) -> Self {
+        SyncSender { transport, sending_nonce: AtomicU64::new(nonce) }
+    }
+
+    /// The wrapped transport.
+    pub fn transport(&self) -> &StatelessTransportState {
+        &self.transport
+    }
+
+    /// The nonce the next message will be written with, unless another thread takes it first.
+    pub fn sending_nonce(&self) -> u64 {
+        self.sending_nonce.load(Ordering::Relaxed)
+    }
+
+    /// Encrypt `payload` under the next free nonce and write the framed message to `message`.
+    ///
+    /// Returns the size of the framed message, nonce included.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`DatagramTransport::write_message()`]. A write that fails after taking its
+    /// nonce still uses it up, so failed writes leave gaps in the nonces sent.
+    pub fn write_message(&self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        // Checked up front too, so an obviously short buffer doesn't use up a nonce.
+        if message.len() < NONCE_LEN {
+            bail!(Error::BufferTooSmall { needed: NONCE_LEN, got: message.len() });
+        }
+        // The last nonce is reserved by the spec for rekeying.
+        let nonce = self
+            .sending_nonce
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |nonce| {
+                if nonce == u64::MAX {
+                    None
+                } else {
+                    Some(nonce + 1)
+                }
+            })
+            .map_err(|_| StateProblem::NonceExhausted)?;
+        write_framed(&self.transport, nonce, payload, message)
+    }
+
+    /// Get back the transport.
+    pub fn into_inner(self) -> StatelessTransportState {
+        self.transport
+    }
+}
+
+/// Encrypt `payload` under `nonce`, writing the nonce and ciphertext to `message`.
+fn write_framed(
+    transport: &StatelessTransportState,
+    nonce: u64,
+    payload: &[u8],
+    message: &mut [u8],
+) -> Result<usize, Error> {
+    if message.len() < NONCE_LEN {
+        bail!(Error::BufferTooSmall { needed: NONCE_LEN, got: message.len() });
+    }
+    let (header, ciphertext) = message.split_at_mut(NONCE_LEN);
+    let len = match transport.write_message(nonce, payload, ciphertext) {
+        Ok(len) => len,
+        Err(Error::BufferTooSmall { needed, got }) => {
+            bail!(Error::BufferTooSmall { needed: needed + NONCE_LEN, got: got + NONCE_LEN })
+        },
+        Err(e) => return Err(e),
+    };
+    header.copy_from_slice(&nonce.to_be_bytes());
+    Ok(NONCE_LEN + len)
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::doctest::nn_handshake;
+
+    fn datagram_pair() -> (DatagramTransport, DatagramTransport) {
+        let (i, r) = nn_handshake().unwrap();
+        (
+            DatagramTransport::new(i.into_stateless_transport_mode().unwrap()),
+            DatagramTransport::new(r.into_stateless_transport_mode().unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::new();
+        assert!(window.insert(5));
+        assert!(!window.insert(5));
+        assert!(window.insert(0));
+        assert!(window.insert(200));
+        assert!(!window.check(5));
+        assert!(window.check(200 - REPLAY_WINDOW_LEN + 1));
+        assert!(!window.check(200 - REPLAY_WINDOW_LEN));
+        assert!(window.insert(199));
+        assert!(!window.insert(199));
+        assert!(window.insert(1000));
+        assert!(window.check(999));
+        assert_eq!(window.highest(), Some(1000));
+    }
+
+    #[test]
+    fn test_framing() {
+        let (mut i, mut r) = datagram_pair();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+
+        let len = i.write_message(b"abc", &mut msg).unwrap();
+        assert_eq!(len, NONCE_LEN + 3 + 16);
+        assert_eq!(&msg[..NONCE_LEN], &0u64.to_be_bytes());
+        assert_eq!(i.sending_nonce(), 1);
+
+        // A forged nonce fails to authenticate and leaves the window alone.
+        let mut forged = msg;
+        forged[NONCE_LEN - 1] = 1;
+        assert!(matches!(r.read_message(&forged[..len], &mut buf), Err(Error::Decrypt)));
+        assert!(r.replay_window().check(1));
+
+        assert_eq!(r.read_message(&msg[..len], &mut buf).unwrap(), 3);
+        assert!(matches!(
+            r.read_message(&msg[..len], &mut buf),
+            Err(Error::State(StateProblem::Replayed))
+        ));
+        assert!(matches!(
+            r.read_message(&msg[..4], &mut buf),
+            Err(Error::TruncatedMessage { needed: NONCE_LEN, got: 4 })
+        ));
+        assert!(matches!(
+            i.write_message(b"abc", &mut msg[..20]),
+            Err(Error::BufferTooSmall { needed: 27, got: 20 })
+        ));
+    }
+
+    #[test]
+    fn test_sequence_stats() {
+        #[derive(Default)]
+        struct Events(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+        impl SequenceObserver for Events {
+            fn gap(&mut self, first: u64, count: u64) {
+                self.0.lock().unwrap().push(format!("gap {} {}", first, count));
+            }
+
+            fn reordered(&mut self, nonce: u64, behind: u64) {
+                self.0.lock().unwrap().push(format!("reordered {} {}", nonce, behind));
+            }
+
+            fn replayed(&mut self, nonce: u64) {
+                self.0.lock().unwrap().push(format!("replayed {}", nonce));
+            }
+        }
+
+        let (mut i, r) = datagram_pair();
+        let events = Events::default();
+        let log = events.0.clone();
+        let mut r = r.with_sequence_observer(events);
+        let mut buf = [0u8; 64];
+
+        let messages: Vec<_> = (0..6)
+            .map(|_| {
+                let mut msg = [0u8; 64];
+                let len = i.write_message(b"abc", &mut msg).unwrap();
+                msg[..len].to_vec()
+            })
+            .collect();
+        for &n in &[1, 2, 5, 3, 3] {
+            let _ = r.read_message(&messages[n], &mut buf);
+        }
+
+        assert_eq!(*log.lock().unwrap(), ["gap 0 1", "gap 3 2", "reordered 3 2", "replayed 3"]);
+        assert_eq!(
+            r.sequence_stats(),
+            SequenceStats { accepted: 4, gaps: 2, skipped: 3, reordered: 1, replayed: 1 }
+        );
+    }
+
+    #[test]
+    fn test_sync_sender() {
+        let (i, mut r) = datagram_pair();
+        let sender = std::sync::Arc::new(SyncSender::new(i.into_inner()));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    (0..16)
+                        .map(|_| {
+                            let mut msg = [0u8; 64];
+                            let len = sender.write_message(b"abc", &mut msg).unwrap();
+                            msg[..len].to_vec()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut messages: Vec<_> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+        assert_eq!(sender.sending_nonce(), 64);
+
+        // Every message got a nonce of its own, and reads fine in whatever order it arrives.
+        messages.sort();
+        messages.dedup_by(|a, b| a[..NONCE_LEN] == b[..NONCE_LEN]);
+        assert_eq!(messages.len(), 64);
+        let mut buf = [0u8; 64];
+        for msg in messages.iter().rev() {
+            assert_eq!(r.read_message(msg, &mut buf).unwrap(), 3);
+        }
+
+        let transport = std::sync::Arc::try_unwrap(sender).unwrap().into_inner();
+        let sender = SyncSender::starting_at(transport, u64::MAX);
+        assert!(matches!(
+            sender.write_message(b"abc", &mut [0u8; 64]),
+            Err(Error::State(StateProblem::NonceExhausted))
+        ));
+    }
+}
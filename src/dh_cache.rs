@@ -0,0 +1,97 @@
+//! Caching static-static DH results, for servers that repeatedly handshake with the same clients.
+//!
+//! In patterns with an `ss` token (like `KK` and `IK`), every handshake between the same two
+//! static keys computes the same DH result. Sharing a [`StaticDhCache`] between handshakes (see
+//! [`Builder::static_dh_cache()`]) saves that scalar multiplication after the first handshake
+//! with each peer.
+//!
+//! The cache holds secrets equivalent to the long-term pairing of the two static keys, so it
+//! should be protected like the local private key, and a peer's entry dropped with
+//! [`StaticDhCache::forget()`] once that peer's key is no longer trusted. Results are zeroed
+//! whenever they leave the cache, whether forgotten, evicted, cleared or dropped with it, and
+//! its `Debug` output only shows how many there are.
+//!
+//! [`Builder::static_dh_cache()`]: crate::Builder::static_dh_cache
+
+use crate::{constants::MAXDHLEN, error::Error};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Mutex, MutexGuard},
+};
+use zeroize::Zeroizing;
+
+/// Results keyed by (local static public key, remote static public key).
+type Entries = HashMap<(Vec<u8>, Vec<u8>), Zeroizing<[u8; MAXDHLEN]>>;
+
+/// A bounded cache of `ss` DH results, keyed by the local and remote static public keys.
+pub struct StaticDhCache {
+    entries:  Mutex<Entries>,
+    capacity: usize,
+}
+
+impl fmt::Debug for StaticDhCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticDhCache")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StaticDhCache {
+    /// Create a cache holding up to `capacity` results. Once full, an arbitrary entry is evicted
+    /// to make room for each new one.
+    pub fn new(capacity: usize) -> Self {
+        StaticDhCache { entries: Mutex::new(HashMap::new()), capacity }
+    }
+
+    /// Drop every cached result involving the remote static key `remote_static`.
+    pub fn forget(&self, remote_static: &[u8]) {
+        self.entries().retain(|(_, remote), _| remote != remote_static);
+    }
+
+    /// Drop every cached result.
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    /// The number of cached results.
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Look up the result for a pair of static keys, computing and caching it if it's missing.
+    pub(crate) fn get_or_compute(
+        &self,
+        local_static: &[u8],
+        remote_static: &[u8],
+        compute: impl FnOnce() -> Result<[u8; MAXDHLEN], Error>,
+    ) -> Result<[u8; MAXDHLEN], Error> {
+        let key = (local_static.to_vec(), remote_static.to_vec());
+        if let Some(result) = self.entries().get(&key) {
+            return Ok(**result);
+        }
+
+        let result = compute()?;
+        let mut entries = self.entries();
+        if entries.len() >= self.capacity {
+            if let Some(evicted) = entries.keys().next().cloned() {
+                entries.remove(&evicted);
+            }
+        }
+        if self.capacity > 0 {
+            entries.insert(key, Zeroizing::new(result));
+        }
+        Ok(result)
+    }
+}
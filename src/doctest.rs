@@ -0,0 +1,48 @@
+//! Setup shared by the examples in the documentation and by the unit tests. Not part of the API.
+
+#[cfg(test)]
+use crate::TransportState;
+use crate::{params::NoiseParams, Builder, Error, HandshakeState};
+
+/// The protocol the examples run when they just need an established session.
+pub const NN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+
+/// An initiator and a responder that have finished an [`NN`] handshake with empty payloads.
+pub fn nn_handshake() -> Result<(HandshakeState, HandshakeState), Error> {
+    let params: NoiseParams = NN.parse()?;
+    let mut i = Builder::new(params.clone()).build_initiator()?;
+    let mut r = Builder::new(params).build_responder()?;
+    let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+    let len = i.write_message(&[], &mut msg)?;
+    r.read_message(&msg[..len], &mut buf)?;
+    let len = r.write_message(&[], &mut msg)?;
+    i.read_message(&msg[..len], &mut buf)?;
+    Ok((i, r))
+}
+
+/// An initiator and a responder for `protocol` that haven't sent anything yet, each with a
+/// fresh static key if the pattern needs one.
+#[cfg(test)]
+pub(crate) fn handshake_pair(protocol: &str) -> (HandshakeState, HandshakeState) {
+    let params: NoiseParams = protocol.parse().unwrap();
+    let build = |initiator| {
+        let mut builder = Builder::new(params.clone());
+        let key = builder.generate_keypair().unwrap().private;
+        if params.handshake.pattern.needs_local_static_key(initiator) {
+            builder = builder.local_private_key(&key);
+        }
+        if initiator {
+            builder.build_initiator().unwrap()
+        } else {
+            builder.build_responder().unwrap()
+        }
+    };
+    (build(true), build(false))
+}
+
+/// [`nn_handshake()`], in transport mode.
+#[cfg(test)]
+pub(crate) fn transport_pair() -> (TransportState, TransportState) {
+    let (i, r) = nn_handshake().unwrap();
+    (i.into_transport_mode().unwrap(), r.into_transport_mode().unwrap())
+}
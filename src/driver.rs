@@ -0,0 +1,277 @@
+//! Retransmitting handshake messages over unreliable transports, without doing any I/O.
+//!
+//! A [`HandshakeDriver`] wraps a [`HandshakeState`] and remembers the last handshake message it
+//! wrote. Until the peer's next message arrives, [`HandshakeDriver::poll_retransmit()`] hands that
+//! message out again whenever its timer fires, backing off exponentially. Incoming copies of the
+//! last message already read (the peer retransmitting because our reply got lost) are recognized
+//! and answered by resending our reply, instead of failing to decrypt.
+//!
+//! The caller owns the socket and the clock: every method that depends on time takes `now`, and
+//! [`HandshakeDriver::poll_timeout()`] says when to call `poll_retransmit()` next.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, driver::*};
+//! # use std::time::{Duration, Instant};
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let mut initiator = HandshakeDriver::new(Builder::new(params.clone()).build_initiator()?);
+//! let mut responder = HandshakeDriver::new(Builder::new(params).build_responder()?);
+//! let mut payload = [0u8; 1024];
+//! let now = Instant::now();
+//!
+//! // -> e (and the packet is lost)
+//! initiator.write_message(&[], now)?;
+//!
+//! // No reply in time, so the message is sent again.
+//! let later = initiator.poll_timeout().unwrap();
+//! let packet = initiator.poll_retransmit(later)?.unwrap().to_vec();
+//! assert_eq!(responder.read_message(&packet, &mut payload, later)?, Read::Message(0));
+//!
+//! // <- e, ee
+//! let reply = responder.write_message(&[], later)?.to_vec();
+//! initiator.read_message(&reply, &mut payload, later)?;
+//! assert!(initiator.poll_timeout().is_none());
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::MAXMSGLEN,
+    error::{Error, StateProblem},
+    HandshakeState, StatelessTransportState, TransportState,
+};
+use std::time::{Duration, Instant};
+
+/// How long to wait for a reply before the first retransmission, unless configured otherwise.
+pub const DEFAULT_INITIAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The longest wait between retransmissions, unless configured otherwise.
+pub const DEFAULT_MAX_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many times a message is retransmitted before giving up, unless configured otherwise.
+pub const DEFAULT_MAX_RETRANSMITS: u32 = 8;
+
+/// What [`HandshakeDriver::read_message()`] made of an incoming message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Read {
+    /// A new handshake message, whose payload of the given length was written to the buffer.
+    Message(usize),
+
+    /// A copy of the last message already read. If a reply to it was written, it's due for
+    /// retransmission right away, and [`HandshakeDriver::poll_retransmit()`] returns it.
+    Duplicate,
+}
+
+/// A [`HandshakeState`] that retransmits its last message until the peer answers it.
+#[derive(Debug)]
+pub struct HandshakeDriver {
+    state:           HandshakeState,
+    last_sent:       Vec<u8>,
+    last_received:   Vec<u8>,
+    deadline:        Option<Instant>,
+    timeout:         Duration,
+    retransmits:     u32,
+    initial_timeout: Duration,
+    max_timeout:     Duration,
+    max_retransmits: u32,
+}
+
+impl HandshakeDriver {
+    /// Drive `state` with the default timeouts.
+    pub fn new(state: HandshakeState) -> Self {
+        HandshakeDriver {
+            state,
+            last_sent: vec![],
+            last_received: vec![],
+            deadline: None,
+            timeout: DEFAULT_INITIAL_TIMEOUT,
+            retransmits: 0,
+            initial_timeout: DEFAULT_INITIAL_TIMEOUT,
+            max_timeout: DEFAULT_MAX_TIMEOUT,
+            max_retransmits: DEFAULT_MAX_RETRANSMITS,
+        }
+    }
+
+    /// Set how long to wait for a reply before the first retransmission of each message. Each
+    /// following wait is twice as long, up to `max_timeout`.
+    pub fn timeouts(mut self, initial_timeout: Duration, max_timeout: Duration) -> Self {
+        self.initial_timeout = initial_timeout;
+        self.max_timeout = max_timeout.max(initial_timeout);
+        self
+    }
+
+    /// Set how many times each message is retransmitted before giving up.
+    pub fn max_retransmits(mut self, max_retransmits: u32) -> Self {
+        self.max_retransmits = max_retransmits;
+        self
+    }
+
+    /// The wrapped handshake state, e.g. to check the remote static key.
+    pub fn state(&self) -> &HandshakeState {
+        &self.state
+    }
+
+    /// Write the next handshake message, returning it so it can be sent.
+    ///
+    /// Unless it's the last message of the handshake, the retransmission timer starts at `now`.
+    /// After the last message, there's no reply to wait for, but it's still resent when the
+    /// peer's previous message arrives again.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`HandshakeState::write_message()`].
+    pub fn write_message(&mut self, payload: &[u8], now: Instant) -> Result<&[u8], Error> {
+        let mut message = vec![0u8; MAXMSGLEN];
+        let len = self.state.write_message(payload, &mut message)?;
+        message.truncate(len);
+        self.last_sent = message;
+        self.timeout = self.initial_timeout;
+        self.retransmits = 0;
+        self.deadline =
+            if self.state.is_handshake_finished() { None } else { Some(now + self.timeout) };
+        Ok(&self.last_sent)
+    }
+
+    /// Read an incoming handshake message, recognizing copies of the last one.
+    ///
+    /// A new message answers the last one written, which stops its retransmission.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`HandshakeState::read_message()`], which leaves the handshake as it was, so
+    /// stray or forged packets can simply be dropped.
+    pub fn read_message(
+        &mut self,
+        message: &[u8],
+        payload: &mut [u8],
+        now: Instant,
+    ) -> Result<Read, Error> {
+        if !self.last_received.is_empty() && message == &self.last_received[..] {
+            if !self.state.is_my_turn() || self.state.is_handshake_finished() {
+                self.deadline = Some(now);
+            }
+            return Ok(Read::Duplicate);
+        }
+        let len = self.state.read_message(message, payload)?;
+        self.last_received = message.to_vec();
+        self.last_sent.clear();
+        self.deadline = None;
+        Ok(Read::Message(len))
+    }
+
+    /// When [`poll_retransmit()`](Self::poll_retransmit) should be called next, if a message is
+    /// waiting for a reply.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Get the last message written if it's due for retransmission at `now`, and restart its
+    /// timer with twice the timeout.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::HandshakeTimedOut)` once the message has been
+    /// retransmitted as many times as allowed without a reply.
+    pub fn poll_retransmit(&mut self, now: Instant) -> Result<Option<&[u8]>, Error> {
+        match self.deadline {
+            Some(deadline) if deadline <= now && !self.last_sent.is_empty() => {},
+            _ => return Ok(None),
+        }
+        if self.retransmits >= self.max_retransmits {
+            self.deadline = None;
+            bail!(StateProblem::HandshakeTimedOut);
+        }
+        self.retransmits += 1;
+        self.timeout = (self.timeout * 2).min(self.max_timeout);
+        self.deadline =
+            if self.state.is_handshake_finished() { None } else { Some(now + self.timeout) };
+        Ok(Some(&self.last_sent))
+    }
+
+    /// Give up the retransmission machinery and get back the handshake state.
+    pub fn into_inner(self) -> HandshakeState {
+        self.state
+    }
+
+    /// Convert the finished handshake into a `TransportState`.
+    ///
+    /// Once this is called, lost final messages can no longer be resent, so wait for the first
+    /// transport message from the peer if our message was the last one.
+    pub fn into_transport_mode(self) -> Result<TransportState, Error> {
+        self.state.into_transport_mode()
+    }
+
+    /// Convert the finished handshake into a `StatelessTransportState`.
+    pub fn into_stateless_transport_mode(self) -> Result<StatelessTransportState, Error> {
+        self.state.into_stateless_transport_mode()
+    }
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::doctest::handshake_pair;
+
+    fn driver_pair() -> (HandshakeDriver, HandshakeDriver) {
+        let (i, r) = handshake_pair("Noise_XX_25519_ChaChaPoly_BLAKE2s");
+        (HandshakeDriver::new(i), HandshakeDriver::new(r))
+    }
+
+    #[test]
+    fn test_backoff_and_give_up() {
+        let (i, _) = driver_pair();
+        let mut d = i.timeouts(Duration::from_secs(1), Duration::from_secs(3)).max_retransmits(3);
+        let start = Instant::now();
+        let sent = d.write_message(&[], start).unwrap().to_vec();
+        assert!(d.poll_retransmit(start).unwrap().is_none());
+
+        let mut now = start;
+        for wait in &[1, 2, 3] {
+            assert_eq!(d.poll_timeout(), Some(now + Duration::from_secs(*wait)));
+            now += Duration::from_secs(*wait);
+            assert_eq!(d.poll_retransmit(now).unwrap(), Some(&sent[..]));
+        }
+        now += Duration::from_secs(3);
+        assert!(matches!(
+            d.poll_retransmit(now),
+            Err(Error::State(StateProblem::HandshakeTimedOut))
+        ));
+        assert!(d.poll_timeout().is_none());
+    }
+
+    #[test]
+    fn test_duplicates_resend_reply() {
+        let (mut i, mut r) = driver_pair();
+        let mut payload = [0u8; 1024];
+        let now = Instant::now();
+
+        let msg1 = i.write_message(b"1", now).unwrap().to_vec();
+        assert_eq!(r.read_message(&msg1, &mut payload, now).unwrap(), Read::Message(1));
+        // Not answered yet, so there's nothing to resend.
+        assert_eq!(r.read_message(&msg1, &mut payload, now).unwrap(), Read::Duplicate);
+        assert!(r.poll_retransmit(now).unwrap().is_none());
+
+        let msg2 = r.write_message(b"2", now).unwrap().to_vec();
+        assert_eq!(i.read_message(&msg2, &mut payload, now).unwrap(), Read::Message(1));
+        assert!(i.poll_timeout().is_none());
+
+        // The initiator's final message is lost, so it sees msg2 again and resends.
+        let msg3 = i.write_message(b"3", now).unwrap().to_vec();
+        assert!(i.state().is_handshake_finished() && i.poll_timeout().is_none());
+        let later = r.poll_timeout().unwrap();
+        assert_eq!(r.poll_retransmit(later).unwrap(), Some(&msg2[..]));
+        assert_eq!(i.read_message(&msg2, &mut payload, later).unwrap(), Read::Duplicate);
+        assert_eq!(i.poll_retransmit(later).unwrap(), Some(&msg3[..]));
+        assert_eq!(r.read_message(&msg3, &mut payload, later).unwrap(), Read::Message(1));
+        assert!(r.poll_timeout().is_none());
+
+        assert!(i.into_transport_mode().is_ok());
+        assert!(r.into_transport_mode().is_ok());
+    }
+}
@@ -0,0 +1,386 @@
+//! Running Noise over an `embedded-io-async` stream, for Embassy and other embedded executors.
+//!
+//! This is the embedded counterpart of the `futures-io` [`stream`](crate::stream) module, and
+//! uses the same framing: every message is sent with a 2-byte big-endian length prefix.
+//! [`handshake()`] drives a [`HandshakeState`] to completion over a stream that implements
+//! [`Read`] + [`Write`], such as an `embassy-net` TCP socket or a UART, and returns an
+//! [`EmbeddedStream`] that implements both traits itself.
+//!
+//! Instead of allocating its buffers, an `EmbeddedStream<S, N>` keeps two [`heapless::Vec`]s of
+//! `N` bytes, which bounds the messages it can send and receive. Both peers have to agree on
+//! that bound: a message longer than `N` is refused with `Error::BufferTooSmall`. The default
+//! of [`DEFAULT_BUFFER_LEN`] fits a typical MTU.
+//!
+//! The futures aren't cancel-safe: dropping a `read` or `write` part way through loses the
+//! framing, and the stream can't be used after that.
+//!
+//! The crate itself still needs `std` (the resolvers hand out boxed primitives), so this is
+//! for targets with an allocator, like the ESP32 family under `esp-idf`.
+//!
+//! Requires the `embedded` feature.
+
+use crate::{
+    constants::{MAXMSGLEN, TAGLEN},
+    error::Error,
+    padding::LENGTH_PREFIX_LEN,
+    HandshakeState, TransportState,
+};
+use embedded_io_async::{ErrorKind, ErrorType, Read, ReadExactError, Write};
+use heapless::Vec;
+use std::fmt;
+
+/// The size of an [`EmbeddedStream`]'s message buffers, unless chosen otherwise.
+pub const DEFAULT_BUFFER_LEN: usize = 1024;
+
+/// The size of the big-endian length prefix on every message.
+const FRAME_HEADER_LEN: usize = 2;
+
+/// What went wrong with an [`EmbeddedStream`] or its handshake.
+#[derive(Debug)]
+pub enum StreamError<E> {
+    /// The underlying stream failed.
+    Io(E),
+
+    /// The stream ended in the middle of a message, or before the handshake was done.
+    UnexpectedEof,
+
+    /// The Noise session failed, e.g. a message didn't decrypt or didn't fit the buffers.
+    Noise(Error),
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error for StreamError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            StreamError::Io(e) => e.kind(),
+            StreamError::UnexpectedEof => ErrorKind::BrokenPipe,
+            StreamError::Noise(_) => ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl<E> From<Error> for StreamError<E> {
+    fn from(error: Error) -> Self {
+        StreamError::Noise(error)
+    }
+}
+
+impl<E> From<ReadExactError<E>> for StreamError<E> {
+    fn from(error: ReadExactError<E>) -> Self {
+        match error {
+            ReadExactError::UnexpectedEof => StreamError::UnexpectedEof,
+            ReadExactError::Other(e) => StreamError::Io(e),
+        }
+    }
+}
+
+/// Run the handshake in `state` over `stream`, with empty handshake payloads, and wrap the
+/// stream in the resulting transport.
+///
+/// # Errors
+///
+/// Fails with `StreamError::Io` if the stream does, `StreamError::UnexpectedEof` if it ends
+/// before the handshake does, and `StreamError::Noise` if the handshake fails or one of its
+/// messages doesn't fit in `N` bytes.
+pub async fn handshake<S, const N: usize>(
+    mut state: HandshakeState,
+    mut stream: S,
+) -> Result<EmbeddedStream<S, N>, StreamError<S::Error>>
+where
+    S: Read + Write,
+{
+    let mut frame = Vec::<u8, N>::new();
+    let mut payload = Vec::<u8, N>::new();
+    while !state.is_handshake_finished() {
+        fill(&mut frame, N);
+        if state.is_my_turn() {
+            let len = state.write_message(&[], &mut frame)?;
+            stream.write_all(&(len as u16).to_be_bytes()).await.map_err(StreamError::Io)?;
+            stream.write_all(&frame[..len]).await.map_err(StreamError::Io)?;
+            stream.flush().await.map_err(StreamError::Io)?;
+        } else {
+            let len =
+                read_frame(&mut stream, &mut frame).await?.ok_or(StreamError::UnexpectedEof)?;
+            fill(&mut payload, N);
+            state.read_message(&frame[..len], &mut payload)?;
+        }
+    }
+    Ok(EmbeddedStream::new(state.into_transport_mode()?, stream))
+}
+
+/// An encrypted stream over an established Noise session, holding messages of up to `N`
+/// bytes.
+///
+/// Each `write` sends one message, so it takes at most `N` less the tag (and a padding
+/// policy's length prefix) bytes at a time. Each `read` hands out what's left of the last
+/// message, or reads the next one.
+pub struct EmbeddedStream<S, const N: usize = DEFAULT_BUFFER_LEN> {
+    stream:     S,
+    transport:  TransportState,
+    frame:      Vec<u8, N>,
+    plaintext:  Vec<u8, N>,
+    handed_out: usize,
+}
+
+impl<S, const N: usize> EmbeddedStream<S, N> {
+    /// The most payload a single message carries.
+    const CHUNK_LEN: usize =
+        (if N < MAXMSGLEN { N } else { MAXMSGLEN }) - TAGLEN - LENGTH_PREFIX_LEN;
+
+    /// Wrap `stream` in an already established `transport`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is too small to hold a message with any payload at all.
+    pub fn new(transport: TransportState, stream: S) -> Self {
+        assert!(N > TAGLEN + LENGTH_PREFIX_LEN, "buffers too small to hold any payload");
+        EmbeddedStream {
+            stream,
+            transport,
+            frame: Vec::new(),
+            plaintext: Vec::new(),
+            handed_out: 0,
+        }
+    }
+
+    /// The Noise session.
+    pub fn transport(&self) -> &TransportState {
+        &self.transport
+    }
+
+    /// The Noise session, e.g. to rekey it.
+    pub fn transport_mut(&mut self) -> &mut TransportState {
+        &mut self.transport
+    }
+
+    /// Give up the stream and the session. Anything received but not yet read is lost.
+    pub fn into_inner(self) -> (TransportState, S) {
+        (self.transport, self.stream)
+    }
+}
+
+impl<S: ErrorType, const N: usize> ErrorType for EmbeddedStream<S, N> {
+    type Error = StreamError<S::Error>;
+}
+
+impl<S: Read + Write, const N: usize> Read for EmbeddedStream<S, N> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        // Empty messages carry nothing to read, so keep going until one that does.
+        while self.handed_out == self.plaintext.len() {
+            let len = match read_frame(&mut self.stream, &mut self.frame).await? {
+                Some(len) => len,
+                None => return Ok(0),
+            };
+            fill(&mut self.plaintext, N);
+            let len = self.transport.read_message(&self.frame[..len], &mut self.plaintext)?;
+            self.plaintext.truncate(len);
+            self.handed_out = 0;
+        }
+
+        let len = buf.len().min(self.plaintext.len() - self.handed_out);
+        buf[..len].copy_from_slice(&self.plaintext[self.handed_out..self.handed_out + len]);
+        self.handed_out += len;
+        Ok(len)
+    }
+}
+
+impl<S: Read + Write, const N: usize> Write for EmbeddedStream<S, N> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk = &buf[..buf.len().min(Self::CHUNK_LEN)];
+        fill(&mut self.frame, N);
+        let len = self.transport.write_message(chunk, &mut self.frame)?;
+        self.stream.write_all(&(len as u16).to_be_bytes()).await.map_err(StreamError::Io)?;
+        self.stream.write_all(&self.frame[..len]).await.map_err(StreamError::Io)?;
+        Ok(chunk.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.stream.flush().await.map_err(StreamError::Io)
+    }
+}
+
+/// Shows the session and how much is left to read, but none of the data.
+impl<S, const N: usize> fmt::Debug for EmbeddedStream<S, N> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("EmbeddedStream")
+            .field("transport", &self.transport)
+            .field("buffer_len", &N)
+            .field("unread", &(self.plaintext.len() - self.handed_out))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Grow `buf` to `len` bytes of zeros, `len` being at most its capacity.
+fn fill<const N: usize>(buf: &mut Vec<u8, N>, len: usize) {
+    buf.clear();
+    buf.resize(len, 0).expect("within capacity");
+}
+
+/// Read the next frame into `frame`, returning its length, or `None` if the stream ended
+/// cleanly before it.
+async fn read_frame<S: Read, const N: usize>(
+    stream: &mut S,
+    frame: &mut Vec<u8, N>,
+) -> Result<Option<usize>, StreamError<S::Error>> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    if stream.read(&mut header[..1]).await.map_err(StreamError::Io)? == 0 {
+        return Ok(None);
+    }
+    stream.read_exact(&mut header[1..]).await?;
+    let len = u16::from_be_bytes(header) as usize;
+    if len > N {
+        return Err(Error::BufferTooSmall { needed: len, got: N }.into());
+    }
+    fill(frame, len);
+    stream.read_exact(frame).await?;
+    Ok(Some(len))
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::Builder;
+    use std::{
+        collections::VecDeque,
+        convert::Infallible,
+        future::{poll_fn, Future},
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    const PARAMS: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+
+    /// One end of an in-memory pipe.
+    struct Pipe {
+        incoming: Arc<Mutex<VecDeque<u8>>>,
+        outgoing: Arc<Mutex<VecDeque<u8>>>,
+    }
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (a, b) = (Arc::default(), Arc::default());
+        (
+            Pipe { incoming: Arc::clone(&a), outgoing: Arc::clone(&b) },
+            Pipe { incoming: b, outgoing: a },
+        )
+    }
+
+    impl ErrorType for Pipe {
+        type Error = Infallible;
+    }
+
+    impl Read for Pipe {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            poll_fn(|_| {
+                let mut incoming = self.incoming.lock().unwrap();
+                if incoming.is_empty() {
+                    if Arc::strong_count(&self.incoming) == 1 {
+                        // The other end was dropped.
+                        return Poll::Ready(Ok(0));
+                    }
+                    // Only ever polled in a busy loop, so there's no need to wake anyone.
+                    return Poll::Pending;
+                }
+                let len = buf.len().min(incoming.len());
+                for (byte, b) in buf.iter_mut().zip(incoming.drain(..len)) {
+                    *byte = b;
+                }
+                Poll::Ready(Ok(len))
+            })
+            .await
+        }
+    }
+
+    impl Write for Pipe {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.outgoing.lock().unwrap().extend(buf);
+            Ok(buf.len())
+        }
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Poll both futures in turn until they're done.
+    fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let (mut a, mut b) = (Box::pin(a), Box::pin(b));
+        let (mut a_out, mut b_out) = (None, None);
+        while a_out.is_none() || b_out.is_none() {
+            if a_out.is_none() {
+                if let Poll::Ready(out) = a.as_mut().poll(&mut cx) {
+                    a_out = Some(out);
+                }
+            }
+            if b_out.is_none() {
+                if let Poll::Ready(out) = b.as_mut().poll(&mut cx) {
+                    b_out = Some(out);
+                }
+            }
+        }
+        (a_out.unwrap(), b_out.unwrap())
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        join(future, async {}).0
+    }
+
+    fn stream_pair<const N: usize>() -> (EmbeddedStream<Pipe, N>, EmbeddedStream<Pipe, N>) {
+        let (a, b) = pipe();
+        let initiator = Builder::new(PARAMS.parse().unwrap()).build_initiator().unwrap();
+        let responder = Builder::new(PARAMS.parse().unwrap()).build_responder().unwrap();
+        let (i, r) = join(handshake(initiator, a), handshake(responder, b));
+        (i.unwrap(), r.unwrap())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (mut i, mut r) = stream_pair::<64>();
+        let big: std::vec::Vec<u8> = (0..200u8).collect();
+
+        block_on(i.write_all(b"hack the planet")).unwrap();
+        let mut buf = [0u8; 4];
+        block_on(r.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"hack");
+
+        // Writes are split up to fit the 64-byte buffers.
+        block_on(i.write_all(&big)).unwrap();
+        assert_eq!(i.transport().sending_nonce(), 1 + big.len().div_ceil(64 - 16 - 2) as u64);
+        let mut rest = [0u8; 11];
+        block_on(r.read_exact(&mut rest)).unwrap();
+        assert_eq!(&rest, b" the planet");
+        let mut received = [0u8; 200];
+        block_on(r.read_exact(&mut received)).unwrap();
+        assert_eq!(&received[..], &big[..]);
+
+        drop(i);
+        assert_eq!(block_on(r.read(&mut buf)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_oversized_message() {
+        let (mut i, _) = stream_pair::<DEFAULT_BUFFER_LEN>();
+        let (_, mut r) = stream_pair::<64>();
+        r.stream.incoming = Arc::clone(&i.stream.outgoing);
+
+        block_on(i.write_all(&[0u8; 100])).unwrap();
+        assert!(matches!(
+            block_on(r.read(&mut [0u8; 100])),
+            Err(StreamError::Noise(Error::BufferTooSmall { needed: 116, got: 64 }))
+        ));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let (mut i, mut r) = stream_pair::<64>();
+        block_on(i.write_all(b"hack the planet")).unwrap();
+        drop(i);
+        r.stream.incoming.lock().unwrap().pop_back();
+        assert!(matches!(block_on(r.read(&mut [0u8; 16])), Err(StreamError::UnexpectedEof)));
+    }
+}
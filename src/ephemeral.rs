@@ -0,0 +1,136 @@
+//! Pre-generated ephemeral keys, for responders handling many handshakes per second.
+//!
+//! An [`EphemeralPool`] generates ephemeral keypairs on a background thread and keeps up to a
+//! fixed number of them ready. Handing it to [`Builder::ephemeral_pool()`] makes the handshake
+//! use one of those keypairs for its `e` token instead of generating one in the middle of
+//! `write_message()`, installing its public key as is rather than deriving it again. If the pool
+//! has run dry, the handshake generates its key as usual.
+//!
+//! Each keypair is handed out exactly once, and private keys are zeroed once dropped, whether
+//! they were used or left in the pool. The background thread stops when the pool is dropped.
+//! Since it needs threads, the pool can't be used on `wasm32-unknown-unknown`.
+//!
+//! [`Builder::ephemeral_pool()`]: crate::Builder::ephemeral_pool
+
+use crate::{
+    error::{Error, InitStage},
+    params::DHChoice,
+    resolvers::BoxedCryptoResolver,
+};
+use std::{
+    fmt,
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+    thread,
+};
+use zeroize::Zeroizing;
+
+/// A pre-generated ephemeral keypair, whose private key is zeroed when dropped.
+pub struct PooledKeypair {
+    /// The private key.
+    pub private: Zeroizing<Vec<u8>>,
+    /// The public key.
+    pub public:  Vec<u8>,
+}
+
+impl fmt::Debug for PooledKeypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledKeypair").field("public", &self.public).finish_non_exhaustive()
+    }
+}
+
+/// A pool of ephemeral keypairs, kept topped up by a background thread.
+pub struct EphemeralPool {
+    dh:   DHChoice,
+    keys: Mutex<Receiver<PooledKeypair>>,
+}
+
+impl EphemeralPool {
+    /// Start a pool of up to `capacity` keypairs for `dh`, using the default crypto resolver.
+    #[cfg(feature = "default-resolver")]
+    pub fn new(dh: DHChoice, capacity: usize) -> Result<Self, Error> {
+        Self::with_resolver(dh, capacity, Box::new(crate::resolvers::DefaultResolver))
+    }
+
+    /// Start a pool of up to `capacity` keypairs for `dh`, using a custom crypto resolver.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Init` if the resolver doesn't support `dh` or has no RNG.
+    pub fn with_resolver(
+        dh: DHChoice,
+        capacity: usize,
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        let mut keypair = resolver.resolve_dh(&dh).ok_or(InitStage::GetDhImpl)?;
+        let mut rng = resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        thread::spawn(move || {
+            // Stops once the RNG fails or the pool (and so the receiver) is dropped.
+            while keypair.generate(&mut *rng).is_ok() {
+                let pooled = PooledKeypair {
+                    private: Zeroizing::new(keypair.privkey().to_vec()),
+                    public:  keypair.pubkey().to_vec(),
+                };
+                if sender.send(pooled).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(EphemeralPool { dh, keys: Mutex::new(receiver) })
+    }
+
+    /// The DH function the pool generates keys for.
+    pub fn dh(&self) -> DHChoice {
+        self.dh
+    }
+
+    /// Take a keypair from the pool, if one is ready.
+    pub fn take(&self) -> Option<PooledKeypair> {
+        self.keys.lock().unwrap_or_else(|e| e.into_inner()).try_recv().ok()
+    }
+}
+
+#[cfg(all(test, feature = "default-resolver"))]
+mod tests {
+    use super::*;
+    use crate::resolvers::{CryptoResolver, DefaultResolver};
+
+    fn wait_for_key(pool: &EphemeralPool) -> PooledKeypair {
+        loop {
+            if let Some(key) = pool.take() {
+                return key;
+            }
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_keys_are_unique() {
+        let pool = EphemeralPool::new(DHChoice::Curve25519, 2).unwrap();
+        let a = wait_for_key(&pool);
+        let b = wait_for_key(&pool);
+        assert_eq!(a.private.len(), 32);
+        assert_ne!(a.private, b.private);
+        assert_ne!(a.public, b.public);
+    }
+
+    #[test]
+    fn test_public_key_matches() {
+        let pool = EphemeralPool::new(DHChoice::Curve25519, 1).unwrap();
+        let pooled = wait_for_key(&pool);
+        let mut dh = DefaultResolver.resolve_dh(&DHChoice::Curve25519).unwrap();
+        dh.set(&pooled.private);
+        assert_eq!(dh.pubkey(), &pooled.public[..]);
+    }
+
+    #[test]
+    fn test_unsupported_dh() {
+        assert!(matches!(
+            EphemeralPool::new(DHChoice::Ed448, 2),
+            Err(Error::Init(InitStage::GetDhImpl))
+        ));
+    }
+}
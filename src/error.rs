@@ -1,5 +1,6 @@
 //! All error types used by Snow operations.
 
+use crate::params::{HandshakeChoice, Token};
 use std::fmt;
 
 /// All errors in snow will include an `ErrorKind`.
@@ -16,18 +17,74 @@ pub enum Error {
     /// Missing prerequisite.
     Prereq(Prerequisite),
 
+    /// Something the handshake pattern needs for this side wasn't given, e.g. the responder's
+    /// static key for an `IK` initiator.
+    PatternPrereq {
+        /// What's missing.
+        missing:   Prerequisite,
+        /// The handshake pattern, with its modifiers.
+        pattern:   String,
+        /// Whether it's the initiator that needs it.
+        initiator: bool,
+    },
+
     /// A state error.
     State(StateProblem),
 
+    /// Processing one of the tokens of a handshake message failed.
+    Handshake {
+        /// The index of the message in the handshake pattern, starting from 0.
+        message: usize,
+        /// The token being processed.
+        token:   Token,
+        /// What went wrong.
+        source:  Box<Error>,
+    },
+
     /// Invalid input.
     Input,
 
+    /// The output buffer can't hold the result of the operation.
+    BufferTooSmall {
+        /// The number of bytes the operation needed.
+        needed: usize,
+        /// The size of the buffer that was provided.
+        got:    usize,
+    },
+
+    /// The RNG failed to provide entropy.
+    Rng,
+
     /// Diffie-hellman failed.
     Dh,
 
     /// Decryption failed.
     Decrypt,
 
+    /// The message is shorter than its contents require, so it was never decrypted.
+    TruncatedMessage {
+        /// The number of bytes the message needed.
+        needed: usize,
+        /// The length of the message that was provided.
+        got:    usize,
+    },
+
+    /// The message is longer than the receiver accepts (see
+    /// [`Builder::max_message_len()`](crate::Builder::max_message_len)), so it was never
+    /// decrypted.
+    MessageTooLarge {
+        /// The longest message accepted.
+        max: usize,
+        /// The length of the message that was provided.
+        got: usize,
+    },
+
+    /// Signature verification failed.
+    Sig,
+
+    /// The two sides bound different prologues.
+    Prologue,
+
     /// Key-encapsulation failed
     #[cfg(feature = "hfs")]
     Kem,
@@ -37,6 +94,7 @@ pub enum Error {
 /// the specific cause of an `Init` error.
 #[allow(missing_docs)]
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum PatternProblem {
     TooFewParameters,
     UnsupportedHandshakeType,
@@ -46,10 +104,42 @@ pub enum PatternProblem {
     UnsupportedCipherType,
     InvalidPsk,
     UnsupportedModifier,
+    UnsupportedSigType,
     #[cfg(feature = "hfs")]
     UnsupportedKemType,
+    ProtocolNameTooLong,
+    NotFipsApproved,
+}
+
+impl PatternProblem {
+    /// A short description of the problem, usable in `const` contexts.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            PatternProblem::TooFewParameters => "too few parameters",
+            PatternProblem::UnsupportedHandshakeType => "unsupported handshake pattern",
+            PatternProblem::UnsupportedBaseType => "unsupported base protocol",
+            PatternProblem::UnsupportedHashType => "unsupported hash function",
+            PatternProblem::UnsupportedDhType => "unsupported DH function",
+            PatternProblem::UnsupportedCipherType => "unsupported cipher",
+            PatternProblem::InvalidPsk => "invalid psk modifier",
+            PatternProblem::UnsupportedModifier => "unsupported handshake modifier",
+            PatternProblem::UnsupportedSigType => "unsupported signature algorithm",
+            #[cfg(feature = "hfs")]
+            PatternProblem::UnsupportedKemType => "unsupported KEM",
+            PatternProblem::ProtocolNameTooLong => "protocol name longer than 255 bytes",
+            PatternProblem::NotFipsApproved => "primitive not approved in FIPS mode",
+        }
+    }
 }
 
+impl fmt::Display for PatternProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::error::Error for PatternProblem {}
+
 impl From<PatternProblem> for Error {
     fn from(reason: PatternProblem) -> Self {
         Error::Pattern(reason)
@@ -60,6 +150,7 @@ impl From<PatternProblem> for Error {
 /// the specific cause of an `Init` error.
 #[allow(missing_docs)]
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum InitStage {
     ValidateKeyLengths,
     ValidatePskLengths,
@@ -68,11 +159,40 @@ pub enum InitStage {
     GetDhImpl,
     GetCipherImpl,
     GetHashImpl,
+    GetSigImpl,
     #[cfg(feature = "hfs")]
     GetKemImpl,
     ValidatePskPosition,
+    ValidateEphemeralPool,
+    ValidateEphemeralKeypair,
+}
+
+impl fmt::Display for InitStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitStage::ValidateKeyLengths => write!(f, "key has the wrong length"),
+            InitStage::ValidatePskLengths => write!(f, "psk has the wrong length"),
+            InitStage::ValidateCipherTypes => write!(f, "invalid cipher"),
+            InitStage::GetRngImpl => write!(f, "resolver has no RNG"),
+            InitStage::GetDhImpl => write!(f, "resolver doesn't support the DH function"),
+            InitStage::GetCipherImpl => write!(f, "resolver doesn't support the cipher"),
+            InitStage::GetHashImpl => write!(f, "resolver doesn't support the hash function"),
+            InitStage::GetSigImpl => write!(f, "resolver doesn't support the signature algorithm"),
+            #[cfg(feature = "hfs")]
+            InitStage::GetKemImpl => write!(f, "resolver doesn't support the KEM"),
+            InitStage::ValidatePskPosition => write!(f, "invalid psk position"),
+            InitStage::ValidateEphemeralPool => {
+                write!(f, "ephemeral pool is for a different DH function")
+            },
+            InitStage::ValidateEphemeralKeypair => {
+                write!(f, "ephemeral public key doesn't belong to the private key")
+            },
+        }
+    }
 }
 
+impl std::error::Error for InitStage {}
+
 impl From<InitStage> for Error {
     fn from(reason: InitStage) -> Self {
         Error::Init(reason)
@@ -82,11 +202,34 @@ impl From<InitStage> for Error {
 /// A prerequisite that may be missing.
 #[allow(missing_docs)]
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Prerequisite {
     LocalPrivateKey,
     RemotePublicKey,
+    /// The PSK for the given `pskN` modifier wasn't set, either on the `Builder` or with
+    /// `HandshakeState::set_psk()` before the handshake reached it.
+    Psk(u8),
+}
+
+impl fmt::Display for Prerequisite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prerequisite::LocalPrivateKey => write!(f, "local_private_key"),
+            Prerequisite::RemotePublicKey => write!(f, "remote_public_key"),
+            Prerequisite::Psk(n) => write!(f, "psk{}", n),
+        }
+    }
+}
+
+impl Prerequisite {
+    /// This prerequisite, as needed by `handshake` for the initiator or responder.
+    pub(crate) fn for_pattern(self, handshake: &HandshakeChoice, initiator: bool) -> Error {
+        Error::PatternPrereq { missing: self, pattern: handshake.to_string(), initiator }
+    }
 }
 
+impl std::error::Error for Prerequisite {}
+
 impl From<Prerequisite> for Error {
     fn from(reason: Prerequisite) -> Self {
         Error::Prereq(reason)
@@ -96,8 +239,10 @@ impl From<Prerequisite> for Error {
 /// Specific errors in the state machine.
 #[allow(missing_docs)]
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum StateProblem {
     MissingKeyMaterial,
+    #[deprecated(note = "missing PSKs are reported as `Prerequisite::Psk`")]
     MissingPsk,
     NotTurnToWrite,
     NotTurnToRead,
@@ -105,8 +250,64 @@ pub enum StateProblem {
     HandshakeAlreadyFinished,
     OneWay,
     StatelessTransportMode,
+    UnknownSession,
+    TicketExpired,
+    EarlyDataUnavailable,
+    HandshakeTimedOut,
+    Replayed,
+    NonceExhausted,
+    PinMismatch,
+    NotPinned,
+    NoMutualProtocol,
+    UnexportableSession,
+    NonceReservationExhausted,
+    Closed,
+    Truncated,
+    KeyUsedInBothRoles,
 }
 
+impl fmt::Display for StateProblem {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateProblem::MissingKeyMaterial => write!(f, "missing key material"),
+            StateProblem::MissingPsk => write!(f, "missing psk"),
+            StateProblem::NotTurnToWrite => write!(f, "not our turn to write"),
+            StateProblem::NotTurnToRead => write!(f, "not our turn to read"),
+            StateProblem::HandshakeNotFinished => write!(f, "handshake not finished"),
+            StateProblem::HandshakeAlreadyFinished => write!(f, "handshake already finished"),
+            StateProblem::OneWay => write!(f, "one-way pattern doesn't allow this direction"),
+            StateProblem::StatelessTransportMode => {
+                write!(f, "protocol doesn't support stateless transport mode")
+            },
+            StateProblem::UnknownSession => write!(f, "no session under that key"),
+            StateProblem::TicketExpired => write!(f, "resumption ticket expired"),
+            StateProblem::EarlyDataUnavailable => {
+                write!(f, "first handshake message can't carry encrypted early data")
+            },
+            StateProblem::HandshakeTimedOut => write!(f, "handshake message was never answered"),
+            StateProblem::Replayed => write!(f, "message nonce was already seen or is too old"),
+            StateProblem::NonceExhausted => write!(f, "no nonces left to send with"),
+            StateProblem::PinMismatch => {
+                write!(f, "remote static key doesn't match the key pinned for this peer")
+            },
+            StateProblem::NotPinned => write!(f, "no remote static key is pinned for this peer"),
+            StateProblem::NoMutualProtocol => write!(f, "no protocol is supported by both sides"),
+            StateProblem::UnexportableSession => write!(f, "session state can't be exported"),
+            StateProblem::NonceReservationExhausted => {
+                write!(f, "every reserved nonce has been used")
+            },
+            StateProblem::Closed => write!(f, "session was closed"),
+            StateProblem::Truncated => write!(f, "session ended without being closed"),
+            StateProblem::KeyUsedInBothRoles => {
+                write!(f, "static key was already used in the other role of this protocol")
+            },
+        }
+    }
+}
+
+impl std::error::Error for StateProblem {}
+
 impl From<StateProblem> for Error {
     fn from(reason: StateProblem) -> Self {
         Error::State(reason)
@@ -116,17 +317,60 @@ impl From<StateProblem> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Pattern(reason) => write!(f, "pattern error: {:?}", reason),
-            Error::Init(reason) => write!(f, "initialization error: {:?}", reason),
-            Error::Prereq(reason) => write!(f, "prerequisite error: {:?}", reason),
-            Error::State(reason) => write!(f, "state error: {:?}", reason),
+            Error::Pattern(reason) => write!(f, "pattern error: {}", reason),
+            Error::Init(reason) => write!(f, "initialization error: {}", reason),
+            Error::Prereq(reason) => write!(f, "missing prerequisite: {}", reason),
+            Error::PatternPrereq { missing, pattern, initiator } => write!(
+                f,
+                "missing prerequisite: pattern {} requires {} for the {}",
+                pattern,
+                missing,
+                if *initiator { "initiator" } else { "responder" }
+            ),
+            Error::State(reason) => write!(f, "state error: {}", reason),
+            Error::Handshake { message, token, source } => {
+                write!(f, "handshake message {} failed at token {}: {}", message, token, source)
+            },
             Error::Input => write!(f, "input error"),
+            Error::BufferTooSmall { needed, got } => {
+                write!(f, "buffer too small: needed {} bytes, got {}", needed, got)
+            },
+            Error::Rng => write!(f, "RNG error"),
             Error::Dh => write!(f, "diffie-hellman error"),
             Error::Decrypt => write!(f, "decrypt error"),
+            Error::TruncatedMessage { needed, got } => {
+                write!(f, "truncated message: needed {} bytes, got {}", needed, got)
+            },
+            Error::MessageTooLarge { max, got } => {
+                write!(f, "message too large: at most {} bytes accepted, got {}", max, got)
+            },
+            Error::Sig => write!(f, "signature error"),
+            Error::Prologue => write!(f, "prologue mismatch"),
             #[cfg(feature = "hfs")]
             Error::Kem => write!(f, "kem error"),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Pattern(reason) => Some(reason),
+            Error::Init(reason) => Some(reason),
+            Error::Prereq(reason) => Some(reason),
+            Error::PatternPrereq { missing, .. } => Some(missing),
+            Error::State(reason) => Some(reason),
+            Error::Handshake { source, .. } => Some(&**source),
+            _ => None,
+        }
+    }
+}
+
+/// Lets `wasm-bindgen` exports return `Result<_, snow::Error>`, surfacing the error to JavaScript
+/// as an `Error` object carrying the `Display` text.
+#[cfg(feature = "wasm")]
+impl From<Error> for wasm_bindgen::JsValue {
+    fn from(e: Error) -> Self {
+        wasm_bindgen::JsError::new(&e.to_string()).into()
+    }
+}
@@ -0,0 +1,663 @@
+//! A C ABI over the handshake and transport APIs, for consuming snow from C, C++, Go, etc.
+//!
+//! Every object is an opaque, heap-allocated handle that must be released with its matching
+//! `*_free` function. Every fallible function returns a [`ScreechError`] code, with
+//! `SCREECH_OK` (zero) meaning success, and hands its results back through out-pointers.
+//!
+//! Buffers are passed as a pointer and a length. A null pointer is only accepted alongside a
+//! zero length. Functions that write into a caller-provided buffer report the number of bytes
+//! written through `out_len`; if the buffer was too small they return
+//! `SCREECH_ERROR_BUFFER_TOO_SMALL` and report the number of bytes that *would* have been needed
+//! instead, so the call can be retried. For handshake messages that is a lower bound: the space
+//! needed to get past the first field that didn't fit.
+//!
+//! A panic never unwinds into the caller: functions returning a [`ScreechError`] report it as
+//! `SCREECH_ERROR_PANIC`, and the others return as if given a null handle. A handle that was in
+//! use when its function panicked may be left in an inconsistent state, and should only be freed.
+//!
+//! The matching header lives at `include/screech.h`. To build a shared or static library, run
+//! `cargo rustc --release --features ffi --crate-type cdylib` (or `staticlib`).
+//!
+//! ```c
+//! ScreechBuilder *builder;
+//! ScreechHandshake *initiator;
+//! screech_builder_new("Noise_NN_25519_ChaChaPoly_BLAKE2s", &builder);
+//! screech_builder_build_initiator(builder, &initiator);
+//! screech_builder_free(builder);
+//!
+//! uint8_t message[65535];
+//! size_t len;
+//! screech_handshake_write_message(initiator, NULL, 0, message, sizeof(message), &len);
+//! ```
+
+use crate::{error::Error, Builder, HandshakeState, Keypair, TransportState};
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_int},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+};
+use zeroize::Zeroizing;
+
+/// The status code returned by every fallible FFI function.
+///
+/// Each non-zero code corresponds to a variant of [`Error`], apart from
+/// `SCREECH_ERROR_NULL_POINTER`, which reports a misuse of the C API itself, and
+/// `SCREECH_ERROR_PANIC`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreechError {
+    /// The call succeeded.
+    Ok               = 0,
+    /// A required pointer was null, or a buffer pointer was null with a non-zero length.
+    NullPointer      = 1,
+    /// See [`Error::Pattern`].
+    Pattern          = 2,
+    /// See [`Error::Init`].
+    Init             = 3,
+    /// See [`Error::Prereq`] and [`Error::PatternPrereq`].
+    Prereq           = 4,
+    /// See [`Error::State`].
+    State            = 5,
+    /// See [`Error::Input`].
+    Input            = 6,
+    /// See [`Error::BufferTooSmall`].
+    BufferTooSmall   = 7,
+    /// See [`Error::Rng`].
+    Rng              = 8,
+    /// See [`Error::Dh`].
+    Dh               = 9,
+    /// See [`Error::Decrypt`].
+    Decrypt          = 10,
+    /// See [`Error::TruncatedMessage`].
+    TruncatedMessage = 11,
+    /// See [`Error::Sig`].
+    Sig              = 12,
+    /// See [`Error::Prologue`].
+    Prologue         = 13,
+    /// Key-encapsulation failed (only with the `hfs` feature).
+    Kem              = 14,
+    /// See [`Error::MessageTooLarge`].
+    MessageTooLarge  = 15,
+    /// The call panicked. The panic was caught before it could unwind into the caller.
+    Panic            = 16,
+}
+
+impl From<&Error> for ScreechError {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::Pattern(_) => ScreechError::Pattern,
+            Error::Init(_) => ScreechError::Init,
+            Error::Prereq(_) | Error::PatternPrereq { .. } => ScreechError::Prereq,
+            Error::State(_) => ScreechError::State,
+            Error::Handshake { source, .. } => ScreechError::from(&**source),
+            Error::Input => ScreechError::Input,
+            Error::BufferTooSmall { .. } => ScreechError::BufferTooSmall,
+            Error::Rng => ScreechError::Rng,
+            Error::Dh => ScreechError::Dh,
+            Error::Decrypt => ScreechError::Decrypt,
+            Error::TruncatedMessage { .. } => ScreechError::TruncatedMessage,
+            Error::MessageTooLarge { .. } => ScreechError::MessageTooLarge,
+            Error::Sig => ScreechError::Sig,
+            Error::Prologue => ScreechError::Prologue,
+            #[cfg(feature = "hfs")]
+            Error::Kem => ScreechError::Kem,
+        }
+    }
+}
+
+/// An opaque handle configuring new handshakes. Unlike [`Builder`], it owns copies of the keys
+/// it's given and can build any number of handshakes.
+pub struct ScreechBuilder {
+    params:            crate::params::NoiseParams,
+    local_private_key: Option<Zeroizing<Vec<u8>>>,
+    remote_public_key: Option<Vec<u8>>,
+    prologue:          Option<Vec<u8>>,
+    psks:              Vec<(u8, Zeroizing<Vec<u8>>)>,
+}
+
+impl ScreechBuilder {
+    fn builder(&self) -> Builder<'_> {
+        let mut builder = Builder::new(self.params.clone());
+        if let Some(key) = &self.local_private_key {
+            builder = builder.local_private_key(key);
+        }
+        if let Some(key) = &self.remote_public_key {
+            builder = builder.remote_public_key(key);
+        }
+        if let Some(prologue) = &self.prologue {
+            builder = builder.prologue(prologue);
+        }
+        for (location, key) in &self.psks {
+            builder = builder.psk(*location, key);
+        }
+        builder
+    }
+}
+
+/// An opaque handle to a [`HandshakeState`].
+pub struct ScreechHandshake(HandshakeState);
+
+/// An opaque handle to a [`TransportState`].
+pub struct ScreechTransport(TransportState);
+
+/// Runs the body of an FFI function, turning a panic into `SCREECH_ERROR_PANIC`.
+fn guard(f: impl FnOnce() -> ScreechError) -> ScreechError {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(ScreechError::Panic)
+}
+
+macro_rules! try_ffi {
+    ($e:expr) => {
+        match $e {
+            Ok(v) => v,
+            Err(e) => return ScreechError::from(&e),
+        }
+    };
+}
+
+macro_rules! deref_mut {
+    ($ptr:expr) => {
+        match $ptr.as_mut() {
+            Some(v) => v,
+            None => return ScreechError::NullPointer,
+        }
+    };
+}
+
+unsafe fn input<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        Some(&[])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(data, len))
+    }
+}
+
+unsafe fn output<'a>(data: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    if len == 0 {
+        Some(&mut [])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts_mut(data, len))
+    }
+}
+
+/// Reports the length written (or, if the buffer was too small, needed) through `out_len`.
+unsafe fn write_out(out_len: *mut usize, result: Result<usize, Error>) -> ScreechError {
+    let out_len = deref_mut!(out_len);
+    match result {
+        Ok(len) => {
+            *out_len = len;
+            ScreechError::Ok
+        },
+        Err(e) => {
+            if let Error::BufferTooSmall { needed, .. } = e {
+                *out_len = needed;
+            }
+            ScreechError::from(&e)
+        },
+    }
+}
+
+/// Create a builder for the NUL-terminated Noise protocol name `name`, storing it in `*out`.
+///
+/// # Safety
+///
+/// `name` must be a valid NUL-terminated string and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_new(
+    name: *const c_char,
+    out: *mut *mut ScreechBuilder,
+) -> ScreechError {
+    guard(|| {
+        if name.is_null() || out.is_null() {
+            return ScreechError::NullPointer;
+        }
+        let name = try_ffi!(CStr::from_ptr(name).to_str().map_err(|_| Error::Input));
+        let params = try_ffi!(name.parse());
+        *out = Box::into_raw(Box::new(ScreechBuilder {
+            params,
+            local_private_key: None,
+            remote_public_key: None,
+            prologue: None,
+            psks: vec![],
+        }));
+        ScreechError::Ok
+    })
+}
+
+/// Release a builder. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `builder` must be null or a handle from [`screech_builder_new`] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_free(builder: *mut ScreechBuilder) {
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        if !builder.is_null() {
+            drop(Box::from_raw(builder));
+        }
+    }));
+}
+
+/// Set the local static private key. The key is copied.
+///
+/// # Safety
+///
+/// `builder` must be a live handle and `key` must be valid for `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_local_private_key(
+    builder: *mut ScreechBuilder,
+    key: *const u8,
+    key_len: usize,
+) -> ScreechError {
+    guard(|| {
+        let builder = deref_mut!(builder);
+        match input(key, key_len) {
+            Some(key) => builder.local_private_key = Some(Zeroizing::new(key.to_vec())),
+            None => return ScreechError::NullPointer,
+        }
+        ScreechError::Ok
+    })
+}
+
+/// Set the remote static public key. The key is copied.
+///
+/// # Safety
+///
+/// `builder` must be a live handle and `key` must be valid for `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_remote_public_key(
+    builder: *mut ScreechBuilder,
+    key: *const u8,
+    key_len: usize,
+) -> ScreechError {
+    guard(|| {
+        let builder = deref_mut!(builder);
+        match input(key, key_len) {
+            Some(key) => builder.remote_public_key = Some(key.to_vec()),
+            None => return ScreechError::NullPointer,
+        }
+        ScreechError::Ok
+    })
+}
+
+/// Set the prologue. The prologue is copied.
+///
+/// # Safety
+///
+/// `builder` must be a live handle and `prologue` must be valid for `prologue_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_prologue(
+    builder: *mut ScreechBuilder,
+    prologue: *const u8,
+    prologue_len: usize,
+) -> ScreechError {
+    guard(|| {
+        let builder = deref_mut!(builder);
+        match input(prologue, prologue_len) {
+            Some(prologue) => builder.prologue = Some(prologue.to_vec()),
+            None => return ScreechError::NullPointer,
+        }
+        ScreechError::Ok
+    })
+}
+
+/// Set the PSK for the `psk{location}` modifier. The key is copied.
+///
+/// # Safety
+///
+/// `builder` must be a live handle and `key` must be valid for `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_psk(
+    builder: *mut ScreechBuilder,
+    location: u8,
+    key: *const u8,
+    key_len: usize,
+) -> ScreechError {
+    guard(|| {
+        let builder = deref_mut!(builder);
+        let key = match input(key, key_len) {
+            Some(key) => Zeroizing::new(key.to_vec()),
+            None => return ScreechError::NullPointer,
+        };
+        builder.psks.retain(|(l, _)| *l != location);
+        builder.psks.push((location, key));
+        ScreechError::Ok
+    })
+}
+
+/// Generate a static keypair for the builder's protocol, writing the private and public keys
+/// into the given buffers and their lengths into `*private_len` and `*public_len`.
+///
+/// If either buffer is too small, nothing is written to the buffers, the needed sizes are
+/// reported and `SCREECH_ERROR_BUFFER_TOO_SMALL` is returned.
+///
+/// # Safety
+///
+/// `builder` must be a live handle, each buffer must be valid for the length it's passed with,
+/// and the length pointers must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_generate_keypair(
+    builder: *const ScreechBuilder,
+    private: *mut u8,
+    private_cap: usize,
+    private_len: *mut usize,
+    public: *mut u8,
+    public_cap: usize,
+    public_len: *mut usize,
+) -> ScreechError {
+    guard(|| {
+        let builder = match builder.as_ref() {
+            Some(builder) => builder,
+            None => return ScreechError::NullPointer,
+        };
+        let private_len = deref_mut!(private_len);
+        let public_len = deref_mut!(public_len);
+        let (private_out, public_out) =
+            match (output(private, private_cap), output(public, public_cap)) {
+                (Some(private), Some(public)) => (private, public),
+                _ => return ScreechError::NullPointer,
+            };
+        let Keypair { private, public } = try_ffi!(builder.builder().generate_keypair());
+        *private_len = private.len();
+        *public_len = public.len();
+        if private_out.len() < private.len() || public_out.len() < public.len() {
+            return ScreechError::BufferTooSmall;
+        }
+        copy_slices!(private, private_out);
+        copy_slices!(public, public_out);
+        ScreechError::Ok
+    })
+}
+
+unsafe fn build(
+    builder: *const ScreechBuilder,
+    initiator: bool,
+    out: *mut *mut ScreechHandshake,
+) -> ScreechError {
+    let builder = match builder.as_ref() {
+        Some(builder) => builder,
+        None => return ScreechError::NullPointer,
+    };
+    let out = deref_mut!(out);
+    let builder = builder.builder();
+    let handshake =
+        try_ffi!(if initiator { builder.build_initiator() } else { builder.build_responder() });
+    *out = Box::into_raw(Box::new(ScreechHandshake(handshake)));
+    ScreechError::Ok
+}
+
+/// Build a handshake for the initiating side, storing it in `*out`. The builder is left intact.
+///
+/// # Safety
+///
+/// `builder` must be a live handle and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_build_initiator(
+    builder: *const ScreechBuilder,
+    out: *mut *mut ScreechHandshake,
+) -> ScreechError {
+    guard(|| build(builder, true, out))
+}
+
+/// Build a handshake for the responding side, storing it in `*out`. The builder is left intact.
+///
+/// # Safety
+///
+/// `builder` must be a live handle and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_builder_build_responder(
+    builder: *const ScreechBuilder,
+    out: *mut *mut ScreechHandshake,
+) -> ScreechError {
+    guard(|| build(builder, false, out))
+}
+
+/// Release a handshake. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `handshake` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn screech_handshake_free(handshake: *mut ScreechHandshake) {
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        if !handshake.is_null() {
+            drop(Box::from_raw(handshake));
+        }
+    }));
+}
+
+/// See [`HandshakeState::write_message`].
+///
+/// # Safety
+///
+/// `handshake` must be a live handle, each buffer must be valid for the length it's passed
+/// with, and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_handshake_write_message(
+    handshake: *mut ScreechHandshake,
+    payload: *const u8,
+    payload_len: usize,
+    message: *mut u8,
+    message_cap: usize,
+    out_len: *mut usize,
+) -> ScreechError {
+    guard(|| {
+        let handshake = deref_mut!(handshake);
+        let (payload, message) = match (input(payload, payload_len), output(message, message_cap)) {
+            (Some(payload), Some(message)) => (payload, message),
+            _ => return ScreechError::NullPointer,
+        };
+        write_out(out_len, handshake.0.write_message(payload, message))
+    })
+}
+
+/// See [`HandshakeState::read_message`].
+///
+/// # Safety
+///
+/// `handshake` must be a live handle, each buffer must be valid for the length it's passed
+/// with, and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_handshake_read_message(
+    handshake: *mut ScreechHandshake,
+    message: *const u8,
+    message_len: usize,
+    payload: *mut u8,
+    payload_cap: usize,
+    out_len: *mut usize,
+) -> ScreechError {
+    guard(|| {
+        let handshake = deref_mut!(handshake);
+        let (message, payload) = match (input(message, message_len), output(payload, payload_cap)) {
+            (Some(message), Some(payload)) => (message, payload),
+            _ => return ScreechError::NullPointer,
+        };
+        write_out(out_len, handshake.0.read_message(message, payload))
+    })
+}
+
+/// See [`HandshakeState::is_handshake_finished`]. Returns 0 for a null handle.
+///
+/// # Safety
+///
+/// `handshake` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn screech_handshake_is_finished(
+    handshake: *const ScreechHandshake,
+) -> c_int {
+    catch_unwind(AssertUnwindSafe(|| {
+        handshake.as_ref().map_or(0, |h| h.0.is_handshake_finished() as c_int)
+    }))
+    .unwrap_or(0)
+}
+
+/// See [`HandshakeState::is_my_turn`]. Returns 0 for a null handle.
+///
+/// # Safety
+///
+/// `handshake` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn screech_handshake_is_my_turn(handshake: *const ScreechHandshake) -> c_int {
+    catch_unwind(AssertUnwindSafe(|| handshake.as_ref().map_or(0, |h| h.0.is_my_turn() as c_int)))
+        .unwrap_or(0)
+}
+
+/// Copy the remote party's static public key into `out`, if it's known yet.
+///
+/// If it isn't, `*out_len` is set to zero.
+///
+/// # Safety
+///
+/// `handshake` must be a live handle, `out` must be valid for `out_cap` bytes and `out_len` must
+/// be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_handshake_get_remote_static(
+    handshake: *const ScreechHandshake,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> ScreechError {
+    guard(|| {
+        let handshake = match handshake.as_ref() {
+            Some(handshake) => handshake,
+            None => return ScreechError::NullPointer,
+        };
+        let out = match output(out, out_cap) {
+            Some(out) => out,
+            None => return ScreechError::NullPointer,
+        };
+        let remote_static = handshake.0.get_remote_static().unwrap_or(&[]);
+        write_out(
+            out_len,
+            if out.len() < remote_static.len() {
+                Err(Error::BufferTooSmall { needed: remote_static.len(), got: out.len() })
+            } else {
+                copy_slices!(remote_static, out);
+                Ok(remote_static.len())
+            },
+        )
+    })
+}
+
+/// Finish a handshake, storing the resulting transport in `*out`.
+///
+/// The handshake handle is consumed whether or not this succeeds, and must not be used or freed
+/// afterwards.
+///
+/// # Safety
+///
+/// `handshake` must be a live handle and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_handshake_into_transport(
+    handshake: *mut ScreechHandshake,
+    out: *mut *mut ScreechTransport,
+) -> ScreechError {
+    guard(|| {
+        if handshake.is_null() || out.is_null() {
+            return ScreechError::NullPointer;
+        }
+        let handshake = Box::from_raw(handshake);
+        *out = ptr::null_mut();
+        let transport = try_ffi!(handshake.0.into_transport_mode());
+        *out = Box::into_raw(Box::new(ScreechTransport(transport)));
+        ScreechError::Ok
+    })
+}
+
+/// Release a transport. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `transport` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn screech_transport_free(transport: *mut ScreechTransport) {
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        if !transport.is_null() {
+            drop(Box::from_raw(transport));
+        }
+    }));
+}
+
+/// See [`TransportState::write_message`].
+///
+/// # Safety
+///
+/// `transport` must be a live handle, each buffer must be valid for the length it's passed
+/// with, and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_transport_write_message(
+    transport: *mut ScreechTransport,
+    payload: *const u8,
+    payload_len: usize,
+    message: *mut u8,
+    message_cap: usize,
+    out_len: *mut usize,
+) -> ScreechError {
+    guard(|| {
+        let transport = deref_mut!(transport);
+        let (payload, message) = match (input(payload, payload_len), output(message, message_cap)) {
+            (Some(payload), Some(message)) => (payload, message),
+            _ => return ScreechError::NullPointer,
+        };
+        write_out(out_len, transport.0.write_message(payload, message))
+    })
+}
+
+/// See [`TransportState::read_message`].
+///
+/// # Safety
+///
+/// `transport` must be a live handle, each buffer must be valid for the length it's passed
+/// with, and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn screech_transport_read_message(
+    transport: *mut ScreechTransport,
+    message: *const u8,
+    message_len: usize,
+    payload: *mut u8,
+    payload_cap: usize,
+    out_len: *mut usize,
+) -> ScreechError {
+    guard(|| {
+        let transport = deref_mut!(transport);
+        let (message, payload) = match (input(message, message_len), output(payload, payload_cap)) {
+            (Some(message), Some(payload)) => (message, payload),
+            _ => return ScreechError::NullPointer,
+        };
+        write_out(out_len, transport.0.read_message(message, payload))
+    })
+}
+
+/// See [`TransportState::rekey_outgoing`].
+///
+/// # Safety
+///
+/// `transport` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn screech_transport_rekey_outgoing(
+    transport: *mut ScreechTransport,
+) -> ScreechError {
+    guard(|| {
+        deref_mut!(transport).0.rekey_outgoing();
+        ScreechError::Ok
+    })
+}
+
+/// See [`TransportState::rekey_incoming`].
+///
+/// # Safety
+///
+/// `transport` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn screech_transport_rekey_incoming(
+    transport: *mut ScreechTransport,
+) -> ScreechError {
+    guard(|| {
+        deref_mut!(transport).0.rekey_incoming();
+        ScreechError::Ok
+    })
+}
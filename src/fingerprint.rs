@@ -0,0 +1,112 @@
+//! Short, standard fingerprints of public keys, for showing and comparing key identities.
+//!
+//! A fingerprint is the SHA-256 hash of the raw public key, written like OpenSSH writes its own
+//! key fingerprints: `SHA256:` followed by the hash in standard base64 without padding, e.g.
+//! `SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU`. Since it hashes the key bytes as sent in
+//! the handshake, it works the same for any DH or signature algorithm.
+//!
+//! The "hash-sha2" feature has to be enabled to use this module.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::fingerprint::*;
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let public_key = [9u8; 32];
+//! let shown = fingerprint(&public_key);
+//! assert!(shown.starts_with("SHA256:"));
+//!
+//! // Later, compare against what the user was shown.
+//! let expected: Fingerprint = shown.parse()?;
+//! assert!(expected.matches(&public_key));
+//! #     Ok(())
+//! # }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    error::Error,
+    utils::{decode_base64, encode_base64},
+};
+use sha2::{Digest, Sha256};
+use std::{convert::TryInto, fmt, str::FromStr};
+
+/// What every fingerprint's text form starts with, naming the hash.
+pub const FINGERPRINT_PREFIX: &str = "SHA256:";
+
+/// The text form of `public_key`'s fingerprint.
+///
+/// Shorthand for `Fingerprint::of(public_key).to_string()`.
+pub fn fingerprint(public_key: &[u8]) -> String {
+    Fingerprint::of(public_key).to_string()
+}
+
+/// The SHA-256 hash of a public key, which displays and parses as its text form.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// The fingerprint of `public_key`.
+    pub fn of(public_key: &[u8]) -> Self {
+        Fingerprint(Sha256::digest(public_key).into())
+    }
+
+    /// Whether this is the fingerprint of `public_key`.
+    pub fn matches(&self, public_key: &[u8]) -> bool {
+        *self == Self::of(public_key)
+    }
+
+    /// The raw hash.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", FINGERPRINT_PREFIX, encode_base64(&self.0, false))
+    }
+}
+
+/// Parses the text form, which has to start with [`FINGERPRINT_PREFIX`]. Padding after the
+/// base64 is tolerated, since other tools sometimes add it.
+impl FromStr for Fingerprint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s.strip_prefix(FINGERPRINT_PREFIX).ok_or(Error::Input)?;
+        let hash = decode_base64(encoded).ok_or(Error::Input)?;
+        Ok(Fingerprint(hash.as_slice().try_into().map_err(|_| Error::Input)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_of_empty_key() {
+        // Matches `echo -n | sha256sum` and OpenSSH's encoding of it.
+        assert_eq!(fingerprint(&[]), "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU");
+    }
+
+    #[test]
+    fn test_fingerprint_round_trip() {
+        let key = [0x42u8; 32];
+        let parsed: Fingerprint = fingerprint(&key).parse().unwrap();
+        assert_eq!(parsed, Fingerprint::of(&key));
+        assert!(parsed.matches(&key));
+        assert!(!parsed.matches(&[0x43u8; 32]));
+
+        let padded = format!("{}=", fingerprint(&key));
+        assert_eq!(padded.parse::<Fingerprint>().unwrap(), parsed);
+    }
+
+    #[test]
+    fn test_fingerprint_rejects_malformed() {
+        let text = fingerprint(&[0x42u8; 32]);
+        for bad in &[&text[7..], &text[..text.len() - 1], "SHA256:", "MD5:AAAA", "SHA256:AAAA"] {
+            assert!(bad.parse::<Fingerprint>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! A handshake and transport generic over their primitives, whose messages can be returned by
+//! value in fixed-size arrays, for targets where the heap is scarce.
+//!
+//! [`FixedHandshakeState`] runs the same state machine as
+//! [`HandshakeStateCore`](crate::HandshakeStateCore), over the [`Dh`], [`Cipher`] and [`Hash`]
+//! implementations it's given instead of boxed ones. Messages can be written into
+//! caller-provided slices like with [`HandshakeState`](crate::HandshakeState), or returned by
+//! value as a [`FixedMessage`] whose capacity is a const generic, so the messages and payloads of
+//! a session can live on the stack.
+//!
+//! The trade-offs are that the ephemeral key is passed in already generated (so no RNG is held),
+//! and that the extensions built on the resolver (signatures, HFS, Disco, padding, observers) aren't
+//! supported. Parsing `NoiseParams` and creating the state allocate, as does the state's first
+//! [`set_psk()`](FixedHandshakeState::set_psk) call if the pattern has no PSKs, and the crate itself
+//! still links `std`.
+//!
+//! ```
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! use snow::{
+//!     fixed::{FixedHandshakeState, FixedKeys, FixedMessage},
+//!     resolvers::{CryptoResolver, DefaultResolver},
+//!     types::{Cipher, Dh, Hash},
+//! };
+//!
+//! type BoxedHandshake = FixedHandshakeState<Box<dyn Dh>, Box<dyn Cipher>, Box<dyn Hash>>;
+//!
+//! let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let resolver = DefaultResolver;
+//! let mut rng = resolver.resolve_rng().unwrap();
+//! let mut start = |initiator| -> Result<BoxedHandshake, snow::Error> {
+//!     let mut e = resolver.resolve_dh(&params.dh).unwrap();
+//!     e.generate(&mut *rng).unwrap();
+//!     let keys = FixedKeys { local_static: None, ephemeral: e, remote_static: None };
+//!     let cipher = || resolver.resolve_cipher(&params.cipher).unwrap();
+//!     let hash = resolver.resolve_hash(&params.hash).unwrap();
+//!     FixedHandshakeState::new(&params, initiator, keys, [cipher(), cipher(), cipher()], hash, &[])
+//! };
+//! let (mut initiator, mut responder) = (start(true)?, start(false)?);
+//!
+//! let message: FixedMessage<64> = initiator.write_fixed(b"")?;
+//! let _: FixedMessage<64> = responder.read_fixed(&message)?;
+//! let message: FixedMessage<64> = responder.write_fixed(b"")?;
+//! let _: FixedMessage<64> = initiator.read_fixed(&message)?;
+//!
+//! let (mut initiator, mut responder) = (initiator.into_transport()?, responder.into_transport()?);
+//! let message: FixedMessage<64> = initiator.write_fixed(b"hello")?;
+//! let payload: FixedMessage<64> = responder.read_fixed(&message)?;
+//! assert_eq!(&payload[..], b"hello");
+//! # Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    cipherstate::{CipherState, CipherStates},
+    constants::{MAXDHLEN, MAXSTATICLEN},
+    error::{Error, InitStage, PatternProblem, Prerequisite},
+    handshakestate::{EphemeralSource, HandshakeStateCore},
+    params::{HandshakeTokens, NoiseParams, Token},
+    symmetricstate::SymmetricState,
+    transportstate::TransportStateCore,
+    types::{Cipher, Dh, Hash, Random},
+    utils::Toggle,
+};
+use std::{convert::TryFrom, fmt, num::NonZeroU32, ops::Deref};
+
+/// A message or payload held in a `[u8; N]`.
+///
+/// Dereferences to the `len()` bytes that were written.
+#[derive(Clone)]
+pub struct FixedMessage<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedMessage<N> {
+    /// Let `write` fill in the array, returning how many bytes it wrote.
+    pub(crate) fn write_with(
+        write: impl FnOnce(&mut [u8]) -> Result<usize, Error>,
+    ) -> Result<Self, Error> {
+        let mut buf = [0u8; N];
+        let len = write(&mut buf)?;
+        Ok(Self { buf, len })
+    }
+
+    /// The full backing array, of which only the first `len()` bytes are the message.
+    pub fn into_array(self) -> ([u8; N], usize) {
+        (self.buf, self.len)
+    }
+}
+
+impl<const N: usize> Deref for FixedMessage<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<const N: usize> fmt::Debug for FixedMessage<N> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FixedMessage").field("len", &self.len).finish_non_exhaustive()
+    }
+}
+
+/// The keys a [`FixedHandshakeState`] starts with.
+pub struct FixedKeys<'a, D> {
+    /// Our static key, if the pattern uses one.
+    pub local_static:  Option<D>,
+    /// A freshly generated ephemeral key. It must never be used for another handshake.
+    pub ephemeral:     D,
+    /// The peer's static public key, if the pattern needs it up front.
+    pub remote_static: Option<&'a [u8]>,
+}
+
+/// A DH key, or the lengths of one that wasn't given, since the core state always holds a local
+/// static instance to take the key lengths from.
+enum FixedDh<D> {
+    Key(D),
+    Absent { name: &'static str, pub_len: usize, priv_len: usize, shared_len: usize },
+}
+
+impl<D: Dh> FixedDh<D> {
+    fn absent_like(dh: &D) -> Self {
+        FixedDh::Absent {
+            name:       dh.name(),
+            pub_len:    dh.pub_len(),
+            priv_len:   dh.priv_len(),
+            shared_len: dh.shared_len(),
+        }
+    }
+}
+
+impl<D: Dh> Dh for FixedDh<D> {
+    fn name(&self) -> &'static str {
+        match self {
+            FixedDh::Key(dh) => dh.name(),
+            FixedDh::Absent { name, .. } => name,
+        }
+    }
+
+    fn pub_len(&self) -> usize {
+        match self {
+            FixedDh::Key(dh) => dh.pub_len(),
+            FixedDh::Absent { pub_len, .. } => *pub_len,
+        }
+    }
+
+    fn priv_len(&self) -> usize {
+        match self {
+            FixedDh::Key(dh) => dh.priv_len(),
+            FixedDh::Absent { priv_len, .. } => *priv_len,
+        }
+    }
+
+    fn shared_len(&self) -> usize {
+        match self {
+            FixedDh::Key(dh) => dh.shared_len(),
+            FixedDh::Absent { shared_len, .. } => *shared_len,
+        }
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        if let FixedDh::Key(dh) = self {
+            dh.set(privkey);
+        }
+    }
+
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        if let FixedDh::Key(dh) = self {
+            dh.set_keypair(privkey, pubkey);
+        }
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        match self {
+            FixedDh::Key(dh) => dh.generate(rng),
+            FixedDh::Absent { .. } => Err(()),
+        }
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        match self {
+            FixedDh::Key(dh) => dh.pubkey(),
+            FixedDh::Absent { .. } => &[],
+        }
+    }
+
+    fn privkey(&self) -> &[u8] {
+        match self {
+            FixedDh::Key(dh) => dh.privkey(),
+            FixedDh::Absent { .. } => &[],
+        }
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        match self {
+            FixedDh::Key(dh) => dh.dh(pubkey, out),
+            FixedDh::Absent { .. } => Err(()),
+        }
+    }
+}
+
+/// Stands in for the RNG, which is never asked for anything since the ephemeral key is given.
+struct NoRng;
+
+impl rand_core::RngCore for NoRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).unwrap();
+    }
+
+    fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        Err(NonZeroU32::new(rand_core::Error::CUSTOM_START).unwrap().into())
+    }
+}
+
+impl rand_core::CryptoRng for NoRng {}
+
+impl Random for NoRng {}
+
+/// The handshake phase of a Noise session, generic over its primitives. See the
+/// [module documentation](self).
+pub struct FixedHandshakeState<D, C, H> {
+    core: HandshakeStateCore<FixedDh<D>, C, H>,
+}
+
+impl<D: Dh, C: Cipher, H: Hash> FixedHandshakeState<D, C, H> {
+    /// Start a handshake for `params`, mixing in `prologue` and any pre-message keys.
+    ///
+    /// `ciphers` are the three instances the session needs: one for the handshake and one for
+    /// each direction of the transport.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if a primitive doesn't match the one named in `params`,
+    /// `Error::Pattern` if the pattern uses Disco, signatures, HFS or a pre-message ephemeral key,
+    /// `Error::Prereq` if a static key the pattern needs up front is missing, and
+    /// `Error::Init(InitStage::ValidateKeyLengths)` if the keys have different lengths.
+    pub fn new(
+        params: &NoiseParams,
+        initiator: bool,
+        keys: FixedKeys<'_, D>,
+        ciphers: [C; 3],
+        hasher: H,
+        prologue: &[u8],
+    ) -> Result<Self, Error> {
+        #[cfg(any(feature = "disco", feature = "legacy-psk"))]
+        if params.base != crate::params::BaseChoice::Noise {
+            bail!(PatternProblem::UnsupportedBaseType);
+        }
+        if keys.ephemeral.name() != params.dh.to_string()
+            || keys.local_static.as_ref().is_some_and(|s| s.name() != keys.ephemeral.name())
+            || ciphers.iter().any(|cipher| cipher.name() != params.cipher.to_string())
+            || hasher.name() != params.hash.to_string()
+        {
+            bail!(Error::Input);
+        }
+
+        let tokens = HandshakeTokens::try_from(&params.handshake)?;
+        for token in tokens.msg_patterns.iter().flatten() {
+            match token {
+                Token::E | Token::S | Token::Dh(_) | Token::Psk(_) => {},
+                _ => bail!(PatternProblem::UnsupportedModifier),
+            }
+        }
+        if tokens.premsg_pattern_i.iter().chain(tokens.premsg_pattern_r).any(|t| *t != Token::S) {
+            bail!(PatternProblem::UnsupportedHandshakeType);
+        }
+        if keys.local_static.is_none() && tokens.needs_local_static(initiator) {
+            bail!(Prerequisite::LocalPrivateKey.for_pattern(&params.handshake, initiator));
+        }
+        if keys.remote_static.is_none() && tokens.needs_remote_static(initiator) {
+            bail!(Prerequisite::RemotePublicKey.for_pattern(&params.handshake, initiator));
+        }
+
+        let dh_len = keys.ephemeral.pub_len();
+        if keys.local_static.as_ref().is_some_and(|s| s.pub_len() != dh_len)
+            || keys.remote_static.is_some_and(|rs| rs.len() != dh_len)
+        {
+            bail!(InitStage::ValidateKeyLengths);
+        }
+        let mut rs = Toggle::off([0u8; MAXSTATICLEN]);
+        if let Some(remote_static) = keys.remote_static {
+            rs[..dh_len].copy_from_slice(remote_static);
+            rs.enable();
+        }
+        let s = match keys.local_static {
+            Some(s) => Toggle::on(FixedDh::Key(s)),
+            None => Toggle::off(FixedDh::absent_like(&keys.ephemeral)),
+        };
+        // Room for every PSK up front, so setting them later doesn't allocate.
+        let psk_count =
+            tokens.msg_patterns.iter().flatten().filter(|t| matches!(t, Token::Psk(_))).count();
+
+        let [cipher, initiator_cipher, responder_cipher] = ciphers;
+        let core = HandshakeStateCore::new(
+            Box::new(NoRng),
+            SymmetricState::new(CipherState::new(cipher), hasher),
+            s,
+            Toggle::off(FixedDh::Key(keys.ephemeral)),
+            EphemeralSource::Pregenerated,
+            None,
+            rs,
+            Toggle::off([0u8; MAXDHLEN]),
+            initiator,
+            params.clone(),
+            Vec::with_capacity(psk_count),
+            prologue,
+            CipherStates::new(
+                CipherState::new(initiator_cipher),
+                CipherState::new(responder_cipher),
+            )?,
+            None,
+        )?;
+        Ok(Self { core })
+    }
+
+    /// Set the PSK for the `pskN` modifier at `location`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`HandshakeState::set_psk()`](crate::HandshakeState::set_psk).
+    pub fn set_psk(&mut self, location: usize, key: &[u8]) -> Result<(), Error> {
+        self.core.set_psk(location, key)
+    }
+
+    /// Write the next handshake message, with `payload`, into `message`.
+    ///
+    /// Returns the length of the message.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`HandshakeState::write_message()`](crate::HandshakeState::write_message),
+    /// leaving the state as it was.
+    pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        self.core.write_message(payload, message)
+    }
+
+    /// Read the next handshake message, writing its payload into `payload`.
+    ///
+    /// Returns the length of the payload.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`HandshakeState::read_message()`](crate::HandshakeState::read_message),
+    /// leaving the state as it was.
+    pub fn read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<usize, Error> {
+        self.core.read_message(message, payload)
+    }
+
+    /// [`write_message()`](Self::write_message) into a [`FixedMessage`] of capacity `N`.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::BufferTooSmall` if the message doesn't fit in `N` bytes, and
+    /// otherwise fails like `write_message()`.
+    pub fn write_fixed<const N: usize>(
+        &mut self,
+        payload: &[u8],
+    ) -> Result<FixedMessage<N>, Error> {
+        FixedMessage::write_with(|message| self.write_message(payload, message))
+    }
+
+    /// [`read_message()`](Self::read_message) into a [`FixedMessage`] of capacity `N`.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::BufferTooSmall` if the payload doesn't fit in `N` bytes, and
+    /// otherwise fails like `read_message()`.
+    pub fn read_fixed<const N: usize>(&mut self, message: &[u8]) -> Result<FixedMessage<N>, Error> {
+        FixedMessage::write_with(|payload| self.read_message(message, payload))
+    }
+
+    /// Get the remote party's static public key, if available.
+    pub fn get_remote_static(&self) -> Option<&[u8]> {
+        self.core.get_remote_static()
+    }
+
+    /// Get the remote party's ephemeral public key, if available.
+    pub fn get_remote_ephemeral(&self) -> Option<&[u8]> {
+        self.core.get_remote_ephemeral()
+    }
+
+    /// Get the local static public key, if available.
+    pub fn get_local_static(&self) -> Option<&[u8]> {
+        self.core.get_local_static()
+    }
+
+    /// Get the handshake hash.
+    pub fn get_handshake_hash(&self) -> &[u8] {
+        self.core.get_handshake_hash()
+    }
+
+    /// Check if this session was started with the "initiator" role.
+    pub fn is_initiator(&self) -> bool {
+        self.core.is_initiator()
+    }
+
+    /// Check whether it is our turn to send in the handshake state machine.
+    pub fn is_my_turn(&self) -> bool {
+        self.core.is_my_turn()
+    }
+
+    /// Check if the handshake is finished and `into_transport()` can now be called.
+    pub fn is_handshake_finished(&self) -> bool {
+        self.core.is_handshake_finished()
+    }
+
+    /// Split the finished handshake into a transport state.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::HandshakeNotFinished)` if there are handshake
+    /// messages left.
+    pub fn into_transport(self) -> Result<TransportStateCore<C>, Error> {
+        self.core.into_transport_core()
+    }
+}
+
+/// Shows where the handshake is and which keys are set, but none of the key material.
+impl<D, C, H> fmt::Debug for FixedHandshakeState<D, C, H> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FixedHandshakeState").field("core", &self.core).finish()
+    }
+}
@@ -0,0 +1,326 @@
+//! Deterministic entry points for fuzz targets.
+//!
+//! Each function here sets up a session from fixed inputs, feeds it attacker-controlled bytes,
+//! and returns whatever error that causes: any input is supposed to give an `Err`, never a panic.
+//! Every source of randomness is replaced with a counter, so the same input always takes the same
+//! path, as coverage-guided fuzzers and differential fuzzing against other implementations need.
+//! The one exception is `hfs`, whose KEM encapsulation draws its own randomness. Don't use any of
+//! this outside of fuzzing.
+//!
+//! The fuzzed bytes are split into messages, each a 2-byte big-endian length followed by that
+//! many bytes; if fewer bytes are left than the length says, the message is whatever is left.
+//!
+//! Requires the `fuzzing` feature.
+//!
+//! # Examples
+//!
+//! A `cargo fuzz` target reading arbitrary handshake messages as an `XX` responder:
+//!
+//! ```no_run
+//! # use snow::fuzzing::*;
+//! # let data: &[u8] = &[];
+//! // fuzz_target!(|data: &[u8]| { ... });
+//! let params = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//! let _ = fuzz_read_handshake(&params, false, &Keys::default(), data);
+//! ```
+
+use crate::{
+    constants::MAXMSGLEN,
+    error::{Error, InitStage},
+    params::{HandshakeModifier, NoiseParams},
+    resolvers::{CryptoResolver, DefaultResolver},
+    types::Random,
+    Builder, HandshakeState, TransportState,
+};
+use rand_core::{impls, CryptoRng, RngCore};
+
+/// The fixed key material sessions are built with.
+#[derive(Debug, Clone, Copy)]
+pub struct Keys<'a> {
+    /// The initiator's static private key, if the pattern has one.
+    pub initiator_private: &'a [u8],
+    /// The responder's static private key, if the pattern has one.
+    pub responder_private: &'a [u8],
+    /// The PSK for every PSK modifier.
+    pub psk:               &'a [u8],
+}
+
+impl Default for Keys<'static> {
+    fn default() -> Self {
+        Keys {
+            initiator_private: &[0x11; 32],
+            responder_private: &[0x22; 32],
+            psk:               &[0x33; 32],
+        }
+    }
+}
+
+/// Read the messages in `bytes` as the other side's handshake messages, writing empty payloads
+/// whenever it's this side's turn, and, once the handshake is done, read the rest as transport
+/// messages. Returns the number of messages read.
+///
+/// # Errors
+///
+/// Fails with the first error from building the session or reading a message, and with
+/// `Error::Input` if `bytes` runs out before the handshake is done.
+pub fn fuzz_read_handshake(
+    params: &NoiseParams,
+    initiator: bool,
+    keys: &Keys<'_>,
+    bytes: &[u8],
+) -> Result<usize, Error> {
+    let mut state = build(params, initiator, keys, 0)?;
+    let mut messages = Messages(bytes);
+    let mut buf = vec![0u8; MAXMSGLEN];
+    let mut read = 0;
+
+    while !state.is_handshake_finished() {
+        if state.is_my_turn() {
+            state.write_message(&[], &mut buf)?;
+        } else {
+            let message = messages.next().ok_or(Error::Input)?;
+            state.read_message(message, &mut buf)?;
+            read += 1;
+        }
+    }
+    read_transport(state.into_transport_mode()?, messages, read)
+}
+
+/// Run a complete handshake between two sessions built from `keys`, then read the messages in
+/// `bytes` as transport messages on the side given by `initiator`. Returns the number of
+/// messages read.
+///
+/// Both sides' keys are known, so a fuzzer can get past decryption by replaying messages from a
+/// run of the same handshake, e.g. produced by [`fuzz_write_transport()`].
+///
+/// # Errors
+///
+/// Fails with the first error from the handshake or from reading a message.
+pub fn fuzz_read_transport(
+    params: &NoiseParams,
+    initiator: bool,
+    keys: &Keys<'_>,
+    bytes: &[u8],
+) -> Result<usize, Error> {
+    let (i, r) = handshake(params, keys)?;
+    let transport = if initiator { i } else { r };
+    read_transport(transport, Messages(bytes), 0)
+}
+
+/// Run the same handshake as [`fuzz_read_transport()`], and encrypt `payloads` (split into
+/// messages the same way) as transport messages from the side given by `initiator`, framed for
+/// feeding back into [`fuzz_read_transport()`] as the other side.
+///
+/// # Errors
+///
+/// Fails with the first error from the handshake or from writing a message.
+pub fn fuzz_write_transport(
+    params: &NoiseParams,
+    initiator: bool,
+    keys: &Keys<'_>,
+    payloads: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let (i, r) = handshake(params, keys)?;
+    let mut transport = if initiator { i } else { r };
+    let mut buf = vec![0u8; MAXMSGLEN];
+    let mut out = Vec::new();
+    for payload in Messages(payloads) {
+        let len = transport.write_message(payload, &mut buf)?;
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+        out.extend_from_slice(&buf[..len]);
+    }
+    Ok(out)
+}
+
+/// Parse `bytes` as a protocol name.
+///
+/// # Errors
+///
+/// Fails with `Error::Input` if `bytes` isn't UTF-8, and `Error::Pattern` if it isn't a valid
+/// protocol name.
+pub fn fuzz_parse_params(bytes: &[u8]) -> Result<NoiseParams, Error> {
+    std::str::from_utf8(bytes).map_err(|_| Error::Input)?.parse()
+}
+
+fn read_transport(
+    mut transport: TransportState,
+    messages: Messages<'_>,
+    mut read: usize,
+) -> Result<usize, Error> {
+    let mut buf = vec![0u8; MAXMSGLEN];
+    for message in messages {
+        transport.read_message(message, &mut buf)?;
+        read += 1;
+    }
+    Ok(read)
+}
+
+/// Run a handshake with empty payloads between two sessions built from `keys`.
+fn handshake(
+    params: &NoiseParams,
+    keys: &Keys<'_>,
+) -> Result<(TransportState, TransportState), Error> {
+    let mut i = build(params, true, keys, 0)?;
+    let mut r = build(params, false, keys, 1 << 32)?;
+    let mut buf = vec![0u8; MAXMSGLEN];
+    let mut payload = vec![0u8; MAXMSGLEN];
+    while !(i.is_handshake_finished() && r.is_handshake_finished()) {
+        let (writer, reader) = if i.is_my_turn() { (&mut i, &mut r) } else { (&mut r, &mut i) };
+        let len = writer.write_message(&[], &mut buf)?;
+        reader.read_message(&buf[..len], &mut payload)?;
+    }
+    Ok((i.into_transport_mode()?, r.into_transport_mode()?))
+}
+
+/// Build one side of the protocol from `keys`, with a counting RNG starting at `seed`.
+fn build(
+    params: &NoiseParams,
+    initiator: bool,
+    keys: &Keys<'_>,
+    seed: u64,
+) -> Result<HandshakeState, Error> {
+    let pattern = params.handshake.pattern;
+    let (local, remote) = if initiator {
+        (keys.initiator_private, keys.responder_private)
+    } else {
+        (keys.responder_private, keys.initiator_private)
+    };
+    let remote_public;
+    let mut builder = Builder::with_resolver(params.clone(), Box::new(DefaultResolver))
+        .rng(Box::new(CountingRng(seed)));
+    if pattern.needs_local_static_key(initiator) {
+        builder = builder.local_private_key(local);
+    }
+    if pattern.need_known_remote_pubkey(initiator) {
+        remote_public = public_key(params, remote)?;
+        builder = builder.remote_public_key(&remote_public);
+    }
+    for modifier in &params.handshake.modifiers.list {
+        if let HandshakeModifier::Psk(location) = *modifier {
+            builder = builder.psk(location, keys.psk);
+        }
+    }
+    if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    }
+}
+
+/// The static public key for `private`, which is a signing key with the `sig` modifier.
+fn public_key(params: &NoiseParams, private: &[u8]) -> Result<Vec<u8>, Error> {
+    if let Some(sig) = &params.sig {
+        let mut signer = DefaultResolver.resolve_sig(sig).ok_or(InitStage::GetSigImpl)?;
+        if private.len() != signer.priv_len() {
+            bail!(InitStage::ValidateKeyLengths);
+        }
+        signer.set(private);
+        return Ok(signer.pubkey().to_vec());
+    }
+    let mut dh = DefaultResolver.resolve_dh(&params.dh).ok_or(InitStage::GetDhImpl)?;
+    if private.len() != dh.priv_len() {
+        bail!(InitStage::ValidateKeyLengths);
+    }
+    dh.set(private);
+    Ok(dh.pubkey().to_vec())
+}
+
+/// Splits fuzzed bytes into length-prefixed messages.
+struct Messages<'a>(&'a [u8]);
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.0.len() < 2 {
+            return None;
+        }
+        let len = usize::from(u16::from_be_bytes([self.0[0], self.0[1]]));
+        let rest = &self.0[2..];
+        let (message, rest) = rest.split_at(len.min(rest.len()));
+        self.0 = rest;
+        Some(message)
+    }
+}
+
+/// Stands in for the RNG, so runs are reproducible.
+struct CountingRng(u64);
+
+impl RngCore for CountingRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 += 1;
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for CountingRng {}
+impl Random for CountingRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(messages: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for message in messages {
+            out.extend_from_slice(&(message.len() as u16).to_be_bytes());
+            out.extend_from_slice(message);
+        }
+        out
+    }
+
+    #[test]
+    fn test_transport_round_trip() {
+        let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let keys = Keys::default();
+        let sealed =
+            fuzz_write_transport(&params, true, &keys, &framed(&[b"one", b"two"])).unwrap();
+        assert_eq!(fuzz_read_transport(&params, false, &keys, &sealed).unwrap(), 2);
+        assert_eq!(
+            sealed,
+            fuzz_write_transport(&params, true, &keys, &framed(&[b"one", b"two"])).unwrap()
+        );
+
+        let mut tampered = sealed.clone();
+        tampered[3] ^= 1;
+        assert!(matches!(
+            fuzz_read_transport(&params, false, &keys, &tampered),
+            Err(Error::Decrypt)
+        ));
+    }
+
+    #[test]
+    fn test_arbitrary_input_errs() {
+        let params: NoiseParams = "Noise_IKpsk2_25519_AESGCM_SHA256".parse().unwrap();
+        let keys = Keys::default();
+        for bytes in [&[0, 0][..], &[0xff, 0xff, 1, 2, 3], &[0; 200]] {
+            for initiator in [true, false] {
+                assert!(fuzz_read_handshake(&params, initiator, &keys, bytes).is_err());
+                assert!(fuzz_read_transport(&params, initiator, &keys, bytes).is_err());
+            }
+        }
+        assert!(matches!(fuzz_read_handshake(&params, false, &keys, &[0]), Err(Error::Input)));
+
+        let short = Keys { initiator_private: &[1; 31], ..keys };
+        assert!(matches!(
+            fuzz_read_handshake(&params, true, &short, &[]),
+            Err(Error::Init(InitStage::ValidateKeyLengths))
+        ));
+        let long = Keys { responder_private: &[1; 33], ..keys };
+        assert!(fuzz_read_handshake(&params, true, &long, &[]).is_err());
+        assert!(fuzz_parse_params(&[0xff]).is_err());
+    }
+}
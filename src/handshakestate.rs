@@ -1,69 +1,203 @@
 #[cfg(feature = "risky-raw-split")]
-use crate::constants::{CIPHERKEYLEN, MAXHASHLEN};
+use crate::constants::CIPHERKEYLEN;
 #[cfg(feature = "hfs")]
 use crate::constants::{MAXKEMCTLEN, MAXKEMPUBLEN, MAXKEMSSLEN};
 #[cfg(feature = "hfs")]
 use crate::types::Kem;
 use crate::{
-    cipherstate::{CipherState, CipherStates},
-    constants::{MAXDHLEN, MAXMSGLEN, PSKLEN, TAGLEN},
-    error::{Error, InitStage, StateProblem},
+    cipherstate::CipherStates,
+    constants::{MAXDHLEN, MAXHASHLEN, MAXMSGLEN, MAXSIGLEN, MAXSTATICLEN, PSKLEN, TAGLEN},
+    dh_cache::StaticDhCache,
+    error::{Error, InitStage, Prerequisite, StateProblem},
+    key_usage::KeyUsageTracker,
+    observer::SessionObserver,
+    padding::PaddingPolicy,
     params::{DhToken, HandshakeTokens, MessagePatterns, NoiseParams, Token},
+    pinning::Pinning,
+    selector::StaticKeySelector,
     stateless_transportstate::StatelessTransportState,
     symmetricstate::SymmetricState,
-    transportstate::TransportState,
-    types::{Dh, Hash, Random},
-    utils::Toggle,
+    transcript::{Step, Transcript},
+    transportstate::{TransportState, TransportStateCore},
+    types::{Cipher, Dh, Hash, Random, Sign},
+    utils::{check_message_len, Toggle},
 };
 use std::{
     convert::{TryFrom, TryInto},
     fmt,
+    sync::Arc,
 };
 
+/// The input key material `resumption_psk()` derives from the chaining key with.
+const RESUMPTION_LABEL: &[u8] = b"snow resumption psk";
+
+/// Early data read from the first handshake message with [`HandshakeState::read_early_data()`].
+///
+/// Early data is always weaker than transport data: anyone who recorded the message can replay
+/// it to the responder, and it isn't forward secret, since it's encrypted before the responder
+/// has contributed an ephemeral key. Only act on it if that's acceptable (e.g. idempotent
+/// requests).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EarlyData {
+    /// The length of the early data written to the payload buffer.
+    pub len: usize,
+
+    /// Whether the early data is authenticated as coming from the initiator, through the
+    /// initiator's static key (`ss`) or a PSK. Even then, a compromise of the responder's static
+    /// key lets an attacker impersonate the initiator in this message.
+    pub sender_authenticated: bool,
+}
+
+/// One part of a handshake message, as [`HandshakeState::message_tokens()`] describes it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum TokenView {
+    /// A token, and whether the key or signature it puts in the message is encrypted. Tokens
+    /// that don't put anything in the message (`ee`, `psk0` and so on) are never encrypted.
+    Token {
+        /// The token.
+        token:     Token,
+        /// Whether what the token sends is encrypted.
+        encrypted: bool,
+    },
+
+    /// The payload, which comes after the tokens, and whether it's encrypted.
+    Payload {
+        /// Whether the payload is encrypted.
+        encrypted: bool,
+    },
+}
+
+/// Work out what each message of `message_patterns` encrypts, starting from whether the
+/// symmetric state already has a key before the first one.
+fn token_views(
+    message_patterns: &[Vec<Token>],
+    mut has_key: bool,
+    uses_psk: bool,
+) -> Vec<Vec<TokenView>> {
+    message_patterns
+        .iter()
+        .map(|tokens| {
+            let mut views: Vec<_> = tokens
+                .iter()
+                .map(|&token| {
+                    let encrypted = match token {
+                        Token::S | Token::Sig => has_key,
+                        #[cfg(feature = "hfs")]
+                        Token::E1 | Token::Ekem1 => has_key,
+                        _ => false,
+                    };
+                    has_key |= match token {
+                        Token::E => uses_psk,
+                        Token::Dh(_) | Token::Psk(_) => true,
+                        #[cfg(feature = "hfs")]
+                        Token::Ekem1 => true,
+                        _ => false,
+                    };
+                    TokenView::Token { token, encrypted }
+                })
+                .collect();
+            views.push(TokenView::Payload { encrypted: has_key });
+            views
+        })
+        .collect()
+}
+
+/// The primitive instances a [`HandshakeStateCore`] is built over, in place of the ones a
+/// resolver would hand out. See [`Builder::build_core_initiator()`](crate::Builder::build_core_initiator).
+pub struct CorePrimitives<D, C, H> {
+    /// The DH instance for the local static key, which gets the builder's private key if one
+    /// was set.
+    pub local_static: D,
+    /// The DH instance for ephemeral keys.
+    pub ephemeral:    D,
+    /// One cipher for the handshake and one for each direction of the transport.
+    pub ciphers:      [C; 3],
+    /// The hash function.
+    pub hash:         H,
+}
+
+/// Where the key for the next `e` token comes from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum EphemeralSource {
+    /// Generate a fresh key with the RNG.
+    Generate,
+
+    /// Always use the key given to the builder. Only for reproducing test vectors.
+    Fixed,
+
+    /// Use the key the builder was given or drew from an
+    /// [`EphemeralPool`](crate::ephemeral::EphemeralPool) once, then go back to generating.
+    Pregenerated,
+}
+
 /// A state machine encompassing the handshake phase of a Noise session.
 ///
 /// **Note:** you are probably looking for [`Builder`](struct.Builder.html) to
 /// get started.
 ///
 /// See: [http://noiseprotocol.org/noise.html#the-handshakestate-object](http://noiseprotocol.org/noise.html#the-handshakestate-object)
-pub struct HandshakeState {
+pub type HandshakeState = HandshakeStateCore;
+
+/// The handshake state machine, generic over its DH, cipher and hash implementations.
+///
+/// [`HandshakeState`] is this with the boxed primitives a resolver hands out. Naming concrete
+/// types instead, through [`Builder::build_core_initiator()`](crate::Builder::build_core_initiator),
+/// lets the compiler inline them into the handshake and the transport, with no dynamic dispatch.
+/// Everything but converting into a [`TransportState`] works the same either way;
+/// [`into_transport_core()`](Self::into_transport_core) gives a generic transport instead.
+pub struct HandshakeStateCore<D = Box<dyn Dh>, C = Box<dyn Cipher>, H = Box<dyn Hash>> {
     pub(crate) rng:              Box<dyn Random>,
-    pub(crate) symmetricstate:   SymmetricState,
-    pub(crate) cipherstates:     CipherStates,
-    pub(crate) s:                Toggle<Box<dyn Dh>>,
-    pub(crate) e:                Toggle<Box<dyn Dh>>,
-    pub(crate) fixed_ephemeral:  bool,
-    pub(crate) rs:               Toggle<[u8; MAXDHLEN]>,
+    pub(crate) symmetricstate:   SymmetricState<C, H>,
+    pub(crate) cipherstates:     CipherStates<C>,
+    pub(crate) s:                Toggle<D>,
+    /// Picks `s` once the first message of each handshake is read, given the prologue kept
+    /// alongside it. When set, `s` is only ever the key it picked for the current handshake.
+    pub(crate) s_selector:       Option<(Arc<dyn StaticKeySelector>, Vec<u8>)>,
+    pub(crate) e:                Toggle<D>,
+    pub(crate) e_source:         EphemeralSource,
+    pub(crate) precomputed_ss:   Option<[u8; MAXDHLEN]>,
+    pub(crate) ss_cache:         Option<Arc<StaticDhCache>>,
+    pub(crate) key_usage:        Option<Arc<KeyUsageTracker>>,
+    pub(crate) signer:           Option<Toggle<Box<dyn Sign>>>,
+    pub(crate) rs:               Toggle<[u8; MAXSTATICLEN]>,
     pub(crate) re:               Toggle<[u8; MAXDHLEN]>,
     pub(crate) initiator:        bool,
     pub(crate) params:           NoiseParams,
-    pub(crate) psks:             [Option<[u8; PSKLEN]>; 10],
+    pub(crate) psks:             Vec<(u8, [u8; PSKLEN])>,
     #[cfg(feature = "hfs")]
     pub(crate) kem:              Option<Box<dyn Kem>>,
     #[cfg(feature = "hfs")]
     pub(crate) kem_re:           Option<[u8; MAXKEMPUBLEN]>,
     pub(crate) my_turn:          bool,
     pub(crate) message_patterns: MessagePatterns,
+    pub(crate) token_views:      Vec<Vec<TokenView>>,
     pub(crate) pattern_position: usize,
+    pub(crate) padding:          Option<PaddingPolicy>,
+    pub(crate) observer:         Option<Arc<dyn SessionObserver>>,
+    pub(crate) pinning:          Option<Pinning>,
+    pub(crate) max_message_len:  Option<usize>,
+    pub(crate) transcript:       Option<Transcript>,
 }
 
-impl HandshakeState {
+impl<D: Dh, C: Cipher, H: Hash> HandshakeStateCore<D, C, H> {
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         rng: Box<dyn Random>,
-        cipherstate: CipherState,
-        hasher: Box<dyn Hash>,
-        s: Toggle<Box<dyn Dh>>,
-        e: Toggle<Box<dyn Dh>>,
-        fixed_ephemeral: bool,
-        rs: Toggle<[u8; MAXDHLEN]>,
+        symmetricstate: SymmetricState<C, H>,
+        s: Toggle<D>,
+        e: Toggle<D>,
+        e_source: EphemeralSource,
+        signer: Option<Toggle<Box<dyn Sign>>>,
+        rs: Toggle<[u8; MAXSTATICLEN]>,
         re: Toggle<[u8; MAXDHLEN]>,
         initiator: bool,
         params: NoiseParams,
-        psks: [Option<[u8; PSKLEN]>; 10],
+        psks: Vec<(u8, [u8; PSKLEN])>,
         prologue: &[u8],
-        cipherstates: CipherStates,
-    ) -> Result<HandshakeState, Error> {
+        cipherstates: CipherStates<C>,
+        padding: Option<PaddingPolicy>,
+    ) -> Result<Self, Error> {
         if (s.is_on() && e.is_on() && s.pub_len() != e.pub_len())
             || (s.is_on() && rs.is_on() && s.pub_len() > rs.len())
             || (s.is_on() && re.is_on() && s.pub_len() > re.len())
@@ -73,69 +207,18 @@ impl HandshakeState {
 
         let tokens = HandshakeTokens::try_from(&params.handshake)?;
 
-        let mut symmetricstate = SymmetricState::new(cipherstate, hasher);
-
-        symmetricstate.initialize(&params.name);
-        symmetricstate.mix_hash(prologue);
-
-        let dh_len = s.pub_len();
-        if initiator {
-            for token in tokens.premsg_pattern_i {
-                symmetricstate.mix_hash(
-                    match *token {
-                        Token::S => &s,
-                        Token::E => &e,
-                        _ => unreachable!(),
-                    }
-                    .get()
-                    .ok_or(StateProblem::MissingKeyMaterial)?
-                    .pubkey(),
-                );
-            }
-            for token in tokens.premsg_pattern_r {
-                symmetricstate.mix_hash(
-                    &match *token {
-                        Token::S => &rs,
-                        Token::E => &re,
-                        _ => unreachable!(),
-                    }
-                    .get()
-                    .ok_or(StateProblem::MissingKeyMaterial)?[..dh_len],
-                );
-            }
-        } else {
-            for token in tokens.premsg_pattern_i {
-                symmetricstate.mix_hash(
-                    &match *token {
-                        Token::S => &rs,
-                        Token::E => &re,
-                        _ => unreachable!(),
-                    }
-                    .get()
-                    .ok_or(StateProblem::MissingKeyMaterial)?[..dh_len],
-                );
-            }
-            for token in tokens.premsg_pattern_r {
-                symmetricstate.mix_hash(
-                    match *token {
-                        Token::S => &s,
-                        Token::E => &e,
-                        _ => unreachable!(),
-                    }
-                    .get()
-                    .ok_or(StateProblem::MissingKeyMaterial)?
-                    .pubkey(),
-                );
-            }
-        }
-
-        Ok(HandshakeState {
+        let mut hs = HandshakeStateCore {
             rng,
             symmetricstate,
             cipherstates,
             s,
+            s_selector: None,
             e,
-            fixed_ephemeral,
+            e_source,
+            precomputed_ss: None,
+            ss_cache: None,
+            key_usage: None,
+            signer,
             rs,
             re,
             initiator,
@@ -147,31 +230,136 @@ impl HandshakeState {
             kem_re: None,
             my_turn: initiator,
             message_patterns: tokens.msg_patterns,
+            token_views: Vec::new(),
             pattern_position: 0,
-        })
+            padding,
+            observer: None,
+            pinning: None,
+            max_message_len: None,
+            transcript: None,
+        };
+        hs.initialize(tokens.premsg_pattern_i, tokens.premsg_pattern_r, prologue)?;
+        Ok(hs)
+    }
+
+    /// Start the symmetric state over, then mix in the prologue and any pre-message keys.
+    fn initialize(
+        &mut self,
+        premsg_pattern_i: &[Token],
+        premsg_pattern_r: &[Token],
+        prologue: &[u8],
+    ) -> Result<(), Error> {
+        self.symmetricstate.initialize(&self.params.name);
+        self.symmetricstate.mix_hash(prologue);
+        #[cfg(feature = "legacy-psk")]
+        if self.params.base == crate::params::BaseChoice::NoisePSK {
+            let psk = self.psk(0).ok_or(Prerequisite::Psk(0))?;
+            self.symmetricstate.mix_psk_legacy(&psk);
+        }
+
+        // In signature mode, static keys are signing keys rather than DH keys.
+        let (local_s, static_len) = match &self.signer {
+            Some(signer) => (signer.get().map(|signer| signer.pubkey()), signer.pub_len()),
+            None => (self.s.get().map(|s| s.pubkey()), self.s.pub_len()),
+        };
+        let local_e = self.e.get().map(|e| e.pubkey());
+        let remote_s = self.rs.get().map(|rs| &rs[..static_len]);
+        let remote_e = self.re.get().map(|re| &re[..self.e.pub_len()]);
+        let initiator = self.initiator;
+
+        for (token, remote) in premsg_pattern_i
+            .iter()
+            .map(|token| (token, !initiator))
+            .chain(premsg_pattern_r.iter().map(|token| (token, initiator)))
+        {
+            self.symmetricstate.mix_hash(
+                match (*token, remote) {
+                    (Token::S, false) => local_s,
+                    (Token::E, false) => local_e,
+                    (Token::S, true) => remote_s,
+                    (Token::E, true) => remote_e,
+                    _ => unreachable!(),
+                }
+                .ok_or(StateProblem::MissingKeyMaterial)?,
+            );
+        }
+        self.token_views = token_views(
+            &self.message_patterns,
+            self.symmetricstate.has_key(),
+            self.params.uses_psk(),
+        );
+        Ok(())
     }
 
     pub(crate) fn dh_len(&self) -> usize {
         self.s.pub_len()
     }
 
+    /// The length of the tag appended to handshake fields once a key has been mixed in.
+    fn tag_len(&self) -> usize {
+        if self.symmetricstate.has_key() {
+            TAGLEN
+        } else {
+            0
+        }
+    }
+
+    /// A copy of the local static public key for the transport state, with its length.
+    pub(crate) fn local_static_copy(&self) -> (usize, Toggle<[u8; MAXSTATICLEN]>) {
+        let mut s = [0u8; MAXSTATICLEN];
+        match self.get_local_static() {
+            Some(pubkey) => {
+                s[..pubkey.len()].copy_from_slice(pubkey);
+                (pubkey.len(), Toggle::on(s))
+            },
+            None => (0, Toggle::off(s)),
+        }
+    }
+
+    /// The length of a static public key, which is a signing key in signature mode.
+    pub(crate) fn remote_static_len(&self) -> usize {
+        match &self.signer {
+            Some(signer) => signer.pub_len(),
+            None => self.dh_len(),
+        }
+    }
+
     #[cfg(feature = "hfs")]
     pub(crate) fn set_kem(&mut self, kem: Box<dyn Kem>) {
         self.kem = Some(kem);
     }
 
     fn dh(&self, token: &DhToken) -> Result<[u8; MAXDHLEN], Error> {
+        if let DhToken::Ss = token {
+            if let Some(ss) = self.precomputed_ss {
+                return Ok(ss);
+            }
+            if let (Some(cache), Some(s), Some(rs)) = (&self.ss_cache, self.s.get(), self.rs.get())
+            {
+                let rs = &rs[..self.dh_len()];
+                return cache.get_or_compute(s.pubkey(), rs, || self.compute_dh(token));
+            }
+        }
+        self.compute_dh(token)
+    }
+
+    fn compute_dh(&self, token: &DhToken) -> Result<[u8; MAXDHLEN], Error> {
         let mut dh_out = [0u8; MAXDHLEN];
-        let (dh, key) = match (token, self.is_initiator()) {
-            (DhToken::Ee, _) => (&self.e, &self.re),
-            (DhToken::Ss, _) => (&self.s, &self.rs),
-            (DhToken::Se, true) | (DhToken::Es, false) => (&self.s, &self.re),
-            (DhToken::Es, true) | (DhToken::Se, false) => (&self.e, &self.rs),
+        let (dh, key): (_, Option<&[u8]>) = match (token, self.is_initiator()) {
+            (DhToken::Ee, _) => (&self.e, self.re.get().map(|re| &re[..])),
+            (DhToken::Ss, _) => (&self.s, self.rs.get().map(|rs| &rs[..])),
+            (DhToken::Se, true) | (DhToken::Es, false) => {
+                (&self.s, self.re.get().map(|re| &re[..]))
+            },
+            (DhToken::Es, true) | (DhToken::Se, false) => {
+                (&self.e, self.rs.get().map(|rs| &rs[..]))
+            },
         };
-        if !(dh.is_on() && key.is_on()) {
-            bail!(StateProblem::MissingKeyMaterial);
-        }
-        dh.dh(&**key, &mut dh_out).map_err(|_| Error::Dh)?;
+        let key = match key {
+            Some(key) if dh.is_on() => key,
+            _ => bail!(StateProblem::MissingKeyMaterial),
+        };
+        dh.dh(key, &mut dh_out).map_err(|_| Error::Dh)?;
         Ok(dh_out)
     }
 
@@ -194,6 +382,80 @@ impl HandshakeState {
         self.symmetricstate.has_key()
     }
 
+    /// This method will return `true` if the payload of the *next* handshake message will be
+    /// encrypted, which is the message we write when [`is_my_turn()`] and the one we read
+    /// otherwise.
+    ///
+    /// This lets you decide whether it's safe to put anything but an empty payload in the
+    /// message before writing it.
+    ///
+    /// [`is_my_turn()`]: #method.is_my_turn
+    pub fn next_message_will_encrypt_payload(&self) -> bool {
+        let tokens = match self.message_patterns.get(self.pattern_position) {
+            Some(tokens) => tokens,
+            None => return self.symmetricstate.has_key(),
+        };
+        self.symmetricstate.has_key()
+            || tokens.iter().any(|token| match token {
+                Token::E => self.params.uses_psk(),
+                Token::Dh(_) | Token::Psk(_) => true,
+                #[cfg(feature = "hfs")]
+                Token::Ekem1 => true,
+                _ => false,
+            })
+    }
+
+    /// Write the first handshake message with `early_data` as its payload, for patterns like
+    /// `IK` that let the initiator send encrypted data right away (0-RTT).
+    ///
+    /// This is [`write_message()`](#method.write_message) with a guard that the payload will
+    /// actually be encrypted. See [`EarlyData`] for the weaker guarantees early data gets.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::EarlyDataUnavailable)` if this isn't the
+    /// initiator's first message, or if its payload wouldn't be encrypted (e.g. in `XX`), and
+    /// otherwise fails like `write_message()`.
+    pub fn write_early_data(
+        &mut self,
+        early_data: &[u8],
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.check_early_data(true)?;
+        self.write_message(early_data, message)
+    }
+
+    /// Read the first handshake message, returning how much early data it carried and what it
+    /// can be trusted for. See [`write_early_data()`](#method.write_early_data).
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::EarlyDataUnavailable)` if this isn't the
+    /// responder's first message, or if its payload isn't encrypted, and otherwise fails like
+    /// [`read_message()`](#method.read_message).
+    pub fn read_early_data(
+        &mut self,
+        message: &[u8],
+        payload: &mut [u8],
+    ) -> Result<EarlyData, Error> {
+        self.check_early_data(false)?;
+        let sender_authenticated = self.message_patterns[0]
+            .iter()
+            .any(|token| matches!(token, Token::Dh(DhToken::Ss) | Token::Psk(_)));
+        let len = self.read_message(message, payload)?;
+        Ok(EarlyData { len, sender_authenticated })
+    }
+
+    fn check_early_data(&self, initiator: bool) -> Result<(), Error> {
+        if self.initiator != initiator
+            || self.pattern_position != 0
+            || !self.next_message_will_encrypt_payload()
+        {
+            bail!(StateProblem::EarlyDataUnavailable);
+        }
+        Ok(())
+    }
+
     /// Construct a message from `payload` (and pending handshake tokens if in handshake state),
     /// and writes it to the `message` buffer.
     ///
@@ -203,22 +465,73 @@ impl HandshakeState {
     ///
     /// Will result in `Error::Input` if the size of the output exceeds the max message
     /// length in the Noise Protocol (65535 bytes).
+    ///
+    /// Will result in `Error::BufferTooSmall` if `message` can't hold the handshake message.
+    ///
+    /// Will result in `Error::Rng` if the RNG fails while generating an ephemeral key.
+    ///
+    /// Other errors from processing one of the message's tokens are wrapped in
+    /// `Error::Handshake`, naming the message and token, e.g. with `Error::Prereq(Prerequisite::Psk(n))` as the
+    /// source if the message mixes in a PSK that hasn't been set.
     pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "write_message",
+            protocol = %self.params.name,
+            initiator = self.initiator,
+            message = self.pattern_position,
+        )
+        .entered();
         let checkpoint = self.symmetricstate.checkpoint();
-        match self._write_message(payload, message) {
+        let mut token = None;
+        let result = self._write_message(payload, message, &mut token);
+        self.record_message(
+            true,
+            result.as_ref().ok().copied(),
+            result.as_ref().map(|_| payload.len()),
+        );
+        match result {
             Ok(res) => {
                 self.pattern_position += 1;
                 self.my_turn = false;
+                trace_event!(
+                    debug,
+                    len = res,
+                    finished = self.is_handshake_finished(),
+                    "handshake message written"
+                );
+                if self.is_handshake_finished() {
+                    self.complete();
+                }
                 Ok(res)
             },
             Err(err) => {
+                trace_event!(debug, error = %err, "handshake message failed");
                 self.symmetricstate.restore(checkpoint);
-                Err(err)
+                Err(self.token_error(err, token))
+            },
+        }
+    }
+
+    /// `err` with the message and token it came from, if it came from a token. Buffer size
+    /// errors already say what they need, so they're left as they are.
+    fn token_error(&self, err: Error, token: Option<Token>) -> Error {
+        match (token, err) {
+            (_, err @ (Error::BufferTooSmall { .. } | Error::TruncatedMessage { .. })) => err,
+            (Some(token), err) => {
+                Error::Handshake { message: self.pattern_position, token, source: Box::new(err) }
             },
+            (None, err) => err,
         }
     }
 
-    fn _write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+    /// Write the message, leaving `current` at the token being processed if one fails.
+    fn _write_message(
+        &mut self,
+        payload: &[u8],
+        message: &mut [u8],
+        current: &mut Option<Token>,
+    ) -> Result<usize, Error> {
         if !self.my_turn {
             bail!(StateProblem::NotTurnToWrite);
         } else if self.pattern_position >= self.message_patterns.len() {
@@ -227,46 +540,74 @@ impl HandshakeState {
 
         let mut byte_index = 0;
         for token in self.message_patterns[self.pattern_position].iter() {
+            trace_event!(trace, ?token, "processing token");
+            *current = Some(*token);
             match token {
                 Token::E => {
-                    if byte_index + self.e.pub_len() > message.len() {
-                        bail!(Error::Input)
+                    let needed = byte_index + self.e.pub_len();
+                    if needed > message.len() {
+                        bail!(Error::BufferTooSmall { needed, got: message.len() });
                     }
 
-                    if !self.fixed_ephemeral {
-                        self.e.generate(&mut *self.rng);
+                    match self.e_source {
+                        EphemeralSource::Generate => {
+                            self.e.generate(&mut *self.rng).map_err(|_| Error::Rng)?
+                        },
+                        EphemeralSource::Fixed => {},
+                        EphemeralSource::Pregenerated => self.e_source = EphemeralSource::Generate,
                     }
                     let pubkey = self.e.pubkey();
                     message[byte_index..byte_index + pubkey.len()].copy_from_slice(pubkey);
                     byte_index += pubkey.len();
                     self.symmetricstate.mix_hash(pubkey);
-                    if self.params.handshake.is_psk() {
+                    if self.params.uses_psk() {
                         self.symmetricstate.mix_key(pubkey);
                     }
                     self.e.enable();
                 },
                 Token::S => {
-                    if !self.s.is_on() {
-                        bail!(StateProblem::MissingKeyMaterial);
-                    } else if byte_index + self.s.pub_len() > message.len() {
-                        bail!(Error::Input)
+                    let pubkey = match &self.signer {
+                        Some(signer) => signer.get().map(|signer| signer.pubkey()),
+                        None => self.s.get().map(|s| s.pubkey()),
+                    }
+                    .ok_or(StateProblem::MissingKeyMaterial)?;
+                    let needed = byte_index + pubkey.len() + self.tag_len();
+                    if needed > message.len() {
+                        bail!(Error::BufferTooSmall { needed, got: message.len() });
+                    }
+
+                    byte_index += self
+                        .symmetricstate
+                        .encrypt_and_mix_hash(pubkey, &mut message[byte_index..])?;
+                },
+                Token::Sig => {
+                    let signer = self
+                        .signer
+                        .as_ref()
+                        .and_then(|signer| signer.get())
+                        .ok_or(StateProblem::MissingKeyMaterial)?;
+                    let needed = byte_index + signer.sig_len() + self.tag_len();
+                    if needed > message.len() {
+                        bail!(Error::BufferTooSmall { needed, got: message.len() });
                     }
 
+                    let mut signature = [0u8; MAXSIGLEN];
+                    let sig_len = signer.sign(self.symmetricstate.handshake_hash(), &mut signature);
                     byte_index += self
                         .symmetricstate
-                        .encrypt_and_mix_hash(self.s.pubkey(), &mut message[byte_index..])?;
+                        .encrypt_and_mix_hash(&signature[..sig_len], &mut message[byte_index..])?;
                 },
-                Token::Psk(n) => match self.psks[*n as usize] {
+                Token::Psk(n) => match self.psk(*n) {
                     Some(psk) => {
                         self.symmetricstate.mix_key_and_hash(&psk);
                     },
                     None => {
-                        bail!(StateProblem::MissingPsk);
+                        bail!(Prerequisite::Psk(*n));
                     },
                 },
                 Token::Dh(t) => {
                     let dh_out = self.dh(t)?;
-                    self.symmetricstate.mix_key(&dh_out[..self.dh_len()]);
+                    self.symmetricstate.mix_key(&dh_out[..self.e.shared_len()]);
                 },
                 #[cfg(feature = "hfs")]
                 Token::E1 => {
@@ -275,14 +616,14 @@ impl HandshakeState {
                         bail!(Error::Input);
                     }
 
-                    kem.generate(&mut *self.rng);
+                    kem.generate(&mut *self.rng).map_err(|_| Error::Rng)?;
                     byte_index += self
                         .symmetricstate
                         .encrypt_and_mix_hash(kem.pubkey(), &mut message[byte_index..])?;
                 },
                 #[cfg(feature = "hfs")]
                 Token::Ekem1 => {
-                    let kem = self.kem.as_mut().unwrap();
+                    let kem = self.kem.as_ref().unwrap();
                     let mut kem_output_buf = [0; MAXKEMSSLEN];
                     let mut ciphertext_buf = [0; MAXKEMCTLEN];
 
@@ -293,7 +634,10 @@ impl HandshakeState {
                     let kem_output = &mut kem_output_buf[..kem.shared_secret_len()];
                     let ciphertext = &mut ciphertext_buf[..kem.ciphertext_len()];
                     let pubkey = &self.kem_re.as_ref().unwrap()[..kem.pub_len()];
-                    if kem.encapsulate(pubkey, kem_output, ciphertext).is_err() {
+                    if kem
+                        .encapsulate_with_rng(&mut *self.rng, pubkey, kem_output, ciphertext)
+                        .is_err()
+                    {
                         bail!(Error::Kem);
                     }
 
@@ -304,10 +648,17 @@ impl HandshakeState {
                     self.symmetricstate.mix_key(&kem_output[..kem.shared_secret_len()]);
                 },
             }
+            if let (Some(step), Some(transcript)) =
+                (self.transcript_step(token, true), &mut self.transcript)
+            {
+                transcript.push_step(step);
+            }
         }
+        *current = None;
 
-        if byte_index + payload.len() + TAGLEN > message.len() {
-            bail!(Error::Input);
+        let needed = byte_index + payload.len() + TAGLEN;
+        if needed > message.len() {
+            bail!(Error::BufferTooSmall { needed, got: message.len() });
         }
         byte_index +=
             self.symmetricstate.encrypt_and_mix_hash(payload, &mut message[byte_index..])?;
@@ -329,25 +680,120 @@ impl HandshakeState {
     /// Will result in `Error::Decrypt` if the contents couldn't be decrypted and/or the
     /// authentication tag didn't verify.
     ///
+    /// Will result in `Error::TruncatedMessage` if `message` is too short to hold the
+    /// handshake tokens and payload tag, or `Error::BufferTooSmall` if `payload` can't hold
+    /// the decrypted payload.
+    ///
+    /// Other errors from processing one of the message's tokens are wrapped in
+    /// `Error::Handshake`, naming the message and token, e.g. with `Error::Decrypt` as the source if an encrypted
+    /// static key didn't verify, or `Error::Prereq(Prerequisite::Psk(n))` if the message mixes
+    /// in a PSK that hasn't been set.
+    ///
+    /// Will result in `Error::MessageTooLarge` if `message` is longer than
+    /// [`Builder::max_message_len()`](crate::Builder::max_message_len) allows.
+    ///
     /// # Panics
     ///
     /// This function will panic if there is no key, or if there is a nonce overflow.
     pub fn read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<usize, Error> {
+        check_message_len(self.max_message_len, message.len())?;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "read_message",
+            protocol = %self.params.name,
+            initiator = self.initiator,
+            message = self.pattern_position,
+        )
+        .entered();
         let checkpoint = self.symmetricstate.checkpoint();
-        match self._read_message(message, payload) {
+        let mut token = None;
+        let result = self._read_message(message, payload, &mut token);
+        self.record_message(false, Some(message.len()), result.as_ref().copied());
+        match result {
             Ok(res) => {
                 self.pattern_position += 1;
                 self.my_turn = true;
+                trace_event!(
+                    debug,
+                    len = res,
+                    finished = self.is_handshake_finished(),
+                    "handshake message read"
+                );
+                if self.is_handshake_finished() {
+                    self.complete();
+                }
                 Ok(res)
             },
             Err(err) => {
+                trace_event!(debug, error = %err, "handshake message failed");
+                if let (Error::Decrypt, Some(observer)) = (&err, &self.observer) {
+                    observer.decrypt_failed();
+                }
                 self.symmetricstate.restore(checkpoint);
-                Err(err)
+                Err(self.token_error(err, token))
             },
         }
     }
 
-    fn _read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<usize, Error> {
+    /// The transcript step for `token`, if there's a transcript to record it in.
+    fn transcript_step(&self, token: &Token, outgoing: bool) -> Option<Step> {
+        self.transcript.as_ref()?;
+        let public_key = match (token, outgoing) {
+            (Token::E, true) => Some(self.e.pubkey()),
+            (Token::E, false) => self.re.get().map(|re| &re[..self.dh_len()]),
+            (Token::S, true) => self.get_local_static(),
+            (Token::S, false) => self.get_remote_static(),
+            _ => None,
+        };
+        Some(Step {
+            token:          token.to_string(),
+            public_key:     public_key.map(<[u8]>::to_vec),
+            handshake_hash: self.symmetricstate.handshake_hash().to_vec(),
+        })
+    }
+
+    /// Close off the message in the transcript, if there is one, with the payload length or the
+    /// error.
+    fn record_message(
+        &mut self,
+        outgoing: bool,
+        message_len: Option<usize>,
+        result: Result<usize, &Error>,
+    ) {
+        if let Some(transcript) = &mut self.transcript {
+            let hash = self.symmetricstate.handshake_hash();
+            let result =
+                result.map(|payload_len| (payload_len, hash)).map_err(|err| err.to_string());
+            transcript.push_message(outgoing, message_len, result);
+        }
+    }
+
+    /// The transcript of the handshake so far, if
+    /// [`Builder::record_transcript()`](crate::Builder::record_transcript) asked for one. See the
+    /// [`transcript`](crate::transcript) module.
+    ///
+    /// It isn't carried over into transport mode, so take it before switching.
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+
+    /// Pin the remote static key and tell the observer, now that the handshake has completed.
+    fn complete(&self) {
+        if let (Some(pinning), Some(rs)) = (&self.pinning, self.get_remote_static()) {
+            pinning.commit(rs);
+        }
+        if let Some(observer) = &self.observer {
+            observer.handshake_completed(&self.params, self.initiator);
+        }
+    }
+
+    /// Read the message, leaving `current` at the token being processed if one fails.
+    fn _read_message(
+        &mut self,
+        message: &[u8],
+        payload: &mut [u8],
+        current: &mut Option<Token>,
+    ) -> Result<usize, Error> {
         if message.len() > MAXMSGLEN {
             bail!(Error::Input);
         } else if self.my_turn {
@@ -358,53 +804,80 @@ impl HandshakeState {
         let last = self.pattern_position == (self.message_patterns.len() - 1);
 
         let dh_len = self.dh_len();
+        let static_len = self.remote_static_len();
         let mut ptr = message;
         for token in self.message_patterns[self.pattern_position].iter() {
+            trace_event!(trace, ?token, "processing token");
+            *current = Some(*token);
             match token {
                 Token::E => {
-                    if ptr.len() < dh_len {
-                        bail!(Error::Input);
-                    }
+                    check_remaining(message, ptr, dh_len)?;
                     self.re[..dh_len].copy_from_slice(&ptr[..dh_len]);
                     ptr = &ptr[dh_len..];
                     self.symmetricstate.mix_hash(&self.re[..dh_len]);
-                    if self.params.handshake.is_psk() {
+                    if self.params.uses_psk() {
                         self.symmetricstate.mix_key(&self.re[..dh_len]);
                     }
                     self.re.enable();
                 },
                 Token::S => {
                     let data = if self.symmetricstate.has_key() {
-                        if ptr.len() < dh_len + TAGLEN {
-                            bail!(Error::Input);
-                        }
-                        let temp = &ptr[..dh_len + TAGLEN];
-                        ptr = &ptr[dh_len + TAGLEN..];
+                        check_remaining(message, ptr, static_len + TAGLEN)?;
+                        let temp = &ptr[..static_len + TAGLEN];
+                        ptr = &ptr[static_len + TAGLEN..];
                         temp
                     } else {
-                        if ptr.len() < dh_len {
-                            bail!(Error::Input);
-                        }
-                        let temp = &ptr[..dh_len];
-                        ptr = &ptr[dh_len..];
+                        check_remaining(message, ptr, static_len)?;
+                        let temp = &ptr[..static_len];
+                        ptr = &ptr[static_len..];
                         temp
                     };
                     self.symmetricstate
-                        .decrypt_and_mix_hash(data, &mut self.rs[..dh_len])
+                        .decrypt_and_mix_hash(data, &mut self.rs[..static_len])
                         .map_err(|_| Error::Decrypt)?;
+                    if let Some(pinning) = &self.pinning {
+                        pinning.check(&self.rs[..static_len])?;
+                    }
                     self.rs.enable();
                 },
-                Token::Psk(n) => match self.psks[*n as usize] {
+                Token::Sig => {
+                    let signer = self.signer.as_ref().ok_or(StateProblem::MissingKeyMaterial)?;
+                    if !self.rs.is_on() {
+                        bail!(StateProblem::MissingKeyMaterial);
+                    }
+                    let sig_len = signer.sig_len();
+                    let read_len =
+                        if self.symmetricstate.has_key() { sig_len + TAGLEN } else { sig_len };
+                    check_remaining(message, ptr, read_len)?;
+
+                    // The signature covers the handshake hash from before it was mixed in.
+                    let hash_len = self.symmetricstate.handshake_hash().len();
+                    let mut signed_hash = [0u8; MAXHASHLEN];
+                    signed_hash[..hash_len].copy_from_slice(self.symmetricstate.handshake_hash());
+                    let mut signature = [0u8; MAXSIGLEN];
+                    self.symmetricstate
+                        .decrypt_and_mix_hash(&ptr[..read_len], &mut signature[..sig_len])
+                        .map_err(|_| Error::Decrypt)?;
+                    if !signer.verify(
+                        &self.rs[..static_len],
+                        &signed_hash[..hash_len],
+                        &signature[..sig_len],
+                    ) {
+                        bail!(Error::Sig);
+                    }
+                    ptr = &ptr[read_len..];
+                },
+                Token::Psk(n) => match self.psk(*n) {
                     Some(psk) => {
                         self.symmetricstate.mix_key_and_hash(&psk);
                     },
                     None => {
-                        bail!(StateProblem::MissingPsk);
+                        bail!(Prerequisite::Psk(*n));
                     },
                 },
                 Token::Dh(t) => {
                     let dh_out = self.dh(t)?;
-                    self.symmetricstate.mix_key(&dh_out[..self.dh_len()]);
+                    self.symmetricstate.mix_key(&dh_out[..self.e.shared_len()]);
                 },
                 #[cfg(feature = "hfs")]
                 Token::E1 => {
@@ -414,9 +887,7 @@ impl HandshakeState {
                     } else {
                         kem.pub_len()
                     };
-                    if ptr.len() < read_len {
-                        bail!(Error::Input);
-                    }
+                    check_remaining(message, ptr, read_len)?;
                     let mut kem_re = [0; MAXKEMPUBLEN];
                     self.symmetricstate
                         .decrypt_and_mix_hash(&ptr[..read_len], &mut kem_re[..kem.pub_len()])
@@ -432,9 +903,7 @@ impl HandshakeState {
                     } else {
                         kem.ciphertext_len()
                     };
-                    if ptr.len() < read_len {
-                        bail!(Error::Input);
-                    }
+                    check_remaining(message, ptr, read_len)?;
                     let mut ciphertext_buf = [0; MAXKEMCTLEN];
                     let ciphertext = &mut ciphertext_buf[..kem.ciphertext_len()];
                     self.symmetricstate
@@ -447,33 +916,162 @@ impl HandshakeState {
                     ptr = &ptr[read_len..];
                 },
             }
+            if let (Some(step), Some(transcript)) =
+                (self.transcript_step(token, false), &mut self.transcript)
+            {
+                transcript.push_step(step);
+            }
         }
+        *current = None;
 
+        let tag_len = self.tag_len();
+        check_remaining(message, ptr, tag_len)?;
+        if payload.len() < ptr.len() - tag_len {
+            bail!(Error::BufferTooSmall { needed: ptr.len() - tag_len, got: payload.len() });
+        }
         self.symmetricstate.decrypt_and_mix_hash(ptr, payload).map_err(|_| Error::Decrypt)?;
         if last {
             self.symmetricstate.split(&mut self.cipherstates.0, &mut self.cipherstates.1);
         }
         let payload_len =
             if self.symmetricstate.has_key() { ptr.len() - TAGLEN } else { ptr.len() };
+        if self.pattern_position == 0 {
+            self.select_local_static(&payload[..payload_len])?;
+        }
         Ok(payload_len)
     }
 
-    /// Set the preshared key at the specified location. It is up to the caller
-    /// to correctly set the location based on the specified handshake - Snow
-    /// won't stop you from placing a PSK in an unused slot.
+    /// Set the static key the selector picks for the first message's `payload`, if the
+    /// responder was built with one instead of a static key.
+    fn select_local_static(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let keypair = match &self.s_selector {
+            Some((selector, prologue)) => selector.select(prologue, payload),
+            None => return Ok(()),
+        };
+        let keypair = keypair.ok_or(Prerequisite::LocalPrivateKey)?;
+        if keypair.private.len() != self.s.priv_len() {
+            bail!(InitStage::ValidateKeyLengths);
+        }
+        self.s.set(&keypair.private);
+        if self.s.pubkey() != &keypair.public[..] {
+            bail!(Error::Input);
+        }
+        self.s.enable();
+        Ok(())
+    }
+
+    /// Set the preshared key for the pattern's `pskN` modifier at `location`, as with
+    /// [`Builder::psk()`](crate::Builder::psk). The legacy `NoisePSK` base takes its PSK at
+    /// location 0.
     ///
     /// # Errors
     ///
-    /// Will result in `Error::Input` if the PSK is not the right length or the location is out of bounds.
+    /// Will result in `Error::Init(InitStage::ValidatePskPosition)` if the pattern has no
+    /// modifier for `location`, and `Error::Input` if the PSK is not the right length.
     pub fn set_psk(&mut self, location: usize, key: &[u8]) -> Result<(), Error> {
-        if key.len() != PSKLEN || self.psks.len() <= location {
+        let tokens = HandshakeTokens::try_from(&self.params.handshake)?;
+        let location = match u8::try_from(location) {
+            Ok(location) if self.params.takes_psk(&tokens, location) => location,
+            _ => bail!(InitStage::ValidatePskPosition),
+        };
+        if key.len() != PSKLEN {
             bail!(Error::Input);
         }
 
         let mut new_psk = [0u8; PSKLEN];
         new_psk.copy_from_slice(key);
-        self.psks[location as usize] = Some(new_psk);
+        self.psks.retain(|(l, _)| *l != location);
+        self.psks.push((location, new_psk));
+
+        Ok(())
+    }
+
+    fn psk(&self, location: u8) -> Option<[u8; PSKLEN]> {
+        self.psks.iter().find(|(l, _)| *l == location).map(|(_, psk)| *psk)
+    }
+
+    /// Start a new handshake with the same parameters and local keys, reusing this state's
+    /// allocated primitives instead of building a fresh `HandshakeState`.
+    ///
+    /// The new handshake takes the `initiator` role, starts from `prologue`, and knows
+    /// `remote_static` up front if the pattern needs it. Ephemeral keys, remote keys learned
+    /// during the previous handshake, a precomputed `ss` result and the progress through the
+    /// pattern are all discarded; PSKs, the padding policy, the RNG, any static DH cache, the
+    /// observer and the pinning are kept. A static key picked by a
+    /// [`StaticKeySelector`](crate::selector::StaticKeySelector) is discarded too, and the
+    /// selector picks again in the new handshake. If the state was built with a
+    /// [`KeyUsageTracker`](crate::key_usage::KeyUsageTracker), the local static key's new role is
+    /// recorded with it, just as when building. An ephemeral key given to
+    /// [`Builder::fixed_ephemeral()`](crate::Builder::fixed_ephemeral) or drawn from an
+    /// [`EphemeralPool`](crate::ephemeral::EphemeralPool) is never reused, so the new handshake
+    /// generates its own.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::PatternPrereq` if the new role needs a static key that isn't
+    /// available, `Error::Init(InitStage::ValidateKeyLengths)` if `remote_static` is too long,
+    /// and `Error::State(StateProblem::KeyUsedInBothRoles)` if the tracker has seen the local
+    /// static key in the other role. In all cases the state is left as it was.
+    pub fn reset(
+        &mut self,
+        initiator: bool,
+        prologue: &[u8],
+        remote_static: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        let tokens = HandshakeTokens::try_from(&self.params.handshake)?;
+        let selects_s = self.s_selector.is_some();
+        let has_local_static = match &self.signer {
+            Some(signer) => signer.is_on(),
+            // A key the selector picked was only for the previous handshake.
+            None => self.s.is_on() && !selects_s,
+        };
+        let selects_s = selects_s && !initiator && !tokens.needs_responder_static_upfront();
+        if !has_local_static && !selects_s && tokens.needs_local_static(initiator) {
+            bail!(Prerequisite::LocalPrivateKey.for_pattern(&self.params.handshake, initiator));
+        }
+        if remote_static.is_none() && tokens.needs_remote_static(initiator) {
+            bail!(Prerequisite::RemotePublicKey.for_pattern(&self.params.handshake, initiator));
+        }
+        if remote_static.is_some_and(|rs| rs.len() > MAXSTATICLEN) {
+            bail!(InitStage::ValidateKeyLengths);
+        }
+        let local_static = if has_local_static { self.get_local_static() } else { None };
+        if let (Some(tracker), Some(public_key)) = (&self.key_usage, local_static) {
+            tracker.record(&self.params.name, public_key, initiator)?;
+        }
 
+        match remote_static {
+            Some(rs) => {
+                self.rs[..rs.len()].copy_from_slice(rs);
+                self.rs.enable();
+            },
+            None => self.rs.disable(),
+        }
+        if self.e_source == EphemeralSource::Pregenerated {
+            self.e_source = EphemeralSource::Generate;
+        }
+        self.e.disable();
+        self.re.disable();
+        #[cfg(feature = "hfs")]
+        {
+            self.kem_re = None;
+        }
+        self.precomputed_ss = None;
+        if let Some((_, selector_prologue)) = &mut self.s_selector {
+            *selector_prologue = prologue.to_vec();
+            self.s.disable();
+        }
+        self.initiator = initiator;
+        self.my_turn = initiator;
+        self.pattern_position = 0;
+        self.initialize(tokens.premsg_pattern_i, tokens.premsg_pattern_r, prologue)?;
+        if let Some(transcript) = &mut self.transcript {
+            *transcript =
+                Transcript::new(&self.params, initiator, self.symmetricstate.handshake_hash());
+        }
+        if let Some(observer) = &self.observer {
+            observer.handshake_started(&self.params, initiator);
+        }
         Ok(())
     }
 
@@ -484,7 +1082,27 @@ impl HandshakeState {
     /// static key is not yet known (as can be the case in the `XX`
     /// pattern, for example).
     pub fn get_remote_static(&self) -> Option<&[u8]> {
-        self.rs.get().map(|rs| &rs[..self.dh_len()])
+        self.rs.get().map(|rs| &rs[..self.remote_static_len()])
+    }
+
+    /// Get the remote party's ephemeral public key, if available, e.g. to derive a cookie MAC
+    /// key or a session identifier from it.
+    ///
+    /// Note: will return `None` until the remote ephemeral key has been received (or given as a
+    /// pre-message key).
+    pub fn get_remote_ephemeral(&self) -> Option<&[u8]> {
+        self.re.get().map(|re| &re[..self.dh_len()])
+    }
+
+    /// Get the local static public key, if this side has one, e.g. to advertise it after
+    /// loading an opaque private key through a resolver.
+    ///
+    /// With the `sig` modifier, this is the public signing key.
+    pub fn get_local_static(&self) -> Option<&[u8]> {
+        match &self.signer {
+            Some(signer) => signer.get().map(|signer| signer.pubkey()),
+            None => self.s.get().map(|s| s.pubkey()),
+        }
     }
 
     /// Get the handshake hash.
@@ -494,6 +1112,24 @@ impl HandshakeState {
         self.symmetricstate.handshake_hash()
     }
 
+    /// Derive a PSK both peers can use to resume this session later, e.g. through an `NNpsk0`
+    /// handshake, without repeating any static key operations.
+    ///
+    /// The PSK is derived from the final chaining key, independently of the transport keys. See
+    /// the [`resumption`](crate::resumption) module for handing it out as a ticket.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State` if the handshake isn't finished.
+    pub fn resumption_psk(&mut self) -> Result<[u8; PSKLEN], Error> {
+        if !self.is_handshake_finished() {
+            bail!(StateProblem::HandshakeNotFinished);
+        }
+        let mut psk = [0u8; PSKLEN];
+        self.symmetricstate.derive_secret(RESUMPTION_LABEL, &mut psk);
+        Ok(psk)
+    }
+
     /// Check if this session was started with the "initiator" role.
     pub fn is_initiator(&self) -> bool {
         self.initiator
@@ -509,6 +1145,28 @@ impl HandshakeState {
         self.my_turn
     }
 
+    /// The index of the next handshake message to be written or read, which is the number of
+    /// handshake messages processed so far.
+    pub fn pattern_position(&self) -> usize {
+        self.pattern_position
+    }
+
+    /// What each message of the handshake carries: its tokens in order, then the payload, and
+    /// which of them are encrypted. Messages alternate starting with the initiator's, and the
+    /// next one to be written or read is at [`pattern_position()`](Self::pattern_position).
+    ///
+    /// This only depends on the protocol, so it can be shown before the handshake
+    /// starts, e.g. to check that a payload will be encrypted by the time it's sent.
+    pub fn message_tokens(&self) -> &[Vec<TokenView>] {
+        &self.token_views
+    }
+
+    /// The number of handshake messages left to be written or read before the handshake is
+    /// finished.
+    pub fn messages_remaining(&self) -> usize {
+        self.message_patterns.len() - self.pattern_position
+    }
+
     /// Perform the split calculation and return the resulting keys.
     ///
     /// This returns raw key material so it should be used with care. The "risky-raw-split"
@@ -520,6 +1178,56 @@ impl HandshakeState {
         (output.0[..CIPHERKEYLEN].try_into().unwrap(), output.1[..CIPHERKEYLEN].try_into().unwrap())
     }
 
+    /// Get the current chaining key, for protocols that derive keys of their own from it, e.g.
+    /// custom exporters. Together with [`get_handshake_hash()`](Self::get_handshake_hash), this is
+    /// all the symmetric state of the handshake besides the cipher key.
+    ///
+    /// Returns a slice of length `Hasher.hash_len()`, or an empty one for Disco handshakes,
+    /// which have no chaining key.
+    ///
+    /// This returns raw key material so it should be used with care: anything derived from it
+    /// the way the handshake derives its own keys would collide with them. The
+    /// "risky-chaining-key" feature has to be enabled to use this function.
+    #[cfg(feature = "risky-chaining-key")]
+    pub fn dangerously_get_chaining_key(&self) -> &[u8] {
+        self.symmetricstate.chaining_key()
+    }
+
+    /// Get the nonce of the cipher encrypting handshake fields and payloads.
+    ///
+    /// The "risky-set-nonce" feature has to be enabled to use this function.
+    #[cfg(feature = "risky-set-nonce")]
+    pub fn dangerously_get_nonce(&self) -> u64 {
+        self.symmetricstate.nonce()
+    }
+
+    /// Set the nonce of the cipher encrypting handshake fields and payloads, e.g. to reproduce
+    /// a message from another implementation's test vectors.
+    ///
+    /// Reusing a nonce under the same key breaks the confidentiality of everything it encrypts,
+    /// so this should only be used for testing. The "risky-set-nonce" feature has to be enabled
+    /// to use this function.
+    #[cfg(feature = "risky-set-nonce")]
+    pub fn dangerously_set_nonce(&mut self, nonce: u64) {
+        self.symmetricstate.set_nonce(nonce)
+    }
+
+    /// Convert this handshake into a [`TransportStateCore`] over the same cipher type.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::HandshakeNotFinished)` if there are handshake
+    /// messages left.
+    pub fn into_transport_core(self) -> Result<TransportStateCore<C>, Error> {
+        if !self.is_handshake_finished() {
+            bail!(StateProblem::HandshakeNotFinished);
+        }
+        let oneway = self.params.handshake.pattern.is_oneway();
+        Ok(TransportStateCore::new(self.cipherstates, self.initiator, oneway))
+    }
+}
+
+impl HandshakeState {
     /// Convert this `HandshakeState` into a `TransportState` with an internally stored nonce.
     pub fn into_transport_mode(self) -> Result<TransportState, Error> {
         self.try_into()
@@ -531,8 +1239,37 @@ impl HandshakeState {
     }
 }
 
-impl fmt::Debug for HandshakeState {
+/// Shows where the handshake is and which keys are set, but none of the key material.
+impl<D, C, H> fmt::Debug for HandshakeStateCore<D, C, H> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("HandshakeState").finish()
+        let psks: Vec<u8> = self.psks.iter().map(|(location, _)| *location).collect();
+        fmt.debug_struct("HandshakeState")
+            .field("protocol", &self.params.name)
+            .field("initiator", &self.initiator)
+            .field("pattern_position", &self.pattern_position)
+            .field("my_turn", &self.my_turn)
+            .field("s", &self.s.is_on())
+            .field("s_selector", &self.s_selector.is_some())
+            .field("e", &self.e.is_on())
+            .field("rs", &self.rs.is_on())
+            .field("re", &self.re.is_on())
+            .field("psks", &psks)
+            .field("padding", &self.padding)
+            .field("observer", &self.observer.is_some())
+            .field("key_usage", &self.key_usage.is_some())
+            .field("pinning", &self.pinning)
+            .field("transcript", &self.transcript.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Make sure that `remaining`, the unread tail of `message`, holds at least `len` more bytes.
+fn check_remaining(message: &[u8], remaining: &[u8], len: usize) -> Result<(), Error> {
+    if remaining.len() < len {
+        bail!(Error::TruncatedMessage {
+            needed: message.len() - remaining.len() + len,
+            got:    message.len(),
+        });
     }
+    Ok(())
 }
@@ -0,0 +1,317 @@
+//! Proving who's behind a static key, with identity evidence in handshake payloads.
+//!
+//! Noise authenticates static keys, but leaves deciding who a static key belongs to to the
+//! application. A common answer is to have a long-term identity key sign the static key, and
+//! possibly have that identity key signed in turn by an authority, like a certificate chain.
+//! This module encodes such [`Evidence`] and checks it with a pluggable [`Verifier`].
+//!
+//! Evidence goes in the payload of the handshake message that carries the sender's static key
+//! (see [`sends_static()`]), as the spec recommends: that payload is encrypted, and can only be
+//! read by whoever the static key was sent to. On the other side, once
+//! [`receives_static()`] said the message carries the peer's static key and it has been read,
+//! [`verify_payload()`] checks the payload against the static key the handshake learned.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, identity::*, params::SigChoice, resolvers::*};
+//! # #[cfg(feature = "sig-ed25519")]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let resolver = DefaultResolver;
+//! let mut rng = resolver.resolve_rng().unwrap();
+//! let mut identity = resolver.resolve_sig(&SigChoice::Ed25519).unwrap();
+//! identity.generate(&mut *rng).unwrap();
+//!
+//! let params: snow::params::NoiseParams = "Noise_NX_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let server_key = Builder::new(params.clone()).generate_keypair()?;
+//! let mut server = Builder::new(params.clone())
+//!     .local_private_key(&server_key.private)
+//!     .build_responder()?;
+//! let mut client = Builder::new(params.clone()).build_initiator()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! # let len = client.write_message(&[], &mut msg)?; server.read_message(&msg[..len], &mut buf)?;
+//!
+//! // The server vouches for its static key with its identity key...
+//! assert!(sends_static(&server));
+//! let evidence = Evidence::sign(&server_key.public, &*identity);
+//! let len = server.write_message(&evidence.to_bytes(), &mut msg)?;
+//!
+//! // ...which the client trusts.
+//! let verifier = ChainVerifier::new(
+//!     resolver.resolve_sig(&SigChoice::Ed25519).unwrap(),
+//!     vec![identity.pubkey().to_vec()],
+//! );
+//! assert!(receives_static(&client));
+//! let len = client.read_message(&msg[..len], &mut buf)?;
+//! let evidence = verify_payload(&client, &buf[..len], &verifier)?;
+//! assert_eq!(evidence.identity(), Some(identity.pubkey()));
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(feature = "sig-ed25519"))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::MAXSIGLEN,
+    error::{Error, Prerequisite},
+    handshakestate::HandshakeState,
+    params::Token,
+    types::Sign,
+};
+use std::convert::TryFrom;
+
+/// What every signature in a chain is over, ahead of the key it vouches for.
+const SIGNATURE_CONTEXT: &[u8] = b"snow identity:";
+
+/// One link of a chain: `signer` vouching for the key before it with `signature`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Link {
+    /// The public key that signed.
+    pub signer:    Vec<u8>,
+    /// The signature over the key before it in the chain.
+    pub signature: Vec<u8>,
+}
+
+/// A chain of signatures leading from a static key to a trusted key.
+///
+/// The first link's signer vouches for the static key, and is the peer's identity; each further
+/// link's signer vouches for the signer of the link before it. A single link is a raw public key
+/// certificate.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Evidence {
+    /// The links, starting from the one over the static key.
+    pub chain: Vec<Link>,
+}
+
+impl Evidence {
+    /// Have `identity` vouch for `static_public`, starting a chain.
+    pub fn sign(static_public: &[u8], identity: &dyn Sign) -> Self {
+        Evidence::default().link(static_public, identity)
+    }
+
+    /// Have `authority` vouch for the last signer in the chain, e.g. a CA endorsing an identity
+    /// key.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the chain is empty.
+    pub fn endorse(self, authority: &dyn Sign) -> Self {
+        let signer = self.chain.last().expect("nothing to endorse").signer.clone();
+        self.link(&signer, authority)
+    }
+
+    fn link(mut self, key: &[u8], signer: &dyn Sign) -> Self {
+        let mut signature = [0u8; MAXSIGLEN];
+        let len = signer.sign(&signed_message(key), &mut signature);
+        self.chain.push(Link {
+            signer:    signer.pubkey().to_vec(),
+            signature: signature[..len].to_vec(),
+        });
+        self
+    }
+
+    /// The key the peer's static key is vouched for by, which identifies the peer.
+    pub fn identity(&self) -> Option<&[u8]> {
+        self.chain.first().map(|link| &link.signer[..])
+    }
+
+    /// The last signer in the chain, which has to be trusted for the chain to be.
+    pub fn root(&self) -> Option<&[u8]> {
+        self.chain.last().map(|link| &link.signer[..])
+    }
+
+    /// Check that every signature in the chain is valid, starting from `static_public`.
+    ///
+    /// This says nothing about whether the chain leads to a trusted key; that's up to the
+    /// [`Verifier`].
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Sig` if the chain is empty or a signature doesn't verify.
+    pub fn check_signatures(&self, static_public: &[u8], sig: &dyn Sign) -> Result<(), Error> {
+        if self.chain.is_empty() {
+            bail!(Error::Sig);
+        }
+        let mut key = static_public;
+        for link in &self.chain {
+            if !sig.verify(&link.signer, &signed_message(key), &link.signature) {
+                bail!(Error::Sig);
+            }
+            key = &link.signer;
+        }
+        Ok(())
+    }
+
+    /// Encode the evidence for a handshake payload: a one-byte link count, then each link's
+    /// signer and signature, each with a big-endian `u16` length prefix.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the chain has more than 255 links or a key or signature is
+    /// longer than 65535 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![u8::try_from(self.chain.len()).expect("chain too long")];
+        for link in &self.chain {
+            for field in &[&link.signer, &link.signature] {
+                let len = u16::try_from(field.len()).expect("field too long");
+                out.extend_from_slice(&len.to_be_bytes());
+                out.extend_from_slice(field);
+            }
+        }
+        out
+    }
+
+    /// Decode evidence encoded by [`to_bytes()`](Self::to_bytes).
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if `bytes` is malformed or has anything after the evidence.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (&count, mut rest) = bytes.split_first().ok_or(Error::Input)?;
+        let mut field = || -> Result<Vec<u8>, Error> {
+            if rest.len() < 2 {
+                bail!(Error::Input);
+            }
+            let len = usize::from(u16::from_be_bytes([rest[0], rest[1]]));
+            let value = rest.get(2..2 + len).ok_or(Error::Input)?.to_vec();
+            rest = &rest[2 + len..];
+            Ok(value)
+        };
+        let chain = (0..count)
+            .map(|_| Ok(Link { signer: field()?, signature: field()? }))
+            .collect::<Result<_, Error>>()?;
+        if !rest.is_empty() {
+            bail!(Error::Input);
+        }
+        Ok(Evidence { chain })
+    }
+}
+
+/// Decides whether evidence proves who's behind a remote static key.
+pub trait Verifier {
+    /// Accept `evidence` for `remote_static`, or fail with an error, usually `Error::Sig`.
+    fn verify(&self, remote_static: &[u8], evidence: &Evidence) -> Result<(), Error>;
+}
+
+/// A [`Verifier`] accepting chains with valid signatures that end in one of a set of trusted
+/// keys.
+///
+/// Trusting identity keys directly accepts raw public key certificates from them; trusting an
+/// authority's key accepts any identity it endorsed.
+pub struct ChainVerifier {
+    sig:           Box<dyn Sign>,
+    trusted_roots: Vec<Vec<u8>>,
+}
+
+impl ChainVerifier {
+    /// Create a verifier checking signatures with `sig`, and trusting chains ending in any of
+    /// `trusted_roots`.
+    pub fn new(sig: Box<dyn Sign>, trusted_roots: Vec<Vec<u8>>) -> Self {
+        ChainVerifier { sig, trusted_roots }
+    }
+}
+
+impl Verifier for ChainVerifier {
+    fn verify(&self, remote_static: &[u8], evidence: &Evidence) -> Result<(), Error> {
+        evidence.check_signatures(remote_static, &*self.sig)?;
+        match evidence.root() {
+            Some(root) if self.trusted_roots.iter().any(|trusted| trusted == root) => Ok(()),
+            _ => bail!(Error::Sig),
+        }
+    }
+}
+
+/// Whether the next message `handshake` writes carries its static key, so its payload is where
+/// evidence for it goes.
+pub fn sends_static(handshake: &HandshakeState) -> bool {
+    handshake.is_my_turn() && next_message_has_static(handshake)
+}
+
+/// Whether the next message `handshake` reads carries the peer's static key, so its payload
+/// should be checked with [`verify_payload()`] once it's been read.
+pub fn receives_static(handshake: &HandshakeState) -> bool {
+    !handshake.is_my_turn() && next_message_has_static(handshake)
+}
+
+fn next_message_has_static(handshake: &HandshakeState) -> bool {
+    handshake
+        .message_patterns
+        .get(handshake.pattern_position)
+        .is_some_and(|tokens| tokens.contains(&Token::S))
+}
+
+/// Decode the evidence in `payload` and check it with `verifier` against the remote static key
+/// `handshake` has learned.
+///
+/// # Errors
+///
+/// Will result in `Error::Prereq(Prerequisite::RemotePublicKey)` if the remote static key isn't
+/// known yet, `Error::Input` if `payload` isn't evidence, and whatever `verifier` fails with if
+/// it doesn't accept it.
+pub fn verify_payload(
+    handshake: &HandshakeState,
+    payload: &[u8],
+    verifier: &dyn Verifier,
+) -> Result<Evidence, Error> {
+    let remote_static = handshake.get_remote_static().ok_or(Prerequisite::RemotePublicKey)?;
+    let evidence = Evidence::from_bytes(payload)?;
+    verifier.verify(remote_static, &evidence)?;
+    Ok(evidence)
+}
+
+/// The message a link's signature is over, binding it to this use.
+fn signed_message(key: &[u8]) -> Vec<u8> {
+    [SIGNATURE_CONTEXT, key].concat()
+}
+
+#[cfg(test)]
+#[cfg(feature = "sig-ed25519")]
+mod tests {
+    use super::*;
+    use crate::{
+        params::SigChoice,
+        resolvers::{CryptoResolver, DefaultResolver},
+    };
+
+    fn signer() -> Box<dyn Sign> {
+        let mut rng = DefaultResolver.resolve_rng().unwrap();
+        let mut sig = DefaultResolver.resolve_sig(&SigChoice::Ed25519).unwrap();
+        sig.generate(&mut *rng).unwrap();
+        sig
+    }
+
+    fn verifier(roots: &[&dyn Sign]) -> ChainVerifier {
+        let roots = roots.iter().map(|root| root.pubkey().to_vec()).collect();
+        ChainVerifier::new(DefaultResolver.resolve_sig(&SigChoice::Ed25519).unwrap(), roots)
+    }
+
+    #[test]
+    fn test_chain() {
+        let (identity, authority, stranger) = (signer(), signer(), signer());
+        let static_public = [7u8; 32];
+        let evidence = Evidence::sign(&static_public, &*identity).endorse(&*authority);
+        let evidence = Evidence::from_bytes(&evidence.to_bytes()).unwrap();
+        assert_eq!(evidence.identity(), Some(identity.pubkey()));
+        assert_eq!(evidence.root(), Some(authority.pubkey()));
+
+        assert!(verifier(&[&*authority]).verify(&static_public, &evidence).is_ok());
+        assert!(verifier(&[&*identity]).verify(&static_public, &evidence).is_err());
+        assert!(verifier(&[&*stranger]).verify(&static_public, &evidence).is_err());
+        assert!(verifier(&[&*authority]).verify(&[8u8; 32], &evidence).is_err());
+
+        let mut forged = evidence.clone();
+        forged.chain[1].signer = stranger.pubkey().to_vec();
+        assert!(verifier(&[&*stranger]).verify(&static_public, &forged).is_err());
+        assert!(verifier(&[&*authority]).verify(&static_public, &Evidence::default()).is_err());
+    }
+
+    #[test]
+    fn test_malformed_evidence() {
+        let bytes = Evidence::sign(&[7u8; 32], &*signer()).to_bytes();
+        for bad in &[&[][..], &bytes[..bytes.len() - 1], &[2, 0, 0, 0, 0]] {
+            assert!(Evidence::from_bytes(bad).is_err());
+        }
+        assert!(Evidence::from_bytes(&[&bytes[..], &[0]].concat()).is_err());
+    }
+}
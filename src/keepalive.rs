@@ -0,0 +1,208 @@
+//! Keep-alive messages and dead peer detection for established sessions, without doing any I/O.
+//!
+//! The convention is the same as [WireGuard's](https://www.wireguard.com/papers/wireguard.pdf)
+//! (section 6.5): a keep-alive is a transport message with an empty payload. It's encrypted and
+//! authenticated like any other message, so it proves the peer is still there, and it can't be
+//! told apart from data on the wire once padding is used.
+//!
+//! A [`KeepAliveTransport`] wraps a [`TransportState`], filters out incoming keep-alives, and
+//! tracks when messages were last sent and received, so the caller only has to check
+//! [`KeepAliveTransport::needs_keepalive()`] and [`KeepAliveTransport::is_peer_dead()`]
+//! periodically.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::keepalive::*;
+//! # use std::time::{Duration, Instant};
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let (i, r) = snow::doctest::nn_handshake()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! # let (transport_i, transport_r) = (i.into_transport_mode()?, r.into_transport_mode()?);
+//! let start = Instant::now();
+//! let mut initiator = KeepAliveTransport::new(transport_i, start);
+//! let mut responder = KeepAliveTransport::new(transport_r, start);
+//!
+//! // Nothing has been sent for a while, so let the peer know we're still here.
+//! let now = start + DEFAULT_KEEPALIVE_INTERVAL;
+//! assert!(initiator.needs_keepalive(now));
+//! let len = initiator.write_keepalive(&mut msg, now)?;
+//! assert_eq!(responder.read_message(&msg[..len], &mut buf, now)?, Received::KeepAlive);
+//! assert_eq!(responder.last_received(), now);
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{Error, TransportState};
+use std::time::{Duration, Instant};
+
+/// How long to stay silent before a keep-alive is due, unless configured otherwise.
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long without hearing from the peer before it's considered dead, unless configured
+/// otherwise.
+pub const DEFAULT_DEAD_PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What [`KeepAliveTransport::read_message()`] made of an incoming message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Received {
+    /// Application data, whose length is given.
+    Data(usize),
+
+    /// A keep-alive, which carries nothing for the application.
+    KeepAlive,
+}
+
+/// A [`TransportState`] that sends and recognizes keep-alives and tracks the peer's liveness.
+#[derive(Debug)]
+pub struct KeepAliveTransport {
+    transport:          TransportState,
+    last_sent:          Instant,
+    last_received:      Instant,
+    keepalive_interval: Duration,
+    dead_peer_timeout:  Duration,
+}
+
+impl KeepAliveTransport {
+    /// Track `transport`, which was established at `now`, with the default timeouts.
+    pub fn new(transport: TransportState, now: Instant) -> Self {
+        KeepAliveTransport {
+            transport,
+            last_sent: now,
+            last_received: now,
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            dead_peer_timeout: DEFAULT_DEAD_PEER_TIMEOUT,
+        }
+    }
+
+    /// Set how long to stay silent before a keep-alive is due.
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = interval;
+        self
+    }
+
+    /// Set how long without hearing from the peer before it's considered dead. This should be
+    /// a few times the peer's keep-alive interval, so a lost keep-alive or two is tolerated.
+    pub fn dead_peer_timeout(mut self, timeout: Duration) -> Self {
+        self.dead_peer_timeout = timeout;
+        self
+    }
+
+    /// The wrapped transport.
+    pub fn transport(&self) -> &TransportState {
+        &self.transport
+    }
+
+    /// Encrypt and send application data. See [`TransportState::write_message()`].
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if `payload` is empty, since that's a keep-alive, and
+    /// otherwise fails like `TransportState::write_message()`.
+    pub fn write_message(
+        &mut self,
+        payload: &[u8],
+        message: &mut [u8],
+        now: Instant,
+    ) -> Result<usize, Error> {
+        if payload.is_empty() {
+            bail!(Error::Input);
+        }
+        let len = self.transport.write_message(payload, message)?;
+        self.last_sent = now;
+        Ok(len)
+    }
+
+    /// Write a keep-alive message.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TransportState::write_message()`].
+    pub fn write_keepalive(&mut self, message: &mut [u8], now: Instant) -> Result<usize, Error> {
+        let len = self.transport.write_message(&[], message)?;
+        self.last_sent = now;
+        Ok(len)
+    }
+
+    /// Decrypt an incoming message, telling keep-alives apart from data.
+    ///
+    /// Only messages that decrypt count as signs of life.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TransportState::read_message()`].
+    pub fn read_message(
+        &mut self,
+        message: &[u8],
+        payload: &mut [u8],
+        now: Instant,
+    ) -> Result<Received, Error> {
+        let len = self.transport.read_message(message, payload)?;
+        self.last_received = now;
+        Ok(if len == 0 { Received::KeepAlive } else { Received::Data(len) })
+    }
+
+    /// When the last message (data or keep-alive) was sent.
+    pub fn last_sent(&self) -> Instant {
+        self.last_sent
+    }
+
+    /// When the last valid message (data or keep-alive) was received.
+    pub fn last_received(&self) -> Instant {
+        self.last_received
+    }
+
+    /// Whether nothing has been sent for the keep-alive interval, so a keep-alive should be
+    /// written now.
+    pub fn needs_keepalive(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_sent) >= self.keepalive_interval
+    }
+
+    /// Whether nothing has been received from the peer for the dead peer timeout.
+    pub fn is_peer_dead(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_received) >= self.dead_peer_timeout
+    }
+
+    /// Give up the liveness tracking and get back the transport.
+    pub fn into_inner(self) -> TransportState {
+        self.transport
+    }
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::doctest::transport_pair;
+
+    #[test]
+    fn test_liveness() {
+        let (i, r) = transport_pair();
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut i = KeepAliveTransport::new(i, start).keepalive_interval(5 * second);
+        let mut r = KeepAliveTransport::new(r, start).dead_peer_timeout(15 * second);
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+
+        assert!(!i.needs_keepalive(start + 4 * second));
+        let len = i.write_message(b"data", &mut msg, start + 4 * second).unwrap();
+        assert_eq!(
+            r.read_message(&msg[..len], &mut buf, start + 4 * second).unwrap(),
+            Received::Data(4)
+        );
+        assert!(!i.needs_keepalive(start + 8 * second));
+        assert!(i.needs_keepalive(start + 9 * second));
+
+        assert!(!r.is_peer_dead(start + 18 * second));
+        assert!(r.is_peer_dead(start + 19 * second));
+
+        // Forged messages aren't signs of life.
+        assert!(r.read_message(&[0u8; 32], &mut buf, start + 19 * second).is_err());
+        assert_eq!(r.last_received(), start + 4 * second);
+
+        assert!(matches!(i.write_message(&[], &mut msg, start), Err(Error::Input)));
+    }
+}
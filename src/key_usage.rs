@@ -0,0 +1,100 @@
+//! Catching a static key used as both initiator and responder of the same protocol.
+//!
+//! A peer whose static key plays both roles of one protocol can be attacked by reflection: its
+//! own handshake messages, replayed back to it, are valid messages from "the peer" holding the
+//! same key, so it ends up talking to itself while believing it authenticated someone. Noise
+//! leaves it to applications to keep the roles apart, e.g. with separate keys or prologues.
+//!
+//! Sharing a [`KeyUsageTracker`] between the builders of a process (see
+//! [`Builder::key_usage_tracker()`]) remembers which role each static key was built for, per
+//! protocol name, and fails building the other role. Keys that legitimately need both roles,
+//! such as those of peers in a symmetric mesh that keep the roles apart some other way, can be
+//! exempted with [`KeyUsageTracker::allow_both_roles()`].
+//!
+//! Only keys given with [`Builder::local_private_key()`] are tracked; keys picked by a
+//! [selector](crate::selector) once the handshake has started aren't.
+//!
+//! [`Builder::key_usage_tracker()`]: crate::Builder::key_usage_tracker
+//! [`Builder::local_private_key()`]: crate::Builder::local_private_key
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, key_usage::*};
+//! # use std::sync::Arc;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let params: snow::params::NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let key = Builder::new(params.clone()).generate_keypair()?.private;
+//! let tracker = Arc::new(KeyUsageTracker::new());
+//!
+//! let builder = || Builder::new(params.clone()).local_private_key(&key);
+//! builder().key_usage_tracker(tracker.clone()).build_responder()?;
+//! builder().key_usage_tracker(tracker.clone()).build_responder()?;
+//! assert!(builder().key_usage_tracker(tracker.clone()).build_initiator().is_err());
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::error::{Error, StateProblem};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+};
+
+#[derive(Debug, Default)]
+struct Usage {
+    /// Whether each (protocol name, static public key) was built as the initiator.
+    roles:   HashMap<(String, Vec<u8>), bool>,
+    allowed: HashSet<Vec<u8>>,
+}
+
+/// Remembers the role each static key was built for, failing builds of the other role.
+#[derive(Debug, Default)]
+pub struct KeyUsageTracker {
+    usage: Mutex<Usage>,
+}
+
+impl KeyUsageTracker {
+    /// Create a tracker that hasn't seen any keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let the static key whose public key is `public_key` play both roles.
+    pub fn allow_both_roles(&self, public_key: &[u8]) {
+        self.usage().allowed.insert(public_key.to_vec());
+    }
+
+    /// Forget the role of the static key whose public key is `public_key`, e.g. once it's
+    /// retired, so it can be built for either role again.
+    pub fn forget(&self, public_key: &[u8]) {
+        self.usage().roles.retain(|(_, key), _| key != public_key);
+    }
+
+    fn usage(&self) -> MutexGuard<'_, Usage> {
+        self.usage.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record that `public_key` is used as the initiator or the responder of `protocol`.
+    pub(crate) fn record(
+        &self,
+        protocol: &str,
+        public_key: &[u8],
+        initiator: bool,
+    ) -> Result<(), Error> {
+        let mut usage = self.usage();
+        if usage.allowed.contains(public_key) {
+            return Ok(());
+        }
+        let role =
+            usage.roles.entry((protocol.to_owned(), public_key.to_vec())).or_insert(initiator);
+        if *role != initiator {
+            bail!(StateProblem::KeyUsedInBothRoles);
+        }
+        Ok(())
+    }
+}
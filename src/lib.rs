@@ -52,6 +52,28 @@
 //! ```
 
 #![warn(missing_docs)]
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
+
+#[cfg(all(
+    feature = "forbid-unsafe",
+    any(
+        feature = "ffi",
+        feature = "mobile",
+        feature = "ring-resolver",
+        feature = "libsodium-resolver",
+        feature = "fips",
+        feature = "x509",
+        feature = "dh-secp256k1",
+        feature = "mlkem",
+        feature = "pqclean_kyber"
+    )
+))]
+compile_error!(
+    "the forbid-unsafe feature can't be combined with FFI bindings or C-backed resolvers"
+);
+
+#[cfg(feature = "mobile")]
+uniffi::setup_scaffolding!();
 
 macro_rules! copy_slices {
     ($inslice:expr, $outslice:expr) => {
@@ -66,30 +88,94 @@ macro_rules! static_slice {
     });
 }
 
+/// Record a `tracing` event with the "tracing" feature, and nothing without it.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)*);
+    };
+}
+
 macro_rules! bail {
     ($e:expr) => {
-        return Err(($e).into());
+        return Err(($e).into())
     };
 }
 
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
 mod builder;
+pub mod chunked;
 mod cipherstate;
+pub mod close;
+#[cfg(feature = "default-resolver")]
+pub mod conformance;
 mod constants;
+pub mod cookie;
+pub mod datagram;
+pub mod dh_cache;
+#[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+#[doc(hidden)]
+pub mod doctest;
+pub mod driver;
+#[cfg(feature = "embedded")]
+pub mod embedded;
+pub mod ephemeral;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "hash-sha2")]
+pub mod fingerprint;
+pub mod fixed;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 mod handshakestate;
+pub mod identity;
+pub mod keepalive;
+pub mod key_usage;
+pub mod keys;
+#[cfg(feature = "mobile")]
+pub mod mobile;
+pub mod mux;
+pub mod negotiate;
 mod stateless_transportstate;
+#[cfg(feature = "disco")]
+mod strobe;
 mod symmetricstate;
 mod transportstate;
 mod utils;
 
+pub mod nls;
+pub mod observer;
+pub mod padding;
 pub mod params;
+pub mod pinning;
+pub mod profiles;
+pub mod prologue;
+#[cfg(feature = "argon2")]
+pub mod psk;
+pub mod ratchet;
+pub mod rehandshake;
 pub mod resolvers;
+pub mod resumption;
+#[cfg(feature = "default-resolver")]
+pub mod sealed;
+pub mod selector;
+pub mod session;
+pub mod socket;
+#[cfg(feature = "futures-io")]
+pub mod stream;
+pub mod transcript;
 pub mod types;
+pub mod typestate;
+#[cfg(feature = "vectors")]
+pub mod vectors;
+pub mod x509;
 
 pub use crate::{
     builder::{Builder, Keypair},
     error::Error,
-    handshakestate::HandshakeState,
+    handshakestate::{CorePrimitives, EarlyData, HandshakeState, HandshakeStateCore, TokenView},
     stateless_transportstate::StatelessTransportState,
-    transportstate::TransportState,
+    transportstate::{TransportState, TransportStateCore},
 };
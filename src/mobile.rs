@@ -0,0 +1,343 @@
+//! [UniFFI](https://mozilla.github.io/uniffi-rs/) bindings, so Kotlin and Swift apps can run Noise
+//! handshakes through idiomatic generated classes.
+//!
+//! [`NoiseBuilder`], [`NoiseHandshake`] and [`NoiseTransport`] mirror [`Builder`],
+//! [`HandshakeState`] and [`TransportState`], with byte arrays in place of caller-provided
+//! buffers. Every error is surfaced as a [`NoiseError`] exception.
+//!
+//! Build the library with `cargo rustc --release --features mobile --crate-type cdylib`, then
+//! generate bindings from it with the bundled `uniffi-bindgen` binary:
+//!
+//! ```sh
+//! cargo run --features mobile --bin uniffi-bindgen -- generate \
+//!     --library target/release/libsnow.so --language swift --out-dir out
+//! ```
+
+use crate::{
+    constants::MAXMSGLEN,
+    error::{Error, StateProblem},
+    params::NoiseParams,
+    Builder, HandshakeState, Keypair, TransportState,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// The exception thrown by every fallible binding, one case per kind of [`Error`].
+///
+/// The message carries the full `Display` text of the underlying error.
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+#[allow(missing_docs)]
+pub enum NoiseError {
+    Pattern(Error),
+    Init(Error),
+    Prereq(Error),
+    State(Error),
+    Input(Error),
+    BufferTooSmall(Error),
+    Rng(Error),
+    Dh(Error),
+    Decrypt(Error),
+    TruncatedMessage(Error),
+    Sig(Error),
+    Prologue(Error),
+    Kem(Error),
+    MessageTooLarge(Error),
+}
+
+impl From<Error> for NoiseError {
+    fn from(e: Error) -> Self {
+        // Handshake errors keep their context, but are classified by what went wrong.
+        let kind = match &e {
+            Error::Handshake { source, .. } => &**source,
+            e => e,
+        };
+        match kind {
+            Error::Pattern(_) => NoiseError::Pattern(e),
+            Error::Init(_) => NoiseError::Init(e),
+            Error::Prereq(_) | Error::PatternPrereq { .. } => NoiseError::Prereq(e),
+            Error::State(_) => NoiseError::State(e),
+            Error::Input => NoiseError::Input(e),
+            Error::BufferTooSmall { .. } => NoiseError::BufferTooSmall(e),
+            Error::Rng => NoiseError::Rng(e),
+            Error::Dh => NoiseError::Dh(e),
+            Error::Decrypt => NoiseError::Decrypt(e),
+            Error::TruncatedMessage { .. } => NoiseError::TruncatedMessage(e),
+            Error::MessageTooLarge { .. } => NoiseError::MessageTooLarge(e),
+            Error::Sig => NoiseError::Sig(e),
+            Error::Prologue => NoiseError::Prologue(e),
+            #[cfg(feature = "hfs")]
+            Error::Kem => NoiseError::Kem(e),
+            Error::Handshake { .. } => NoiseError::State(e),
+        }
+    }
+}
+
+impl From<StateProblem> for NoiseError {
+    fn from(reason: StateProblem) -> Self {
+        Error::from(reason).into()
+    }
+}
+
+impl fmt::Display for NoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoiseError::Pattern(e)
+            | NoiseError::Init(e)
+            | NoiseError::Prereq(e)
+            | NoiseError::State(e)
+            | NoiseError::Input(e)
+            | NoiseError::BufferTooSmall(e)
+            | NoiseError::Rng(e)
+            | NoiseError::Dh(e)
+            | NoiseError::Decrypt(e)
+            | NoiseError::TruncatedMessage(e)
+            | NoiseError::Sig(e)
+            | NoiseError::Prologue(e)
+            | NoiseError::Kem(e)
+            | NoiseError::MessageTooLarge(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for NoiseError {}
+
+/// The state of a mutex is never left inconsistent by a panic in here, so poisoning is ignored.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Default)]
+struct BuilderKeys {
+    local_private_key: Option<Vec<u8>>,
+    remote_public_key: Option<Vec<u8>>,
+    prologue:          Option<Vec<u8>>,
+    psks:              Vec<(u8, Vec<u8>)>,
+}
+
+/// See [`Builder`]. Keys are copied in, and the same builder can build any number of handshakes.
+#[derive(uniffi::Object)]
+pub struct NoiseBuilder {
+    params: NoiseParams,
+    keys:   Mutex<BuilderKeys>,
+}
+
+#[uniffi::export]
+impl NoiseBuilder {
+    /// Create a builder for a Noise protocol name, e.g. `Noise_XX_25519_ChaChaPoly_BLAKE2s`.
+    #[uniffi::constructor]
+    pub fn new(params: String) -> Result<Arc<Self>, NoiseError> {
+        let params = params.parse::<NoiseParams>()?;
+        Ok(Arc::new(NoiseBuilder { params, keys: Mutex::default() }))
+    }
+
+    /// See [`Builder::local_private_key`].
+    pub fn local_private_key(self: Arc<Self>, key: Vec<u8>) -> Arc<Self> {
+        lock(&self.keys).local_private_key = Some(key);
+        self
+    }
+
+    /// See [`Builder::remote_public_key`].
+    pub fn remote_public_key(self: Arc<Self>, key: Vec<u8>) -> Arc<Self> {
+        lock(&self.keys).remote_public_key = Some(key);
+        self
+    }
+
+    /// See [`Builder::prologue`].
+    pub fn prologue(self: Arc<Self>, prologue: Vec<u8>) -> Arc<Self> {
+        lock(&self.keys).prologue = Some(prologue);
+        self
+    }
+
+    /// See [`Builder::psk`].
+    pub fn psk(self: Arc<Self>, location: u8, key: Vec<u8>) -> Arc<Self> {
+        {
+            let mut keys = lock(&self.keys);
+            keys.psks.retain(|(l, _)| *l != location);
+            keys.psks.push((location, key));
+        }
+        self
+    }
+
+    /// See [`Builder::generate_keypair`].
+    pub fn generate_keypair(&self) -> Result<Keypair, NoiseError> {
+        Ok(Builder::new(self.params.clone()).generate_keypair()?)
+    }
+
+    /// See [`Builder::build_initiator`].
+    pub fn build_initiator(&self) -> Result<Arc<NoiseHandshake>, NoiseError> {
+        self.build(true)
+    }
+
+    /// See [`Builder::build_responder`].
+    pub fn build_responder(&self) -> Result<Arc<NoiseHandshake>, NoiseError> {
+        self.build(false)
+    }
+}
+
+impl NoiseBuilder {
+    fn build(&self, initiator: bool) -> Result<Arc<NoiseHandshake>, NoiseError> {
+        let keys = lock(&self.keys);
+        let mut builder = Builder::new(self.params.clone());
+        if let Some(key) = &keys.local_private_key {
+            builder = builder.local_private_key(key);
+        }
+        if let Some(key) = &keys.remote_public_key {
+            builder = builder.remote_public_key(key);
+        }
+        if let Some(prologue) = &keys.prologue {
+            builder = builder.prologue(prologue);
+        }
+        for (location, key) in &keys.psks {
+            builder = builder.psk(*location, key);
+        }
+        let state = if initiator { builder.build_initiator() } else { builder.build_responder() }?;
+        Ok(Arc::new(NoiseHandshake { state: Mutex::new(Some(state)) }))
+    }
+}
+
+/// See [`HandshakeState`]. Once turned into a [`NoiseTransport`], every method fails with
+/// [`NoiseError::State`].
+#[derive(uniffi::Object)]
+pub struct NoiseHandshake {
+    state: Mutex<Option<HandshakeState>>,
+}
+
+impl NoiseHandshake {
+    fn with<T>(
+        &self,
+        f: impl FnOnce(&mut HandshakeState) -> Result<T, Error>,
+    ) -> Result<T, NoiseError> {
+        let mut state = lock(&self.state);
+        let state = state.as_mut().ok_or(StateProblem::HandshakeAlreadyFinished)?;
+        Ok(f(state)?)
+    }
+}
+
+#[uniffi::export]
+impl NoiseHandshake {
+    /// See [`HandshakeState::write_message`]. Returns the handshake message.
+    pub fn write_message(&self, payload: Vec<u8>) -> Result<Vec<u8>, NoiseError> {
+        self.with(|state| {
+            let mut message = vec![0u8; MAXMSGLEN];
+            let len = state.write_message(&payload, &mut message)?;
+            message.truncate(len);
+            Ok(message)
+        })
+    }
+
+    /// See [`HandshakeState::read_message`]. Returns the payload.
+    pub fn read_message(&self, message: Vec<u8>) -> Result<Vec<u8>, NoiseError> {
+        self.with(|state| {
+            let mut payload = vec![0u8; message.len()];
+            let len = state.read_message(&message, &mut payload)?;
+            payload.truncate(len);
+            Ok(payload)
+        })
+    }
+
+    /// See [`HandshakeState::is_handshake_finished`].
+    pub fn is_handshake_finished(&self) -> bool {
+        lock(&self.state).as_ref().is_none_or(HandshakeState::is_handshake_finished)
+    }
+
+    /// See [`HandshakeState::is_my_turn`].
+    pub fn is_my_turn(&self) -> bool {
+        lock(&self.state).as_ref().is_some_and(HandshakeState::is_my_turn)
+    }
+
+    /// See [`HandshakeState::get_remote_static`].
+    pub fn get_remote_static(&self) -> Result<Option<Vec<u8>>, NoiseError> {
+        self.with(|state| Ok(state.get_remote_static().map(<[u8]>::to_vec)))
+    }
+
+    /// See [`HandshakeState::get_remote_ephemeral`].
+    pub fn get_remote_ephemeral(&self) -> Result<Option<Vec<u8>>, NoiseError> {
+        self.with(|state| Ok(state.get_remote_ephemeral().map(<[u8]>::to_vec)))
+    }
+
+    /// See [`HandshakeState::get_local_static`].
+    pub fn get_local_static(&self) -> Result<Option<Vec<u8>>, NoiseError> {
+        self.with(|state| Ok(state.get_local_static().map(<[u8]>::to_vec)))
+    }
+
+    /// See [`HandshakeState::get_handshake_hash`].
+    pub fn get_handshake_hash(&self) -> Result<Vec<u8>, NoiseError> {
+        self.with(|state| Ok(state.get_handshake_hash().to_vec()))
+    }
+
+    /// See [`HandshakeState::into_transport_mode`]. This handshake can't be used afterwards,
+    /// even if the conversion fails.
+    pub fn into_transport_mode(&self) -> Result<Arc<NoiseTransport>, NoiseError> {
+        let state = lock(&self.state).take().ok_or(StateProblem::HandshakeAlreadyFinished)?;
+        let state = state.into_transport_mode()?;
+        Ok(Arc::new(NoiseTransport { state: Mutex::new(state) }))
+    }
+}
+
+/// See [`TransportState`].
+#[derive(uniffi::Object)]
+pub struct NoiseTransport {
+    state: Mutex<TransportState>,
+}
+
+#[uniffi::export]
+impl NoiseTransport {
+    /// See [`TransportState::write_message`]. Returns the transport message.
+    pub fn write_message(&self, payload: Vec<u8>) -> Result<Vec<u8>, NoiseError> {
+        let mut message = vec![0u8; MAXMSGLEN];
+        let len = lock(&self.state).write_message(&payload, &mut message)?;
+        message.truncate(len);
+        Ok(message)
+    }
+
+    /// See [`TransportState::read_message`]. Returns the payload.
+    pub fn read_message(&self, message: Vec<u8>) -> Result<Vec<u8>, NoiseError> {
+        let mut payload = vec![0u8; message.len()];
+        let len = lock(&self.state).read_message(&message, &mut payload)?;
+        payload.truncate(len);
+        Ok(payload)
+    }
+
+    /// See [`TransportState::get_remote_static`].
+    pub fn get_remote_static(&self) -> Option<Vec<u8>> {
+        lock(&self.state).get_remote_static().map(<[u8]>::to_vec)
+    }
+
+    /// See [`TransportState::get_local_static`].
+    pub fn get_local_static(&self) -> Option<Vec<u8>> {
+        lock(&self.state).get_local_static().map(<[u8]>::to_vec)
+    }
+
+    /// See [`TransportState::rekey_outgoing`].
+    pub fn rekey_outgoing(&self) {
+        lock(&self.state).rekey_outgoing()
+    }
+
+    /// See [`TransportState::rekey_incoming`].
+    pub fn rekey_incoming(&self) {
+        lock(&self.state).rekey_incoming()
+    }
+
+    /// See [`TransportState::sending_nonce`].
+    pub fn sending_nonce(&self) -> u64 {
+        lock(&self.state).sending_nonce()
+    }
+
+    /// See [`TransportState::receiving_nonce`].
+    pub fn receiving_nonce(&self) -> u64 {
+        lock(&self.state).receiving_nonce()
+    }
+
+    /// See [`TransportState::set_receiving_nonce`].
+    pub fn set_receiving_nonce(&self, nonce: u64) {
+        lock(&self.state).set_receiving_nonce(nonce)
+    }
+
+    /// See [`TransportState::is_initiator`].
+    pub fn is_initiator(&self) -> bool {
+        lock(&self.state).is_initiator()
+    }
+}
@@ -0,0 +1,195 @@
+//! Multiplexing logical channels over a single transport.
+//!
+//! Each message carries one channel's data, prefixed with the channel ID (a big-endian `u32`)
+//! inside the encrypted payload, so channel IDs and boundaries are as confidential as the data.
+//! Channels don't need to be opened: the first message on an ID starts tracking it. How many
+//! messages and bytes went each way is accounted per channel, so applications can enforce their
+//! own quotas or flow control on top.
+//!
+//! Every message still goes through the one transport and its nonce sequence, so messages have
+//! to be read in the order they were written, across all channels.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::mux::*;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let (mut i, mut r) = snow::doctest::nn_handshake()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! let mut initiator = Mux::new(i.into_transport_mode()?);
+//! let mut responder = Mux::new(r.into_transport_mode()?);
+//!
+//! let len = initiator.write_message(7, b"control", &mut msg)?;
+//! let (channel, len) = responder.read_message(&msg[..len], &mut buf)?;
+//! assert_eq!((channel, &buf[..len]), (7, &b"control"[..]));
+//! assert_eq!(responder.stats(7).unwrap().bytes_received, 7);
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{Error, TransportState};
+use std::{collections::HashMap, io::IoSlice};
+
+/// The length of the channel ID prefixed to each message's payload.
+pub const CHANNEL_HEADER_LEN: usize = 4;
+
+/// How much traffic a channel has carried in each direction.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ChannelStats {
+    /// Messages written on the channel.
+    pub messages_sent: u64,
+
+    /// Payload bytes written on the channel, not counting the channel header.
+    pub bytes_sent: u64,
+
+    /// Messages read from the channel.
+    pub messages_received: u64,
+
+    /// Payload bytes read from the channel, not counting the channel header.
+    pub bytes_received: u64,
+}
+
+/// A [`TransportState`] carrying several logical channels.
+#[derive(Debug)]
+pub struct Mux {
+    transport: TransportState,
+    channels:  HashMap<u32, ChannelStats>,
+}
+
+impl Mux {
+    /// Multiplex channels over `transport`.
+    pub fn new(transport: TransportState) -> Self {
+        Mux { transport, channels: HashMap::new() }
+    }
+
+    /// The wrapped transport.
+    pub fn transport(&self) -> &TransportState {
+        &self.transport
+    }
+
+    /// Encrypt `payload` as a message on `channel` into `message`.
+    ///
+    /// Returns the size of the written message.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TransportState::write_message()`], counting the channel header towards the
+    /// payload length.
+    pub fn write_message(
+        &mut self,
+        channel: u32,
+        payload: &[u8],
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        let header = channel.to_be_bytes();
+        let len = self
+            .transport
+            .write_message_vectored(&[IoSlice::new(&header), IoSlice::new(payload)], message)?;
+        let stats = self.channels.entry(channel).or_default();
+        stats.messages_sent += 1;
+        stats.bytes_sent += payload.len() as u64;
+        Ok(len)
+    }
+
+    /// Decrypt a message into `payload`, returning its channel and the length of its data.
+    ///
+    /// `payload` has to be able to hold the channel header too, though only the data is left in
+    /// it.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::TruncatedMessage` if the message decrypts but is too short to have
+    /// a channel header, and otherwise fails like [`TransportState::read_message()`].
+    pub fn read_message(
+        &mut self,
+        message: &[u8],
+        payload: &mut [u8],
+    ) -> Result<(u32, usize), Error> {
+        let len = self.transport.read_message(message, payload)?;
+        if len < CHANNEL_HEADER_LEN {
+            bail!(Error::TruncatedMessage { needed: CHANNEL_HEADER_LEN, got: len });
+        }
+        let mut header = [0u8; CHANNEL_HEADER_LEN];
+        header.copy_from_slice(&payload[..CHANNEL_HEADER_LEN]);
+        let channel = u32::from_be_bytes(header);
+        payload.copy_within(CHANNEL_HEADER_LEN..len, 0);
+        let len = len - CHANNEL_HEADER_LEN;
+
+        let stats = self.channels.entry(channel).or_default();
+        stats.messages_received += 1;
+        stats.bytes_received += len as u64;
+        Ok((channel, len))
+    }
+
+    /// The traffic on `channel` so far, if it has carried any messages.
+    pub fn stats(&self, channel: u32) -> Option<&ChannelStats> {
+        self.channels.get(&channel)
+    }
+
+    /// Every channel that has carried messages, with its traffic.
+    pub fn channels(&self) -> impl Iterator<Item = (u32, &ChannelStats)> {
+        self.channels.iter().map(|(channel, stats)| (*channel, stats))
+    }
+
+    /// Stop tracking `channel`, returning its traffic. A later message on the same ID starts
+    /// counting from zero again.
+    pub fn close_channel(&mut self, channel: u32) -> Option<ChannelStats> {
+        self.channels.remove(&channel)
+    }
+
+    /// Give up the multiplexing and get back the transport.
+    pub fn into_inner(self) -> TransportState {
+        self.transport
+    }
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::doctest::transport_pair;
+
+    fn mux_pair() -> (Mux, Mux) {
+        let (i, r) = transport_pair();
+        (Mux::new(i), Mux::new(r))
+    }
+
+    #[test]
+    fn test_channels() {
+        let (mut i, mut r) = mux_pair();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        for (channel, data) in &[(1u32, &b"abc"[..]), (2, b""), (1, b"defgh"), (u32::MAX, b"z")] {
+            let len = i.write_message(*channel, data, &mut msg).unwrap();
+            let (got, len) = r.read_message(&msg[..len], &mut buf).unwrap();
+            assert_eq!((got, &buf[..len]), (*channel, *data));
+        }
+
+        let expected = ChannelStats {
+            messages_sent:     2,
+            bytes_sent:        8,
+            messages_received: 0,
+            bytes_received:    0,
+        };
+        assert_eq!(i.stats(1), Some(&expected));
+        assert_eq!(r.stats(1).unwrap().bytes_received, 8);
+        assert_eq!(r.stats(2).unwrap().messages_received, 1);
+        assert_eq!(r.channels().count(), 3);
+        assert_eq!(r.close_channel(2).unwrap().messages_received, 1);
+        assert!(r.stats(2).is_none());
+    }
+
+    #[test]
+    fn test_missing_header() {
+        let (i, mut r) = mux_pair();
+        let mut i = i.into_inner();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = i.write_message(b"ab", &mut msg).unwrap();
+        assert!(matches!(
+            r.read_message(&msg[..len], &mut buf),
+            Err(Error::TruncatedMessage { needed: CHANNEL_HEADER_LEN, got: 2 })
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+//! Runtime protocol agility: agreeing on one of several Noise protocols before the handshake.
+//!
+//! Each side lists the [`NoiseParams`] it accepts, most preferred first, in a [`Negotiator`]. The
+//! initiator sends its [`offer()`](Negotiator::offer), the responder picks the protocol it
+//! prefers most among those offered with [`select()`](Negotiator::select) and sends back the
+//! [selection](Negotiated::selection), which the initiator checks with
+//! [`accept()`](Negotiator::accept). Both sides end up with the same [`Negotiated`] protocol.
+//!
+//! Neither message is protected, so both are bound into the [prologue](Negotiated::prologue) of
+//! the handshake that follows: an attacker who strips the strongest protocols out of the offer
+//! (or changes the selection) makes the handshake fail, rather than downgrading it.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, negotiate::Negotiator};
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let client = Negotiator::new(vec![
+//!     "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse()?,
+//!     "Noise_NN_25519_AESGCM_SHA256".parse()?,
+//! ])
+//! .context(b"my-app v1");
+//! let server =
+//!     Negotiator::new(vec!["Noise_NN_25519_AESGCM_SHA256".parse()?]).context(b"my-app v1");
+//!
+//! let offer = client.offer();
+//! let server_choice = server.select(&offer)?;
+//! let client_choice = client.accept(server_choice.selection())?;
+//! assert_eq!(client_choice.params().name, "Noise_NN_25519_AESGCM_SHA256");
+//!
+//! let initiator = Builder::new(client_choice.params().clone())
+//!     .prologue(client_choice.prologue())
+//!     .build_initiator()?;
+//! let responder = Builder::new(server_choice.params().clone())
+//!     .prologue(server_choice.prologue())
+//!     .build_responder()?;
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    error::{Error, StateProblem},
+    params::NoiseParams,
+    prologue::PrologueBuilder,
+};
+
+/// The context label the negotiation is bound under when none is given.
+const DEFAULT_CONTEXT: &[u8] = b"snow negotiation";
+
+/// One side's ordered list of acceptable protocols.
+#[derive(Clone, Debug)]
+pub struct Negotiator {
+    supported: Vec<NoiseParams>,
+    context:   Vec<u8>,
+}
+
+impl Negotiator {
+    /// Create a negotiator for `supported`, most preferred first.
+    ///
+    /// At most 255 protocols are offered; any after that are ignored.
+    pub fn new(supported: Vec<NoiseParams>) -> Self {
+        Negotiator { supported, context: DEFAULT_CONTEXT.to_vec() }
+    }
+
+    /// Set an application context label, also bound into the prologue. Both sides have to use
+    /// the same one.
+    pub fn context(mut self, context: &[u8]) -> Self {
+        self.context = context.to_vec();
+        self
+    }
+
+    /// The acceptable protocols, most preferred first.
+    pub fn supported(&self) -> &[NoiseParams] {
+        &self.supported
+    }
+
+    /// Encode the initiator's offer: a one-byte count, then each protocol name with a one-byte
+    /// length prefix, in order of preference.
+    pub fn offer(&self) -> Vec<u8> {
+        let offered = &self.supported[..self.supported.len().min(255)];
+        let mut offer = vec![offered.len() as u8];
+        for params in offered {
+            // Protocol names can't be longer than 255 bytes, or they wouldn't have parsed.
+            offer.push(params.name.len() as u8);
+            offer.extend_from_slice(params.name.as_bytes());
+        }
+        offer
+    }
+
+    /// As the responder, pick the most preferred of our protocols that the initiator offered.
+    ///
+    /// Offered names we don't know are skipped, so that peers can offer protocols newer than
+    /// ours.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if `offer` is malformed, and
+    /// `Error::State(StateProblem::NoMutualProtocol)` if none of the offered protocols are
+    /// supported.
+    pub fn select(&self, offer: &[u8]) -> Result<Negotiated, Error> {
+        let offered = decode_offer(offer)?;
+        for params in &self.supported {
+            if let Some(index) = offered.iter().position(|name| *name == params.name.as_bytes()) {
+                return Ok(self.negotiated(params, offer, index as u8));
+            }
+        }
+        bail!(StateProblem::NoMutualProtocol);
+    }
+
+    /// As the initiator, check the responder's selection against our offer.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if `selection` doesn't pick one of the offered protocols.
+    pub fn accept(&self, selection: &[u8]) -> Result<Negotiated, Error> {
+        let index = match *selection {
+            // Only the first 255 protocols are offered.
+            [index] if index < 255 => index,
+            _ => bail!(Error::Input),
+        };
+        let params = self.supported.get(usize::from(index)).ok_or(Error::Input)?;
+        Ok(self.negotiated(params, &self.offer(), index))
+    }
+
+    fn negotiated(&self, params: &NoiseParams, offer: &[u8], index: u8) -> Negotiated {
+        let prologue = PrologueBuilder::new(&self.context)
+            .field("offer", offer)
+            .field("selection", &[index])
+            .build();
+        Negotiated {
+            params:    params.clone(),
+            selection: index,
+            prologue:  prologue.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// The protocol both sides agreed on.
+#[derive(Clone, Debug)]
+pub struct Negotiated {
+    params:    NoiseParams,
+    selection: u8,
+    prologue:  Vec<u8>,
+}
+
+impl Negotiated {
+    /// The agreed protocol.
+    pub fn params(&self) -> &NoiseParams {
+        &self.params
+    }
+
+    /// The responder's reply to the offer: the position of the chosen protocol in it, as one
+    /// byte.
+    pub fn selection(&self) -> &[u8] {
+        std::slice::from_ref(&self.selection)
+    }
+
+    /// The prologue binding the offer and selection, to be passed to
+    /// [`Builder::prologue()`](crate::Builder::prologue).
+    pub fn prologue(&self) -> &[u8] {
+        &self.prologue
+    }
+}
+
+fn decode_offer(offer: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let (&count, mut rest) = offer.split_first().ok_or(Error::Input)?;
+    let mut names = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        let (&len, tail) = rest.split_first().ok_or(Error::Input)?;
+        names.push(tail.get(..usize::from(len)).ok_or(Error::Input)?);
+        rest = &tail[usize::from(len)..];
+    }
+    if !rest.is_empty() {
+        bail!(Error::Input);
+    }
+    Ok(names)
+}
+
+#[cfg(all(test, not(feature = "fips")))]
+mod tests {
+    use super::*;
+
+    fn negotiator(names: &[&str]) -> Negotiator {
+        Negotiator::new(names.iter().map(|name| name.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn test_responder_preference_wins() {
+        let client = negotiator(&[
+            "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+            "Noise_XX_25519_AESGCM_SHA256",
+            "Noise_XX_448_AESGCM_SHA512",
+        ]);
+        let server = negotiator(&["Noise_XX_448_AESGCM_SHA512", "Noise_XX_25519_AESGCM_SHA256"]);
+
+        let server_choice = server.select(&client.offer()).unwrap();
+        let client_choice = client.accept(server_choice.selection()).unwrap();
+        assert_eq!(server_choice.params().name, "Noise_XX_448_AESGCM_SHA512");
+        assert_eq!(client_choice.params(), server_choice.params());
+        assert_eq!(client_choice.prologue(), server_choice.prologue());
+    }
+
+    #[test]
+    fn test_downgraded_offer_changes_prologue() {
+        let client = negotiator(&["Noise_XX_448_AESGCM_SHA512", "Noise_XX_25519_AESGCM_SHA256"]);
+        let server = negotiator(&["Noise_XX_448_AESGCM_SHA512", "Noise_XX_25519_AESGCM_SHA256"]);
+
+        // An attacker strips the preferred protocol from the offer.
+        let stripped = negotiator(&["Noise_XX_25519_AESGCM_SHA256"]).offer();
+        let server_choice = server.select(&stripped).unwrap();
+        let client_choice = client.accept(&[1]).unwrap();
+        assert_eq!(client_choice.params(), server_choice.params());
+        assert_ne!(client_choice.prologue(), server_choice.prologue());
+    }
+
+    #[test]
+    fn test_no_mutual_protocol() {
+        let client = negotiator(&["Noise_XX_25519_ChaChaPoly_BLAKE2s"]);
+        let server = negotiator(&["Noise_XX_448_AESGCM_SHA512"]);
+        assert!(matches!(
+            server.select(&client.offer()),
+            Err(Error::State(StateProblem::NoMutualProtocol))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_messages() {
+        let client = negotiator(&["Noise_XX_25519_ChaChaPoly_BLAKE2s"]);
+        let offer = client.offer();
+        for bad in &[&[][..], &offer[..offer.len() - 1], &[&offer[..], &[0]].concat()] {
+            assert!(matches!(client.select(bad), Err(Error::Input)));
+        }
+        for bad in &[&[][..], &[1], &[0, 0]] {
+            assert!(matches!(client.accept(bad), Err(Error::Input)));
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Payload structures for the NoiseLingoSocket (NLS) profile of [NoiseSocket](crate::socket).
+//!
+//! NLS fixes the schema of the NoiseSocket negotiation data and of the handshake payloads, so
+//! that independent implementations agree on how to offer protocols, identify PSKs, and exchange
+//! evidence (certificates, signatures and the like) for static keys. All of the messages are
+//! encoded as [protocol buffers](https://developers.google.com/protocol-buffers/docs/encoding);
+//! unknown fields are skipped when decoding so newer peers remain compatible.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::nls::{NegotiationRequest, NegotiationResponse};
+//! let request = NegotiationRequest {
+//!     initial_protocol: "Noise_XX_25519_AESGCM_SHA256".to_owned(),
+//!     retry_protocols: vec!["Noise_XX_25519_ChaChaPoly_BLAKE2s".to_owned()],
+//!     ..Default::default()
+//! };
+//!
+//! let request = NegotiationRequest::decode(&request.encode()).unwrap();
+//! let response = NegotiationResponse::Retry(request.retry_protocols[0].clone());
+//! assert_eq!(NegotiationResponse::decode(&response.encode()).unwrap(), response);
+//! ```
+
+use crate::{error::Error, socket::Negotiation};
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+/// The negotiation data sent by the initiator alongside its first handshake message.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct NegotiationRequest {
+    /// The name of the server the initiator is trying to reach, for virtual hosting.
+    pub server_name: String,
+
+    /// The protocol name of the handshake message this request is sent with.
+    pub initial_protocol: String,
+
+    /// Protocols the initiator is willing to switch to, in order of preference.
+    pub switch_protocols: Vec<String>,
+
+    /// Protocols the initiator is willing to retry with, in order of preference.
+    pub retry_protocols: Vec<String>,
+
+    /// The protocol the responder rejected, if this request is sent as part of a retry.
+    pub rejected_protocol: String,
+
+    /// An identifier for the PSK the initiator is using, if the protocol has a PSK.
+    pub psk_id: Vec<u8>,
+}
+
+impl NegotiationRequest {
+    /// Encode this request as a protocol buffer.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_bytes(&mut out, 1, self.server_name.as_bytes());
+        put_bytes(&mut out, 2, self.initial_protocol.as_bytes());
+        for protocol in &self.switch_protocols {
+            put_repeated(&mut out, 3, protocol.as_bytes());
+        }
+        for protocol in &self.retry_protocols {
+            put_repeated(&mut out, 4, protocol.as_bytes());
+        }
+        put_bytes(&mut out, 5, self.rejected_protocol.as_bytes());
+        put_bytes(&mut out, 6, &self.psk_id);
+        out
+    }
+
+    /// Decode a request from a protocol buffer.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the buffer is malformed.
+    pub fn decode(mut input: &[u8]) -> Result<Self, Error> {
+        let mut request = NegotiationRequest::default();
+        while !input.is_empty() {
+            match next_field(&mut input)? {
+                (1, Field::Bytes(v)) => request.server_name = utf8(v)?,
+                (2, Field::Bytes(v)) => request.initial_protocol = utf8(v)?,
+                (3, Field::Bytes(v)) => request.switch_protocols.push(utf8(v)?),
+                (4, Field::Bytes(v)) => request.retry_protocols.push(utf8(v)?),
+                (5, Field::Bytes(v)) => request.rejected_protocol = utf8(v)?,
+                (6, Field::Bytes(v)) => request.psk_id = v.to_vec(),
+                _ => {},
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// The negotiation data sent by the responder in reply to a [`NegotiationRequest`].
+#[derive(Clone, PartialEq, Debug)]
+pub enum NegotiationResponse {
+    /// Continue with the initial protocol. This is encoded as empty negotiation data.
+    Accept,
+
+    /// The responder switched to the named protocol.
+    Switch(String),
+
+    /// The initiator should retry with the named protocol.
+    Retry(String),
+
+    /// The responder rejected the request, with a human-readable explanation.
+    Reject(String),
+}
+
+impl NegotiationResponse {
+    /// Encode this response as a protocol buffer.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            NegotiationResponse::Accept => {},
+            NegotiationResponse::Switch(protocol) => put_repeated(&mut out, 3, protocol.as_bytes()),
+            NegotiationResponse::Retry(protocol) => put_repeated(&mut out, 4, protocol.as_bytes()),
+            NegotiationResponse::Reject(reason) => put_repeated(&mut out, 5, reason.as_bytes()),
+        }
+        out
+    }
+
+    /// Decode a response from a protocol buffer.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the buffer is malformed.
+    pub fn decode(mut input: &[u8]) -> Result<Self, Error> {
+        let mut response = NegotiationResponse::Accept;
+        while !input.is_empty() {
+            response = match next_field(&mut input)? {
+                (3, Field::Bytes(v)) => NegotiationResponse::Switch(utf8(v)?),
+                (4, Field::Bytes(v)) => NegotiationResponse::Retry(utf8(v)?),
+                (5, Field::Bytes(v)) => NegotiationResponse::Reject(utf8(v)?),
+                _ => response,
+            }
+        }
+        Ok(response)
+    }
+
+    /// Convert this response into the [`Negotiation`] it describes, parsing any protocol name.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Pattern` if a protocol name can't be parsed.
+    pub fn to_negotiation(&self) -> Result<Negotiation, Error> {
+        Ok(match self {
+            NegotiationResponse::Accept => Negotiation::Accept,
+            NegotiationResponse::Switch(protocol) => Negotiation::Switch(protocol.parse()?),
+            NegotiationResponse::Retry(protocol) => Negotiation::Retry(protocol.parse()?),
+            NegotiationResponse::Reject(_) => Negotiation::Reject,
+        })
+    }
+}
+
+/// The payload carried in NLS handshake messages, used to exchange evidence for static keys.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct HandshakePayload {
+    /// The kinds of evidence the sender would like the other side to provide.
+    pub evidence_request_types: Vec<String>,
+
+    /// The kind of each blob in `evidence_blobs`, in the same order.
+    pub evidence_blob_types: Vec<String>,
+
+    /// Evidence for the sender's static key, such as a certificate or signature.
+    pub evidence_blobs: Vec<Vec<u8>>,
+
+    /// An identifier for the PSK the sender is using, if the protocol has a PSK.
+    pub psk_id: Vec<u8>,
+}
+
+impl HandshakePayload {
+    /// Encode this payload as a protocol buffer.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for kind in &self.evidence_request_types {
+            put_repeated(&mut out, 1, kind.as_bytes());
+        }
+        for kind in &self.evidence_blob_types {
+            put_repeated(&mut out, 2, kind.as_bytes());
+        }
+        for blob in &self.evidence_blobs {
+            put_repeated(&mut out, 3, blob);
+        }
+        put_bytes(&mut out, 4, &self.psk_id);
+        out
+    }
+
+    /// Decode a payload from a protocol buffer.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the buffer is malformed.
+    pub fn decode(mut input: &[u8]) -> Result<Self, Error> {
+        let mut payload = HandshakePayload::default();
+        while !input.is_empty() {
+            match next_field(&mut input)? {
+                (1, Field::Bytes(v)) => payload.evidence_request_types.push(utf8(v)?),
+                (2, Field::Bytes(v)) => payload.evidence_blob_types.push(utf8(v)?),
+                (3, Field::Bytes(v)) => payload.evidence_blobs.push(v.to_vec()),
+                (4, Field::Bytes(v)) => payload.psk_id = v.to_vec(),
+                _ => {},
+            }
+        }
+        Ok(payload)
+    }
+
+    /// Iterate over the evidence blobs paired with their types.
+    pub fn evidence(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.evidence_blob_types
+            .iter()
+            .zip(self.evidence_blobs.iter())
+            .map(|(kind, blob)| (kind.as_str(), blob.as_slice()))
+    }
+}
+
+enum Field<'a> {
+    Varint,
+    Bytes(&'a [u8]),
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Write a singular length-delimited field, omitting it if empty as proto3 does.
+fn put_bytes(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+    if !value.is_empty() {
+        put_repeated(out, field, value);
+    }
+}
+
+/// Write one element of a repeated length-delimited field.
+fn put_repeated(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_varint(out, field << 3 | WIRE_LEN);
+    put_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn get_varint(input: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first().ok_or(Error::Input)?;
+        *input = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!(Error::Input);
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if input.len() < len {
+        bail!(Error::Input);
+    }
+    let (value, rest) = input.split_at(len);
+    *input = rest;
+    Ok(value)
+}
+
+fn next_field<'a>(input: &mut &'a [u8]) -> Result<(u64, Field<'a>), Error> {
+    let key = get_varint(input)?;
+    let value = match key & 0x7 {
+        WIRE_VARINT => {
+            get_varint(input)?;
+            Field::Varint
+        },
+        WIRE_FIXED64 => {
+            take(input, 8)?;
+            Field::Varint
+        },
+        WIRE_LEN => {
+            let len = get_varint(input)? as usize;
+            Field::Bytes(take(input, len)?)
+        },
+        WIRE_FIXED32 => {
+            take(input, 4)?;
+            Field::Varint
+        },
+        _ => bail!(Error::Input),
+    };
+    Ok((key >> 3, value))
+}
+
+fn utf8(value: &[u8]) -> Result<String, Error> {
+    String::from_utf8(value.to_vec()).map_err(|_| Error::Input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let request = NegotiationRequest {
+            server_name:       "example.com".to_owned(),
+            initial_protocol:  "Noise_XX_25519_AESGCM_SHA256".to_owned(),
+            switch_protocols:  vec!["Noise_XXfallback_25519_AESGCM_SHA256".to_owned()],
+            retry_protocols:   vec!["a".to_owned(), "b".to_owned()],
+            rejected_protocol: String::new(),
+            psk_id:            vec![1, 2, 3],
+        };
+        assert_eq!(NegotiationRequest::decode(&request.encode()).unwrap(), request);
+    }
+
+    #[test]
+    fn test_payload_encoding() {
+        let payload = HandshakePayload {
+            evidence_blob_types: vec!["x".to_owned()],
+            evidence_blobs: vec![vec![0xff; 2]],
+            ..Default::default()
+        };
+        assert_eq!(payload.encode(), b"\x12\x01x\x1a\x02\xff\xff");
+        assert_eq!(payload.evidence().collect::<Vec<_>>(), vec![("x", &[0xff, 0xff][..])]);
+    }
+
+    #[test]
+    fn test_skips_unknown_fields() {
+        // field 15 (varint), field 9 (fixed32), then psk_id
+        let encoded = b"\x78\x96\x01\x4d\x01\x02\x03\x04\x22\x01\x07";
+        let payload = HandshakePayload::decode(encoded).unwrap();
+        assert_eq!(payload.psk_id, vec![7]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(HandshakePayload::decode(b"\x1a\x05\x00").is_err());
+        assert!(HandshakePayload::decode(b"\x78\xff").is_err());
+        assert!(NegotiationRequest::decode(b"\x0a\x01\xff").is_err());
+    }
+}
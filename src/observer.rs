@@ -0,0 +1,89 @@
+//! Hooks for watching what sessions do, e.g. to export metrics, without wrapping every call.
+//!
+//! Implement [`SessionObserver`] for whatever keeps your counters, and hand it to
+//! [`Builder::observer()`](crate::Builder::observer). The handshake, and then the transport state
+//! it turns into, report their events to it as they happen. One observer can be shared by every
+//! session in the process, so it's passed around in an `Arc`.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, observer::*};
+//! # use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! #[derive(Default)]
+//! struct Counters {
+//!     handshakes: AtomicUsize,
+//!     bytes_out:  AtomicUsize,
+//! }
+//!
+//! impl SessionObserver for Counters {
+//!     fn handshake_completed(&self, _params: &snow::params::NoiseParams, _initiator: bool) {
+//!         self.handshakes.fetch_add(1, Ordering::Relaxed);
+//!     }
+//!
+//!     fn message_encrypted(&self, len: usize) {
+//!         self.bytes_out.fetch_add(len, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let counters = Arc::new(Counters::default());
+//! let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let mut initiator = Builder::new(params.clone()).observer(counters.clone()).build_initiator()?;
+//! # let mut responder = Builder::new(params).build_responder()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! # let len = initiator.write_message(&[], &mut msg)?; responder.read_message(&msg[..len], &mut buf)?;
+//! # let len = responder.write_message(&[], &mut msg)?; initiator.read_message(&msg[..len], &mut buf)?;
+//! // ... finish the handshake ...
+//! let mut initiator = initiator.into_transport_mode()?;
+//! initiator.write_message(b"hello", &mut msg)?;
+//!
+//! assert_eq!(counters.handshakes.load(Ordering::Relaxed), 1);
+//! assert_eq!(counters.bytes_out.load(Ordering::Relaxed), 5 + 16);
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::params::NoiseParams;
+
+/// Which way the traffic keyed by a rekeyed cipher flows, from this side's point of view.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// Messages this side writes.
+    Outgoing,
+
+    /// Messages this side reads.
+    Incoming,
+}
+
+/// Receives a session's events as they happen.
+///
+/// Every method does nothing by default, so implementors only override what they count. They're
+/// called inline, while the session is in the middle of the operation that triggered them, so
+/// they should be quick and must not block.
+pub trait SessionObserver: Send + Sync {
+    /// A handshake was built, or reset to start over.
+    fn handshake_started(&self, _params: &NoiseParams, _initiator: bool) {}
+
+    /// The last handshake message was written or read, so the session may switch to transport
+    /// mode.
+    fn handshake_completed(&self, _params: &NoiseParams, _initiator: bool) {}
+
+    /// A transport message was written; `len` is its length on the wire.
+    fn message_encrypted(&self, _len: usize) {}
+
+    /// A transport message was read; `len` is its length on the wire.
+    fn message_decrypted(&self, _len: usize) {}
+
+    /// A handshake or transport message failed to decrypt, because it was tampered with, sent
+    /// under other keys, or isn't a Noise message at all.
+    fn decrypt_failed(&self) {}
+
+    /// A transport cipher got a new key, through `rekey_outgoing()`, `rekey_incoming()` or one of
+    /// the manual rekeys.
+    fn rekeyed(&self, _direction: Direction) {}
+}
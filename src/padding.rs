@@ -0,0 +1,265 @@
+//! Length-hiding padding policies for transport messages.
+//!
+//! When a policy is set with [`Builder::padding()`](crate::Builder::padding), every transport
+//! payload is framed before encryption as a 2-byte big-endian body length, followed by the body
+//! itself and then zero-valued padding. The framing is stripped again after decryption, so the
+//! caller only ever sees the original body. This is the same convention used by the
+//! [NoiseSocket](https://noisesocket.org/) specification.
+//!
+//! Both sides of a session must agree on whether padding is in use, but they are free to use
+//! different policies.
+
+use crate::{
+    constants::{MAXMSGLEN, TAGLEN},
+    error::Error,
+    types::Random,
+};
+#[cfg(not(feature = "forbid-unsafe"))]
+use std::mem::MaybeUninit;
+use std::{io::IoSlice, ops::Range};
+
+/// The length of the big-endian body length prefix added to each padded plaintext.
+pub(crate) const LENGTH_PREFIX_LEN: usize = 2;
+
+/// The largest framed plaintext that still fits in a single Noise message.
+const MAXPADDEDLEN: usize = MAXMSGLEN - TAGLEN;
+
+/// How much padding to add to each transport message.
+///
+/// Sizes are capped at the largest plaintext that fits in a single Noise message when the policy
+/// is given to the [`Builder`](crate::Builder).
+///
+/// # Examples
+///
+/// ```
+/// # use snow::{Builder, padding::PaddingPolicy};
+/// # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+/// let noise = Builder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+///     .padding(PaddingPolicy::Block(256))
+///     .build_initiator()
+///     .unwrap();
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub enum PaddingPolicy {
+    /// Pad each framed plaintext up to the next multiple of the given block size.
+    Block(usize),
+
+    /// Pad each framed plaintext up to the smallest of the given sizes that fits it.
+    ///
+    /// Messages larger than every bucket are sent with no padding at all.
+    Buckets(Vec<usize>),
+
+    /// Append a uniformly random number of padding bytes between zero and the given maximum.
+    Random(usize),
+}
+
+impl PaddingPolicy {
+    /// This policy with every size capped at the largest plaintext that fits in a single Noise
+    /// message.
+    pub(crate) fn clamped(self) -> Self {
+        match self {
+            PaddingPolicy::Block(block) => PaddingPolicy::Block(block.min(MAXPADDEDLEN)),
+            PaddingPolicy::Buckets(buckets) => PaddingPolicy::Buckets(
+                buckets.into_iter().map(|bucket| bucket.min(MAXPADDEDLEN)).collect(),
+            ),
+            PaddingPolicy::Random(max) => PaddingPolicy::Random(max.min(MAXPADDEDLEN)),
+        }
+    }
+
+    /// The length of the framed and padded plaintext for a body of `len` bytes.
+    ///
+    /// This is never less than the framed length of the body, and never more than the maximum
+    /// plaintext that fits in a single Noise message.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the framed body doesn't fit in a single Noise message,
+    /// and `Error::Rng` if the RNG fails while picking a random length.
+    pub(crate) fn padded_len(&self, len: usize, rng: &mut dyn Random) -> Result<usize, Error> {
+        let framed = match len.checked_add(LENGTH_PREFIX_LEN) {
+            Some(framed) if framed <= MAXPADDEDLEN => framed,
+            _ => bail!(Error::Input),
+        };
+        let target = match self {
+            PaddingPolicy::Block(block) if *block > 1 => {
+                framed.div_ceil(*block).saturating_mul(*block)
+            },
+            PaddingPolicy::Block(_) => framed,
+            PaddingPolicy::Buckets(buckets) => {
+                buckets.iter().copied().filter(|bucket| *bucket >= framed).min().unwrap_or(framed)
+            },
+            PaddingPolicy::Random(max) => {
+                let mut random = [0u8; 8];
+                rng.try_fill_bytes(&mut random).map_err(|_| Error::Rng)?;
+                let pad = u64::from_le_bytes(random) % (*max as u64).saturating_add(1);
+                framed.saturating_add(pad as usize)
+            },
+        };
+        Ok(target.min(MAXPADDEDLEN).max(framed))
+    }
+}
+
+/// Frame `body` with its length prefix and zero-pad it out to `padded_len` bytes.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if the framed body doesn't fit in a single Noise message.
+pub(crate) fn pad(body: &[u8], padded_len: usize) -> Result<Vec<u8>, Error> {
+    if body.len() + LENGTH_PREFIX_LEN > MAXPADDEDLEN {
+        bail!(Error::Input);
+    }
+
+    let mut plaintext = vec![0u8; padded_len];
+    plaintext[..LENGTH_PREFIX_LEN].copy_from_slice(&(body.len() as u16).to_be_bytes());
+    plaintext[LENGTH_PREFIX_LEN..LENGTH_PREFIX_LEN + body.len()].copy_from_slice(body);
+    Ok(plaintext)
+}
+
+/// Gather the slices of a payload into the front of `plaintext`, framing and zero-padding them
+/// out to the whole buffer if `framed` is set.
+///
+/// `plaintext` must be large enough for the gathered (and framed) payload.
+pub(crate) fn gather(payload: &[IoSlice<'_>], plaintext: &mut [u8], framed: bool) {
+    let mut pos = 0;
+    if framed {
+        let len = payload.iter().map(|slice| slice.len()).sum::<usize>() as u16;
+        plaintext[..LENGTH_PREFIX_LEN].copy_from_slice(&len.to_be_bytes());
+        pos = LENGTH_PREFIX_LEN;
+    }
+    for slice in payload {
+        plaintext[pos..pos + slice.len()].copy_from_slice(slice);
+        pos += slice.len();
+    }
+    if framed {
+        plaintext[pos..].fill(0);
+    }
+}
+
+/// Like [`gather()`], into a buffer that hasn't been initialized. Every byte of `plaintext` is
+/// written, so it has to be exactly as long as the gathered (and framed) payload.
+#[cfg(not(feature = "forbid-unsafe"))]
+pub(crate) fn gather_uninit(payload: &[u8], plaintext: &mut [MaybeUninit<u8>], framed: bool) {
+    let (prefix, rest) = plaintext.split_at_mut(if framed { LENGTH_PREFIX_LEN } else { 0 });
+    let (body, pad) = rest.split_at_mut(payload.len());
+    if framed {
+        write_uninit(prefix, &(payload.len() as u16).to_be_bytes());
+        pad.fill(MaybeUninit::new(0));
+    }
+    write_uninit(body, payload);
+}
+
+#[cfg(not(feature = "forbid-unsafe"))]
+fn write_uninit(dst: &mut [MaybeUninit<u8>], src: &[u8]) {
+    for (dst, src) in dst.iter_mut().zip(src) {
+        *dst = MaybeUninit::new(*src);
+    }
+}
+
+/// Strip the framing from a decrypted plaintext in place, moving the body to the front
+/// of `plaintext`.
+///
+/// Returns the length of the body.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if the length prefix is missing or runs past the end of
+/// the plaintext.
+pub(crate) fn unpad(plaintext: &mut [u8]) -> Result<usize, Error> {
+    let body = body_range(plaintext)?;
+    let len = body.len();
+    plaintext.copy_within(body, 0);
+    Ok(len)
+}
+
+/// Find the body of a decrypted plaintext without moving it.
+///
+/// # Errors
+///
+/// Same as [`unpad()`].
+pub(crate) fn body_range(plaintext: &[u8]) -> Result<Range<usize>, Error> {
+    if plaintext.len() < LENGTH_PREFIX_LEN {
+        bail!(Error::Input);
+    }
+
+    let len = u16::from_be_bytes([plaintext[0], plaintext[1]]) as usize;
+    if LENGTH_PREFIX_LEN + len > plaintext.len() {
+        bail!(Error::Input);
+    }
+    Ok(LENGTH_PREFIX_LEN..LENGTH_PREFIX_LEN + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::{impls, CryptoRng, RngCore};
+
+    struct FixedRng(u64);
+
+    impl RngCore for FixedRng {
+        fn next_u32(&mut self) -> u32 {
+            self.0 as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for FixedRng {}
+    impl Random for FixedRng {}
+
+    #[test]
+    fn test_padded_len() {
+        let mut rng = FixedRng(7);
+        let mut padded_len = |policy: PaddingPolicy, len| policy.padded_len(len, &mut rng).unwrap();
+        assert_eq!(padded_len(PaddingPolicy::Block(16), 0), 16);
+        assert_eq!(padded_len(PaddingPolicy::Block(16), 14), 16);
+        assert_eq!(padded_len(PaddingPolicy::Block(16), 15), 32);
+        assert_eq!(padded_len(PaddingPolicy::Block(0), 15), 17);
+        assert_eq!(padded_len(PaddingPolicy::Buckets(vec![512, 64]), 70), 512);
+        assert_eq!(padded_len(PaddingPolicy::Buckets(vec![512, 64]), 600), 602);
+        assert_eq!(padded_len(PaddingPolicy::Random(4), 10), 14);
+        assert_eq!(padded_len(PaddingPolicy::Block(MAXMSGLEN), 10), MAXPADDEDLEN);
+        assert_eq!(padded_len(PaddingPolicy::Block(usize::MAX), 10), MAXPADDEDLEN);
+        assert_eq!(padded_len(PaddingPolicy::Random(usize::MAX), 10), 19);
+        assert!(PaddingPolicy::Block(16).padded_len(MAXPADDEDLEN, &mut rng).is_err());
+        assert!(PaddingPolicy::Block(16).padded_len(usize::MAX, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_clamped() {
+        assert_eq!(PaddingPolicy::Block(usize::MAX).clamped(), PaddingPolicy::Block(MAXPADDEDLEN));
+        assert_eq!(
+            PaddingPolicy::Buckets(vec![64, usize::MAX]).clamped(),
+            PaddingPolicy::Buckets(vec![64, MAXPADDEDLEN])
+        );
+        assert_eq!(
+            PaddingPolicy::Random(usize::MAX).clamped(),
+            PaddingPolicy::Random(MAXPADDEDLEN)
+        );
+        assert_eq!(PaddingPolicy::Random(32).clamped(), PaddingPolicy::Random(32));
+    }
+
+    #[test]
+    fn test_pad_roundtrip() {
+        let mut plaintext = pad(b"hello", 32).unwrap();
+        assert_eq!(plaintext.len(), 32);
+        assert_eq!(unpad(&mut plaintext).unwrap(), 5);
+        assert_eq!(&plaintext[..5], b"hello");
+    }
+
+    #[test]
+    fn test_unpad_malformed() {
+        assert!(unpad(&mut [0u8]).is_err());
+        assert!(unpad(&mut [0u8, 4, 1, 2, 3]).is_err());
+    }
+}
@@ -1,22 +1,42 @@
 //! All structures related to Noise parameter definitions (cryptographic primitive choices, protocol
 //! patterns/names)
 
-use crate::error::{Error, PatternProblem};
-use std::str::FromStr;
+use crate::{
+    constants::MAXPROTOCOLNAMELEN,
+    error::{Error, PatternProblem},
+};
+use std::{fmt, str::FromStr};
 mod patterns;
-
-pub use self::patterns::{
-    HandshakeChoice, HandshakeModifier, HandshakeModifierList, HandshakePattern,
-    SUPPORTED_HANDSHAKE_PATTERNS,
+mod validate;
+
+pub use self::{
+    patterns::{
+        DhToken, HandshakeChoice, HandshakeModifier, HandshakeModifierList, HandshakePattern,
+        HandshakeTokens, Token, SUPPORTED_HANDSHAKE_PATTERNS,
+    },
+    validate::check_protocol_name,
 };
 
-pub(crate) use self::patterns::{DhToken, HandshakeTokens, MessagePatterns, Token};
+pub(crate) use self::patterns::MessagePatterns;
 
 /// I recommend you choose `Noise`.
+///
+/// With the `disco` feature, `NoiseDisco` replaces the symmetric primitives with a single
+/// [Strobe](https://strobe.sourceforge.io/) duplex, as specified by
+/// [Disco](https://www.discocrypto.com/disco.html).
+///
+/// With the `legacy-psk` feature, `NoisePSK` is the PSK mode of earlier revisions of the spec,
+/// from before the `pskN` modifiers, for talking to deployments that still use it. The PSK (set
+/// at location 0) is mixed in right after the prologue, and every ephemeral key is mixed into the
+/// key as well as the hash. It can't be combined with `pskN` modifiers.
 #[allow(missing_docs)]
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum BaseChoice {
     Noise,
+    #[cfg(feature = "disco")]
+    NoiseDisco,
+    #[cfg(feature = "legacy-psk")]
+    NoisePSK,
 }
 
 impl FromStr for BaseChoice {
@@ -26,17 +46,40 @@ impl FromStr for BaseChoice {
         use self::BaseChoice::*;
         match s {
             "Noise" => Ok(Noise),
+            #[cfg(feature = "disco")]
+            "NoiseDisco" => Ok(NoiseDisco),
+            #[cfg(feature = "legacy-psk")]
+            "NoisePSK" => Ok(NoisePSK),
             _ => bail!(PatternProblem::UnsupportedBaseType),
         }
     }
 }
 
-/// One of `25519` or `448`, per the spec.
+impl fmt::Display for BaseChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::BaseChoice::*;
+        f.write_str(match self {
+            Noise => "Noise",
+            #[cfg(feature = "disco")]
+            NoiseDisco => "NoiseDisco",
+            #[cfg(feature = "legacy-psk")]
+            NoisePSK => "NoisePSK",
+        })
+    }
+}
+
+/// One of `25519` or `448`, per the spec, or `P256` or `secp256k1`.
 #[allow(missing_docs)]
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum DHChoice {
     Curve25519,
     Ed448,
+    /// ECDH over NIST P-256, with public keys as 32-byte x-coordinates.
+    P256,
+    /// ECDH over secp256k1 as the Lightning Network uses it, with 33-byte compressed public keys
+    /// and the SHA-256 of the compressed shared point as the DH output. The default resolver
+    /// implements it with the `dh-secp256k1` feature.
+    Secp256k1,
 }
 
 impl FromStr for DHChoice {
@@ -47,11 +90,25 @@ impl FromStr for DHChoice {
         match s {
             "25519" => Ok(Curve25519),
             "448" => Ok(Ed448),
+            "P256" => Ok(P256),
+            "secp256k1" => Ok(Secp256k1),
             _ => bail!(PatternProblem::UnsupportedDhType),
         }
     }
 }
 
+impl fmt::Display for DHChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::DHChoice::*;
+        f.write_str(match self {
+            Curve25519 => "25519",
+            Ed448 => "448",
+            P256 => "P256",
+            Secp256k1 => "secp256k1",
+        })
+    }
+}
+
 /// One of `ChaChaPoly` or `AESGCM`, per the spec.
 #[allow(missing_docs)]
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -60,6 +117,18 @@ pub enum CipherChoice {
     #[cfg(feature = "xchachapoly")]
     XChaChaPoly,
     AESGCM,
+    /// AEGIS-128L, which only uses the first 16 bytes of the 32-byte key. Not in the spec.
+    #[cfg(feature = "cipher-aegis")]
+    Aegis128L,
+    /// AEGIS-256. Not in the spec.
+    #[cfg(feature = "cipher-aegis")]
+    Aegis256,
+    /// Ascon-128, which only uses the first 16 bytes of the 32-byte key. Not in the spec.
+    #[cfg(feature = "cipher-ascon")]
+    Ascon128,
+    /// Strobe, which is only valid with the `NoiseDisco` base.
+    #[cfg(feature = "disco")]
+    Strobe,
 }
 
 impl FromStr for CipherChoice {
@@ -72,19 +141,52 @@ impl FromStr for CipherChoice {
             #[cfg(feature = "xchachapoly")]
             "XChaChaPoly" => Ok(XChaChaPoly),
             "AESGCM" => Ok(AESGCM),
+            #[cfg(feature = "cipher-aegis")]
+            "AEGIS128L" => Ok(Aegis128L),
+            #[cfg(feature = "cipher-aegis")]
+            "AEGIS256" => Ok(Aegis256),
+            #[cfg(feature = "cipher-ascon")]
+            "Ascon128" => Ok(Ascon128),
             _ => bail!(PatternProblem::UnsupportedCipherType),
         }
     }
 }
 
+impl fmt::Display for CipherChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::CipherChoice::*;
+        f.write_str(match self {
+            ChaChaPoly => "ChaChaPoly",
+            #[cfg(feature = "xchachapoly")]
+            XChaChaPoly => "XChaChaPoly",
+            AESGCM => "AESGCM",
+            #[cfg(feature = "cipher-aegis")]
+            Aegis128L => "AEGIS128L",
+            #[cfg(feature = "cipher-aegis")]
+            Aegis256 => "AEGIS256",
+            #[cfg(feature = "cipher-ascon")]
+            Ascon128 => "Ascon128",
+            #[cfg(feature = "disco")]
+            Strobe => crate::strobe::STROBE_VERSION,
+        })
+    }
+}
+
 /// One of the supported SHA-family or BLAKE-family hash choices, per the spec.
 #[allow(missing_docs)]
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum HashChoice {
     SHA256,
+    SHA384,
     SHA512,
     Blake2s,
     Blake2b,
+    /// Ascon-Hash, whose HMAC uses 64-byte blocks. Not in the spec.
+    #[cfg(feature = "hash-ascon")]
+    AsconHash,
+    /// Strobe, which is only valid with the `NoiseDisco` base.
+    #[cfg(feature = "disco")]
+    Strobe,
 }
 
 impl FromStr for HashChoice {
@@ -94,19 +196,80 @@ impl FromStr for HashChoice {
         use self::HashChoice::*;
         match s {
             "SHA256" => Ok(SHA256),
+            "SHA384" => Ok(SHA384),
             "SHA512" => Ok(SHA512),
             "BLAKE2s" => Ok(Blake2s),
             "BLAKE2b" => Ok(Blake2b),
+            #[cfg(feature = "hash-ascon")]
+            "AsconHash" => Ok(AsconHash),
             _ => bail!(PatternProblem::UnsupportedHashType),
         }
     }
 }
 
-/// One of the supported Kems provided for unstable HFS extension.
-#[cfg(feature = "hfs")]
+impl fmt::Display for HashChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::HashChoice::*;
+        f.write_str(match self {
+            SHA256 => "SHA256",
+            SHA384 => "SHA384",
+            SHA512 => "SHA512",
+            Blake2s => "BLAKE2s",
+            Blake2b => "BLAKE2b",
+            #[cfg(feature = "hash-ascon")]
+            AsconHash => "AsconHash",
+            #[cfg(feature = "disco")]
+            Strobe => crate::strobe::STROBE_VERSION,
+        })
+    }
+}
+
+/// The signature algorithm for the signatures extension (see [`HandshakeModifier::Sig`]).
+///
+/// Only `Ed25519` is supported, since none of the resolvers implement `Ed448`.
 #[allow(missing_docs)]
 #[derive(PartialEq, Copy, Clone, Debug)]
+pub enum SigChoice {
+    Ed25519,
+}
+
+impl FromStr for SigChoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::SigChoice::*;
+        match s {
+            "Ed25519" => Ok(Ed25519),
+            _ => bail!(PatternProblem::UnsupportedSigType),
+        }
+    }
+}
+
+impl fmt::Display for SigChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::SigChoice::*;
+        f.write_str(match self {
+            Ed25519 => "Ed25519",
+        })
+    }
+}
+
+/// One of the supported Kems for the HFS (hybrid forward secrecy) extension, named after the DH
+/// function in a protocol name, e.g. `Noise_XXhfs_25519+MLKEM768_ChaChaPoly_BLAKE2s`.
+#[cfg(feature = "hfs")]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum KemChoice {
+    /// ML-KEM (FIPS 203) at NIST security level 1.
+    MlKem512,
+    /// ML-KEM (FIPS 203) at NIST security level 3.
+    MlKem768,
+    /// ML-KEM (FIPS 203) at NIST security level 5.
+    MlKem1024,
+    /// Round 3 Kyber at NIST security level 1.
+    Kyber512,
+    /// Round 3 Kyber at NIST security level 3.
+    Kyber768,
+    /// Round 3 Kyber at NIST security level 5.
     Kyber1024,
 }
 
@@ -117,12 +280,32 @@ impl FromStr for KemChoice {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use self::KemChoice::*;
         match s {
+            "MLKEM512" => Ok(MlKem512),
+            "MLKEM768" => Ok(MlKem768),
+            "MLKEM1024" => Ok(MlKem1024),
+            "Kyber512" => Ok(Kyber512),
+            "Kyber768" => Ok(Kyber768),
             "Kyber1024" => Ok(Kyber1024),
             _ => bail!(PatternProblem::UnsupportedKemType),
         }
     }
 }
 
+#[cfg(feature = "hfs")]
+impl fmt::Display for KemChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::KemChoice::*;
+        f.write_str(match self {
+            MlKem512 => "MLKEM512",
+            MlKem768 => "MLKEM768",
+            MlKem1024 => "MLKEM1024",
+            Kyber512 => "Kyber512",
+            Kyber768 => "Kyber768",
+            Kyber1024 => "Kyber1024",
+        })
+    }
+}
+
 /// The set of choices (as specified in the Noise spec) that constitute a full protocol definition.
 ///
 /// See: [Chapter 11: Protocol Names](http://noiseprotocol.org/noise.html#protocol-names).
@@ -136,6 +319,15 @@ impl FromStr for KemChoice {
 ///
 /// let params: NoiseParams = "Noise_XX_25519_AESGCM_SHA256".parse().unwrap();
 /// ```
+///
+/// Secondary algorithms for extensions follow the DH function, separated by `+`:
+///
+/// ```
+/// # use snow::params::*;
+///
+/// let params: NoiseParams = "Noise_XXsig_25519+Ed25519_AESGCM_SHA256".parse().unwrap();
+/// assert_eq!(params.sig, Some(SigChoice::Ed25519));
+/// ```
 #[allow(missing_docs)]
 #[derive(PartialEq, Clone, Debug)]
 pub struct NoiseParams {
@@ -145,6 +337,7 @@ pub struct NoiseParams {
     pub dh:        DHChoice,
     #[cfg(feature = "hfs")]
     pub kem:       Option<KemChoice>,
+    pub sig:       Option<SigChoice>,
     pub cipher:    CipherChoice,
     pub hash:      HashChoice,
 }
@@ -160,7 +353,7 @@ impl NoiseParams {
         cipher: CipherChoice,
         hash: HashChoice,
     ) -> Self {
-        NoiseParams { name, base, handshake, dh, cipher, hash }
+        NoiseParams { name, base, handshake, dh, sig: None, cipher, hash }
     }
 
     #[cfg(feature = "hfs")]
@@ -174,60 +367,275 @@ impl NoiseParams {
         cipher: CipherChoice,
         hash: HashChoice,
     ) -> Self {
-        NoiseParams { name, base, handshake, dh, kem, cipher, hash }
+        NoiseParams { name, base, handshake, dh, kem, sig: None, cipher, hash }
+    }
+
+    /// Whether every primitive is FIPS-approved: P-256, AES-GCM and SHA-256 or SHA-384, with no
+    /// signature algorithm or KEM.
+    ///
+    /// With the `fips` feature, names that aren't fail to parse with
+    /// `PatternProblem::NotFipsApproved`.
+    pub fn is_fips_approved(&self) -> bool {
+        #[cfg(feature = "hfs")]
+        if self.kem.is_some() {
+            return false;
+        }
+        self.base == BaseChoice::Noise
+            && self.dh == DHChoice::P256
+            && self.sig.is_none()
+            && self.cipher == CipherChoice::AESGCM
+            && matches!(self.hash, HashChoice::SHA256 | HashChoice::SHA384)
+    }
+
+    /// Whether a PSK is mixed in, so ephemeral keys are mixed into the key as well as the hash.
+    pub(crate) fn uses_psk(&self) -> bool {
+        #[cfg(feature = "legacy-psk")]
+        if self.base == BaseChoice::NoisePSK {
+            return true;
+        }
+        self.handshake.is_psk()
+    }
+
+    /// Whether the protocol takes a PSK at `location`: one of the pattern's `pskN` modifiers, or
+    /// location 0 for the legacy `NoisePSK` base, which has no modifiers.
+    pub(crate) fn takes_psk(&self, tokens: &HandshakeTokens, location: u8) -> bool {
+        let legacy_psk = self.uses_psk() && !self.handshake.is_psk();
+        tokens.uses_psk(location) || (legacy_psk && location == 0)
     }
 }
 
 impl FromStr for NoiseParams {
     type Err = Error;
 
-    #[cfg(not(feature = "hfs"))]
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() > MAXPROTOCOLNAMELEN {
+            bail!(PatternProblem::ProtocolNameTooLong);
+        }
         let mut split = s.split('_');
-        Ok(NoiseParams::new(
-            s.to_owned(),
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-        ))
+        let base: BaseChoice = split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?;
+        let handshake: HandshakeChoice =
+            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?;
+
+        // The DH function may be followed by a KEM (for hfs) and/or a signature algorithm
+        // (for sig), e.g. "25519+Kyber1024" or "25519+Ed25519".
+        let mut dh_split = split.next().ok_or(PatternProblem::TooFewParameters)?.split('+');
+        let dh = dh_split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?;
+        #[cfg(feature = "hfs")]
+        let mut kem = None;
+        let mut sig = None;
+        for extra in dh_split {
+            match extra.parse::<SigChoice>() {
+                Ok(choice) if sig.is_none() => sig = Some(choice),
+                #[cfg(feature = "hfs")]
+                Err(_) if kem.is_none() => kem = Some(extra.parse()?),
+                Ok(_) => bail!(PatternProblem::TooFewParameters),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let (cipher, hash) = match base {
+            // Disco names a single Strobe version in place of the cipher and hash functions.
+            #[cfg(feature = "disco")]
+            BaseChoice::NoiseDisco => {
+                match split.next().ok_or(PatternProblem::TooFewParameters)? {
+                    crate::strobe::STROBE_VERSION => (CipherChoice::Strobe, HashChoice::Strobe),
+                    _ => bail!(PatternProblem::UnsupportedCipherType),
+                }
+            },
+            // The legacy PSK mode mixes its PSK in at the start instead of with modifiers.
+            #[cfg(feature = "legacy-psk")]
+            BaseChoice::NoisePSK if handshake.is_psk() => {
+                bail!(PatternProblem::UnsupportedModifier)
+            },
+            _ => (
+                split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
+                split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
+            ),
+        };
+
+        // Validate that a signature algorithm is specified iff the sig modifier is present
+        if handshake.is_sig() != sig.is_some() {
+            bail!(PatternProblem::TooFewParameters);
+        }
+
+        #[cfg(not(feature = "hfs"))]
+        let mut p = NoiseParams::new(s.to_owned(), base, handshake, dh, cipher, hash);
+        #[cfg(feature = "hfs")]
+        let mut p = {
+            // Validate that a KEM is specified iff the hfs modifier is present
+            if handshake.is_hfs() != kem.is_some() {
+                bail!(PatternProblem::TooFewParameters);
+            }
+            NoiseParams::new(s.to_owned(), base, handshake, dh, kem, cipher, hash)
+        };
+        p.sig = sig;
+        #[cfg(feature = "fips")]
+        if !p.is_fips_approved() {
+            bail!(PatternProblem::NotFipsApproved);
+        }
+        Ok(p)
     }
+}
 
-    #[cfg(feature = "hfs")]
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut split = s.split('_').peekable();
-        let p = NoiseParams::new(
-            s.to_owned(),
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-            split
-                .peek()
-                .ok_or(PatternProblem::TooFewParameters)?
-                .splitn(2, '+')
-                .nth(0)
-                .ok_or(PatternProblem::TooFewParameters)?
-                .parse()?,
-            split
-                .next()
-                .ok_or(PatternProblem::TooFewParameters)?
-                .splitn(2, '+')
-                .nth(1)
-                .map(|s| s.parse())
-                .transpose()?,
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
-            split.next().ok_or(PatternProblem::TooFewParameters)?.parse()?,
+impl fmt::Display for NoiseParams {
+    /// Writes the canonical protocol name for these parameters, which may differ from `name`
+    /// only in the order of the algorithms following the DH function.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}_{}", self.base, self.handshake, self.dh)?;
+        #[cfg(feature = "hfs")]
+        if let Some(kem) = self.kem {
+            write!(f, "+{}", kem)?;
+        }
+        if let Some(sig) = self.sig {
+            write!(f, "+{}", sig)?;
+        }
+        match self.base {
+            #[cfg(feature = "disco")]
+            BaseChoice::NoiseDisco => write!(f, "_{}", crate::strobe::STROBE_VERSION),
+            _ => write!(f, "_{}_{}", self.cipher, self.hash),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for NoiseParams {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for NoiseParams {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Constructs [`NoiseParams`] from its parts, including handshake modifiers, without writing
+/// out the protocol name.
+///
+/// Unless they're set, the DH function, cipher and hash default to `25519`, `ChaChaPoly` and
+/// `BLAKE2s`.
+///
+/// # Examples
+///
+/// ```
+/// # use snow::params::*;
+///
+/// let params = NoiseParamsBuilder::new(HandshakePattern::XX)
+///     .psk(0)
+///     .dh(DHChoice::Curve25519)
+///     .cipher(CipherChoice::AESGCM)
+///     .hash(HashChoice::SHA256)
+///     .build()
+///     .unwrap();
+/// assert_eq!(params.name, "Noise_XXpsk0_25519_AESGCM_SHA256");
+/// ```
+#[derive(Clone, Debug)]
+pub struct NoiseParamsBuilder {
+    params: NoiseParams,
+}
+
+impl NoiseParamsBuilder {
+    /// Start building parameters for the given handshake pattern.
+    pub fn new(pattern: HandshakePattern) -> Self {
+        let handshake =
+            HandshakeChoice { pattern, modifiers: HandshakeModifierList { list: vec![] } };
+        #[cfg(not(feature = "hfs"))]
+        let params = NoiseParams::new(
+            String::new(),
+            BaseChoice::Noise,
+            handshake,
+            DHChoice::Curve25519,
+            CipherChoice::ChaChaPoly,
+            HashChoice::Blake2s,
+        );
+        #[cfg(feature = "hfs")]
+        let params = NoiseParams::new(
+            String::new(),
+            BaseChoice::Noise,
+            handshake,
+            DHChoice::Curve25519,
+            None,
+            CipherChoice::ChaChaPoly,
+            HashChoice::Blake2s,
         );
+        NoiseParamsBuilder { params }
+    }
 
-        // Validate that a KEM is specified iff the hfs modifier is present
-        if p.handshake.is_hfs() != p.kem.is_some() {
-            bail!(PatternProblem::TooFewParameters);
+    /// The base protocol, `Noise` unless set. Disco's Strobe also has to be picked as the cipher
+    /// and hash.
+    pub fn base(mut self, base: BaseChoice) -> Self {
+        self.params.base = base;
+        self
+    }
+
+    /// Append a modifier to the pattern's list of modifiers.
+    pub fn modifier(mut self, modifier: HandshakeModifier) -> Self {
+        self.params.handshake.modifiers.list.push(modifier);
+        self
+    }
+
+    /// Append a `pskN` modifier.
+    pub fn psk(self, location: u8) -> Self {
+        self.modifier(HandshakeModifier::Psk(location))
+    }
+
+    /// Append the `fallback` modifier.
+    pub fn fallback(self) -> Self {
+        self.modifier(HandshakeModifier::Fallback)
+    }
+
+    /// Append the `sig` modifier, authenticating static keys with the given signature algorithm.
+    pub fn sig(mut self, sig: SigChoice) -> Self {
+        self.params.sig = Some(sig);
+        self.modifier(HandshakeModifier::Sig)
+    }
+
+    /// Append the `hfs` modifier, using the given KEM.
+    #[cfg(feature = "hfs")]
+    pub fn hfs(mut self, kem: KemChoice) -> Self {
+        self.params.kem = Some(kem);
+        self.modifier(HandshakeModifier::Hfs)
+    }
+
+    /// The DH function.
+    pub fn dh(mut self, dh: DHChoice) -> Self {
+        self.params.dh = dh;
+        self
+    }
+
+    /// The cipher function.
+    pub fn cipher(mut self, cipher: CipherChoice) -> Self {
+        self.params.cipher = cipher;
+        self
+    }
+
+    /// The hash function.
+    pub fn hash(mut self, hash: HashChoice) -> Self {
+        self.params.hash = hash;
+        self
+    }
+
+    /// Build the parameters, naming them with their canonical protocol name.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the same `Error::Pattern` as parsing the protocol name would, e.g. for Strobe
+    /// without the Disco base, or for a Disco base without Strobe.
+    pub fn build(self) -> Result<NoiseParams, Error> {
+        let params: NoiseParams = self.params.to_string().parse()?;
+        if params.cipher != self.params.cipher {
+            bail!(PatternProblem::UnsupportedCipherType);
+        } else if params.hash != self.params.hash {
+            bail!(PatternProblem::UnsupportedHashType);
         }
-        Ok(p)
+        Ok(params)
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "fips")))]
 mod tests {
     use super::*;
     use std::convert::TryFrom;
@@ -243,12 +651,116 @@ mod tests {
         assert!(p.handshake.modifiers.list.is_empty());
     }
 
+    #[cfg(feature = "pattern-deferred")]
     #[test]
     fn test_basic_deferred() {
         let p: NoiseParams = "Noise_X1X1_25519_AESGCM_SHA256".parse().unwrap();
         assert!(p.handshake.modifiers.list.is_empty());
     }
 
+    #[cfg(not(feature = "pattern-deferred"))]
+    #[test]
+    fn test_deferred_patterns_compiled_out() {
+        assert!("Noise_XK1_25519_ChaChaPoly_BLAKE2s".parse::<NoiseParams>().is_err());
+        assert!("Noise_XK_25519_ChaChaPoly_BLAKE2s".parse::<NoiseParams>().is_ok());
+    }
+
+    #[test]
+    fn test_check_protocol_name_matches_parse() {
+        let names = [
+            "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+            "Noise_IKpsk2_448_AESGCM_SHA512",
+            "Noise_XXfallback+psk0_25519_AESGCM_SHA256",
+            "Noise_XXsig+psk3_25519+Ed25519_AESGCM_SHA256",
+            "Noise_X1X1_25519_AESGCM_SHA256",
+            "Noise_XX_P256_AESGCM_SHA384",
+            "Noise_XK_secp256k1_ChaChaPoly_SHA256",
+            "Noise_XX_25519_ChaChaPoly_BLAKE2s_trailing",
+            "Noise_XX_25519_ChaChaPoly_BLAKE2",
+            "Noise_XX_25519_ChaChaPoly",
+            "Noise_XXpsk256_25519_AESGCM_SHA256",
+            "Noise_XXpsk_25519_AESGCM_SHA256",
+            "Noise_XXsig_25519_AESGCM_SHA256",
+            "Noise_XX_25519+Ed25519+Ed448_AESGCM_SHA256",
+            "Noise_XY_25519_AESGCM_SHA256",
+            "Noise_XXhfs_25519+Kyber1024_AESGCM_SHA256",
+            "Noise_XXhfs_25519+Kyber512_ChaChaPoly_BLAKE2s",
+            "Noise_XXhfs_25519+MLKEM768_ChaChaPoly_BLAKE2s",
+            "Noise_XXhfs_25519+Kyber768+Ed25519_AESGCM_SHA256",
+            "Noise_XXhfs_25519+Kyber999_AESGCM_SHA256",
+            "NoiseDisco_XX_25519_STROBEv1.0.2",
+            "NoisePSK_XX_25519_ChaChaPoly_BLAKE2s",
+            "NoisePSK_XXpsk3_25519_ChaChaPoly_BLAKE2s",
+            "Noice_XX_25519_AESGCM_SHA256",
+            "",
+        ];
+        for name in &names {
+            assert_eq!(
+                check_protocol_name(name).is_ok(),
+                name.parse::<NoiseParams>().is_ok(),
+                "disagreement on {:?}",
+                name
+            );
+        }
+
+        let p = crate::noise_params!("Noise_NNpsk0_25519_ChaChaPoly_SHA256");
+        assert_eq!(p, "Noise_NNpsk0_25519_ChaChaPoly_SHA256".parse().unwrap());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let names = [
+            "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+            "Noise_IKpsk2_448_AESGCM_SHA512",
+            "Noise_XXfallback+psk0_25519_AESGCM_SHA256",
+            "Noise_XXsig+psk3_25519+Ed25519_AESGCM_BLAKE2b",
+        ];
+        for name in &names {
+            let p: NoiseParams = name.parse().unwrap();
+            assert_eq!(&p.to_string(), name);
+        }
+    }
+
+    #[test]
+    fn test_params_builder() {
+        let p = NoiseParamsBuilder::new(HandshakePattern::XX)
+            .fallback()
+            .psk(0)
+            .sig(SigChoice::Ed25519)
+            .hash(HashChoice::SHA256)
+            .build()
+            .unwrap();
+        assert_eq!(p, "Noise_XXfallback+psk0+sig_25519+Ed25519_ChaChaPoly_SHA256".parse().unwrap());
+
+        let p = NoiseParamsBuilder::new(HandshakePattern::NK).build().unwrap();
+        assert_eq!(p.name, "Noise_NK_25519_ChaChaPoly_BLAKE2s");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_params_serde() {
+        let p: NoiseParams = "Noise_XXpsk3_25519_AESGCM_SHA256".parse().unwrap();
+        let json = serde_json::to_string(&p).unwrap();
+        assert_eq!(json, "\"Noise_XXpsk3_25519_AESGCM_SHA256\"");
+        assert_eq!(serde_json::from_str::<NoiseParams>(&json).unwrap(), p);
+        assert!(serde_json::from_str::<NoiseParams>("\"Noise_XX_25519\"").is_err());
+    }
+
+    #[test]
+    fn test_protocol_name_too_long() {
+        let name = format!("Noise_XX_25519_AESGCM_SHA256_{}", "x".repeat(226));
+        assert_eq!(name.len(), 255);
+        assert!(name.parse::<NoiseParams>().is_ok());
+        assert!(check_protocol_name(&name).is_ok());
+
+        let name = name + "x";
+        assert!(matches!(
+            name.parse::<NoiseParams>(),
+            Err(Error::Pattern(PatternProblem::ProtocolNameTooLong))
+        ));
+        assert!(matches!(check_protocol_name(&name), Err(PatternProblem::ProtocolNameTooLong)));
+    }
+
     #[test]
     fn test_fallback_mod() {
         let p: NoiseParams = "Noise_XXfallback_25519_AESGCM_SHA256".parse().unwrap();
@@ -282,6 +794,64 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "disco")]
+    fn test_disco() {
+        let p: NoiseParams = "NoiseDisco_XX_25519_STROBEv1.0.2".parse().unwrap();
+        assert_eq!(p.base, BaseChoice::NoiseDisco);
+        assert_eq!(p.cipher, CipherChoice::Strobe);
+        assert_eq!(p.hash, HashChoice::Strobe);
+
+        assert!("NoiseDisco_XX_25519_ChaChaPoly_SHA256".parse::<NoiseParams>().is_err());
+        assert!("Noise_XX_25519_STROBEv1.0.2".parse::<NoiseParams>().is_err());
+
+        assert_eq!(p.to_string(), "NoiseDisco_XX_25519_STROBEv1.0.2");
+        let builder = NoiseParamsBuilder::new(HandshakePattern::XX).base(BaseChoice::NoiseDisco);
+        assert!(builder.clone().build().is_err());
+        let built = builder.cipher(CipherChoice::Strobe).hash(HashChoice::Strobe).build().unwrap();
+        assert_eq!(built, p);
+    }
+
+    #[test]
+    #[cfg(feature = "legacy-psk")]
+    fn test_legacy_psk() {
+        let p: NoiseParams = "NoisePSK_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        assert_eq!(p.base, BaseChoice::NoisePSK);
+        assert!(p.uses_psk() && !p.handshake.is_psk());
+        assert_eq!(p.to_string(), "NoisePSK_XX_25519_ChaChaPoly_BLAKE2s");
+
+        assert!(matches!(
+            "NoisePSK_XXpsk3_25519_ChaChaPoly_BLAKE2s".parse::<NoiseParams>(),
+            Err(Error::Pattern(PatternProblem::UnsupportedModifier))
+        ));
+    }
+
+    #[test]
+    fn test_sig_mod() {
+        let p: NoiseParams = "Noise_XXsig+psk3_25519+Ed25519_AESGCM_SHA256".parse().unwrap();
+        assert!(p.handshake.is_sig() && p.handshake.is_psk());
+        assert_eq!(p.sig, Some(SigChoice::Ed25519));
+
+        assert!("Noise_XXsig_25519_AESGCM_SHA256".parse::<NoiseParams>().is_err());
+        assert!("Noise_XX_25519+Ed25519_AESGCM_SHA256".parse::<NoiseParams>().is_err());
+        assert!("Noise_XXsig_25519+Ed25519+Ed448_AESGCM_SHA256".parse::<NoiseParams>().is_err());
+        assert!("Noise_XXsig_25519+Ed448_AESGCM_SHA256".parse::<NoiseParams>().is_err());
+    }
+
+    #[test]
+    fn test_sig_handshake_tokens() {
+        let p: NoiseParams = "Noise_XXsig_25519+Ed25519_AESGCM_SHA256".parse().unwrap();
+        let tokens = HandshakeTokens::try_from(&p.handshake).unwrap();
+        assert_eq!(
+            tokens.msg_patterns[1],
+            vec![Token::E, Token::Dh(DhToken::Ee), Token::S, Token::Sig]
+        );
+        assert_eq!(tokens.msg_patterns[2], vec![Token::S, Token::Sig]);
+
+        let p: NoiseParams = "Noise_IKsig_25519+Ed25519_AESGCM_SHA256".parse().unwrap();
+        assert!(HandshakeTokens::try_from(&p.handshake).is_err());
+    }
+
     #[test]
     fn test_modified_psk_handshake() {
         let p: NoiseParams = "Noise_XXpsk0_25519_AESGCM_SHA256".parse().unwrap();
@@ -309,4 +879,68 @@ mod tests {
             _ => panic!("missing token!"),
         }
     }
+
+    #[test]
+    fn test_static_key_requirements() {
+        for pattern in SUPPORTED_HANDSHAKE_PATTERNS {
+            let choice = HandshakeChoice {
+                pattern:   *pattern,
+                modifiers: HandshakeModifierList { list: vec![] },
+            };
+            let tokens = HandshakeTokens::try_from(&choice).unwrap();
+            for &initiator in &[true, false] {
+                assert_eq!(
+                    tokens.needs_local_static(initiator),
+                    pattern.needs_local_static_key(initiator)
+                );
+                assert_eq!(
+                    tokens.needs_remote_static(initiator),
+                    pattern.need_known_remote_pubkey(initiator)
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "hfs")]
+    fn test_hfs_psk_handshake_tokens() {
+        use self::DhToken::*;
+
+        let p: NoiseParams = "Noise_XXhfs+psk2_25519+Kyber768_ChaChaPoly_SHA256".parse().unwrap();
+        assert!(p.handshake.is_hfs() && p.handshake.is_psk());
+        assert_eq!(p.kem, Some(KemChoice::Kyber768));
+        let tokens = HandshakeTokens::try_from(&p.handshake).unwrap();
+        assert_eq!(tokens.msg_patterns[0], vec![Token::E, Token::E1]);
+        assert_eq!(
+            tokens.msg_patterns[1],
+            vec![Token::E, Token::Dh(Ee), Token::Ekem1, Token::S, Token::Dh(Es), Token::Psk(2)]
+        );
+
+        // The KEM tokens land in the same places whichever order the modifiers are named in.
+        let hfs_first: NoiseParams =
+            "Noise_IKhfs+psk1_25519+Kyber768_ChaChaPoly_SHA256".parse().unwrap();
+        let psk_first: NoiseParams =
+            "Noise_IKpsk1+hfs_25519+Kyber768_ChaChaPoly_SHA256".parse().unwrap();
+        let tokens = HandshakeTokens::try_from(&hfs_first.handshake).unwrap();
+        assert_eq!(
+            tokens.msg_patterns,
+            HandshakeTokens::try_from(&psk_first.handshake).unwrap().msg_patterns
+        );
+        assert_eq!(
+            tokens.msg_patterns[0],
+            vec![Token::E, Token::Dh(Es), Token::E1, Token::S, Token::Dh(Ss), Token::Psk(1)]
+        );
+
+        let p: NoiseParams = "Noise_NNpsk0+hfs_25519+Kyber768_ChaChaPoly_SHA256".parse().unwrap();
+        let tokens = HandshakeTokens::try_from(&p.handshake).unwrap();
+        assert_eq!(tokens.msg_patterns[0], vec![Token::Psk(0), Token::E, Token::E1]);
+
+        for name in &[
+            "Noise_XXhfs+hfs_25519+Kyber768_ChaChaPoly_SHA256",
+            "Noise_XXsig+sig_25519+Ed25519_ChaChaPoly_SHA256",
+        ] {
+            assert!(name.parse::<NoiseParams>().is_err(), "{}", name);
+            assert!(check_protocol_name(name).is_err(), "{}", name);
+        }
+    }
 }
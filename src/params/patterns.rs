@@ -1,5 +1,5 @@
 use crate::error::{Error, PatternProblem};
-use std::{convert::TryFrom, str::FromStr};
+use std::{convert::TryFrom, fmt, str::FromStr};
 
 /// A small helper macro that behaves similar to the `vec![]` standard macro,
 /// except it allocates a bit extra to avoid resizing.
@@ -25,16 +25,21 @@ macro_rules! pattern_enum {
     // NOTE: see https://danielkeep.github.io/tlborm/book/mbe-macro-rules.html and
     // https://doc.rust-lang.org/rust-by-example/macros.html for a great overview
     // of `macro_rules!`.
+    //
+    // The optional second group of variants only exists when its attribute (a `cfg`) allows.
     ($name:ident {
         $($variant:ident),* $(,)*
-    }) => {
+    } $(#[$gate:meta] {
+        $($gated:ident),* $(,)*
+    })?) => {
         /// One of the patterns as defined in the
         /// [Handshake Pattern](http://noiseprotocol.org/noise.html#handshake-patterns)
         /// section.
         #[allow(missing_docs)]
         #[derive(Copy, Clone, PartialEq, Debug)]
         pub enum $name {
-            $($variant),*,
+            $($variant,)*
+            $($(#[$gate] $gated,)*)?
         }
 
         impl FromStr for $name {
@@ -43,9 +48,12 @@ macro_rules! pattern_enum {
                 use self::$name::*;
                 match s {
                     $(
-                        stringify!($variant) => Ok($variant)
-                    ),
-                    *,
+                        stringify!($variant) => Ok($variant),
+                    )*
+                    $($(
+                        #[$gate]
+                        stringify!($gated) => Ok($gated),
+                    )*)?
                     _    => bail!(PatternProblem::UnsupportedHandshakeType)
                 }
             }
@@ -53,57 +61,97 @@ macro_rules! pattern_enum {
 
         impl $name {
             /// The equivalent of the `ToString` trait, but for `&'static str`.
-            pub fn as_str(self) -> &'static str {
+            pub const fn as_str(self) -> &'static str {
                 use self::$name::*;
                 match self {
                     $(
-                        $variant => stringify!($variant)
-                    ),
-                    *
+                        $variant => stringify!($variant),
+                    )*
+                    $($(
+                        #[$gate]
+                        $gated => stringify!($gated),
+                    )*)?
                 }
             }
         }
 
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
         #[doc(hidden)]
-        pub const SUPPORTED_HANDSHAKE_PATTERNS: &'static [$name] = &[$($name::$variant),*];
+        pub const SUPPORTED_HANDSHAKE_PATTERNS: &'static [$name] = &[
+            $($name::$variant,)*
+            $($(#[$gate] $name::$gated,)*)?
+        ];
     }
 }
 
 /// The tokens which describe patterns involving DH calculations.
 ///
 /// See: http://noiseprotocol.org/noise.html#handshake-patterns
-#[allow(missing_docs)]
-#[derive(Copy, Clone, PartialEq, Debug)]
-pub(crate) enum DhToken {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DhToken {
+    /// DH between the initiator's and responder's ephemeral keys.
     Ee,
+    /// DH between the initiator's ephemeral key and the responder's static key.
     Es,
+    /// DH between the initiator's static key and the responder's ephemeral key.
     Se,
+    /// DH between the initiator's and responder's static keys.
     Ss,
 }
 
 /// The tokens which describe message patterns.
 ///
 /// See: http://noiseprotocol.org/noise.html#handshake-patterns
-#[allow(missing_docs)]
-#[derive(Copy, Clone, PartialEq, Debug)]
-pub(crate) enum Token {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Token {
+    /// The sender's ephemeral public key.
     E,
+    /// The sender's static public key, encrypted once there's a key to encrypt it with.
     S,
+    /// A DH calculation mixed into the chaining key.
     Dh(DhToken),
+    /// The PSK at the given location, mixed into the chaining key.
     Psk(u8),
+    /// A signature over the handshake hash, for the `sig` modifier.
+    Sig,
+    /// The sender's ephemeral KEM public key, for the `hfs` modifier.
     #[cfg(feature = "hfs")]
     E1,
+    /// The KEM ciphertext encapsulated to the peer's ephemeral KEM key, for the `hfs` modifier.
     #[cfg(feature = "hfs")]
     Ekem1,
 }
 
+/// The spec's notation for the token, e.g. `ee` or `psk1`.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::E => f.write_str("e"),
+            Token::S => f.write_str("s"),
+            Token::Dh(DhToken::Ee) => f.write_str("ee"),
+            Token::Dh(DhToken::Es) => f.write_str("es"),
+            Token::Dh(DhToken::Se) => f.write_str("se"),
+            Token::Dh(DhToken::Ss) => f.write_str("ss"),
+            Token::Psk(n) => write!(f, "psk{}", n),
+            Token::Sig => f.write_str("sig"),
+            #[cfg(feature = "hfs")]
+            Token::E1 => f.write_str("e1"),
+            #[cfg(feature = "hfs")]
+            Token::Ekem1 => f.write_str("ekem1"),
+        }
+    }
+}
+
 #[cfg(feature = "hfs")]
 impl Token {
     fn is_dh(&self) -> bool {
-        match *self {
-            Dh(_) => true,
-            _ => false,
-        }
+        matches!(*self, Dh(_))
     }
 }
 
@@ -115,7 +163,8 @@ pattern_enum! {
 
         // 7.5. Interactive handshake patterns (fundamental)
         NN, NK, NX, XN, XK, XX, KN, KK, KX, IN, IK, IX,
-
+    }
+    #[cfg(feature = "pattern-deferred")] {
         // 7.6. Interactive handshake patterns (deferred)
         NK1, NX1, X1N, X1K, XK1, X1K1, X1X, XX1, X1X1, K1N, K1K, KK1, K1K1, K1X,
         KX1, K1X1, I1N, I1K, IK1, I1K1, I1X, IX1, I1X1
@@ -132,27 +181,32 @@ impl HandshakePattern {
 
     /// Whether this pattern requires a long-term static key.
     pub fn needs_local_static_key(self, initiator: bool) -> bool {
+        let (i, r) = self.static_key_letters();
         if initiator {
-            !matches!(self, N | NN | NK | NX | NK1 | NX1)
+            i != 'N'
         } else {
-            !matches!(self, NN | XN | KN | IN | X1N | K1N | I1N)
+            r != 'N'
         }
     }
 
     /// Whether this pattern demands a remote public key pre-message.
     pub fn need_known_remote_pubkey(self, initiator: bool) -> bool {
+        let (i, r) = self.static_key_letters();
         if initiator {
-            matches!(
-                self, 
-                N | K | X | NK | XK | KK | IK | NK1 | X1K | XK1 | X1K1 | K1K | KK1 | K1K1 | I1K | IK1 | I1K1
-            )
+            r == 'K'
         } else {
-            matches!(
-                self,
-                K | KN | KK | KX | K1N | K1K | KK1 | K1K1 | K1X | KX1 | K1X1
-            )
+            i == 'K'
         }
     }
+
+    /// The letters describing the initiator's and responder's static keys, e.g. `('X', 'K')`
+    /// for `X1K1`. One-way patterns only name the initiator's, since the responder's is always
+    /// known to the initiator.
+    fn static_key_letters(self) -> (char, char) {
+        let mut letters = self.as_str().chars().filter(|c| *c != '1');
+        let initiator = letters.next().unwrap_or('N');
+        (initiator, letters.next().unwrap_or('K'))
+    }
 }
 
 /// A modifier applied to the base pattern as defined in the Noise spec.
@@ -164,6 +218,9 @@ pub enum HandshakeModifier {
     /// Modify the base pattern to its "fallback" form
     Fallback,
 
+    /// Modify the base pattern to authenticate static keys with signatures instead of DH
+    Sig,
+
     #[cfg(feature = "hfs")]
     /// Modify the base pattern to use Hybrid-Forward-Secrecy
     Hfs,
@@ -174,10 +231,11 @@ impl FromStr for HandshakeModifier {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            s if s.starts_with("psk") => Ok(HandshakeModifier::Psk(
-                (&s[3..]).parse().map_err(|_| PatternProblem::InvalidPsk)?,
-            )),
+            s if s.starts_with("psk") => {
+                Ok(HandshakeModifier::Psk(s[3..].parse().map_err(|_| PatternProblem::InvalidPsk)?))
+            },
             "fallback" => Ok(HandshakeModifier::Fallback),
+            "sig" => Ok(HandshakeModifier::Sig),
             #[cfg(feature = "hfs")]
             "hfs" => Ok(HandshakeModifier::Hfs),
             _ => bail!(PatternProblem::UnsupportedModifier),
@@ -185,6 +243,30 @@ impl FromStr for HandshakeModifier {
     }
 }
 
+impl HandshakeModifier {
+    /// Whether this modifier adds tokens of its own, rather than positioning a PSK.
+    fn is_token_modifier(self) -> bool {
+        match self {
+            HandshakeModifier::Sig => true,
+            #[cfg(feature = "hfs")]
+            HandshakeModifier::Hfs => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for HandshakeModifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeModifier::Psk(n) => write!(f, "psk{}", n),
+            HandshakeModifier::Fallback => f.write_str("fallback"),
+            HandshakeModifier::Sig => f.write_str("sig"),
+            #[cfg(feature = "hfs")]
+            HandshakeModifier::Hfs => f.write_str("hfs"),
+        }
+    }
+}
+
 /// Handshake modifiers that will be used during key exchange handshake.
 #[derive(Clone, PartialEq, Debug)]
 pub struct HandshakeModifierList {
@@ -202,13 +284,30 @@ impl FromStr for HandshakeModifierList {
             let modifier_names = s.split('+');
             let mut modifiers = vec![];
             for modifier_name in modifier_names {
-                modifiers.push(modifier_name.parse()?);
+                let modifier: HandshakeModifier = modifier_name.parse()?;
+                // Applying sig or hfs twice would add their tokens twice.
+                if modifier.is_token_modifier() && modifiers.contains(&modifier) {
+                    bail!(PatternProblem::UnsupportedModifier);
+                }
+                modifiers.push(modifier);
             }
             Ok(HandshakeModifierList { list: modifiers })
         }
     }
 }
 
+impl fmt::Display for HandshakeModifierList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, modifier) in self.list.iter().enumerate() {
+            if i > 0 {
+                f.write_str("+")?;
+            }
+            write!(f, "{}", modifier)?;
+        }
+        Ok(())
+    }
+}
+
 /// The pattern/modifier combination choice (no primitives specified)
 /// for a full noise protocol definition.
 #[derive(Clone, PartialEq, Debug)]
@@ -236,6 +335,11 @@ impl HandshakeChoice {
         self.modifiers.list.contains(&HandshakeModifier::Fallback)
     }
 
+    /// Whether the handshake choice includes the sig modifier.
+    pub fn is_sig(&self) -> bool {
+        self.modifiers.list.contains(&HandshakeModifier::Sig)
+    }
+
     /// Whether the handshake choice includes the hfs modifier.
     #[cfg(feature = "hfs")]
     pub fn is_hfs(&self) -> bool {
@@ -246,7 +350,7 @@ impl HandshakeChoice {
     fn parse_pattern_and_modifier(s: &str) -> Result<(HandshakePattern, &str), Error> {
         for i in (1..=4).rev() {
             if s.len() > i - 1 && s.is_char_boundary(i) {
-                if let Ok(p) = (&s[..i]).parse() {
+                if let Ok(p) = s[..i].parse() {
                     return Ok((p, &s[i..]));
                 }
             }
@@ -267,17 +371,60 @@ impl FromStr for HandshakeChoice {
     }
 }
 
+impl fmt::Display for HandshakeChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.pattern, self.modifiers)
+    }
+}
+
 type PremessagePatterns = &'static [Token];
 pub(crate) type MessagePatterns = Vec<Vec<Token>>;
 
-/// The defined token patterns for a given handshake.
+/// The defined token patterns for a given handshake, with its modifiers applied.
+///
+/// Converting a [`HandshakeChoice`] with `TryFrom` gives the token schedule the handshake will
+/// follow, for tools that want to inspect or display it.
 ///
 /// See: http://noiseprotocol.org/noise.html#handshake-patterns
-#[derive(Debug)]
-pub(crate) struct HandshakeTokens {
-    pub premsg_pattern_i: PremessagePatterns,
-    pub premsg_pattern_r: PremessagePatterns,
-    pub msg_patterns:     MessagePatterns,
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HandshakeTokens {
+    /// The initiator's pre-message, i.e. the keys the responder knows beforehand.
+    pub premsg_pattern_i: &'static [Token],
+    /// The responder's pre-message, i.e. the keys the initiator knows beforehand.
+    pub premsg_pattern_r: &'static [Token],
+    /// The tokens of each message, starting with the initiator's first.
+    pub msg_patterns:     Vec<Vec<Token>>,
+}
+
+impl HandshakeTokens {
+    /// Whether the given side needs its own static key, i.e. it either sends one or is
+    /// pre-shared with the peer.
+    pub fn needs_local_static(&self, initiator: bool) -> bool {
+        let (premsg, first) =
+            if initiator { (self.premsg_pattern_i, 0) } else { (self.premsg_pattern_r, 1) };
+        premsg.contains(&Token::S)
+            || self.msg_patterns.iter().skip(first).step_by(2).any(|m| m.contains(&Token::S))
+    }
+
+    /// Whether the responder needs its static key before it has read the first message, as
+    /// opposed to only once it sends it.
+    pub(crate) fn needs_responder_static_upfront(&self) -> bool {
+        self.premsg_pattern_r.contains(&Token::S)
+            || self.msg_patterns.first().is_some_and(|first| {
+                first.iter().any(|t| matches!(t, Token::Dh(DhToken::Es) | Token::Dh(DhToken::Ss)))
+            })
+    }
+
+    /// Whether a `pskN` token for the given location appears in the message patterns.
+    pub fn uses_psk(&self, location: u8) -> bool {
+        self.msg_patterns.iter().flatten().any(|t| *t == Token::Psk(location))
+    }
+
+    /// Whether the given side must know the peer's static key before the handshake.
+    pub fn needs_remote_static(&self, initiator: bool) -> bool {
+        let premsg = if initiator { self.premsg_pattern_r } else { self.premsg_pattern_i };
+        premsg.contains(&Token::S)
+    }
 }
 
 use self::{DhToken::*, HandshakePattern::*, Token::*};
@@ -369,126 +516,14 @@ impl<'a> TryFrom<&'a HandshakeChoice> for HandshakeTokens {
                 static_slice![Token: ],
                 message_vec![&[E, S], &[E, Dh(Ee), Dh(Se), S, Dh(Es)]],
             ),
-            NK1 => (
-                static_slice![Token: ],
-                static_slice![Token: S],
-                message_vec![&[E], &[E, Dh(Ee), Dh(Es)]],
-            ),
-            NX1 => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Es)]]
-            ),
-            X1N => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee)], &[S], &[Dh(Se)]]
-            ),
-            X1K => (
-                static_slice![Token: ],
-                static_slice![Token: S],
-                message_vec![&[E, Dh(Es)], &[E, Dh(Ee)], &[S], &[Dh(Se)]]
-            ),
-            XK1 => (
-                static_slice![Token: ],
-                static_slice![Token: S],
-                message_vec![&[E], &[E, Dh(Ee), Dh(Es)], &[S, Dh(Se)]]
-            ),
-            X1K1 => (
-                static_slice![Token: ],
-                static_slice![Token: S],
-                message_vec![&[E], &[E, Dh(Ee), Dh(Es)], &[S], &[Dh(Se)]]
-            ),
-            X1X => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee), S, Dh(Es)], &[S], &[Dh(Se)]],
-            ),
-            XX1 => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Es), S, Dh(Se)]],
-            ),
-            X1X1 => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Es), S], &[Dh(Se)]],
-            ),
-            K1N => (
-                static_slice![Token: S],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee)], &[Dh(Se)]],
-            ),
-            K1K => (
-                static_slice![Token: S],
-                static_slice![Token: S],
-                message_vec![&[E, Dh(Es)], &[E, Dh(Ee)], &[Dh(Se)]],
-            ),
-            KK1 => (
-                static_slice![Token: S],
-                static_slice![Token: S],
-                message_vec![&[E], &[E, Dh(Ee), Dh(Se), Dh(Es)]],
-            ),
-            K1K1 => (
-                static_slice![Token: S],
-                static_slice![Token: S],
-                message_vec![&[E], &[E, Dh(Ee), Dh(Es)], &[Dh(Se)]],
-            ),
-            K1X => (
-                static_slice![Token: S],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee), S, Dh(Es)], &[Dh(Se)]],
-            ),
-            KX1 => (
-                static_slice![Token: S],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee), Dh(Se), S], &[Dh(Es)]],
-            ),
-            K1X1 => (
-                static_slice![Token: S],
-                static_slice![Token: ],
-                message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Se), Dh(Es)]],
-            ),
-            I1N => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E, S], &[E, Dh(Ee)], &[Dh(Se)]],
-            ),
-            I1K => (
-                static_slice![Token: ],
-                static_slice![Token: S],
-                message_vec![&[E, Dh(Es), S], &[E, Dh(Ee)], &[Dh(Se)]],
-            ),
-            IK1 => (
-                static_slice![Token: ],
-                static_slice![Token: S],
-                message_vec![&[E, S], &[E, Dh(Ee), Dh(Se), Dh(Es)]],
-            ),
-            I1K1 => (
-                static_slice![Token: ],
-                static_slice![Token: S],
-                message_vec![&[E, S], &[E, Dh(Ee), Dh(Es)], &[Dh(Se)]],
-            ),
-            I1X => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E, S], &[E, Dh(Ee), S, Dh(Es)], &[Dh(Se)]],
-            ),
-            IX1 => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E, S], &[E, Dh(Ee), Dh(Se), S], &[Dh(Es)]],
-            ),
-            I1X1 => (
-                static_slice![Token: ],
-                static_slice![Token: ],
-                message_vec![&[E, S], &[E, Dh(Ee), S], &[Dh(Se), Dh(Es)]],
-            ),
+            #[cfg(feature = "pattern-deferred")]
+            deferred => deferred_patterns(deferred),
         };
 
         for modifier in handshake.modifiers.list.iter() {
             match modifier {
                 HandshakeModifier::Psk(n) => apply_psk_modifier(&mut patterns, *n),
+                HandshakeModifier::Sig => apply_sig_modifier(&mut patterns)?,
                 #[cfg(feature = "hfs")]
                 HandshakeModifier::Hfs => apply_hfs_modifier(&mut patterns),
                 _ => bail!(PatternProblem::UnsupportedModifier),
@@ -503,6 +538,130 @@ impl<'a> TryFrom<&'a HandshakeChoice> for HandshakeTokens {
     }
 }
 
+/// The tokens of the deferred patterns from section 7.6 of the spec.
+#[cfg(feature = "pattern-deferred")]
+#[rustfmt::skip]
+fn deferred_patterns(pattern: HandshakePattern) -> Patterns {
+    match pattern {
+        NK1 => (
+            static_slice![Token: ],
+            static_slice![Token: S],
+            message_vec![&[E], &[E, Dh(Ee), Dh(Es)]],
+        ),
+        NX1 => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Es)]]
+        ),
+        X1N => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee)], &[S], &[Dh(Se)]]
+        ),
+        X1K => (
+            static_slice![Token: ],
+            static_slice![Token: S],
+            message_vec![&[E, Dh(Es)], &[E, Dh(Ee)], &[S], &[Dh(Se)]]
+        ),
+        XK1 => (
+            static_slice![Token: ],
+            static_slice![Token: S],
+            message_vec![&[E], &[E, Dh(Ee), Dh(Es)], &[S, Dh(Se)]]
+        ),
+        X1K1 => (
+            static_slice![Token: ],
+            static_slice![Token: S],
+            message_vec![&[E], &[E, Dh(Ee), Dh(Es)], &[S], &[Dh(Se)]]
+        ),
+        X1X => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee), S, Dh(Es)], &[S], &[Dh(Se)]],
+        ),
+        XX1 => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Es), S, Dh(Se)]],
+        ),
+        X1X1 => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Es), S], &[Dh(Se)]],
+        ),
+        K1N => (
+            static_slice![Token: S],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee)], &[Dh(Se)]],
+        ),
+        K1K => (
+            static_slice![Token: S],
+            static_slice![Token: S],
+            message_vec![&[E, Dh(Es)], &[E, Dh(Ee)], &[Dh(Se)]],
+        ),
+        KK1 => (
+            static_slice![Token: S],
+            static_slice![Token: S],
+            message_vec![&[E], &[E, Dh(Ee), Dh(Se), Dh(Es)]],
+        ),
+        K1K1 => (
+            static_slice![Token: S],
+            static_slice![Token: S],
+            message_vec![&[E], &[E, Dh(Ee), Dh(Es)], &[Dh(Se)]],
+        ),
+        K1X => (
+            static_slice![Token: S],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee), S, Dh(Es)], &[Dh(Se)]],
+        ),
+        KX1 => (
+            static_slice![Token: S],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee), Dh(Se), S], &[Dh(Es)]],
+        ),
+        K1X1 => (
+            static_slice![Token: S],
+            static_slice![Token: ],
+            message_vec![&[E], &[E, Dh(Ee), S], &[Dh(Se), Dh(Es)]],
+        ),
+        I1N => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E, S], &[E, Dh(Ee)], &[Dh(Se)]],
+        ),
+        I1K => (
+            static_slice![Token: ],
+            static_slice![Token: S],
+            message_vec![&[E, Dh(Es), S], &[E, Dh(Ee)], &[Dh(Se)]],
+        ),
+        IK1 => (
+            static_slice![Token: ],
+            static_slice![Token: S],
+            message_vec![&[E, S], &[E, Dh(Ee), Dh(Se), Dh(Es)]],
+        ),
+        I1K1 => (
+            static_slice![Token: ],
+            static_slice![Token: S],
+            message_vec![&[E, S], &[E, Dh(Ee), Dh(Es)], &[Dh(Se)]],
+        ),
+        I1X => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E, S], &[E, Dh(Ee), S, Dh(Es)], &[Dh(Se)]],
+        ),
+        IX1 => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E, S], &[E, Dh(Ee), Dh(Se), S], &[Dh(Es)]],
+        ),
+        I1X1 => (
+            static_slice![Token: ],
+            static_slice![Token: ],
+            message_vec![&[E, S], &[E, Dh(Ee), S], &[Dh(Se), Dh(Es)]],
+        ),
+        _ => unreachable!("{} isn't a deferred pattern", pattern.as_str()),
+    }
+}
+
 #[cfg(feature = "hfs")]
 /// Check that this handshake is not HFS *and* one-way.
 ///
@@ -533,6 +692,25 @@ fn apply_psk_modifier(patterns: &mut Patterns, n: u8) {
     }
 }
 
+fn apply_sig_modifier(patterns: &mut Patterns) -> Result<(), Error> {
+    // Each side authenticates its static key by signing the handshake hash, in place of the
+    // DH between its static key and the other side's ephemeral ("se" for the initiator, "es"
+    // for the responder). Patterns that need any other DH with a static key can't be expressed
+    // with signatures.
+    for (i, msg) in patterns.2.iter_mut().enumerate() {
+        let own_static_dh = if i % 2 == 0 { Token::Dh(Se) } else { Token::Dh(Es) };
+        for token in msg.iter_mut() {
+            if *token == own_static_dh {
+                *token = Token::Sig;
+            }
+        }
+        if msg.iter().any(|token| matches!(token, Token::Dh(Es) | Token::Dh(Se) | Token::Dh(Ss))) {
+            bail!(PatternProblem::UnsupportedModifier);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(feature = "hfs")]
 fn apply_hfs_modifier(patterns: &mut Patterns) {
     // From the HFS spec, Section 5:
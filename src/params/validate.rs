@@ -0,0 +1,264 @@
+//! Compile-time validation of protocol names, mirroring the `FromStr` implementations of the
+//! parameter types so that a name accepted here is also accepted by `str::parse`.
+
+use super::SUPPORTED_HANDSHAKE_PATTERNS;
+use crate::{constants::MAXPROTOCOLNAMELEN, error::PatternProblem};
+
+/// Parse a Noise protocol name into a [`NoiseParams`](crate::params::NoiseParams), validating
+/// it at compile time.
+///
+/// A typo in the name (or a primitive whose feature is disabled) fails the build instead of
+/// returning an error at runtime. The `NoiseParams` itself still owns its name, so the
+/// expression builds it when evaluated, which can never fail.
+///
+/// # Examples
+///
+/// ```
+/// let params = snow::noise_params!("Noise_XX_25519_ChaChaPoly_BLAKE2s");
+/// assert_eq!(params.name, "Noise_XX_25519_ChaChaPoly_BLAKE2s");
+/// ```
+///
+/// ```compile_fail
+/// let params = snow::noise_params!("Noise_XX_25519_ChaChaPoly_BLAKE2");
+/// ```
+#[macro_export]
+macro_rules! noise_params {
+    ($name:expr) => {{
+        const NAME: &str = $name;
+        const _: () = match $crate::params::check_protocol_name(NAME) {
+            Ok(()) => (),
+            Err(problem) => panic!("{}", problem.as_str()),
+        };
+        match <$crate::params::NoiseParams as ::core::str::FromStr>::from_str(NAME) {
+            Ok(params) => params,
+            Err(_) => unreachable!("protocol name was validated at compile time"),
+        }
+    }};
+}
+
+/// Check a protocol name in a `const` context, reporting the same problem `str::parse` would.
+///
+/// This is what backs [`noise_params!`](crate::noise_params); it only checks the name itself, so
+/// a pattern that can't be combined with its modifiers is still caught by the [`Builder`].
+///
+/// [`Builder`]: crate::Builder
+pub const fn check_protocol_name(name: &str) -> Result<(), PatternProblem> {
+    if name.len() > MAXPROTOCOLNAMELEN {
+        return Err(PatternProblem::ProtocolNameTooLong);
+    }
+    let rest = Some(name.as_bytes());
+    let (base, rest) = match next_field(rest, b'_') {
+        Some(split) => split,
+        None => return Err(PatternProblem::TooFewParameters),
+    };
+    let legacy_psk = cfg!(feature = "legacy-psk") && eq(base, b"NoisePSK");
+    let disco = if eq(base, b"Noise") || legacy_psk {
+        false
+    } else if cfg!(feature = "disco") && eq(base, b"NoiseDisco") {
+        true
+    } else {
+        return Err(PatternProblem::UnsupportedBaseType);
+    };
+
+    let (handshake, rest) = match next_field(rest, b'_') {
+        Some(split) => split,
+        None => return Err(PatternProblem::TooFewParameters),
+    };
+    let (is_psk, is_sig, is_hfs) = match check_handshake(handshake) {
+        Ok(modifiers) => modifiers,
+        Err(problem) => return Err(problem),
+    };
+    if legacy_psk && is_psk {
+        return Err(PatternProblem::UnsupportedModifier);
+    }
+
+    let (dh, rest) = match next_field(rest, b'_') {
+        Some(split) => split,
+        None => return Err(PatternProblem::TooFewParameters),
+    };
+    let (has_sig, has_kem) = match check_dh(dh) {
+        Ok(extras) => extras,
+        Err(problem) => return Err(problem),
+    };
+
+    let mut fips_approved = !disco && !legacy_psk && !has_sig && !has_kem && eq(dh, b"P256");
+    if disco {
+        #[cfg(feature = "disco")]
+        match next_field(rest, b'_') {
+            Some((version, _)) if eq(version, crate::strobe::STROBE_VERSION.as_bytes()) => {},
+            Some(_) => return Err(PatternProblem::UnsupportedCipherType),
+            None => return Err(PatternProblem::TooFewParameters),
+        }
+    } else {
+        let (cipher, rest) = match next_field(rest, b'_') {
+            Some(split) => split,
+            None => return Err(PatternProblem::TooFewParameters),
+        };
+        if !(eq(cipher, b"ChaChaPoly")
+            || eq(cipher, b"AESGCM")
+            || (cfg!(feature = "xchachapoly") && eq(cipher, b"XChaChaPoly"))
+            || (cfg!(feature = "cipher-aegis")
+                && (eq(cipher, b"AEGIS128L") || eq(cipher, b"AEGIS256")))
+            || (cfg!(feature = "cipher-ascon") && eq(cipher, b"Ascon128")))
+        {
+            return Err(PatternProblem::UnsupportedCipherType);
+        }
+        let hash = match next_field(rest, b'_') {
+            Some((hash, _)) => hash,
+            None => return Err(PatternProblem::TooFewParameters),
+        };
+        if !(eq(hash, b"SHA256")
+            || eq(hash, b"SHA384")
+            || eq(hash, b"SHA512")
+            || eq(hash, b"BLAKE2s")
+            || eq(hash, b"BLAKE2b")
+            || (cfg!(feature = "hash-ascon") && eq(hash, b"AsconHash")))
+        {
+            return Err(PatternProblem::UnsupportedHashType);
+        }
+        fips_approved &= eq(cipher, b"AESGCM") && (eq(hash, b"SHA256") || eq(hash, b"SHA384"));
+    }
+
+    if is_sig != has_sig || is_hfs != has_kem {
+        return Err(PatternProblem::TooFewParameters);
+    }
+    if cfg!(feature = "fips") && !fips_approved {
+        return Err(PatternProblem::NotFipsApproved);
+    }
+    Ok(())
+}
+
+/// Checks the pattern and its modifiers, returning whether any psk modifiers and the sig and hfs
+/// modifiers are present.
+const fn check_handshake(s: &[u8]) -> Result<(bool, bool, bool), PatternProblem> {
+    let mut i = if s.len() < 4 { s.len() } else { 4 };
+    let modifiers = loop {
+        if i == 0 {
+            return Err(PatternProblem::UnsupportedHandshakeType);
+        }
+        let (pattern, modifiers) = s.split_at(i);
+        if is_supported_pattern(pattern) {
+            break modifiers;
+        }
+        i -= 1;
+    };
+
+    let (mut is_psk, mut is_sig, mut is_hfs) = (false, false, false);
+    let mut rest = if modifiers.is_empty() { None } else { Some(modifiers) };
+    while let Some((modifier, next)) = next_field(rest, b'+') {
+        if modifier.len() >= 3 && eq(modifier.split_at(3).0, b"psk") {
+            if !is_u8(modifier.split_at(3).1) {
+                return Err(PatternProblem::InvalidPsk);
+            }
+            is_psk = true;
+        } else if eq(modifier, b"sig") && !is_sig {
+            is_sig = true;
+        } else if cfg!(feature = "hfs") && eq(modifier, b"hfs") && !is_hfs {
+            is_hfs = true;
+        } else if !eq(modifier, b"fallback") {
+            return Err(PatternProblem::UnsupportedModifier);
+        }
+        rest = next;
+    }
+    Ok((is_psk, is_sig, is_hfs))
+}
+
+/// Checks the DH function and any trailing `+` algorithms, returning whether a signature
+/// algorithm and a KEM were named.
+const fn check_dh(s: &[u8]) -> Result<(bool, bool), PatternProblem> {
+    let (dh, mut rest) = match next_field(Some(s), b'+') {
+        Some(split) => split,
+        None => return Err(PatternProblem::TooFewParameters),
+    };
+    if !(eq(dh, b"25519") || eq(dh, b"448") || eq(dh, b"P256") || eq(dh, b"secp256k1")) {
+        return Err(PatternProblem::UnsupportedDhType);
+    }
+
+    let (mut sig, mut kem) = (false, false);
+    while let Some((extra, next)) = next_field(rest, b'+') {
+        if eq(extra, b"Ed25519") {
+            if sig {
+                return Err(PatternProblem::TooFewParameters);
+            }
+            sig = true;
+        } else if cfg!(feature = "hfs") && !kem {
+            if !(eq(extra, b"MLKEM512")
+                || eq(extra, b"MLKEM768")
+                || eq(extra, b"MLKEM1024")
+                || eq(extra, b"Kyber512")
+                || eq(extra, b"Kyber768")
+                || eq(extra, b"Kyber1024"))
+            {
+                #[cfg(feature = "hfs")]
+                return Err(PatternProblem::UnsupportedKemType);
+            }
+            kem = true;
+        } else {
+            return Err(PatternProblem::UnsupportedSigType);
+        }
+        rest = next;
+    }
+    Ok((sig, kem))
+}
+
+const fn is_supported_pattern(s: &[u8]) -> bool {
+    let mut i = 0;
+    while i < SUPPORTED_HANDSHAKE_PATTERNS.len() {
+        if eq(s, SUPPORTED_HANDSHAKE_PATTERNS[i].as_str().as_bytes()) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Whether `s` is what `str::parse::<u8>` accepts: an optional `+` and a decimal up to 255.
+const fn is_u8(s: &[u8]) -> bool {
+    let mut i = if !s.is_empty() && s[0] == b'+' { 1 } else { 0 };
+    if i == s.len() {
+        return false;
+    }
+    let mut value = 0u32;
+    while i < s.len() {
+        if !s[i].is_ascii_digit() {
+            return false;
+        }
+        value = value * 10 + (s[i] - b'0') as u32;
+        if value > u8::MAX as u32 {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Splits the next field off `rest` like `str::split` does, yielding `None` once it's exhausted.
+const fn next_field(rest: Option<&[u8]>, sep: u8) -> Option<(&[u8], Option<&[u8]>)> {
+    let s = match rest {
+        Some(s) => s,
+        None => return None,
+    };
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == sep {
+            let (field, tail) = s.split_at(i);
+            return Some((field, Some(tail.split_at(1).1)));
+        }
+        i += 1;
+    }
+    Some((s, None))
+}
+
+const fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
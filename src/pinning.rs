@@ -0,0 +1,240 @@
+//! Pinning remote static keys to peer identities, like SSH's `known_hosts`.
+//!
+//! A [`PinStore`] remembers which remote static key belongs to which peer, under whatever name
+//! the application uses for it (a host name, an account, ...). Handing one to
+//! [`Builder::pinning()`] makes the handshake check the key the peer sends against the pinned
+//! one, as soon as it's read:
+//!
+//! - With [`PinPolicy::TrustOnFirstUse`], a peer without a pinned key is accepted, and the key it
+//!   used gets pinned once the handshake completes. A later handshake with a different key fails.
+//! - With [`PinPolicy::Strict`], only peers whose key has already been pinned are accepted.
+//!
+//! Either way, a key that doesn't match the pinned one fails the handshake at its `s` token, with
+//! `StateProblem::PinMismatch` as the source of the `Error::Handshake`. A legitimately rotated key has to be re-pinned by the
+//! application, e.g. after the user confirmed its [fingerprint](crate::fingerprint).
+//!
+//! [`KnownHosts`] is a ready-made store, which can be saved to and loaded from a text file.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, pinning::*};
+//! # use std::sync::Arc;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let known_hosts = Arc::new(KnownHosts::new());
+//! let params: snow::params::NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let server_key = Builder::new(params.clone()).generate_keypair()?;
+//! # let client_key = Builder::new(params.clone()).generate_keypair()?;
+//! let mut client = Builder::new(params.clone())
+//!     .local_private_key(&client_key.private)
+//!     .pinning(known_hosts.clone(), "server.example", PinPolicy::TrustOnFirstUse)
+//!     .build_initiator()?;
+//! # let mut server =
+//! #     Builder::new(params).local_private_key(&server_key.private).build_responder()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! # let len = client.write_message(&[], &mut msg)?; server.read_message(&msg[..len], &mut buf)?;
+//! # let len = server.write_message(&[], &mut msg)?; client.read_message(&msg[..len], &mut buf)?;
+//! # let len = client.write_message(&[], &mut msg)?; server.read_message(&msg[..len], &mut buf)?;
+//! // ... finish the handshake ...
+//! assert_eq!(known_hosts.pinned_key("server.example"), Some(server_key.public));
+//!
+//! // Save the pins for next time.
+//! let saved = known_hosts.to_string();
+//! let known_hosts: KnownHosts = saved.parse()?;
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+//!
+//! [`Builder::pinning()`]: crate::Builder::pinning
+
+use crate::{
+    error::{Error, StateProblem},
+    utils::{decode_hex, encode_hex},
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Persists the remote static key pinned for each peer.
+///
+/// Implement this to keep pins in a database or a config file; [`KnownHosts`] keeps them in
+/// memory.
+pub trait PinStore: Send + Sync {
+    /// The remote static key pinned for `peer`, if there is one.
+    fn pinned_key(&self, peer: &str) -> Option<Vec<u8>>;
+
+    /// Pin `remote_static` as `peer`'s key, replacing any key pinned before.
+    fn pin(&self, peer: &str, remote_static: &[u8]);
+}
+
+/// What to do with a peer that has no pinned key yet.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PinPolicy {
+    /// Accept it, and pin the key it used once the handshake completes.
+    TrustOnFirstUse,
+
+    /// Reject it with `StateProblem::NotPinned`.
+    Strict,
+}
+
+/// The pinning a handshake was built with, checked as the remote static key comes in.
+#[derive(Clone)]
+pub(crate) struct Pinning {
+    pub(crate) store:  Arc<dyn PinStore>,
+    pub(crate) peer:   String,
+    pub(crate) policy: PinPolicy,
+}
+
+impl Pinning {
+    /// Check a remote static key that was just read against the pinned one.
+    pub(crate) fn check(&self, remote_static: &[u8]) -> Result<(), Error> {
+        match self.store.pinned_key(&self.peer) {
+            Some(pinned) if pinned != remote_static => bail!(StateProblem::PinMismatch),
+            Some(_) => Ok(()),
+            None if self.policy == PinPolicy::Strict => bail!(StateProblem::NotPinned),
+            None => Ok(()),
+        }
+    }
+
+    /// Pin the key of a peer that completed the handshake, if trusting it on first use.
+    pub(crate) fn commit(&self, remote_static: &[u8]) {
+        if self.policy == PinPolicy::TrustOnFirstUse && self.store.pinned_key(&self.peer).is_none()
+        {
+            self.store.pin(&self.peer, remote_static);
+        }
+    }
+}
+
+impl fmt::Debug for Pinning {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Pinning").field("peer", &self.peer).field("policy", &self.policy).finish()
+    }
+}
+
+/// An in-memory [`PinStore`], in the spirit of SSH's `known_hosts` file.
+///
+/// Its text form, from `to_string()` and `parse()`, has one `<peer> <hex key>` line per pin,
+/// sorted by peer. Blank lines and lines starting with `#` are skipped when parsing. Peer names
+/// can't contain whitespace.
+#[derive(Default, Debug)]
+pub struct KnownHosts {
+    pins: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl KnownHosts {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop the key pinned for `peer`, so it's trusted on first use again.
+    pub fn forget(&self, peer: &str) {
+        self.pins().remove(peer);
+    }
+
+    /// The number of pinned peers.
+    pub fn len(&self) -> usize {
+        self.pins().len()
+    }
+
+    /// Whether no peer is pinned.
+    pub fn is_empty(&self) -> bool {
+        self.pins().is_empty()
+    }
+
+    fn pins(&self) -> MutexGuard<'_, BTreeMap<String, Vec<u8>>> {
+        self.pins.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl PinStore for KnownHosts {
+    fn pinned_key(&self, peer: &str) -> Option<Vec<u8>> {
+        self.pins().get(peer).cloned()
+    }
+
+    fn pin(&self, peer: &str, remote_static: &[u8]) {
+        self.pins().insert(peer.to_owned(), remote_static.to_vec());
+    }
+}
+
+impl fmt::Display for KnownHosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (peer, key) in self.pins().iter() {
+            writeln!(f, "{} {}", peer, encode_hex(key))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for KnownHosts {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let known_hosts = KnownHosts::new();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (peer, key) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(peer), Some(key), None) => (peer, key),
+                _ => bail!(Error::Input),
+            };
+            known_hosts.pin(peer, &decode_hex(key).ok_or(Error::Input)?);
+        }
+        Ok(known_hosts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_hosts_round_trip() {
+        let known_hosts = KnownHosts::new();
+        known_hosts.pin("b.example", &[0xde, 0xad]);
+        known_hosts.pin("a.example", &[0x01, 0x02, 0xff]);
+        let text = known_hosts.to_string();
+        assert_eq!(text, "a.example 0102ff\nb.example dead\n");
+
+        let parsed: KnownHosts = format!("# pins\n\n{}", text).parse().unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed.pinned_key("b.example"), Some(vec![0xde, 0xad]));
+        parsed.forget("b.example");
+        assert_eq!(parsed.pinned_key("b.example"), None);
+    }
+
+    #[test]
+    fn test_known_hosts_rejects_malformed_lines() {
+        for text in &["a.example", "a.example 0g", "a.example 012", "a.example 01 02"] {
+            assert!(text.parse::<KnownHosts>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_policies() {
+        let store = Arc::new(KnownHosts::new());
+        let tofu = Pinning {
+            store:  store.clone(),
+            peer:   "peer".into(),
+            policy: PinPolicy::TrustOnFirstUse,
+        };
+        let strict = Pinning { policy: PinPolicy::Strict, ..tofu.clone() };
+
+        assert!(tofu.check(&[1; 32]).is_ok());
+        assert!(matches!(strict.check(&[1; 32]), Err(Error::State(StateProblem::NotPinned))));
+        tofu.commit(&[1; 32]);
+        assert!(strict.check(&[1; 32]).is_ok());
+        assert!(matches!(tofu.check(&[2; 32]), Err(Error::State(StateProblem::PinMismatch))));
+        tofu.commit(&[2; 32]);
+        assert_eq!(store.pinned_key("peer"), Some(vec![1; 32]));
+    }
+}
@@ -0,0 +1,518 @@
+//! The Lightning Network's transport,
+//! [BOLT #8](https://github.com/lightning/bolts/blob/master/08-transport.md), for talking to
+//! Lightning nodes.
+//!
+//! BOLT #8 runs [`PROTOCOL_NAME`] with [`PROLOGUE`], sending each of the three handshake messages
+//! ("acts") with empty payloads and a leading [`VERSION`] byte. Afterwards, every message is
+//! sent as an encrypted 2-byte length (a [`HEADER_LEN`]-byte header) followed by the encrypted
+//! body, and each direction rotates its key after [`KEY_ROTATION_INTERVAL`] encryptions, using
+//! the handshake's final chaining key. Nonces are encoded like Noise's ChaChaPoly does: 32 zero
+//! bits followed by the 64-bit counter, little endian.
+//!
+//! The default resolver implements secp256k1 (see [`DHChoice::Secp256k1`]) with the
+//! `dh-secp256k1` feature. Without it, the handshake needs a custom resolver for it, e.g. in a
+//! [`FallbackResolver`](crate::resolvers::FallbackResolver) in front of the default one.
+//!
+//! Requires the `cipher-chachapoly` and `hash-sha2` features.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "dh-secp256k1")]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! use snow::{profiles::bolt8::*, resolvers::DefaultResolver, Builder};
+//!
+//! let node = Builder::with_resolver(params(), Box::new(DefaultResolver)).generate_keypair()?;
+//! let mut initiator = initiator(Box::new(DefaultResolver), &[1; 32], &node.public)?;
+//! let mut responder = responder(Box::new(DefaultResolver), &node.private)?;
+//!
+//! let mut act = [0u8; ACT_THREE_LEN];
+//! for _ in 0..3 {
+//!     let (writer, reader) = if initiator.is_my_turn() {
+//!         (&mut initiator, &mut responder)
+//!     } else {
+//!         (&mut responder, &mut initiator)
+//!     };
+//!     let len = write_act(writer, &mut act)?;
+//!     read_act(reader, &act[..len])?;
+//! }
+//!
+//! let mut initiator = Transport::new(initiator)?;
+//! let mut responder = Transport::new(responder)?;
+//! let mut message = [0u8; 64];
+//! let len = initiator.write_message(b"hello", &mut message)?;
+//!
+//! let body_len = responder.read_header(&message[..HEADER_LEN])?;
+//! let mut payload = [0u8; 64];
+//! let len = responder.read_body(&message[HEADER_LEN..HEADER_LEN + body_len], &mut payload)?;
+//! assert_eq!(&payload[..len], b"hello");
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "dh-secp256k1"))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+//!
+//! [`DHChoice::Secp256k1`]: crate::params::DHChoice::Secp256k1
+
+use crate::{
+    constants::{CIPHERKEYLEN, MAXHASHLEN, TAGLEN},
+    error::{Error, InitStage, StateProblem},
+    params::NoiseParams,
+    resolvers::{BoxedCryptoResolver, DefaultResolver},
+    types::{Cipher, Hash},
+    Builder, HandshakeState,
+};
+
+/// The Noise protocol BOLT #8 runs.
+pub const PROTOCOL_NAME: &str = "Noise_XK_secp256k1_ChaChaPoly_SHA256";
+
+/// The prologue of every BOLT #8 handshake.
+pub const PROLOGUE: &[u8] = b"lightning";
+
+/// The handshake version, the first byte of each act.
+pub const VERSION: u8 = 0;
+
+/// The length of act one: the version, the initiator's ephemeral key and a tag.
+pub const ACT_ONE_LEN: usize = 1 + PUBKEY_LEN + TAGLEN;
+
+/// The length of act two: the version, the responder's ephemeral key and a tag.
+pub const ACT_TWO_LEN: usize = 1 + PUBKEY_LEN + TAGLEN;
+
+/// The length of act three: the version, the initiator's encrypted static key and a tag.
+pub const ACT_THREE_LEN: usize = 1 + PUBKEY_LEN + TAGLEN + TAGLEN;
+
+/// The length of a transport message's header, its encrypted 2-byte length.
+pub const HEADER_LEN: usize = 2 + TAGLEN;
+
+/// The longest payload a transport message can carry.
+pub const MAX_MESSAGE_LEN: usize = u16::MAX as usize;
+
+/// The number of encryptions (two per message) each direction does before rotating its key.
+pub const KEY_ROTATION_INTERVAL: u64 = 1000;
+
+/// The length of a compressed secp256k1 public key.
+const PUBKEY_LEN: usize = 33;
+
+/// The [`PROTOCOL_NAME`] BOLT #8 runs.
+pub fn params() -> NoiseParams {
+    PROTOCOL_NAME.parse().expect("BOLT #8's protocol name is supported")
+}
+
+/// Build the initiator of a handshake with the node whose static public key is `remote_public`.
+///
+/// # Errors
+///
+/// Fails like [`Builder::build_initiator()`], e.g. if `resolver` doesn't implement secp256k1.
+pub fn initiator(
+    resolver: BoxedCryptoResolver,
+    local_private: &[u8],
+    remote_public: &[u8],
+) -> Result<HandshakeState, Error> {
+    Builder::with_resolver(params(), resolver)
+        .prologue(PROLOGUE)
+        .local_private_key(local_private)
+        .remote_public_key(remote_public)
+        .build_initiator()
+}
+
+/// Build the responder of a handshake.
+///
+/// # Errors
+///
+/// Fails like [`Builder::build_responder()`], e.g. if `resolver` doesn't implement secp256k1.
+pub fn responder(
+    resolver: BoxedCryptoResolver,
+    local_private: &[u8],
+) -> Result<HandshakeState, Error> {
+    Builder::with_resolver(params(), resolver)
+        .prologue(PROLOGUE)
+        .local_private_key(local_private)
+        .build_responder()
+}
+
+/// Write the next act into `out`, returning its length.
+///
+/// # Errors
+///
+/// Fails with `Error::BufferTooSmall` if `out` can't hold the act, with `Error::Input` if
+/// `handshake` isn't running BOLT #8, and otherwise like [`HandshakeState::write_message()`].
+pub fn write_act(handshake: &mut HandshakeState, out: &mut [u8]) -> Result<usize, Error> {
+    let len = act_len(handshake)?;
+    if out.len() < len {
+        bail!(Error::BufferTooSmall { needed: len, got: out.len() });
+    }
+    out[0] = VERSION;
+    if handshake.write_message(&[], &mut out[1..len])? != len - 1 {
+        bail!(Error::Input);
+    }
+    Ok(len)
+}
+
+/// Read the next act, which has to be exactly as long as [`ACT_ONE_LEN`], [`ACT_TWO_LEN`] or
+/// [`ACT_THREE_LEN`] says.
+///
+/// # Errors
+///
+/// Fails with `Error::Input` if `act` has the wrong length or an unknown version, and otherwise
+/// like [`HandshakeState::read_message()`]. BOLT #8 says to close the connection on any error.
+pub fn read_act(handshake: &mut HandshakeState, act: &[u8]) -> Result<(), Error> {
+    if act.len() != act_len(handshake)? || act[0] != VERSION {
+        bail!(Error::Input);
+    }
+    handshake.read_message(&act[1..], &mut [])?;
+    Ok(())
+}
+
+fn act_len(handshake: &HandshakeState) -> Result<usize, Error> {
+    Ok(match handshake.pattern_position() {
+        0 => ACT_ONE_LEN,
+        1 => ACT_TWO_LEN,
+        2 => ACT_THREE_LEN,
+        _ => bail!(StateProblem::HandshakeAlreadyFinished),
+    })
+}
+
+/// The transport after a BOLT #8 handshake.
+///
+/// Reading is split in two, like a connection reads it: first the fixed-length header, which
+/// gives the length of the body, and then the body.
+pub struct Transport {
+    sending:      Direction,
+    receiving:    Direction,
+    hash:         Box<dyn Hash>,
+    expected_len: Option<usize>,
+}
+
+impl Transport {
+    /// Start the transport from a finished handshake, using the default crypto resolver.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`with_resolver()`](Self::with_resolver).
+    pub fn new(handshake: HandshakeState) -> Result<Self, Error> {
+        Self::with_resolver(handshake, Box::new(DefaultResolver))
+    }
+
+    /// Start the transport from a finished handshake, using a custom crypto resolver.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::State` if the handshake isn't finished, `Error::Input` if it didn't
+    /// run BOLT #8, and `Error::Init` if the resolver doesn't support ChaChaPoly or SHA-256.
+    pub fn with_resolver(
+        mut handshake: HandshakeState,
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        if !handshake.is_handshake_finished() {
+            bail!(StateProblem::HandshakeNotFinished);
+        } else if handshake.params.name != PROTOCOL_NAME {
+            bail!(Error::Input);
+        }
+        let mut keys = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        handshake.symmetricstate.split_raw(&mut keys.0, &mut keys.1);
+        let (sending, receiving) =
+            if handshake.is_initiator() { (keys.0, keys.1) } else { (keys.1, keys.0) };
+        Self::from_keys(
+            handshake.symmetricstate.chaining_key(),
+            &sending[..CIPHERKEYLEN],
+            &receiving[..CIPHERKEYLEN],
+            &resolver,
+        )
+    }
+
+    fn from_keys(
+        chaining_key: &[u8],
+        sending: &[u8],
+        receiving: &[u8],
+        resolver: &BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        let params = params();
+        let hash = resolver.resolve_hash(&params.hash).ok_or(InitStage::GetHashImpl)?;
+        let direction = |key| -> Result<Direction, Error> {
+            let cipher = resolver.resolve_cipher(&params.cipher).ok_or(InitStage::GetCipherImpl)?;
+            Ok(Direction::new(cipher, chaining_key, key))
+        };
+        Ok(Transport {
+            sending: direction(sending)?,
+            receiving: direction(receiving)?,
+            hash,
+            expected_len: None,
+        })
+    }
+
+    /// Encrypt `payload` into `out` as a header followed by the body, returning the length
+    /// written ([`HEADER_LEN`] plus the payload's length plus `TAGLEN`).
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::Input` if `payload` is longer than [`MAX_MESSAGE_LEN`], and with
+    /// `Error::BufferTooSmall` if `out` can't hold the message.
+    pub fn write_message(&mut self, payload: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+        if payload.len() > MAX_MESSAGE_LEN {
+            bail!(Error::Input);
+        }
+        let needed = HEADER_LEN + payload.len() + TAGLEN;
+        if out.len() < needed {
+            bail!(Error::BufferTooSmall { needed, got: out.len() });
+        }
+        let len = (payload.len() as u16).to_be_bytes();
+        self.sending.encrypt(&mut *self.hash, &len, &mut out[..HEADER_LEN]);
+        self.sending.encrypt(&mut *self.hash, payload, &mut out[HEADER_LEN..needed]);
+        Ok(needed)
+    }
+
+    /// Decrypt a message's header, returning the length of the body that follows it (the
+    /// payload's length plus `TAGLEN`), which has to be passed to
+    /// [`read_body()`](Self::read_body) next.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::Input` if `header` isn't [`HEADER_LEN`] bytes or a body is still
+    /// expected, and `Error::Decrypt` if it doesn't decrypt.
+    pub fn read_header(&mut self, header: &[u8]) -> Result<usize, Error> {
+        if header.len() != HEADER_LEN || self.expected_len.is_some() {
+            bail!(Error::Input);
+        }
+        let mut len = [0u8; 2];
+        self.receiving.decrypt(&mut *self.hash, header, &mut len)?;
+        let body_len = usize::from(u16::from_be_bytes(len)) + TAGLEN;
+        self.expected_len = Some(body_len);
+        Ok(body_len)
+    }
+
+    /// Decrypt the body of the message whose header was just read into `out`, returning the
+    /// payload's length.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::Input` if no header was read or `body` doesn't have the length it
+    /// gave, `Error::BufferTooSmall` if `out` can't hold the payload, and `Error::Decrypt` if
+    /// `body` doesn't decrypt.
+    pub fn read_body(&mut self, body: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+        if self.expected_len != Some(body.len()) {
+            bail!(Error::Input);
+        }
+        let needed = body.len() - TAGLEN;
+        if out.len() < needed {
+            bail!(Error::BufferTooSmall { needed, got: out.len() });
+        }
+        self.receiving.decrypt(&mut *self.hash, body, &mut out[..needed])?;
+        self.expected_len = None;
+        Ok(needed)
+    }
+}
+
+/// One direction's key, nonce and chaining key.
+struct Direction {
+    cipher:       Box<dyn Cipher>,
+    chaining_key: [u8; MAXHASHLEN],
+    key:          [u8; CIPHERKEYLEN],
+    nonce:        u64,
+}
+
+impl Direction {
+    fn new(mut cipher: Box<dyn Cipher>, chaining_key: &[u8], key: &[u8]) -> Self {
+        cipher.set(key);
+        let mut direction =
+            Direction { cipher, chaining_key: [0; MAXHASHLEN], key: [0; CIPHERKEYLEN], nonce: 0 };
+        direction.chaining_key[..chaining_key.len()].copy_from_slice(chaining_key);
+        direction.key.copy_from_slice(key);
+        direction
+    }
+
+    fn encrypt(&mut self, hash: &mut dyn Hash, plaintext: &[u8], out: &mut [u8]) {
+        self.cipher.encrypt(self.nonce, &[], plaintext, out);
+        self.advance(hash);
+    }
+
+    fn decrypt(
+        &mut self,
+        hash: &mut dyn Hash,
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        self.cipher.decrypt(self.nonce, &[], ciphertext, out).map_err(|_| Error::Decrypt)?;
+        self.advance(hash);
+        Ok(())
+    }
+
+    /// Count an encryption, rotating the key to `ck, k = HKDF(ck, k)` every
+    /// [`KEY_ROTATION_INTERVAL`] of them.
+    fn advance(&mut self, hash: &mut dyn Hash) {
+        self.nonce += 1;
+        if self.nonce < KEY_ROTATION_INTERVAL {
+            return;
+        }
+        let hash_len = hash.hash_len();
+        let mut next = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        hash.hkdf(&self.chaining_key[..hash_len], &self.key, 2, &mut next.0, &mut next.1, &mut []);
+        self.chaining_key = next.0;
+        self.key.copy_from_slice(&next.1[..CIPHERKEYLEN]);
+        self.cipher.set(&self.key);
+        self.nonce = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "dh-secp256k1")]
+    fn handshake() -> (HandshakeState, HandshakeState) {
+        let keypair =
+            Builder::with_resolver(params(), Box::new(DefaultResolver)).generate_keypair().unwrap();
+        let initiator = initiator(Box::new(DefaultResolver), &[1; 32], &keypair.public).unwrap();
+        let responder = responder(Box::new(DefaultResolver), &keypair.private).unwrap();
+        (initiator, responder)
+    }
+
+    /// The successful handshake test vectors from BOLT #8's appendix, followed by its first
+    /// encrypted message.
+    #[cfg(feature = "dh-secp256k1")]
+    #[test]
+    fn test_handshake_expected_values() {
+        let responder_public =
+            hex::decode("028d7500dd4c12685d1f568b4c2b5048e8534b873319f3a8daa612b469132ec7f7")
+                .unwrap();
+        let mut initiator = Builder::with_resolver(params(), Box::new(DefaultResolver))
+            .prologue(PROLOGUE)
+            .local_private_key(&[0x11; 32])
+            .remote_public_key(&responder_public)
+            .fixed_ephemeral_key_for_testing_only(&[0x12; 32])
+            .build_initiator()
+            .unwrap();
+        let mut responder = Builder::with_resolver(params(), Box::new(DefaultResolver))
+            .prologue(PROLOGUE)
+            .local_private_key(&[0x21; 32])
+            .fixed_ephemeral_key_for_testing_only(&[0x22; 32])
+            .build_responder()
+            .unwrap();
+
+        let expected = [
+            "00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8\
+             afe6c195782c6a",
+            "0002466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9e\
+             f6eafca3f730ae",
+            "00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5\
+             bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba",
+        ];
+        let mut act = [0u8; ACT_THREE_LEN];
+        for expected in expected {
+            let (writer, reader) = if initiator.is_my_turn() {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            let len = write_act(writer, &mut act).unwrap();
+            assert_eq!(hex::encode(&act[..len]), expected);
+            read_act(reader, &act[..len]).unwrap();
+        }
+        assert_eq!(
+            hex::encode(responder.get_remote_static().unwrap()),
+            "034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa"
+        );
+
+        let mut initiator = Transport::new(initiator).unwrap();
+        let mut message = [0u8; 64];
+        let len = initiator.write_message(b"hello", &mut message).unwrap();
+        assert_eq!(
+            hex::encode(&message[..len]),
+            "cf2b30ddf0cf3f80e7c35a6e6730b59fe802473180f396d88a8fb0db8cbcf25d2f214cf9ea1d95"
+        );
+    }
+
+    #[cfg(feature = "dh-secp256k1")]
+    #[test]
+    fn test_acts() {
+        let (mut initiator, mut responder) = handshake();
+        let mut act = [0u8; ACT_THREE_LEN];
+        assert!(matches!(
+            write_act(&mut initiator, &mut act[..ACT_ONE_LEN - 1]),
+            Err(Error::BufferTooSmall { .. })
+        ));
+
+        assert_eq!(write_act(&mut initiator, &mut act).unwrap(), ACT_ONE_LEN);
+        assert!(matches!(read_act(&mut responder, &act[..ACT_ONE_LEN - 1]), Err(Error::Input)));
+        let mut versioned = act;
+        versioned[0] = 1;
+        assert!(matches!(read_act(&mut responder, &versioned[..ACT_ONE_LEN]), Err(Error::Input)));
+        read_act(&mut responder, &act[..ACT_ONE_LEN]).unwrap();
+
+        assert_eq!(write_act(&mut responder, &mut act).unwrap(), ACT_TWO_LEN);
+        read_act(&mut initiator, &act[..ACT_TWO_LEN]).unwrap();
+        assert_eq!(write_act(&mut initiator, &mut act).unwrap(), ACT_THREE_LEN);
+        read_act(&mut responder, &act).unwrap();
+        assert!(matches!(write_act(&mut responder, &mut act), Err(Error::State(_))));
+
+        let mut writer = Transport::new(initiator).unwrap();
+        let mut reader = Transport::new(responder).unwrap();
+        for _ in 0..2 {
+            let mut message = [0u8; 128];
+            let mut payload = [0u8; 128];
+            let len = writer.write_message(b"hello", &mut message).unwrap();
+            assert_eq!(len, HEADER_LEN + 5 + TAGLEN);
+            assert!(matches!(
+                reader.read_body(&message[HEADER_LEN..len], &mut payload),
+                Err(Error::Input)
+            ));
+            assert_eq!(reader.read_header(&message[..HEADER_LEN]).unwrap(), 5 + TAGLEN);
+            assert_eq!(reader.read_body(&message[HEADER_LEN..len], &mut payload).unwrap(), 5);
+            assert_eq!(&payload[..5], b"hello");
+            // And the other way around.
+            std::mem::swap(&mut writer, &mut reader);
+        }
+    }
+
+    #[cfg(feature = "dh-secp256k1")]
+    #[test]
+    fn test_transport_not_finished() {
+        let (initiator, _) = handshake();
+        assert!(matches!(
+            Transport::new(initiator),
+            Err(Error::State(StateProblem::HandshakeNotFinished))
+        ));
+    }
+
+    /// The message encryption test vectors from BOLT #8.
+    #[test]
+    fn test_key_rotation_expected_values() {
+        let ck = hex::decode("919219dbb2920afa8db80f9a51787a840bcf111ed8d588caf9ab4be716e42b01")
+            .unwrap();
+        let sk = hex::decode("969ab31b4d288cedf6218839b27a3e2140827047f2c0f01bf5c04435d43511a9")
+            .unwrap();
+        let rk = hex::decode("bb9020b8965f4df047e07f955f3c4b88418984aadc5cdb35096b9ea8fa5c3442")
+            .unwrap();
+        let resolver: BoxedCryptoResolver = Box::new(DefaultResolver);
+        let mut initiator = Transport::from_keys(&ck, &sk, &rk, &resolver).unwrap();
+        let mut responder = Transport::from_keys(&ck, &rk, &sk, &resolver).unwrap();
+        let expected = [
+            (0, "cf2b30ddf0cf3f80e7c35a6e6730b59fe802473180f396d88a8fb0db8cbcf25d2f214cf9ea1d95"),
+            (1, "72887022101f0b6753e0c7de21657d35a4cb2a1f5cde2650528bbc8f837d0f0d7ad833b1a256a1"),
+            (500, "178cb9d7387190fa34db9c2d50027d21793c9bc2d40b1e14dcf30ebeeeb220f48364f7a4c68bf8"),
+            (501, "1b186c57d44eb6de4c057c49940d79bb838a145cb528d6e8fd26dbe50a60ca2c104b56b60e45bd"),
+            (
+                1000,
+                "4a2f3cc3b5e78ddb83dcb426d9863d9d9a723b0337c89dd0b005d89f8d3c05c52b76b29b740f09",
+            ),
+            (
+                1001,
+                "2ecd8c8a5629d0d02ab457a0fdd0f7b90a192cd46be5ecb6ca570bfc5e268338b1a16cf4ef2d36",
+            ),
+        ];
+
+        let mut message = [0u8; 64];
+        let mut payload = [0u8; 64];
+        let mut expected = expected.iter().peekable();
+        for i in 0..=1001 {
+            let len = initiator.write_message(b"hello", &mut message).unwrap();
+            if let Some((_, hex)) = expected.next_if(|(n, _)| *n == i) {
+                assert_eq!(hex::encode(&message[..len]), *hex, "message {}", i);
+            }
+            let body_len = responder.read_header(&message[..HEADER_LEN]).unwrap();
+            let body = &message[HEADER_LEN..HEADER_LEN + body_len];
+            assert_eq!(responder.read_body(body, &mut payload).unwrap(), 5);
+        }
+        assert!(expected.next().is_none());
+    }
+}
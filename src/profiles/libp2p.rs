@@ -0,0 +1,470 @@
+//! [libp2p's Noise handshake](https://github.com/libp2p/specs/blob/master/noise/README.md), for
+//! dropping into libp2p-compatible stacks.
+//!
+//! libp2p runs [`PROTOCOL_NAME`] and binds each peer's Noise static key to its libp2p identity
+//! key: the second and third handshake messages carry a [`Payload`] with the sender's identity
+//! key, its signature over [`STATIC_KEY_DOMAIN`] followed by the Noise static public key, and
+//! optionally [`Extensions`] such as the stream muxers the sender supports, so they can be
+//! agreed on without another round trip. The first message has no payload, since it would be
+//! neither encrypted nor authenticated.
+//!
+//! Every handshake and transport message is framed with a 2-byte big-endian length.
+//!
+//! Only Ed25519 identity keys are supported. Requires the `cipher-chachapoly`, `dh-25519`,
+//! `hash-sha2` and `sig-ed25519` features.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{profiles::libp2p::*, Builder};
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let initiator_static = Builder::new(params()).generate_keypair()?;
+//! # let responder_static = Builder::new(params()).generate_keypair()?;
+//! let (initiator_id, responder_id) = (Identity::generate()?, Identity::generate()?);
+//! let mut initiator = initiator(&initiator_static.private)?;
+//! let mut responder = responder(&responder_static.private)?;
+//! let muxers = Extensions { stream_muxers: vec!["/yamux/1.0.0".into()], ..Default::default() };
+//!
+//! let mut frame = [0u8; 1024];
+//! let len = write_message(&mut initiator, &initiator_id, None, &mut frame)?;
+//! assert!(read_message(&mut responder, &frame[..len])?.is_none());
+//!
+//! let len = write_message(&mut responder, &responder_id, Some(&muxers), &mut frame)?;
+//! let remote = read_message(&mut initiator, &frame[..len])?.unwrap();
+//! assert_eq!(&remote.identity_key[..], responder_id.public_key());
+//! assert_eq!(remote.extensions, Some(muxers));
+//!
+//! let len = write_message(&mut initiator, &initiator_id, None, &mut frame)?;
+//! let remote = read_message(&mut responder, &frame[..len])?.unwrap();
+//! assert_eq!(&remote.identity_key[..], initiator_id.public_key());
+//! #     Ok(())
+//! # }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::MAXMSGLEN,
+    error::{Error, InitStage, StateProblem},
+    params::{NoiseParams, SigChoice},
+    resolvers::{CryptoResolver, DefaultResolver},
+    types::Sign,
+    Builder, HandshakeState,
+};
+use std::convert::TryFrom;
+
+/// The Noise protocol libp2p runs.
+pub const PROTOCOL_NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// What an identity key signs, followed by the Noise static public key.
+pub const STATIC_KEY_DOMAIN: &[u8] = b"noise-libp2p-static-key:";
+
+/// The length of the big-endian length prefix of every message.
+pub const LENGTH_PREFIX_LEN: usize = 2;
+
+/// The `KeyType` of Ed25519 keys in libp2p's `PublicKey` message.
+const KEY_TYPE_ED25519: u64 = 1;
+
+const ED25519_KEY_LEN: usize = 32;
+const ED25519_SIG_LEN: usize = 64;
+
+/// The [`PROTOCOL_NAME`] libp2p runs.
+pub fn params() -> NoiseParams {
+    PROTOCOL_NAME.parse().expect("libp2p's protocol name is supported")
+}
+
+/// Build the initiator of a handshake, with `local_private` as its Noise static key (not its
+/// identity key).
+///
+/// # Errors
+///
+/// Fails like [`Builder::build_initiator()`], e.g. if the key has the wrong length.
+pub fn initiator(local_private: &[u8]) -> Result<HandshakeState, Error> {
+    Builder::with_resolver(params(), Box::new(DefaultResolver))
+        .local_private_key(local_private)
+        .build_initiator()
+}
+
+/// Build the responder of a handshake, with `local_private` as its Noise static key (not its
+/// identity key).
+///
+/// # Errors
+///
+/// Fails like [`Builder::build_responder()`], e.g. if the key has the wrong length.
+pub fn responder(local_private: &[u8]) -> Result<HandshakeState, Error> {
+    Builder::with_resolver(params(), Box::new(DefaultResolver))
+        .local_private_key(local_private)
+        .build_responder()
+}
+
+/// A libp2p identity key pair.
+pub struct Identity {
+    signer: Box<dyn Sign>,
+}
+
+impl Identity {
+    /// The identity with the Ed25519 private key `private`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::Init` if `private` isn't 32 bytes.
+    pub fn new(private: &[u8]) -> Result<Self, Error> {
+        let mut signer = ed25519()?;
+        if private.len() != signer.priv_len() {
+            bail!(InitStage::ValidateKeyLengths);
+        }
+        signer.set(private);
+        Ok(Identity { signer })
+    }
+
+    /// A new random identity.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::Rng` if the RNG fails.
+    pub fn generate() -> Result<Self, Error> {
+        let mut signer = ed25519()?;
+        let mut rng = DefaultResolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
+        signer.generate(&mut *rng).map_err(|_| Error::Rng)?;
+        Ok(Identity { signer })
+    }
+
+    /// The Ed25519 public key.
+    pub fn public_key(&self) -> &[u8] {
+        self.signer.pubkey()
+    }
+
+    /// The Ed25519 private key.
+    pub fn private_key(&self) -> &[u8] {
+        self.signer.privkey()
+    }
+
+    /// The payload binding this identity to the Noise static public key `noise_static`.
+    pub fn sign_payload(&self, noise_static: &[u8], extensions: Option<Extensions>) -> Payload {
+        let mut identity_key = Vec::with_capacity(4 + ED25519_KEY_LEN);
+        put_varint_field(&mut identity_key, 1, KEY_TYPE_ED25519);
+        put_bytes_field(&mut identity_key, 2, self.public_key());
+        let mut identity_sig = vec![0u8; self.signer.sig_len()];
+        self.signer.sign(&[STATIC_KEY_DOMAIN, noise_static].concat(), &mut identity_sig);
+        Payload { identity_key, identity_sig, extensions }
+    }
+}
+
+/// The `NoiseExtensions` a handshake payload can carry.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Extensions {
+    /// The hashes of the certificates a WebTransport server uses.
+    pub webtransport_certhashes: Vec<Vec<u8>>,
+    /// The stream muxers the sender supports, in order of preference, e.g. `/yamux/1.0.0`.
+    pub stream_muxers:           Vec<String>,
+}
+
+/// A `NoiseHandshakePayload`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Payload {
+    /// The sender's identity key, as a protobuf-encoded libp2p `PublicKey`.
+    pub identity_key: Vec<u8>,
+    /// The identity key's signature over [`STATIC_KEY_DOMAIN`] and the Noise static key.
+    pub identity_sig: Vec<u8>,
+    /// The sender's extensions.
+    pub extensions:   Option<Extensions>,
+}
+
+impl Payload {
+    /// Encode the payload as protobuf.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_bytes_field(&mut out, 1, &self.identity_key);
+        put_bytes_field(&mut out, 2, &self.identity_sig);
+        if let Some(extensions) = &self.extensions {
+            let mut encoded = Vec::new();
+            for hash in &extensions.webtransport_certhashes {
+                put_bytes_field(&mut encoded, 1, hash);
+            }
+            for muxer in &extensions.stream_muxers {
+                put_bytes_field(&mut encoded, 2, muxer.as_bytes());
+            }
+            put_bytes_field(&mut out, 4, &encoded);
+        }
+        out
+    }
+
+    /// Decode a protobuf-encoded payload, skipping unknown fields (like the early data field of
+    /// earlier versions of the spec).
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::Input` if `bytes` isn't a valid encoding.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut payload = Payload::default();
+        let mut fields = Fields(bytes);
+        while let Some((number, value)) = fields.next_field()? {
+            match (number, value) {
+                (1, Value::Bytes(key)) => payload.identity_key = key.to_vec(),
+                (2, Value::Bytes(sig)) => payload.identity_sig = sig.to_vec(),
+                (4, Value::Bytes(encoded)) => {
+                    let extensions = payload.extensions.get_or_insert_with(Default::default);
+                    let mut fields = Fields(encoded);
+                    while let Some((number, value)) = fields.next_field()? {
+                        match (number, value) {
+                            (1, Value::Bytes(hash)) => {
+                                extensions.webtransport_certhashes.push(hash.to_vec())
+                            },
+                            (2, Value::Bytes(muxer)) => extensions
+                                .stream_muxers
+                                .push(String::from_utf8(muxer.to_vec()).map_err(|_| Error::Input)?),
+                            (1, _) | (2, _) => bail!(Error::Input),
+                            _ => {},
+                        }
+                    }
+                },
+                (1, _) | (2, _) | (4, _) => bail!(Error::Input),
+                _ => {},
+            }
+        }
+        Ok(payload)
+    }
+
+    /// Check the signature over `noise_static`, the sender's Noise static public key, returning
+    /// its Ed25519 identity key.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::Input` if the identity key isn't a valid Ed25519 `PublicKey`, and
+    /// `Error::Sig` if the signature doesn't verify.
+    pub fn verify(&self, noise_static: &[u8]) -> Result<[u8; ED25519_KEY_LEN], Error> {
+        let (mut key_type, mut data) = (None, None);
+        let mut fields = Fields(&self.identity_key);
+        while let Some((number, value)) = fields.next_field()? {
+            match (number, value) {
+                (1, Value::Varint(value)) => key_type = Some(value),
+                (2, Value::Bytes(bytes)) => data = Some(bytes),
+                _ => bail!(Error::Input),
+            }
+        }
+        let mut identity_key = [0u8; ED25519_KEY_LEN];
+        match (key_type, data) {
+            (Some(KEY_TYPE_ED25519), Some(data)) if data.len() == ED25519_KEY_LEN => {
+                identity_key.copy_from_slice(data)
+            },
+            _ => bail!(Error::Input),
+        }
+        let message = [STATIC_KEY_DOMAIN, noise_static].concat();
+        if self.identity_sig.len() != ED25519_SIG_LEN
+            || !ed25519()?.verify(&identity_key, &message, &self.identity_sig)
+        {
+            bail!(Error::Sig);
+        }
+        Ok(identity_key)
+    }
+}
+
+/// What the other side proved in its handshake payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Remote {
+    /// Its Ed25519 identity key.
+    pub identity_key: [u8; ED25519_KEY_LEN],
+    /// The extensions it sent.
+    pub extensions:   Option<Extensions>,
+}
+
+/// Write the next handshake message into `out` with its length prefix, returning the length of
+/// the frame. The second and third messages carry `identity`'s signed payload with
+/// `extensions`; the first carries nothing.
+///
+/// # Errors
+///
+/// Fails with `Error::BufferTooSmall` if `out` can't hold the frame, and otherwise like
+/// [`HandshakeState::write_message()`].
+pub fn write_message(
+    handshake: &mut HandshakeState,
+    identity: &Identity,
+    extensions: Option<&Extensions>,
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    let payload = if handshake.pattern_position() == 0 {
+        Vec::new()
+    } else {
+        let noise_static =
+            handshake.get_local_static().ok_or(StateProblem::MissingKeyMaterial)?.to_vec();
+        identity.sign_payload(&noise_static, extensions.cloned()).encode()
+    };
+    if out.len() < LENGTH_PREFIX_LEN {
+        bail!(Error::BufferTooSmall { needed: LENGTH_PREFIX_LEN, got: out.len() });
+    }
+    let end = out.len().min(LENGTH_PREFIX_LEN + MAXMSGLEN);
+    let len = handshake.write_message(&payload, &mut out[LENGTH_PREFIX_LEN..end])?;
+    out[..LENGTH_PREFIX_LEN].copy_from_slice(&(len as u16).to_be_bytes());
+    Ok(LENGTH_PREFIX_LEN + len)
+}
+
+/// Read a handshake message frame, including its length prefix.
+///
+/// Returns what the other side proved about itself for the second and third messages, and
+/// `None` for the first, whose payload is ignored.
+///
+/// # Errors
+///
+/// Fails with `Error::Input` if the length prefix doesn't match the frame or the payload is
+/// malformed, `Error::Sig` if the payload's signature doesn't verify, and otherwise like
+/// [`HandshakeState::read_message()`].
+pub fn read_message(handshake: &mut HandshakeState, frame: &[u8]) -> Result<Option<Remote>, Error> {
+    if frame.len() < LENGTH_PREFIX_LEN
+        || usize::from(u16::from_be_bytes([frame[0], frame[1]])) != frame.len() - LENGTH_PREFIX_LEN
+    {
+        bail!(Error::Input);
+    }
+    let first = handshake.pattern_position() == 0;
+    let mut payload = vec![0u8; frame.len()];
+    let len = handshake.read_message(&frame[LENGTH_PREFIX_LEN..], &mut payload)?;
+    if first {
+        return Ok(None);
+    }
+    let payload = Payload::decode(&payload[..len])?;
+    let noise_static = handshake.get_remote_static().ok_or(StateProblem::MissingKeyMaterial)?;
+    let identity_key = payload.verify(noise_static)?;
+    Ok(Some(Remote { identity_key, extensions: payload.extensions }))
+}
+
+fn ed25519() -> Result<Box<dyn Sign>, Error> {
+    Ok(DefaultResolver.resolve_sig(&SigChoice::Ed25519).ok_or(InitStage::GetSigImpl)?)
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_varint_field(out: &mut Vec<u8>, number: u64, value: u64) {
+    put_varint(out, number << 3);
+    put_varint(out, value);
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+    put_varint(out, number << 3 | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// A protobuf field's value.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterates over the fields of a protobuf message.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>, Error> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => self.take(8).map(|_| Value::Fixed)?,
+            2 => {
+                let len = usize::try_from(self.varint()?).map_err(|_| Error::Input)?;
+                Value::Bytes(self.take(len)?)
+            },
+            5 => self.take(4).map(|_| Value::Fixed)?,
+            _ => bail!(Error::Input),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        for (i, byte) in self.0.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(value);
+            }
+        }
+        bail!(Error::Input)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            bail!(Error::Input);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(seed: u8) -> Identity {
+        Identity::new(&[seed; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_payload_encoding() {
+        let extensions = Extensions {
+            webtransport_certhashes: vec![vec![1, 2]],
+            stream_muxers:           vec!["/yamux/1.0.0".into(), "/mplex/6.7.0".into()],
+        };
+        let payload = identity(1).sign_payload(&[9; 32], Some(extensions));
+        let encoded = payload.encode();
+        assert_eq!(encoded[..6], [0x0a, 36, 0x08, 0x01, 0x12, 32]);
+        assert_eq!(encoded[38..40], [0x12, 64]);
+        assert_eq!(Payload::decode(&encoded).unwrap(), payload);
+        assert_eq!(&payload.verify(&[9; 32]).unwrap()[..], identity(1).public_key());
+        assert!(matches!(payload.verify(&[8; 32]), Err(Error::Sig)));
+
+        // Unknown fields, like the old early data field, are skipped.
+        let mut with_data = encoded.clone();
+        with_data.extend_from_slice(&[0x1a, 3, b'a', b'b', b'c', 0x28, 0x96, 0x01]);
+        assert_eq!(Payload::decode(&with_data).unwrap(), payload);
+
+        for bad in [&encoded[..encoded.len() - 1], &[0x0a, 0x80], &[0x08, 1], &[0x0b]] {
+            assert!(matches!(Payload::decode(bad), Err(Error::Input)), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let keys = [[1u8; 32], [2u8; 32]];
+        let ids = [identity(3), identity(4)];
+        let mut sides = [initiator(&keys[0]).unwrap(), responder(&keys[1]).unwrap()];
+        let mut frame = [0u8; 1024];
+        let mut remotes = Vec::new();
+        for i in 0..3 {
+            let (writer, reader) = (i % 2, 1 - i % 2);
+            let [a, b] = &mut sides;
+            let (w, r) = if writer == 0 { (a, b) } else { (b, a) };
+            let len = write_message(w, &ids[writer], None, &mut frame).unwrap();
+            assert_eq!(usize::from(u16::from_be_bytes([frame[0], frame[1]])), len - 2);
+            assert!(matches!(read_message(r, &frame[..len - 1]), Err(Error::Input)));
+            remotes.push(read_message(r, &frame[..len]).unwrap().map(|r| (reader, r)));
+        }
+        assert!(remotes[0].is_none());
+        for (reader, remote) in remotes.into_iter().flatten() {
+            assert_eq!(&remote.identity_key[..], ids[1 - reader].public_key());
+        }
+    }
+
+    #[test]
+    fn test_wrong_identity_rejected() {
+        let mut initiator = initiator(&[1; 32]).unwrap();
+        let mut responder = responder(&[2; 32]).unwrap();
+        let mut frame = [0u8; 1024];
+        let len = write_message(&mut initiator, &identity(3), None, &mut frame).unwrap();
+        read_message(&mut responder, &frame[..len]).unwrap();
+
+        // A payload signed over a different static key is rejected.
+        let payload = identity(4).sign_payload(&[7; 32], None).encode();
+        let len = responder.write_message(&payload, &mut frame[2..]).unwrap();
+        frame[..2].copy_from_slice(&(len as u16).to_be_bytes());
+        assert!(matches!(read_message(&mut initiator, &frame[..len + 2]), Err(Error::Sig)));
+    }
+}
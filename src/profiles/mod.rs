@@ -0,0 +1,14 @@
+//! Helpers for interoperating with protocols that are built on a Noise handshake, but wrap it in
+//! framing and other details of their own.
+
+#[cfg(all(feature = "cipher-chachapoly", feature = "hash-sha2"))]
+pub mod bolt8;
+#[cfg(all(
+    feature = "cipher-chachapoly",
+    feature = "dh-25519",
+    feature = "hash-sha2",
+    feature = "sig-ed25519"
+))]
+pub mod libp2p;
+#[cfg(all(feature = "cipher-chachapoly", feature = "dh-25519", feature = "hash-blake2"))]
+pub mod wireguard;
@@ -0,0 +1,620 @@
+//! [WireGuard's](https://www.wireguard.com/papers/wireguard.pdf) handshake (section 5.4 of the
+//! paper), for talking to WireGuard peers.
+//!
+//! WireGuard runs [`CONSTRUCTION`] with [`IDENTIFIER`] as the prologue, and wraps the two
+//! handshake messages in packets with a type, the peers' session indices and two MACs:
+//!
+//! * `mac1` is keyed by the receiver's static public key (see [`mac1()`]), so a peer can cheaply
+//!   drop packets from anyone who doesn't know who they're talking to.
+//! * `mac2` is keyed by a cookie the receiver handed out in a cookie reply (see [`cookie()`] and
+//!   [`write_cookie_reply()`]), and is all zeroes without one.
+//!
+//! The initiator's payload is a [`Tai64n`] timestamp, which the responder has to check is later
+//! than any it has seen from the same peer before answering, to stop replayed initiations.
+//!
+//! Unlike [`cookie`](crate::cookie), which follows the same scheme with the protocol's own
+//! primitives, everything here is byte-for-byte what WireGuard puts on the wire: the MACs are
+//! keyed BLAKE2s, and cookie replies are encrypted with XChaCha20-Poly1305.
+//!
+//! Transport packets (type [`MESSAGE_TRANSPORT`]) carry their nonce in the clear, and are left to
+//! [`StatelessTransportState`](crate::StatelessTransportState).
+//!
+//! Requires the `cipher-chachapoly`, `dh-25519` and `hash-blake2` features.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{profiles::wireguard::*, Builder};
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let initiator_key = Builder::new(params()).generate_keypair()?;
+//! # let responder_key = Builder::new(params()).generate_keypair()?;
+//! let mut initiator = initiator(&initiator_key.private, &responder_key.public, None)?;
+//! let mut responder = responder(&responder_key.private, None)?;
+//!
+//! let packet = write_initiation(&mut initiator, 1, Tai64n::now(), None)?;
+//! let initiation = read_initiation(&mut responder, &packet)?;
+//! assert_eq!(initiation.sender, 1);
+//!
+//! let packet = write_response(&mut responder, 2, initiation.sender, None)?;
+//! let response = read_response(&mut initiator, &packet)?;
+//! assert_eq!((response.sender, response.receiver), (2, 1));
+//! #     Ok(())
+//! # }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::TAGLEN,
+    error::{Error, StateProblem},
+    params::NoiseParams,
+    resolvers::DefaultResolver,
+    Builder, HandshakeState,
+};
+use blake2::{
+    digest::{Update, VariableOutput},
+    Blake2s, Digest, VarBlake2s,
+};
+use chacha20poly1305::{
+    aead::{AeadInPlace, NewAead},
+    XChaCha20Poly1305,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+/// The Noise protocol WireGuard runs.
+pub const CONSTRUCTION: &str = "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+
+/// The prologue of every WireGuard handshake.
+pub const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+
+/// Hashed with the receiver's static public key to key `mac1`.
+pub const LABEL_MAC1: &[u8] = b"mac1----";
+
+/// Hashed with the sender's static public key to key the encryption of cookie replies.
+pub const LABEL_COOKIE: &[u8] = b"cookie--";
+
+/// The type of handshake initiation packets.
+pub const MESSAGE_INITIATION: u8 = 1;
+
+/// The type of handshake response packets.
+pub const MESSAGE_RESPONSE: u8 = 2;
+
+/// The type of cookie reply packets.
+pub const MESSAGE_COOKIE_REPLY: u8 = 3;
+
+/// The type of transport data packets.
+pub const MESSAGE_TRANSPORT: u8 = 4;
+
+/// The length of each of `mac1` and `mac2`.
+pub const MAC_LEN: usize = 16;
+
+/// The length of a cookie.
+pub const COOKIE_LEN: usize = 16;
+
+/// The length of the random nonce a cookie reply is encrypted with.
+pub const COOKIE_NONCE_LEN: usize = 24;
+
+/// The length of a [`Tai64n`] timestamp.
+pub const TIMESTAMP_LEN: usize = 12;
+
+/// The length of an initiation packet: type, sender index, ephemeral key, encrypted static key,
+/// encrypted timestamp and MACs.
+pub const INITIATION_LEN: usize = 8 + 32 + (32 + TAGLEN) + (TIMESTAMP_LEN + TAGLEN) + 2 * MAC_LEN;
+
+/// The length of a response packet: type, sender and receiver indices, ephemeral key, encrypted
+/// empty payload and MACs.
+pub const RESPONSE_LEN: usize = 12 + 32 + TAGLEN + 2 * MAC_LEN;
+
+/// The length of a cookie reply packet: type, receiver index, nonce and encrypted cookie.
+pub const COOKIE_REPLY_LEN: usize = 8 + COOKIE_NONCE_LEN + COOKIE_LEN + TAGLEN;
+
+/// The TAI64 label of the Unix epoch, as WireGuard counts it: 2^62 plus the 10 seconds TAI was
+/// ahead of UTC in 1970.
+const TAI64_EPOCH: u64 = 0x4000_0000_0000_000a;
+
+/// [`Tai64n::now()`] rounds nanoseconds down to a multiple of this (about 17ms), like WireGuard
+/// does, so timestamps don't leak precise timing.
+const WHITENER: u32 = 1 << 24;
+
+/// The [`CONSTRUCTION`] WireGuard runs.
+pub fn params() -> NoiseParams {
+    CONSTRUCTION.parse().expect("WireGuard's construction is supported")
+}
+
+/// Build the initiator of a handshake with the peer whose static public key is `remote_public`.
+///
+/// `psk` is the peers' preshared key, if they have one; WireGuard uses all zeroes otherwise.
+///
+/// # Errors
+///
+/// Fails like [`Builder::build_initiator()`], e.g. if a key has the wrong length.
+pub fn initiator(
+    local_private: &[u8],
+    remote_public: &[u8],
+    psk: Option<&[u8]>,
+) -> Result<HandshakeState, Error> {
+    Builder::with_resolver(params(), Box::new(DefaultResolver))
+        .prologue(IDENTIFIER)
+        .local_private_key(local_private)
+        .remote_public_key(remote_public)
+        .psk(2, psk.unwrap_or(&[0; 32]))
+        .build_initiator()
+}
+
+/// Build the responder of a handshake.
+///
+/// The PSK isn't needed until the response, so a responder with per-peer PSKs can pass `None`
+/// and [`set_psk(2, ..)`](HandshakeState::set_psk) once [`read_initiation()`] has told it who the
+/// peer is.
+///
+/// # Errors
+///
+/// Fails like [`Builder::build_responder()`], e.g. if a key has the wrong length.
+pub fn responder(local_private: &[u8], psk: Option<&[u8]>) -> Result<HandshakeState, Error> {
+    Builder::with_resolver(params(), Box::new(DefaultResolver))
+        .prologue(IDENTIFIER)
+        .local_private_key(local_private)
+        .psk(2, psk.unwrap_or(&[0; 32]))
+        .build_responder()
+}
+
+/// A TAI64N timestamp, the initiator's handshake payload.
+///
+/// Timestamps compare in chronological order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tai64n([u8; TIMESTAMP_LEN]);
+
+impl Tai64n {
+    /// The current time, with the nanoseconds rounded down like WireGuard does.
+    pub fn now() -> Self {
+        let mut timestamp = Self::from_system_time(SystemTime::now());
+        let nanos =
+            u32::from_be_bytes([timestamp.0[8], timestamp.0[9], timestamp.0[10], timestamp.0[11]]);
+        timestamp.0[8..].copy_from_slice(&(nanos - nanos % WHITENER).to_be_bytes());
+        timestamp
+    }
+
+    /// The timestamp of `time`, or of the Unix epoch if `time` is before it.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut bytes = [0u8; TIMESTAMP_LEN];
+        bytes[..8].copy_from_slice(&(TAI64_EPOCH + since_epoch.as_secs()).to_be_bytes());
+        bytes[8..].copy_from_slice(&since_epoch.subsec_nanos().to_be_bytes());
+        Tai64n(bytes)
+    }
+
+    /// The timestamp encoded in `bytes`.
+    pub fn from_bytes(bytes: [u8; TIMESTAMP_LEN]) -> Self {
+        Tai64n(bytes)
+    }
+
+    /// The encoded timestamp.
+    pub fn as_bytes(&self) -> &[u8; TIMESTAMP_LEN] {
+        &self.0
+    }
+}
+
+/// The fields of an initiation packet, from [`read_initiation()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Initiation {
+    /// The initiator's index for the session.
+    pub sender:    u32,
+    /// The initiator's timestamp.
+    pub timestamp: Tai64n,
+}
+
+/// The fields of a response packet, from [`read_response()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Response {
+    /// The responder's index for the session.
+    pub sender:   u32,
+    /// The initiator's index for the session, from its initiation.
+    pub receiver: u32,
+}
+
+/// Write the handshake initiation packet, with `sender` as the initiator's index for the session.
+///
+/// `cookie` is the last cookie the responder sent, if it was received less than two minutes ago.
+///
+/// # Errors
+///
+/// Fails like [`HandshakeState::write_message()`], and with `Error::Input` if `handshake` isn't
+/// a WireGuard initiator.
+pub fn write_initiation(
+    handshake: &mut HandshakeState,
+    sender: u32,
+    timestamp: Tai64n,
+    cookie: Option<&[u8; COOKIE_LEN]>,
+) -> Result<[u8; INITIATION_LEN], Error> {
+    let remote = static_key(handshake.get_remote_static())?;
+    let mut packet = [0u8; INITIATION_LEN];
+    packet[0] = MESSAGE_INITIATION;
+    packet[4..8].copy_from_slice(&sender.to_le_bytes());
+    let body = &mut packet[8..INITIATION_LEN - 2 * MAC_LEN];
+    if handshake.write_message(timestamp.as_bytes(), body)? != body.len() {
+        bail!(Error::Input);
+    }
+    seal_macs(&mut packet, &remote, cookie);
+    Ok(packet)
+}
+
+/// Read a handshake initiation packet, after checking its `mac1`.
+///
+/// The caller still has to check that the timestamp is later than that of any initiation it has
+/// seen from the peer ([`HandshakeState::get_remote_static()`]), and `mac2` if it's under load.
+///
+/// # Errors
+///
+/// Fails with `Error::Input` if `packet` isn't an initiation, `Error::Decrypt` if its `mac1` is
+/// wrong, and otherwise like [`HandshakeState::read_message()`].
+pub fn read_initiation(handshake: &mut HandshakeState, packet: &[u8]) -> Result<Initiation, Error> {
+    let local = static_key(handshake.get_local_static())?;
+    check_packet(packet, MESSAGE_INITIATION, INITIATION_LEN, &local)?;
+    let mut timestamp = [0u8; TIMESTAMP_LEN];
+    if handshake.read_message(&packet[8..INITIATION_LEN - 2 * MAC_LEN], &mut timestamp)?
+        != TIMESTAMP_LEN
+    {
+        bail!(Error::Input);
+    }
+    Ok(Initiation { sender: index(&packet[4..8]), timestamp: Tai64n(timestamp) })
+}
+
+/// Write the handshake response packet, with `sender` as the responder's index for the session
+/// and `receiver` as the initiator's (from [`Initiation::sender`]).
+///
+/// `cookie` is the last cookie the initiator sent, if it was received less than two minutes ago.
+///
+/// # Errors
+///
+/// Fails like [`HandshakeState::write_message()`], and with `Error::Input` if `handshake` isn't
+/// a WireGuard responder.
+pub fn write_response(
+    handshake: &mut HandshakeState,
+    sender: u32,
+    receiver: u32,
+    cookie: Option<&[u8; COOKIE_LEN]>,
+) -> Result<[u8; RESPONSE_LEN], Error> {
+    let remote = static_key(handshake.get_remote_static())?;
+    let mut packet = [0u8; RESPONSE_LEN];
+    packet[0] = MESSAGE_RESPONSE;
+    packet[4..8].copy_from_slice(&sender.to_le_bytes());
+    packet[8..12].copy_from_slice(&receiver.to_le_bytes());
+    let body = &mut packet[12..RESPONSE_LEN - 2 * MAC_LEN];
+    if handshake.write_message(&[], body)? != body.len() {
+        bail!(Error::Input);
+    }
+    seal_macs(&mut packet, &remote, cookie);
+    Ok(packet)
+}
+
+/// Read a handshake response packet, after checking its `mac1`.
+///
+/// # Errors
+///
+/// Fails with `Error::Input` if `packet` isn't a response, `Error::Decrypt` if its `mac1` is
+/// wrong, and otherwise like [`HandshakeState::read_message()`].
+pub fn read_response(handshake: &mut HandshakeState, packet: &[u8]) -> Result<Response, Error> {
+    let local = static_key(handshake.get_local_static())?;
+    check_packet(packet, MESSAGE_RESPONSE, RESPONSE_LEN, &local)?;
+    if handshake.read_message(&packet[12..RESPONSE_LEN - 2 * MAC_LEN], &mut [])? != 0 {
+        bail!(Error::Input);
+    }
+    Ok(Response { sender: index(&packet[4..8]), receiver: index(&packet[8..12]) })
+}
+
+/// `mac1` of a packet whose bytes up to `mac1` are `message`, sent to the peer with the static
+/// public key `receiver_public`.
+pub fn mac1(receiver_public: &[u8], message: &[u8]) -> [u8; MAC_LEN] {
+    mac(&hash(LABEL_MAC1, receiver_public), message)
+}
+
+/// `mac2` of a packet whose bytes up to `mac2` are `message`.
+pub fn mac2(cookie: &[u8; COOKIE_LEN], message: &[u8]) -> [u8; MAC_LEN] {
+    mac(cookie, message)
+}
+
+/// Whether the `mac2` of `packet` (a whole initiation or response) was made with `cookie`.
+///
+/// A peer under load only answers packets with a valid `mac2`, and sends a cookie reply for the
+/// rest.
+pub fn check_mac2(packet: &[u8], cookie: &[u8; COOKIE_LEN]) -> bool {
+    match packet.len().checked_sub(MAC_LEN) {
+        Some(len) if len >= MAC_LEN => mac2(cookie, &packet[..len]).ct_eq(&packet[len..]).into(),
+        _ => false,
+    }
+}
+
+/// The cookie for the peer at `source` (an encoding of its IP address and port), under the
+/// random `secret`, which WireGuard changes every two minutes.
+pub fn cookie(secret: &[u8; 32], source: &[u8]) -> [u8; COOKIE_LEN] {
+    mac(secret, source)
+}
+
+/// Write a cookie reply to `packet` (an initiation or response with a valid `mac1`), giving its
+/// sender `cookie`.
+///
+/// `local_public` is the static public key of the peer replying, and `nonce` has to be random.
+///
+/// # Errors
+///
+/// Fails with `Error::Input` if `packet` is too short to be an initiation or response.
+pub fn write_cookie_reply(
+    packet: &[u8],
+    local_public: &[u8],
+    cookie: &[u8; COOKIE_LEN],
+    nonce: &[u8; COOKIE_NONCE_LEN],
+) -> Result<[u8; COOKIE_REPLY_LEN], Error> {
+    if packet.len() < RESPONSE_LEN {
+        bail!(Error::Input);
+    }
+    let mut reply = [0u8; COOKIE_REPLY_LEN];
+    reply[0] = MESSAGE_COOKIE_REPLY;
+    reply[4..8].copy_from_slice(&packet[4..8]);
+    reply[8..8 + COOKIE_NONCE_LEN].copy_from_slice(nonce);
+    let (encrypted, tag) = reply[8 + COOKIE_NONCE_LEN..].split_at_mut(COOKIE_LEN);
+    encrypted.copy_from_slice(cookie);
+    let cipher = XChaCha20Poly1305::new(&hash(LABEL_COOKIE, local_public).into());
+    let mac1 = &packet[packet.len() - 2 * MAC_LEN..packet.len() - MAC_LEN];
+    let sealed = cipher
+        .encrypt_in_place_detached(nonce.into(), mac1, encrypted)
+        .map_err(|_| Error::Input)?;
+    tag.copy_from_slice(&sealed);
+    Ok(reply)
+}
+
+/// Read the cookie from a cookie reply to `sent`, the last packet sent to the peer with the
+/// static public key `remote_public`. The reply's receiver index (its bytes 4 to 8, little
+/// endian) says which session `sent` belongs to.
+///
+/// # Errors
+///
+/// Fails with `Error::Input` if `reply` isn't a cookie reply or `sent` is too short, and with
+/// `Error::Decrypt` if the reply wasn't made by the peer in answer to `sent`.
+pub fn read_cookie_reply(
+    reply: &[u8],
+    remote_public: &[u8],
+    sent: &[u8],
+) -> Result<[u8; COOKIE_LEN], Error> {
+    if reply.len() != COOKIE_REPLY_LEN
+        || reply[..4] != [MESSAGE_COOKIE_REPLY, 0, 0, 0]
+        || sent.len() < RESPONSE_LEN
+    {
+        bail!(Error::Input);
+    }
+    let nonce = &reply[8..8 + COOKIE_NONCE_LEN];
+    let (encrypted, tag) = reply[8 + COOKIE_NONCE_LEN..].split_at(COOKIE_LEN);
+    let mut cookie = [0u8; COOKIE_LEN];
+    cookie.copy_from_slice(encrypted);
+    let cipher = XChaCha20Poly1305::new(&hash(LABEL_COOKIE, remote_public).into());
+    let mac1 = &sent[sent.len() - 2 * MAC_LEN..sent.len() - MAC_LEN];
+    cipher
+        .decrypt_in_place_detached(nonce.into(), mac1, &mut cookie, tag.into())
+        .map_err(|_| Error::Decrypt)?;
+    Ok(cookie)
+}
+
+/// Fill in the MACs at the end of `packet`.
+fn seal_macs(packet: &mut [u8], receiver_public: &[u8], cookie: Option<&[u8; COOKIE_LEN]>) {
+    let len = packet.len() - 2 * MAC_LEN;
+    let mac1 = mac1(receiver_public, &packet[..len]);
+    packet[len..len + MAC_LEN].copy_from_slice(&mac1);
+    if let Some(cookie) = cookie {
+        let mac2 = mac2(cookie, &packet[..len + MAC_LEN]);
+        packet[len + MAC_LEN..].copy_from_slice(&mac2);
+    }
+}
+
+/// Check the type, length and `mac1` of a received handshake packet.
+fn check_packet(packet: &[u8], kind: u8, len: usize, local_public: &[u8]) -> Result<(), Error> {
+    if packet.len() != len || packet[..4] != [kind, 0, 0, 0] {
+        bail!(Error::Input);
+    }
+    let mac1_start = len - 2 * MAC_LEN;
+    let expected = mac1(local_public, &packet[..mac1_start]);
+    if !bool::from(expected.ct_eq(&packet[mac1_start..mac1_start + MAC_LEN])) {
+        bail!(Error::Decrypt);
+    }
+    Ok(())
+}
+
+fn static_key(key: Option<&[u8]>) -> Result<[u8; 32], Error> {
+    let mut out = [0u8; 32];
+    match key {
+        Some(key) if key.len() == out.len() => out.copy_from_slice(key),
+        _ => bail!(StateProblem::MissingKeyMaterial),
+    }
+    Ok(out)
+}
+
+fn index(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// BLAKE2s-256 of `label || key`.
+fn hash(label: &[u8], key: &[u8]) -> [u8; 32] {
+    let mut hasher = Blake2s::new();
+    Digest::update(&mut hasher, label);
+    Digest::update(&mut hasher, key);
+    hasher.finalize().into()
+}
+
+/// BLAKE2s keyed with `key`, with a 16-byte output.
+fn mac(key: &[u8], data: &[u8]) -> [u8; MAC_LEN] {
+    let mut mac = VarBlake2s::new_keyed(key, MAC_LEN);
+    Update::update(&mut mac, data);
+    let mut out = [0u8; MAC_LEN];
+    mac.finalize_variable(|result| out.copy_from_slice(result));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn keypair(seed: u8) -> ([u8; 32], [u8; 32]) {
+        let private = [seed; 32];
+        (private, x25519_dalek::x25519(private, x25519_dalek::X25519_BASEPOINT_BYTES))
+    }
+
+    #[test]
+    fn test_handshake_round_trip() {
+        let (i_private, i_public) = keypair(1);
+        let (r_private, r_public) = keypair(2);
+        let psk = [9u8; 32];
+        let mut initiator = initiator(&i_private, &r_public, Some(&psk)).unwrap();
+        let mut responder = responder(&r_private, None).unwrap();
+
+        let timestamp = Tai64n::now();
+        let packet = write_initiation(&mut initiator, 7, timestamp, None).unwrap();
+        assert_eq!(packet[INITIATION_LEN - MAC_LEN..], [0; MAC_LEN]);
+        let mut tampered = packet;
+        tampered[20] ^= 1;
+        assert!(matches!(read_initiation(&mut responder, &tampered), Err(Error::Decrypt)));
+
+        let initiation = read_initiation(&mut responder, &packet).unwrap();
+        assert_eq!(initiation, Initiation { sender: 7, timestamp });
+        assert_eq!(responder.get_remote_static().unwrap(), &i_public);
+        responder.set_psk(2, &psk).unwrap();
+
+        let packet = write_response(&mut responder, 8, 7, None).unwrap();
+        assert_eq!(
+            read_response(&mut initiator, &packet).unwrap(),
+            Response { sender: 8, receiver: 7 }
+        );
+        assert_eq!(initiator.get_handshake_hash(), responder.get_handshake_hash());
+        assert!(initiator.is_handshake_finished() && responder.is_handshake_finished());
+    }
+
+    #[test]
+    fn test_cookie_reply() {
+        let (i_private, i_public) = keypair(1);
+        let (r_private, r_public) = keypair(2);
+        let mut initiator = initiator(&i_private, &r_public, None).unwrap();
+        let mut responder = responder(&r_private, None).unwrap();
+        let cookie = cookie(&[3; 32], b"192.0.2.1:51820");
+
+        let packet = write_initiation(&mut initiator, 1, Tai64n::now(), None).unwrap();
+        assert!(!check_mac2(&packet, &cookie));
+        let reply = write_cookie_reply(&packet, &r_public, &cookie, &[4; 24]).unwrap();
+        assert_eq!(reply[4..8], 1u32.to_le_bytes());
+        assert!(matches!(read_cookie_reply(&reply, &i_public, &packet), Err(Error::Decrypt)));
+        assert_eq!(read_cookie_reply(&reply, &r_public, &packet).unwrap(), cookie);
+
+        let mut initiator = self::initiator(&i_private, &r_public, None).unwrap();
+        let packet = write_initiation(&mut initiator, 1, Tai64n::now(), Some(&cookie)).unwrap();
+        assert!(check_mac2(&packet, &cookie));
+        assert!(read_initiation(&mut responder, &packet).is_ok());
+    }
+
+    /// BLAKE2s over the concatenation of `parts`.
+    fn blake2s(parts: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = Blake2s::new();
+        for part in parts {
+            Digest::update(&mut hasher, part);
+        }
+        hasher.finalize().into()
+    }
+
+    /// HMAC-BLAKE2s, for a key no longer than the block.
+    fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut block = [0u8; 64];
+        block[..key.len()].copy_from_slice(key);
+        let ipad: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+        let opad: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+        blake2s(&[&opad, &blake2s(&[&ipad, data])])
+    }
+
+    /// The first two outputs of the whitepaper's `KDF_n(key, input)`.
+    fn kdf2(key: &[u8], input: &[u8]) -> ([u8; 32], [u8; 32]) {
+        let prk = hmac(key, input);
+        let first = hmac(&prk, &[1]);
+        let second = hmac(&prk, &[&first[..], &[2]].concat());
+        (first, second)
+    }
+
+    /// ChaCha20-Poly1305 with a zero counter, which is all the initiation uses.
+    fn aead(key: &[u8; 32], plaintext: &[u8], ad: &[u8]) -> Vec<u8> {
+        let mut out = plaintext.to_vec();
+        let tag = chacha20poly1305::ChaCha20Poly1305::new(key.into())
+            .encrypt_in_place_detached(&[0; 12].into(), ad, &mut out)
+            .unwrap();
+        out.extend_from_slice(&tag);
+        out
+    }
+
+    /// The initiation packet as section 5.4.2 of the whitepaper computes it, step by step.
+    fn reference_initiation(
+        i_private: [u8; 32],
+        r_public: [u8; 32],
+        e_private: [u8; 32],
+        sender: u32,
+        timestamp: &[u8; TIMESTAMP_LEN],
+    ) -> Vec<u8> {
+        let dh = |private, public| x25519_dalek::x25519(private, public);
+        let i_public = dh(i_private, x25519_dalek::X25519_BASEPOINT_BYTES);
+        let e_public = dh(e_private, x25519_dalek::X25519_BASEPOINT_BYTES);
+
+        let c = blake2s(&[CONSTRUCTION.as_bytes()]);
+        let h = blake2s(&[&c, IDENTIFIER]);
+        let h = blake2s(&[&h, &r_public]);
+        let (c, _) = kdf2(&c, &e_public);
+        let h = blake2s(&[&h, &e_public]);
+        let (c, k) = kdf2(&c, &dh(e_private, r_public));
+        let encrypted_static = aead(&k, &i_public, &h);
+        let h = blake2s(&[&h, &encrypted_static]);
+        let (_, k) = kdf2(&c, &dh(i_private, r_public));
+        let encrypted_timestamp = aead(&k, timestamp, &h);
+
+        let mut packet = vec![MESSAGE_INITIATION, 0, 0, 0];
+        packet.extend_from_slice(&sender.to_le_bytes());
+        packet.extend_from_slice(&e_public);
+        packet.extend_from_slice(&encrypted_static);
+        packet.extend_from_slice(&encrypted_timestamp);
+        let mut mac1 = VarBlake2s::new_keyed(&blake2s(&[LABEL_MAC1, &r_public]), MAC_LEN);
+        Update::update(&mut mac1, &packet);
+        mac1.finalize_variable(|result| packet.extend_from_slice(result));
+        packet.extend_from_slice(&[0; MAC_LEN]);
+        packet
+    }
+
+    #[test]
+    fn test_initiation_matches_whitepaper() {
+        let (i_private, _) = keypair(1);
+        let (_, r_public) = keypair(2);
+        let mut initiator = Builder::new(params())
+            .prologue(IDENTIFIER)
+            .local_private_key(&i_private)
+            .remote_public_key(&r_public)
+            .psk(2, &[0; 32])
+            .fixed_ephemeral_key_for_testing_only(&[5; 32])
+            .build_initiator()
+            .unwrap();
+        let mut timestamp = [0u8; TIMESTAMP_LEN];
+        for (i, byte) in timestamp.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let packet =
+            write_initiation(&mut initiator, 7, Tai64n::from_bytes(timestamp), None).unwrap();
+        let expected = reference_initiation(i_private, r_public, [5; 32], 7, &timestamp);
+        assert_eq!(expected.len(), INITIATION_LEN);
+        assert_eq!(hex::encode(&packet[..]), hex::encode(expected));
+    }
+
+    #[test]
+    fn test_tai64n() {
+        let time = UNIX_EPOCH + Duration::new(1_500_000_000, 123_456_789);
+        let timestamp = Tai64n::from_system_time(time);
+        assert_eq!(timestamp.as_bytes()[..8], (TAI64_EPOCH + 1_500_000_000).to_be_bytes());
+        assert_eq!(timestamp.as_bytes()[8..], 123_456_789u32.to_be_bytes());
+        assert!(Tai64n::from_system_time(time + Duration::from_secs(1)) > timestamp);
+        assert_eq!(
+            Tai64n::from_system_time(UNIX_EPOCH - Duration::from_secs(1)).as_bytes()[..8],
+            TAI64_EPOCH.to_be_bytes()
+        );
+
+        let mut nanos = [0u8; 4];
+        nanos.copy_from_slice(&Tai64n::now().as_bytes()[8..]);
+        assert_eq!(u32::from_be_bytes(nanos) % WHITENER, 0);
+    }
+}
@@ -0,0 +1,275 @@
+//! Canonical encoding of negotiation inputs into the handshake prologue.
+//!
+//! Anything both sides used to decide how to talk to each other (the protocols they support,
+//! version numbers, the addresses they connected over) should be mixed in to the handshake as
+//! its prologue, so that an attacker who tampers with the negotiation causes the handshake to
+//! fail. [`PrologueBuilder`] encodes these inputs canonically: entries are sorted and
+//! deduplicated, so both sides produce identical bytes regardless of the order they add them in.
+//!
+//! When the negotiation is a conversation rather than a set of values, a [`Transcript`] records
+//! every message exchanged before the handshake instead, in order and tagged with the side that
+//! sent it, so that both sides bind exactly what went over the wire.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, prologue::PrologueBuilder};
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let prologue = PrologueBuilder::new(b"my-app")
+//!     .protocol("Noise_NN_25519_ChaChaPoly_BLAKE2s")
+//!     .protocol("Noise_NN_25519_AESGCM_SHA256")
+//!     .version(2)
+//!     .build();
+//!
+//! let noise = Builder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse()?)
+//!     .prologue(prologue.as_bytes())
+//!     .build_initiator()?;
+//!
+//! // If the handshake fails, the peer's encoded prologue can be compared to tell a
+//! // negotiation mismatch apart from other failures.
+//! # let peer_prologue = prologue.clone();
+//! prologue.verify(peer_prologue.as_bytes())?;
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # fn main() { try_main().unwrap(); }
+//! ```
+
+use crate::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use subtle::ConstantTimeEq;
+
+const TAG_PROTOCOL: u8 = 1;
+const TAG_VERSION: u8 = 2;
+const TAG_INITIATOR_ADDRESS: u8 = 3;
+const TAG_RESPONDER_ADDRESS: u8 = 4;
+const TAG_FIELD: u8 = 5;
+const TAG_INITIATOR_MESSAGE: u8 = 6;
+const TAG_RESPONDER_MESSAGE: u8 = 7;
+
+/// Collects negotiation inputs and encodes them into a [`Prologue`].
+#[derive(Clone, Debug)]
+pub struct PrologueBuilder {
+    context: Vec<u8>,
+    entries: Vec<(u8, Vec<u8>)>,
+}
+
+impl PrologueBuilder {
+    /// Create a builder for the given application context label, which is always encoded first.
+    pub fn new(context: &[u8]) -> Self {
+        PrologueBuilder { context: context.to_vec(), entries: vec![] }
+    }
+
+    /// Bind a supported protocol name.
+    pub fn protocol(self, name: &str) -> Self {
+        self.entry(TAG_PROTOCOL, name.as_bytes().to_vec())
+    }
+
+    /// Bind a list of supported protocol names.
+    pub fn protocols<'a>(self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        names.into_iter().fold(self, |builder, name| builder.protocol(name))
+    }
+
+    /// Bind a supported application protocol version.
+    pub fn version(self, version: u32) -> Self {
+        self.entry(TAG_VERSION, version.to_be_bytes().to_vec())
+    }
+
+    /// Bind the network address of the initiator.
+    pub fn initiator_address(self, address: SocketAddr) -> Self {
+        self.entry(TAG_INITIATOR_ADDRESS, encode_address(address))
+    }
+
+    /// Bind the network address of the responder.
+    pub fn responder_address(self, address: SocketAddr) -> Self {
+        self.entry(TAG_RESPONDER_ADDRESS, encode_address(address))
+    }
+
+    /// Bind an arbitrary application-defined value under `label`.
+    pub fn field(self, label: &str, value: &[u8]) -> Self {
+        let mut encoded = Vec::with_capacity(2 + label.len() + value.len());
+        push_length_prefixed(&mut encoded, label.as_bytes());
+        encoded.extend_from_slice(value);
+        self.entry(TAG_FIELD, encoded)
+    }
+
+    /// Encode the collected inputs.
+    pub fn build(mut self) -> Prologue {
+        self.entries.sort();
+        self.entries.dedup();
+
+        let mut bytes = Vec::new();
+        push_length_prefixed(&mut bytes, &self.context);
+        for (tag, value) in &self.entries {
+            bytes.push(*tag);
+            push_length_prefixed(&mut bytes, value);
+        }
+        Prologue { bytes }
+    }
+
+    fn entry(mut self, tag: u8, value: Vec<u8>) -> Self {
+        self.entries.push((tag, value));
+        self
+    }
+}
+
+/// An encoded prologue, ready to be passed to [`Builder::prologue()`](crate::Builder::prologue).
+#[derive(Clone, PartialEq, Debug)]
+pub struct Prologue {
+    bytes: Vec<u8>,
+}
+
+impl Prologue {
+    /// The encoded prologue.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Check the peer's encoded prologue against this one.
+    ///
+    /// A mismatched prologue otherwise only shows up as a decryption failure partway through
+    /// the handshake, so this is useful for telling it apart from other failures.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Prologue` if the prologues differ.
+    pub fn verify(&self, peer: &[u8]) -> Result<(), Error> {
+        if !bool::from(self.bytes.ct_eq(peer)) {
+            bail!(Error::Prologue);
+        }
+        Ok(())
+    }
+}
+
+impl AsRef<[u8]> for Prologue {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Records the messages exchanged before the handshake, to bind them into its prologue.
+///
+/// Each side records what it sent and what it received, in the order it happened; both end up
+/// with the same [`Prologue`] as long as nothing was tampered with. If the handshake then fails,
+/// comparing the prologues with [`Prologue::verify()`] tells whether the negotiation was
+/// tampered with.
+///
+/// # Examples
+///
+/// ```
+/// # use snow::prologue::Transcript;
+/// let mut client = Transcript::new(b"my-app", true);
+/// let mut server = Transcript::new(b"my-app", false);
+///
+/// client.sent(b"HELLO v1 v2");
+/// server.received(b"HELLO v1 v2");
+/// server.sent(b"USE v2");
+/// client.received(b"USE v2");
+///
+/// assert_eq!(client.prologue(), server.prologue());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Transcript {
+    initiator: bool,
+    bytes:     Vec<u8>,
+}
+
+impl Transcript {
+    /// Start a transcript for the given application context label, as the side that will be the
+    /// handshake's initiator or responder.
+    pub fn new(context: &[u8], initiator: bool) -> Self {
+        let mut bytes = Vec::new();
+        push_length_prefixed(&mut bytes, context);
+        Transcript { initiator, bytes }
+    }
+
+    /// Record a message this side sent.
+    pub fn sent(&mut self, message: &[u8]) -> &mut Self {
+        self.record(self.initiator, message)
+    }
+
+    /// Record a message the other side sent.
+    pub fn received(&mut self, message: &[u8]) -> &mut Self {
+        self.record(!self.initiator, message)
+    }
+
+    /// The prologue binding everything recorded so far.
+    pub fn prologue(&self) -> Prologue {
+        Prologue { bytes: self.bytes.clone() }
+    }
+
+    fn record(&mut self, from_initiator: bool, message: &[u8]) -> &mut Self {
+        self.bytes.push(if from_initiator { TAG_INITIATOR_MESSAGE } else { TAG_RESPONDER_MESSAGE });
+        self.bytes.extend_from_slice(&(message.len() as u64).to_be_bytes());
+        self.bytes.extend_from_slice(message);
+        self
+    }
+}
+
+fn encode_address(address: SocketAddr) -> Vec<u8> {
+    let mut encoded = match address.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    encoded.extend_from_slice(&address.port().to_be_bytes());
+    encoded
+}
+
+fn push_length_prefixed(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_order() {
+        let a = PrologueBuilder::new(b"ctx").protocol("b").version(1).protocol("a").build();
+        let b = PrologueBuilder::new(b"ctx").protocols(vec!["a", "b", "a"]).version(1).build();
+        assert_eq!(a, b);
+        assert_eq!(
+            a.as_bytes(),
+            b"\x00\x03ctx\x01\x00\x01a\x01\x00\x01b\x02\x00\x04\x00\x00\x00\x01"
+        );
+    }
+
+    #[test]
+    fn test_transcript() {
+        let mut client = Transcript::new(b"ctx", true);
+        let mut server = Transcript::new(b"ctx", false);
+        client.sent(b"ab").received(b"c");
+        server.received(b"ab").sent(b"c");
+        assert_eq!(client.prologue(), server.prologue());
+        assert_eq!(
+            client.prologue().as_bytes(),
+            &b"\x00\x03ctx\x06\0\0\0\0\0\0\0\x02ab\x07\0\0\0\0\0\0\0\x01c"[..]
+        );
+
+        // Message boundaries and directions are bound, not just the bytes.
+        let mut split = Transcript::new(b"ctx", false);
+        split.received(b"a").received(b"b").sent(b"c");
+        let mut reflected = Transcript::new(b"ctx", false);
+        reflected.sent(b"ab").received(b"c");
+        assert!(matches!(
+            split.prologue().verify(client.prologue().as_bytes()),
+            Err(Error::Prologue)
+        ));
+        assert!(matches!(
+            reflected.prologue().verify(client.prologue().as_bytes()),
+            Err(Error::Prologue)
+        ));
+    }
+
+    #[test]
+    fn test_verify() {
+        let address = "127.0.0.1:4000".parse().unwrap();
+        let ours = PrologueBuilder::new(b"ctx").initiator_address(address).build();
+        let theirs = PrologueBuilder::new(b"ctx").responder_address(address).build();
+        assert!(ours.verify(ours.as_bytes()).is_ok());
+        assert!(matches!(ours.verify(theirs.as_bytes()), Err(Error::Prologue)));
+    }
+}
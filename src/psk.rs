@@ -0,0 +1,106 @@
+//! Deriving PSKs from passphrases, for "password-protected" links.
+//!
+//! A passphrase has far less entropy than a random 32-byte key, so using something like
+//! `sha256(passphrase)` as the PSK lets anyone who records a handshake brute-force the
+//! passphrase offline at hashing speed. [`derive_psk()`] stretches it with Argon2id instead,
+//! which makes every guess cost a configurable amount of memory and time.
+//!
+//! Both peers have to use the same salt and cost. The salt doesn't need to be secret, but it
+//! should be unique to the link (e.g. random bytes stored alongside its configuration), so
+//! that guesses against one link don't carry over to another.
+//!
+//! Requires the `argon2` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::psk::{derive_psk, PassphraseCost};
+//! let salt = b"link 7 salt, 16+";
+//! let psk = derive_psk(b"correct horse battery staple", salt, PassphraseCost::default()).unwrap();
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! let noise = snow::Builder::new("Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+//!     .psk(0, &psk)
+//!     .build_initiator()
+//!     .unwrap();
+//! ```
+
+use crate::{constants::PSKLEN, Error};
+use argon2::{Algorithm, Argon2, Params, Version};
+
+/// The shortest salt [`derive_psk()`] accepts.
+pub const MIN_SALT_LEN: usize = argon2::MIN_SALT_LEN;
+
+/// How expensive deriving a PSK from a passphrase is, in Argon2id terms.
+///
+/// The default (19 MiB, 2 passes, 1 lane) follows the OWASP recommendation for Argon2id. Raise
+/// it as far as the slowest peer tolerates; lowering it only makes sense for tests.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PassphraseCost {
+    /// Memory to use, in KiB.
+    pub memory_kib: u32,
+
+    /// Number of passes over the memory.
+    pub iterations: u32,
+
+    /// Degree of parallelism (lanes).
+    pub parallelism: u32,
+}
+
+impl Default for PassphraseCost {
+    fn default() -> Self {
+        PassphraseCost {
+            memory_kib:  Params::DEFAULT_M_COST,
+            iterations:  Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Derive a PSK from `passphrase` and `salt` with Argon2id.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if the salt is shorter than [`MIN_SALT_LEN`] or the cost is
+/// outside of what Argon2 allows (e.g. less than 8 KiB of memory per lane).
+pub fn derive_psk(
+    passphrase: &[u8],
+    salt: &[u8],
+    cost: PassphraseCost,
+) -> Result<[u8; PSKLEN], Error> {
+    if salt.len() < MIN_SALT_LEN {
+        bail!(Error::Input);
+    }
+    let params = Params::new(cost.memory_kib, cost.iterations, cost.parallelism, Some(PSKLEN))
+        .map_err(|_| Error::Input)?;
+    let mut psk = [0u8; PSKLEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut psk)
+        .map_err(|_| Error::Input)?;
+    Ok(psk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHEAP: PassphraseCost =
+        PassphraseCost { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    #[test]
+    fn test_derive_psk() {
+        let psk = derive_psk(b"hunter2", b"saltsalt", CHEAP).unwrap();
+        assert_eq!(psk, derive_psk(b"hunter2", b"saltsalt", CHEAP).unwrap());
+        assert_ne!(psk, derive_psk(b"hunter3", b"saltsalt", CHEAP).unwrap());
+        assert_ne!(psk, derive_psk(b"hunter2", b"saltsalT", CHEAP).unwrap());
+
+        let costlier = PassphraseCost { iterations: 2, ..CHEAP };
+        assert_ne!(psk, derive_psk(b"hunter2", b"saltsalt", costlier).unwrap());
+    }
+
+    #[test]
+    fn test_derive_psk_rejects_bad_input() {
+        assert!(matches!(derive_psk(b"hunter2", b"salt", CHEAP), Err(Error::Input)));
+        let cost = PassphraseCost { memory_kib: 1, ..CHEAP };
+        assert!(matches!(derive_psk(b"hunter2", b"saltsalt", cost), Err(Error::Input)));
+    }
+}
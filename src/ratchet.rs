@@ -0,0 +1,462 @@
+//! Post-compromise security for long-lived sessions, by ratcheting fresh DH outputs into the
+//! transport keys.
+//!
+//! A plain [`rekey`](crate::TransportState::rekey_outgoing) only hashes the current key forward:
+//! whoever learned it can follow along. A [`RatchetTransport`] instead has the peers run a fresh
+//! ephemeral DH exchange inside the transport every so often, and mixes its output into a root
+//! key derived from the handshake, like the DH ratchet of Signal's double ratchet. Once a ratchet
+//! completes, an attacker who stole the keys before it (but doesn't control the traffic) is
+//! locked out again.
+//!
+//! Every payload starts with a frame type byte, so the ratchet's messages travel alongside data
+//! over the same transport:
+//!
+//! 1. Either side sends an *offer* with a new ephemeral public key.
+//! 2. The peer answers with its own new ephemeral public key, still under the old keys, and
+//!    starts sending under the new ones.
+//! 3. The offering side switches to receiving under the new keys, and sends a *confirm* under the
+//!    old sending key before switching that too, so the peer knows when to follow.
+//!
+//! [`RatchetTransport::read_message()`] handles these messages itself; whenever
+//! [`RatchetTransport::needs_ratchet_message()`] says so, the next message to send has to be
+//! [`RatchetTransport::write_ratchet()`]. If both sides offer at once, the offer of the
+//! handshake's initiator wins. Like the transport underneath, this relies on messages being
+//! delivered in order.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::ratchet::*;
+//! # #[cfg(feature = "default-resolver")]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let (mut i, mut r) = snow::doctest::nn_handshake()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! // Wrap each side's finished handshake.
+//! let mut alice = RatchetTransport::new(i)?;
+//! let mut bob = RatchetTransport::new(r)?;
+//!
+//! // Alice offers a ratchet, Bob answers and Alice confirms.
+//! let len = alice.write_ratchet(&mut msg)?;
+//! assert_eq!(bob.read_message(&msg[..len], &mut buf)?, Received::Ratchet);
+//! let len = bob.write_ratchet(&mut msg)?;
+//! assert_eq!(alice.read_message(&msg[..len], &mut buf)?, Received::Ratchet);
+//! let len = alice.write_ratchet(&mut msg)?;
+//! assert_eq!(bob.read_message(&msg[..len], &mut buf)?, Received::Ratchet);
+//! assert_eq!((alice.epoch(), bob.epoch()), (1, 1));
+//!
+//! let len = alice.write_message(b"hello", &mut msg)?;
+//! assert_eq!(bob.read_message(&msg[..len], &mut buf)?, Received::Data(5));
+//! assert_eq!(&buf[..5], b"hello");
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(feature = "default-resolver"))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::{CIPHERKEYLEN, MAXDHLEN, MAXHASHLEN},
+    error::{Error, InitStage, StateProblem},
+    handshakestate::HandshakeState,
+    resolvers::BoxedCryptoResolver,
+    types::{Dh, Hash, Random},
+    TransportState,
+};
+use std::{fmt, io::IoSlice};
+
+/// How many messages are sent between ratchets, unless configured otherwise.
+pub const DEFAULT_RATCHET_INTERVAL: u64 = 1 << 16;
+
+const ROOT_LABEL: &[u8] = b"snow ratchet root";
+
+const FRAME_DATA: u8 = 0;
+const FRAME_OFFER: u8 = 1;
+const FRAME_ANSWER: u8 = 2;
+const FRAME_CONFIRM: u8 = 3;
+
+/// What [`RatchetTransport::read_message()`] made of an incoming message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Received {
+    /// Application data, whose length is given.
+    Data(usize),
+
+    /// A ratchet message, which carries nothing for the application.
+    Ratchet,
+}
+
+/// Where this side is in a ratchet.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Phase {
+    Idle,
+    /// We offered our ephemeral key and wait for the answer.
+    Offered,
+    /// The peer offered this key, and we owe it an answer.
+    Answering([u8; MAXDHLEN]),
+    /// We answered and send under the new key; the peer's confirm switches our receiving key.
+    AwaitingConfirm([u8; CIPHERKEYLEN]),
+    /// We got the answer and receive under the new key; we owe the peer a confirm, after which
+    /// we switch our sending key.
+    Confirming([u8; CIPHERKEYLEN]),
+}
+
+/// A [`TransportState`] whose keys are ratcheted forward with fresh DH exchanges.
+pub struct RatchetTransport {
+    transport: TransportState,
+    dh:        Box<dyn Dh>,
+    hash:      Box<dyn Hash>,
+    rng:       Box<dyn Random>,
+    root:      [u8; MAXHASHLEN],
+    phase:     Phase,
+    epoch:     u64,
+    sent:      u64,
+    interval:  u64,
+}
+
+impl RatchetTransport {
+    /// Wrap the transport of a finished handshake, using the default crypto resolver.
+    #[cfg(feature = "default-resolver")]
+    pub fn new(handshake: HandshakeState) -> Result<Self, Error> {
+        Self::with_resolver(handshake, Box::new(crate::resolvers::DefaultResolver))
+    }
+
+    /// Wrap the transport of a finished handshake, using a custom crypto resolver for the
+    /// ratchet's DH exchanges and key derivation.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State` if the handshake isn't finished, and `Error::Init` if the
+    /// resolver doesn't support the handshake's DH or hash function.
+    pub fn with_resolver(
+        mut handshake: HandshakeState,
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        if !handshake.is_handshake_finished() {
+            bail!(StateProblem::HandshakeNotFinished);
+        }
+        let dh = resolver.resolve_dh(&handshake.params.dh).ok_or(InitStage::GetDhImpl)?;
+        let hash = resolver.resolve_hash(&handshake.params.hash).ok_or(InitStage::GetHashImpl)?;
+        let rng = resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
+        let mut root = [0u8; MAXHASHLEN];
+        handshake.symmetricstate.derive_secret(ROOT_LABEL, &mut root[..hash.hash_len()]);
+        Ok(RatchetTransport {
+            transport: handshake.into_transport_mode()?,
+            dh,
+            hash,
+            rng,
+            root,
+            phase: Phase::Idle,
+            epoch: 0,
+            sent: 0,
+            interval: DEFAULT_RATCHET_INTERVAL,
+        })
+    }
+
+    /// Set how many messages to send before [`should_ratchet()`](Self::should_ratchet) suggests
+    /// starting a ratchet.
+    pub fn ratchet_interval(mut self, messages: u64) -> Self {
+        self.interval = messages;
+        self
+    }
+
+    /// The wrapped transport.
+    pub fn transport(&self) -> &TransportState {
+        &self.transport
+    }
+
+    /// How many ratchets have completed on this side.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Whether a ratchet is under way.
+    pub fn is_ratcheting(&self) -> bool {
+        self.phase != Phase::Idle
+    }
+
+    /// Whether the peer is waiting on our [`write_ratchet()`](Self::write_ratchet) to continue a
+    /// ratchet.
+    pub fn needs_ratchet_message(&self) -> bool {
+        matches!(self.phase, Phase::Answering(_) | Phase::Confirming(_))
+    }
+
+    /// Whether [`write_ratchet()`](Self::write_ratchet) should be called: either the peer is
+    /// waiting on it, or enough messages were sent since the last ratchet to start a new one.
+    pub fn should_ratchet(&self) -> bool {
+        self.needs_ratchet_message() || (self.phase == Phase::Idle && self.sent >= self.interval)
+    }
+
+    /// Encrypt application data. See [`TransportState::write_message()`].
+    ///
+    /// The payload gets one byte longer on the wire, for the frame type.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TransportState::write_message()`].
+    pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        let data = [IoSlice::new(&[FRAME_DATA]), IoSlice::new(payload)];
+        let len = self.transport.write_message_vectored(&data, message)?;
+        self.sent += 1;
+        Ok(len)
+    }
+
+    /// Write the next ratchet message: an offer that starts a ratchet, or the answer or confirm
+    /// the peer is waiting on.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::NotTurnToWrite)` if we're waiting on the peer,
+    /// `Error::Rng` or `Error::Dh` if generating the key or the exchange fails, and otherwise
+    /// fails like [`TransportState::write_message()`].
+    pub fn write_ratchet(&mut self, message: &mut [u8]) -> Result<usize, Error> {
+        match self.phase {
+            Phase::Idle => {
+                self.dh.generate(&mut *self.rng).map_err(|_| Error::Rng)?;
+                let offer = [IoSlice::new(&[FRAME_OFFER]), IoSlice::new(self.dh.pubkey())];
+                let len = self.transport.write_message_vectored(&offer, message)?;
+                self.phase = Phase::Offered;
+                Ok(len)
+            },
+            Phase::Answering(offer) => {
+                self.dh.generate(&mut *self.rng).map_err(|_| Error::Rng)?;
+                let (outgoing, incoming) = self.derive_keys(&offer[..self.dh.pub_len()])?;
+                let answer = [IoSlice::new(&[FRAME_ANSWER]), IoSlice::new(self.dh.pubkey())];
+                let len = self.transport.write_message_vectored(&answer, message)?;
+                self.set_outgoing_key(&outgoing);
+                self.phase = Phase::AwaitingConfirm(incoming);
+                Ok(len)
+            },
+            Phase::Confirming(outgoing) => {
+                let confirm = [IoSlice::new(&[FRAME_CONFIRM])];
+                let len = self.transport.write_message_vectored(&confirm, message)?;
+                self.set_outgoing_key(&outgoing);
+                self.finish();
+                Ok(len)
+            },
+            Phase::Offered | Phase::AwaitingConfirm(_) => bail!(StateProblem::NotTurnToWrite),
+        }
+    }
+
+    /// Decrypt an incoming message, handling it if it's part of a ratchet.
+    ///
+    /// `payload` needs room for the frame type byte and, for ratchet messages, a public key.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the message isn't a valid frame or a ratchet message
+    /// comes out of turn, and otherwise fails like [`TransportState::read_message()`].
+    pub fn read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<Received, Error> {
+        let len = self.transport.read_message(message, payload)?;
+        let (&frame, body) = payload[..len].split_first().ok_or(Error::Input)?;
+        let pub_len = self.dh.pub_len();
+        match (frame, self.phase) {
+            (FRAME_DATA, _) => {
+                payload.copy_within(1..len, 0);
+                return Ok(Received::Data(len - 1));
+            },
+            // When both sides offer at once, the initiator's offer wins.
+            (FRAME_OFFER, Phase::Offered) if self.transport.is_initiator() => {},
+            (FRAME_OFFER, Phase::Idle | Phase::Offered) if body.len() == pub_len => {
+                let mut offer = [0u8; MAXDHLEN];
+                offer[..pub_len].copy_from_slice(body);
+                self.phase = Phase::Answering(offer);
+            },
+            (FRAME_ANSWER, Phase::Offered) if body.len() == pub_len => {
+                let (outgoing, incoming) = self.derive_keys(body)?;
+                self.set_incoming_key(&incoming);
+                self.phase = Phase::Confirming(outgoing);
+            },
+            (FRAME_CONFIRM, Phase::AwaitingConfirm(incoming)) if body.is_empty() => {
+                self.set_incoming_key(&incoming);
+                self.finish();
+            },
+            _ => bail!(Error::Input),
+        }
+        Ok(Received::Ratchet)
+    }
+
+    /// Give up the ratchet and get back the transport, with its current keys.
+    pub fn into_inner(self) -> TransportState {
+        self.transport
+    }
+
+    /// Mix the DH between our ephemeral key and the peer's into the root key, returning our new
+    /// (outgoing, incoming) cipher keys.
+    fn derive_keys(
+        &mut self,
+        remote: &[u8],
+    ) -> Result<([u8; CIPHERKEYLEN], [u8; CIPHERKEYLEN]), Error> {
+        let mut dh_out = [0u8; MAXDHLEN];
+        self.dh.dh(remote, &mut dh_out).map_err(|_| Error::Dh)?;
+        let hash_len = self.hash.hash_len();
+        let root = self.root;
+        let (mut next_root, mut initiator, mut responder) =
+            ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        self.hash.hkdf(
+            &root[..hash_len],
+            &dh_out[..self.dh.shared_len()],
+            3,
+            &mut next_root,
+            &mut initiator,
+            &mut responder,
+        );
+        self.root = next_root;
+
+        let (mut initiator_key, mut responder_key) = ([0u8; CIPHERKEYLEN], [0u8; CIPHERKEYLEN]);
+        initiator_key.copy_from_slice(&initiator[..CIPHERKEYLEN]);
+        responder_key.copy_from_slice(&responder[..CIPHERKEYLEN]);
+        Ok(if self.transport.is_initiator() {
+            (initiator_key, responder_key)
+        } else {
+            (responder_key, initiator_key)
+        })
+    }
+
+    fn set_outgoing_key(&mut self, key: &[u8]) {
+        if self.transport.is_initiator() {
+            self.transport.rekey_initiator_manually(key);
+        } else {
+            self.transport.rekey_responder_manually(key);
+        }
+    }
+
+    fn set_incoming_key(&mut self, key: &[u8]) {
+        if self.transport.is_initiator() {
+            self.transport.rekey_responder_manually(key);
+        } else {
+            self.transport.rekey_initiator_manually(key);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.phase = Phase::Idle;
+        self.epoch += 1;
+        self.sent = 0;
+    }
+}
+
+impl fmt::Debug for RatchetTransport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("RatchetTransport")
+            .field("transport", &self.transport)
+            .field("epoch", &self.epoch)
+            .field("ratcheting", &self.is_ratcheting())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "default-resolver"))]
+mod tests {
+    use super::*;
+    use crate::doctest::nn_handshake;
+
+    fn ratchet_pair() -> (RatchetTransport, RatchetTransport) {
+        let (i, r) = nn_handshake().unwrap();
+        (RatchetTransport::new(i).unwrap(), RatchetTransport::new(r).unwrap())
+    }
+
+    fn send(from: &mut RatchetTransport, to: &mut RatchetTransport, data: &[u8]) {
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = from.write_message(data, &mut msg).unwrap();
+        assert_eq!(to.read_message(&msg[..len], &mut buf).unwrap(), Received::Data(data.len()));
+        assert_eq!(&buf[..data.len()], data);
+    }
+
+    fn relay_ratchet(from: &mut RatchetTransport, to: &mut RatchetTransport) {
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = from.write_ratchet(&mut msg).unwrap();
+        assert_eq!(to.read_message(&msg[..len], &mut buf).unwrap(), Received::Ratchet);
+    }
+
+    #[test]
+    fn test_ratchet_interleaved_with_data() {
+        let (mut i, mut r) = ratchet_pair();
+        for _ in 0..2 {
+            // The responder starts this time, and data keeps flowing both ways in between.
+            relay_ratchet(&mut r, &mut i);
+            send(&mut r, &mut i, b"after offer");
+            send(&mut i, &mut r, b"before answer");
+            assert!(i.needs_ratchet_message());
+            relay_ratchet(&mut i, &mut r);
+            send(&mut i, &mut r, b"after answer");
+            send(&mut r, &mut i, b"before confirm");
+            relay_ratchet(&mut r, &mut i);
+            send(&mut r, &mut i, b"after confirm");
+            send(&mut i, &mut r, b"after confirm");
+            assert!(!i.is_ratcheting() && !r.is_ratcheting());
+        }
+        assert_eq!((i.epoch(), r.epoch()), (2, 2));
+    }
+
+    #[test]
+    fn test_handshake_keys_are_retired() {
+        use crate::{params::CipherChoice, resolvers::CryptoResolver};
+
+        let (mut i, r) = nn_handshake().unwrap();
+        // Someone who stole the keys the handshake ended with...
+        let (mut initiator_key, mut responder_key) = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        i.symmetricstate.split_raw(&mut initiator_key, &mut responder_key);
+        let mut stolen =
+            crate::resolvers::DefaultResolver.resolve_cipher(&CipherChoice::ChaChaPoly).unwrap();
+        stolen.set(&initiator_key[..CIPHERKEYLEN]);
+
+        let (mut i, mut r) = (RatchetTransport::new(i).unwrap(), RatchetTransport::new(r).unwrap());
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = i.write_message(b"before", &mut msg).unwrap();
+        assert!(stolen.decrypt(0, &[], &msg[..len], &mut buf).is_ok());
+        r.read_message(&msg[..len], &mut buf).unwrap();
+
+        // ...can't follow the session past a ratchet.
+        relay_ratchet(&mut i, &mut r);
+        relay_ratchet(&mut r, &mut i);
+        relay_ratchet(&mut i, &mut r);
+        let len = i.write_message(b"after", &mut msg).unwrap();
+        let nonce = i.transport().sending_nonce() - 1;
+        assert!(stolen.decrypt(nonce, &[], &msg[..len], &mut buf).is_err());
+        assert_eq!(r.read_message(&msg[..len], &mut buf).unwrap(), Received::Data(5));
+    }
+
+    #[test]
+    fn test_simultaneous_offers() {
+        let (mut i, mut r) = ratchet_pair();
+        let (mut msg_i, mut msg_r, mut buf) = ([0u8; 1024], [0u8; 1024], [0u8; 1024]);
+        let len_i = i.write_ratchet(&mut msg_i).unwrap();
+        let len_r = r.write_ratchet(&mut msg_r).unwrap();
+        assert_eq!(i.read_message(&msg_r[..len_r], &mut buf).unwrap(), Received::Ratchet);
+        assert_eq!(r.read_message(&msg_i[..len_i], &mut buf).unwrap(), Received::Ratchet);
+        assert!(!i.needs_ratchet_message() && r.needs_ratchet_message());
+
+        relay_ratchet(&mut r, &mut i);
+        relay_ratchet(&mut i, &mut r);
+        send(&mut i, &mut r, b"ok");
+        send(&mut r, &mut i, b"ok");
+    }
+
+    #[test]
+    fn test_out_of_turn() {
+        let (mut i, mut r) = ratchet_pair();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        relay_ratchet(&mut i, &mut r);
+        assert!(matches!(
+            i.write_ratchet(&mut msg),
+            Err(Error::State(StateProblem::NotTurnToWrite))
+        ));
+
+        // A confirm before the answer is a protocol violation.
+        let mut stale = r.into_inner();
+        let len = stale.write_message(&[FRAME_CONFIRM], &mut msg).unwrap();
+        assert!(matches!(i.read_message(&msg[..len], &mut buf), Err(Error::Input)));
+    }
+
+    #[test]
+    fn test_should_ratchet() {
+        let (i, _) = ratchet_pair();
+        let mut i = i.ratchet_interval(2);
+        let mut msg = [0u8; 1024];
+        assert!(!i.should_ratchet());
+        i.write_message(b"a", &mut msg).unwrap();
+        i.write_message(b"b", &mut msg).unwrap();
+        assert!(i.should_ratchet());
+        i.write_ratchet(&mut msg).unwrap();
+        assert!(!i.should_ratchet());
+    }
+}
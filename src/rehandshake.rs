@@ -0,0 +1,214 @@
+//! Fresh handshakes run over an established session, for refreshing long-lived connections
+//! without dropping the link.
+//!
+//! [`TransportState::begin_rehandshake()`] starts a new handshake in the same role, whose
+//! messages travel encrypted inside the current transport. The two sides switch to the new
+//! handshake's keys together: each calls [`Rehandshake::finish()`] once the handshake completes,
+//! and every message after the last handshake message is under the new keys. Since the new keys
+//! come from new ephemeral DH exchanges, this gives the session the forward secrecy refresh
+//! that [rekeying](TransportState::rekey_outgoing) alone can't. The current session's handshake
+//! hash goes into the new handshake's prologue, so the new keys are bound to the old session.
+//!
+//! Nothing is negotiated on the wire: both peers have to agree on when to start, and on the
+//! new handshake's parameters, which may well be a different protocol than the current one's.
+//! Like the transport underneath, this relies on messages being delivered in order, and on no
+//! other messages being sent while the handshake runs.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::Builder;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let params: snow::params::NoiseParams = snow::doctest::NN.parse()?;
+//! # let (i, r) = snow::doctest::nn_handshake()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! # let (initiator, responder) = (i.into_transport_mode()?, r.into_transport_mode()?);
+//! // On failure, the current session comes back with the error.
+//! let mut initiator = initiator.begin_rehandshake(Builder::new(params.clone())).map_err(|(_, e)| e)?;
+//! let mut responder = responder.begin_rehandshake(Builder::new(params)).map_err(|(_, e)| e)?;
+//!
+//! let len = initiator.write_message(&[], &mut msg)?;
+//! responder.read_message(&msg[..len], &mut buf)?;
+//! let len = responder.write_message(&[], &mut msg)?;
+//! initiator.read_message(&msg[..len], &mut buf)?;
+//!
+//! let (mut initiator, mut responder) = (initiator.finish()?, responder.finish()?);
+//! let len = initiator.write_message(b"new keys", &mut msg)?;
+//! assert_eq!(responder.read_message(&msg[..len], &mut buf)?, 8);
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::{MAXMSGLEN, TAGLEN},
+    Error, HandshakeState, TransportState,
+};
+
+/// A handshake running inside an established [`TransportState`].
+#[derive(Debug)]
+pub struct Rehandshake {
+    transport: TransportState,
+    handshake: HandshakeState,
+    buffer:    Vec<u8>,
+}
+
+impl Rehandshake {
+    pub(crate) fn new(transport: TransportState, handshake: HandshakeState) -> Self {
+        Rehandshake { transport, handshake, buffer: vec![0u8; MAXMSGLEN - TAGLEN] }
+    }
+
+    /// The session the handshake runs inside, whose keys are still in use.
+    pub fn transport(&self) -> &TransportState {
+        &self.transport
+    }
+
+    /// The new handshake.
+    pub fn handshake(&self) -> &HandshakeState {
+        &self.handshake
+    }
+
+    /// Whether the new handshake is complete, so [`finish()`](Self::finish) can switch to it.
+    pub fn is_finished(&self) -> bool {
+        self.handshake.is_handshake_finished()
+    }
+
+    /// Write the next handshake message, encrypted by the current session, to `message`.
+    ///
+    /// Returns the size of the message, which is the 16-byte tag of the current session more
+    /// than the handshake message would be on its own.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`HandshakeState::write_message()`], counting the current session's tag
+    /// towards the size of `message`, or like [`TransportState::write_message()`].
+    pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        let room = message.len().saturating_sub(TAGLEN).min(self.buffer.len());
+        let len = match self.handshake.write_message(payload, &mut self.buffer[..room]) {
+            Ok(len) => len,
+            Err(Error::BufferTooSmall { needed, .. }) => {
+                bail!(Error::BufferTooSmall { needed: needed + TAGLEN, got: message.len() })
+            },
+            Err(e) => return Err(e),
+        };
+        self.transport.write_message(&self.buffer[..len], message)
+    }
+
+    /// Decrypt an incoming message with the current session, and read the handshake message
+    /// inside, writing its payload to `payload`.
+    ///
+    /// Returns the size of the payload.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TransportState::read_message()`] or [`HandshakeState::read_message()`].
+    /// Either way, the peers have fallen out of step, and the connection should be dropped.
+    pub fn read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<usize, Error> {
+        let len = self.transport.read_message(message, &mut self.buffer)?;
+        self.handshake.read_message(&self.buffer[..len], payload)
+    }
+
+    /// Switch to the new handshake's session, dropping the current one.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`HandshakeState::into_transport_mode()`], e.g. if the handshake isn't
+    /// finished yet.
+    pub fn finish(self) -> Result<TransportState, Error> {
+        self.handshake.into_transport_mode()
+    }
+
+    /// Abandon the new handshake and get back the current session. Only the peer knows how far
+    /// it got, so the two are probably out of step unless the handshake never started.
+    pub fn cancel(self) -> TransportState {
+        self.transport
+    }
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::{
+        doctest::{transport_pair, NN as PARAMS},
+        error::StateProblem,
+        params::NoiseParams,
+        Builder,
+    };
+
+    #[test]
+    fn test_rehandshake() {
+        let (mut i, mut r) = transport_pair();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+
+        // A message under the old keys, which shouldn't be readable under the new ones.
+        let stale_len = i.write_message(b"old", &mut msg).unwrap();
+        let stale = msg[..stale_len].to_vec();
+        r.read_message(&stale, &mut buf).unwrap();
+
+        // Switch protocols, with static keys the old handshake didn't have.
+        let params: NoiseParams = "Noise_XX_25519_AESGCM_SHA256".parse().unwrap();
+        let i_key = Builder::new(params.clone()).generate_keypair().unwrap().private;
+        let r_key = Builder::new(params.clone()).generate_keypair().unwrap().private;
+        let mut i =
+            i.begin_rehandshake(Builder::new(params.clone()).local_private_key(&i_key)).unwrap();
+        let mut r =
+            r.begin_rehandshake(Builder::new(params.clone()).local_private_key(&r_key)).unwrap();
+
+        let len = i.write_message(b"one", &mut msg).unwrap();
+        assert_eq!(r.read_message(&msg[..len], &mut buf).unwrap(), 3);
+        assert!(matches!(
+            r.write_message(&[], &mut msg[..64]),
+            Err(Error::BufferTooSmall { got: 64, .. })
+        ));
+        let len = r.write_message(b"two", &mut msg).unwrap();
+        assert_eq!(i.read_message(&msg[..len], &mut buf).unwrap(), 3);
+        assert!(!i.is_finished());
+        let len = i.write_message(b"three", &mut msg).unwrap();
+        assert_eq!(r.read_message(&msg[..len], &mut buf).unwrap(), 5);
+        assert!(i.is_finished() && r.is_finished());
+
+        let (mut i, mut r) = (i.finish().unwrap(), r.finish().unwrap());
+        assert_eq!(r.get_remote_static(), i.get_local_static());
+        assert_eq!(i.sending_nonce(), 0);
+        assert!(matches!(r.read_message(&stale, &mut buf), Err(Error::Decrypt)));
+
+        let len = r.write_message(b"new", &mut msg).unwrap();
+        assert_eq!(i.read_message(&msg[..len], &mut buf).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_unfinished() {
+        let (i, _) = transport_pair();
+        let i = i.begin_rehandshake(Builder::new(PARAMS.parse().unwrap())).unwrap();
+        assert!(matches!(i.finish(), Err(Error::State(StateProblem::HandshakeNotFinished))));
+    }
+
+    #[test]
+    fn test_build_failure_keeps_session() {
+        let (i, mut r) = transport_pair();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        // XX needs a local static key.
+        let (mut i, err) = i
+            .begin_rehandshake(Builder::new("Noise_XX_25519_AESGCM_SHA256".parse().unwrap()))
+            .unwrap_err();
+        assert!(matches!(err, Error::PatternPrereq { .. }));
+        let len = i.write_message(b"still here", &mut msg).unwrap();
+        assert_eq!(r.read_message(&msg[..len], &mut buf).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_bound_to_session() {
+        let (a_i, a_r) = transport_pair();
+        let (b_i, _) = transport_pair();
+        let begin = |t: TransportState| {
+            let builder = Builder::new(PARAMS.parse().unwrap()).prologue(b"app");
+            t.begin_rehandshake(builder).unwrap()
+        };
+        let (a_i, a_r, b_i) = (begin(a_i), begin(a_r), begin(b_i));
+        assert_eq!(a_i.handshake().get_handshake_hash(), a_r.handshake().get_handshake_hash());
+        assert_ne!(a_i.handshake().get_handshake_hash(), b_i.handshake().get_handshake_hash());
+    }
+}
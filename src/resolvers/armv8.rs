@@ -0,0 +1,244 @@
+use super::CryptoResolver;
+use crate::{
+    constants::{CIPHERKEYLEN, TAGLEN},
+    params::{CipherChoice, DHChoice, HashChoice},
+    types::{default_rekey, Cipher, Dh, Hash, Random},
+};
+use aes_gcm_armv8::{
+    aead::{AeadInOut, KeyInit},
+    Aes256Gcm,
+};
+use core::convert::TryInto;
+use sha2_armv8::{Digest, Sha256, Sha512};
+
+/// A resolver for the primitives that ARMv8 CPUs have instructions for: AES-GCM (with the AES
+/// and PMULL instructions) and SHA-256/SHA-512 (with the SHA2 and SHA512 instructions).
+///
+/// The instructions are detected at runtime, so the same binary falls back to constant-time
+/// software implementations on CPUs without them (and uses AES-NI and CLMUL on x86). Everything
+/// else resolves to `None`, so this is meant to be combined with another resolver through
+/// [`FallbackResolver`](super::FallbackResolver).
+#[derive(Default)]
+pub struct Armv8Resolver;
+
+impl Armv8Resolver {
+    /// Whether this CPU has the AES, PMULL and SHA2 instructions, so AES-GCM and SHA-256 run at
+    /// hardware speed.
+    pub fn is_accelerated() -> bool {
+        #[cfg(target_arch = "aarch64")]
+        {
+            std::arch::is_aarch64_feature_detected!("aes")
+                && std::arch::is_aarch64_feature_detected!("pmull")
+                && std::arch::is_aarch64_feature_detected!("sha2")
+        }
+        #[cfg(not(target_arch = "aarch64"))]
+        {
+            false
+        }
+    }
+}
+
+impl CryptoResolver for Armv8Resolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        None
+    }
+
+    fn resolve_dh(&self, _choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        None
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        match *choice {
+            HashChoice::SHA256 => Some(Box::new(HashSHA256::default())),
+            HashChoice::SHA512 => Some(Box::new(HashSHA512::default())),
+            _ => None,
+        }
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        match *choice {
+            CipherChoice::AESGCM => Some(Box::new(CipherAesGcm::default())),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps `aes-gcm`'s AES256-GCM implementation, with its ARMv8 backend.
+#[derive(Default)]
+struct CipherAesGcm {
+    key: [u8; 32],
+}
+
+impl CipherAesGcm {
+    fn aead_and_nonce(&self, nonce: u64) -> (Aes256Gcm, [u8; 12]) {
+        let mut nonce_bytes = [0u8; 12];
+        copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[4..]);
+        (Aes256Gcm::new(&self.key.into()), nonce_bytes)
+    }
+}
+
+impl Cipher for CipherAesGcm {
+    fn name(&self) -> &'static str {
+        "AESGCM"
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        copy_slices!(key, &mut self.key)
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        let (aead, nonce_bytes) = self.aead_and_nonce(nonce);
+        let tag = aead
+            .encrypt_inout_detached(
+                &nonce_bytes.into(),
+                authtext,
+                (&mut in_out[..plaintext_len]).into(),
+            )
+            .expect("Encryption failed!");
+
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
+
+        plaintext_len + TAGLEN
+    }
+
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let (aead, nonce_bytes) = self.aead_and_nonce(nonce);
+        let message_len = ciphertext.len() - TAGLEN;
+
+        copy_slices!(ciphertext[..message_len], out);
+
+        let tag = ciphertext[message_len..].try_into().map_err(|_| ())?;
+        aead.decrypt_inout_detached(
+            &nonce_bytes.into(),
+            authtext,
+            (&mut out[..message_len]).into(),
+            tag,
+        )
+        .map(|_| message_len)
+        .map_err(|_| ())
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
+}
+
+/// Wraps `RustCrypto`'s SHA-256 implementation, with its ARMv8 backend.
+#[derive(Default)]
+struct HashSHA256 {
+    hasher: Sha256,
+}
+
+impl Hash for HashSHA256 {
+    fn block_len(&self) -> usize {
+        64
+    }
+
+    fn hash_len(&self) -> usize {
+        32
+    }
+
+    fn name(&self) -> &'static str {
+        "SHA256"
+    }
+
+    fn reset(&mut self) {
+        self.hasher = Sha256::new();
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        let hash = self.hasher.finalize_reset();
+        copy_slices!(hash, out)
+    }
+}
+
+/// Wraps `RustCrypto`'s SHA-512 implementation, with its ARMv8 backend.
+#[derive(Default)]
+struct HashSHA512 {
+    hasher: Sha512,
+}
+
+impl Hash for HashSHA512 {
+    fn name(&self) -> &'static str {
+        "SHA512"
+    }
+
+    fn block_len(&self) -> usize {
+        128
+    }
+
+    fn hash_len(&self) -> usize {
+        64
+    }
+
+    fn reset(&mut self) {
+        self.hasher = Sha512::new();
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        let hash = self.hasher.finalize_reset();
+        copy_slices!(hash, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256() {
+        let mut output = [0u8; 32];
+        let mut hasher = HashSHA256::default();
+        hasher.input(b"abc");
+        hasher.result(&mut output);
+        assert_eq!(
+            hex::encode(output),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_aesgcm() {
+        // AES256-GCM tests - gcm-spec.pdf, Test Case 14
+        let mut cipher = CipherAesGcm::default();
+        cipher.set(&[0u8; 32]);
+        let mut ciphertext = [0u8; 32];
+        cipher.encrypt(0, &[], &[0u8; 16], &mut ciphertext);
+        assert_eq!(
+            hex::encode(ciphertext),
+            "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919"
+        );
+
+        let mut plaintext = [1u8; 16];
+        assert_eq!(cipher.decrypt(0, &[], &ciphertext, &mut plaintext), Ok(16));
+        assert_eq!(plaintext, [0u8; 16]);
+        ciphertext[0] ^= 1;
+        assert!(cipher.decrypt(0, &[], &ciphertext, &mut plaintext).is_err());
+    }
+}
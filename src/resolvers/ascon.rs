@@ -0,0 +1,274 @@
+//! Ascon-128 and Ascon-Hash (version 1.2, as selected by NIST's lightweight cryptography
+//! competition) in portable Rust, for the default resolver.
+//!
+//! Both are built on one 320-bit permutation made of 64-bit XORs, ANDs, NOTs and rotations, which
+//! is small and constant-time on 32-bit microcontrollers without any tables or AES instructions.
+
+#[cfg(feature = "cipher-ascon")]
+use subtle::ConstantTimeEq;
+
+const ROUND_CONSTANTS: [u64; 12] =
+    [0xf0, 0xe1, 0xd2, 0xc3, 0xb4, 0xa5, 0x96, 0x87, 0x78, 0x69, 0x5a, 0x4b];
+
+/// The number of bytes absorbed per permutation.
+const RATE: usize = 8;
+
+#[derive(Clone)]
+struct State([u64; 5]);
+
+impl State {
+    /// The last `rounds` rounds of the permutation, i.e. p^a with 12 and p^b with 6.
+    fn permute(&mut self, rounds: usize) {
+        for &constant in &ROUND_CONSTANTS[12 - rounds..] {
+            self.round(constant);
+        }
+    }
+
+    fn round(&mut self, constant: u64) {
+        let [mut x0, mut x1, mut x2, mut x3, mut x4] = self.0;
+        x2 ^= constant;
+
+        x0 ^= x4;
+        x4 ^= x3;
+        x2 ^= x1;
+        let (t0, t1, t2, t3, t4) = (!x0 & x1, !x1 & x2, !x2 & x3, !x3 & x4, !x4 & x0);
+        x0 ^= t1;
+        x1 ^= t2;
+        x2 ^= t3;
+        x3 ^= t4;
+        x4 ^= t0;
+        x1 ^= x0;
+        x0 ^= x4;
+        x3 ^= x2;
+        x2 = !x2;
+
+        self.0 = [
+            x0 ^ x0.rotate_right(19) ^ x0.rotate_right(28),
+            x1 ^ x1.rotate_right(61) ^ x1.rotate_right(39),
+            x2 ^ x2.rotate_right(1) ^ x2.rotate_right(6),
+            x3 ^ x3.rotate_right(10) ^ x3.rotate_right(17),
+            x4 ^ x4.rotate_right(7) ^ x4.rotate_right(41),
+        ];
+    }
+}
+
+fn load(bytes: &[u8]) -> u64 {
+    let mut block = [0u8; RATE];
+    block.copy_from_slice(bytes);
+    u64::from_be_bytes(block)
+}
+
+/// The last, partial block of an input, padded with a one bit and zeros.
+fn pad(bytes: &[u8]) -> u64 {
+    let mut block = [0u8; RATE];
+    block[..bytes.len()].copy_from_slice(bytes);
+    block[bytes.len()] = 0x80;
+    u64::from_be_bytes(block)
+}
+
+#[cfg(feature = "cipher-ascon")]
+const AEAD_IV: u64 = 0x8040_0c06_0000_0000;
+
+/// Ascon-128's state after absorbing the key, nonce and associated data.
+#[cfg(feature = "cipher-ascon")]
+fn aead_state(key: &[u8; 16], nonce: &[u8; 16], authtext: &[u8]) -> State {
+    let (k0, k1) = (load(&key[..8]), load(&key[8..]));
+    let mut state = State([AEAD_IV, k0, k1, load(&nonce[..8]), load(&nonce[8..])]);
+    state.permute(12);
+    state.0[3] ^= k0;
+    state.0[4] ^= k1;
+
+    if !authtext.is_empty() {
+        let mut blocks = authtext.chunks_exact(RATE);
+        for block in &mut blocks {
+            state.0[0] ^= load(block);
+            state.permute(6);
+        }
+        state.0[0] ^= pad(blocks.remainder());
+        state.permute(6);
+    }
+    state.0[4] ^= 1;
+    state
+}
+
+#[cfg(feature = "cipher-ascon")]
+fn tag(mut state: State, key: &[u8; 16]) -> [u8; 16] {
+    let (k0, k1) = (load(&key[..8]), load(&key[8..]));
+    state.0[1] ^= k0;
+    state.0[2] ^= k1;
+    state.permute(12);
+    let mut tag = [0u8; 16];
+    tag[..8].copy_from_slice(&(state.0[3] ^ k0).to_be_bytes());
+    tag[8..].copy_from_slice(&(state.0[4] ^ k1).to_be_bytes());
+    tag
+}
+
+/// Encrypts `in_out` in place with Ascon-128 and returns the tag.
+#[cfg(feature = "cipher-ascon")]
+pub(super) fn seal(
+    key: &[u8; 16],
+    nonce: &[u8; 16],
+    authtext: &[u8],
+    in_out: &mut [u8],
+) -> [u8; 16] {
+    let mut state = aead_state(key, nonce, authtext);
+    let mut blocks = in_out.chunks_exact_mut(RATE);
+    for block in &mut blocks {
+        state.0[0] ^= load(block);
+        block.copy_from_slice(&state.0[0].to_be_bytes());
+        state.permute(6);
+    }
+    let last = blocks.into_remainder();
+    state.0[0] ^= pad(last);
+    last.copy_from_slice(&state.0[0].to_be_bytes()[..last.len()]);
+    tag(state, key)
+}
+
+/// Decrypts `in_out` in place with Ascon-128 and checks `tag`, zeroing `in_out` if it doesn't
+/// match.
+#[cfg(feature = "cipher-ascon")]
+pub(super) fn open(
+    key: &[u8; 16],
+    nonce: &[u8; 16],
+    authtext: &[u8],
+    in_out: &mut [u8],
+    expected: &[u8],
+) -> Result<(), ()> {
+    let mut state = aead_state(key, nonce, authtext);
+    let mut blocks = in_out.chunks_exact_mut(RATE);
+    for block in &mut blocks {
+        let ciphertext = load(block);
+        block.copy_from_slice(&(state.0[0] ^ ciphertext).to_be_bytes());
+        state.0[0] = ciphertext;
+        state.permute(6);
+    }
+    let last = blocks.into_remainder();
+    last.iter_mut().zip(&state.0[0].to_be_bytes()).for_each(|(byte, x)| *byte ^= x);
+    state.0[0] ^= pad(last);
+
+    if bool::from(tag(state, key).ct_eq(expected)) {
+        Ok(())
+    } else {
+        in_out.iter_mut().for_each(|byte| *byte = 0);
+        Err(())
+    }
+}
+
+/// Ascon-Hash, with a 32-byte digest.
+#[cfg(feature = "hash-ascon")]
+#[derive(Clone)]
+pub(super) struct AsconHash {
+    state:    State,
+    buffer:   [u8; RATE],
+    buffered: usize,
+}
+
+#[cfg(feature = "hash-ascon")]
+impl AsconHash {
+    const IV: u64 = 0x0040_0c00_0000_0100;
+
+    pub(super) fn new() -> Self {
+        let mut state = State([Self::IV, 0, 0, 0, 0]);
+        state.permute(12);
+        AsconHash { state, buffer: [0u8; RATE], buffered: 0 }
+    }
+
+    pub(super) fn input(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let len = data.len().min(RATE - self.buffered);
+            self.buffer[self.buffered..self.buffered + len].copy_from_slice(&data[..len]);
+            self.buffered += len;
+            data = &data[len..];
+            if self.buffered == RATE {
+                self.state.0[0] ^= load(&self.buffer);
+                self.state.permute(12);
+                self.buffered = 0;
+            }
+        }
+    }
+
+    pub(super) fn result(&self, out: &mut [u8]) {
+        let mut state = self.state.clone();
+        state.0[0] ^= pad(&self.buffer[..self.buffered]);
+        for block in out[..32].chunks_exact_mut(RATE) {
+            state.permute(12);
+            block.copy_from_slice(&state.0[0].to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // Known-answer tests from the Ascon v1.2 submission (LWC_AEAD_KAT_128_128.txt), with key and
+    // nonce 00 01 02 ... 0f and counting bytes for the associated data and plaintext.
+    #[cfg(feature = "cipher-ascon")]
+    #[test]
+    fn test_ascon128() {
+        let mut key = [0u8; 16];
+        key.iter_mut().enumerate().for_each(|(i, byte)| *byte = i as u8);
+        let check = |ad: &str, msg: &str, ciphertext: &str| {
+            let (ad, ciphertext) = (unhex(ad), unhex(ciphertext));
+            let mut buf = unhex(msg);
+            let tag = seal(&key, &key, &ad, &mut buf);
+            assert_eq!([&buf[..], &tag[..]].concat(), ciphertext);
+
+            open(&key, &key, &ad, &mut buf, &tag).unwrap();
+            assert_eq!(buf, unhex(msg));
+            let mut bad_tag = tag;
+            bad_tag[15] ^= 1;
+            assert!(open(&key, &key, &ad, &mut buf, &bad_tag).is_err());
+        };
+        // Counts 1, 2 and 34: nothing, a byte of associated data, a byte of plaintext.
+        check("", "", "e355159f292911f794cb1432a0103a8a");
+        check("00", "", "944df887cd4901614c5dedbc42fc0da0");
+        check("", "00", "bc18c3f4e39eca7222490d967c79bffc92");
+        // Count 35: a byte of each.
+        check("00", "00", "bd4102b707775c3c155ae497b43bf834e5");
+        // Count 109: a full and a partial block of associated data, with a partial block of
+        // plaintext.
+        check("000102030405060708", "000102", "3225020cff6c9660bf5c3f03860fe114532049");
+        // Count 265: exactly one block of plaintext.
+        check("", "0001020304050607", "bc820dbdf7a4631c01a8807a44254b42ac6bb490da1e000a");
+        // Count 511: a full and a partial block of each.
+        check(
+            "000102030405060708090a0b0c0d0e",
+            "000102030405060708090a0b0c0d0e",
+            "2e83cc36f088232a8ee9bab74d02931359cd914feeafe892d429e62f3de0c9",
+        );
+        // Count 1089: four full blocks of each.
+        check(
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "b96c78651b6246b0c3b1a5d373b0d5168dca4a96734cf0ddf5f92f8d15e30270\
+             279bf6a6cc3f2fc9350b915c292bdb8d",
+        );
+    }
+
+    // Known-answer tests from the Ascon v1.2 submission (LWC_HASH_KAT_256.txt), fed in uneven
+    // chunks so inputs straddle blocks.
+    #[cfg(feature = "hash-ascon")]
+    #[test]
+    fn test_ascon_hash() {
+        let digest = |input: &[u8]| {
+            let mut hash = AsconHash::new();
+            input.chunks(3).for_each(|chunk| hash.input(chunk));
+            let mut out = [0u8; 32];
+            hash.result(&mut out);
+            out.to_vec()
+        };
+        assert_eq!(
+            digest(b""),
+            unhex("7346bc14f036e87ae03d0997913088f5f68411434b3cf8b54fa796a80d251f91")
+        );
+        assert_eq!(
+            digest(&[0]),
+            unhex("8dd446ada58a7740ecf56eb638ef775f7d5c0fd5f0c2bbbdfdec29609d3c43a2")
+        );
+    }
+}
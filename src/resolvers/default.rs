@@ -1,88 +1,297 @@
+#[cfg(all(feature = "cipher-aesgcm", not(feature = "cipher-chachapoly")))]
+use aes_gcm::aead::{AeadInPlace, NewAead};
+#[cfg(all(feature = "hash-blake2", not(feature = "hash-sha2")))]
+use blake2::Digest;
+#[cfg(feature = "hash-blake2")]
 use blake2::{Blake2b, Blake2s};
 #[cfg(feature = "xchachapoly")]
 use chacha20poly1305::XChaCha20Poly1305;
+#[cfg(feature = "cipher-chachapoly")]
 use chacha20poly1305::{
     aead::{AeadInPlace, NewAead},
     ChaCha20Poly1305,
 };
+#[cfg(feature = "sig-ed25519")]
+use core::convert::TryFrom;
+#[cfg(feature = "dh-25519")]
 use core::convert::TryInto;
-#[cfg(feature = "pqclean_kyber1024")]
-use pqcrypto_kyber::kyber1024;
-#[cfg(feature = "pqclean_kyber1024")]
+#[cfg(feature = "sig-ed25519")]
+use ed25519_dalek::Signer;
+#[cfg(feature = "pqclean_kyber")]
+use pqcrypto_kyber::{kyber1024, kyber512, kyber768};
+#[cfg(feature = "pqclean_kyber")]
 use pqcrypto_traits::kem::{Ciphertext, PublicKey, SecretKey, SharedSecret};
 use rand::rngs::OsRng;
-use sha2::{Digest, Sha256, Sha512};
+#[cfg(feature = "hash-sha2")]
+use sha2::{Digest, Sha256, Sha384, Sha512};
+#[cfg(feature = "dh-25519")]
 use x25519_dalek as x25519;
 
+#[cfg(feature = "cipher-aegis")]
+use super::aegis::{self, Aegis128L, Aegis256};
+#[cfg(feature = "cipher-ascon")]
+use super::ascon;
+#[cfg(feature = "hash-ascon")]
+use super::ascon::AsconHash;
+#[cfg(all(
+    feature = "cipher-chachapoly",
+    target_arch = "aarch64",
+    not(feature = "forbid-unsafe")
+))]
+use super::chacha_neon;
+#[cfg(feature = "mlkem")]
+use super::mlkem::MlKem;
+#[cfg(feature = "dh-secp256k1")]
+use super::secp256k1::DhSecp256k1;
 use super::CryptoResolver;
-#[cfg(feature = "pqclean_kyber1024")]
+#[cfg(any(feature = "pqclean_kyber", feature = "mlkem"))]
 use crate::params::KemChoice;
-#[cfg(feature = "pqclean_kyber1024")]
+#[cfg(any(feature = "hash-sha2", feature = "hash-blake2"))]
+use crate::types::HmacPads;
+#[cfg(any(feature = "pqclean_kyber", feature = "mlkem"))]
 use crate::types::Kem;
+#[cfg(any(
+    feature = "cipher-aesgcm",
+    feature = "cipher-chachapoly",
+    feature = "cipher-aegis",
+    feature = "cipher-ascon"
+))]
 use crate::{
-    constants::TAGLEN,
-    params::{CipherChoice, DHChoice, HashChoice},
-    types::{Cipher, Dh, Hash, Random},
+    constants::{CIPHERKEYLEN, TAGLEN},
+    types::default_rekey,
+};
+use crate::{
+    params::{CipherChoice, DHChoice, HashChoice, SigChoice},
+    types::{Cipher, Dh, Hash, Random, Sign},
 };
 
 /// The default resolver provided by snow. This resolver is designed to
 /// support as many of the Noise spec primitives as possible with
 /// pure-Rust (or nearly pure-Rust) implementations.
+///
+/// Each primitive is behind its own feature (`cipher-aesgcm`, `cipher-chachapoly`, `hash-sha2`,
+/// `hash-blake2`, `dh-25519` and `sig-ed25519`), all of which are enabled by `default-resolver`.
+/// Primitives whose feature is disabled resolve to `None`.
 #[derive(Default)]
 pub struct DefaultResolver;
 
 impl CryptoResolver for DefaultResolver {
     fn resolve_rng(&self) -> Option<Box<dyn Random>> {
-        Some(Box::new(OsRng::default()))
+        Some(Box::new(OsRng))
     }
 
     fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
         match *choice {
+            #[cfg(feature = "dh-25519")]
             DHChoice::Curve25519 => Some(Box::new(Dh25519::default())),
+            #[cfg(feature = "dh-secp256k1")]
+            DHChoice::Secp256k1 => Some(Box::new(DhSecp256k1::default())),
             _ => None,
         }
     }
 
+    #[allow(unreachable_patterns)]
     fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
         match *choice {
+            #[cfg(feature = "hash-sha2")]
             HashChoice::SHA256 => Some(Box::new(HashSHA256::default())),
+            #[cfg(feature = "hash-sha2")]
+            HashChoice::SHA384 => Some(Box::new(HashSHA384::default())),
+            #[cfg(feature = "hash-sha2")]
             HashChoice::SHA512 => Some(Box::new(HashSHA512::default())),
+            #[cfg(feature = "hash-blake2")]
             HashChoice::Blake2s => Some(Box::new(HashBLAKE2s::default())),
+            #[cfg(feature = "hash-blake2")]
             HashChoice::Blake2b => Some(Box::new(HashBLAKE2b::default())),
+            #[cfg(feature = "hash-ascon")]
+            HashChoice::AsconHash => Some(Box::new(HashAscon::default())),
+            _ => None,
         }
     }
 
+    #[allow(unreachable_patterns)]
     fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
         match *choice {
+            #[cfg(feature = "cipher-chachapoly")]
             CipherChoice::ChaChaPoly => Some(Box::new(CipherChaChaPoly::default())),
             #[cfg(feature = "xchachapoly")]
             CipherChoice::XChaChaPoly => Some(Box::new(CipherXChaChaPoly::default())),
+            #[cfg(feature = "cipher-aesgcm")]
             CipherChoice::AESGCM => Some(Box::new(CipherAesGcm::default())),
+            #[cfg(feature = "cipher-aegis")]
+            CipherChoice::Aegis128L => Some(Box::new(CipherAegis128L::default())),
+            #[cfg(feature = "cipher-aegis")]
+            CipherChoice::Aegis256 => Some(Box::new(CipherAegis256::default())),
+            #[cfg(feature = "cipher-ascon")]
+            CipherChoice::Ascon128 => Some(Box::new(CipherAscon128::default())),
+            _ => None,
+        }
+    }
+
+    #[allow(unreachable_patterns)]
+    fn resolve_sig(&self, choice: &SigChoice) -> Option<Box<dyn Sign>> {
+        match *choice {
+            #[cfg(feature = "sig-ed25519")]
+            SigChoice::Ed25519 => Some(Box::new(SignEd25519::default())),
+            _ => None,
         }
     }
 
-    #[cfg(feature = "pqclean_kyber1024")]
+    #[cfg(any(feature = "pqclean_kyber", feature = "mlkem"))]
+    #[allow(unreachable_patterns)]
     fn resolve_kem(&self, choice: &KemChoice) -> Option<Box<dyn Kem>> {
         match *choice {
+            #[cfg(feature = "mlkem")]
+            KemChoice::MlKem512 => Some(Box::new(MlKem::mlkem512())),
+            #[cfg(feature = "mlkem")]
+            KemChoice::MlKem768 => Some(Box::new(MlKem::mlkem768())),
+            #[cfg(feature = "mlkem")]
+            KemChoice::MlKem1024 => Some(Box::new(MlKem::mlkem1024())),
+            #[cfg(feature = "pqclean_kyber")]
+            KemChoice::Kyber512 => Some(Box::new(Kyber512::default())),
+            #[cfg(feature = "pqclean_kyber")]
+            KemChoice::Kyber768 => Some(Box::new(Kyber768::default())),
+            #[cfg(feature = "pqclean_kyber")]
             KemChoice::Kyber1024 => Some(Box::new(Kyber1024::default())),
+            _ => None,
+        }
+    }
+}
+
+impl DefaultResolver {
+    /// Which implementation of `choice` the default resolver runs on this CPU, or `None` if it
+    /// doesn't provide the cipher.
+    ///
+    /// CPU features are detected at runtime, so on x86 and x86_64 AES-GCM uses AES-NI and CLMUL
+    /// and ChaCha20-Poly1305 uses AVX2 whenever they're available, and on aarch64
+    /// ChaCha20-Poly1305 uses NEON, without any configuration. This reports what gets picked,
+    /// e.g. for logging or benchmarks.
+    #[allow(unreachable_patterns)]
+    pub fn cipher_implementation(choice: &CipherChoice) -> Option<Implementation> {
+        match *choice {
+            #[cfg(feature = "cipher-chachapoly")]
+            CipherChoice::ChaChaPoly => Some(chachapoly_implementation()),
+            #[cfg(feature = "xchachapoly")]
+            CipherChoice::XChaChaPoly => Some(chacha20_crate_implementation()),
+            #[cfg(feature = "cipher-aesgcm")]
+            CipherChoice::AESGCM => Some(aesgcm_implementation()),
+            #[cfg(feature = "cipher-aegis")]
+            CipherChoice::Aegis128L | CipherChoice::Aegis256 => Some(aegis_implementation()),
+            #[cfg(feature = "cipher-ascon")]
+            CipherChoice::Ascon128 => Some(Implementation::Software),
+            _ => None,
+        }
+    }
+}
+
+/// The implementation of a primitive that runs on this CPU, as reported by
+/// [`DefaultResolver::cipher_implementation()`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Implementation {
+    /// Portable, constant-time software.
+    Software,
+
+    /// AES-NI for the AES rounds, with GHASH in software.
+    AesNi,
+
+    /// AES-NI for the AES rounds and CLMUL for GHASH.
+    AesNiClmul,
+
+    /// SSE2 vector instructions.
+    Sse2,
+
+    /// AVX2 vector instructions.
+    Avx2,
+
+    /// NEON vector instructions.
+    Neon,
+}
+
+#[cfg(feature = "cipher-aesgcm")]
+fn aesgcm_implementation() -> Implementation {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("aes") && is_x86_feature_detected!("sse2") {
+            if is_x86_feature_detected!("pclmulqdq") && is_x86_feature_detected!("sse4.1") {
+                return Implementation::AesNiClmul;
+            }
+            return Implementation::AesNi;
+        }
+    }
+    Implementation::Software
+}
+
+/// What the `aes` crate's round function picks.
+#[cfg(feature = "cipher-aegis")]
+fn aegis_implementation() -> Implementation {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("aes") {
+            return Implementation::AesNi;
         }
     }
+    Implementation::Software
+}
+
+#[cfg(feature = "cipher-chachapoly")]
+fn chachapoly_implementation() -> Implementation {
+    if neon_detected() {
+        return Implementation::Neon;
+    }
+    chacha20_crate_implementation()
+}
+
+/// Whether ChaChaPoly can use the NEON backend, which the `chacha20` crate lacks.
+#[cfg(feature = "cipher-chachapoly")]
+fn neon_detected() -> bool {
+    #[cfg(all(target_arch = "aarch64", not(feature = "forbid-unsafe")))]
+    {
+        return std::arch::is_aarch64_feature_detected!("neon");
+    }
+    #[allow(unreachable_code)]
+    false
+}
+
+/// What the `chacha20` crate picks, which only vectorizes on x86.
+#[cfg(any(feature = "cipher-chachapoly", feature = "xchachapoly"))]
+fn chacha20_crate_implementation() -> Implementation {
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse2"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return Implementation::Avx2;
+        }
+        return Implementation::Sse2;
+    }
+    #[allow(unreachable_code)]
+    Implementation::Software
 }
 
 /// Wraps x25519-dalek.
+#[cfg(feature = "dh-25519")]
 #[derive(Default)]
 struct Dh25519 {
     privkey: [u8; 32],
     pubkey:  [u8; 32],
 }
 
+/// Wraps ed25519-dalek.
+#[cfg(feature = "sig-ed25519")]
+#[derive(Default)]
+struct SignEd25519 {
+    privkey: [u8; 32],
+    pubkey:  [u8; 32],
+}
+
 /// Wraps `aes-gcm`'s AES256-GCM implementation.
+#[cfg(feature = "cipher-aesgcm")]
 #[derive(Default)]
 struct CipherAesGcm {
     key: [u8; 32],
 }
 
-/// Wraps `chacha20_poly1305_aead`'s ChaCha20Poly1305 implementation.
+/// Wraps `chacha20poly1305`'s ChaCha20Poly1305 implementation, or the NEON one on aarch64.
+#[cfg(feature = "cipher-chachapoly")]
 #[derive(Default)]
 struct CipherChaChaPoly {
     key: [u8; 32],
@@ -95,35 +304,156 @@ struct CipherXChaChaPoly {
     key: [u8; 32],
 }
 
-/// Wraps `RustCrypto`'s SHA-256 implementation.
-struct HashSHA256 {
-    hasher: Sha256,
+/// AEGIS-128L, which only takes a 16-byte key, so it's keyed with the first half of the 32-byte
+/// key and the second half is ignored.
+#[cfg(feature = "cipher-aegis")]
+#[derive(Default)]
+struct CipherAegis128L {
+    key: [u8; 32],
 }
 
-/// Wraps `RustCrypto`'s SHA-512 implementation.
-struct HashSHA512 {
-    hasher: Sha512,
+/// AEGIS-256.
+#[cfg(feature = "cipher-aegis")]
+#[derive(Default)]
+struct CipherAegis256 {
+    key: [u8; 32],
 }
 
-/// Wraps `blake2-rfc`'s implementation.
-struct HashBLAKE2b {
-    hasher: Blake2b,
+/// Ascon-128 (v1.2), which only takes a 16-byte key, so it's keyed with the first half of the
+/// 32-byte key and the second half is ignored.
+#[cfg(feature = "cipher-ascon")]
+#[derive(Default)]
+struct CipherAscon128 {
+    key: [u8; 32],
 }
 
-/// Wraps `blake2-rfc`'s implementation.
-struct HashBLAKE2s {
-    hasher: Blake2s,
+/// Ascon-Hash.
+#[cfg(feature = "hash-ascon")]
+struct HashAscon {
+    hasher: AsconHash,
 }
 
-/// Wraps `kyber1024`'s implementation
-#[cfg(feature = "pqclean_kyber1024")]
-struct Kyber1024 {
-    privkey: kyber1024::SecretKey,
-    pubkey:  kyber1024::PublicKey,
+/// A RustCrypto hasher's states after hashing HMAC's inner and outer key blocks, which are
+/// cloned for each HMAC under that key.
+#[cfg(any(feature = "hash-sha2", feature = "hash-blake2"))]
+#[derive(Default)]
+struct HmacStates<D> {
+    inner: D,
+    outer: D,
 }
 
+#[cfg(any(feature = "hash-sha2", feature = "hash-blake2"))]
+impl<D: Digest + Clone + Default> HmacStates<D> {
+    fn new(key: &[u8], block_len: usize) -> Self {
+        let pads = HmacPads::new(key, block_len);
+        let mut states = HmacStates::<D>::default();
+        states.inner.update(pads.inner(block_len));
+        states.outer.update(pads.outer(block_len));
+        states
+    }
+
+    fn hmac(&self, data: &[u8], out: &mut [u8]) {
+        let mut inner = self.inner.clone();
+        inner.update(data);
+        let mut outer = self.outer.clone();
+        outer.update(inner.finalize());
+        let hash = outer.finalize();
+        copy_slices!(hash, out);
+    }
+}
+
+/// Implements `Hash` for a wrapper around a RustCrypto hasher, keeping HMAC's key block states
+/// for `hkdf()`.
+#[cfg(any(feature = "hash-sha2", feature = "hash-blake2"))]
+macro_rules! rustcrypto_hash {
+    (
+        $(#[doc = $doc:literal])*
+        #[cfg($cfg:meta)]
+        $name:ident($hasher:ty), $noise_name:literal, $block_len:literal, $hash_len:literal
+    ) => {
+        $(#[doc = $doc])*
+        #[cfg($cfg)]
+        #[derive(Default)]
+        struct $name {
+            hasher: $hasher,
+            hmac:   Option<HmacStates<$hasher>>,
+        }
+
+        #[cfg($cfg)]
+        impl Hash for $name {
+            fn name(&self) -> &'static str {
+                $noise_name
+            }
+
+            fn block_len(&self) -> usize {
+                $block_len
+            }
+
+            fn hash_len(&self) -> usize {
+                $hash_len
+            }
+
+            fn reset(&mut self) {
+                self.hasher = <$hasher>::default();
+            }
+
+            fn input(&mut self, data: &[u8]) {
+                self.hasher.update(data);
+            }
+
+            fn result(&mut self, out: &mut [u8]) {
+                let hash = self.hasher.finalize_reset();
+                copy_slices!(hash, out)
+            }
+
+            fn precompute_hmac(&mut self, key: &[u8]) -> bool {
+                self.hmac = Some(HmacStates::new(key, self.block_len()));
+                true
+            }
+
+            fn hmac_precomputed(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
+                match &self.hmac {
+                    Some(hmac) => hmac.hmac(data, out),
+                    None => self.hmac(key, data, out),
+                }
+            }
+
+            fn clear_precomputed_hmac(&mut self) {
+                self.hmac = None;
+            }
+        }
+    };
+}
+
+rustcrypto_hash!(
+    /// Wraps `RustCrypto`'s SHA-256 implementation.
+    #[cfg(feature = "hash-sha2")]
+    HashSHA256(Sha256), "SHA256", 64, 32
+);
+rustcrypto_hash!(
+    /// Wraps `RustCrypto`'s SHA-384 implementation.
+    #[cfg(feature = "hash-sha2")]
+    HashSHA384(Sha384), "SHA384", 128, 48
+);
+rustcrypto_hash!(
+    /// Wraps `RustCrypto`'s SHA-512 implementation.
+    #[cfg(feature = "hash-sha2")]
+    HashSHA512(Sha512), "SHA512", 128, 64
+);
+rustcrypto_hash!(
+    /// Wraps `blake2-rfc`'s implementation.
+    #[cfg(feature = "hash-blake2")]
+    HashBLAKE2b(Blake2b), "BLAKE2b", 128, 64
+);
+rustcrypto_hash!(
+    /// Wraps `blake2-rfc`'s implementation.
+    #[cfg(feature = "hash-blake2")]
+    HashBLAKE2s(Blake2s), "BLAKE2s", 64, 32
+);
+
 impl Random for OsRng {}
 
+#[cfg(feature = "dh-25519")]
 impl Dh for Dh25519 {
     fn name(&self) -> &'static str {
         "25519"
@@ -142,9 +472,15 @@ impl Dh for Dh25519 {
         self.pubkey = x25519::x25519(self.privkey, x25519::X25519_BASEPOINT_BYTES);
     }
 
-    fn generate(&mut self, rng: &mut dyn Random) {
-        rng.fill_bytes(&mut self.privkey);
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        copy_slices!(pubkey, &mut self.pubkey);
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        rng.try_fill_bytes(&mut self.privkey).map_err(|_| ())?;
         self.pubkey = x25519::x25519(self.privkey, x25519::X25519_BASEPOINT_BYTES);
+        Ok(())
     }
 
     fn pubkey(&self) -> &[u8] {
@@ -162,6 +498,73 @@ impl Dh for Dh25519 {
     }
 }
 
+#[cfg(feature = "sig-ed25519")]
+impl SignEd25519 {
+    fn derive_pubkey(&mut self) {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&self.privkey).unwrap();
+        self.pubkey = ed25519_dalek::PublicKey::from(&secret).to_bytes();
+    }
+}
+
+#[cfg(feature = "sig-ed25519")]
+impl Sign for SignEd25519 {
+    fn name(&self) -> &'static str {
+        "Ed25519"
+    }
+
+    fn pub_len(&self) -> usize {
+        ed25519_dalek::PUBLIC_KEY_LENGTH
+    }
+
+    fn priv_len(&self) -> usize {
+        ed25519_dalek::SECRET_KEY_LENGTH
+    }
+
+    fn sig_len(&self) -> usize {
+        ed25519_dalek::SIGNATURE_LENGTH
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        self.derive_pubkey();
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        rng.try_fill_bytes(&mut self.privkey).map_err(|_| ())?;
+        self.derive_pubkey();
+        Ok(())
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey
+    }
+
+    fn sign(&self, message: &[u8], out: &mut [u8]) -> usize {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&self.privkey).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        let keypair = ed25519_dalek::Keypair { secret, public };
+        let signature = keypair.sign(message).to_bytes();
+        copy_slices!(&signature, out);
+        signature.len()
+    }
+
+    fn verify(&self, pubkey: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        let pubkey = match ed25519_dalek::PublicKey::from_bytes(pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return false,
+        };
+        match ed25519_dalek::Signature::try_from(signature) {
+            Ok(signature) => pubkey.verify_strict(message, &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(feature = "cipher-aesgcm")]
 impl Cipher for CipherAesGcm {
     fn name(&self) -> &'static str {
         "AESGCM"
@@ -172,20 +575,30 @@ impl Cipher for CipherAesGcm {
     }
 
     fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
         let aead = aes_gcm::Aes256Gcm::new(&self.key.into());
 
         let mut nonce_bytes = [0u8; 12];
         copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[4..]);
 
-        copy_slices!(plaintext, out);
-
         let tag = aead
-            .encrypt_in_place_detached(&nonce_bytes.into(), authtext, &mut out[0..plaintext.len()])
+            .encrypt_in_place_detached(&nonce_bytes.into(), authtext, &mut in_out[..plaintext_len])
             .expect("Encryption failed!");
 
-        copy_slices!(tag, &mut out[plaintext.len()..]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
 
-        plaintext.len() + TAGLEN
+        plaintext_len + TAGLEN
     }
 
     fn decrypt(
@@ -213,8 +626,33 @@ impl Cipher for CipherAesGcm {
         .map(|_| message_len)
         .map_err(|_| ())
     }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let aead = aes_gcm::Aes256Gcm::new(&self.key.into());
+
+        let mut nonce_bytes = [0u8; 12];
+        copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[4..]);
+
+        let message_len = ciphertext_len - TAGLEN;
+        let (message, tag) = in_out[..ciphertext_len].split_at_mut(message_len);
+
+        aead.decrypt_in_place_detached(&nonce_bytes.into(), authtext, message, (&*tag).into())
+            .map(|_| message_len)
+            .map_err(|_| ())
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
 }
 
+#[cfg(feature = "cipher-chachapoly")]
 impl Cipher for CipherChaChaPoly {
     fn name(&self) -> &'static str {
         "ChaChaPoly"
@@ -225,18 +663,45 @@ impl Cipher for CipherChaChaPoly {
     }
 
     fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
         let mut nonce_bytes = [0u8; 12];
         copy_slices!(&nonce.to_le_bytes(), &mut nonce_bytes[4..]);
 
-        copy_slices!(plaintext, out);
+        #[cfg(all(target_arch = "aarch64", not(feature = "forbid-unsafe")))]
+        {
+            if neon_detected() {
+                // Safe because the CPU was just checked for NEON.
+                let tag = unsafe {
+                    chacha_neon::seal(
+                        &self.key,
+                        &nonce_bytes,
+                        authtext,
+                        &mut in_out[..plaintext_len],
+                    )
+                };
+                copy_slices!(tag, &mut in_out[plaintext_len..]);
+                return plaintext_len + TAGLEN;
+            }
+        }
 
         let tag = ChaCha20Poly1305::new(&self.key.into())
-            .encrypt_in_place_detached(&nonce_bytes.into(), authtext, &mut out[0..plaintext.len()])
+            .encrypt_in_place_detached(&nonce_bytes.into(), authtext, &mut in_out[..plaintext_len])
             .unwrap();
 
-        copy_slices!(tag, &mut out[plaintext.len()..]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
 
-        plaintext.len() + tag.len()
+        plaintext_len + tag.len()
     }
 
     fn decrypt(
@@ -253,6 +718,23 @@ impl Cipher for CipherChaChaPoly {
 
         copy_slices!(ciphertext[..message_len], out);
 
+        #[cfg(all(target_arch = "aarch64", not(feature = "forbid-unsafe")))]
+        {
+            if neon_detected() {
+                // Safe because the CPU was just checked for NEON.
+                return unsafe {
+                    chacha_neon::open(
+                        &self.key,
+                        &nonce_bytes,
+                        authtext,
+                        &mut out[..message_len],
+                        &ciphertext[message_len..],
+                    )
+                }
+                .map(|_| message_len);
+            }
+        }
+
         let result = ChaCha20Poly1305::new(&self.key.into()).decrypt_in_place_detached(
             &nonce_bytes.into(),
             authtext,
@@ -265,6 +747,40 @@ impl Cipher for CipherChaChaPoly {
             Err(_) => Err(()),
         }
     }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let mut nonce_bytes = [0u8; 12];
+        copy_slices!(&nonce.to_le_bytes(), &mut nonce_bytes[4..]);
+
+        let message_len = ciphertext_len - TAGLEN;
+        let (message, tag) = in_out[..ciphertext_len].split_at_mut(message_len);
+
+        #[cfg(all(target_arch = "aarch64", not(feature = "forbid-unsafe")))]
+        {
+            if neon_detected() {
+                // Safe because the CPU was just checked for NEON.
+                return unsafe {
+                    chacha_neon::open(&self.key, &nonce_bytes, authtext, message, tag)
+                }
+                .map(|_| message_len);
+            }
+        }
+
+        ChaCha20Poly1305::new(&self.key.into())
+            .decrypt_in_place_detached(&nonce_bytes.into(), authtext, message, (&*tag).into())
+            .map(|_| message_len)
+            .map_err(|_| ())
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
 }
 
 #[cfg(feature = "xchachapoly")]
@@ -278,18 +794,28 @@ impl Cipher for CipherXChaChaPoly {
     }
 
     fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
         let mut nonce_bytes = [0u8; 24];
         copy_slices!(&nonce.to_le_bytes(), &mut nonce_bytes[16..]);
 
-        copy_slices!(plaintext, out);
-
         let tag = XChaCha20Poly1305::new(&self.key.into())
-            .encrypt_in_place_detached(&nonce_bytes.into(), authtext, &mut out[0..plaintext.len()])
+            .encrypt_in_place_detached(&nonce_bytes.into(), authtext, &mut in_out[..plaintext_len])
             .unwrap();
 
-        copy_slices!(tag, &mut out[plaintext.len()..]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
 
-        plaintext.len() + tag.len()
+        plaintext_len + tag.len()
     }
 
     fn decrypt(
@@ -318,118 +844,290 @@ impl Cipher for CipherXChaChaPoly {
             Err(_) => Err(()),
         }
     }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let mut nonce_bytes = [0u8; 24];
+        copy_slices!(&nonce.to_le_bytes(), &mut nonce_bytes[16..]);
+
+        let message_len = ciphertext_len - TAGLEN;
+        let (message, tag) = in_out[..ciphertext_len].split_at_mut(message_len);
+
+        XChaCha20Poly1305::new(&self.key.into())
+            .decrypt_in_place_detached(&nonce_bytes.into(), authtext, message, (&*tag).into())
+            .map(|_| message_len)
+            .map_err(|_| ())
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
 }
 
-impl Default for HashSHA256 {
-    fn default() -> HashSHA256 {
-        HashSHA256 { hasher: Sha256::new() }
+#[cfg(feature = "cipher-aegis")]
+impl CipherAegis128L {
+    /// The state for `nonce`, which is encoded big-endian at the end of an otherwise zero AEGIS
+    /// nonce, as for AES-GCM.
+    fn state(&self, nonce: u64) -> Aegis128L {
+        let mut key = [0u8; 16];
+        copy_slices!(&self.key[..16], &mut key);
+        let mut nonce_bytes = [0u8; 16];
+        copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[16 - 8..]);
+        Aegis128L::new(&key, &nonce_bytes)
     }
 }
 
-impl Hash for HashSHA256 {
-    fn block_len(&self) -> usize {
-        64
+#[cfg(feature = "cipher-aegis")]
+impl Cipher for CipherAegis128L {
+    fn name(&self) -> &'static str {
+        "AEGIS128L"
     }
 
-    fn hash_len(&self) -> usize {
-        32
+    fn set(&mut self, key: &[u8]) {
+        copy_slices!(key, &mut self.key)
     }
 
-    fn name(&self) -> &'static str {
-        "SHA256"
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
     }
 
-    fn reset(&mut self) {
-        self.hasher = Sha256::new();
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        let tag = aegis::seal(self.state(nonce), authtext, &mut in_out[..plaintext_len]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
+
+        plaintext_len + TAGLEN
     }
 
-    fn input(&mut self, data: &[u8]) {
-        self.hasher.update(data);
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext.len() - TAGLEN;
+
+        copy_slices!(ciphertext[..message_len], out);
+
+        aegis::open(
+            self.state(nonce),
+            authtext,
+            &mut out[..message_len],
+            &ciphertext[message_len..],
+        )
+        .map(|_| message_len)
     }
 
-    fn result(&mut self, out: &mut [u8]) {
-        let hash = self.hasher.finalize_reset();
-        copy_slices!(hash.as_slice(), out)
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext_len - TAGLEN;
+        let (message, tag) = in_out[..ciphertext_len].split_at_mut(message_len);
+
+        aegis::open(self.state(nonce), authtext, message, tag).map(|_| message_len)
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
     }
 }
 
-impl Default for HashSHA512 {
-    fn default() -> HashSHA512 {
-        HashSHA512 { hasher: Sha512::new() }
+#[cfg(feature = "cipher-aegis")]
+impl CipherAegis256 {
+    /// The state for `nonce`, which is encoded big-endian at the end of an otherwise zero AEGIS
+    /// nonce, as for AES-GCM.
+    fn state(&self, nonce: u64) -> Aegis256 {
+        let key = self.key;
+        let mut nonce_bytes = [0u8; 32];
+        copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[32 - 8..]);
+        Aegis256::new(&key, &nonce_bytes)
     }
 }
 
-impl Hash for HashSHA512 {
+#[cfg(feature = "cipher-aegis")]
+impl Cipher for CipherAegis256 {
     fn name(&self) -> &'static str {
-        "SHA512"
+        "AEGIS256"
     }
 
-    fn block_len(&self) -> usize {
-        128
+    fn set(&mut self, key: &[u8]) {
+        copy_slices!(key, &mut self.key)
     }
 
-    fn hash_len(&self) -> usize {
-        64
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
     }
 
-    fn reset(&mut self) {
-        self.hasher = Sha512::new();
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        let tag = aegis::seal(self.state(nonce), authtext, &mut in_out[..plaintext_len]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
+
+        plaintext_len + TAGLEN
     }
 
-    fn input(&mut self, data: &[u8]) {
-        self.hasher.update(data);
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext.len() - TAGLEN;
+
+        copy_slices!(ciphertext[..message_len], out);
+
+        aegis::open(
+            self.state(nonce),
+            authtext,
+            &mut out[..message_len],
+            &ciphertext[message_len..],
+        )
+        .map(|_| message_len)
     }
 
-    fn result(&mut self, out: &mut [u8]) {
-        let hash = self.hasher.finalize_reset();
-        copy_slices!(hash.as_slice(), out)
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext_len - TAGLEN;
+        let (message, tag) = in_out[..ciphertext_len].split_at_mut(message_len);
+
+        aegis::open(self.state(nonce), authtext, message, tag).map(|_| message_len)
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
     }
 }
 
-impl Default for HashBLAKE2b {
-    fn default() -> HashBLAKE2b {
-        HashBLAKE2b { hasher: Blake2b::default() }
+#[cfg(feature = "cipher-ascon")]
+impl CipherAscon128 {
+    fn key(&self) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        copy_slices!(&self.key[..16], &mut key);
+        key
+    }
+
+    /// `nonce` encoded big-endian at the end of an otherwise zero Ascon nonce, as for AES-GCM.
+    fn nonce(nonce: u64) -> [u8; 16] {
+        let mut nonce_bytes = [0u8; 16];
+        copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[8..]);
+        nonce_bytes
     }
 }
 
-impl Hash for HashBLAKE2b {
+#[cfg(feature = "cipher-ascon")]
+impl Cipher for CipherAscon128 {
     fn name(&self) -> &'static str {
-        "BLAKE2b"
+        "Ascon128"
     }
 
-    fn block_len(&self) -> usize {
-        128
+    fn set(&mut self, key: &[u8]) {
+        copy_slices!(key, &mut self.key)
     }
 
-    fn hash_len(&self) -> usize {
-        64
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
     }
 
-    fn reset(&mut self) {
-        self.hasher = Blake2b::default();
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        let tag =
+            ascon::seal(&self.key(), &Self::nonce(nonce), authtext, &mut in_out[..plaintext_len]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
+
+        plaintext_len + TAGLEN
     }
 
-    fn input(&mut self, data: &[u8]) {
-        self.hasher.update(data);
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext.len() - TAGLEN;
+
+        copy_slices!(ciphertext[..message_len], out);
+
+        ascon::open(
+            &self.key(),
+            &Self::nonce(nonce),
+            authtext,
+            &mut out[..message_len],
+            &ciphertext[message_len..],
+        )
+        .map(|_| message_len)
     }
 
-    fn result(&mut self, out: &mut [u8]) {
-        let hash = self.hasher.finalize_reset();
-        out[..64].copy_from_slice(&hash);
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext_len - TAGLEN;
+        let (message, tag) = in_out[..ciphertext_len].split_at_mut(message_len);
+
+        ascon::open(&self.key(), &Self::nonce(nonce), authtext, message, tag).map(|_| message_len)
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
     }
 }
 
-impl Default for HashBLAKE2s {
-    fn default() -> HashBLAKE2s {
-        HashBLAKE2s { hasher: Blake2s::default() }
+#[cfg(feature = "hash-ascon")]
+impl Default for HashAscon {
+    fn default() -> HashAscon {
+        HashAscon { hasher: AsconHash::new() }
     }
 }
 
-impl Hash for HashBLAKE2s {
+#[cfg(feature = "hash-ascon")]
+impl Hash for HashAscon {
     fn name(&self) -> &'static str {
-        "BLAKE2s"
+        "AsconHash"
     }
 
+    // The sponge only absorbs 8 bytes at a time, but HMAC needs room for a whole 32-byte key in
+    // a block, so HMAC uses SHA-256's 64-byte blocks.
     fn block_len(&self) -> usize {
         64
     }
@@ -439,93 +1137,112 @@ impl Hash for HashBLAKE2s {
     }
 
     fn reset(&mut self) {
-        self.hasher = Blake2s::default();
+        self.hasher = AsconHash::new();
     }
 
     fn input(&mut self, data: &[u8]) {
-        self.hasher.update(data);
+        self.hasher.input(data);
     }
 
     fn result(&mut self, out: &mut [u8]) {
-        let hash = self.hasher.finalize_reset();
-        out[..32].copy_from_slice(&hash);
+        self.hasher.result(out);
+        self.reset();
     }
 }
 
-#[cfg(feature = "pqclean_kyber1024")]
-impl Default for Kyber1024 {
-    fn default() -> Self {
-        Kyber1024 {
-            pubkey:  kyber1024::PublicKey::from_bytes(&[0; kyber1024::public_key_bytes()]).unwrap(),
-            privkey: kyber1024::SecretKey::from_bytes(&[0; kyber1024::secret_key_bytes()]).unwrap(),
+/// Wraps one of PQClean's Kyber parameter sets, which draw their randomness from the OS
+/// rather than the handshake's RNG.
+///
+/// This is round 3 Kyber, which isn't compatible with the ML-KEM that NIST standardized, and
+/// pqcrypto-kyber is no longer maintained; it's only kept for peers that still use it.
+macro_rules! pqclean_kyber {
+    ($name:ident, $kyber:ident) => {
+        #[cfg(feature = "pqclean_kyber")]
+        struct $name {
+            privkey: $kyber::SecretKey,
+            pubkey:  $kyber::PublicKey,
         }
-    }
-}
-
-#[cfg(feature = "pqclean_kyber1024")]
-impl Kem for Kyber1024 {
-    fn name(&self) -> &'static str {
-        "Kyber1024"
-    }
-
-    /// The length in bytes of a public key for this primitive.
-    fn pub_len(&self) -> usize {
-        kyber1024::public_key_bytes()
-    }
-
-    /// The length in bytes the Kem cipherthext for this primitive.
-    fn ciphertext_len(&self) -> usize {
-        kyber1024::ciphertext_bytes()
-    }
-
-    /// Shared secret length in bytes that this Kem encapsulates.
-    fn shared_secret_len(&self) -> usize {
-        kyber1024::shared_secret_bytes()
-    }
-
-    /// Generate a new private key.
-    fn generate(&mut self, _rng: &mut dyn Random) {
-        // PQClean uses their own random generator
-        let (pk, sk) = kyber1024::keypair();
-        self.pubkey = pk;
-        self.privkey = sk;
-    }
-
-    /// Get the public key.
-    fn pubkey(&self) -> &[u8] {
-        self.pubkey.as_bytes()
-    }
 
-    /// Generate a shared secret and encapsulate it using this Kem.
-    #[must_use]
-    fn encapsulate(
-        &self,
-        pubkey: &[u8],
-        shared_secret_out: &mut [u8],
-        ciphertext_out: &mut [u8],
-    ) -> Result<(usize, usize), ()> {
-        let pubkey = kyber1024::PublicKey::from_bytes(pubkey).map_err(|_| ())?;
-        let (shared_secret, ciphertext) = kyber1024::encapsulate(&pubkey);
-        shared_secret_out.copy_from_slice(shared_secret.as_bytes());
-        ciphertext_out.copy_from_slice(ciphertext.as_bytes());
-        Ok((shared_secret.as_bytes().len(), ciphertext.as_bytes().len()))
-    }
+        #[cfg(feature = "pqclean_kyber")]
+        impl Default for $name {
+            fn default() -> Self {
+                $name {
+                    pubkey:  $kyber::PublicKey::from_bytes(&[0; $kyber::public_key_bytes()])
+                        .unwrap(),
+                    privkey: $kyber::SecretKey::from_bytes(&[0; $kyber::secret_key_bytes()])
+                        .unwrap(),
+                }
+            }
+        }
 
-    /// Decapsulate a ciphertext producing a shared secret.
-    #[must_use]
-    fn decapsulate(&self, ciphertext: &[u8], shared_secret_out: &mut [u8]) -> Result<usize, ()> {
-        let ciphertext = kyber1024::Ciphertext::from_bytes(ciphertext).map_err(|_| ())?;
-        let shared_secret = kyber1024::decapsulate(&ciphertext, &self.privkey);
-        shared_secret_out.copy_from_slice(shared_secret.as_bytes());
-        Ok(shared_secret.as_bytes().len())
-    }
+        #[cfg(feature = "pqclean_kyber")]
+        impl Kem for $name {
+            fn name(&self) -> &'static str {
+                stringify!($name)
+            }
+
+            fn pub_len(&self) -> usize {
+                $kyber::public_key_bytes()
+            }
+
+            fn ciphertext_len(&self) -> usize {
+                $kyber::ciphertext_bytes()
+            }
+
+            fn shared_secret_len(&self) -> usize {
+                $kyber::shared_secret_bytes()
+            }
+
+            fn generate(&mut self, _rng: &mut dyn Random) -> Result<(), ()> {
+                let (pk, sk) = $kyber::keypair();
+                self.pubkey = pk;
+                self.privkey = sk;
+                Ok(())
+            }
+
+            fn pubkey(&self) -> &[u8] {
+                self.pubkey.as_bytes()
+            }
+
+            fn encapsulate(
+                &self,
+                pubkey: &[u8],
+                shared_secret_out: &mut [u8],
+                ciphertext_out: &mut [u8],
+            ) -> Result<(usize, usize), ()> {
+                let pubkey = $kyber::PublicKey::from_bytes(pubkey).map_err(|_| ())?;
+                let (shared_secret, ciphertext) = $kyber::encapsulate(&pubkey);
+                shared_secret_out.copy_from_slice(shared_secret.as_bytes());
+                ciphertext_out.copy_from_slice(ciphertext.as_bytes());
+                Ok((shared_secret.as_bytes().len(), ciphertext.as_bytes().len()))
+            }
+
+            fn decapsulate(
+                &self,
+                ciphertext: &[u8],
+                shared_secret_out: &mut [u8],
+            ) -> Result<usize, ()> {
+                let ciphertext = $kyber::Ciphertext::from_bytes(ciphertext).map_err(|_| ())?;
+                let shared_secret = $kyber::decapsulate(&ciphertext, &self.privkey);
+                shared_secret_out.copy_from_slice(shared_secret.as_bytes());
+                Ok(shared_secret.as_bytes().len())
+            }
+        }
+    };
 }
 
+pqclean_kyber!(Kyber512, kyber512);
+pqclean_kyber!(Kyber768, kyber768);
+pqclean_kyber!(Kyber1024, kyber1024);
+
 #[cfg(test)]
 mod tests {
-    use hex::FromHex;
     use super::*;
+    use crate::constants::MAXHASHLEN;
+    #[allow(unused_imports)]
+    use hex::FromHex;
 
+    #[cfg(feature = "hash-sha2")]
     #[test]
     fn test_sha256() {
         let mut output = [0u8; 32];
@@ -538,6 +1255,21 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "hash-sha2")]
+    #[test]
+    fn test_sha384() {
+        let mut output = [0u8; 48];
+        let mut hasher: HashSHA384 = Default::default();
+        hasher.input(b"abc");
+        hasher.result(&mut output);
+        assert!(
+            hex::encode(output)
+                == "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+                    8086072ba1e7cc2358baeca134c825a7"
+        );
+    }
+
+    #[cfg(feature = "hash-sha2")]
     #[test]
     fn test_hmac_sha256_sha512() {
         let key = Vec::<u8>::from_hex("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
@@ -554,7 +1286,7 @@ mod tests {
         let mut hasher: HashSHA512 = Default::default();
         hasher.hmac(&key, &data, &mut output2);
         assert!(
-            hex::encode(output2.to_vec())
+            hex::encode(output2)
                 == "fa73b0089d56a284efb0f0756c890be9\
                                      b1b5dbdd8ee81a3655f83e33b2279d39\
                                      bf3e848279a722c806b485a47e67c807\
@@ -562,6 +1294,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hmac_precomputed() {
+        let resolver = DefaultResolver;
+        let choices = [
+            HashChoice::SHA256,
+            HashChoice::SHA384,
+            HashChoice::SHA512,
+            HashChoice::Blake2s,
+            HashChoice::Blake2b,
+        ];
+        for mut hasher in choices.iter().filter_map(|choice| resolver.resolve_hash(choice)) {
+            let key = [0x0bu8; 64];
+            let key = &key[..hasher.hash_len()];
+            for data in [&b""[..], b"Hi There", &[0xddu8; 200]] {
+                let mut expected = [0u8; MAXHASHLEN];
+                hasher.hmac(key, data, &mut expected);
+
+                // Without precomputed states, it falls back to hmac().
+                let mut output = [0u8; MAXHASHLEN];
+                hasher.hmac_precomputed(key, data, &mut output);
+                assert_eq!(&output[..], &expected[..], "{}", hasher.name());
+
+                let mut output = [0u8; MAXHASHLEN];
+                assert!(hasher.precompute_hmac(key));
+                hasher.hmac_precomputed(key, data, &mut output);
+                assert_eq!(&output[..], &expected[..], "{}", hasher.name());
+                hasher.clear_precomputed_hmac();
+            }
+        }
+    }
+
+    #[cfg(feature = "hash-blake2")]
     #[test]
     fn test_blake2b() {
         // BLAKE2b test - draft-saarinen-blake2-06
@@ -570,7 +1334,7 @@ mod tests {
         hasher.input(b"abc");
         hasher.result(&mut output);
         assert!(
-            hex::encode(output.to_vec())
+            hex::encode(output)
                 == "ba80a53f981c4d0d6a2797b69f12f6e9\
                                     4c212f14685ac4b74b12bb6fdbffa2d1\
                                     7d87c5392aab792dc252d5de4533cc95\
@@ -578,6 +1342,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "hash-blake2")]
     #[test]
     fn test_blake2s() {
         // BLAKE2s test - draft-saarinen-blake2-06
@@ -592,6 +1357,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "dh-25519")]
     #[test]
     fn test_curve25519() {
         // Curve25519 test - draft-curves-10
@@ -611,6 +1377,30 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "sig-ed25519")]
+    #[test]
+    fn test_ed25519() {
+        // Ed25519 test - RFC 8032, Section 7.1, Test 1
+        let mut keypair: SignEd25519 = Default::default();
+        let secret =
+            Vec::<u8>::from_hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                .unwrap();
+        keypair.set(&secret);
+        assert!(
+            hex::encode(keypair.pubkey())
+                == "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        let mut signature = [0u8; 64];
+        assert_eq!(keypair.sign(b"", &mut signature), 64);
+        assert!(
+            hex::encode(signature)
+                == "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+        assert!(keypair.verify(keypair.pubkey(), b"", &signature));
+        assert!(!keypair.verify(keypair.pubkey(), b"x", &signature));
+    }
+
+    #[cfg(feature = "cipher-aesgcm")]
     #[test]
     fn test_aesgcm() {
         // AES256-GCM tests - gcm-spec.pdf
@@ -653,6 +1443,26 @@ mod tests {
         assert!(cipher4.decrypt(nonce, &authtext, &ciphertext2, &mut resulttext2).is_err());
     }
 
+    #[test]
+    fn test_cipher_implementation() {
+        #[cfg(feature = "cipher-aesgcm")]
+        assert!(DefaultResolver::cipher_implementation(&CipherChoice::AESGCM).is_some());
+        #[cfg(all(
+            feature = "cipher-chachapoly",
+            any(
+                target_arch = "x86_64",
+                all(target_arch = "aarch64", not(feature = "forbid-unsafe"))
+            )
+        ))]
+        assert_ne!(
+            DefaultResolver::cipher_implementation(&CipherChoice::ChaChaPoly),
+            Some(Implementation::Software)
+        );
+        #[cfg(feature = "disco")]
+        assert_eq!(DefaultResolver::cipher_implementation(&CipherChoice::Strobe), None);
+    }
+
+    #[cfg(feature = "cipher-chachapoly")]
     #[test]
     fn test_chachapoly_empty() {
         //ChaChaPoly round-trip test, empty plaintext
@@ -674,6 +1484,7 @@ mod tests {
         assert!(cipher2.decrypt(nonce, &authtext, &ciphertext, &mut resulttext).is_err());
     }
 
+    #[cfg(feature = "cipher-chachapoly")]
     #[test]
     fn test_chachapoly_nonempty() {
         //ChaChaPoly round-trip test, non-empty plaintext
@@ -690,7 +1501,7 @@ mod tests {
         let mut cipher2: CipherChaChaPoly = Default::default();
         cipher2.set(&key);
         cipher2.decrypt(nonce, &authtext, &ciphertext, &mut resulttext).unwrap();
-        assert!(hex::encode(resulttext.to_vec()) == hex::encode(plaintext.to_vec()));
+        assert!(hex::encode(resulttext) == hex::encode(plaintext));
     }
 
     #[cfg(feature = "xchachapoly")]
@@ -710,9 +1521,105 @@ mod tests {
         let mut cipher2: CipherXChaChaPoly = Default::default();
         cipher2.set(&key);
         cipher2.decrypt(nonce, &authtext, &ciphertext, &mut resulttext).unwrap();
-        assert!(hex::encode(resulttext.to_vec()) == hex::encode(plaintext.to_vec()));
+        assert!(hex::encode(resulttext) == hex::encode(plaintext));
     }
 
+    #[cfg(feature = "cipher-aegis")]
+    #[test]
+    fn test_aegis_round_trip() {
+        for choice in [CipherChoice::Aegis128L, CipherChoice::Aegis256] {
+            let mut cipher = DefaultResolver.resolve_cipher(&choice).unwrap();
+            cipher.set(&[7u8; 32]);
+            let plaintext = [0x34u8; 117];
+            let mut ciphertext = [0u8; 133];
+            assert_eq!(cipher.encrypt(5, b"ad", &plaintext, &mut ciphertext), 133);
+
+            let mut resulttext = [0u8; 117];
+            assert_eq!(cipher.decrypt(5, b"ad", &ciphertext, &mut resulttext), Ok(117));
+            assert_eq!(resulttext, plaintext);
+            assert!(cipher.decrypt(6, b"ad", &ciphertext, &mut resulttext).is_err());
+
+            let mut in_out = ciphertext;
+            assert_eq!(cipher.decrypt_in_place(5, b"ad", &mut in_out, 133), Ok(117));
+            assert_eq!(in_out[..117], plaintext[..]);
+        }
+    }
+
+    #[cfg(feature = "cipher-aegis")]
+    #[test]
+    fn test_aegis128l_ignores_second_half_of_key() {
+        let mut key = [7u8; 32];
+        let mut ciphertexts = [[0u8; 29]; 2];
+        for ciphertext in &mut ciphertexts {
+            let mut cipher: CipherAegis128L = Default::default();
+            cipher.set(&key);
+            cipher.encrypt(5, b"ad", &[0x34; 13], ciphertext);
+            key[16..].iter_mut().for_each(|byte| *byte = 0xff);
+        }
+        assert_eq!(ciphertexts[0], ciphertexts[1]);
+
+        let mut cipher: CipherAegis128L = Default::default();
+        cipher.set(&[8u8; 32]);
+        let mut other = [0u8; 29];
+        cipher.encrypt(5, b"ad", &[0x34; 13], &mut other);
+        assert_ne!(other, ciphertexts[0]);
+    }
+
+    /// Ascon-128 as a Noise cipher is Ascon-128 keyed with the first 16 bytes of the key, with
+    /// the nonce encoded like AES-GCM's.
+    #[cfg(feature = "cipher-ascon")]
+    #[test]
+    fn test_ascon128_noise_key_and_nonce() {
+        use std::convert::TryInto;
+
+        let mut key = [0u8; 32];
+        key.iter_mut().enumerate().for_each(|(i, byte)| *byte = i as u8);
+        let plaintext = [0x34u8; 13];
+        let mut ciphertext = [0u8; 29];
+        let mut cipher: CipherAscon128 = Default::default();
+        cipher.set(&key);
+        cipher.encrypt(0x0102030405060708, b"ad", &plaintext, &mut ciphertext);
+
+        let mut expected = plaintext;
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let tag = ascon::seal(key[..16].try_into().unwrap(), &nonce, b"ad", &mut expected);
+        assert_eq!(ciphertext[..13], expected[..]);
+        assert_eq!(ciphertext[13..], tag[..]);
+
+        // The second half of the key isn't used.
+        key[16..].iter_mut().for_each(|byte| *byte = 0xff);
+        let mut other = [0u8; 29];
+        cipher.set(&key);
+        cipher.encrypt(0x0102030405060708, b"ad", &plaintext, &mut other);
+        assert_eq!(other, ciphertext);
+    }
+
+    /// HMAC with Ascon-Hash pads its key to 64 bytes, not to the sponge's 8-byte rate.
+    #[cfg(feature = "hash-ascon")]
+    #[test]
+    fn test_ascon_hash_hmac_blocks() {
+        let key = [0x0bu8; 32];
+        let digest = |parts: &[&[u8]]| {
+            let mut hasher = AsconHash::new();
+            parts.iter().for_each(|part| hasher.input(part));
+            let mut out = [0u8; 32];
+            hasher.result(&mut out);
+            out
+        };
+        let mut ipad = [0x36u8; 64];
+        let mut opad = [0x5cu8; 64];
+        for (i, byte) in key.iter().enumerate() {
+            ipad[i] ^= byte;
+            opad[i] ^= byte;
+        }
+        let expected = digest(&[&opad, &digest(&[&ipad, b"Hi There"])]);
+
+        let mut out = [0u8; 32];
+        HashAscon::default().hmac(&key, b"Hi There", &mut out);
+        assert_eq!(out, expected);
+    }
+
+    #[cfg(feature = "cipher-chachapoly")]
     #[test]
     fn test_chachapoly_known_answer() {
         //ChaChaPoly known-answer test - RFC 7539
@@ -776,53 +1683,60 @@ mod tests {
                                  6d206f74686572207468616e20617320\
                                  2fe2809c776f726b20696e2070726f67\
                                  726573732e2fe2809d";
-        assert!(hex::encode(out[..ciphertext.len()].to_owned()) == desired_plaintext);
+        assert!(hex::encode(&out[..ciphertext.len()]) == desired_plaintext);
     }
 
-    #[test]
-    #[cfg(feature = "pqclean_kyber1024")]
-    fn test_kyber1024() {
-        let mut rng = OsRng::default();
-        let mut kem_1 = Kyber1024::default();
-        let kem_2 = Kyber1024::default();
-
-        let mut shared_secret_1 = vec![0; kem_1.shared_secret_len()];
-        let mut shared_secret_2 = vec![0; kem_2.shared_secret_len()];
-        let mut ciphertext = vec![0; kem_1.ciphertext_len()];
-
-        kem_1.generate(&mut rng);
-        let (ss1_len, ct_len) =
-            kem_2.encapsulate(kem_1.pubkey(), &mut shared_secret_1, &mut ciphertext).unwrap();
-        let ss2_len = kem_1.decapsulate(&mut ciphertext, &mut shared_secret_2).unwrap();
+    #[cfg(feature = "pqclean_kyber")]
+    fn kybers() -> Vec<(Box<dyn Kem>, Box<dyn Kem>)> {
+        vec![
+            (Box::new(Kyber512::default()), Box::new(Kyber512::default())),
+            (Box::new(Kyber768::default()), Box::new(Kyber768::default())),
+            (Box::new(Kyber1024::default()), Box::new(Kyber1024::default())),
+        ]
+    }
 
-        assert_eq!(shared_secret_1, shared_secret_2);
-        assert_eq!(ss1_len, shared_secret_1.len());
-        assert_eq!(ss2_len, shared_secret_2.len());
-        assert_eq!(ss1_len, ss2_len);
-        assert_eq!(ct_len, ciphertext.len());
+    #[test]
+    #[cfg(feature = "pqclean_kyber")]
+    fn test_kyber() {
+        let mut rng = OsRng;
+        for (mut kem_1, kem_2) in kybers() {
+            let mut shared_secret_1 = vec![0; kem_1.shared_secret_len()];
+            let mut shared_secret_2 = vec![0; kem_2.shared_secret_len()];
+            let mut ciphertext = vec![0; kem_1.ciphertext_len()];
+
+            kem_1.generate(&mut rng).unwrap();
+            let (ss1_len, ct_len) =
+                kem_2.encapsulate(kem_1.pubkey(), &mut shared_secret_1, &mut ciphertext).unwrap();
+            let ss2_len = kem_1.decapsulate(&ciphertext, &mut shared_secret_2).unwrap();
+
+            assert_eq!(shared_secret_1, shared_secret_2, "{}", kem_1.name());
+            assert_eq!(ss1_len, shared_secret_1.len());
+            assert_eq!(ss2_len, shared_secret_2.len());
+            assert_eq!(ss1_len, ss2_len);
+            assert_eq!(ct_len, ciphertext.len());
+        }
     }
 
     #[test]
-    #[cfg(feature = "pqclean_kyber1024")]
-    fn test_kyber1024_fail() {
-        let mut rng = OsRng::default();
-        let mut kem_1 = Kyber1024::default();
-        let kem_2 = Kyber1024::default();
-
-        let mut shared_secret_1 = vec![0; kem_1.shared_secret_len()];
-        let mut shared_secret_2 = vec![0; kem_2.shared_secret_len()];
-        let mut ciphertext = vec![0; kem_1.ciphertext_len()];
-        let mut bad_ciphertext = vec![0; kem_1.ciphertext_len()];
-
-        kem_1.generate(&mut rng);
-        let (ss1_len, ct_len) =
-            kem_2.encapsulate(kem_1.pubkey(), &mut shared_secret_1, &mut ciphertext).unwrap();
-        let ss2_len = kem_1.decapsulate(&mut bad_ciphertext, &mut shared_secret_2).unwrap();
-
-        assert_ne!(shared_secret_1, shared_secret_2);
-        assert_eq!(ss1_len, shared_secret_1.len());
-        assert_eq!(ss2_len, shared_secret_2.len());
-        assert_eq!(ss1_len, ss2_len);
-        assert_eq!(ct_len, ciphertext.len());
+    #[cfg(feature = "pqclean_kyber")]
+    fn test_kyber_fail() {
+        let mut rng = OsRng;
+        for (mut kem_1, kem_2) in kybers() {
+            let mut shared_secret_1 = vec![0; kem_1.shared_secret_len()];
+            let mut shared_secret_2 = vec![0; kem_2.shared_secret_len()];
+            let mut ciphertext = vec![0; kem_1.ciphertext_len()];
+            let bad_ciphertext = vec![0; kem_1.ciphertext_len()];
+
+            kem_1.generate(&mut rng).unwrap();
+            let (ss1_len, ct_len) =
+                kem_2.encapsulate(kem_1.pubkey(), &mut shared_secret_1, &mut ciphertext).unwrap();
+            let ss2_len = kem_1.decapsulate(&bad_ciphertext, &mut shared_secret_2).unwrap();
+
+            assert_ne!(shared_secret_1, shared_secret_2, "{}", kem_1.name());
+            assert_eq!(ss1_len, shared_secret_1.len());
+            assert_eq!(ss2_len, shared_secret_2.len());
+            assert_eq!(ss1_len, ss2_len);
+            assert_eq!(ct_len, ciphertext.len());
+        }
     }
 }
@@ -0,0 +1,608 @@
+use super::CryptoResolver;
+use crate::{
+    constants::{CIPHERKEYLEN, MAXHASHLEN, TAGLEN},
+    params::{CipherChoice, DHChoice, HashChoice},
+    types::{default_rekey, Cipher, Dh, Hash, Random},
+};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    derive::Deriver,
+    ec::{EcGroup, EcKey, EcPoint},
+    hash::{Hasher, MessageDigest},
+    kdf::{hkdf, HkdfMode},
+    md::Md,
+    nid::Nid,
+    pkey::PKey,
+    rand::rand_bytes,
+    sign::Signer,
+    symm::{decrypt_aead, encrypt_aead, Cipher as SymmCipher},
+};
+use std::ptr;
+
+/// A resolver for the FIPS-approved primitives (P-256, AES-GCM, SHA-256 and SHA-384), backed by
+/// the system [OpenSSL](https://www.openssl.org/) 3.
+///
+/// Randomness, key agreement, encryption, hashing, HMAC and HKDF all go through OpenSSL's EVP
+/// interfaces. They only run in OpenSSL's validated FIPS provider if OpenSSL is configured to
+/// load it and to require it by default (`default_properties = fips=yes`), so the resolver checks
+/// for that (see [`fips_active()`](Self::fips_active)) and resolves nothing otherwise, failing
+/// builds with `Error::Init`. This is the default resolver with the `fips` feature.
+#[derive(Default)]
+pub struct FipsResolver {
+    /// Skips the FIPS provider check, so the primitives can be tested with the default provider.
+    #[cfg(test)]
+    unchecked: bool,
+}
+
+impl FipsResolver {
+    /// Whether OpenSSL's FIPS provider is active: OpenSSL's default properties require
+    /// `fips=yes`, and a FIPS provider is loaded to serve them.
+    pub fn fips_active() -> bool {
+        openssl::init();
+        // Safe to call with the null (default) library context once OpenSSL is initialized.
+        let required =
+            unsafe { openssl_sys::EVP_default_properties_is_fips_enabled(ptr::null_mut()) == 1 };
+        // With `fips=yes` required, fetching a digest fails unless a FIPS provider serves it.
+        required && Hasher::new(MessageDigest::sha256()).is_ok()
+    }
+
+    fn active(&self) -> bool {
+        #[cfg(test)]
+        if self.unchecked {
+            return true;
+        }
+        Self::fips_active()
+    }
+}
+
+impl CryptoResolver for FipsResolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        if !self.active() {
+            return None;
+        }
+        Some(Box::new(OpensslRng))
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        if !self.active() {
+            return None;
+        }
+        match *choice {
+            DHChoice::P256 => Some(Box::new(DhP256::default())),
+            _ => None,
+        }
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        if !self.active() {
+            return None;
+        }
+        match *choice {
+            HashChoice::SHA256 => Some(Box::new(HashSha2::sha256())),
+            HashChoice::SHA384 => Some(Box::new(HashSha2::sha384())),
+            _ => None,
+        }
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        if !self.active() {
+            return None;
+        }
+        match *choice {
+            CipherChoice::AESGCM => Some(Box::new(CipherAesGcm::default())),
+            _ => None,
+        }
+    }
+}
+
+/// OpenSSL's DRBG.
+struct OpensslRng;
+
+impl rand_core::RngCore for OpensslRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).unwrap();
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        rand_bytes(dest).map_err(rand_core::Error::new)
+    }
+}
+
+impl rand_core::CryptoRng for OpensslRng {}
+
+impl Random for OpensslRng {}
+
+/// ECDH over P-256.
+///
+/// Public keys are just the 32-byte x-coordinate, like X25519's u-coordinate: the shared secret
+/// (the x-coordinate of the product) is the same for either point with that x-coordinate, so
+/// public keys and DH outputs have the same length, as Noise expects.
+#[derive(Default)]
+struct DhP256 {
+    privkey: [u8; 32],
+    pubkey:  [u8; 32],
+}
+
+impl DhP256 {
+    fn group() -> EcGroup {
+        EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("P-256 unavailable")
+    }
+
+    /// The private key, if it's a valid scalar.
+    fn ec_key(&self) -> Option<EcKey<openssl::pkey::Private>> {
+        let group = Self::group();
+        let mut ctx = BigNumContext::new().ok()?;
+        let mut order = BigNum::new().ok()?;
+        group.order(&mut order, &mut ctx).ok()?;
+        let scalar = BigNum::from_slice(&self.privkey).ok()?;
+        if scalar.num_bits() == 0 || scalar >= order {
+            return None;
+        }
+        let mut public = EcPoint::new(&group).ok()?;
+        public.mul_generator2(&group, &scalar, &mut ctx).ok()?;
+        EcKey::from_private_components(&group, &scalar, &public).ok()
+    }
+
+    fn derive_pubkey(&mut self) -> Result<(), ()> {
+        let key = self.ec_key().ok_or(())?;
+        let mut ctx = BigNumContext::new().map_err(|_| ())?;
+        let (mut x, mut y) = (BigNum::new().map_err(|_| ())?, BigNum::new().map_err(|_| ())?);
+        key.public_key()
+            .affine_coordinates(&Self::group(), &mut x, &mut y, &mut ctx)
+            .map_err(|_| ())?;
+        let x = x.to_vec_padded(32).map_err(|_| ())?;
+        self.pubkey.copy_from_slice(&x);
+        Ok(())
+    }
+}
+
+impl Dh for DhP256 {
+    fn name(&self) -> &'static str {
+        "P256"
+    }
+
+    fn pub_len(&self) -> usize {
+        32
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        if self.derive_pubkey().is_err() {
+            self.pubkey = [0; 32];
+        }
+    }
+
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        copy_slices!(pubkey, &mut self.pubkey);
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        // Rejection sampling, though a random 32 bytes is at least the group order with
+        // probability about 2^-32.
+        loop {
+            rng.try_fill_bytes(&mut self.privkey).map_err(|_| ())?;
+            if self.derive_pubkey().is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        let group = Self::group();
+        let mut ctx = BigNumContext::new().map_err(|_| ())?;
+        let mut compressed = [0u8; 33];
+        compressed[0] = 0x02;
+        compressed[1..].copy_from_slice(&pubkey[..32]);
+        let point = EcPoint::from_bytes(&group, &compressed, &mut ctx).map_err(|_| ())?;
+        let peer = EcKey::from_public_key(&group, &point).map_err(|_| ())?;
+        let peer = PKey::from_ec_key(peer).map_err(|_| ())?;
+        let local = PKey::from_ec_key(self.ec_key().ok_or(())?).map_err(|_| ())?;
+
+        let mut deriver = Deriver::new(&local).map_err(|_| ())?;
+        deriver.set_peer(&peer).map_err(|_| ())?;
+        match deriver.derive(&mut out[..32]) {
+            Ok(32) => Ok(()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// AES256-GCM, with Noise's big-endian nonce.
+#[derive(Default)]
+struct CipherAesGcm {
+    key: [u8; 32],
+}
+
+impl CipherAesGcm {
+    fn nonce(nonce: u64) -> [u8; 12] {
+        let mut nonce_bytes = [0u8; 12];
+        copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[4..]);
+        nonce_bytes
+    }
+}
+
+impl Cipher for CipherAesGcm {
+    fn name(&self) -> &'static str {
+        "AESGCM"
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        copy_slices!(key, &mut self.key)
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        let mut tag = [0u8; TAGLEN];
+        let ciphertext = encrypt_aead(
+            SymmCipher::aes_256_gcm(),
+            &self.key,
+            Some(&Self::nonce(nonce)),
+            authtext,
+            plaintext,
+            &mut tag,
+        )
+        .expect("Encryption failed!");
+
+        copy_slices!(ciphertext, out);
+        copy_slices!(tag, &mut out[plaintext.len()..]);
+        plaintext.len() + TAGLEN
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        let plaintext = in_out[..plaintext_len].to_vec();
+        self.encrypt(nonce, authtext, &plaintext, in_out)
+    }
+
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext.len() - TAGLEN;
+        let plaintext = decrypt_aead(
+            SymmCipher::aes_256_gcm(),
+            &self.key,
+            Some(&Self::nonce(nonce)),
+            authtext,
+            &ciphertext[..message_len],
+            &ciphertext[message_len..],
+        )
+        .map_err(|_| ())?;
+
+        copy_slices!(plaintext, out);
+        Ok(message_len)
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
+}
+
+/// SHA-256 or SHA-384, through OpenSSL's EVP digests, with HMAC and HKDF through OpenSSL's
+/// rather than built from the digest.
+struct HashSha2 {
+    digest: MessageDigest,
+    md:     &'static openssl::md::MdRef,
+    name:   &'static str,
+    hasher: Hasher,
+}
+
+impl HashSha2 {
+    fn sha256() -> Self {
+        Self::new(MessageDigest::sha256(), Md::sha256(), "SHA256")
+    }
+
+    fn sha384() -> Self {
+        Self::new(MessageDigest::sha384(), Md::sha384(), "SHA384")
+    }
+
+    fn new(digest: MessageDigest, md: &'static openssl::md::MdRef, name: &'static str) -> Self {
+        HashSha2 { digest, md, name, hasher: Hasher::new(digest).expect("digest unavailable") }
+    }
+}
+
+impl Hash for HashSha2 {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn block_len(&self) -> usize {
+        self.digest.block_size()
+    }
+
+    fn hash_len(&self) -> usize {
+        self.digest.size()
+    }
+
+    fn reset(&mut self) {
+        self.hasher = Hasher::new(self.digest).expect("digest unavailable");
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        self.hasher.update(data).expect("digest failed");
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        let hash = self.hasher.finish().expect("digest failed");
+        copy_slices!(hash, out)
+    }
+
+    fn hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
+        let key = PKey::hmac(key).expect("HMAC key rejected");
+        let mut signer = Signer::new(self.digest, &key).expect("HMAC unavailable");
+        signer.update(data).expect("HMAC failed");
+        let mac = signer.sign_to_vec().expect("HMAC failed");
+        copy_slices!(mac, out)
+    }
+
+    /// Noise's HKDF is RFC 5869's, with the chaining key as the salt and no info, so this is a
+    /// single call to OpenSSL's HKDF for all the outputs.
+    fn hkdf(
+        &mut self,
+        chaining_key: &[u8],
+        input_key_material: &[u8],
+        outputs: usize,
+        out1: &mut [u8],
+        out2: &mut [u8],
+        out3: &mut [u8],
+    ) {
+        let hash_len = self.hash_len();
+        let mut okm = [0u8; 3 * MAXHASHLEN];
+        let okm = &mut okm[..outputs * hash_len];
+        hkdf(
+            self.md,
+            input_key_material,
+            Some(chaining_key),
+            None,
+            HkdfMode::ExtractAndExpand,
+            None,
+            okm,
+        )
+        .expect("HKDF failed");
+        for (out, chunk) in [out1, out2, out3].iter_mut().zip(okm.chunks(hash_len)) {
+            copy_slices!(chunk, out);
+        }
+        okm.iter_mut().for_each(|byte| *byte = 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{Error, PatternProblem},
+        params::{check_protocol_name, NoiseParams},
+        Builder,
+    };
+    use hex::FromHex;
+
+    #[test]
+    fn test_fips_names() {
+        for name in &["Noise_XX_P256_AESGCM_SHA256", "Noise_IKpsk2_P256_AESGCM_SHA384"] {
+            assert!(check_protocol_name(name).is_ok());
+            assert!(name.parse::<NoiseParams>().unwrap().is_fips_approved());
+        }
+        for name in &[
+            "Noise_XX_25519_AESGCM_SHA256",
+            "Noise_XX_P256_ChaChaPoly_SHA256",
+            "Noise_XX_P256_AESGCM_SHA512",
+            "Noise_XX_P256_AESGCM_BLAKE2s",
+            "Noise_XXsig_P256+Ed25519_AESGCM_SHA256",
+        ] {
+            assert!(matches!(check_protocol_name(name), Err(PatternProblem::NotFipsApproved)));
+            assert!(matches!(
+                name.parse::<NoiseParams>(),
+                Err(Error::Pattern(PatternProblem::NotFipsApproved))
+            ));
+        }
+        // Names that are simply invalid still say so.
+        assert!(matches!(
+            "Noise_XX_P256_AESGCM_SHA1".parse::<NoiseParams>(),
+            Err(Error::Pattern(PatternProblem::UnsupportedHashType))
+        ));
+    }
+
+    fn unchecked() -> FipsResolver {
+        FipsResolver { unchecked: true }
+    }
+
+    #[test]
+    fn test_requires_fips_provider() {
+        let params: NoiseParams = "Noise_NN_P256_AESGCM_SHA256".parse().unwrap();
+        if FipsResolver::fips_active() {
+            assert!(Builder::new(params).build_initiator().is_ok());
+        } else {
+            assert!(FipsResolver::default().resolve_hash(&HashChoice::SHA256).is_none());
+            assert!(matches!(Builder::new(params).build_initiator(), Err(Error::Init(_))));
+        }
+    }
+
+    #[test]
+    fn test_hmac_hkdf() {
+        // The default implementations, built on the digest alone, are the reference.
+        struct Reference(HashSha2);
+        impl Hash for Reference {
+            fn name(&self) -> &'static str {
+                self.0.name()
+            }
+
+            fn block_len(&self) -> usize {
+                self.0.block_len()
+            }
+
+            fn hash_len(&self) -> usize {
+                self.0.hash_len()
+            }
+
+            fn reset(&mut self) {
+                self.0.reset()
+            }
+
+            fn input(&mut self, data: &[u8]) {
+                self.0.input(data)
+            }
+
+            fn result(&mut self, out: &mut [u8]) {
+                self.0.result(out)
+            }
+        }
+
+        for (mut hash, mut reference) in [
+            (HashSha2::sha256(), Reference(HashSha2::sha256())),
+            (HashSha2::sha384(), Reference(HashSha2::sha384())),
+        ] {
+            let (mut ours, mut theirs) = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+            hash.hmac(b"key", b"data", &mut ours);
+            reference.hmac(b"key", b"data", &mut theirs);
+            assert_eq!(ours, theirs);
+
+            let ck = [7u8; MAXHASHLEN];
+            for (outputs, ikm) in [(1, &b""[..]), (2, &b"ikm"[..]), (3, &[1u8; 32][..])] {
+                let mut ours = [[0u8; MAXHASHLEN]; 3];
+                let mut theirs = [[0u8; MAXHASHLEN]; 3];
+                let [o1, o2, o3] = &mut ours;
+                hash.hkdf(&ck[..hash.hash_len()], ikm, outputs, o1, o2, o3);
+                let [t1, t2, t3] = &mut theirs;
+                reference.hkdf(&ck[..hash.hash_len()], ikm, outputs, t1, t2, t3);
+                assert_eq!(ours, theirs);
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_test() {
+        use crate::resolvers::{self_test, KatOutcome, Primitive};
+
+        let report = self_test(&unchecked());
+        assert!(report.passed());
+        assert_eq!(report.outcome(Primitive::Dh(DHChoice::P256)), Some(KatOutcome::Passed));
+        assert_eq!(report.outcome(Primitive::Hash(HashChoice::SHA384)), Some(KatOutcome::Passed));
+    }
+
+    #[test]
+    fn test_handshake() {
+        let params: NoiseParams = "Noise_XXpsk3_P256_AESGCM_SHA384".parse().unwrap();
+        let psk = [7u8; 32];
+        let builder = || Builder::with_resolver(params.clone(), Box::new(unchecked()));
+        let i_key = builder().generate_keypair().unwrap();
+        let r_key = builder().generate_keypair().unwrap();
+        let mut i =
+            builder().local_private_key(&i_key.private).psk(3, &psk).build_initiator().unwrap();
+        let mut r =
+            builder().local_private_key(&r_key.private).psk(3, &psk).build_responder().unwrap();
+
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = i.write_message(&[], &mut msg).unwrap();
+        r.read_message(&msg[..len], &mut buf).unwrap();
+        let len = r.write_message(&[], &mut msg).unwrap();
+        i.read_message(&msg[..len], &mut buf).unwrap();
+        let len = i.write_message(&[], &mut msg).unwrap();
+        r.read_message(&msg[..len], &mut buf).unwrap();
+        assert_eq!(r.get_remote_static().unwrap(), &i_key.public[..]);
+
+        let mut i = i.into_transport_mode().unwrap();
+        let mut r = r.into_transport_mode().unwrap();
+        let len = i.write_message(b"fips", &mut msg).unwrap();
+        let len = r.read_message(&msg[..len], &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"fips");
+    }
+
+    #[test]
+    fn test_sha384() {
+        let mut output = [0u8; 48];
+        let mut hasher = HashSha2::sha384();
+        hasher.input(b"abc");
+        hasher.result(&mut output);
+        assert_eq!(
+            hex::encode(output),
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+             8086072ba1e7cc2358baeca134c825a7"
+        );
+    }
+
+    #[test]
+    fn test_aesgcm() {
+        // AES256-GCM tests - gcm-spec.pdf, Test Case 14
+        let mut cipher = CipherAesGcm::default();
+        cipher.set(&[0u8; 32]);
+        let mut ciphertext = [0u8; 32];
+        cipher.encrypt(0, &[], &[0u8; 16], &mut ciphertext);
+        assert_eq!(
+            hex::encode(ciphertext),
+            "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919"
+        );
+
+        let mut plaintext = [1u8; 16];
+        assert_eq!(cipher.decrypt(0, &[], &ciphertext, &mut plaintext), Ok(16));
+        assert_eq!(plaintext, [0u8; 16]);
+        ciphertext[0] ^= 1;
+        assert!(cipher.decrypt(0, &[], &ciphertext, &mut plaintext).is_err());
+    }
+
+    #[test]
+    fn test_p256() {
+        // RFC 5903, section 8.1
+        let mut initiator = DhP256::default();
+        initiator.set(
+            &<[u8; 32]>::from_hex(
+                "c88f01f510d9ac3f70a292daa2316de544e9aab8afe84049c62a9c57862d1433",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            hex::encode(initiator.pubkey()),
+            "dad0b65394221cf9b051e1feca5787d098dfe637fc90b9ef945d0c3772581180"
+        );
+        let mut responder = DhP256::default();
+        responder.set(
+            &<[u8; 32]>::from_hex(
+                "c6ef9c5d78ae012a011164acb397ce2088685d8f06bf9be0b283ab46476bee53",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            hex::encode(responder.pubkey()),
+            "d12dfb5289c8d4f81208b70270398c342296970a0bccb74c736fc7554494bf63"
+        );
+
+        let (mut shared1, mut shared2) = ([0u8; 32], [0u8; 32]);
+        initiator.dh(responder.pubkey(), &mut shared1).unwrap();
+        responder.dh(initiator.pubkey(), &mut shared2).unwrap();
+        assert_eq!(
+            hex::encode(shared1),
+            "d6840f6b42f6edafd13116e0e12565202fef8e9ece7dce03812464d04b9442de"
+        );
+        assert_eq!(shared1, shared2);
+
+        // Not a valid x-coordinate on the curve.
+        assert!(initiator.dh(&[0xff; 32], &mut shared1).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use super::CryptoResolver;
+use crate::{
+    constants::{CIPHERKEYLEN, TAGLEN},
+    params::{CipherChoice, DHChoice, HashChoice},
+    types::{default_rekey, Cipher, Dh, Hash, Random},
+};
+use core::num::NonZeroU32;
+use embedded_hal::blocking::rng::Read;
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
+
+type RngFactory = Box<dyn Fn() -> Box<dyn Random> + Send + Sync>;
+type AesFactory = Box<dyn Fn() -> Box<dyn AesPeripheral> + Send + Sync>;
+
+/// An AES-256 block cipher engine, usually an MCU's on-chip AES peripheral.
+///
+/// Only the forward direction is needed: [`HardwareResolver`] runs GCM on top of it, with GHASH
+/// in constant-time software. Each cipher the resolver hands out gets its own engine and keeps
+/// its key loaded in it, so an engine that shares a single peripheral with others has to reload
+/// its key before each use.
+pub trait AesPeripheral: Send + Sync {
+    /// Load an AES-256 key.
+    fn set_key(&mut self, key: &[u8; 32]);
+
+    /// Encrypt a single block in place with the loaded key.
+    fn encrypt_block(&self, block: &mut [u8; 16]);
+}
+
+/// A [`Random`] provider backed by an `embedded-hal` TRNG.
+///
+/// The TRNG is used as-is, with no conditioning or health tests, so it should be one whose output
+/// the vendor documents as suitable for key generation.
+pub struct HardwareRng<T>(Mutex<T>);
+
+impl<T: Read + Send> HardwareRng<T> {
+    /// Wrap a TRNG peripheral.
+    pub fn new(trng: T) -> Self {
+        Self(Mutex::new(trng))
+    }
+
+    /// Give the TRNG peripheral back.
+    pub fn into_inner(self) -> T {
+        self.0.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: Read + Send> rand_core::RngCore for HardwareRng<T> {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).unwrap();
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        let trng = self.0.get_mut().unwrap_or_else(|e| e.into_inner());
+        trng.read(dest).map_err(|_| {
+            rand_core::Error::from(NonZeroU32::new(rand_core::Error::CUSTOM_START).unwrap())
+        })
+    }
+}
+
+impl<T: Read + Send> rand_core::CryptoRng for HardwareRng<T> {}
+
+impl<T: Read + Send> Random for HardwareRng<T> {}
+
+/// A resolver for the primitives an MCU can provide in hardware: randomness from a TRNG and
+/// AES-GCM on an AES peripheral.
+///
+/// Both are optional hooks, registered as factories that are called once per resolved primitive.
+/// Everything else resolves to `None`, so this is meant to be combined with another resolver
+/// through [`FallbackResolver`](super::FallbackResolver).
+#[derive(Default)]
+pub struct HardwareResolver {
+    rng: Option<RngFactory>,
+    aes: Option<AesFactory>,
+}
+
+impl HardwareResolver {
+    /// Create a resolver with no hooks, which resolves nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve the RNG with `factory`, e.g. one returning a [`HardwareRng`].
+    pub fn rng<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> Box<dyn Random> + Send + Sync + 'static,
+    {
+        self.rng = Some(Box::new(factory));
+        self
+    }
+
+    /// Resolve AESGCM to GCM over the AES engines `factory` returns.
+    pub fn aes<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> Box<dyn AesPeripheral> + Send + Sync + 'static,
+    {
+        self.aes = Some(Box::new(factory));
+        self
+    }
+}
+
+impl CryptoResolver for HardwareResolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        self.rng.as_ref().map(|factory| factory())
+    }
+
+    fn resolve_dh(&self, _choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        None
+    }
+
+    fn resolve_hash(&self, _choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        None
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        match (*choice, &self.aes) {
+            (CipherChoice::AESGCM, Some(factory)) => {
+                Some(Box::new(CipherAesGcm { engine: factory(), hash_key: 0 }))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// AES256-GCM with the block cipher on an [`AesPeripheral`].
+struct CipherAesGcm {
+    engine:   Box<dyn AesPeripheral>,
+    hash_key: u128,
+}
+
+impl CipherAesGcm {
+    fn counter_block(nonce: u64, counter: u32) -> [u8; 16] {
+        let mut block = [0u8; 16];
+        copy_slices!(&nonce.to_be_bytes(), &mut block[4..12]);
+        copy_slices!(&counter.to_be_bytes(), &mut block[12..]);
+        block
+    }
+
+    /// XOR `data` with the keystream, starting from counter 2 as GCM does.
+    fn apply_keystream(&self, nonce: u64, data: &mut [u8]) {
+        for (i, chunk) in data.chunks_mut(16).enumerate() {
+            let mut keystream = Self::counter_block(nonce, 2 + i as u32);
+            self.engine.encrypt_block(&mut keystream);
+            for (byte, key) in chunk.iter_mut().zip(keystream.iter()) {
+                *byte ^= key;
+            }
+        }
+    }
+
+    fn tag(&self, nonce: u64, authtext: &[u8], ciphertext: &[u8]) -> [u8; TAGLEN] {
+        let mut ghash = 0u128;
+        for data in [authtext, ciphertext] {
+            for chunk in data.chunks(16) {
+                let mut block = [0u8; 16];
+                copy_slices!(chunk, &mut block);
+                ghash = gf128_mul(ghash ^ u128::from_be_bytes(block), self.hash_key);
+            }
+        }
+        let lengths = (((authtext.len() as u128) * 8) << 64) | ((ciphertext.len() as u128) * 8);
+        ghash = gf128_mul(ghash ^ lengths, self.hash_key);
+
+        let mut tag = Self::counter_block(nonce, 1);
+        self.engine.encrypt_block(&mut tag);
+        (u128::from_be_bytes(tag) ^ ghash).to_be_bytes()
+    }
+}
+
+/// Multiplication in GCM's GF(2^128), without secret-dependent branches or lookups.
+fn gf128_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+    let mut product = 0u128;
+    let mut v = y;
+    for i in (0..128).rev() {
+        product ^= v & 0u128.wrapping_sub((x >> i) & 1);
+        v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+    }
+    product
+}
+
+impl Cipher for CipherAesGcm {
+    fn name(&self) -> &'static str {
+        "AESGCM"
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        let mut aes_key = [0u8; 32];
+        copy_slices!(key, &mut aes_key);
+        self.engine.set_key(&aes_key);
+
+        let mut hash_key = [0u8; 16];
+        self.engine.encrypt_block(&mut hash_key);
+        self.hash_key = u128::from_be_bytes(hash_key);
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        self.apply_keystream(nonce, &mut in_out[..plaintext_len]);
+        let tag = self.tag(nonce, authtext, &in_out[..plaintext_len]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
+
+        plaintext_len + TAGLEN
+    }
+
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let message_len = ciphertext.len() - TAGLEN;
+        let tag = self.tag(nonce, authtext, &ciphertext[..message_len]);
+        if !bool::from(tag.ct_eq(&ciphertext[message_len..])) {
+            return Err(());
+        }
+
+        copy_slices!(ciphertext[..message_len], out);
+        self.apply_keystream(nonce, &mut out[..message_len]);
+        Ok(message_len)
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::RngCore;
+
+    /// A software stand-in for an AES peripheral.
+    #[cfg(feature = "cipher-aesgcm")]
+    #[derive(Default)]
+    struct SoftAes(Option<aes_gcm::aes::Aes256>);
+
+    #[cfg(feature = "cipher-aesgcm")]
+    impl AesPeripheral for SoftAes {
+        fn set_key(&mut self, key: &[u8; 32]) {
+            use aes_gcm::aes::cipher::NewBlockCipher;
+            self.0 = Some(aes_gcm::aes::Aes256::new(key.into()));
+        }
+
+        fn encrypt_block(&self, block: &mut [u8; 16]) {
+            use aes_gcm::aes::cipher::BlockEncrypt;
+            self.0.as_ref().unwrap().encrypt_block(block.into());
+        }
+    }
+
+    /// A TRNG that counts up, or fails once it runs dry.
+    struct CountingTrng(u8);
+
+    impl Read for CountingTrng {
+        type Error = ();
+
+        fn read(&mut self, buffer: &mut [u8]) -> Result<(), ()> {
+            for byte in buffer {
+                *byte = self.0.checked_add(1).ok_or(())?;
+                self.0 = *byte;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rng() {
+        let resolver = HardwareResolver::new().rng(|| Box::new(HardwareRng::new(CountingTrng(0))));
+        let mut rng = resolver.resolve_rng().unwrap();
+        let mut bytes = [0u8; 4];
+        rng.fill_bytes(&mut bytes);
+        assert_eq!(bytes, [1, 2, 3, 4]);
+        assert!(rng.try_fill_bytes(&mut [0u8; 255]).is_err());
+        assert!(resolver.resolve_cipher(&CipherChoice::AESGCM).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "cipher-aesgcm")]
+    fn test_aesgcm() {
+        // AES256-GCM tests - gcm-spec.pdf, Test Case 14
+        let resolver = HardwareResolver::new().aes(|| Box::new(SoftAes::default()));
+        let mut cipher = resolver.resolve_cipher(&CipherChoice::AESGCM).unwrap();
+        cipher.set(&[0u8; 32]);
+        let mut ciphertext = [0u8; 32];
+        cipher.encrypt(0, &[], &[0u8; 16], &mut ciphertext);
+        assert_eq!(
+            hex::encode(ciphertext),
+            "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919"
+        );
+
+        let mut plaintext = [1u8; 16];
+        assert_eq!(cipher.decrypt(0, &[], &ciphertext, &mut plaintext), Ok(16));
+        assert_eq!(plaintext, [0u8; 16]);
+        ciphertext[0] ^= 1;
+        assert!(cipher.decrypt(0, &[], &ciphertext, &mut plaintext).is_err());
+        assert!(crate::resolvers::self_test(&resolver).passed());
+    }
+
+    #[test]
+    #[cfg(feature = "cipher-aesgcm")]
+    fn test_matches_default_resolver() {
+        let resolver = HardwareResolver::new().aes(|| Box::new(SoftAes::default()));
+        let mut hardware = resolver.resolve_cipher(&CipherChoice::AESGCM).unwrap();
+        let mut software =
+            crate::resolvers::DefaultResolver.resolve_cipher(&CipherChoice::AESGCM).unwrap();
+        hardware.set(&[7u8; 32]);
+        software.set(&[7u8; 32]);
+
+        let plaintext: Vec<u8> = (0..100).collect();
+        for len in [0, 1, 15, 16, 17, 100] {
+            let mut expected = [0u8; 116];
+            let mut actual = [0u8; 116];
+            software.encrypt(42, b"authtext", &plaintext[..len], &mut expected);
+            hardware.encrypt(42, b"authtext", &plaintext[..len], &mut actual);
+            assert_eq!(expected[..len + TAGLEN], actual[..len + TAGLEN]);
+        }
+    }
+}
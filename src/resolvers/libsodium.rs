@@ -4,8 +4,9 @@ use byteorder::{ByteOrder, LittleEndian};
 
 use super::CryptoResolver;
 use crate::{
+    constants::CIPHERKEYLEN,
     params::{CipherChoice, DHChoice, HashChoice},
-    types::{Cipher, Dh, Hash, Random},
+    types::{default_rekey, Cipher, Dh, Hash, Random},
 };
 
 use sodiumoxide::crypto::{
@@ -89,15 +90,23 @@ impl Dh for SodiumDh25519 {
         self.pubkey = sodium_curve25519::scalarmult_base(&self.privkey);
     }
 
-    fn generate(&mut self, rng: &mut dyn Random) {
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        self.privkey = sodium_curve25519::Scalar::from_slice(privkey)
+            .expect("Can't construct private key for Dh25519");
+        self.pubkey = sodium_curve25519::GroupElement::from_slice(pubkey)
+            .expect("Can't construct public key for Dh25519");
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
         let mut privkey_bytes = [0; 32];
-        rng.fill_bytes(&mut privkey_bytes);
+        rng.try_fill_bytes(&mut privkey_bytes).map_err(|_| ())?;
 
         Self::convert_to_private_key(&mut privkey_bytes);
 
         self.privkey = sodium_curve25519::Scalar::from_slice(&privkey_bytes)
             .expect("Can't construct private key for Dh25519");
         self.pubkey = sodium_curve25519::scalarmult_base(&self.privkey);
+        Ok(())
     }
 
     fn pubkey(&self) -> &[u8] {
@@ -178,6 +187,10 @@ impl Cipher for SodiumChaChaPoly {
             Err(_) => Err(()),
         }
     }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
 }
 
 // Hash Sha256.
@@ -289,10 +302,10 @@ mod tests {
 
         // Create two keypairs.
         let mut keypair_a = SodiumDh25519::default();
-        keypair_a.generate(&mut rng);
+        keypair_a.generate(&mut rng).unwrap();
 
         let mut keypair_b = SodiumDh25519::default();
-        keypair_b.generate(&mut rng);
+        keypair_b.generate(&mut rng).unwrap();
 
         // Create shared secrets with public keys of each other.
         let mut our_shared_secret = [0u8; 32];
@@ -0,0 +1,253 @@
+//! ML-KEM (FIPS 203) for the default resolver, run in the system OpenSSL, which must be 3.5 or
+//! later.
+//!
+//! Both the key generation seed and the encapsulation randomness are drawn from the handshake's
+//! RNG, so a handshake with a fixed RNG is as reproducible with ML-KEM as it is without.
+
+use crate::types::{Kem, Random};
+use foreign_types::ForeignTypeRef;
+use openssl::{
+    pkey::{KeyType, PKey, Private},
+    pkey_ctx::PkeyCtx,
+};
+use std::{os::raw::c_char, ptr};
+use zeroize::Zeroizing;
+
+/// One of the ML-KEM parameter sets, with the sizes FIPS 203 gives for it.
+pub(super) struct MlKem {
+    name:           &'static str,
+    key_type:       KeyType,
+    pub_len:        usize,
+    ciphertext_len: usize,
+    key:            Option<PKey<Private>>,
+    pubkey:         Vec<u8>,
+}
+
+impl MlKem {
+    pub(super) fn mlkem512() -> Self {
+        Self::new("MLKEM512", KeyType::ML_KEM_512, 800, 768)
+    }
+
+    pub(super) fn mlkem768() -> Self {
+        Self::new("MLKEM768", KeyType::ML_KEM_768, 1184, 1088)
+    }
+
+    pub(super) fn mlkem1024() -> Self {
+        Self::new("MLKEM1024", KeyType::ML_KEM_1024, 1568, 1568)
+    }
+
+    fn new(name: &'static str, key_type: KeyType, pub_len: usize, ciphertext_len: usize) -> Self {
+        MlKem { name, key_type, pub_len, ciphertext_len, key: None, pubkey: Vec::new() }
+    }
+
+    /// Encapsulates to `pubkey`, with `ikme` as the 32 bytes of randomness FIPS 203 calls `m`.
+    fn encapsulate_with_ikme(
+        &self,
+        ikme: &[u8; 32],
+        pubkey: &[u8],
+        shared_secret_out: &mut [u8],
+        ciphertext_out: &mut [u8],
+    ) -> Result<(usize, usize), ()> {
+        if pubkey.len() != self.pub_len {
+            return Err(());
+        }
+        let pubkey = PKey::public_key_from_raw_bytes_ex(None, self.key_type, None, pubkey)
+            .map_err(|_| ())?;
+        let ctx = PkeyCtx::new(&pubkey).map_err(|_| ())?;
+        let (mut ciphertext_len, mut shared_secret_len) =
+            (ciphertext_out.len(), shared_secret_out.len());
+        let ok = unsafe {
+            let params = [
+                openssl_sys::OSSL_PARAM_construct_octet_string(
+                    b"ikme\0".as_ptr() as *const c_char,
+                    ikme.as_ptr() as *mut _,
+                    ikme.len(),
+                ),
+                openssl_sys::OSSL_PARAM_construct_end(),
+            ];
+            openssl_sys::EVP_PKEY_encapsulate_init(ctx.as_ptr(), params.as_ptr()) == 1
+                && openssl_sys::EVP_PKEY_encapsulate(
+                    ctx.as_ptr(),
+                    ciphertext_out.as_mut_ptr(),
+                    &mut ciphertext_len,
+                    shared_secret_out.as_mut_ptr(),
+                    &mut shared_secret_len,
+                ) == 1
+        };
+        if !ok {
+            return Err(());
+        }
+        Ok((shared_secret_len, ciphertext_len))
+    }
+}
+
+impl Kem for MlKem {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn pub_len(&self) -> usize {
+        self.pub_len
+    }
+
+    fn ciphertext_len(&self) -> usize {
+        self.ciphertext_len
+    }
+
+    fn shared_secret_len(&self) -> usize {
+        32
+    }
+
+    /// Derives the key pair from 64 bytes of `rng`, the seeds FIPS 203 calls `d` and `z`.
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        let mut seed = Zeroizing::new([0u8; 64]);
+        rng.try_fill_bytes(&mut seed[..]).map_err(|_| ())?;
+        let key =
+            PKey::private_key_from_seed(None, self.key_type, None, &seed[..]).map_err(|_| ())?;
+        self.pubkey = key.raw_public_key().map_err(|_| ())?;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    /// Encapsulates with randomness from OpenSSL's DRBG.
+    fn encapsulate(
+        &self,
+        pubkey: &[u8],
+        shared_secret_out: &mut [u8],
+        ciphertext_out: &mut [u8],
+    ) -> Result<(usize, usize), ()> {
+        let mut ikme = Zeroizing::new([0u8; 32]);
+        openssl::rand::rand_priv_bytes(&mut ikme[..]).map_err(|_| ())?;
+        self.encapsulate_with_ikme(&ikme, pubkey, shared_secret_out, ciphertext_out)
+    }
+
+    fn encapsulate_with_rng(
+        &self,
+        rng: &mut dyn Random,
+        pubkey: &[u8],
+        shared_secret_out: &mut [u8],
+        ciphertext_out: &mut [u8],
+    ) -> Result<(usize, usize), ()> {
+        let mut ikme = Zeroizing::new([0u8; 32]);
+        rng.try_fill_bytes(&mut ikme[..]).map_err(|_| ())?;
+        self.encapsulate_with_ikme(&ikme, pubkey, shared_secret_out, ciphertext_out)
+    }
+
+    fn decapsulate(&self, ciphertext: &[u8], shared_secret_out: &mut [u8]) -> Result<usize, ()> {
+        let key = self.key.as_ref().ok_or(())?;
+        if ciphertext.len() != self.ciphertext_len {
+            return Err(());
+        }
+        let ctx = PkeyCtx::new(key).map_err(|_| ())?;
+        let mut shared_secret_len = shared_secret_out.len();
+        let ok = unsafe {
+            openssl_sys::EVP_PKEY_decapsulate_init(ctx.as_ptr(), ptr::null()) == 1
+                && openssl_sys::EVP_PKEY_decapsulate(
+                    ctx.as_ptr(),
+                    shared_secret_out.as_mut_ptr(),
+                    &mut shared_secret_len,
+                    ciphertext.as_ptr(),
+                    ciphertext.len(),
+                ) == 1
+        };
+        if !ok {
+            return Err(());
+        }
+        Ok(shared_secret_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::{CryptoRng, RngCore};
+
+    /// Hands out fixed bytes, then fails.
+    struct FixedBytes(Vec<u8>);
+
+    impl RngCore for FixedBytes {
+        fn next_u32(&mut self) -> u32 {
+            unimplemented!()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unimplemented!()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.try_fill_bytes(dest).unwrap()
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            if dest.len() > self.0.len() {
+                let code = std::num::NonZeroU32::new(rand_core::Error::CUSTOM_START).unwrap();
+                return Err(code.into());
+            }
+            dest.copy_from_slice(&self.0[..dest.len()]);
+            self.0.drain(..dest.len());
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for FixedBytes {}
+    impl Random for FixedBytes {}
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // Key generation vector 0 of the ML-KEM-768 known-answer tests, seeded with d || z.
+    #[test]
+    fn test_mlkem768_keygen() {
+        let mut kem = MlKem::mlkem768();
+        let mut rng = FixedBytes(unhex(concat!(
+            "6dbbc4375136df3b07f7c70e639e223e177e7fd53b161b3f4d57791794f12624",
+            "f696484048ec21f96cf50a56d0759c448f3779752f0383d37449690694cf7a68",
+        )));
+        kem.generate(&mut rng).unwrap();
+        let expected = unhex("01f60af1dc8e6360ae78b59d4a5042eb9145a269046d6236b8304f305c2d9dcb189fe5a62df89b2f5a7bce3bbc753c1e78f730a99869f809aba856b676b707b26601d1d909bab32451494eb7d0a2153a6350b79789a9b115f83ea12037256562f06a1d5aba378da77039d3bdecaca8e6a22a49050a76300a0267cdb38b7ac77903c50ca53b99283cac6b95fba651b11a4d1a692e4072965060587669f253b1bb182e661446168ac60221894660020e9bb5f5b7124a0303e2543ea3ea6ce97a2482b255ca346fb27a847b33b93f3ab2d33064c6e6632d1a23f1144e907b246b479f4a5c928929a1e24150f5241258a5b67766a66f6a33846495907828ebe44ecc5b73124071ba479073910410a16d5d5696b48b194752979795772a91c348f502b37aa650983ebb89bf3c081ff273544129c9137a6e1834c8f2e7ce14c7870c53c05b9b94ecd38e6645911b0912336863ec168831f811881075cf38a59de4b5c738aa6ef03d779b295588cfb62491cc7b3e08b48473354f9ac8061c152a9e205997499b970b69bce66fe42bca2924ccdf0103d0a4c39193c2df25118d72b17aab26b0c60d4cd2c306ca4696c185de05035f4a09cf970aecc8cc93436f83b1aeaf452c41929a2eabc151938f74c93b858546df2264eeeab602e04a85c522f8fb1a5214afd8d4cae57a47b6f381a23126bd9917173128af917f1d483691c450d1151cfe9a1492d473ed862e27da92500c86a20019e9f975e4f54ad319ba2c5630c4014219d7ba235456fe530140193d662445e6a941d1e238567ba8d4d95ab1c7447d690821876d017270cfb169f2d792f03c800720697b410ab41c66f2b24585125655eb10aa1087ffcb7750cb887ad4467377500a6a7d3a82976b415a54469577b4138d919b03f4c9a4d3390bdcb6f1717a5fa4ab25a34f4ba5039bb22c7f3c234ea4427347aa7251464e631904d7cac4784f78b49d5f4a104a301809a779f6466131f9c62bb67147f4cd4973a6aa1c29ae6a8647b6268be089fe048ce990cd638743d285c889a707f581b63af41731f0246b054bc4b47aab01b6842a2709d02e8158ab90f48b69d136082b34cb0673b74aa3f54508ed029fb8f5045ee0639e150ee3b3c85f68a310ec0441980100b42abf2bad10d4a9e0c7b2bc5bbcaf73cbcdc49dc2c949111936779b178974a0392947745a47189bc3fa8a679c80af964a9f9b1b56577274a2a669d2da6704aa496af407fa1aa964cc3dc3140f5f959a7ea974bdb1b83e48a99c0a3e2d75b0669b5c1278962540609166266da18886fc237af30cefd569dbe399e6652e45f06a5dfc9a758a4987088ff8e38a3cf36b9d988f0e070b68d0b88f7bcc41306080d889780c7e238895ccaa4f3577225cca4c8a9330ce613e717798c9670924b271ac402b51538b8b5967ac490dcab5300e6c54d6a3632f3b973e4186ee1a7e2e85649185b26370c387235c4df28a9937a49d4078bf883f4e6346cb3251d9e13f1bda087b285afaa80e262641c5527b0a184b8bc84a62e577314658e2029d850064f7a7b81f253e7cc124a9c5b039dc9b179a80c2f6aee6ea0815172537331a57b505baa76ff5b4c1f0da754b6194f4b39a9b18730d3cdab925d691ed77a8db9927ea233ac2a12744fdc27e5d221b9369adb325d8");
+        assert_eq!(kem.pubkey(), &expected[..]);
+        assert!(kem.generate(&mut rng).is_err());
+    }
+
+    #[test]
+    fn test_mlkem_roundtrip() {
+        for new in &[MlKem::mlkem512, MlKem::mlkem768, MlKem::mlkem1024] {
+            let mut alice = new();
+            alice.generate(&mut FixedBytes(vec![1; 64])).unwrap();
+            let bob = new();
+
+            let mut ciphertext = vec![0u8; bob.ciphertext_len()];
+            let (mut ss_alice, mut ss_bob) = ([0u8; 32], [0u8; 32]);
+            let encapsulate = |ciphertext: &mut [u8], ss: &mut [u8]| {
+                bob.encapsulate_with_rng(
+                    &mut FixedBytes(vec![2; 32]),
+                    alice.pubkey(),
+                    ss,
+                    ciphertext,
+                )
+            };
+            assert_eq!(encapsulate(&mut ciphertext, &mut ss_bob), Ok((32, bob.ciphertext_len())));
+            assert_eq!(alice.decapsulate(&ciphertext, &mut ss_alice), Ok(32));
+            assert_eq!(ss_alice, ss_bob);
+
+            // The same randomness encapsulates to the same ciphertext.
+            let (mut again, mut ss_again) = (vec![0u8; bob.ciphertext_len()], [0u8; 32]);
+            encapsulate(&mut again, &mut ss_again).unwrap();
+            assert_eq!((again, ss_again), (ciphertext.clone(), ss_bob));
+
+            // A tampered ciphertext implicitly rejects, to a different secret.
+            ciphertext[0] ^= 1;
+            alice.decapsulate(&ciphertext, &mut ss_alice).unwrap();
+            assert_ne!(ss_alice, ss_bob);
+
+            assert!(bob.decapsulate(&ciphertext, &mut ss_alice).is_err());
+            assert!(alice.decapsulate(&ciphertext[1..], &mut ss_alice).is_err());
+            assert!(bob.encapsulate(&alice.pubkey()[1..], &mut ss_bob, &mut ciphertext).is_err());
+        }
+    }
+}
@@ -1,30 +1,68 @@
 //! The wrappers around the default collection of cryptography and entropy providers.
 
+/// AEGIS for the default resolver.
+#[cfg(feature = "cipher-aegis")]
+mod aegis;
+/// An ARMv8 crypto extension primitive resolver.
+#[cfg(feature = "armv8-resolver")]
+mod armv8;
+/// Ascon for the default resolver.
+#[cfg(any(feature = "cipher-ascon", feature = "hash-ascon"))]
+mod ascon;
+/// NEON ChaCha20-Poly1305 for the default resolver on aarch64.
+#[cfg(all(feature = "cipher-chachapoly", target_arch = "aarch64", not(feature = "forbid-unsafe")))]
+mod chacha_neon;
 /// The default primitive resolver.
-#[cfg(feature = "default-resolver")]
+#[cfg(feature = "default-resolver-core")]
 mod default;
+/// A FIPS-approved primitive resolver.
+#[cfg(feature = "fips")]
+mod fips;
+/// A resolver for MCU TRNGs and AES peripherals.
+#[cfg(feature = "hardware-resolver")]
+mod hardware;
 /// A libsodium primitive resolver.
 #[cfg(feature = "libsodium-resolver")]
 mod libsodium;
+/// ML-KEM for the default resolver, through OpenSSL.
+#[cfg(feature = "mlkem")]
+mod mlkem;
 /// A ring primitive resolver.
 #[cfg(feature = "ring-resolver")]
 mod ring;
+/// secp256k1 for the default resolver, through OpenSSL.
+#[cfg(feature = "dh-secp256k1")]
+mod secp256k1;
+/// Known-answer tests for resolved primitives.
+mod self_test;
+/// A resolver built on formally verified field arithmetic.
+#[cfg(feature = "verified-resolver")]
+mod verified;
 
 #[cfg(feature = "hfs")]
 use crate::params::KemChoice;
 #[cfg(feature = "hfs")]
 use crate::types::Kem;
 use crate::{
-    params::{CipherChoice, DHChoice, HashChoice},
-    types::{Cipher, Dh, Hash, Random},
+    params::{CipherChoice, DHChoice, HashChoice, SigChoice},
+    types::{Cipher, Dh, Hash, Random, Sign},
 };
 
-#[cfg(feature = "default-resolver")]
-pub use self::default::DefaultResolver;
+#[cfg(feature = "armv8-resolver")]
+pub use self::armv8::Armv8Resolver;
+#[cfg(feature = "default-resolver-core")]
+pub use self::default::{DefaultResolver, Implementation};
+#[cfg(feature = "fips")]
+pub use self::fips::FipsResolver;
+#[cfg(feature = "hardware-resolver")]
+pub use self::hardware::{AesPeripheral, HardwareResolver, HardwareRng};
 #[cfg(feature = "libsodium-resolver")]
 pub use self::libsodium::SodiumResolver;
 #[cfg(feature = "ring-resolver")]
 pub use self::ring::RingResolver;
+pub use self::self_test::{self_test, KatOutcome, KatResult, Primitive, SelfTestReport};
+#[cfg(feature = "verified-resolver")]
+pub use self::verified::VerifiedResolver;
 
 /// Boxed CryptoResolver
 pub type BoxedCryptoResolver = Box<dyn CryptoResolver + Send>;
@@ -43,6 +81,11 @@ pub trait CryptoResolver {
     /// Provide an implementation of the Cipher trait for the given CipherChoice or None if unavailable.
     fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>>;
 
+    /// Provide an implementation of the Sign trait for the given SigChoice or None if unavailable
+    fn resolve_sig(&self, _choice: &SigChoice) -> Option<Box<dyn Sign>> {
+        None
+    }
+
     /// Provide an implementation of the Kem trait for the given KemChoice or None if unavailable
     #[cfg(feature = "hfs")]
     fn resolve_kem(&self, _choice: &KemChoice) -> Option<Box<dyn Kem>> {
@@ -82,6 +125,10 @@ impl CryptoResolver for FallbackResolver {
         self.preferred.resolve_cipher(choice).or_else(|| self.fallback.resolve_cipher(choice))
     }
 
+    fn resolve_sig(&self, choice: &SigChoice) -> Option<Box<dyn Sign>> {
+        self.preferred.resolve_sig(choice).or_else(|| self.fallback.resolve_sig(choice))
+    }
+
     #[cfg(feature = "hfs")]
     fn resolve_kem(&self, choice: &KemChoice) -> Option<Box<dyn Kem>> {
         self.preferred.resolve_kem(choice).or_else(|| self.fallback.resolve_kem(choice))
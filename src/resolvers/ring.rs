@@ -1,8 +1,8 @@
 use super::CryptoResolver;
 use crate::{
-    constants::TAGLEN,
+    constants::{CIPHERKEYLEN, TAGLEN},
     params::{CipherChoice, DHChoice, HashChoice},
-    types::{Cipher, Dh, Hash, Random},
+    types::{default_rekey, Cipher, Dh, Hash, Random},
 };
 use ring::{
     aead::{self, LessSafeKey, UnboundKey},
@@ -39,6 +39,12 @@ impl CryptoResolver for RingResolver {
             CipherChoice::ChaChaPoly => Some(Box::new(CipherChaChaPoly::default())),
             #[cfg(feature = "xchachapoly")]
             CipherChoice::XChaChaPoly => None,
+            #[cfg(feature = "cipher-aegis")]
+            CipherChoice::Aegis128L | CipherChoice::Aegis256 => None,
+            #[cfg(feature = "cipher-ascon")]
+            CipherChoice::Ascon128 => None,
+            #[cfg(feature = "disco")]
+            CipherChoice::Strobe => None,
         }
     }
 }
@@ -152,6 +158,27 @@ impl Cipher for CipherAESGCM {
             Ok(out0.len())
         }
     }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let mut nonce_bytes = [0u8; 12];
+        copy_slices!(&nonce.to_be_bytes(), &mut nonce_bytes[4..]);
+        let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
+
+        self.key
+            .open_in_place(nonce, aead::Aad::from(authtext), &mut in_out[..ciphertext_len])
+            .map(|plaintext| plaintext.len())
+            .map_err(|_| ())
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
 }
 
 struct CipherChaChaPoly {
@@ -230,6 +257,27 @@ impl Cipher for CipherChaChaPoly {
             Ok(out0.len())
         }
     }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let mut nonce_bytes = [0u8; 12];
+        copy_slices!(&nonce.to_le_bytes(), &mut nonce_bytes[4..]);
+        let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
+
+        self.key
+            .open_in_place(nonce, aead::Aad::from(authtext), &mut in_out[..ciphertext_len])
+            .map(|plaintext| plaintext.len())
+            .map_err(|_| ())
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
 }
 struct HashSHA256 {
     context: digest::Context,
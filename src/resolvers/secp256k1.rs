@@ -0,0 +1,169 @@
+//! ECDH over secp256k1 for the default resolver, run in the system OpenSSL, as the Lightning
+//! Network's [BOLT #8](https://github.com/lightning/bolts/blob/master/08-transport.md) uses it.
+//!
+//! Public keys are 33-byte compressed points, and the DH output is the SHA-256 of the compressed
+//! shared point rather than its x-coordinate, which is why this doesn't go through OpenSSL's own
+//! ECDH.
+
+use crate::types::{Dh, Random};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcPoint, PointConversionForm},
+    nid::Nid,
+    sha::sha256,
+};
+
+/// The length of a compressed public key.
+const PUB_LEN: usize = 33;
+
+pub(super) struct DhSecp256k1 {
+    privkey: [u8; 32],
+    pubkey:  [u8; PUB_LEN],
+}
+
+impl Default for DhSecp256k1 {
+    fn default() -> DhSecp256k1 {
+        DhSecp256k1 { privkey: [0; 32], pubkey: [0; PUB_LEN] }
+    }
+}
+
+impl DhSecp256k1 {
+    fn group() -> EcGroup {
+        EcGroup::from_curve_name(Nid::SECP256K1).expect("secp256k1 unavailable")
+    }
+
+    /// The private key, if it's a valid scalar.
+    fn scalar(&self, group: &EcGroup, ctx: &mut BigNumContext) -> Option<BigNum> {
+        let mut order = BigNum::new().ok()?;
+        group.order(&mut order, ctx).ok()?;
+        let scalar = BigNum::from_slice(&self.privkey).ok()?;
+        if scalar.num_bits() == 0 || scalar >= order {
+            return None;
+        }
+        Some(scalar)
+    }
+
+    fn derive_pubkey(&mut self) -> Result<(), ()> {
+        let group = Self::group();
+        let mut ctx = BigNumContext::new().map_err(|_| ())?;
+        let scalar = self.scalar(&group, &mut ctx).ok_or(())?;
+        let mut public = EcPoint::new(&group).map_err(|_| ())?;
+        public.mul_generator2(&group, &scalar, &mut ctx).map_err(|_| ())?;
+        let bytes =
+            public.to_bytes(&group, PointConversionForm::COMPRESSED, &mut ctx).map_err(|_| ())?;
+        copy_slices!(bytes, &mut self.pubkey);
+        Ok(())
+    }
+}
+
+impl Dh for DhSecp256k1 {
+    fn name(&self) -> &'static str {
+        "secp256k1"
+    }
+
+    fn pub_len(&self) -> usize {
+        PUB_LEN
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn shared_len(&self) -> usize {
+        32
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        if self.derive_pubkey().is_err() {
+            self.pubkey = [0; PUB_LEN];
+        }
+    }
+
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        copy_slices!(pubkey, &mut self.pubkey);
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        // Rejection sampling, though a random 32 bytes is at least the group order with
+        // probability about 2^-128.
+        loop {
+            rng.try_fill_bytes(&mut self.privkey).map_err(|_| ())?;
+            if self.derive_pubkey().is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        let group = Self::group();
+        let mut ctx = BigNumContext::new().map_err(|_| ())?;
+        let scalar = self.scalar(&group, &mut ctx).ok_or(())?;
+        // Only compressed points, which OpenSSL checks are on the curve.
+        if pubkey.len() < PUB_LEN || !matches!(pubkey[0], 2 | 3) {
+            return Err(());
+        }
+        let peer = EcPoint::from_bytes(&group, &pubkey[..PUB_LEN], &mut ctx).map_err(|_| ())?;
+        let mut shared = EcPoint::new(&group).map_err(|_| ())?;
+        shared.mul2(&group, &peer, &scalar, &mut ctx).map_err(|_| ())?;
+        // Fails for the point at infinity.
+        let shared =
+            shared.to_bytes(&group, PointConversionForm::COMPRESSED, &mut ctx).map_err(|_| ())?;
+        copy_slices!(sha256(&shared), &mut out[..32]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The keys and the first DH of the handshake test vectors in BOLT #8's appendix.
+    #[test]
+    fn test_bolt8_keys() {
+        let mut initiator_e = DhSecp256k1::default();
+        initiator_e.set(&[0x12; 32]);
+        assert_eq!(
+            hex::encode(initiator_e.pubkey()),
+            "036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f7"
+        );
+        let mut responder_s = DhSecp256k1::default();
+        responder_s.set(&[0x21; 32]);
+        assert_eq!(
+            hex::encode(responder_s.pubkey()),
+            "028d7500dd4c12685d1f568b4c2b5048e8534b873319f3a8daa612b469132ec7f7"
+        );
+
+        let expected = "1e2fb3c8fe8fb9f262f649f64d26ecf0f2c0a805a767cf02dc2d77a6ef1fdcc3";
+        let mut out = [0u8; 32];
+        initiator_e.dh(responder_s.pubkey(), &mut out).unwrap();
+        assert_eq!(hex::encode(out), expected);
+        responder_s.dh(initiator_e.pubkey(), &mut out).unwrap();
+        assert_eq!(hex::encode(out), expected);
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let mut dh = DhSecp256k1::default();
+        let mut out = [0u8; 32];
+        // Zero isn't a valid private key.
+        dh.set(&[0; 32]);
+        assert_eq!(dh.pubkey(), &[0; PUB_LEN][..]);
+
+        dh.set(&[0x11; 32]);
+        let mut peer = [0u8; PUB_LEN];
+        // Not a compressed point, or not on the curve (nothing has x = 0).
+        assert!(dh.dh(&peer, &mut out).is_err());
+        peer[0] = 2;
+        assert!(dh.dh(&peer, &mut out).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use super::CryptoResolver;
+use crate::{
+    constants::{MAXDHLEN, MAXHASHLEN, TAGLEN},
+    params::{CipherChoice, DHChoice, HashChoice},
+    types::{Cipher, Dh, Hash},
+};
+use std::fmt;
+
+/// A primitive covered by [`self_test()`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Primitive {
+    /// A DH function.
+    Dh(DHChoice),
+    /// A cipher.
+    Cipher(CipherChoice),
+    /// A hash function.
+    Hash(HashChoice),
+}
+
+impl fmt::Display for Primitive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Primitive::Dh(choice) => write!(f, "{}", choice),
+            Primitive::Cipher(choice) => write!(f, "{}", choice),
+            Primitive::Hash(choice) => write!(f, "{}", choice),
+        }
+    }
+}
+
+/// How a primitive fared in [`self_test()`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum KatOutcome {
+    /// Every known answer matched.
+    Passed,
+    /// The implementation got a known answer wrong, so it mustn't be used.
+    Failed,
+    /// The resolver doesn't provide this primitive, so there was nothing to test.
+    Unavailable,
+}
+
+/// The outcome of the known-answer tests for one primitive.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct KatResult {
+    /// The primitive that was tested.
+    pub primitive: Primitive,
+    /// How it fared.
+    pub outcome:   KatOutcome,
+}
+
+/// The results of [`self_test()`], one per primitive.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SelfTestReport {
+    /// Every primitive that has a known-answer test, whether or not the resolver provides it.
+    pub results: Vec<KatResult>,
+}
+
+impl SelfTestReport {
+    /// Whether no primitive failed. Unavailable primitives don't count against this.
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// The primitives that got a known answer wrong.
+    pub fn failures(&self) -> impl Iterator<Item = Primitive> + '_ {
+        self.results
+            .iter()
+            .filter(|result| result.outcome == KatOutcome::Failed)
+            .map(|result| result.primitive)
+    }
+
+    /// The outcome for `primitive`, if it has a known-answer test.
+    pub fn outcome(&self, primitive: Primitive) -> Option<KatOutcome> {
+        self.results.iter().find(|result| result.primitive == primitive).map(|r| r.outcome)
+    }
+}
+
+/// Run known-answer tests on every DH function, cipher and hash function `resolver` provides.
+///
+/// Each primitive is run on inputs with published (or independently computed) outputs, the way
+/// some certification regimes require before a crypto module may be used. Nothing is cached, so
+/// call this once at startup and refuse to go on if the report didn't pass.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "default-resolver")]
+/// # {
+/// use snow::resolvers::{self_test, DefaultResolver};
+///
+/// let report = self_test(&DefaultResolver);
+/// assert!(report.passed());
+/// # }
+/// ```
+pub fn self_test(resolver: &dyn CryptoResolver) -> SelfTestReport {
+    let mut results = Vec::new();
+    for kat in DH_KATS {
+        let outcome = run(resolver.resolve_dh(&kat.choice), |dh| dh_kat(dh, kat));
+        results.push(KatResult { primitive: Primitive::Dh(kat.choice), outcome });
+    }
+    for kat in CIPHER_KATS {
+        let outcome = run(resolver.resolve_cipher(&kat.choice), |cipher| cipher_kat(cipher, kat));
+        results.push(KatResult { primitive: Primitive::Cipher(kat.choice), outcome });
+    }
+    for kat in HASH_KATS {
+        let outcome = run(resolver.resolve_hash(&kat.choice), |hash| hash_kat(hash, kat));
+        results.push(KatResult { primitive: Primitive::Hash(kat.choice), outcome });
+    }
+    SelfTestReport { results }
+}
+
+fn run<T: ?Sized>(implementation: Option<Box<T>>, kat: impl FnOnce(&mut T) -> bool) -> KatOutcome {
+    match implementation {
+        None => KatOutcome::Unavailable,
+        Some(mut implementation) => {
+            if kat(&mut implementation) {
+                KatOutcome::Passed
+            } else {
+                KatOutcome::Failed
+            }
+        },
+    }
+}
+
+struct DhKat {
+    choice:  DHChoice,
+    private: &'static str,
+    public:  &'static str,
+    peer:    &'static str,
+    shared:  &'static str,
+}
+
+struct CipherKat {
+    choice:     CipherChoice,
+    ciphertext: &'static str,
+}
+
+struct HashKat {
+    choice: HashChoice,
+    digest: &'static str,
+}
+
+const DH_KATS: &[DhKat] = &[
+    // RFC 7748, section 6.1
+    DhKat {
+        choice:  DHChoice::Curve25519,
+        private: "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+        public:  "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a",
+        peer:    "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f",
+        shared:  "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742",
+    },
+    // RFC 7748, section 6.2
+    DhKat {
+        choice:  DHChoice::Ed448,
+        private: "9a8f4925d1519f5775cf46b04b5800d4ee9ee8bae8bc5565d498c28dd9c9baf5\
+                  74a9419744897391006382a6f127ab1d9ac2d8c0a598726b",
+        public:  "9b08f7cc31b7e3e67d22d5aea121074a273bd2b83de09c63faa73d2c22c5d9bb\
+                  c836647241d953d40c5b12da88120d53177f80e532c41fa0",
+        peer:    "3eb7a829b0cd20f5bcfc0b599b6feccf6da4627107bdb0d4f345b43027d8b972\
+                  fc3e34fb4232a13ca706dcb57aec3dae07bdc1c67bf33609",
+        shared:  "07fff4181ac6cc95ec1c16a94a0f74d12da232ce40a77552281d282bb60c0b56\
+                  fd2464c335543936521c24403085d59a449a5037514a879d",
+    },
+    // RFC 5903, section 8.1, with public keys as x-coordinates
+    DhKat {
+        choice:  DHChoice::P256,
+        private: "c88f01f510d9ac3f70a292daa2316de544e9aab8afe84049c62a9c57862d1433",
+        public:  "dad0b65394221cf9b051e1feca5787d098dfe637fc90b9ef945d0c3772581180",
+        peer:    "d12dfb5289c8d4f81208b70270398c342296970a0bccb74c736fc7554494bf63",
+        shared:  "d6840f6b42f6edafd13116e0e12565202fef8e9ece7dce03812464d04b9442de",
+    },
+];
+
+// Each cipher encrypts CIPHER_KAT_PLAINTEXT under the key 00 01 02 ... 1f, nonce 1 and associated
+// data "ad". The ciphertexts were computed with independent implementations.
+const CIPHER_KAT_PLAINTEXT: &[u8] = b"snow self-test";
+const CIPHER_KAT_NONCE: u64 = 1;
+const CIPHER_KAT_AD: &[u8] = b"ad";
+
+const CIPHER_KATS: &[CipherKat] = &[
+    CipherKat {
+        choice:     CipherChoice::ChaChaPoly,
+        ciphertext: "ec399c289559ca744f9bda3921e1630fea8cb67b42952d40c3c421119d16",
+    },
+    #[cfg(feature = "xchachapoly")]
+    CipherKat {
+        choice:     CipherChoice::XChaChaPoly,
+        ciphertext: "41063853ace41388d2d2ccde7b6e5b662e3a983ee338f9536ee21a9beb22",
+    },
+    CipherKat {
+        choice:     CipherChoice::AESGCM,
+        ciphertext: "66b8d08b648755726803255c9fd27995bc9f722da6ebac5033a6880f3d57",
+    },
+];
+
+// Each hash function hashes "abc", per FIPS 180-4 and RFC 7693.
+const HASH_KAT_INPUT: &[u8] = b"abc";
+
+const HASH_KATS: &[HashKat] = &[
+    HashKat {
+        choice: HashChoice::SHA256,
+        digest: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+    },
+    HashKat {
+        choice: HashChoice::SHA384,
+        digest: "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+                 8086072ba1e7cc2358baeca134c825a7",
+    },
+    HashKat {
+        choice: HashChoice::SHA512,
+        digest: "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+    },
+    HashKat {
+        choice: HashChoice::Blake2s,
+        digest: "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982",
+    },
+    HashKat {
+        choice: HashChoice::Blake2b,
+        digest: "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+                 7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+    },
+];
+
+/// Decode a vector. The vectors are constants, so this can't fail.
+fn unhex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).expect("invalid test vector"))
+        .collect()
+}
+
+fn dh_kat(dh: &mut dyn Dh, kat: &DhKat) -> bool {
+    let shared = unhex(kat.shared);
+    let mut out = [0u8; MAXDHLEN];
+    dh.set(&unhex(kat.private));
+    dh.pubkey() == &unhex(kat.public)[..]
+        && dh.dh(&unhex(kat.peer), &mut out).is_ok()
+        && out[..shared.len()] == shared[..]
+}
+
+fn cipher_kat(cipher: &mut dyn Cipher, kat: &CipherKat) -> bool {
+    let key: Vec<u8> = (0..32).collect();
+    let expected = unhex(kat.ciphertext);
+    let mut ciphertext = [0u8; 64];
+    let mut plaintext = [0u8; 64];
+    cipher.set(&key);
+
+    let len =
+        cipher.encrypt(CIPHER_KAT_NONCE, CIPHER_KAT_AD, CIPHER_KAT_PLAINTEXT, &mut ciphertext);
+    if len != CIPHER_KAT_PLAINTEXT.len() + TAGLEN || ciphertext[..len] != expected[..] {
+        return false;
+    }
+    let decrypted = cipher.decrypt(CIPHER_KAT_NONCE, CIPHER_KAT_AD, &expected, &mut plaintext);
+    if decrypted != Ok(CIPHER_KAT_PLAINTEXT.len())
+        || &plaintext[..len - TAGLEN] != CIPHER_KAT_PLAINTEXT
+    {
+        return false;
+    }
+    // A corrupted tag has to be rejected too.
+    ciphertext[len - 1] ^= 1;
+    cipher.decrypt(CIPHER_KAT_NONCE, CIPHER_KAT_AD, &ciphertext[..len], &mut plaintext).is_err()
+}
+
+fn hash_kat(hash: &mut dyn Hash, kat: &HashKat) -> bool {
+    let expected = unhex(kat.digest);
+    let mut out = [0u8; MAXHASHLEN];
+    hash.reset();
+    hash.input(HASH_KAT_INPUT);
+    hash.result(&mut out);
+    hash.hash_len() == expected.len() && out[..expected.len()] == expected[..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Random;
+
+    /// Resolves ChaChaPoly to a cipher that gets the answer wrong, and nothing else.
+    struct BrokenResolver;
+
+    struct BrokenCipher;
+
+    impl Cipher for BrokenCipher {
+        fn name(&self) -> &'static str {
+            "ChaChaPoly"
+        }
+
+        fn set(&mut self, _key: &[u8]) {}
+
+        fn encrypt(&self, _: u64, _: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+            copy_slices!(plaintext, out);
+            plaintext.len() + TAGLEN
+        }
+
+        fn decrypt(
+            &self,
+            _: u64,
+            _: &[u8],
+            ciphertext: &[u8],
+            out: &mut [u8],
+        ) -> Result<usize, ()> {
+            copy_slices!(ciphertext, out);
+            Ok(ciphertext.len() - TAGLEN)
+        }
+    }
+
+    impl CryptoResolver for BrokenResolver {
+        fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+            None
+        }
+
+        fn resolve_dh(&self, _choice: &DHChoice) -> Option<Box<dyn Dh>> {
+            None
+        }
+
+        fn resolve_hash(&self, _choice: &HashChoice) -> Option<Box<dyn Hash>> {
+            None
+        }
+
+        fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+            match *choice {
+                CipherChoice::ChaChaPoly => Some(Box::new(BrokenCipher)),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_broken_resolver() {
+        let report = self_test(&BrokenResolver);
+        assert!(!report.passed());
+        assert_eq!(
+            report.failures().collect::<Vec<_>>(),
+            vec![Primitive::Cipher(CipherChoice::ChaChaPoly)]
+        );
+        assert_eq!(
+            report.outcome(Primitive::Hash(HashChoice::SHA256)),
+            Some(KatOutcome::Unavailable)
+        );
+    }
+
+    #[cfg(feature = "default-resolver")]
+    #[test]
+    fn test_default_resolver() {
+        let report = self_test(&super::super::DefaultResolver);
+        assert!(report.passed());
+        for primitive in &[
+            Primitive::Dh(DHChoice::Curve25519),
+            Primitive::Cipher(CipherChoice::ChaChaPoly),
+            Primitive::Cipher(CipherChoice::AESGCM),
+            Primitive::Hash(HashChoice::SHA384),
+            Primitive::Hash(HashChoice::Blake2b),
+        ] {
+            assert_eq!(report.outcome(*primitive), Some(KatOutcome::Passed), "{}", primitive);
+        }
+    }
+}
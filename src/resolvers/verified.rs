@@ -0,0 +1,492 @@
+use super::CryptoResolver;
+use crate::{
+    constants::{CIPHERKEYLEN, TAGLEN},
+    params::{CipherChoice, DHChoice, HashChoice},
+    types::{default_rekey, Cipher, Dh, Hash, Random},
+};
+use core::convert::TryInto;
+use fiat_crypto::{
+    curve25519_64::{
+        fiat_25519_add, fiat_25519_carry_mul, fiat_25519_carry_scmul_121666,
+        fiat_25519_carry_square, fiat_25519_from_bytes, fiat_25519_loose_field_element as Loose,
+        fiat_25519_relax, fiat_25519_selectznz, fiat_25519_sub,
+        fiat_25519_tight_field_element as Tight, fiat_25519_to_bytes,
+    },
+    poly1305_64::{
+        fiat_poly1305_add, fiat_poly1305_carry_mul, fiat_poly1305_from_bytes,
+        fiat_poly1305_loose_field_element, fiat_poly1305_relax, fiat_poly1305_tight_field_element,
+        fiat_poly1305_to_bytes,
+    },
+};
+use subtle::ConstantTimeEq;
+
+/// A resolver for Curve25519 and ChaChaPoly built on formally verified field arithmetic.
+///
+/// Only the field arithmetic is verified: the additions, subtractions, multiplications,
+/// squarings and conversions modulo 2^255 - 19 (for X25519) and 2^130 - 5 (for Poly1305) are
+/// generated by [fiat-crypto](https://github.com/mit-plv/fiat-crypto), which comes with
+/// machine-checked proofs that they're correct for all inputs and free of secret-dependent
+/// branches.
+///
+/// Nothing else is. The Montgomery ladder, the field inversion, scalar clamping, the ChaCha20
+/// rounds, the Poly1305 block handling and the AEAD construction are ordinary Rust transcribed
+/// from RFC 7748 and RFC 8439 and checked against their test vectors, just like the default
+/// resolver's. So this resolver rules out carry and reduction bugs in the arithmetic, the
+/// classic source of rare wrong results, but isn't a verified implementation of X25519 or
+/// ChaCha20-Poly1305 as a whole.
+///
+/// Everything else resolves to `None`, so this is meant to be combined with another resolver
+/// (for the RNG and hash, at least) through [`FallbackResolver`](super::FallbackResolver).
+#[derive(Default)]
+pub struct VerifiedResolver;
+
+impl CryptoResolver for VerifiedResolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        None
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        match *choice {
+            DHChoice::Curve25519 => Some(Box::new(Dh25519::default())),
+            _ => None,
+        }
+    }
+
+    fn resolve_hash(&self, _choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        None
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        match *choice {
+            CipherChoice::ChaChaPoly => Some(Box::new(CipherChaChaPoly::default())),
+            _ => None,
+        }
+    }
+}
+
+/// X25519 over fiat-crypto's field arithmetic.
+#[derive(Default)]
+struct Dh25519 {
+    privkey: [u8; 32],
+    pubkey:  [u8; 32],
+}
+
+const BASEPOINT: [u8; 32] = {
+    let mut basepoint = [0u8; 32];
+    basepoint[0] = 9;
+    basepoint
+};
+
+impl Dh for Dh25519 {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn pub_len(&self) -> usize {
+        32
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        self.pubkey = x25519(&self.privkey, &BASEPOINT);
+    }
+
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        copy_slices!(privkey, &mut self.privkey);
+        copy_slices!(pubkey, &mut self.pubkey);
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        rng.try_fill_bytes(&mut self.privkey).map_err(|_| ())?;
+        self.pubkey = x25519(&self.privkey, &BASEPOINT);
+        Ok(())
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        let result = x25519(&self.privkey, pubkey[..32].try_into().unwrap());
+        copy_slices!(&result, out);
+        Ok(())
+    }
+}
+
+fn fe_add(a: &Tight, b: &Tight) -> Loose {
+    let mut out = Loose([0; 5]);
+    fiat_25519_add(&mut out, a, b);
+    out
+}
+
+fn fe_sub(a: &Tight, b: &Tight) -> Loose {
+    let mut out = Loose([0; 5]);
+    fiat_25519_sub(&mut out, a, b);
+    out
+}
+
+fn fe_mul(a: &Loose, b: &Loose) -> Tight {
+    let mut out = Tight([0; 5]);
+    fiat_25519_carry_mul(&mut out, a, b);
+    out
+}
+
+fn fe_square(a: &Loose) -> Tight {
+    let mut out = Tight([0; 5]);
+    fiat_25519_carry_square(&mut out, a);
+    out
+}
+
+fn fe_relax(a: &Tight) -> Loose {
+    let mut out = Loose([0; 5]);
+    fiat_25519_relax(&mut out, a);
+    out
+}
+
+/// `a` squared `n` times.
+fn fe_pow2k(a: &Tight, n: usize) -> Tight {
+    let mut out = *a;
+    for _ in 0..n {
+        out = fe_square(&fe_relax(&out));
+    }
+    out
+}
+
+fn fe_mul_tight(a: &Tight, b: &Tight) -> Tight {
+    fe_mul(&fe_relax(a), &fe_relax(b))
+}
+
+/// `z^(p - 2)`, the inverse of `z`, with the usual addition chain.
+fn fe_invert(z: &Tight) -> Tight {
+    let z2 = fe_pow2k(z, 1);
+    let z9 = fe_mul_tight(&fe_pow2k(&z2, 2), z);
+    let z11 = fe_mul_tight(&z9, &z2);
+    let z_5_0 = fe_mul_tight(&fe_pow2k(&z11, 1), &z9);
+    let z_10_0 = fe_mul_tight(&fe_pow2k(&z_5_0, 5), &z_5_0);
+    let z_20_0 = fe_mul_tight(&fe_pow2k(&z_10_0, 10), &z_10_0);
+    let z_40_0 = fe_mul_tight(&fe_pow2k(&z_20_0, 20), &z_20_0);
+    let z_50_0 = fe_mul_tight(&fe_pow2k(&z_40_0, 10), &z_10_0);
+    let z_100_0 = fe_mul_tight(&fe_pow2k(&z_50_0, 50), &z_50_0);
+    let z_200_0 = fe_mul_tight(&fe_pow2k(&z_100_0, 100), &z_100_0);
+    let z_250_0 = fe_mul_tight(&fe_pow2k(&z_200_0, 50), &z_50_0);
+    fe_mul_tight(&fe_pow2k(&z_250_0, 5), &z11)
+}
+
+/// Swap `a` and `b` if `swap` is 1, in constant time.
+fn fe_cswap(swap: u8, a: &mut Tight, b: &mut Tight) {
+    let (mut new_a, mut new_b) = ([0u64; 5], [0u64; 5]);
+    fiat_25519_selectznz(&mut new_a, swap, &a.0, &b.0);
+    fiat_25519_selectznz(&mut new_b, swap, &b.0, &a.0);
+    a.0 = new_a;
+    b.0 = new_b;
+}
+
+/// The X25519 function of RFC 7748, section 5.
+fn x25519(scalar: &[u8; 32], u: &[u8; 32]) -> [u8; 32] {
+    let mut k = *scalar;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+    let mut u = *u;
+    u[31] &= 127;
+
+    let mut x1 = Tight([0; 5]);
+    fiat_25519_from_bytes(&mut x1, &u);
+    let mut x2 = Tight([1, 0, 0, 0, 0]);
+    let mut z2 = Tight([0; 5]);
+    let mut x3 = x1;
+    let mut z3 = Tight([1, 0, 0, 0, 0]);
+    let mut swap = 0u8;
+
+    for t in (0..255).rev() {
+        let k_t = (k[t / 8] >> (t % 8)) & 1;
+        swap ^= k_t;
+        fe_cswap(swap, &mut x2, &mut x3);
+        fe_cswap(swap, &mut z2, &mut z3);
+        swap = k_t;
+
+        let a = fe_add(&x2, &z2);
+        let aa = fe_square(&a);
+        let b = fe_sub(&x2, &z2);
+        let bb = fe_square(&b);
+        let e = fe_sub(&aa, &bb);
+        let c = fe_add(&x3, &z3);
+        let d = fe_sub(&x3, &z3);
+        let da = fe_mul(&d, &a);
+        let cb = fe_mul(&c, &b);
+        x3 = fe_square(&fe_add(&da, &cb));
+        z3 = fe_mul(&fe_relax(&x1), &fe_relax(&fe_square(&fe_sub(&da, &cb))));
+        x2 = fe_mul(&fe_relax(&aa), &fe_relax(&bb));
+        // a24 * E + AA, with AA = BB + E, is 121666 * E + BB.
+        let mut e121666 = Tight([0; 5]);
+        fiat_25519_carry_scmul_121666(&mut e121666, &e);
+        z2 = fe_mul(&e, &fe_add(&bb, &e121666));
+    }
+    fe_cswap(swap, &mut x2, &mut x3);
+    fe_cswap(swap, &mut z2, &mut z3);
+
+    let mut out = [0u8; 32];
+    fiat_25519_to_bytes(&mut out, &fe_mul_tight(&x2, &fe_invert(&z2)));
+    out
+}
+
+/// ChaCha20-Poly1305 (RFC 8439) with Poly1305 over fiat-crypto's field arithmetic.
+#[derive(Default)]
+struct CipherChaChaPoly {
+    key: [u8; 32],
+}
+
+impl CipherChaChaPoly {
+    fn nonce(nonce: u64) -> [u8; 12] {
+        let mut nonce_bytes = [0u8; 12];
+        copy_slices!(&nonce.to_le_bytes(), &mut nonce_bytes[4..]);
+        nonce_bytes
+    }
+}
+
+impl Cipher for CipherChaChaPoly {
+    fn name(&self) -> &'static str {
+        "ChaChaPoly"
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        copy_slices!(key, &mut self.key);
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        copy_slices!(plaintext, out);
+
+        self.encrypt_in_place(nonce, authtext, out, plaintext.len())
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        let nonce = Self::nonce(nonce);
+        chacha20_xor(&self.key, 1, &nonce, &mut in_out[..plaintext_len]);
+        let tag = aead_tag(&self.key, &nonce, authtext, &in_out[..plaintext_len]);
+        copy_slices!(tag, &mut in_out[plaintext_len..]);
+
+        plaintext_len + TAGLEN
+    }
+
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let nonce = Self::nonce(nonce);
+        let message_len = ciphertext.len() - TAGLEN;
+        let tag = aead_tag(&self.key, &nonce, authtext, &ciphertext[..message_len]);
+        if !bool::from(tag.ct_eq(&ciphertext[message_len..])) {
+            return Err(());
+        }
+
+        copy_slices!(ciphertext[..message_len], out);
+        chacha20_xor(&self.key, 1, &nonce, &mut out[..message_len]);
+        Ok(message_len)
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        Some(default_rekey(self))
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block function of RFC 8439, section 2.3.
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    for (word, bytes) in initial[4..12].iter_mut().zip(key.chunks_exact(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    initial[12] = counter;
+    for (word, bytes) in initial[13..].iter_mut().zip(nonce.chunks_exact(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut block = [0u8; 64];
+    for ((out, word), initial) in block.chunks_exact_mut(4).zip(&state).zip(&initial) {
+        out.copy_from_slice(&word.wrapping_add(*initial).to_le_bytes());
+    }
+    block
+}
+
+/// XOR `data` with the ChaCha20 keystream starting at block `counter`.
+fn chacha20_xor(key: &[u8; 32], counter: u32, nonce: &[u8; 12], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let keystream = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        for (byte, key_byte) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+/// Poly1305 (RFC 8439, section 2.5) over the concatenation of `chunks`, each of which is padded
+/// to a multiple of 16 bytes unless it's the last.
+fn poly1305(key: &[u8; 32], chunks: &[&[u8]]) -> [u8; 16] {
+    let mut r_bytes = [0u8; 17];
+    r_bytes[..16].copy_from_slice(&key[..16]);
+    for i in &[3, 7, 11, 15] {
+        r_bytes[*i] &= 15;
+    }
+    for i in &[4, 8, 12] {
+        r_bytes[*i] &= 252;
+    }
+    let mut r = fiat_poly1305_tight_field_element([0; 3]);
+    fiat_poly1305_from_bytes(&mut r, &r_bytes);
+    let r = poly_relax(&r);
+
+    let mut acc = fiat_poly1305_tight_field_element([0; 3]);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let padded = i + 1 < chunks.len();
+        for block in chunk.chunks(16) {
+            let mut n_bytes = [0u8; 17];
+            n_bytes[..block.len()].copy_from_slice(block);
+            n_bytes[if padded { 16 } else { block.len() }] = 1;
+            let mut n = fiat_poly1305_tight_field_element([0; 3]);
+            fiat_poly1305_from_bytes(&mut n, &n_bytes);
+            let mut sum = fiat_poly1305_loose_field_element([0; 3]);
+            fiat_poly1305_add(&mut sum, &acc, &n);
+            fiat_poly1305_carry_mul(&mut acc, &sum, &r);
+        }
+    }
+
+    let mut acc_bytes = [0u8; 17];
+    fiat_poly1305_to_bytes(&mut acc_bytes, &acc);
+    let acc = u128::from_le_bytes(acc_bytes[..16].try_into().unwrap());
+    let s = u128::from_le_bytes(key[16..].try_into().unwrap());
+    acc.wrapping_add(s).to_le_bytes()
+}
+
+fn poly_relax(a: &fiat_poly1305_tight_field_element) -> fiat_poly1305_loose_field_element {
+    let mut out = fiat_poly1305_loose_field_element([0; 3]);
+    fiat_poly1305_relax(&mut out, a);
+    out
+}
+
+/// The tag of the AEAD construction of RFC 8439, section 2.8.
+fn aead_tag(key: &[u8; 32], nonce: &[u8; 12], authtext: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let block = chacha20_block(key, 0, nonce);
+    let poly_key: &[u8; 32] = block[..32].try_into().unwrap();
+    let mut lengths = [0u8; 16];
+    lengths[..8].copy_from_slice(&(authtext.len() as u64).to_le_bytes());
+    lengths[8..].copy_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly1305(poly_key, &[authtext, ciphertext, &lengths])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::FromHex;
+
+    fn hex32(s: &str) -> [u8; 32] {
+        <[u8; 32]>::from_hex(s).unwrap()
+    }
+
+    #[test]
+    fn test_x25519_rfc7748() {
+        // Section 5.2
+        let scalar = hex32("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let u = hex32("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+        assert_eq!(
+            hex::encode(x25519(&scalar, &u)),
+            "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"
+        );
+
+        // Section 6.1
+        let mut alice = Dh25519::default();
+        alice.set(&hex32("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a"));
+        assert_eq!(
+            hex::encode(alice.pubkey()),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+        let bob = hex32("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f");
+        let mut shared = [0u8; 32];
+        alice.dh(&bob, &mut shared).unwrap();
+        assert_eq!(
+            hex::encode(shared),
+            "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742"
+        );
+    }
+
+    #[test]
+    fn test_poly1305_rfc8439() {
+        // Section 2.5.2
+        let key = hex32("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b");
+        assert_eq!(
+            hex::encode(poly1305(&key, &[b"Cryptographic Forum Research Group"])),
+            "a8061dc1305136c6c22b8baf0c0127a9"
+        );
+    }
+
+    #[test]
+    fn test_chachapoly_rfc8439() {
+        // Section 2.8.2
+        let key = hex32("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = <[u8; 12]>::from_hex("070000004041424344454647").unwrap();
+        let aad = Vec::<u8>::from_hex("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let mut data = b"Ladies and Gentlemen of the class of '99: If I could offer you only \
+                         one tip for the future, sunscreen would be it."
+            .to_vec();
+        chacha20_xor(&key, 1, &nonce, &mut data);
+        assert!(hex::encode(&data).starts_with("d31a8d34648e60db7b86afbc53ef7ec2"));
+        assert!(hex::encode(&data).ends_with("3ff4def08e4b7a9de576d26586cec64b6116"));
+        assert_eq!(
+            hex::encode(aead_tag(&key, &nonce, &aad, &data)),
+            "1ae10b594f09e26a7e902ecbd0600691"
+        );
+    }
+
+    #[test]
+    fn test_chachapoly_round_trip() {
+        let mut cipher = CipherChaChaPoly::default();
+        cipher.set(&[7u8; 32]);
+        for len in &[0usize, 1, 15, 16, 17, 64, 65, 200] {
+            let plaintext: Vec<u8> = (0..*len as u8).collect();
+            let mut ciphertext = vec![0u8; len + TAGLEN];
+            cipher.encrypt(42, b"ad", &plaintext, &mut ciphertext);
+            let mut out = vec![0u8; *len];
+            assert_eq!(cipher.decrypt(42, b"ad", &ciphertext, &mut out), Ok(*len));
+            assert_eq!(out, plaintext);
+            assert!(cipher.decrypt(42, b"da", &ciphertext, &mut out).is_err());
+            assert!(cipher.decrypt(43, b"ad", &ciphertext, &mut out).is_err());
+        }
+    }
+}
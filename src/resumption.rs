@@ -0,0 +1,226 @@
+//! Session resumption tickets, for cheap reconnection through a PSK handshake.
+//!
+//! At the end of a full handshake, both peers derive the same resumption PSK with
+//! [`HandshakeState::resumption_psk()`](crate::HandshakeState::resumption_psk). The responder
+//! seals it into an opaque ticket with a [`TicketIssuer`] and sends the ticket to the initiator
+//! over the new transport. To reconnect, the initiator sends the ticket back in the clear (e.g.
+//! as the negotiation data of a [`HandshakeFrame`](crate::socket::HandshakeFrame)) next to the
+//! first message of a handshake that uses the PSK, like `Noise_NNpsk0`. The responder opens the
+//! ticket to recover the PSK, so it doesn't have to store anything per client.
+//!
+//! A ticket is a random salt followed by the PSK and its issue time, encrypted with the
+//! protocol's cipher under a key derived from the issuer's secret key and the salt. The
+//! protocol name is authenticated along with it, so a ticket can't be used with a different
+//! protocol.
+//!
+//! Resuming through a PSK-only pattern authenticates the peers only as whoever held the
+//! original session; it also doesn't give forward secrecy for the first message if the
+//! ticket key is later compromised. Keep [`TicketIssuer::lifetime()`] short.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, resumption::*};
+//! # #[cfg(feature = "default-resolver")]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let params: snow::params::NoiseParams = snow::doctest::NN.parse()?;
+//! # let (mut initiator, mut responder) = snow::doctest::nn_handshake()?;
+//! # let ticket_key = [9u8; 32];
+//! let resumed: snow::params::NoiseParams = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let mut issuer = TicketIssuer::new(&resumed, &ticket_key)?;
+//!
+//! // After the full handshake, the responder issues a ticket for the client to keep...
+//! let mut ticket = [0u8; TICKET_LEN];
+//! issuer.seal(&responder.resumption_psk()?, &mut ticket)?;
+//! let client_psk = initiator.resumption_psk()?;
+//!
+//! // ...and when the client comes back with it, recovers the same PSK.
+//! let psk = issuer.open(&ticket)?;
+//! assert_eq!(psk, client_psk);
+//! let responder = Builder::new(resumed).psk(0, &psk).build_responder()?;
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(feature = "default-resolver"))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    constants::{CIPHERKEYLEN, MAXHASHLEN, PSKLEN, TAGLEN},
+    error::{Error, InitStage, StateProblem},
+    params::NoiseParams,
+    resolvers::BoxedCryptoResolver,
+    types::{Cipher, Hash, Random},
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The length of the random salt each ticket's encryption key is derived from.
+pub const SALT_LEN: usize = 24;
+
+/// The length of a ticket: the salt, then the encrypted PSK and issue time.
+pub const TICKET_LEN: usize = SALT_LEN + PSKLEN + 8 + TAGLEN;
+
+/// How long tickets stay valid unless [`TicketIssuer::lifetime()`] says otherwise.
+pub const DEFAULT_TICKET_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+const LABEL_TICKET: &[u8] = b"ticket--";
+
+/// Seals resumption PSKs into tickets and opens them again.
+///
+/// Every responder that should accept a ticket needs the same secret key.
+pub struct TicketIssuer {
+    name:     String,
+    hash:     Box<dyn Hash>,
+    cipher:   Box<dyn Cipher>,
+    rng:      Box<dyn Random>,
+    key:      [u8; MAXHASHLEN],
+    lifetime: Duration,
+}
+
+impl TicketIssuer {
+    /// Create an issuer for tickets that resume sessions with `params`, using the default
+    /// crypto resolver.
+    #[cfg(feature = "default-resolver")]
+    pub fn new(params: &NoiseParams, secret_key: &[u8]) -> Result<Self, Error> {
+        Self::with_resolver(params, secret_key, Box::new(crate::resolvers::DefaultResolver))
+    }
+
+    /// Create an issuer for tickets that resume sessions with `params`, using a custom crypto
+    /// resolver.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if `secret_key` is shorter than 32 bytes.
+    pub fn with_resolver(
+        params: &NoiseParams,
+        secret_key: &[u8],
+        resolver: BoxedCryptoResolver,
+    ) -> Result<Self, Error> {
+        if secret_key.len() < CIPHERKEYLEN {
+            bail!(Error::Input);
+        }
+        let mut hash = resolver.resolve_hash(&params.hash).ok_or(InitStage::GetHashImpl)?;
+        let cipher = resolver.resolve_cipher(&params.cipher).ok_or(InitStage::GetCipherImpl)?;
+        let rng = resolver.resolve_rng().ok_or(InitStage::GetRngImpl)?;
+        let mut key = [0u8; MAXHASHLEN];
+        hash.reset();
+        hash.input(LABEL_TICKET);
+        hash.input(secret_key);
+        hash.result(&mut key);
+        Ok(TicketIssuer {
+            name: params.name.clone(),
+            hash,
+            cipher,
+            rng,
+            key,
+            lifetime: DEFAULT_TICKET_LIFETIME,
+        })
+    }
+
+    /// Set how long after being sealed a ticket can still be opened.
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Seal `psk` into a ticket written to `out`, returning its length ([`TICKET_LEN`]).
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::BufferTooSmall` if `out` can't hold the ticket, and `Error::Rng`
+    /// if the RNG fails.
+    pub fn seal(&mut self, psk: &[u8; PSKLEN], out: &mut [u8]) -> Result<usize, Error> {
+        if out.len() < TICKET_LEN {
+            bail!(Error::BufferTooSmall { needed: TICKET_LEN, got: out.len() });
+        }
+        let issued = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut plaintext = [0u8; PSKLEN + 8];
+        plaintext[..PSKLEN].copy_from_slice(psk);
+        plaintext[PSKLEN..].copy_from_slice(&issued.to_be_bytes());
+
+        let (salt, ciphertext) = out[..TICKET_LEN].split_at_mut(SALT_LEN);
+        self.rng.try_fill_bytes(salt).map_err(|_| Error::Rng)?;
+        self.set_ticket_key(salt);
+        self.cipher.encrypt(0, self.name.as_bytes(), &plaintext, ciphertext);
+        Ok(TICKET_LEN)
+    }
+
+    /// Open a ticket made by an issuer with the same secret key, returning its PSK.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::TruncatedMessage` if `ticket` is too short, `Error::Decrypt` if it
+    /// wasn't sealed with this secret key for this protocol, and `Error::State` if it has
+    /// outlived the lifetime.
+    pub fn open(&mut self, ticket: &[u8]) -> Result<[u8; PSKLEN], Error> {
+        if ticket.len() < TICKET_LEN {
+            bail!(Error::TruncatedMessage { needed: TICKET_LEN, got: ticket.len() });
+        }
+        let (salt, ciphertext) = ticket[..TICKET_LEN].split_at(SALT_LEN);
+        self.set_ticket_key(salt);
+        let mut plaintext = [0u8; PSKLEN + 8];
+        self.cipher
+            .decrypt(0, self.name.as_bytes(), ciphertext, &mut plaintext)
+            .map_err(|_| Error::Decrypt)?;
+
+        let mut issued = [0u8; 8];
+        issued.copy_from_slice(&plaintext[PSKLEN..]);
+        let issued = UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(issued));
+        match SystemTime::now().duration_since(issued) {
+            Ok(age) if age > self.lifetime => bail!(StateProblem::TicketExpired),
+            _ => {},
+        }
+        let mut psk = [0u8; PSKLEN];
+        psk.copy_from_slice(&plaintext[..PSKLEN]);
+        Ok(psk)
+    }
+
+    /// Key the cipher for the ticket with the given salt.
+    fn set_ticket_key(&mut self, salt: &[u8]) {
+        let key = self.key;
+        let mut out = [0u8; MAXHASHLEN];
+        self.hash.hmac(&key[..self.hash.hash_len()], salt, &mut out);
+        self.cipher.set(&out[..CIPHERKEYLEN]);
+    }
+}
+
+#[cfg(all(test, feature = "default-resolver"))]
+mod tests {
+    use super::*;
+
+    fn issuer(secret_key: &[u8]) -> TicketIssuer {
+        let params: NoiseParams = "Noise_NNpsk0_25519_AESGCM_SHA256".parse().unwrap();
+        TicketIssuer::new(&params, secret_key).unwrap()
+    }
+
+    #[test]
+    fn test_ticket_round_trip() {
+        let mut issuer = issuer(&[1u8; 32]);
+        let mut ticket = [0u8; TICKET_LEN];
+        assert_eq!(issuer.seal(&[7u8; PSKLEN], &mut ticket).unwrap(), TICKET_LEN);
+        assert_eq!(issuer.open(&ticket).unwrap(), [7u8; PSKLEN]);
+
+        let mut other = [0u8; TICKET_LEN];
+        issuer.seal(&[7u8; PSKLEN], &mut other).unwrap();
+        assert_ne!(ticket, other);
+    }
+
+    #[test]
+    fn test_ticket_rejected() {
+        let mut ticket = [0u8; TICKET_LEN];
+        issuer(&[1u8; 32]).seal(&[7u8; PSKLEN], &mut ticket).unwrap();
+
+        assert!(matches!(issuer(&[2u8; 32]).open(&ticket), Err(Error::Decrypt)));
+        let params: NoiseParams = "Noise_XXpsk3_25519_AESGCM_SHA256".parse().unwrap();
+        let mut other_protocol = TicketIssuer::new(&params, &[1u8; 32]).unwrap();
+        assert!(matches!(other_protocol.open(&ticket), Err(Error::Decrypt)));
+        assert!(matches!(
+            issuer(&[1u8; 32]).open(&ticket[..TICKET_LEN - 1]),
+            Err(Error::TruncatedMessage { .. })
+        ));
+
+        let mut expired = issuer(&[1u8; 32]).lifetime(Duration::from_secs(0));
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(matches!(expired.open(&ticket), Err(Error::State(StateProblem::TicketExpired))));
+    }
+}
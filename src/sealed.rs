@@ -0,0 +1,331 @@
+//! Encrypting files and blobs to a recipient's static public key, in the style of age.
+//!
+//! A [`Sealer`] runs the one message of an `N` (anonymous sender) or `X` (authenticated sender)
+//! handshake towards the recipient's public key, and encrypts the payload after it as a
+//! [`chunked`](crate::chunked) stream keyed from that handshake. An [`Opener`] holding the
+//! recipient's private key reads the handshake message back and decrypts the stream, telling
+//! whether the payload was cut short and, for `X`, whose static key sent it.
+//!
+//! The sealed payload starts with a header that names the protocol, so the opener doesn't need
+//! to be told which one was used:
+//!
+//! | bytes | contents                                    |
+//! |-------|---------------------------------------------|
+//! | 1     | length of the protocol name                 |
+//! | n     | protocol name, e.g. `Noise_N_25519_ChaChaPoly_BLAKE2s` |
+//! | 2     | big-endian length of the handshake message  |
+//! | m     | handshake message                           |
+//! | ...   | chunked stream                              |
+//!
+//! Every sealed payload gets a fresh ephemeral key, so sealing the same data twice gives
+//! unrelated outputs. Like any one-way pattern, this isn't forward secret against a later
+//! compromise of the recipient's private key, and the `X` sender authentication doesn't stop the
+//! recipient from passing a sealed payload on as if it had been sent to someone else.
+//!
+//! Errors from the Noise session surface as `io::Error`s of kind `InvalidData`, wrapping the
+//! [`Error`]. Requires the `default-resolver` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, sealed::*};
+//! # use std::io::Read;
+//! # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+//! let params: snow::params::NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let recipient = Builder::new(params.clone()).generate_keypair()?;
+//!
+//! let sealed = Sealer::new(params, &recipient.public).seal(b"attack at dawn")?;
+//!
+//! let mut opened = Opener::new(&recipient.private).reader(&sealed[..])?;
+//! let mut plaintext = Vec::new();
+//! opened.read_to_end(&mut plaintext)?;
+//! assert_eq!(plaintext, b"attack at dawn");
+//! assert_eq!(opened.sender(), None);
+//! #     Ok(())
+//! # }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{
+    chunked::{invalid, StreamReader, StreamWriter, DEFAULT_CHUNK_LEN},
+    constants::MAXMSGLEN,
+    error::{Error, PatternProblem, Prerequisite},
+    params::{HandshakePattern, NoiseParams},
+    resolvers::DefaultResolver,
+    Builder, HandshakeState,
+};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+const PROLOGUE: &[u8] = b"snow sealed";
+
+/// The stream ID of the payload within the handshake's keys.
+const STREAM_ID: u64 = 0;
+
+/// Encrypts payloads to a recipient's static public key.
+pub struct Sealer<'a> {
+    params:    NoiseParams,
+    recipient: &'a [u8],
+    sender:    Option<&'a [u8]>,
+    chunk_len: usize,
+}
+
+impl<'a> Sealer<'a> {
+    /// Seal payloads to `recipient` with `params`, which have to use the `N` pattern, or the
+    /// `X` pattern along with a [`sender()`](Self::sender) key.
+    pub fn new(params: NoiseParams, recipient: &'a [u8]) -> Self {
+        Sealer { params, recipient, sender: None, chunk_len: DEFAULT_CHUNK_LEN }
+    }
+
+    /// Authenticate payloads with the sender's static private key, for the `X` pattern.
+    pub fn sender(mut self, private_key: &'a [u8]) -> Self {
+        self.sender = Some(private_key);
+        self
+    }
+
+    /// Set how much plaintext each chunk of the stream holds. The opener has to be set up with
+    /// the same length.
+    pub fn chunk_len(mut self, len: usize) -> Self {
+        self.chunk_len = len;
+        self
+    }
+
+    /// Write the header to `out` and return the writer to encrypt the payload with. The sealed
+    /// payload is only complete once [`StreamWriter::finish()`] has been called.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `out`'s I/O errors, and with `ErrorKind::InvalidData` if the parameters
+    /// aren't for `N` or `X`, an `X` sealer has no sender key, or the handshake fails.
+    pub fn writer<W: Write>(&self, mut out: W) -> io::Result<StreamWriter<W>> {
+        let mut handshake = self.handshake().map_err(invalid)?;
+        let mut msg = [0u8; MAXMSGLEN];
+        let len = handshake.write_message(&[], &mut msg).map_err(invalid)?;
+
+        let name = self.params.name.as_bytes();
+        out.write_all(&[name.len() as u8])?;
+        out.write_all(name)?;
+        out.write_all(&(len as u16).to_be_bytes())?;
+        out.write_all(&msg[..len])?;
+        Ok(StreamWriter::new(&mut handshake, STREAM_ID, out)
+            .map_err(invalid)?
+            .chunk_len(self.chunk_len))
+    }
+
+    /// Seal `plaintext` in one go.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`writer()`](Self::writer).
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut writer = self.writer(Vec::new())?;
+        writer.write_all(plaintext)?;
+        writer.finish()
+    }
+
+    fn handshake(&self) -> Result<HandshakeState, Error> {
+        let builder = Builder::with_resolver(self.params.clone(), Box::new(DefaultResolver))
+            .prologue(PROLOGUE)
+            .remote_public_key(self.recipient);
+        match (self.params.handshake.pattern, self.sender) {
+            _ if !self.params.handshake.modifiers.list.is_empty() => {
+                bail!(PatternProblem::UnsupportedModifier)
+            },
+            (HandshakePattern::N, _) => builder.build_initiator(),
+            (HandshakePattern::X, Some(sender)) => {
+                builder.local_private_key(sender).build_initiator()
+            },
+            (HandshakePattern::X, None) => bail!(Prerequisite::LocalPrivateKey),
+            _ => bail!(PatternProblem::UnsupportedHandshakeType),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Sealer<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Sealer")
+            .field("params", &self.params.name)
+            .field("authenticated", &self.sender.is_some())
+            .field("chunk_len", &self.chunk_len)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Decrypts payloads sealed to this recipient's static key.
+pub struct Opener<'a> {
+    private_key: &'a [u8],
+    chunk_len:   usize,
+}
+
+impl<'a> Opener<'a> {
+    /// Open payloads sealed to the public key of `private_key`, with any `N` or `X` protocol.
+    pub fn new(private_key: &'a [u8]) -> Self {
+        Opener { private_key, chunk_len: DEFAULT_CHUNK_LEN }
+    }
+
+    /// Set how much plaintext each chunk of the stream holds, as the sealer did.
+    pub fn chunk_len(mut self, len: usize) -> Self {
+        self.chunk_len = len;
+        self
+    }
+
+    /// Read the header from `input` and return the reader that decrypts the payload.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `input`'s I/O errors, and with `ErrorKind::InvalidData` if the header names a
+    /// protocol that isn't supported, or isn't for `N` or `X`, or the handshake message doesn't
+    /// decrypt with this private key.
+    pub fn reader<R: Read>(&self, mut input: R) -> io::Result<Opened<R>> {
+        let mut len = [0u8; 2];
+        input.read_exact(&mut len[..1])?;
+        let mut name = vec![0u8; usize::from(len[0])];
+        input.read_exact(&mut name)?;
+        input.read_exact(&mut len)?;
+        let mut msg = vec![0u8; usize::from(u16::from_be_bytes(len))];
+        input.read_exact(&mut msg)?;
+
+        let mut handshake = self.handshake(&name, &msg).map_err(invalid)?;
+        let sender = handshake.get_remote_static().map(|s| s.to_vec());
+        let reader = StreamReader::new(&mut handshake, STREAM_ID, input).map_err(invalid)?;
+        Ok(Opened { reader: reader.chunk_len(self.chunk_len), sender })
+    }
+
+    /// Open `sealed` in one go, returning the plaintext and, for `X`, the sender's static public
+    /// key.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`reader()`](Self::reader), and with `ErrorKind::UnexpectedEof` or
+    /// `ErrorKind::InvalidData` if the payload was truncated or tampered with.
+    pub fn open(&self, sealed: &[u8]) -> io::Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let mut opened = self.reader(sealed)?;
+        let mut plaintext = Vec::new();
+        opened.read_to_end(&mut plaintext)?;
+        Ok((plaintext, opened.sender))
+    }
+
+    fn handshake(&self, name: &[u8], msg: &[u8]) -> Result<HandshakeState, Error> {
+        let params: NoiseParams = std::str::from_utf8(name).map_err(|_| Error::Input)?.parse()?;
+        if !params.handshake.modifiers.list.is_empty() {
+            bail!(PatternProblem::UnsupportedModifier);
+        }
+        if !matches!(params.handshake.pattern, HandshakePattern::N | HandshakePattern::X) {
+            bail!(PatternProblem::UnsupportedHandshakeType);
+        }
+        let mut handshake = Builder::with_resolver(params, Box::new(DefaultResolver))
+            .prologue(PROLOGUE)
+            .local_private_key(self.private_key)
+            .build_responder()?;
+        handshake.read_message(msg, &mut [])?;
+        Ok(handshake)
+    }
+}
+
+impl<'a> fmt::Debug for Opener<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Opener").field("chunk_len", &self.chunk_len).finish_non_exhaustive()
+    }
+}
+
+/// A sealed payload being decrypted, returned by [`Opener::reader()`].
+///
+/// Reading fails as [`StreamReader`] does if the payload was truncated or tampered with.
+pub struct Opened<R: Read> {
+    reader: StreamReader<R>,
+    sender: Option<Vec<u8>>,
+}
+
+impl<R: Read> Opened<R> {
+    /// The sender's static public key, if the payload was sealed with `X`.
+    ///
+    /// Only trust it once the whole payload has been read, and after checking that it's a key
+    /// you expect.
+    pub fn sender(&self) -> Option<&[u8]> {
+        self.sender.as_deref()
+    }
+
+    /// Whether the whole payload has been read and authenticated.
+    pub fn is_finished(&self) -> bool {
+        self.reader.is_finished()
+    }
+}
+
+impl<R: Read> Read for Opened<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Read> fmt::Debug for Opened<R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Opened")
+            .field("sender", &self.sender)
+            .field("reader", &self.reader)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keypair(params: &NoiseParams) -> crate::Keypair {
+        Builder::new(params.clone()).generate_keypair().unwrap()
+    }
+
+    #[test]
+    fn test_seal_open_n() {
+        let params: NoiseParams = "Noise_N_25519_AESGCM_SHA256".parse().unwrap();
+        let recipient = keypair(&params);
+        let data = vec![3u8; 300];
+        let sealed =
+            Sealer::new(params.clone(), &recipient.public).chunk_len(64).seal(&data).unwrap();
+        assert_ne!(
+            sealed,
+            Sealer::new(params, &recipient.public).chunk_len(64).seal(&data).unwrap()
+        );
+
+        let (plaintext, sender) =
+            Opener::new(&recipient.private).chunk_len(64).open(&sealed).unwrap();
+        assert_eq!(plaintext, data);
+        assert_eq!(sender, None);
+
+        let other = keypair(&"Noise_N_25519_AESGCM_SHA256".parse().unwrap());
+        let err = Opener::new(&other.private).chunk_len(64).open(&sealed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = Opener::new(&recipient.private).chunk_len(64).open(&sealed[..200]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_seal_open_x() {
+        let params: NoiseParams = "Noise_X_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let (recipient, sender) = (keypair(&params), keypair(&params));
+        assert!(Sealer::new(params.clone(), &recipient.public).seal(b"hi").is_err());
+
+        let sealed =
+            Sealer::new(params, &recipient.public).sender(&sender.private).seal(b"hi").unwrap();
+        let (plaintext, from) = Opener::new(&recipient.private).open(&sealed).unwrap();
+        assert_eq!(plaintext, b"hi");
+        assert_eq!(from.as_deref(), Some(&sender.public[..]));
+    }
+
+    #[test]
+    fn test_unsupported_patterns() {
+        let params: NoiseParams = "Noise_K_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let recipient = keypair(&params);
+        let err = Sealer::new(params, &recipient.public).seal(b"hi").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A header naming an interactive pattern is rejected before any handshake.
+        let name = b"Noise_NN_25519_ChaChaPoly_BLAKE2s";
+        let mut sealed = vec![name.len() as u8];
+        sealed.extend_from_slice(name);
+        sealed.extend_from_slice(&[0, 0]);
+        let err = Opener::new(&recipient.private).open(&sealed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,68 @@
+//! Responders with several identities, choosing which static key to answer with once the
+//! initiator has said who it's trying to reach.
+//!
+//! A server hosting several names behind one address may want a different static key for each,
+//! like TLS picks a certificate by SNI. Instead of a local private key, give the responder's
+//! [`Builder`](crate::Builder) a [`StaticKeySelector`] with
+//! [`Builder::local_key_selector()`](crate::Builder::local_key_selector). Once the responder has
+//! read the first handshake message, the selector gets the prologue and that message's payload,
+//! and returns the keypair to continue with.
+//!
+//! That only works for patterns where the responder's static key isn't needed to read the first
+//! message, i.e. where the responder sends it (`NX`, `XX`, `IX` and the like) rather than the
+//! initiator knowing it beforehand (`NK`, `XK`, `IK`, ...). The first payload isn't encrypted in
+//! those patterns, so the selector shouldn't be anything that needs to stay private.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, Keypair, selector::*};
+//! # use std::sync::Arc;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let params: snow::params::NoiseParams = "Noise_NX_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let alpha = Builder::new(params.clone()).generate_keypair()?;
+//! let beta = Builder::new(params.clone()).generate_keypair()?;
+//! let beta_public = beta.public.clone();
+//!
+//! let mut responder = Builder::new(params.clone())
+//!     .local_key_selector(Arc::new(move |_prologue: &[u8], payload: &[u8]| match payload {
+//!         b"alpha.example" => Some(alpha.clone()),
+//!         b"beta.example" => Some(beta.clone()),
+//!         _ => None,
+//!     }))
+//!     .build_responder()?;
+//! let mut initiator = Builder::new(params).build_initiator()?;
+//!
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! let len = initiator.write_message(b"beta.example", &mut msg)?;
+//! responder.read_message(&msg[..len], &mut buf)?;
+//! let len = responder.write_message(&[], &mut msg)?;
+//! initiator.read_message(&msg[..len], &mut buf)?;
+//! assert_eq!(initiator.get_remote_static(), Some(&beta_public[..]));
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::Keypair;
+
+/// Picks a responder's static keypair from what the initiator sent.
+///
+/// Implemented for closures taking the prologue and the first message's payload.
+pub trait StaticKeySelector: Send + Sync {
+    /// The keypair to answer a handshake with, given its `prologue` and the `payload` of the
+    /// first message, or `None` to refuse the handshake.
+    fn select(&self, prologue: &[u8], payload: &[u8]) -> Option<Keypair>;
+}
+
+impl<F> StaticKeySelector for F
+where
+    F: Fn(&[u8], &[u8]) -> Option<Keypair> + Send + Sync,
+{
+    fn select(&self, prologue: &[u8], payload: &[u8]) -> Option<Keypair> {
+        self(prologue, payload)
+    }
+}
@@ -0,0 +1,258 @@
+//! Tracking many concurrent sessions, for servers talking Noise to many peers at once.
+//!
+//! A [`SessionManager`] holds each session under a key of the caller's choosing: a session ID
+//! carried in the caller's framing, the peer's address, or (once it's known) the peer's remote
+//! static key. Sessions start out as half-open handshakes, are promoted to transports with
+//! [`SessionManager::complete_handshake()`] (optionally moving to a new key at the same time), and
+//! handshakes that never finish are dropped by [`SessionManager::expire_stale_handshakes()`].
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, session::SessionManager};
+//! # use std::time::Duration;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! # let params: snow::params::NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! # let key = Builder::new(params.clone()).generate_keypair()?.private;
+//! let mut sessions = SessionManager::new(Duration::from_secs(10));
+//!
+//! // A new peer connected with session ID 7.
+//! let noise = Builder::new(params).local_private_key(&key).build_responder()?;
+//! sessions.insert_handshake(7u64.to_be_bytes().to_vec(), noise);
+//! # let id = 7u64.to_be_bytes().to_vec();
+//!
+//! // ... drive sessions.handshake_mut(&id) with the peer's messages ...
+//! # let noise = sessions.handshake_mut(&id).unwrap();
+//! # let mut i = Builder::new("Noise_XX_25519_ChaChaPoly_BLAKE2s".parse()?)
+//! #     .local_private_key(&key).build_initiator()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! # let len = i.write_message(&[], &mut msg)?; noise.read_message(&msg[..len], &mut buf)?;
+//! # let len = noise.write_message(&[], &mut msg)?; i.read_message(&msg[..len], &mut buf)?;
+//! # let len = i.write_message(&[], &mut msg)?; noise.read_message(&msg[..len], &mut buf)?;
+//!
+//! // Once the handshake is done, re-key the session by the peer's identity.
+//! let remote_static = sessions.handshake_mut(&id).unwrap().get_remote_static().unwrap().to_vec();
+//! let transport = sessions.complete_handshake_as(&id, remote_static)?;
+//!
+//! // Periodically, drop handshakes that have been half-open for too long.
+//! sessions.expire_stale_handshakes();
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{error::StateProblem, Error, HandshakeState, TransportState};
+use std::{
+    collections::{hash_map, HashMap},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// A session tracked by a [`SessionManager`].
+#[derive(Debug)]
+pub enum Session {
+    /// A half-open session whose handshake hasn't been completed yet.
+    Handshake(Box<HandshakeState>),
+
+    /// An established session.
+    Transport(Box<TransportState>),
+}
+
+#[derive(Debug)]
+struct Tracked {
+    session: Session,
+    started: Instant,
+}
+
+/// A set of sessions keyed by session ID or remote static key.
+#[derive(Debug)]
+pub struct SessionManager<K = Vec<u8>> {
+    sessions:          HashMap<K, Tracked>,
+    handshake_timeout: Duration,
+}
+
+impl<K: Eq + Hash> SessionManager<K> {
+    /// Create an empty manager which considers handshakes older than `handshake_timeout` stale.
+    pub fn new(handshake_timeout: Duration) -> Self {
+        SessionManager { sessions: HashMap::new(), handshake_timeout }
+    }
+
+    /// Track a new half-open handshake, returning any session it replaces.
+    pub fn insert_handshake(&mut self, key: K, state: HandshakeState) -> Option<Session> {
+        self.insert(key, Session::Handshake(Box::new(state)))
+    }
+
+    /// Track an established transport, returning any session it replaces.
+    pub fn insert_transport(&mut self, key: K, state: TransportState) -> Option<Session> {
+        self.insert(key, Session::Transport(Box::new(state)))
+    }
+
+    fn insert(&mut self, key: K, session: Session) -> Option<Session> {
+        let entry = Tracked { session, started: Instant::now() };
+        self.sessions.insert(key, entry).map(|entry| entry.session)
+    }
+
+    /// Get the session under `key`.
+    pub fn get(&self, key: &K) -> Option<&Session> {
+        self.sessions.get(key).map(|entry| &entry.session)
+    }
+
+    /// Get the session under `key`, if it's still handshaking.
+    pub fn handshake_mut(&mut self, key: &K) -> Option<&mut HandshakeState> {
+        match self.sessions.get_mut(key).map(|entry| &mut entry.session) {
+            Some(Session::Handshake(state)) => Some(&mut **state),
+            _ => None,
+        }
+    }
+
+    /// Get the session under `key`, if it's established.
+    pub fn transport_mut(&mut self, key: &K) -> Option<&mut TransportState> {
+        match self.sessions.get_mut(key).map(|entry| &mut entry.session) {
+            Some(Session::Transport(state)) => Some(&mut **state),
+            _ => None,
+        }
+    }
+
+    /// Stop tracking the session under `key`, returning it.
+    pub fn remove(&mut self, key: &K) -> Option<Session> {
+        self.sessions.remove(key).map(|entry| entry.session)
+    }
+
+    /// Turn the finished handshake under `key` into a transport.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::UnknownSession)` if there's no handshake under
+    /// `key`, and `Error::State(StateProblem::HandshakeNotFinished)` if it isn't finished yet. In
+    /// both cases the session is left as it was.
+    pub fn complete_handshake(&mut self, key: &K) -> Result<&mut TransportState, Error>
+    where
+        K: Clone,
+    {
+        self.complete_handshake_as(key, key.clone())
+    }
+
+    /// Turn the finished handshake under `key` into a transport tracked under `new_key`
+    /// instead, e.g. to move from a session ID to the now-known remote static key. Any session
+    /// already under `new_key` is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::UnknownSession)` if there's no handshake under
+    /// `key`, and `Error::State(StateProblem::HandshakeNotFinished)` if it isn't finished yet. In
+    /// both cases the session is left as it was.
+    pub fn complete_handshake_as(
+        &mut self,
+        key: &K,
+        new_key: K,
+    ) -> Result<&mut TransportState, Error> {
+        match self.handshake_mut(key) {
+            Some(state) if !state.is_handshake_finished() => {
+                bail!(StateProblem::HandshakeNotFinished)
+            },
+            Some(_) => {},
+            None => bail!(StateProblem::UnknownSession),
+        }
+        let transport = match self.remove(key) {
+            Some(Session::Handshake(state)) => state.into_transport_mode()?,
+            _ => unreachable!("checked above"),
+        };
+        let tracked =
+            Tracked { session: Session::Transport(Box::new(transport)), started: Instant::now() };
+        let tracked = match self.sessions.entry(new_key) {
+            hash_map::Entry::Occupied(mut occupied) => {
+                occupied.insert(tracked);
+                occupied.into_mut()
+            },
+            hash_map::Entry::Vacant(vacant) => vacant.insert(tracked),
+        };
+        match &mut tracked.session {
+            Session::Transport(state) => Ok(&mut **state),
+            Session::Handshake(_) => unreachable!("just inserted"),
+        }
+    }
+
+    /// Drop every handshake that was started longer ago than the handshake timeout, returning
+    /// how many were dropped. Established sessions are never expired.
+    pub fn expire_stale_handshakes(&mut self) -> usize {
+        let before = self.sessions.len();
+        let timeout = self.handshake_timeout;
+        self.sessions.retain(|_, entry| match entry.session {
+            Session::Handshake(_) => entry.started.elapsed() < timeout,
+            Session::Transport(_) => true,
+        });
+        before - self.sessions.len()
+    }
+
+    /// The number of sessions still handshaking.
+    pub fn half_open_count(&self) -> usize {
+        self.sessions
+            .values()
+            .filter(|entry| matches!(entry.session, Session::Handshake(_)))
+            .count()
+    }
+
+    /// The number of tracked sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Whether no sessions are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::{
+        doctest::{nn_handshake, NN as PARAMS},
+        Builder,
+    };
+
+    #[test]
+    fn test_complete_handshake() {
+        let mut sessions = SessionManager::new(Duration::from_secs(60));
+        let unfinished = Builder::new(PARAMS.parse().unwrap()).build_initiator().unwrap();
+        sessions.insert_handshake(1u32, unfinished);
+        assert!(matches!(
+            sessions.complete_handshake(&1),
+            Err(Error::State(StateProblem::HandshakeNotFinished))
+        ));
+        assert!(sessions.handshake_mut(&1).is_some());
+        assert!(matches!(
+            sessions.complete_handshake(&2),
+            Err(Error::State(StateProblem::UnknownSession))
+        ));
+
+        let (i, _) = nn_handshake().unwrap();
+        sessions.insert_handshake(2, i);
+        assert_eq!(sessions.half_open_count(), 2);
+        sessions.complete_handshake_as(&2, 3).unwrap();
+        assert!(sessions.get(&2).is_none());
+        assert!(sessions.transport_mut(&3).is_some());
+        assert!(sessions.handshake_mut(&3).is_none());
+        assert_eq!((sessions.len(), sessions.half_open_count()), (2, 1));
+    }
+
+    #[test]
+    fn test_expire_stale_handshakes() {
+        let (i, r) = nn_handshake().unwrap();
+        let mut sessions = SessionManager::new(Duration::from_secs(60));
+        sessions.insert_handshake(1u32, i);
+        assert_eq!(sessions.expire_stale_handshakes(), 0);
+
+        let mut sessions = SessionManager::new(Duration::from_secs(0));
+        sessions.insert_handshake(1u32, r);
+        sessions.complete_handshake(&1).unwrap();
+        let (i, _) = nn_handshake().unwrap();
+        sessions.insert_handshake(2, i);
+        assert_eq!(sessions.expire_stale_handshakes(), 1);
+        assert!(sessions.transport_mut(&1).is_some());
+        assert_eq!(sessions.len(), 1);
+    }
+}
@@ -0,0 +1,433 @@
+//! Framing and negotiation for the [NoiseSocket](https://noisesocket.org/) protocol.
+//!
+//! NoiseSocket wraps Noise handshake messages in a small envelope that carries application
+//! defined `negotiation_data` alongside the Noise message, letting the two sides agree on which
+//! Noise protocol to run:
+//!
+//! 1. The initiator sends an *offer*: its negotiation data and the first message of the
+//!    handshake it would like to use, bound to the session by [`initial_prologue()`].
+//! 2. The responder decides on a [`Negotiation`]. It can *accept* the offer and carry on with the
+//!    handshake, *switch* to a different protocol (taking the initiator role for it, with
+//!    [`switch_prologue()`]), ask the initiator to *retry* with a different protocol (with
+//!    [`retry_prologue()`]), or *reject* the offer outright.
+//! 3. Once the handshake completes, transport messages are sent with a plain 2-byte length
+//!    prefix (see [`write_transport_frame()`]).
+//!
+//! To also hide the lengths of transport payloads, combine this with a
+//! [`PaddingPolicy`](crate::padding::PaddingPolicy), which uses the NoiseSocket padding format.
+//! To keep the length prefixes themselves from standing out to deep packet inspection, frame
+//! transport messages with a [`FrameObfuscation`], like the `SipHashObfuscation` of I2P's NTCP2
+//! (with the "siphasher" feature).
+
+use crate::{error::Error, params::NoiseParams};
+use std::convert::TryFrom;
+
+/// The prologue prefix for the initiator's first choice of protocol.
+pub const INIT1_PREFIX: &[u8] = b"NoiseSocketInit1";
+
+/// The prologue prefix for a protocol the responder switched to.
+pub const INIT2_PREFIX: &[u8] = b"NoiseSocketInit2";
+
+/// The prologue prefix for a protocol the responder asked the initiator to retry with.
+pub const INIT3_PREFIX: &[u8] = b"NoiseSocketInit3";
+
+/// The size of each big-endian length prefix in a frame.
+const LENGTH_LEN: usize = 2;
+
+/// The responder's decision on an initiator's offer.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Negotiation {
+    /// Continue the offered handshake.
+    Accept,
+
+    /// Start a new handshake with the given parameters, with the responder as its initiator.
+    Switch(NoiseParams),
+
+    /// Ask the initiator to start a new handshake with the given parameters.
+    Retry(NoiseParams),
+
+    /// Refuse the offer. The responder should send its negotiation data and close the
+    /// connection.
+    Reject,
+}
+
+impl Negotiation {
+    /// Whether the responder's reply to this decision carries a Noise message.
+    ///
+    /// `Retry` and `Reject` replies have an empty Noise message, and the initiator tells
+    /// them apart using the negotiation data.
+    pub fn has_noise_message(&self) -> bool {
+        matches!(self, Negotiation::Accept | Negotiation::Switch(_))
+    }
+}
+
+/// A NoiseSocket handshake message: negotiation data followed by a Noise message,
+/// each preceded by its 2-byte big-endian length.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HandshakeFrame<'a> {
+    /// The application's negotiation data, which is empty after the first two messages.
+    pub negotiation_data: &'a [u8],
+
+    /// The Noise handshake message itself, which is empty in `Retry` and `Reject` replies.
+    pub noise_message: &'a [u8],
+}
+
+impl<'a> HandshakeFrame<'a> {
+    /// Create a frame from its parts.
+    pub fn new(negotiation_data: &'a [u8], noise_message: &'a [u8]) -> Self {
+        HandshakeFrame { negotiation_data, noise_message }
+    }
+
+    /// The number of bytes this frame occupies on the wire.
+    pub fn len(&self) -> usize {
+        2 * LENGTH_LEN + self.negotiation_data.len() + self.noise_message.len()
+    }
+
+    /// Whether both the negotiation data and the Noise message are empty.
+    pub fn is_empty(&self) -> bool {
+        self.negotiation_data.is_empty() && self.noise_message.is_empty()
+    }
+
+    /// Write this frame into `out`.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if either part is longer than 65535 bytes, or
+    /// `Error::BufferTooSmall` if `out` is too small to hold the frame.
+    pub fn write(&self, out: &mut [u8]) -> Result<usize, Error> {
+        if out.len() < self.len() {
+            bail!(Error::BufferTooSmall { needed: self.len(), got: out.len() });
+        }
+
+        let len = write_field(self.negotiation_data, out)?;
+        Ok(len + write_field(self.noise_message, &mut out[len..])?)
+    }
+
+    /// Parse a frame from the front of `input`.
+    ///
+    /// Returns the frame along with the number of bytes it occupied.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::TruncatedMessage` if `input` is truncated.
+    pub fn read(input: &'a [u8]) -> Result<(Self, usize), Error> {
+        let (negotiation_data, len) = read_field(input)?;
+        let (noise_message, noise_len) = read_field(&input[len..])?;
+        Ok((HandshakeFrame { negotiation_data, noise_message }, len + noise_len))
+    }
+}
+
+/// Write a transport `noise_message` into `out`, preceded by its 2-byte big-endian length.
+///
+/// Returns the number of bytes written.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if the message is longer than 65535 bytes, or
+/// `Error::BufferTooSmall` if `out` is too small.
+pub fn write_transport_frame(noise_message: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    write_field(noise_message, out)
+}
+
+/// Parse a transport message from the front of `input`.
+///
+/// Returns the Noise message along with the number of bytes the frame occupied.
+///
+/// # Errors
+///
+/// Will result in `Error::TruncatedMessage` if `input` is truncated.
+pub fn read_transport_frame(input: &[u8]) -> Result<(&[u8], usize), Error> {
+    read_field(input)
+}
+
+/// Hides the length prefixes of transport frames from anyone watching the connection.
+///
+/// Each side applies it to every frame in order, so both ends must agree on how it's set up
+/// (e.g. with keys derived from the
+/// [handshake hash](crate::HandshakeStateCore::get_handshake_hash)).
+pub trait FrameObfuscation {
+    /// Obfuscate the big-endian length prefix of the next outgoing frame in place.
+    fn obfuscate(&mut self, header: &mut [u8; 2]);
+
+    /// Recover the big-endian length prefix of the next incoming frame in place.
+    fn deobfuscate(&mut self, header: &mut [u8; 2]);
+}
+
+/// NTCP2's length obfuscation: the n-th length in each direction is XORed with the first two
+/// bytes of `IV[n] = SipHash-2-4(key, IV[n-1])`, with the IVs little-endian.
+///
+/// Requires the `siphasher` feature.
+#[cfg(feature = "siphasher")]
+#[derive(Clone)]
+pub struct SipHashObfuscation {
+    send:    SipHashMask,
+    receive: SipHashMask,
+}
+
+#[cfg(feature = "siphasher")]
+impl SipHashObfuscation {
+    /// Obfuscate outgoing lengths with the SipHash key `send_key` (`k1 || k2`) and initial IV
+    /// `send_iv`, and incoming lengths with `receive_key` and `receive_iv`, the other side's
+    /// `send_key` and `send_iv`.
+    pub fn new(
+        send_key: [u8; 16],
+        send_iv: [u8; 8],
+        receive_key: [u8; 16],
+        receive_iv: [u8; 8],
+    ) -> Self {
+        SipHashObfuscation {
+            send:    SipHashMask { key: send_key, iv: send_iv },
+            receive: SipHashMask { key: receive_key, iv: receive_iv },
+        }
+    }
+}
+
+#[cfg(feature = "siphasher")]
+impl FrameObfuscation for SipHashObfuscation {
+    fn obfuscate(&mut self, header: &mut [u8; 2]) {
+        self.send.apply(header);
+    }
+
+    fn deobfuscate(&mut self, header: &mut [u8; 2]) {
+        self.receive.apply(header);
+    }
+}
+
+/// Shows nothing, to keep the keys and IVs out of logs.
+#[cfg(feature = "siphasher")]
+impl std::fmt::Debug for SipHashObfuscation {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("SipHashObfuscation").finish_non_exhaustive()
+    }
+}
+
+/// One direction of a [`SipHashObfuscation`].
+#[cfg(feature = "siphasher")]
+#[derive(Clone)]
+struct SipHashMask {
+    key: [u8; 16],
+    iv:  [u8; 8],
+}
+
+#[cfg(feature = "siphasher")]
+impl SipHashMask {
+    fn apply(&mut self, header: &mut [u8; 2]) {
+        use std::hash::Hasher;
+
+        let mut hasher = siphasher::sip::SipHasher24::new_with_key(&self.key);
+        hasher.write(&self.iv);
+        self.iv = hasher.finish().to_le_bytes();
+        header[0] ^= self.iv[0];
+        header[1] ^= self.iv[1];
+    }
+}
+
+/// Like [`write_transport_frame()`], but with the length prefix run through `obfuscation`.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if the message is longer than 65535 bytes, or
+/// `Error::BufferTooSmall` if `out` is too small. Either way `obfuscation` is left untouched.
+pub fn write_obfuscated_transport_frame(
+    noise_message: &[u8],
+    out: &mut [u8],
+    obfuscation: &mut dyn FrameObfuscation,
+) -> Result<usize, Error> {
+    let len = write_field(noise_message, out)?;
+    let mut header = [out[0], out[1]];
+    obfuscation.obfuscate(&mut header);
+    out[..LENGTH_LEN].copy_from_slice(&header);
+    Ok(len)
+}
+
+/// Like [`read_transport_frame()`], but with the length prefix run through `obfuscation`.
+///
+/// # Errors
+///
+/// Will result in `Error::TruncatedMessage` if `input` is truncated. If the length prefix was
+/// there but the rest of the frame wasn't, `obfuscation` has already moved past it, so a
+/// reader that gets frames in pieces should keep the connection's frames in sync itself, with
+/// [`FrameObfuscation::deobfuscate()`].
+pub fn read_obfuscated_transport_frame<'a>(
+    input: &'a [u8],
+    obfuscation: &mut dyn FrameObfuscation,
+) -> Result<(&'a [u8], usize), Error> {
+    if input.len() < LENGTH_LEN {
+        bail!(Error::TruncatedMessage { needed: LENGTH_LEN, got: input.len() });
+    }
+
+    let mut header = [input[0], input[1]];
+    obfuscation.deobfuscate(&mut header);
+    let len = u16::from_be_bytes(header) as usize;
+    if input.len() < LENGTH_LEN + len {
+        bail!(Error::TruncatedMessage { needed: LENGTH_LEN + len, got: input.len() });
+    }
+    Ok((&input[LENGTH_LEN..LENGTH_LEN + len], LENGTH_LEN + len))
+}
+
+/// The prologue for the initiator's first choice of protocol.
+///
+/// Both sides must use this prologue when building the `HandshakeState` for the offered
+/// protocol.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if the negotiation data is longer than 65535 bytes.
+pub fn initial_prologue(initiator_negotiation_data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut prologue =
+        Vec::with_capacity(INIT1_PREFIX.len() + LENGTH_LEN + initiator_negotiation_data.len());
+    prologue.extend_from_slice(INIT1_PREFIX);
+    push_field(&mut prologue, initiator_negotiation_data)?;
+    Ok(prologue)
+}
+
+/// The prologue for a protocol the responder switched to.
+///
+/// `offer` is the initiator's first handshake message, and `responder_negotiation_data` is the
+/// negotiation data the responder sent along with its switch.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if any part of the offer, or the negotiation data, is longer
+/// than 65535 bytes.
+pub fn switch_prologue(
+    offer: &HandshakeFrame<'_>,
+    responder_negotiation_data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    renegotiated_prologue(INIT2_PREFIX, offer, responder_negotiation_data)
+}
+
+/// The prologue for a protocol the responder asked the initiator to retry with.
+///
+/// `offer` is the initiator's first handshake message, and `responder_negotiation_data` is the
+/// negotiation data the responder sent along with its retry request.
+///
+/// # Errors
+///
+/// Same as [`switch_prologue()`].
+pub fn retry_prologue(
+    offer: &HandshakeFrame<'_>,
+    responder_negotiation_data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    renegotiated_prologue(INIT3_PREFIX, offer, responder_negotiation_data)
+}
+
+fn renegotiated_prologue(
+    prefix: &[u8],
+    offer: &HandshakeFrame<'_>,
+    responder_negotiation_data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let mut prologue = Vec::with_capacity(
+        prefix.len() + offer.len() + LENGTH_LEN + responder_negotiation_data.len(),
+    );
+    prologue.extend_from_slice(prefix);
+    push_field(&mut prologue, offer.negotiation_data)?;
+    push_field(&mut prologue, offer.noise_message)?;
+    push_field(&mut prologue, responder_negotiation_data)?;
+    Ok(prologue)
+}
+
+fn push_field(out: &mut Vec<u8>, field: &[u8]) -> Result<(), Error> {
+    out.extend_from_slice(&field_len(field)?.to_be_bytes());
+    out.extend_from_slice(field);
+    Ok(())
+}
+
+fn write_field(field: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let len = field_len(field)?;
+    if out.len() < LENGTH_LEN + field.len() {
+        bail!(Error::BufferTooSmall { needed: LENGTH_LEN + field.len(), got: out.len() });
+    }
+
+    out[..LENGTH_LEN].copy_from_slice(&len.to_be_bytes());
+    out[LENGTH_LEN..LENGTH_LEN + field.len()].copy_from_slice(field);
+    Ok(LENGTH_LEN + field.len())
+}
+
+/// The length prefix for `field`, which has to fit in 16 bits.
+fn field_len(field: &[u8]) -> Result<u16, Error> {
+    u16::try_from(field.len()).map_err(|_| Error::Input)
+}
+
+fn read_field(input: &[u8]) -> Result<(&[u8], usize), Error> {
+    if input.len() < LENGTH_LEN {
+        bail!(Error::TruncatedMessage { needed: LENGTH_LEN, got: input.len() });
+    }
+
+    let len = u16::from_be_bytes([input[0], input[1]]) as usize;
+    if input.len() < LENGTH_LEN + len {
+        bail!(Error::TruncatedMessage { needed: LENGTH_LEN + len, got: input.len() });
+    }
+    Ok((&input[LENGTH_LEN..LENGTH_LEN + len], LENGTH_LEN + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake_frame_roundtrip() {
+        let mut buf = [0u8; 64];
+        let frame = HandshakeFrame::new(b"v1", b"noise");
+        let len = frame.write(&mut buf).unwrap();
+        assert_eq!(len, frame.len());
+        assert_eq!(&buf[..len], b"\x00\x02v1\x00\x05noise");
+
+        let (parsed, parsed_len) = HandshakeFrame::read(&buf[..len]).unwrap();
+        assert_eq!(parsed, frame);
+        assert_eq!(parsed_len, len);
+    }
+
+    #[test]
+    fn test_truncated_frames() {
+        assert!(HandshakeFrame::read(b"\x00\x02v1\x00\x05noi").is_err());
+        assert!(read_transport_frame(b"\x00").is_err());
+        assert!(HandshakeFrame::new(b"v1", b"noise").write(&mut [0u8; 10]).is_err());
+    }
+
+    #[test]
+    fn test_prologues() {
+        assert_eq!(initial_prologue(b"v1").unwrap(), b"NoiseSocketInit1\x00\x02v1");
+        let offer = HandshakeFrame::new(b"v1", b"e");
+        assert_eq!(
+            switch_prologue(&offer, b"v2").unwrap(),
+            b"NoiseSocketInit2\x00\x02v1\x00\x01e\x00\x02v2"
+        );
+        assert_eq!(
+            retry_prologue(&offer, b"").unwrap(),
+            b"NoiseSocketInit3\x00\x02v1\x00\x01e\x00\x00"
+        );
+
+        let oversized = vec![0u8; 65536];
+        assert!(matches!(initial_prologue(&oversized), Err(Error::Input)));
+        assert!(matches!(retry_prologue(&offer, &oversized), Err(Error::Input)));
+        assert!(matches!(write_transport_frame(&oversized, &mut [0u8; 70000]), Err(Error::Input)));
+    }
+
+    #[cfg(feature = "siphasher")]
+    #[test]
+    fn test_siphash_obfuscation() {
+        // With the key 00..0f, SipHash-2-4 takes the IV 00..07 to 62 24 93 9a 79 f5 f5 93 (the
+        // reference implementation's vectors), so the first mask is 62 24.
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let iv: [u8; 8] = core::array::from_fn(|i| i as u8);
+        let mut alice = SipHashObfuscation::new(key, iv, [1; 16], [2; 8]);
+        let mut bob = SipHashObfuscation::new([1; 16], [2; 8], key, iv);
+
+        let mut buf = [0u8; 64];
+        let len = write_obfuscated_transport_frame(b"noise", &mut buf, &mut alice).unwrap();
+        assert_eq!(&buf[..len], b"\x62\x21noise");
+        let (message, read) = read_obfuscated_transport_frame(&buf[..len], &mut bob).unwrap();
+        assert_eq!((message, read), (&b"noise"[..], len));
+
+        // The masks keep changing, so the same length looks different every time.
+        let second = write_obfuscated_transport_frame(b"noise", &mut buf, &mut alice).unwrap();
+        assert_ne!(&buf[..2], b"\x62\x21");
+        assert_eq!(read_obfuscated_transport_frame(&buf[..second], &mut bob).unwrap().0, b"noise");
+
+        let len = write_obfuscated_transport_frame(b"back", &mut buf, &mut bob).unwrap();
+        assert_eq!(read_obfuscated_transport_frame(&buf[..len], &mut alice).unwrap().0, b"back");
+    }
+}
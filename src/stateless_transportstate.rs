@@ -1,12 +1,25 @@
 use crate::{
     cipherstate::StatelessCipherStates,
-    constants::{MAXDHLEN, MAXMSGLEN, TAGLEN},
+    constants::{MAXMSGLEN, MAXSTATICLEN, TAGLEN},
     error::{Error, StateProblem},
     handshakestate::HandshakeState,
+    observer::{Direction, SessionObserver},
+    padding::{self, PaddingPolicy},
     params::HandshakePattern,
-    utils::Toggle,
+    types::Random,
+    utils::{check_message_len, Toggle, Traffic},
+};
+#[cfg(feature = "bytes")]
+use bytes::{Bytes, BytesMut};
+#[cfg(not(feature = "forbid-unsafe"))]
+use std::mem::MaybeUninit;
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::IoSlice,
+    ops::Range,
+    sync::{Arc, Mutex},
 };
-use std::{convert::TryFrom, fmt};
 
 /// A state machine encompassing the transport phase of a Noise session, using the two
 /// `CipherState`s (for sending and receiving) that were spawned from the `SymmetricState`'s
@@ -14,11 +27,18 @@ use std::{convert::TryFrom, fmt};
 ///
 /// See: http://noiseprotocol.org/noise.html#the-handshakestate-object
 pub struct StatelessTransportState {
-    cipherstates: StatelessCipherStates,
-    pattern:      HandshakePattern,
-    dh_len:       usize,
-    rs:           Toggle<[u8; MAXDHLEN]>,
-    initiator:    bool,
+    cipherstates:    StatelessCipherStates,
+    pattern:         HandshakePattern,
+    s_len:           usize,
+    s:               Toggle<[u8; MAXSTATICLEN]>,
+    rs_len:          usize,
+    rs:              Toggle<[u8; MAXSTATICLEN]>,
+    initiator:       bool,
+    rng:             Mutex<Box<dyn Random>>,
+    padding:         Option<PaddingPolicy>,
+    observer:        Option<Arc<dyn SessionObserver>>,
+    traffic:         Traffic,
+    max_message_len: Option<usize>,
 }
 
 impl StatelessTransportState {
@@ -27,11 +47,40 @@ impl StatelessTransportState {
             bail!(StateProblem::HandshakeNotFinished);
         }
 
-        let dh_len = handshake.dh_len();
-        let HandshakeState { cipherstates, params, rs, initiator, .. } = handshake;
+        // Disco's cipherstates are duplexes that must see every message in order.
+        if handshake.cipherstates.0.is_stateful() {
+            bail!(StateProblem::StatelessTransportMode);
+        }
+
+        let (s_len, s) = handshake.local_static_copy();
+        let rs_len = handshake.remote_static_len();
+        let HandshakeState {
+            cipherstates,
+            params,
+            rs,
+            initiator,
+            rng,
+            padding,
+            observer,
+            max_message_len,
+            ..
+        } = handshake;
         let pattern = params.handshake.pattern;
 
-        Ok(Self { cipherstates: cipherstates.into(), pattern, dh_len, rs, initiator })
+        Ok(Self {
+            cipherstates: cipherstates.into(),
+            pattern,
+            s_len,
+            s,
+            rs_len,
+            rs,
+            initiator,
+            rng: Mutex::new(rng),
+            padding,
+            observer,
+            traffic: Traffic::default(),
+            max_message_len,
+        })
     }
 
     /// Get the remote party's static public key, if available.
@@ -41,7 +90,34 @@ impl StatelessTransportState {
     /// static key is not yet known (as can be the case in the `XX`
     /// pattern, for example).
     pub fn get_remote_static(&self) -> Option<&[u8]> {
-        self.rs.get().map(|rs| &rs[..self.dh_len])
+        self.rs.get().map(|rs| &rs[..self.rs_len])
+    }
+
+    /// Get the local static public key, if this side had one in the handshake.
+    pub fn get_local_static(&self) -> Option<&[u8]> {
+        self.s.get().map(|s| &s[..self.s_len])
+    }
+
+    /// The number of bytes sent in transport messages, counting their tags (and padding), i.e.
+    /// what went out on the wire.
+    pub fn bytes_sent(&self) -> u64 {
+        self.traffic.bytes_sent()
+    }
+
+    /// The number of bytes received in transport messages that decrypted successfully,
+    /// counting their tags (and padding).
+    pub fn bytes_received(&self) -> u64 {
+        self.traffic.bytes_received()
+    }
+
+    /// The number of transport messages sent.
+    pub fn messages_sent(&self) -> u64 {
+        self.traffic.messages_sent()
+    }
+
+    /// The number of transport messages received that decrypted successfully.
+    pub fn messages_received(&self) -> u64 {
+        self.traffic.messages_received()
     }
 
     /// Construct a message from `payload` (and pending handshake tokens if in handshake state),
@@ -53,20 +129,172 @@ impl StatelessTransportState {
     ///
     /// Will result in `Error::Input` if the size of the output exceeds the max message
     /// length in the Noise Protocol (65535 bytes).
+    ///
+    /// Will result in `Error::BufferTooSmall` if `message` can't hold the encrypted payload.
+    ///
+    /// Will result in `Error::State(StateProblem::OneWay)` if this is the responder of a
+    /// one-way pattern (`N`, `K` or `X`), which may only receive.
     pub fn write_message(
         &self,
         nonce: u64,
         payload: &[u8],
         message: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.write_message_with_ad(nonce, &[], payload, message)
+    }
+
+    /// Like [`write_message()`](Self::write_message), but also authenticates `ad` as associated
+    /// data, e.g. a plaintext header with routing information. `ad` isn't part of the message:
+    /// the reader has to get it some other way and pass it to
+    /// [`read_message_with_ad()`](Self::read_message_with_ad).
+    ///
+    /// Returns the size of the written payload.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`write_message()`](Self::write_message).
+    pub fn write_message_with_ad(
+        &self,
+        nonce: u64,
+        ad: &[u8],
+        payload: &[u8],
+        message: &mut [u8],
     ) -> Result<usize, Error> {
         if !self.initiator && self.pattern.is_oneway() {
             bail!(StateProblem::OneWay);
-        } else if payload.len() + TAGLEN > MAXMSGLEN || payload.len() + TAGLEN > message.len() {
-            bail!(Error::Input);
         }
 
         let cipher = if self.initiator { &self.cipherstates.0 } else { &self.cipherstates.1 };
-        cipher.encrypt(nonce, payload, message)
+        let len = match &self.padding {
+            Some(policy) => {
+                let padded_len = {
+                    let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+                    policy.padded_len(payload.len(), &mut **rng)?
+                };
+                if padded_len + TAGLEN > message.len() {
+                    bail!(Error::BufferTooSmall {
+                        needed: padded_len + TAGLEN,
+                        got:    message.len(),
+                    });
+                }
+                cipher.encrypt_ad(nonce, ad, &padding::pad(payload, padded_len)?, message)?
+            },
+            None => {
+                if payload.len() + TAGLEN > MAXMSGLEN {
+                    bail!(Error::Input);
+                }
+                if payload.len() + TAGLEN > message.len() {
+                    bail!(Error::BufferTooSmall {
+                        needed: payload.len() + TAGLEN,
+                        got:    message.len(),
+                    });
+                }
+                cipher.encrypt_ad(nonce, ad, payload, message)?
+            },
+        };
+        self.traffic.sent(len);
+        self.observe(|observer| observer.message_encrypted(len));
+        Ok(len)
+    }
+
+    /// Construct a message from a `payload` split across several buffers, e.g. a protocol
+    /// header and body, gathering them straight into `message` rather than concatenating them
+    /// first.
+    ///
+    /// Returns the size of the written payload.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`write_message()`](Self::write_message).
+    pub fn write_message_vectored(
+        &self,
+        nonce: u64,
+        payload: &[IoSlice<'_>],
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        let payload_len = payload.iter().map(|slice| slice.len()).sum::<usize>();
+        let plaintext_len = self.plaintext_len(payload_len)?;
+        self.seal(nonce, payload, plaintext_len, message)
+    }
+
+    /// Like [`write_message()`](Self::write_message), but writes into a buffer that hasn't been
+    /// initialized, so large buffers don't have to be zeroed before every message.
+    ///
+    /// Returns the written message, which is the only part of `message` that gets initialized.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`write_message()`](Self::write_message).
+    ///
+    /// Not available with the "forbid-unsafe" feature, since it takes unsafe code to hand out
+    /// the initialized part of the buffer.
+    #[cfg(not(feature = "forbid-unsafe"))]
+    pub fn write_message_uninit<'m>(
+        &self,
+        nonce: u64,
+        payload: &[u8],
+        message: &'m mut [MaybeUninit<u8>],
+    ) -> Result<&'m mut [u8], Error> {
+        let plaintext_len = self.plaintext_len(payload.len())?;
+        let framed = self.padding.is_some();
+        let message = crate::utils::init_message(message, payload, plaintext_len, framed)?;
+        let len = self.encrypt_plaintext(nonce, message, plaintext_len)?;
+        Ok(&mut message[..len])
+    }
+
+    /// Check that a payload of `payload_len` bytes may be sent, returning the length of its
+    /// (possibly padded) plaintext.
+    fn plaintext_len(&self, payload_len: usize) -> Result<usize, Error> {
+        if !self.initiator && self.pattern.is_oneway() {
+            bail!(StateProblem::OneWay);
+        }
+
+        match &self.padding {
+            Some(policy) => {
+                if payload_len + padding::LENGTH_PREFIX_LEN + TAGLEN > MAXMSGLEN {
+                    bail!(Error::Input);
+                }
+                let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+                policy.padded_len(payload_len, &mut **rng)
+            },
+            None => {
+                if payload_len + TAGLEN > MAXMSGLEN {
+                    bail!(Error::Input);
+                }
+                Ok(payload_len)
+            },
+        }
+    }
+
+    /// Gather `payload` into a `plaintext_len`-byte plaintext at the front of `message`, then
+    /// encrypt it in place.
+    fn seal(
+        &self,
+        nonce: u64,
+        payload: &[IoSlice<'_>],
+        plaintext_len: usize,
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        if plaintext_len + TAGLEN > message.len() {
+            bail!(Error::BufferTooSmall { needed: plaintext_len + TAGLEN, got: message.len() });
+        }
+
+        padding::gather(payload, &mut message[..plaintext_len], self.padding.is_some());
+        self.encrypt_plaintext(nonce, message, plaintext_len)
+    }
+
+    /// Encrypt the `plaintext_len`-byte plaintext at the front of `message` in place.
+    fn encrypt_plaintext(
+        &self,
+        nonce: u64,
+        message: &mut [u8],
+        plaintext_len: usize,
+    ) -> Result<usize, Error> {
+        let cipher = if self.initiator { &self.cipherstates.0 } else { &self.cipherstates.1 };
+        let len = cipher.encrypt_in_place(nonce, message, plaintext_len)?;
+        self.traffic.sent(len);
+        self.observe(|observer| observer.message_encrypted(len));
+        Ok(len)
     }
 
     /// Reads a noise message from `input`
@@ -78,6 +306,18 @@ impl StatelessTransportState {
     /// Will result in `Error::Decrypt` if the contents couldn't be decrypted and/or the
     /// authentication tag didn't verify.
     ///
+    /// Will result in `Error::TruncatedMessage` if `payload` is too short to hold a tag, or
+    /// `Error::BufferTooSmall` if `message` can't hold the decrypted payload.
+    ///
+    /// Will result in `Error::State(StateProblem::OneWay)` if this is the initiator of a
+    /// one-way pattern (`N`, `K` or `X`), which may only send.
+    ///
+    /// If a padding policy is in use, `message` must be large enough to hold the padded
+    /// plaintext, and malformed padding will result in `Error::Input`.
+    ///
+    /// Will result in `Error::MessageTooLarge` if `payload` is longer than
+    /// [`Builder::max_message_len()`](crate::Builder::max_message_len) allows.
+    ///
     /// # Panics
     ///
     /// This function will panic if there is no key.
@@ -86,12 +326,190 @@ impl StatelessTransportState {
         nonce: u64,
         payload: &[u8],
         message: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.read_message_with_ad(nonce, &[], payload, message)
+    }
+
+    /// Like [`read_message()`](Self::read_message), but also checks that the message was
+    /// written with `ad` as associated data.
+    ///
+    /// Returns the size of the payload written to `message`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`read_message()`](Self::read_message). An `ad` other than the writer's fails
+    /// to authenticate like a tampered message, with `Error::Decrypt`.
+    pub fn read_message_with_ad(
+        &self,
+        nonce: u64,
+        ad: &[u8],
+        payload: &[u8],
+        message: &mut [u8],
     ) -> Result<usize, Error> {
         if self.initiator && self.pattern.is_oneway() {
             bail!(StateProblem::OneWay);
         }
+        check_message_len(self.max_message_len, payload.len())?;
+        if payload.len() < TAGLEN {
+            bail!(Error::TruncatedMessage { needed: TAGLEN, got: payload.len() });
+        }
+        if message.len() < payload.len() - TAGLEN {
+            bail!(Error::BufferTooSmall { needed: payload.len() - TAGLEN, got: message.len() });
+        }
         let cipher = if self.initiator { &self.cipherstates.1 } else { &self.cipherstates.0 };
-        cipher.decrypt(nonce, payload, message).map_err(|_| Error::Decrypt)
+        let len = match cipher.decrypt_ad(nonce, ad, payload, message) {
+            Ok(len) => len,
+            Err(_) => {
+                self.observe(|observer| observer.decrypt_failed());
+                bail!(Error::Decrypt);
+            },
+        };
+        self.traffic.received(payload.len());
+        self.observe(|observer| observer.message_decrypted(payload.len()));
+        match self.padding {
+            Some(_) => padding::unpad(&mut message[..len]),
+            None => Ok(len),
+        }
+    }
+
+    /// Like [`read_message()`](Self::read_message), but decrypts `message` in place instead of
+    /// into a separate buffer, so e.g. a proxy can forward the payload straight from the buffer
+    /// it was received into.
+    ///
+    /// Returns where the payload is in `message`, which is left holding the plaintext. With a
+    /// padding policy, the payload starts after the padded plaintext's length prefix.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`read_message()`](Self::read_message). If decryption fails, the contents of
+    /// `message` are unspecified.
+    pub fn read_message_in_place(
+        &self,
+        nonce: u64,
+        message: &mut [u8],
+    ) -> Result<Range<usize>, Error> {
+        if self.initiator && self.pattern.is_oneway() {
+            bail!(StateProblem::OneWay);
+        }
+        check_message_len(self.max_message_len, message.len())?;
+        if message.len() < TAGLEN {
+            bail!(Error::TruncatedMessage { needed: TAGLEN, got: message.len() });
+        }
+        let message_len = message.len();
+        let cipher = if self.initiator { &self.cipherstates.1 } else { &self.cipherstates.0 };
+        let len = match cipher.decrypt_in_place(nonce, message, message_len) {
+            Ok(len) => len,
+            Err(_) => {
+                self.observe(|observer| observer.decrypt_failed());
+                bail!(Error::Decrypt);
+            },
+        };
+        self.traffic.received(message_len);
+        self.observe(|observer| observer.message_decrypted(message_len));
+        match self.padding {
+            Some(_) => padding::body_range(&message[..len]),
+            None => Ok(0..len),
+        }
+    }
+
+    /// Like [`read_message()`](Self::read_message), but writes into a buffer that hasn't been
+    /// initialized.
+    ///
+    /// Ciphers decrypt into initialized memory, so the part of `payload` the message decrypts
+    /// into is zeroed first: only the rest of a large buffer is spared.
+    ///
+    /// Returns the payload, which is at the front of the part of `payload` that gets initialized.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`read_message()`](Self::read_message).
+    ///
+    /// Not available with the "forbid-unsafe" feature, since it takes unsafe code to hand out
+    /// the initialized part of the buffer.
+    #[cfg(not(feature = "forbid-unsafe"))]
+    pub fn read_message_uninit<'p>(
+        &self,
+        nonce: u64,
+        message: &[u8],
+        payload: &'p mut [MaybeUninit<u8>],
+    ) -> Result<&'p mut [u8], Error> {
+        check_message_len(self.max_message_len, message.len())?;
+        let payload = crate::utils::init_prefix(payload, message.len().saturating_sub(TAGLEN));
+        let len = self.read_message(nonce, message, payload)?;
+        Ok(&mut payload[..len])
+    }
+
+    /// Construct a message from `payload` like [`write_message()`](Self::write_message), but
+    /// append it to `message`, growing the buffer by only as much as the message needs.
+    ///
+    /// Returns the size of the appended message. On error, `message` is left as it was.
+    ///
+    /// The "bytes" feature has to be enabled to use this function.
+    #[cfg(feature = "bytes")]
+    pub fn write_message_bytes(
+        &self,
+        nonce: u64,
+        payload: &[u8],
+        message: &mut BytesMut,
+    ) -> Result<usize, Error> {
+        let start = message.len();
+        let max_len = match self.padding {
+            Some(_) => MAXMSGLEN,
+            None => (payload.len() + TAGLEN).min(MAXMSGLEN),
+        };
+        message.resize(start + max_len, 0);
+        let result = self.write_message(nonce, payload, &mut message[start..]);
+        message.truncate(start + result.as_ref().map_or(0, |len| *len));
+        result
+    }
+
+    /// Read a noise message like [`read_message()`](Self::read_message), e.g. straight out of
+    /// a `Bytes` frame, returning the payload in a new buffer.
+    ///
+    /// The "bytes" feature has to be enabled to use this function.
+    #[cfg(feature = "bytes")]
+    pub fn read_message_bytes(&self, nonce: u64, message: &[u8]) -> Result<Bytes, Error> {
+        check_message_len(self.max_message_len, message.len())?;
+        let mut payload = BytesMut::zeroed(message.len().saturating_sub(TAGLEN));
+        let len = self.read_message(nonce, message, &mut payload)?;
+        payload.truncate(len);
+        Ok(payload.freeze())
+    }
+
+    /// Reads a batch of noise messages, each with its own nonce, into the matching buffers in
+    /// `outputs`. With the "rayon" feature enabled, the messages are decrypted in parallel.
+    ///
+    /// Returns one result per message, in order, as [`read_message()`](Self::read_message)
+    /// would have returned it, so a single bad message doesn't spoil the rest of the batch.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if `messages` and `outputs` have different lengths.
+    pub fn read_messages(
+        &self,
+        messages: &[(u64, &[u8])],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<Vec<Result<usize, Error>>, Error> {
+        if messages.len() != outputs.len() {
+            bail!(Error::Input);
+        }
+
+        #[cfg(feature = "rayon")]
+        let results = {
+            use rayon::prelude::*;
+            messages
+                .par_iter()
+                .zip(outputs.par_iter_mut())
+                .map(|(&(nonce, message), output)| self.read_message(nonce, message, output))
+                .collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let results = messages
+            .iter()
+            .zip(outputs.iter_mut())
+            .map(|(&(nonce, message), output)| self.read_message(nonce, message, output))
+            .collect();
+        Ok(results)
     }
 
     /// Generates a new key for the egress symmetric cipher according to Section 4.2
@@ -104,6 +522,7 @@ impl StatelessTransportState {
         } else {
             self.cipherstates.rekey_responder()
         }
+        self.observe(|observer| observer.rekeyed(Direction::Outgoing));
     }
 
     /// Generates a new key for the ingress symmetric cipher according to Section 4.2
@@ -116,6 +535,7 @@ impl StatelessTransportState {
         } else {
             self.cipherstates.rekey_initiator()
         }
+        self.observe(|observer| observer.rekeyed(Direction::Incoming));
     }
 
     /// Set a new key for the one or both of the initiator-egress and responder-egress symmetric ciphers.
@@ -130,23 +550,41 @@ impl StatelessTransportState {
 
     /// Set a new key for the initiator-egress symmetric cipher.
     pub fn rekey_initiator_manually(&mut self, key: &[u8]) {
-        self.cipherstates.rekey_initiator_manually(key)
+        self.cipherstates.rekey_initiator_manually(key);
+        let direction = if self.initiator { Direction::Outgoing } else { Direction::Incoming };
+        self.observe(|observer| observer.rekeyed(direction));
     }
 
     /// Set a new key for the responder-egress symmetric cipher.
     pub fn rekey_responder_manually(&mut self, key: &[u8]) {
-        self.cipherstates.rekey_responder_manually(key)
+        self.cipherstates.rekey_responder_manually(key);
+        let direction = if self.initiator { Direction::Incoming } else { Direction::Outgoing };
+        self.observe(|observer| observer.rekeyed(direction));
     }
 
     /// Check if this session was started with the "initiator" role.
     pub fn is_initiator(&self) -> bool {
         self.initiator
     }
+
+    /// Report an event to the observer, if there is one.
+    fn observe(&self, event: impl FnOnce(&dyn SessionObserver)) {
+        if let Some(observer) = &self.observer {
+            event(&**observer);
+        }
+    }
 }
 
+/// Shows the session's shape, but none of the key material.
 impl fmt::Debug for StatelessTransportState {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("StatelessTransportState").finish()
+        fmt.debug_struct("StatelessTransportState")
+            .field("pattern", &self.pattern)
+            .field("initiator", &self.initiator)
+            .field("rs", &self.rs.is_on())
+            .field("padding", &self.padding)
+            .field("observer", &self.observer.is_some())
+            .finish_non_exhaustive()
     }
 }
 
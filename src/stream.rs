@@ -0,0 +1,557 @@
+//! Running Noise over a `futures-io` byte stream, for async runtimes like smol and async-std.
+//!
+//! [`handshake()`] drives a [`HandshakeState`] to completion over any
+//! [`AsyncRead`] + [`AsyncWrite`] stream, and returns a [`NoiseStream`] that implements both
+//! traits itself: whatever is written to it goes out encrypted, and reads return the decrypted
+//! payloads. Every message, handshake or transport, is sent with a 2-byte big-endian length
+//! prefix, the same framing as [`socket::write_transport_frame()`](crate::socket::write_transport_frame).
+//! [`NoiseStream::with_frame_obfuscation()`] hides the prefixes of transport messages.
+//!
+//! Errors from the Noise session surface as `io::Error`s of kind `InvalidData`, wrapping the
+//! [`Error`].
+//!
+//! Requires the `futures-io` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use snow::{Builder, stream::handshake};
+//! # use futures_io::{AsyncRead, AsyncWrite};
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! async fn connect<S: AsyncRead + AsyncWrite + Unpin>(socket: S) -> std::io::Result<()> {
+//!     let params = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//!     let initiator = Builder::new(params).build_initiator().unwrap();
+//!     let stream = handshake(initiator, socket).await?;
+//!
+//!     // `stream` is an `AsyncRead + AsyncWrite` now, to use as any other.
+//! #   drop(stream);
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{
+    constants::{MAXMSGLEN, TAGLEN},
+    error::Error,
+    padding::LENGTH_PREFIX_LEN,
+    socket::FrameObfuscation,
+    HandshakeState, TransportState,
+};
+use futures_io::{AsyncRead, AsyncWrite};
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The size of the big-endian length prefix on every message.
+const FRAME_HEADER_LEN: usize = 2;
+
+/// The most payload a single transport message carries, leaving room for the tag and for a
+/// padding policy's length prefix.
+const MAX_CHUNK_LEN: usize = MAXMSGLEN - TAGLEN - LENGTH_PREFIX_LEN;
+
+/// Run the handshake in `state` over `stream`, with empty handshake payloads, and wrap the
+/// stream in the resulting transport.
+///
+/// # Errors
+///
+/// Fails with the stream's I/O errors, with `ErrorKind::UnexpectedEof` if the stream ends
+/// before the handshake does, and with `ErrorKind::InvalidData` if the handshake fails.
+pub async fn handshake<S>(mut state: HandshakeState, mut stream: S) -> io::Result<NoiseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; FRAME_HEADER_LEN + MAXMSGLEN];
+    let mut payload = vec![0u8; MAXMSGLEN];
+    while !state.is_handshake_finished() {
+        if state.is_my_turn() {
+            let len = state.write_message(&[], &mut buf[FRAME_HEADER_LEN..]).map_err(invalid)?;
+            buf[..FRAME_HEADER_LEN].copy_from_slice(&(len as u16).to_be_bytes());
+            WriteAll { stream: &mut stream, buf: &buf[..FRAME_HEADER_LEN + len] }.await?;
+            Flush { stream: &mut stream }.await?;
+        } else {
+            ReadExact { stream: &mut stream, buf: &mut buf[..FRAME_HEADER_LEN] }.await?;
+            let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+            ReadExact { stream: &mut stream, buf: &mut buf[..len] }.await?;
+            state.read_message(&buf[..len], &mut payload).map_err(invalid)?;
+        }
+    }
+    Ok(NoiseStream::new(state.into_transport_mode().map_err(invalid)?, stream))
+}
+
+/// An encrypted stream over an established Noise session.
+///
+/// Writes are split into messages of at most 65535 bytes and buffered until they've gone out,
+/// so a `poll_write` that returns `Ready` has only encrypted the data: call `poll_flush` (or
+/// `flush().await`) to make sure it was sent. Reads hand out one decrypted message at a time.
+pub struct NoiseStream<S> {
+    stream:      S,
+    transport:   TransportState,
+    outgoing:    Vec<u8>,
+    sent:        usize,
+    incoming:    Vec<u8>,
+    received:    usize,
+    plaintext:   Vec<u8>,
+    handed_out:  usize,
+    obfuscation: Option<Box<dyn FrameObfuscation + Send>>,
+}
+
+impl<S> NoiseStream<S> {
+    /// Wrap `stream` in an already established `transport`.
+    pub fn new(transport: TransportState, stream: S) -> Self {
+        NoiseStream {
+            stream,
+            transport,
+            outgoing: Vec::new(),
+            sent: 0,
+            incoming: vec![0u8; FRAME_HEADER_LEN + MAXMSGLEN],
+            received: 0,
+            plaintext: Vec::new(),
+            handed_out: 0,
+            obfuscation: None,
+        }
+    }
+
+    /// Run the length prefix of every transport message, sent or received, through
+    /// `obfuscation` from now on.
+    pub fn with_frame_obfuscation(
+        mut self,
+        obfuscation: impl FrameObfuscation + Send + 'static,
+    ) -> Self {
+        self.obfuscation = Some(Box::new(obfuscation));
+        self
+    }
+
+    /// The Noise session.
+    pub fn transport(&self) -> &TransportState {
+        &self.transport
+    }
+
+    /// The Noise session, e.g. to rekey it.
+    pub fn transport_mut(&mut self) -> &mut TransportState {
+        &mut self.transport
+    }
+
+    /// The underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Give up the stream and the session. Anything buffered but not yet sent or read is lost.
+    pub fn into_inner(self) -> (TransportState, S) {
+        (self.transport, self.stream)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NoiseStream<S> {
+    /// Write out as much of the buffered message as the stream takes.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.sent < self.outgoing.len() {
+            match Pin::new(&mut self.stream).poll_write(cx, &self.outgoing[self.sent..])? {
+                Poll::Ready(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(n) => self.sent += n,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.outgoing.clear();
+        self.sent = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.poll_send(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let chunk = &buf[..buf.len().min(MAX_CHUNK_LEN)];
+        this.outgoing.resize(FRAME_HEADER_LEN + MAXMSGLEN, 0);
+        let len = this
+            .transport
+            .write_message(chunk, &mut this.outgoing[FRAME_HEADER_LEN..])
+            .map_err(invalid)?;
+        this.outgoing.truncate(FRAME_HEADER_LEN + len);
+        let mut header = (len as u16).to_be_bytes();
+        if let Some(obfuscation) = &mut this.obfuscation {
+            obfuscation.obfuscate(&mut header);
+        }
+        this.outgoing[..FRAME_HEADER_LEN].copy_from_slice(&header);
+
+        // The chunk is taken either way; whatever doesn't go out now goes on the next poll.
+        let _ = this.poll_send(cx)?;
+        Poll::Ready(Ok(chunk.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.poll_send(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.poll_send(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.stream).poll_close(cx)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Empty messages carry nothing to read, so keep going until one that does.
+        while this.handed_out == this.plaintext.len() {
+            let needed = if this.received < FRAME_HEADER_LEN {
+                FRAME_HEADER_LEN
+            } else {
+                FRAME_HEADER_LEN + u16::from_be_bytes([this.incoming[0], this.incoming[1]]) as usize
+            };
+            if this.received < needed {
+                let n = match Pin::new(&mut this.stream)
+                    .poll_read(cx, &mut this.incoming[this.received..needed])?
+                {
+                    Poll::Ready(n) => n,
+                    Poll::Pending => return Poll::Pending,
+                };
+                if n == 0 {
+                    if this.received == 0 {
+                        return Poll::Ready(Ok(0));
+                    }
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                this.received += n;
+                if let (FRAME_HEADER_LEN, Some(obfuscation)) =
+                    (this.received, &mut this.obfuscation)
+                {
+                    let mut header = [this.incoming[0], this.incoming[1]];
+                    obfuscation.deobfuscate(&mut header);
+                    this.incoming[..FRAME_HEADER_LEN].copy_from_slice(&header);
+                }
+                continue;
+            }
+            if needed == FRAME_HEADER_LEN {
+                // A zero-length frame can't be a Noise message, as it has no room for the tag.
+                return Poll::Ready(Err(invalid(Error::Input)));
+            }
+
+            this.plaintext.resize(MAXMSGLEN, 0);
+            let len = this
+                .transport
+                .read_message(&this.incoming[FRAME_HEADER_LEN..needed], &mut this.plaintext)
+                .map_err(invalid)?;
+            this.plaintext.truncate(len);
+            this.handed_out = 0;
+            this.received = 0;
+        }
+
+        let len = buf.len().min(this.plaintext.len() - this.handed_out);
+        buf[..len].copy_from_slice(&this.plaintext[this.handed_out..this.handed_out + len]);
+        this.handed_out += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+/// Shows the session and how much is buffered, but none of the data.
+impl<S> fmt::Debug for NoiseStream<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("NoiseStream")
+            .field("transport", &self.transport)
+            .field("unsent", &(self.outgoing.len() - self.sent))
+            .field("unread", &(self.plaintext.len() - self.handed_out))
+            .finish_non_exhaustive()
+    }
+}
+
+fn invalid(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+struct ReadExact<'a, S> {
+    stream: &'a mut S,
+    buf:    &'a mut [u8],
+}
+
+impl<S: AsyncRead + Unpin> Future for ReadExact<'_, S> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        while !this.buf.is_empty() {
+            let n = match Pin::new(&mut *this.stream).poll_read(cx, this.buf)? {
+                Poll::Ready(0) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                Poll::Ready(n) => n,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.buf = &mut std::mem::take(&mut this.buf)[n..];
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct WriteAll<'a, S> {
+    stream: &'a mut S,
+    buf:    &'a [u8],
+}
+
+impl<S: AsyncWrite + Unpin> Future for WriteAll<'_, S> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        while !this.buf.is_empty() {
+            match Pin::new(&mut *this.stream).poll_write(cx, this.buf)? {
+                Poll::Ready(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(n) => this.buf = &this.buf[n..],
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct Flush<'a, S> {
+    stream: &'a mut S,
+}
+
+impl<S: AsyncWrite + Unpin> Future for Flush<'_, S> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_flush(cx)
+    }
+}
+
+#[cfg(all(test, any(feature = "default-resolver", feature = "ring-accelerated")))]
+mod tests {
+    use super::*;
+    use crate::Builder;
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        task::{Wake, Waker},
+    };
+
+    const PARAMS: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+
+    /// One end of an in-memory pipe, which takes at most `max_write` bytes per write.
+    struct Pipe {
+        incoming:  Arc<Mutex<VecDeque<u8>>>,
+        outgoing:  Arc<Mutex<VecDeque<u8>>>,
+        max_write: usize,
+    }
+
+    fn pipe(max_write: usize) -> (Pipe, Pipe) {
+        let (a, b) = (Arc::default(), Arc::default());
+        (
+            Pipe { incoming: Arc::clone(&a), outgoing: Arc::clone(&b), max_write },
+            Pipe { incoming: b, outgoing: a, max_write },
+        )
+    }
+
+    impl AsyncRead for Pipe {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let mut incoming = self.incoming.lock().unwrap();
+            if incoming.is_empty() {
+                if Arc::strong_count(&self.incoming) == 1 {
+                    // The other end was dropped.
+                    return Poll::Ready(Ok(0));
+                }
+                // Only ever polled in a busy loop, so there's no need to wake anyone.
+                return Poll::Pending;
+            }
+            let len = buf.len().min(incoming.len());
+            for (byte, b) in buf.iter_mut().zip(incoming.drain(..len)) {
+                *byte = b;
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl AsyncWrite for Pipe {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let len = buf.len().min(self.max_write);
+            self.outgoing.lock().unwrap().extend(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Poll both futures in turn until they're done.
+    fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let (mut a, mut b) = (Box::pin(a), Box::pin(b));
+        let (mut a_out, mut b_out) = (None, None);
+        while a_out.is_none() || b_out.is_none() {
+            if a_out.is_none() {
+                if let Poll::Ready(out) = a.as_mut().poll(&mut cx) {
+                    a_out = Some(out);
+                }
+            }
+            if b_out.is_none() {
+                if let Poll::Ready(out) = b.as_mut().poll(&mut cx) {
+                    b_out = Some(out);
+                }
+            }
+        }
+        (a_out.unwrap(), b_out.unwrap())
+    }
+
+    fn stream_pair(max_write: usize) -> (NoiseStream<Pipe>, NoiseStream<Pipe>) {
+        let (a, b) = pipe(max_write);
+        let initiator = Builder::new(PARAMS.parse().unwrap()).build_initiator().unwrap();
+        let responder = Builder::new(PARAMS.parse().unwrap()).build_responder().unwrap();
+        let (i, r) = join(handshake(initiator, a), handshake(responder, b));
+        (i.unwrap(), r.unwrap())
+    }
+
+    fn poll_once<F: Future>(future: F) -> F::Output {
+        join(future, async {}).0
+    }
+
+    async fn write_all<S: AsyncWrite + Unpin>(stream: &mut S, buf: &[u8]) -> io::Result<()> {
+        WriteAll { stream, buf }.await?;
+        Flush { stream }.await
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Short writes on the pipe make sure partial frames get picked up where they left off.
+        let (mut i, mut r) = stream_pair(7);
+        let big: Vec<u8> = (0..100_000u32).map(|n| n as u8).collect();
+
+        poll_once(write_all(&mut i, b"hack the planet")).unwrap();
+        let mut buf = [0u8; 4];
+        poll_once(ReadExact { stream: &mut r, buf: &mut buf }).unwrap();
+        assert_eq!(&buf, b"hack");
+
+        let (sent, received) = join(write_all(&mut i, &big), async {
+            let mut rest = [0u8; 11];
+            ReadExact { stream: &mut r, buf: &mut rest }.await?;
+            assert_eq!(&rest, b" the planet");
+            let mut received = vec![0u8; big.len()];
+            ReadExact { stream: &mut r, buf: &mut received }.await.map(|_| received)
+        });
+        sent.unwrap();
+        assert_eq!(received.unwrap(), big);
+        assert_eq!(i.transport().sending_nonce(), 3);
+    }
+
+    #[test]
+    fn test_tampering() {
+        let (mut i, mut r) = stream_pair(usize::MAX);
+        poll_once(write_all(&mut i, b"hack the planet")).unwrap();
+        let last = r.stream.incoming.lock().unwrap().back_mut().map(|b| *b ^= 1);
+        assert!(last.is_some());
+
+        let mut buf = [0u8; 15];
+        let err = poll_once(ReadExact { stream: &mut r, buf: &mut buf }).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// XORs each length prefix with a counter, so consecutive ones differ.
+    struct Counter(u16);
+
+    impl FrameObfuscation for Counter {
+        fn obfuscate(&mut self, header: &mut [u8; 2]) {
+            self.0 += 1;
+            *header = (u16::from_be_bytes(*header) ^ self.0).to_be_bytes();
+        }
+
+        fn deobfuscate(&mut self, header: &mut [u8; 2]) {
+            self.obfuscate(header)
+        }
+    }
+
+    #[test]
+    fn test_frame_obfuscation() {
+        let (i, r) = stream_pair(1);
+        let (mut i, mut r) =
+            (i.with_frame_obfuscation(Counter(0)), r.with_frame_obfuscation(Counter(0)));
+        poll_once(write_all(&mut i, b"hack")).unwrap();
+        let header: Vec<u8> = r.stream.incoming.lock().unwrap().iter().take(2).copied().collect();
+        assert_eq!(header, [0, (4 + TAGLEN as u8) ^ 1]);
+
+        for _ in 0..3 {
+            let mut buf = [0u8; 4];
+            poll_once(write_all(&mut i, b"hack")).unwrap();
+            poll_once(ReadExact { stream: &mut r, buf: &mut buf }).unwrap();
+            assert_eq!(&buf, b"hack");
+        }
+    }
+
+    #[test]
+    fn test_eof() {
+        let (mut i, mut r) = stream_pair(usize::MAX);
+        poll_once(write_all(&mut i, b"hack the planet")).unwrap();
+        poll_once(write_all(&mut i, b"hack the planet")).unwrap();
+        drop(i);
+
+        let mut buf = [0u8; 15];
+        poll_once(ReadExact { stream: &mut r, buf: &mut buf }).unwrap();
+        // A truncated frame followed by the end of the stream isn't a clean EOF...
+        r.stream.incoming.lock().unwrap().pop_back();
+        let err = poll_once(ReadExact { stream: &mut r, buf: &mut buf }).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // ...but ending between frames is.
+        let (mut i, mut r) = stream_pair(usize::MAX);
+        poll_once(write_all(&mut i, b"hack the planet")).unwrap();
+        drop(i);
+        poll_once(ReadExact { stream: &mut r, buf: &mut buf }).unwrap();
+        assert_eq!(poll_once(Read { stream: &mut r, buf: &mut buf }).unwrap(), 0);
+    }
+
+    struct Read<'a, S> {
+        stream: &'a mut S,
+        buf:    &'a mut [u8],
+    }
+
+    impl<S: AsyncRead + Unpin> Future for Read<'_, S> {
+        type Output = io::Result<usize>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = &mut *self;
+            Pin::new(&mut *this.stream).poll_read(cx, this.buf)
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! A minimal [Strobe](https://strobe.sourceforge.io/specs/) v1.0.2 duplex, at the 128-bit
+//! security level, covering only the operations needed by Disco.
+
+/// The Strobe version string mixed in to the initial state.
+pub(crate) const STROBE_VERSION: &str = "STROBEv1.0.2";
+
+/// The Keccak-f\[1600\] state size in bytes.
+const STATE_LEN: usize = 200;
+
+/// The duplex rate for 128-bit security, i.e. `STATE_LEN - 2 * 128 / 8 - 2`.
+const RATE: usize = 166;
+
+const FLAG_I: u8 = 1 << 0;
+const FLAG_A: u8 = 1 << 1;
+const FLAG_C: u8 = 1 << 2;
+const FLAG_T: u8 = 1 << 3;
+const FLAG_M: u8 = 1 << 4;
+
+#[derive(Clone)]
+pub(crate) struct Strobe {
+    st:          [u8; STATE_LEN],
+    pos:         usize,
+    pos_begin:   usize,
+    is_receiver: Option<bool>,
+}
+
+impl Strobe {
+    pub fn new(proto: &[u8]) -> Self {
+        let mut st = [0u8; STATE_LEN];
+        st[..6].copy_from_slice(&[0x01, RATE as u8 + 2, 0x01, 0x00, 0x01, 0x60]);
+        st[6..18].copy_from_slice(STROBE_VERSION.as_bytes());
+        keccak_f(&mut st);
+
+        let mut strobe = Strobe { st, pos: 0, pos_begin: 0, is_receiver: None };
+        strobe.begin_op(FLAG_M | FLAG_A);
+        strobe.absorb(proto);
+        strobe
+    }
+
+    /// Mix associated data into the state.
+    pub fn ad(&mut self, data: &[u8]) {
+        self.begin_op(FLAG_A);
+        self.absorb(data);
+    }
+
+    /// Mix associated metadata into the state.
+    pub fn meta_ad(&mut self, data: &[u8]) {
+        self.begin_op(FLAG_M | FLAG_A);
+        self.absorb(data);
+    }
+
+    /// Mix a plaintext message being sent into the state.
+    pub fn send_clr(&mut self, data: &[u8]) {
+        self.begin_op(FLAG_A | FLAG_T);
+        self.absorb(data);
+    }
+
+    /// Mix a plaintext message being received into the state.
+    pub fn recv_clr(&mut self, data: &[u8]) {
+        self.begin_op(FLAG_I | FLAG_A | FLAG_T);
+        self.absorb(data);
+    }
+
+    /// Encrypt `data` in place.
+    pub fn send_enc(&mut self, data: &mut [u8]) {
+        self.begin_op(FLAG_A | FLAG_C | FLAG_T);
+        for b in data {
+            self.st[self.pos] ^= *b;
+            *b = self.st[self.pos];
+            self.advance();
+        }
+    }
+
+    /// Decrypt `data` in place.
+    pub fn recv_enc(&mut self, data: &mut [u8]) {
+        self.begin_op(FLAG_I | FLAG_A | FLAG_C | FLAG_T);
+        self.exchange(data);
+    }
+
+    /// Write a MAC of the current state to `out`.
+    pub fn send_mac(&mut self, out: &mut [u8]) {
+        self.begin_op(FLAG_C | FLAG_T);
+        for b in out {
+            *b = self.st[self.pos];
+            self.advance();
+        }
+    }
+
+    /// Check `mac` against the current state, in constant time.
+    pub fn recv_mac(&mut self, mac: &[u8]) -> Result<(), ()> {
+        self.begin_op(FLAG_I | FLAG_C | FLAG_T);
+        let mut diff = 0u8;
+        for b in mac {
+            diff |= *b ^ self.st[self.pos];
+            self.st[self.pos] = *b;
+            self.advance();
+        }
+        if diff == 0 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Fill `out` with pseudorandom bytes derived from the current state.
+    pub fn prf(&mut self, out: &mut [u8]) {
+        self.begin_op(FLAG_I | FLAG_A | FLAG_C);
+        for b in out {
+            *b = self.st[self.pos];
+            self.st[self.pos] = 0;
+            self.advance();
+        }
+    }
+
+    /// Irreversibly zero `len` bytes of the state, preventing rollback.
+    pub fn ratchet(&mut self, len: usize) {
+        self.begin_op(FLAG_C);
+        for _ in 0..len {
+            self.st[self.pos] = 0;
+            self.advance();
+        }
+    }
+
+    fn begin_op(&mut self, mut flags: u8) {
+        if flags & FLAG_T != 0 {
+            // Both sides agree on the direction of the message by flipping the I flag relative
+            // to the direction of their first transport operation.
+            let receiving = flags & FLAG_I != 0;
+            let is_receiver = *self.is_receiver.get_or_insert(receiving);
+            flags = (flags & !FLAG_I) | (is_receiver != receiving) as u8;
+        }
+
+        let old_begin = self.pos_begin as u8;
+        self.pos_begin = self.pos + 1;
+        self.absorb(&[old_begin, flags]);
+
+        if flags & FLAG_C != 0 && self.pos != 0 {
+            self.run_f();
+        }
+    }
+
+    fn absorb(&mut self, data: &[u8]) {
+        for b in data {
+            self.st[self.pos] ^= *b;
+            self.advance();
+        }
+    }
+
+    fn exchange(&mut self, data: &mut [u8]) {
+        for b in data {
+            *b ^= self.st[self.pos];
+            self.st[self.pos] ^= *b;
+            self.advance();
+        }
+    }
+
+    fn advance(&mut self) {
+        self.pos += 1;
+        if self.pos == RATE {
+            self.run_f();
+        }
+    }
+
+    fn run_f(&mut self) {
+        self.st[self.pos] ^= self.pos_begin as u8;
+        self.st[self.pos + 1] ^= 0x04;
+        self.st[RATE + 1] ^= 0x80;
+        keccak_f(&mut self.st);
+        self.pos = 0;
+        self.pos_begin = 0;
+    }
+}
+
+fn keccak_f(st: &mut [u8; STATE_LEN]) {
+    let mut lanes = [0u64; STATE_LEN / 8];
+    for (lane, bytes) in lanes.iter_mut().zip(st.chunks_exact(8)) {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(bytes);
+        *lane = u64::from_le_bytes(buf);
+    }
+    keccak::f1600(&mut lanes);
+    for (lane, bytes) in lanes.iter().zip(st.chunks_exact_mut(8)) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init() {
+        // From the reference Python implementation: Strobe("", security=128).st
+        let strobe = Strobe::new(b"");
+        assert_eq!(strobe.st[..8], [0x9c, 0x7f, 0x16, 0x8f, 0xf8, 0xfd, 0x55, 0xda]);
+        assert_eq!(strobe.st[192..], [0xfe, 0xfa, 0xa1, 0x6a, 0xbf, 0xd9, 0xfb, 0xf6]);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut sender = Strobe::new(b"test");
+        let mut receiver = sender.clone();
+        let mut message = *b"hello";
+        let mut mac = [0u8; 16];
+        sender.send_enc(&mut message);
+        sender.send_mac(&mut mac);
+        assert_ne!(&message, b"hello");
+
+        let mut tampered = receiver.clone();
+        receiver.recv_enc(&mut message);
+        assert_eq!(&message, b"hello");
+        assert!(receiver.recv_mac(&mac).is_ok());
+
+        mac[0] ^= 1;
+        tampered.recv_enc(&mut [0u8; 5]);
+        assert!(tampered.recv_mac(&mac).is_err());
+    }
+}
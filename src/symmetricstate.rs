@@ -1,88 +1,177 @@
 use crate::{
-    cipherstate::CipherState,
+    cipherstate::{CipherCheckpoint, CipherState},
     constants::{CIPHERKEYLEN, MAXHASHLEN},
     error::Error,
-    types::Hash,
+    types::{Cipher, Hash},
 };
+#[cfg(feature = "disco")]
+use crate::{constants::TAGLEN, strobe::Strobe};
 
-#[derive(Copy, Clone)]
+/// The length of a Disco handshake hash.
+#[cfg(feature = "disco")]
+const DISCO_HASHLEN: usize = 32;
+
+#[derive(Clone)]
 pub(crate) struct SymmetricStateData {
     h:       [u8; MAXHASHLEN],
     ck:      [u8; MAXHASHLEN],
     has_key: bool,
+    #[cfg(feature = "disco")]
+    strobe:  Option<Strobe>,
 }
 
 impl Default for SymmetricStateData {
     fn default() -> Self {
         SymmetricStateData {
-            h:       [0u8; MAXHASHLEN],
-            ck:      [0u8; MAXHASHLEN],
-            has_key: false,
+            h:                                [0u8; MAXHASHLEN],
+            ck:                               [0u8; MAXHASHLEN],
+            has_key:                          false,
+            #[cfg(feature = "disco")]
+            strobe:                           None,
         }
     }
 }
 
-pub(crate) struct SymmetricState {
-    cipherstate: CipherState,
-    hasher:      Box<dyn Hash>,
-    inner:       SymmetricStateData,
+/// What `SymmetricState::restore()` needs to undo a failed handshake message.
+pub(crate) struct SymmetricCheckpoint {
+    inner:  SymmetricStateData,
+    cipher: Option<CipherCheckpoint>,
+}
+
+/// The primitives a `SymmetricState` is built from.
+enum Primitives<C, H> {
+    Noise {
+        cipherstate: CipherState<C>,
+        hasher:      H,
+    },
+
+    /// Disco replaces the cipher and hash with a single Strobe duplex, which is kept in
+    /// `SymmetricStateData` so that it's covered by checkpoints.
+    #[cfg(feature = "disco")]
+    Disco,
+}
+
+pub(crate) struct SymmetricState<C = Box<dyn Cipher>, H = Box<dyn Hash>> {
+    primitives: Primitives<C, H>,
+    inner:      SymmetricStateData,
 }
 
+#[cfg(feature = "disco")]
 impl SymmetricState {
-    pub fn new(cipherstate: CipherState, hasher: Box<dyn Hash>) -> SymmetricState {
-        SymmetricState { cipherstate, hasher, inner: SymmetricStateData::default() }
+    pub fn new_disco() -> SymmetricState {
+        SymmetricState { primitives: Primitives::Disco, inner: SymmetricStateData::default() }
+    }
+}
+
+impl<C: Cipher, H: Hash> SymmetricState<C, H> {
+    pub fn new(cipherstate: CipherState<C>, hasher: H) -> Self {
+        SymmetricState {
+            primitives: Primitives::Noise { cipherstate, hasher },
+            inner:      SymmetricStateData::default(),
+        }
     }
 
     pub fn initialize(&mut self, handshake_name: &str) {
-        if handshake_name.len() <= self.hasher.hash_len() {
-            copy_slices!(handshake_name.as_bytes(), self.inner.h);
-        } else {
-            self.hasher.reset();
-            self.hasher.input(handshake_name.as_bytes());
-            self.hasher.result(&mut self.inner.h);
-        }
-        copy_slices!(&self.inner.h, &mut self.inner.ck);
+        match &mut self.primitives {
+            Primitives::Noise { hasher, .. } => {
+                if handshake_name.len() <= hasher.hash_len() {
+                    copy_slices!(handshake_name.as_bytes(), self.inner.h);
+                } else {
+                    hasher.reset();
+                    hasher.input(handshake_name.as_bytes());
+                    hasher.result(&mut self.inner.h);
+                }
+                copy_slices!(&self.inner.h, &mut self.inner.ck);
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => {
+                self.inner.strobe = Some(Strobe::new(handshake_name.as_bytes()));
+                self.update_disco_hash();
+            },
+        }
         self.inner.has_key = false;
     }
 
     pub fn mix_key(&mut self, data: &[u8]) {
-        let hash_len = self.hasher.hash_len();
-        let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
-        self.hasher.hkdf(
-            &self.inner.ck[..hash_len],
-            data,
-            2,
-            &mut hkdf_output.0,
-            &mut hkdf_output.1,
-            &mut [],
-        );
-        copy_slices!(&hkdf_output.0, &mut self.inner.ck);
-        self.cipherstate.set(&hkdf_output.1[..CIPHERKEYLEN], 0);
+        match &mut self.primitives {
+            Primitives::Noise { cipherstate, hasher } => {
+                let hash_len = hasher.hash_len();
+                let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+                hasher.hkdf(
+                    &self.inner.ck[..hash_len],
+                    data,
+                    2,
+                    &mut hkdf_output.0,
+                    &mut hkdf_output.1,
+                    &mut [],
+                );
+                copy_slices!(&hkdf_output.0, &mut self.inner.ck);
+                cipherstate.set(&hkdf_output.1[..CIPHERKEYLEN], 0);
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => self.mix_disco(data),
+        }
         self.inner.has_key = true;
     }
 
     pub fn mix_hash(&mut self, data: &[u8]) {
-        let hash_len = self.hasher.hash_len();
-        self.hasher.reset();
-        self.hasher.input(&self.inner.h[..hash_len]);
-        self.hasher.input(data);
-        self.hasher.result(&mut self.inner.h);
+        match &mut self.primitives {
+            Primitives::Noise { hasher, .. } => {
+                let hash_len = hasher.hash_len();
+                hasher.reset();
+                hasher.input(&self.inner.h[..hash_len]);
+                hasher.input(data);
+                hasher.result(&mut self.inner.h);
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => self.mix_disco(data),
+        }
     }
 
     pub fn mix_key_and_hash(&mut self, data: &[u8]) {
-        let hash_len = self.hasher.hash_len();
-        let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
-        self.hasher.hkdf(
-            &self.inner.ck[..hash_len],
-            data,
-            3,
-            &mut hkdf_output.0,
-            &mut hkdf_output.1,
-            &mut hkdf_output.2,
-        );
-        copy_slices!(&hkdf_output.0, &mut self.inner.ck);
-        self.mix_hash(&hkdf_output.1[..hash_len]);
-        self.cipherstate.set(&hkdf_output.2[..CIPHERKEYLEN], 0);
+        match &mut self.primitives {
+            Primitives::Noise { cipherstate, hasher } => {
+                let hash_len = hasher.hash_len();
+                let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+                hasher.hkdf(
+                    &self.inner.ck[..hash_len],
+                    data,
+                    3,
+                    &mut hkdf_output.0,
+                    &mut hkdf_output.1,
+                    &mut hkdf_output.2,
+                );
+                copy_slices!(&hkdf_output.0, &mut self.inner.ck);
+                cipherstate.set(&hkdf_output.2[..CIPHERKEYLEN], 0);
+                self.mix_hash(&hkdf_output.1[..hash_len]);
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => self.mix_disco(data),
+        }
+    }
+
+    /// The `MixPsk()` of the spec's legacy PSK mode: `ck, temp = HKDF(ck, psk, 2)`, then
+    /// `MixHash(temp)`, without touching the cipher key.
+    #[cfg(feature = "legacy-psk")]
+    pub fn mix_psk_legacy(&mut self, psk: &[u8]) {
+        match &mut self.primitives {
+            Primitives::Noise { hasher, .. } => {
+                let hash_len = hasher.hash_len();
+                let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+                hasher.hkdf(
+                    &self.inner.ck[..hash_len],
+                    psk,
+                    2,
+                    &mut hkdf_output.0,
+                    &mut hkdf_output.1,
+                    &mut [],
+                );
+                copy_slices!(&hkdf_output.0, &mut self.inner.ck);
+                self.mix_hash(&hkdf_output.1[..hash_len]);
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => self.mix_disco(psk),
+        }
     }
 
     pub fn has_key(&self) -> bool {
@@ -95,54 +184,200 @@ impl SymmetricState {
         plaintext: &[u8],
         out: &mut [u8],
     ) -> Result<usize, Error> {
-        let hash_len = self.hasher.hash_len();
-        let output_len = if self.inner.has_key {
-            self.cipherstate.encrypt_ad(&self.inner.h[..hash_len], plaintext, out)?
-        } else {
-            copy_slices!(plaintext, out);
-            plaintext.len()
-        };
-        self.mix_hash(&out[..output_len]);
-        Ok(output_len)
+        match &mut self.primitives {
+            Primitives::Noise { cipherstate, hasher } => {
+                let hash_len = hasher.hash_len();
+                let output_len = if self.inner.has_key {
+                    cipherstate.encrypt_ad(&self.inner.h[..hash_len], plaintext, out)?
+                } else {
+                    copy_slices!(plaintext, out);
+                    plaintext.len()
+                };
+                self.mix_hash(&out[..output_len]);
+                Ok(output_len)
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => {
+                let strobe = self.inner.strobe.as_mut().unwrap();
+                let len = plaintext.len();
+                copy_slices!(plaintext, out);
+                let output_len = if self.inner.has_key {
+                    strobe.send_enc(&mut out[..len]);
+                    strobe.send_mac(&mut out[len..len + TAGLEN]);
+                    len + TAGLEN
+                } else {
+                    strobe.send_clr(plaintext);
+                    len
+                };
+                self.update_disco_hash();
+                Ok(output_len)
+            },
+        }
     }
 
     pub fn decrypt_and_mix_hash(&mut self, data: &[u8], out: &mut [u8]) -> Result<usize, ()> {
-        let hash_len = self.hasher.hash_len();
-        let payload_len = if self.inner.has_key {
-            self.cipherstate.decrypt_ad(&self.inner.h[..hash_len], data, out)?
-        } else {
-            if out.len() < data.len() {
-                return Err(());
-            }
-            copy_slices!(data, out);
-            data.len()
-        };
-        self.mix_hash(data);
-        Ok(payload_len)
+        match &mut self.primitives {
+            Primitives::Noise { cipherstate, hasher } => {
+                let hash_len = hasher.hash_len();
+                let payload_len = if self.inner.has_key {
+                    cipherstate.decrypt_ad(&self.inner.h[..hash_len], data, out)?
+                } else {
+                    if out.len() < data.len() {
+                        return Err(());
+                    }
+                    copy_slices!(data, out);
+                    data.len()
+                };
+                self.mix_hash(data);
+                Ok(payload_len)
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => {
+                let strobe = self.inner.strobe.as_mut().unwrap();
+                let payload_len = if self.inner.has_key {
+                    if data.len() < TAGLEN || out.len() < data.len() - TAGLEN {
+                        return Err(());
+                    }
+                    let len = data.len() - TAGLEN;
+                    copy_slices!(&data[..len], out);
+                    strobe.recv_enc(&mut out[..len]);
+                    strobe.recv_mac(&data[len..])?;
+                    len
+                } else {
+                    if out.len() < data.len() {
+                        return Err(());
+                    }
+                    copy_slices!(data, out);
+                    strobe.recv_clr(data);
+                    data.len()
+                };
+                self.update_disco_hash();
+                Ok(payload_len)
+            },
+        }
     }
 
-    pub fn split(&mut self, child1: &mut CipherState, child2: &mut CipherState) {
+    pub fn split(&mut self, child1: &mut CipherState<C>, child2: &mut CipherState<C>) {
+        #[cfg(feature = "disco")]
+        if let Primitives::Disco = self.primitives {
+            child1.set_strobe(self.split_disco(b"initiator"));
+            child2.set_strobe(self.split_disco(b"responder"));
+            return;
+        }
+
         let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
         self.split_raw(&mut hkdf_output.0, &mut hkdf_output.1);
         child1.set(&hkdf_output.0[..CIPHERKEYLEN], 0);
         child2.set(&hkdf_output.1[..CIPHERKEYLEN], 0);
     }
 
-    pub fn split_raw(&mut self, mut out1: &mut [u8], mut out2: &mut [u8]) {
-        let hash_len = self.hasher.hash_len();
-        self.hasher.hkdf(&self.inner.ck[..hash_len], &[0u8; 0], 2, &mut out1, &mut out2, &mut []);
+    pub fn split_raw(&mut self, out1: &mut [u8], out2: &mut [u8]) {
+        match &mut self.primitives {
+            Primitives::Noise { hasher, .. } => {
+                let hash_len = hasher.hash_len();
+                hasher.hkdf(&self.inner.ck[..hash_len], &[0u8; 0], 2, out1, out2, &mut []);
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => {
+                self.split_disco(b"initiator").prf(out1);
+                self.split_disco(b"responder").prf(out2);
+            },
+        }
+    }
+
+    /// The chaining key, for protocols that keep deriving keys from it after the handshake.
+    #[cfg(any(
+        feature = "risky-chaining-key",
+        all(feature = "cipher-chachapoly", feature = "hash-sha2")
+    ))]
+    pub fn chaining_key(&self) -> &[u8] {
+        match &self.primitives {
+            Primitives::Noise { hasher, .. } => &self.inner.ck[..hasher.hash_len()],
+            #[cfg(feature = "disco")]
+            Primitives::Disco => &[],
+        }
+    }
+
+    /// Derive a secret from the chaining key that's independent of the transport keys, by
+    /// using `label` as the input key material (`split()` uses none).
+    pub fn derive_secret(&mut self, label: &[u8], out: &mut [u8]) {
+        match &mut self.primitives {
+            Primitives::Noise { hasher, .. } => {
+                let hash_len = hasher.hash_len();
+                let mut secret = [0u8; MAXHASHLEN];
+                hasher.hkdf(&self.inner.ck[..hash_len], label, 1, &mut secret, &mut [], &mut []);
+                out.copy_from_slice(&secret[..out.len()]);
+            },
+            #[cfg(feature = "disco")]
+            Primitives::Disco => self.split_disco(label).prf(out),
+        }
     }
 
-    pub(crate) fn checkpoint(&mut self) -> SymmetricStateData {
-        self.inner
+    /// Disco has no nonce, since the duplex state already changes with every message.
+    #[cfg(feature = "risky-set-nonce")]
+    pub fn nonce(&self) -> u64 {
+        match &self.primitives {
+            Primitives::Noise { cipherstate, .. } => cipherstate.nonce(),
+            #[cfg(feature = "disco")]
+            Primitives::Disco => 0,
+        }
     }
 
-    pub(crate) fn restore(&mut self, checkpoint: SymmetricStateData) {
-        self.inner = checkpoint;
+    #[cfg(feature = "risky-set-nonce")]
+    pub fn set_nonce(&mut self, nonce: u64) {
+        match &mut self.primitives {
+            Primitives::Noise { cipherstate, .. } => cipherstate.set_nonce(nonce),
+            #[cfg(feature = "disco")]
+            Primitives::Disco => {},
+        }
+    }
+
+    pub(crate) fn checkpoint(&mut self) -> SymmetricCheckpoint {
+        let cipher = match &self.primitives {
+            Primitives::Noise { cipherstate, .. } => Some(cipherstate.checkpoint()),
+            #[cfg(feature = "disco")]
+            Primitives::Disco => None,
+        };
+        SymmetricCheckpoint { inner: self.inner.clone(), cipher }
+    }
+
+    pub(crate) fn restore(&mut self, checkpoint: SymmetricCheckpoint) {
+        if let (Primitives::Noise { cipherstate, .. }, Some(cipher)) =
+            (&mut self.primitives, checkpoint.cipher)
+        {
+            cipherstate.restore(cipher);
+        }
+        self.inner = checkpoint.inner;
     }
 
     pub fn handshake_hash(&self) -> &[u8] {
-        let hash_len = self.hasher.hash_len();
+        let hash_len = match &self.primitives {
+            Primitives::Noise { hasher, .. } => hasher.hash_len(),
+            #[cfg(feature = "disco")]
+            Primitives::Disco => DISCO_HASHLEN,
+        };
         &self.inner.h[..hash_len]
     }
+
+    #[cfg(feature = "disco")]
+    fn mix_disco(&mut self, data: &[u8]) {
+        self.inner.strobe.as_mut().unwrap().ad(data);
+        self.update_disco_hash();
+    }
+
+    /// Disco's handshake hash is a PRF output of the current duplex state, which is computed on
+    /// a copy so the handshake itself isn't affected.
+    #[cfg(feature = "disco")]
+    fn update_disco_hash(&mut self) {
+        let mut strobe = self.inner.strobe.clone().unwrap();
+        strobe.prf(&mut self.inner.h[..DISCO_HASHLEN]);
+    }
+
+    #[cfg(feature = "disco")]
+    fn split_disco(&self, label: &[u8]) -> Strobe {
+        let mut strobe = self.inner.strobe.clone().unwrap();
+        strobe.meta_ad(label);
+        strobe.ratchet(TAGLEN);
+        strobe
+    }
 }
@@ -0,0 +1,120 @@
+//! Opt-in records of what a handshake did, for compliance audits and for tracking down interop
+//! failures byte by byte.
+//!
+//! A handshake built with [`Builder::record_transcript()`](crate::Builder::record_transcript)
+//! keeps a [`Transcript`] of every message it writes or reads: the tokens it processed, the
+//! public keys they carried, the handshake hash after each one, and the message and payload
+//! lengths. Messages that fail are recorded too, up to the token that failed, along with the
+//! error. Comparing two sides' transcripts (or one side's against another implementation's
+//! trace) shows the first step where their handshake hashes part ways.
+//!
+//! Nothing secret goes into a transcript: no private keys, PSKs, DH results, chaining keys or
+//! payloads. Static public keys are recorded though, which the handshake itself may have
+//! encrypted, so a transcript reveals who the peers are.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::Builder;
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let mut initiator = Builder::new(params.clone()).record_transcript().build_initiator()?;
+//! let mut responder = Builder::new(params).record_transcript().build_responder()?;
+//! # let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//! # let len = initiator.write_message(&[], &mut msg)?; responder.read_message(&msg[..len], &mut buf)?;
+//! # let len = responder.write_message(&[], &mut msg)?; initiator.read_message(&msg[..len], &mut buf)?;
+//! // ... finish the handshake ...
+//! let (sent, received) = (initiator.transcript().unwrap(), responder.transcript().unwrap());
+//! let steps: Vec<_> = sent.messages[1].steps.iter().map(|step| step.token.as_str()).collect();
+//! assert_eq!(steps, ["e", "ee"]);
+//! assert_eq!(sent.messages[0].handshake_hash, received.messages[0].handshake_hash);
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::params::NoiseParams;
+
+/// The record of one side of a handshake.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Transcript {
+    /// The protocol name.
+    pub protocol:     String,
+    /// Whether this side is the initiator.
+    pub initiator:    bool,
+    /// The handshake hash once the prologue and any pre-message keys are mixed in, before the
+    /// first message.
+    pub initial_hash: Vec<u8>,
+    /// Every message written or read, in order.
+    pub messages:     Vec<MessageRecord>,
+    /// The steps of the message in progress.
+    pending:          Vec<Step>,
+}
+
+/// One handshake message in a [`Transcript`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MessageRecord {
+    /// Whether this side wrote the message, rather than read it.
+    pub outgoing:       bool,
+    /// The length of the message on the wire, unless writing it failed.
+    pub message_len:    Option<usize>,
+    /// The length of the payload, unless the message failed.
+    pub payload_len:    Option<usize>,
+    /// The tokens processed, up to the one that failed if the message did.
+    pub steps:          Vec<Step>,
+    /// The handshake hash once the payload is mixed in, unless the message failed.
+    pub handshake_hash: Option<Vec<u8>>,
+    /// Why the message failed, if it did.
+    pub error:          Option<String>,
+}
+
+/// One token of a handshake message.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Step {
+    /// The token, as the spec writes it: `e`, `s`, `ee`, `psk1` and so on.
+    pub token:          String,
+    /// The public key an `e` or `s` token carried.
+    pub public_key:     Option<Vec<u8>>,
+    /// The handshake hash after the token.
+    pub handshake_hash: Vec<u8>,
+}
+
+impl Transcript {
+    pub(crate) fn new(params: &NoiseParams, initiator: bool, initial_hash: &[u8]) -> Self {
+        Transcript {
+            protocol: params.name.clone(),
+            initiator,
+            initial_hash: initial_hash.to_vec(),
+            messages: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push_step(&mut self, step: Step) {
+        self.pending.push(step);
+    }
+
+    /// Close off the message in progress with the steps pushed since the last one.
+    pub(crate) fn push_message(
+        &mut self,
+        outgoing: bool,
+        message_len: Option<usize>,
+        result: Result<(usize, &[u8]), String>,
+    ) {
+        let (payload_len, handshake_hash, error) = match result {
+            Ok((payload_len, hash)) => (Some(payload_len), Some(hash.to_vec()), None),
+            Err(error) => (None, None, Some(error)),
+        };
+        self.messages.push(MessageRecord {
+            outgoing,
+            message_len,
+            payload_len,
+            steps: std::mem::take(&mut self.pending),
+            handshake_hash,
+            error,
+        });
+    }
+}
@@ -1,12 +1,33 @@
 use crate::{
-    cipherstate::CipherStates,
-    constants::{MAXDHLEN, MAXMSGLEN, TAGLEN},
-    error::{Error, StateProblem},
+    cipherstate::{CipherState, CipherStates},
+    constants::{CIPHERKEYLEN, MAXHASHLEN, MAXMSGLEN, MAXSTATICLEN, TAGLEN},
+    error::{Error, InitStage, StateProblem},
+    fixed::FixedMessage,
     handshakestate::HandshakeState,
-    params::HandshakePattern,
-    utils::Toggle,
+    observer::{Direction, SessionObserver},
+    padding::{self, PaddingPolicy},
+    params::NoiseParams,
+    rehandshake::Rehandshake,
+    resolvers::BoxedCryptoResolver,
+    types::{Cipher, Random},
+    utils::{check_message_len, Toggle, Traffic},
+    Builder,
 };
-use std::{convert::TryFrom, fmt};
+#[cfg(feature = "bytes")]
+use bytes::{Bytes, BytesMut};
+#[cfg(not(feature = "forbid-unsafe"))]
+use std::mem::MaybeUninit;
+use std::{convert::TryFrom, fmt, io::IoSlice, ops::Range, sync::Arc};
+
+/// The label mixed into the chaining key, along with the handshake hash, for the secret every
+/// sub-session is derived from.
+const SUBSESSION_LABEL: &[u8] = b"snow subsession";
+
+/// The associated data of key update messages, which keeps them from passing for data.
+const KEY_UPDATE_AD: &[u8] = b"snow key update";
+
+/// The label at the start of a rehandshake's prologue, ahead of the current handshake hash.
+const REHANDSHAKE_LABEL: &[u8] = b"snow rehandshake";
 
 /// A state machine encompassing the transport phase of a Noise session, using the two
 /// `CipherState`s (for sending and receiving) that were spawned from the `SymmetricState`'s
@@ -14,24 +35,67 @@ use std::{convert::TryFrom, fmt};
 ///
 /// Also see: [the relevant Noise spec section](http://noiseprotocol.org/noise.html#the-handshakestate-object).
 pub struct TransportState {
-    cipherstates: CipherStates,
-    pattern:      HandshakePattern,
-    dh_len:       usize,
-    rs:           Toggle<[u8; MAXDHLEN]>,
-    initiator:    bool,
+    core:              TransportStateCore<Box<dyn Cipher>>,
+    pub(crate) params: NoiseParams,
+    s_len:             usize,
+    s:                 Toggle<[u8; MAXSTATICLEN]>,
+    rs_len:            usize,
+    rs:                Toggle<[u8; MAXSTATICLEN]>,
+    rng:               Box<dyn Random>,
+    padding:           Option<PaddingPolicy>,
+    observer:          Option<Arc<dyn SessionObserver>>,
+    traffic:           Traffic,
+    max_message_len:   Option<usize>,
+    /// The end of the block of sending nonces handed out by `reserve_nonces()`, if any.
+    reserved_until:    Option<u64>,
+    /// The secret `derive_subsession()` derives from, which imported sessions don't have.
+    subsession_root:   Option<Vec<u8>>,
+    /// The hash of the handshake that set up this session, which imported sessions don't have.
+    handshake_hash:    Option<Vec<u8>>,
 }
 
 impl TransportState {
-    pub(crate) fn new(handshake: HandshakeState) -> Result<Self, Error> {
+    pub(crate) fn new(mut handshake: HandshakeState) -> Result<Self, Error> {
         if !handshake.is_handshake_finished() {
             bail!(StateProblem::HandshakeNotFinished);
         }
 
-        let dh_len = handshake.dh_len();
-        let HandshakeState { cipherstates, params, rs, initiator, .. } = handshake;
-        let pattern = params.handshake.pattern;
+        let mut subsession_root = vec![0u8; handshake.get_handshake_hash().len()];
+        let label = [SUBSESSION_LABEL, handshake.get_handshake_hash()].concat();
+        handshake.symmetricstate.derive_secret(&label, &mut subsession_root);
+        let handshake_hash = handshake.get_handshake_hash().to_vec();
+
+        let (s_len, s) = handshake.local_static_copy();
+        let rs_len = handshake.remote_static_len();
+        let HandshakeState {
+            cipherstates,
+            params,
+            rs,
+            initiator,
+            rng,
+            padding,
+            observer,
+            max_message_len,
+            ..
+        } = handshake;
 
-        Ok(TransportState { cipherstates, pattern, dh_len, rs, initiator })
+        let oneway = params.handshake.pattern.is_oneway();
+        Ok(TransportState {
+            core: TransportStateCore::new(cipherstates, initiator, oneway),
+            params,
+            s_len,
+            s,
+            rs_len,
+            rs,
+            rng,
+            padding,
+            observer,
+            traffic: Traffic::default(),
+            max_message_len,
+            reserved_until: None,
+            subsession_root: Some(subsession_root),
+            handshake_hash: Some(handshake_hash),
+        })
     }
 
     /// Get the remote party's static public key, if available.
@@ -41,7 +105,40 @@ impl TransportState {
     /// static key is not yet known (as can be the case in the `XX`
     /// pattern, for example).
     pub fn get_remote_static(&self) -> Option<&[u8]> {
-        self.rs.get().map(|rs| &rs[..self.dh_len])
+        self.rs.get().map(|rs| &rs[..self.rs_len])
+    }
+
+    /// Get the local static public key, if this side had one in the handshake.
+    ///
+    /// Note: exported sessions don't include it, so this returns `None` for sessions restored
+    /// with [`Builder::import_session()`](crate::Builder::import_session).
+    pub fn get_local_static(&self) -> Option<&[u8]> {
+        self.s.get().map(|s| &s[..self.s_len])
+    }
+
+    /// The number of bytes sent in transport messages, counting their tags (and padding), i.e.
+    /// what went out on the wire.
+    ///
+    /// Sessions restored with [`Builder::import_session()`](crate::Builder::import_session)
+    /// count from zero.
+    pub fn bytes_sent(&self) -> u64 {
+        self.traffic.bytes_sent()
+    }
+
+    /// The number of bytes received in transport messages that decrypted successfully,
+    /// counting their tags (and padding).
+    pub fn bytes_received(&self) -> u64 {
+        self.traffic.bytes_received()
+    }
+
+    /// The number of transport messages sent.
+    pub fn messages_sent(&self) -> u64 {
+        self.traffic.messages_sent()
+    }
+
+    /// The number of transport messages received that decrypted successfully.
+    pub fn messages_received(&self) -> u64 {
+        self.traffic.messages_received()
     }
 
     /// Construct a message from `payload` (and pending handshake tokens if in handshake state),
@@ -53,16 +150,155 @@ impl TransportState {
     ///
     /// Will result in `Error::Input` if the size of the output exceeds the max message
     /// length in the Noise Protocol (65535 bytes).
+    ///
+    /// Will result in `Error::BufferTooSmall` if `message` can't hold the encrypted payload.
+    ///
+    /// Will result in `Error::State(StateProblem::OneWay)` if this is the responder of a
+    /// one-way pattern (`N`, `K` or `X`), which may only receive.
+    ///
+    /// Will result in `Error::State(StateProblem::NonceReservationExhausted)` if nonces were
+    /// reserved with [`reserve_nonces()`](Self::reserve_nonces) and they've all been used.
     pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
-        if !self.initiator && self.pattern.is_oneway() {
-            bail!(StateProblem::OneWay);
-        } else if payload.len() + TAGLEN > MAXMSGLEN || payload.len() + TAGLEN > message.len() {
-            bail!(Error::Input);
+        self.write_message_with_ad(&[], payload, message)
+    }
+
+    /// Like [`write_message()`](Self::write_message), but also authenticates `ad` as associated
+    /// data, e.g. a plaintext header with routing information. `ad` isn't part of the message:
+    /// the reader has to get it some other way and pass it to
+    /// [`read_message_with_ad()`](Self::read_message_with_ad).
+    ///
+    /// Returns the size of the written payload.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`write_message()`](Self::write_message).
+    pub fn write_message_with_ad(
+        &mut self,
+        ad: &[u8],
+        payload: &[u8],
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.check_sendable()?;
+
+        let len = match &self.padding {
+            Some(policy) => {
+                let padded_len = policy.padded_len(payload.len(), &mut *self.rng)?;
+                if padded_len + TAGLEN > message.len() {
+                    bail!(Error::BufferTooSmall {
+                        needed: padded_len + TAGLEN,
+                        got:    message.len(),
+                    });
+                }
+                self.core.write_message_with_ad(ad, &padding::pad(payload, padded_len)?, message)?
+            },
+            None => self.core.write_message_with_ad(ad, payload, message)?,
+        };
+        self.traffic.sent(len);
+        self.observe(|observer| observer.message_encrypted(len));
+        Ok(len)
+    }
+
+    /// Construct a message from a `payload` split across several buffers, e.g. a protocol
+    /// header and body, gathering them straight into `message` rather than concatenating them
+    /// first.
+    ///
+    /// Returns the size of the written payload.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`write_message()`](Self::write_message).
+    pub fn write_message_vectored(
+        &mut self,
+        payload: &[IoSlice<'_>],
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        let payload_len = payload.iter().map(|slice| slice.len()).sum::<usize>();
+        let plaintext_len = self.plaintext_len(payload_len)?;
+        self.seal(payload, plaintext_len, message)
+    }
+
+    /// Like [`write_message()`](Self::write_message), but writes into a buffer that hasn't been
+    /// initialized, so large buffers don't have to be zeroed before every message.
+    ///
+    /// Returns the written message, which is the only part of `message` that gets initialized.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`write_message()`](Self::write_message).
+    ///
+    /// Not available with the "forbid-unsafe" feature, since it takes unsafe code to hand out
+    /// the initialized part of the buffer.
+    #[cfg(not(feature = "forbid-unsafe"))]
+    pub fn write_message_uninit<'m>(
+        &mut self,
+        payload: &[u8],
+        message: &'m mut [MaybeUninit<u8>],
+    ) -> Result<&'m mut [u8], Error> {
+        let plaintext_len = self.plaintext_len(payload.len())?;
+        let framed = self.padding.is_some();
+        let message = crate::utils::init_message(message, payload, plaintext_len, framed)?;
+        let len = self.encrypt_plaintext(message, plaintext_len)?;
+        Ok(&mut message[..len])
+    }
+
+    /// Check that a payload of `payload_len` bytes may be sent, returning the length of its
+    /// (possibly padded) plaintext.
+    fn plaintext_len(&mut self, payload_len: usize) -> Result<usize, Error> {
+        self.check_sendable()?;
+
+        match &self.padding {
+            Some(policy) => {
+                if payload_len + padding::LENGTH_PREFIX_LEN + TAGLEN > MAXMSGLEN {
+                    bail!(Error::Input);
+                }
+                policy.padded_len(payload_len, &mut *self.rng)
+            },
+            None => {
+                if payload_len + TAGLEN > MAXMSGLEN {
+                    bail!(Error::Input);
+                }
+                Ok(payload_len)
+            },
         }
+    }
 
-        let cipher =
-            if self.initiator { &mut self.cipherstates.0 } else { &mut self.cipherstates.1 };
-        Ok(cipher.encrypt(payload, message)?)
+    /// Check that this side may send, and has a nonce left to send with.
+    fn check_sendable(&self) -> Result<(), Error> {
+        self.core.check_sendable()?;
+        if let Some(end) = self.reserved_until {
+            if self.sending_nonce() >= end {
+                bail!(StateProblem::NonceReservationExhausted);
+            }
+        }
+        Ok(())
+    }
+
+    /// Gather `payload` into a `plaintext_len`-byte plaintext at the front of `message`, then
+    /// encrypt it in place.
+    fn seal(
+        &mut self,
+        payload: &[IoSlice<'_>],
+        plaintext_len: usize,
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        if plaintext_len + TAGLEN > message.len() {
+            bail!(Error::BufferTooSmall { needed: plaintext_len + TAGLEN, got: message.len() });
+        }
+
+        padding::gather(payload, &mut message[..plaintext_len], self.padding.is_some());
+        self.encrypt_plaintext(message, plaintext_len)
+    }
+
+    /// Encrypt the `plaintext_len`-byte plaintext at the front of `message` in place.
+    fn encrypt_plaintext(
+        &mut self,
+        message: &mut [u8],
+        plaintext_len: usize,
+    ) -> Result<usize, Error> {
+        let len = self.core.encrypt_in_place(message, plaintext_len)?;
+        self.traffic.sent(len);
+        self.observe(|observer| observer.message_encrypted(len));
+        Ok(len)
     }
 
     /// Reads a noise message from `input`
@@ -74,16 +310,147 @@ impl TransportState {
     /// Will result in `Error::Decrypt` if the contents couldn't be decrypted and/or the
     /// authentication tag didn't verify.
     ///
+    /// Will result in `Error::TruncatedMessage` if `payload` is too short to hold a tag, or
+    /// `Error::BufferTooSmall` if `message` can't hold the decrypted payload.
+    ///
+    /// Will result in `Error::State(StateProblem::OneWay)` if this is the initiator of a
+    /// one-way pattern (`N`, `K` or `X`), which may only send.
+    ///
+    /// If a padding policy is in use, `message` must be large enough to hold the padded
+    /// plaintext, and malformed padding will result in `Error::Input`.
+    ///
+    /// Will result in `Error::MessageTooLarge` if `payload` is longer than
+    /// [`Builder::max_message_len()`](crate::Builder::max_message_len) allows.
+    ///
     /// # Panics
     ///
     /// This function will panic if there is no key, or if there is a nonce overflow.
     pub fn read_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
-        if self.initiator && self.pattern.is_oneway() {
-            bail!(StateProblem::OneWay);
+        self.read_message_with_ad(&[], payload, message)
+    }
+
+    /// Like [`read_message()`](Self::read_message), but also checks that the message was
+    /// written with `ad` as associated data.
+    ///
+    /// Returns the size of the payload written to `message`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`read_message()`](Self::read_message). An `ad` other than the writer's fails
+    /// to authenticate like a tampered message, with `Error::Decrypt`.
+    pub fn read_message_with_ad(
+        &mut self,
+        ad: &[u8],
+        payload: &[u8],
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.core.check_receivable()?;
+        check_message_len(self.max_message_len, payload.len())?;
+        let len = match self.core.read_message_with_ad(ad, payload, message) {
+            Err(Error::Decrypt) => {
+                self.observe(|observer| observer.decrypt_failed());
+                bail!(Error::Decrypt);
+            },
+            result => result?,
+        };
+        self.traffic.received(payload.len());
+        self.observe(|observer| observer.message_decrypted(payload.len()));
+        match self.padding {
+            Some(_) => padding::unpad(&mut message[..len]),
+            None => Ok(len),
         }
-        let cipher =
-            if self.initiator { &mut self.cipherstates.1 } else { &mut self.cipherstates.0 };
-        cipher.decrypt(payload, message).map_err(|_| Error::Decrypt)
+    }
+
+    /// Like [`read_message()`](Self::read_message), but decrypts `message` in place instead of
+    /// into a separate buffer, so e.g. a proxy can forward the payload straight from the buffer
+    /// it was received into.
+    ///
+    /// Returns where the payload is in `message`, which is left holding the plaintext. With a
+    /// padding policy, the payload starts after the padded plaintext's length prefix.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`read_message()`](Self::read_message). If decryption fails, the contents of
+    /// `message` are unspecified.
+    pub fn read_message_in_place(&mut self, message: &mut [u8]) -> Result<Range<usize>, Error> {
+        self.core.check_receivable()?;
+        check_message_len(self.max_message_len, message.len())?;
+        let message_len = message.len();
+        let len = match self.core.decrypt_in_place(message) {
+            Err(Error::Decrypt) => {
+                self.observe(|observer| observer.decrypt_failed());
+                bail!(Error::Decrypt);
+            },
+            result => result?,
+        };
+        self.traffic.received(message_len);
+        self.observe(|observer| observer.message_decrypted(message_len));
+        match self.padding {
+            Some(_) => padding::body_range(&message[..len]),
+            None => Ok(0..len),
+        }
+    }
+
+    /// Like [`read_message()`](Self::read_message), but writes into a buffer that hasn't been
+    /// initialized.
+    ///
+    /// Ciphers decrypt into initialized memory, so the part of `payload` the message decrypts
+    /// into is zeroed first: only the rest of a large buffer is spared.
+    ///
+    /// Returns the payload, which is at the front of the part of `payload` that gets initialized.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`read_message()`](Self::read_message).
+    ///
+    /// Not available with the "forbid-unsafe" feature, since it takes unsafe code to hand out
+    /// the initialized part of the buffer.
+    #[cfg(not(feature = "forbid-unsafe"))]
+    pub fn read_message_uninit<'p>(
+        &mut self,
+        message: &[u8],
+        payload: &'p mut [MaybeUninit<u8>],
+    ) -> Result<&'p mut [u8], Error> {
+        check_message_len(self.max_message_len, message.len())?;
+        let payload = crate::utils::init_prefix(payload, message.len().saturating_sub(TAGLEN));
+        let len = self.read_message(message, payload)?;
+        Ok(&mut payload[..len])
+    }
+
+    /// Construct a message from `payload` like [`write_message()`](Self::write_message), but
+    /// append it to `message`, growing the buffer by only as much as the message needs.
+    ///
+    /// Returns the size of the appended message. On error, `message` is left as it was.
+    ///
+    /// The "bytes" feature has to be enabled to use this function.
+    #[cfg(feature = "bytes")]
+    pub fn write_message_bytes(
+        &mut self,
+        payload: &[u8],
+        message: &mut BytesMut,
+    ) -> Result<usize, Error> {
+        let start = message.len();
+        let max_len = match self.padding {
+            Some(_) => MAXMSGLEN,
+            None => (payload.len() + TAGLEN).min(MAXMSGLEN),
+        };
+        message.resize(start + max_len, 0);
+        let result = self.write_message(payload, &mut message[start..]);
+        message.truncate(start + result.as_ref().map_or(0, |len| *len));
+        result
+    }
+
+    /// Read a noise message like [`read_message()`](Self::read_message), e.g. straight out of
+    /// a `Bytes` frame, returning the payload in a new buffer.
+    ///
+    /// The "bytes" feature has to be enabled to use this function.
+    #[cfg(feature = "bytes")]
+    pub fn read_message_bytes(&mut self, message: &[u8]) -> Result<Bytes, Error> {
+        check_message_len(self.max_message_len, message.len())?;
+        let mut payload = BytesMut::zeroed(message.len().saturating_sub(TAGLEN));
+        let len = self.read_message(message, &mut payload)?;
+        payload.truncate(len);
+        Ok(payload.freeze())
     }
 
     /// Generates a new key for the egress symmetric cipher according to Section 4.2
@@ -91,11 +458,8 @@ impl TransportState {
     /// responder is the responsibility of the application, as described in Section 11.3
     /// of the Noise Specification.
     pub fn rekey_outgoing(&mut self) {
-        if self.initiator {
-            self.cipherstates.rekey_initiator()
-        } else {
-            self.cipherstates.rekey_responder()
-        }
+        self.core.rekey_outgoing();
+        self.observe(|observer| observer.rekeyed(Direction::Outgoing));
     }
 
     /// Generates a new key for the ingress symmetric cipher according to Section 4.2
@@ -103,11 +467,45 @@ impl TransportState {
     /// responder is the responsibility of the application, as described in Section 11.3
     /// of the Noise Specification.
     pub fn rekey_incoming(&mut self) {
-        if self.initiator {
-            self.cipherstates.rekey_responder()
-        } else {
-            self.cipherstates.rekey_initiator()
+        self.core.rekey_incoming();
+        self.observe(|observer| observer.rekeyed(Direction::Incoming));
+    }
+
+    /// Write a key update message to `message`, then [rekey the sender](Self::rekey_outgoing).
+    /// The message is the last one under the old key, and the peer passes it to
+    /// [`read_key_update()`](Self::read_key_update), which rekeys its receiver to match, so
+    /// neither side has to guess when the other switched.
+    ///
+    /// The message has an empty payload, and is written with associated data that data
+    /// messages don't have. Telling the peer which messages are key updates is up to the
+    /// application's framing.
+    ///
+    /// Returns the size of the message.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`write_message()`](Self::write_message), without rekeying.
+    pub fn write_key_update(&mut self, message: &mut [u8]) -> Result<usize, Error> {
+        let len = self.write_message_with_ad(KEY_UPDATE_AD, &[], message)?;
+        self.rekey_outgoing();
+        Ok(len)
+    }
+
+    /// Read a message written by the peer's [`write_key_update()`](Self::write_key_update),
+    /// then [rekey the receiver](Self::rekey_incoming).
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`read_message()`](Self::read_message), without rekeying. A data message
+    /// fails with `Error::Decrypt`, and `Error::Input` means a key update with a payload.
+    pub fn read_key_update(&mut self, message: &[u8]) -> Result<(), Error> {
+        let mut payload = vec![0u8; message.len().saturating_sub(TAGLEN)];
+        let len = self.read_message_with_ad(KEY_UPDATE_AD, message, &mut payload)?;
+        if len != 0 {
+            bail!(Error::Input);
         }
+        self.rekey_incoming();
+        Ok(())
     }
 
     /// Set a new key for the one or both of the initiator-egress and responder-egress symmetric ciphers.
@@ -122,21 +520,31 @@ impl TransportState {
 
     /// Set a new key for the initiator-egress symmetric cipher.
     pub fn rekey_initiator_manually(&mut self, key: &[u8]) {
-        self.cipherstates.rekey_initiator_manually(key)
+        self.core.cipherstates.rekey_initiator_manually(key);
+        let direction = if self.is_initiator() { Direction::Outgoing } else { Direction::Incoming };
+        self.observe(|observer| observer.rekeyed(direction));
     }
 
     /// Set a new key for the responder-egress symmetric cipher.
     pub fn rekey_responder_manually(&mut self, key: &[u8]) {
-        self.cipherstates.rekey_responder_manually(key)
+        self.core.cipherstates.rekey_responder_manually(key);
+        let direction = if self.is_initiator() { Direction::Incoming } else { Direction::Outgoing };
+        self.observe(|observer| observer.rekeyed(direction));
     }
 
     /// Sets the *receiving* CipherState's nonce. Useful for using noise on lossy transports.
     pub fn set_receiving_nonce(&mut self, nonce: u64) {
-        if self.initiator {
-            self.cipherstates.1.set_nonce(nonce);
-        } else {
-            self.cipherstates.0.set_nonce(nonce);
-        }
+        self.core.receiver().set_nonce(nonce);
+    }
+
+    /// Sets the *sending* CipherState's nonce.
+    ///
+    /// Reusing a nonce under the same key breaks the confidentiality of everything it encrypts,
+    /// so this should only be used for testing. The "risky-set-nonce" feature has to be enabled
+    /// to use this function.
+    #[cfg(feature = "risky-set-nonce")]
+    pub fn dangerously_set_sending_nonce(&mut self, nonce: u64) {
+        self.core.sender().set_nonce(nonce);
     }
 
     /// Get the forthcoming inbound nonce value.
@@ -145,11 +553,7 @@ impl TransportState {
     ///
     /// Will result in `Error::State` if not in transport mode.
     pub fn receiving_nonce(&self) -> u64 {
-        if self.initiator {
-            self.cipherstates.1.nonce()
-        } else {
-            self.cipherstates.0.nonce()
-        }
+        self.core.receiving_nonce()
     }
 
     /// Get the forthcoming outbound nonce value.
@@ -158,22 +562,284 @@ impl TransportState {
     ///
     /// Will result in `Error::State` if not in transport mode.
     pub fn sending_nonce(&self) -> u64 {
-        if self.initiator {
-            self.cipherstates.0.nonce()
-        } else {
-            self.cipherstates.1.nonce()
-        }
+        self.core.sending_nonce()
     }
 
     /// Check if this session was started with the "initiator" role.
     pub fn is_initiator(&self) -> bool {
-        self.initiator
+        self.core.is_initiator()
+    }
+
+    /// Derive a sub-session for `label`: a fresh pair of transport keys, independent of this
+    /// session's and of every other label's, for running parallel channels off one handshake.
+    ///
+    /// Both peers get the same keys for the same label, so each label should only be derived
+    /// once per session: a second sub-session for it would start over at nonce 0. The keys are
+    /// `HKDF(root, label)`, where `root` is derived from the final chaining key and handshake
+    /// hash when the handshake finishes, so rekeying this session doesn't change them.
+    ///
+    /// `resolver` provides the hash and cipher, which are the protocol's.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::MissingKeyMaterial)` for sessions picked up
+    /// with [`Builder::import_session()`](crate::Builder::import_session), as exports don't
+    /// carry what sub-sessions are derived from, and `Error::Init` if `resolver` doesn't support
+    /// the protocol's hash or cipher.
+    pub fn derive_subsession(
+        &self,
+        label: &[u8],
+        resolver: BoxedCryptoResolver,
+    ) -> Result<TransportStateCore<Box<dyn Cipher>>, Error> {
+        let root = self.subsession_root.as_ref().ok_or(StateProblem::MissingKeyMaterial)?;
+        let mut hash = resolver.resolve_hash(&self.params.hash).ok_or(InitStage::GetHashImpl)?;
+        let mut initiator = CipherState::new(
+            resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?,
+        );
+        let mut responder = CipherState::new(
+            resolver.resolve_cipher(&self.params.cipher).ok_or(InitStage::GetCipherImpl)?,
+        );
+
+        let mut keys = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        hash.hkdf(root, label, 2, &mut keys.0, &mut keys.1, &mut []);
+        initiator.set(&keys.0[..CIPHERKEYLEN], 0);
+        responder.set(&keys.1[..CIPHERKEYLEN], 0);
+
+        let cipherstates = CipherStates::new(initiator, responder)?;
+        Ok(TransportStateCore::new(cipherstates, self.core.initiator, self.core.oneway))
+    }
+
+    /// Start a fresh handshake with `builder`, in this session's role, that runs encrypted
+    /// inside this session and replaces it once complete. The peer has to start one at the same
+    /// point in the message stream.
+    ///
+    /// The new handshake's prologue starts with this session's handshake hash, ahead of any
+    /// prologue set on `builder`, so it only completes with the peer of this session.
+    ///
+    /// See the [`rehandshake`](crate::rehandshake) module for an overview.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Builder::build_initiator()`] or [`Builder::build_responder()`], handing
+    /// back this session along with the error. Sessions picked up with
+    /// [`Builder::import_session()`](crate::Builder::import_session) fail with
+    /// `Error::State(StateProblem::MissingKeyMaterial)`, as exports don't carry the handshake
+    /// hash.
+    #[allow(clippy::result_large_err)]
+    pub fn begin_rehandshake(
+        self,
+        builder: Builder<'_>,
+    ) -> Result<Rehandshake, (TransportState, Error)> {
+        let handshake_hash = match &self.handshake_hash {
+            Some(hash) => hash,
+            None => return Err((self, StateProblem::MissingKeyMaterial.into())),
+        };
+        let prologue =
+            [REHANDSHAKE_LABEL, handshake_hash, builder.get_prologue().unwrap_or(&[])].concat();
+        let builder = builder.prologue(&prologue);
+        let handshake =
+            if self.is_initiator() { builder.build_initiator() } else { builder.build_responder() };
+        match handshake {
+            Ok(handshake) => Ok(Rehandshake::new(self, handshake)),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    /// Reserve the next `n` sending nonces ahead of time, for sessions that are persisted
+    /// with [`export_session()`](Self::export_session).
+    ///
+    /// Returns the checkpoint: the sending nonce just past the reserved block. From then on,
+    /// an export records the checkpoint rather than the current sending nonce, so a session
+    /// restored from it skips forward past every nonce that may have been used since, even
+    /// if later exports never made it to disk. Once the block is used up, sending fails until
+    /// more nonces are reserved (and the new export is persisted).
+    ///
+    /// Reserving again replaces the previous block, starting from the current sending nonce.
+    ///
+    /// A restored sender leaves a gap in the nonces its peer sees, so this only suits peers
+    /// that can follow it: the receiving side of a
+    /// [`DatagramTransport`](crate::datagram::DatagramTransport), or a framing that carries
+    /// the nonce.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::NonceExhausted)` if there aren't `n`
+    /// nonces left.
+    pub fn reserve_nonces(&mut self, n: u64) -> Result<u64, Error> {
+        // u64::MAX is reserved for rekeying, so it's never sent with.
+        match self.sending_nonce().checked_add(n) {
+            Some(end) if end < u64::MAX => {
+                self.reserved_until = Some(end);
+                Ok(end)
+            },
+            _ => bail!(StateProblem::NonceExhausted),
+        }
+    }
+
+    /// How many reserved nonces are left to send with, or `None` if none were reserved.
+    pub fn reserved_nonces_left(&self) -> Option<u64> {
+        self.reserved_until.map(|end| end.saturating_sub(self.sending_nonce()))
+    }
+
+    /// Export the session's state, so it can be persisted and picked up again later with
+    /// [`Builder::import_session()`](crate::Builder::import_session).
+    ///
+    /// The export holds the protocol name, the role, the remote static key, and both transport
+    /// keys with their nonces. The padding policy, observer and RNG aren't included; they come
+    /// from the `Builder` the session is imported with.
+    ///
+    /// If nonces were reserved with [`reserve_nonces()`](Self::reserve_nonces), the sending
+    /// nonce is recorded as the end of the reserved block.
+    ///
+    /// The transport keys are in the clear, so treat the export like a private key. To store
+    /// it at rest, prefer [`Builder::seal_session()`](crate::Builder::seal_session).
+    ///
+    /// Once exported, stop using this `TransportState`: picking the session up again from the
+    /// export would reuse the nonces it goes on to send with. The exception is sending within
+    /// a block of reserved nonces, which is what reserving them is for.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::State(StateProblem::UnexportableSession)` for Disco sessions,
+    /// whose duplex state can't be exported, and for sessions that have rekeyed with a cipher
+    /// that doesn't hand back its new key (see [`Cipher::rekey_returning_key()`]).
+    pub fn export_session(&self) -> Result<Vec<u8>, Error> {
+        let cipherstates = [&self.core.cipherstates.0, &self.core.cipherstates.1];
+        if !cipherstates.iter().all(|cipherstate| cipherstate.is_exportable()) {
+            bail!(StateProblem::UnexportableSession);
+        }
+
+        let rs = self.get_remote_static().unwrap_or(&[]);
+        let name = self.params.name.as_bytes();
+        let mut out = Vec::with_capacity(EXPORT_HEADER_LEN + name.len() + rs.len());
+        out.push(EXPORT_VERSION);
+        out.push(name.len() as u8);
+        out.extend_from_slice(name);
+        out.push(self.is_initiator() as u8);
+        out.push(rs.len() as u8);
+        out.extend_from_slice(rs);
+        for (i, cipherstate) in cipherstates.iter().enumerate() {
+            let sending = (i == 0) == self.is_initiator();
+            let key = cipherstate.key();
+            let nonce = match self.reserved_until {
+                Some(end) if sending => end,
+                _ => cipherstate.nonce(),
+            };
+            out.push(key.is_some() as u8);
+            out.extend_from_slice(key.unwrap_or(&[0u8; CIPHERKEYLEN]));
+            out.extend_from_slice(&nonce.to_be_bytes());
+        }
+        Ok(out)
+    }
+
+    /// Rebuild a session from [`export_session()`](Self::export_session)'s output.
+    pub(crate) fn import(
+        params: NoiseParams,
+        ciphers: (Box<dyn Cipher>, Box<dyn Cipher>),
+        rng: Box<dyn Random>,
+        padding: Option<PaddingPolicy>,
+        observer: Option<Arc<dyn SessionObserver>>,
+        max_message_len: Option<usize>,
+        exported: &[u8],
+    ) -> Result<Self, Error> {
+        let mut reader = exported;
+        if take(&mut reader, 1)? != [EXPORT_VERSION] {
+            bail!(Error::Input);
+        }
+        let name_len = take(&mut reader, 1)?[0] as usize;
+        if take(&mut reader, name_len)? != params.name.as_bytes() {
+            bail!(Error::Input);
+        }
+        let initiator = take_flag(&mut reader)?;
+        let rs_len = take(&mut reader, 1)?[0] as usize;
+        if rs_len > MAXSTATICLEN {
+            bail!(Error::Input);
+        }
+        let mut rs_buf = [0u8; MAXSTATICLEN];
+        rs_buf[..rs_len].copy_from_slice(take(&mut reader, rs_len)?);
+        let rs = if rs_len > 0 { Toggle::on(rs_buf) } else { Toggle::off(rs_buf) };
+
+        let mut cipherstates = [CipherState::new(ciphers.0), CipherState::new(ciphers.1)];
+        for cipherstate in &mut cipherstates {
+            let has_key = take_flag(&mut reader)?;
+            let key = take(&mut reader, CIPHERKEYLEN)?;
+            let mut nonce = [0u8; 8];
+            nonce.copy_from_slice(take(&mut reader, 8)?);
+            if has_key {
+                cipherstate.set(key, u64::from_be_bytes(nonce));
+            }
+        }
+        if !reader.is_empty() {
+            bail!(Error::Input);
+        }
+
+        let [initiator_cipherstate, responder_cipherstate] = cipherstates;
+        let cipherstates = CipherStates::new(initiator_cipherstate, responder_cipherstate)?;
+        let oneway = params.handshake.pattern.is_oneway();
+        Ok(TransportState {
+            core: TransportStateCore::new(cipherstates, initiator, oneway),
+            params,
+            s_len: 0,
+            s: Toggle::off([0u8; MAXSTATICLEN]),
+            rs_len,
+            rs,
+            rng,
+            padding,
+            observer,
+            traffic: Traffic::default(),
+            max_message_len,
+            reserved_until: None,
+            subsession_root: None,
+            handshake_hash: None,
+        })
+    }
+
+    /// Report an event to the observer, if there is one.
+    fn observe(&self, event: impl FnOnce(&dyn SessionObserver)) {
+        if let Some(observer) = &self.observer {
+            event(&**observer);
+        }
     }
 }
 
+/// Version byte leading an exported session.
+const EXPORT_VERSION: u8 = 1;
+
+/// Length of an exported session, less the protocol name and remote static key.
+const EXPORT_HEADER_LEN: usize = 4 + 2 * (1 + CIPHERKEYLEN + 8);
+
+/// Split the next `len` bytes off `reader`.
+fn take<'a>(reader: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if reader.len() < len {
+        bail!(Error::Input);
+    }
+    let (head, tail) = reader.split_at(len);
+    *reader = tail;
+    Ok(head)
+}
+
+fn take_flag(reader: &mut &[u8]) -> Result<bool, Error> {
+    match take(reader, 1)? {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => bail!(Error::Input),
+    }
+}
+
+/// Shows the session's shape and nonces, but none of the key material.
 impl fmt::Debug for TransportState {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("TransportState").finish()
+        fmt.debug_struct("TransportState")
+            .field("pattern", &self.params.handshake.pattern)
+            .field("initiator", &self.is_initiator())
+            .field("cipher", &self.core.cipherstates.0.name())
+            .field("sending_nonce", &self.sending_nonce())
+            .field("receiving_nonce", &self.receiving_nonce())
+            .field("rs", &self.rs.is_on())
+            .field("padding", &self.padding)
+            .field("observer", &self.observer.is_some())
+            .field("reserved_until", &self.reserved_until)
+            .finish_non_exhaustive()
     }
 }
 
@@ -184,3 +850,209 @@ impl TryFrom<HandshakeState> for TransportState {
         TransportState::new(old)
     }
 }
+
+/// The transport phase of a session, generic over its cipher implementation.
+///
+/// Made with [`HandshakeStateCore::into_transport_core()`](crate::HandshakeStateCore::into_transport_core)
+/// or [`FixedHandshakeState::into_transport()`](crate::fixed::FixedHandshakeState::into_transport).
+/// It only encrypts and decrypts: padding, observers and the other extras of
+/// [`TransportState`] need the boxed types.
+pub struct TransportStateCore<C> {
+    cipherstates: CipherStates<C>,
+    initiator:    bool,
+    oneway:       bool,
+}
+
+impl<C: Cipher> TransportStateCore<C> {
+    pub(crate) fn new(cipherstates: CipherStates<C>, initiator: bool, oneway: bool) -> Self {
+        Self { cipherstates, initiator, oneway }
+    }
+
+    /// Encrypt `payload` into `message`, returning the length of the message.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if the message would be longer than 65535 bytes,
+    /// `Error::BufferTooSmall` if it doesn't fit in `message`,
+    /// `Error::State(StateProblem::NonceExhausted)` once every nonce has been used, and
+    /// `Error::State(StateProblem::OneWay)` for the responder of a one-way pattern.
+    pub fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, Error> {
+        self.write_message_with_ad(&[], payload, message)
+    }
+
+    fn write_message_with_ad(
+        &mut self,
+        ad: &[u8],
+        payload: &[u8],
+        message: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.check_sendable()?;
+        let needed = payload.len() + TAGLEN;
+        if needed > MAXMSGLEN {
+            bail!(Error::Input);
+        } else if needed > message.len() {
+            bail!(Error::BufferTooSmall { needed, got: message.len() });
+        }
+        self.sender().encrypt_ad(ad, payload, message)
+    }
+
+    /// Encrypt the first `plaintext_len` bytes of `message` in place, appending the tag.
+    fn encrypt_in_place(
+        &mut self,
+        message: &mut [u8],
+        plaintext_len: usize,
+    ) -> Result<usize, Error> {
+        self.check_sendable()?;
+        let needed = plaintext_len + TAGLEN;
+        if needed > MAXMSGLEN {
+            bail!(Error::Input);
+        } else if needed > message.len() {
+            bail!(Error::BufferTooSmall { needed, got: message.len() });
+        }
+        self.sender().encrypt_in_place(message, plaintext_len)
+    }
+
+    /// Decrypt `message` into `payload`, returning the length of the payload.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::TruncatedMessage` if `message` is shorter than a tag,
+    /// `Error::BufferTooSmall` if `payload` can't hold the plaintext, `Error::Decrypt` if the
+    /// message didn't authenticate, and `Error::State(StateProblem::OneWay)` for the initiator
+    /// of a one-way pattern.
+    pub fn read_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<usize, Error> {
+        self.read_message_with_ad(&[], message, payload)
+    }
+
+    fn read_message_with_ad(
+        &mut self,
+        ad: &[u8],
+        message: &[u8],
+        payload: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.check_receivable()?;
+        if message.len() < TAGLEN {
+            bail!(Error::TruncatedMessage { needed: TAGLEN, got: message.len() });
+        } else if payload.len() < message.len() - TAGLEN {
+            bail!(Error::BufferTooSmall { needed: message.len() - TAGLEN, got: payload.len() });
+        }
+        self.receiver().decrypt_ad(ad, message, payload).map_err(|_| Error::Decrypt)
+    }
+
+    /// Decrypt `message` in place, returning the length of the plaintext at its front.
+    fn decrypt_in_place(&mut self, message: &mut [u8]) -> Result<usize, Error> {
+        self.check_receivable()?;
+        if message.len() < TAGLEN {
+            bail!(Error::TruncatedMessage { needed: TAGLEN, got: message.len() });
+        }
+        let len = message.len();
+        self.receiver().decrypt_in_place(&[], message, len).map_err(|_| Error::Decrypt)
+    }
+
+    /// Check that this side may send, and has a nonce left to send with.
+    fn check_sendable(&self) -> Result<(), Error> {
+        if !self.initiator && self.oneway {
+            bail!(StateProblem::OneWay);
+        }
+        if self.sending_nonce() == u64::MAX {
+            bail!(StateProblem::NonceExhausted);
+        }
+        Ok(())
+    }
+
+    /// Check that this side may receive.
+    fn check_receivable(&self) -> Result<(), Error> {
+        if self.initiator && self.oneway {
+            bail!(StateProblem::OneWay);
+        }
+        Ok(())
+    }
+
+    fn sender(&mut self) -> &mut CipherState<C> {
+        if self.initiator {
+            &mut self.cipherstates.0
+        } else {
+            &mut self.cipherstates.1
+        }
+    }
+
+    fn receiver(&mut self) -> &mut CipherState<C> {
+        if self.initiator {
+            &mut self.cipherstates.1
+        } else {
+            &mut self.cipherstates.0
+        }
+    }
+
+    /// [`write_message()`](Self::write_message) into a [`FixedMessage`] of capacity `N`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `write_message()`.
+    pub fn write_fixed<const N: usize>(
+        &mut self,
+        payload: &[u8],
+    ) -> Result<FixedMessage<N>, Error> {
+        FixedMessage::write_with(|message| self.write_message(payload, message))
+    }
+
+    /// [`read_message()`](Self::read_message) into a [`FixedMessage`] of capacity `N`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `read_message()`.
+    pub fn read_fixed<const N: usize>(&mut self, message: &[u8]) -> Result<FixedMessage<N>, Error> {
+        FixedMessage::write_with(|payload| self.read_message(message, payload))
+    }
+
+    /// Rekey the outgoing cipher according to Section 4.2 of the Noise Specification.
+    pub fn rekey_outgoing(&mut self) {
+        if self.initiator {
+            self.cipherstates.rekey_initiator()
+        } else {
+            self.cipherstates.rekey_responder()
+        }
+    }
+
+    /// Rekey the incoming cipher according to Section 4.2 of the Noise Specification.
+    pub fn rekey_incoming(&mut self) {
+        if self.initiator {
+            self.cipherstates.rekey_responder()
+        } else {
+            self.cipherstates.rekey_initiator()
+        }
+    }
+
+    /// The nonce the next outgoing message will use.
+    pub fn sending_nonce(&self) -> u64 {
+        if self.initiator {
+            self.cipherstates.0.nonce()
+        } else {
+            self.cipherstates.1.nonce()
+        }
+    }
+
+    /// The nonce the next incoming message is expected to use.
+    pub fn receiving_nonce(&self) -> u64 {
+        if self.initiator {
+            self.cipherstates.1.nonce()
+        } else {
+            self.cipherstates.0.nonce()
+        }
+    }
+
+    /// Check if this session was started with the "initiator" role.
+    pub fn is_initiator(&self) -> bool {
+        self.initiator
+    }
+}
+
+impl<C: Cipher> fmt::Debug for TransportStateCore<C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("TransportStateCore")
+            .field("initiator", &self.initiator)
+            .field("sending_nonce", &self.sending_nonce())
+            .field("receiving_nonce", &self.receiving_nonce())
+            .finish_non_exhaustive()
+    }
+}
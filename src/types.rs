@@ -2,6 +2,7 @@
 
 use crate::constants::{CIPHERKEYLEN, MAXBLOCKLEN, MAXHASHLEN, TAGLEN};
 use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
 
 /// CSPRNG operations
 pub trait Random: CryptoRng + RngCore + Send + Sync {}
@@ -17,11 +18,27 @@ pub trait Dh: Send + Sync {
     /// The length in bytes of a private key for this primitive
     fn priv_len(&self) -> usize;
 
+    /// The length in bytes of a Diffie-Hellman output, which is the length of a public key
+    /// unless the primitive encodes them differently (like secp256k1's compressed points).
+    fn shared_len(&self) -> usize {
+        self.pub_len()
+    }
+
     /// Set the private key
     fn set(&mut self, privkey: &[u8]);
 
-    /// Generate a new private key
-    fn generate(&mut self, rng: &mut dyn Random);
+    /// Set the private key along with its public key, which the caller has already derived
+    /// (e.g. when the keypair was generated ahead of time), so it needn't be derived again.
+    ///
+    /// The default implementation ignores `pubkey` and calls [`set()`](Self::set).
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        let _ = pubkey;
+        self.set(privkey);
+    }
+
+    /// Generate a new private key, failing if `rng` can't provide enough entropy
+    #[allow(clippy::result_unit_err)]
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()>;
 
     /// Get the public key
     fn pubkey(&self) -> &[u8];
@@ -29,10 +46,47 @@ pub trait Dh: Send + Sync {
     /// Get the private key
     fn privkey(&self) -> &[u8];
 
-    /// Calculate a Diffie-Hellman exchange.
+    /// Calculate a Diffie-Hellman exchange, writing [`shared_len()`](Self::shared_len) bytes.
+    #[allow(clippy::result_unit_err)]
     fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()>;
 }
 
+/// Signature operations, used in place of DH for static keys by the `sig` modifier.
+pub trait Sign: Send + Sync {
+    /// The string that the Noise spec defines for the primitive
+    fn name(&self) -> &'static str;
+
+    /// The length in bytes of a public key for this primitive
+    fn pub_len(&self) -> usize;
+
+    /// The length in bytes of a private key for this primitive
+    fn priv_len(&self) -> usize;
+
+    /// The length in bytes of a signature for this primitive
+    fn sig_len(&self) -> usize;
+
+    /// Set the private key
+    fn set(&mut self, privkey: &[u8]);
+
+    /// Generate a new private key, failing if `rng` can't provide enough entropy
+    #[allow(clippy::result_unit_err)]
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()>;
+
+    /// Get the public key
+    fn pubkey(&self) -> &[u8];
+
+    /// Get the private key
+    fn privkey(&self) -> &[u8];
+
+    /// Sign `message` with the private key, writing the signature to `out`.
+    ///
+    /// Returns the length of the signature.
+    fn sign(&self, message: &[u8], out: &mut [u8]) -> usize;
+
+    /// Check that `signature` is a valid signature of `message` under `pubkey`.
+    fn verify(&self, pubkey: &[u8], message: &[u8], signature: &[u8]) -> bool;
+}
+
 /// Cipher operations
 pub trait Cipher: Send + Sync {
     /// The string that the Noise spec defines for the primitive
@@ -44,7 +98,24 @@ pub trait Cipher: Send + Sync {
     /// Encrypt (with associated data) a given plaintext.
     fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize;
 
+    /// Encrypt (with associated data) the first `plaintext_len` bytes of `in_out` in place,
+    /// writing the tag right after them.
+    ///
+    /// The default implementation copies the plaintext out before calling `encrypt()`, so
+    /// primitives that can encrypt in place should override it.
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        let plaintext = in_out[..plaintext_len].to_vec();
+        self.encrypt(nonce, authtext, &plaintext, in_out)
+    }
+
     /// Decrypt (with associated data) a given ciphertext.
+    #[allow(clippy::result_unit_err)]
     fn decrypt(
         &self,
         nonce: u64,
@@ -53,17 +124,53 @@ pub trait Cipher: Send + Sync {
         out: &mut [u8],
     ) -> Result<usize, ()>;
 
+    /// Decrypt (with associated data) the ciphertext and tag making up the first
+    /// `ciphertext_len` bytes of `in_out` in place, leaving the plaintext at the front.
+    ///
+    /// The default implementation copies the ciphertext out before calling `decrypt()`, so
+    /// primitives that can decrypt in place should override it.
+    #[allow(clippy::result_unit_err)]
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        let ciphertext = in_out[..ciphertext_len].to_vec();
+        self.decrypt(nonce, authtext, &ciphertext, in_out)
+    }
+
     /// Rekey according to Section 4.2 of the Noise Specification, with a default
     /// implementation guaranteed to be secure for all ciphers.
     fn rekey(&mut self) {
-        let mut ciphertext = [0; CIPHERKEYLEN + TAGLEN];
-        let ciphertext_len =
-            self.encrypt(u64::max_value(), &[], &[0; CIPHERKEYLEN], &mut ciphertext);
-        assert_eq!(ciphertext_len, ciphertext.len());
-        self.set(&ciphertext[..CIPHERKEYLEN]);
+        default_rekey(self);
+    }
+
+    /// Rekey like [`rekey()`](Self::rekey), returning the new key so that sessions can still be
+    /// exported afterwards, or `None` if the key can't be handed out.
+    ///
+    /// The default calls `rekey()` and returns `None`, since it can't know how an overridden
+    /// `rekey()` derives its key. Ciphers that keep the default `rekey()` can return
+    /// `Some(default_rekey(self))`.
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        self.rekey();
+        None
     }
 }
 
+/// The default [`Cipher::rekey()`]: `REKEY()` from Section 4.2 of the Noise Specification,
+/// returning the new key as well as setting it.
+pub fn default_rekey<C: Cipher + ?Sized>(cipher: &mut C) -> [u8; CIPHERKEYLEN] {
+    let mut ciphertext = Zeroizing::new([0; CIPHERKEYLEN + TAGLEN]);
+    let ciphertext_len = cipher.encrypt(u64::MAX, &[], &[0; CIPHERKEYLEN], &mut *ciphertext);
+    assert_eq!(ciphertext_len, ciphertext.len());
+    let mut key = [0; CIPHERKEYLEN];
+    key.copy_from_slice(&ciphertext[..CIPHERKEYLEN]);
+    cipher.set(&key);
+    key
+}
+
 /// Hashing operations
 pub trait Hash: Send + Sync {
     /// The string that the Noise spec defines for the primitive
@@ -88,26 +195,39 @@ pub trait Hash: Send + Sync {
     ///
     /// NOTE: This method clobbers the existing internal state
     fn hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
-        assert!(key.len() <= self.block_len());
         let block_len = self.block_len();
         let hash_len = self.hash_len();
-        let mut ipad = [0x36u8; MAXBLOCKLEN];
-        let mut opad = [0x5cu8; MAXBLOCKLEN];
-        for count in 0..key.len() {
-            ipad[count] ^= key[count];
-            opad[count] ^= key[count];
-        }
+        let pads = HmacPads::new(key, block_len);
         self.reset();
-        self.input(&ipad[..block_len]);
+        self.input(pads.inner(block_len));
         self.input(data);
         let mut inner_output = [0u8; MAXHASHLEN];
         self.result(&mut inner_output);
         self.reset();
-        self.input(&opad[..block_len]);
+        self.input(pads.outer(block_len));
         self.input(&inner_output[..hash_len]);
         self.result(out);
     }
 
+    /// Hash HMAC's two padded key blocks for `key` once and keep the resulting states, so that
+    /// [`hmac_precomputed()`](Hash::hmac_precomputed) can start from them. `hkdf()` does this
+    /// for its expansion steps, which all use the same key.
+    ///
+    /// Returns `false` if the primitive can't keep a copy of its state, which is the default.
+    fn precompute_hmac(&mut self, _key: &[u8]) -> bool {
+        false
+    }
+
+    /// Calculate HMAC under `key`, starting from the states the last `precompute_hmac()` kept
+    /// for it. The default, for primitives that don't keep any, is [`hmac()`](Hash::hmac).
+    fn hmac_precomputed(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
+        self.hmac(key, data, out)
+    }
+
+    /// Drop the states kept by `precompute_hmac()`, which `hkdf()` does once it's done with
+    /// them.
+    fn clear_precomputed_hmac(&mut self) {}
+
     /// Derive keys as specified in the Noise spec.
     ///
     /// NOTE: This method clobbers the existing internal state
@@ -123,23 +243,58 @@ pub trait Hash: Send + Sync {
         let hash_len = self.hash_len();
         let mut temp_key = [0u8; MAXHASHLEN];
         self.hmac(chaining_key, input_key_material, &mut temp_key);
-        self.hmac(&temp_key, &[1u8], out1);
-        if outputs == 1 {
-            return;
+        let temp_key = &temp_key[..hash_len];
+        let precomputed = outputs > 1 && self.precompute_hmac(temp_key);
+        let expand = |hash: &mut Self, data: &[u8], out: &mut [u8]| {
+            if precomputed {
+                hash.hmac_precomputed(temp_key, data, out);
+            } else {
+                hash.hmac(temp_key, data, out);
+            }
+        };
+
+        expand(self, &[1u8], out1);
+        if outputs > 1 {
+            let mut in2 = [0u8; MAXHASHLEN + 1];
+            copy_slices!(&out1[0..hash_len], &mut in2);
+            in2[hash_len] = 2;
+            expand(self, &in2[..=hash_len], out2);
         }
+        if outputs > 2 {
+            let mut in3 = [0u8; MAXHASHLEN + 1];
+            copy_slices!(&out2[0..hash_len], &mut in3);
+            in3[hash_len] = 3;
+            expand(self, &in3[..=hash_len], out3);
+        }
+        if precomputed {
+            self.clear_precomputed_hmac();
+        }
+    }
+}
 
-        let mut in2 = [0u8; MAXHASHLEN + 1];
-        copy_slices!(&out1[0..hash_len], &mut in2);
-        in2[hash_len] = 2;
-        self.hmac(&temp_key, &in2[..=hash_len], out2);
-        if outputs == 2 {
-            return;
+/// HMAC's inner and outer key blocks, i.e. the key XORed with the ipad and opad bytes.
+pub(crate) struct HmacPads {
+    ipad: [u8; MAXBLOCKLEN],
+    opad: [u8; MAXBLOCKLEN],
+}
+
+impl HmacPads {
+    pub(crate) fn new(key: &[u8], block_len: usize) -> Self {
+        assert!(key.len() <= block_len);
+        let mut pads = HmacPads { ipad: [0x36u8; MAXBLOCKLEN], opad: [0x5cu8; MAXBLOCKLEN] };
+        for (count, byte) in key.iter().enumerate() {
+            pads.ipad[count] ^= byte;
+            pads.opad[count] ^= byte;
         }
+        pads
+    }
+
+    pub(crate) fn inner(&self, block_len: usize) -> &[u8] {
+        &self.ipad[..block_len]
+    }
 
-        let mut in3 = [0u8; MAXHASHLEN + 1];
-        copy_slices!(&out2[0..hash_len], &mut in3);
-        in3[hash_len] = 3;
-        self.hmac(&temp_key, &in3[..=hash_len], out3);
+    pub(crate) fn outer(&self, block_len: usize) -> &[u8] {
+        &self.opad[..block_len]
     }
 }
 
@@ -158,14 +313,15 @@ pub trait Kem: Send + Sync {
     /// Shared secret length in bytes that this Kem encapsulates.
     fn shared_secret_len(&self) -> usize;
 
-    /// Generate a new private key.
-    fn generate(&mut self, rng: &mut dyn Random);
+    /// Generate a new private key, failing if `rng` can't provide enough entropy.
+    #[allow(clippy::result_unit_err)]
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()>;
 
     /// Get the public key
     fn pubkey(&self) -> &[u8];
 
     /// Generate a shared secret and encapsulate it using this Kem.
-    #[must_use]
+    #[allow(clippy::result_unit_err)]
     fn encapsulate(
         &self,
         pubkey: &[u8],
@@ -173,7 +329,172 @@ pub trait Kem: Send + Sync {
         ciphertext_out: &mut [u8],
     ) -> Result<(usize, usize), ()>;
 
+    /// Like [`encapsulate()`](Self::encapsulate), but with the encapsulation's randomness drawn
+    /// from `rng`, which the handshake passes its own RNG to. The default ignores `rng`, for Kems
+    /// that can only draw it themselves.
+    #[allow(clippy::result_unit_err)]
+    fn encapsulate_with_rng(
+        &self,
+        rng: &mut dyn Random,
+        pubkey: &[u8],
+        shared_secret_out: &mut [u8],
+        ciphertext_out: &mut [u8],
+    ) -> Result<(usize, usize), ()> {
+        let _ = rng;
+        self.encapsulate(pubkey, shared_secret_out, ciphertext_out)
+    }
+
     /// Decapsulate a ciphertext producing a shared secret.
-    #[must_use]
+    #[allow(clippy::result_unit_err)]
     fn decapsulate(&self, ciphertext: &[u8], shared_secret_out: &mut [u8]) -> Result<usize, ()>;
 }
+
+/// Boxed primitives, like the ones a resolver hands out, can be used wherever a primitive is a
+/// type parameter, e.g. in [`FixedHandshakeState`](crate::fixed::FixedHandshakeState).
+impl<T: Dh + ?Sized> Dh for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn pub_len(&self) -> usize {
+        (**self).pub_len()
+    }
+
+    fn priv_len(&self) -> usize {
+        (**self).priv_len()
+    }
+
+    fn shared_len(&self) -> usize {
+        (**self).shared_len()
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        (**self).set(privkey)
+    }
+
+    fn set_keypair(&mut self, privkey: &[u8], pubkey: &[u8]) {
+        (**self).set_keypair(privkey, pubkey)
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) -> Result<(), ()> {
+        (**self).generate(rng)
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        (**self).pubkey()
+    }
+
+    fn privkey(&self) -> &[u8] {
+        (**self).privkey()
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        (**self).dh(pubkey, out)
+    }
+}
+
+impl<T: Cipher + ?Sized> Cipher for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        (**self).set(key)
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        (**self).encrypt(nonce, authtext, plaintext, out)
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        plaintext_len: usize,
+    ) -> usize {
+        (**self).encrypt_in_place(nonce, authtext, in_out, plaintext_len)
+    }
+
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        (**self).decrypt(nonce, authtext, ciphertext, out)
+    }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        in_out: &mut [u8],
+        ciphertext_len: usize,
+    ) -> Result<usize, ()> {
+        (**self).decrypt_in_place(nonce, authtext, in_out, ciphertext_len)
+    }
+
+    fn rekey(&mut self) {
+        (**self).rekey()
+    }
+
+    fn rekey_returning_key(&mut self) -> Option<[u8; CIPHERKEYLEN]> {
+        (**self).rekey_returning_key()
+    }
+}
+
+impl<T: Hash + ?Sized> Hash for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn block_len(&self) -> usize {
+        (**self).block_len()
+    }
+
+    fn hash_len(&self) -> usize {
+        (**self).hash_len()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        (**self).input(data)
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        (**self).result(out)
+    }
+
+    fn hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
+        (**self).hmac(key, data, out)
+    }
+
+    fn precompute_hmac(&mut self, key: &[u8]) -> bool {
+        (**self).precompute_hmac(key)
+    }
+
+    fn hmac_precomputed(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
+        (**self).hmac_precomputed(key, data, out)
+    }
+
+    fn clear_precomputed_hmac(&mut self) {
+        (**self).clear_precomputed_hmac()
+    }
+
+    fn hkdf(
+        &mut self,
+        chaining_key: &[u8],
+        input_key_material: &[u8],
+        outputs: usize,
+        out1: &mut [u8],
+        out2: &mut [u8],
+        out3: &mut [u8],
+    ) {
+        (**self).hkdf(chaining_key, input_key_material, outputs, out1, out2, out3)
+    }
+}
@@ -0,0 +1,246 @@
+//! A strongly-typed wrapper around [`HandshakeState`] that tracks whose turn it is in the type.
+//!
+//! A [`Handshake<Writing>`] can only write the next message and a [`Handshake<Reading>`] can only
+//! read it, so the `NotTurnToWrite`, `NotTurnToRead` and `HandshakeAlreadyFinished` errors can't
+//! happen. Each step consumes the handshake and hands back either the handshake for the next
+//! turn or, after the last message, a [`Complete`] handshake that converts into transport mode
+//! without a fallible check.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::{Builder, typestate::Next};
+//! # #[cfg(any(feature = "default-resolver", feature = "ring-accelerated"))]
+//! # fn try_main() -> Result<(), snow::Error> {
+//! let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse()?;
+//! let initiator = Builder::new(params.clone()).build_typed_initiator()?;
+//! let responder = Builder::new(params).build_typed_responder()?;
+//! let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+//!
+//! // -> e
+//! let (len, initiator) = initiator.write_message(&[], &mut msg)?;
+//! let (_, responder) = responder.read_message(&msg[..len], &mut buf)?;
+//!
+//! // <- e, ee
+//! let (len, responder) = responder.expect_continue().write_message(&[], &mut msg)?;
+//! let (_, initiator) = initiator.expect_continue().read_message(&msg[..len], &mut buf)?;
+//!
+//! match (initiator, responder) {
+//!     (Next::Finished(initiator), Next::Finished(responder)) => {
+//!         let _initiator = initiator.into_transport_mode();
+//!         let _responder = responder.into_transport_mode();
+//!     },
+//!     _ => unreachable!("NN is two messages long"),
+//! }
+//! #     Ok(())
+//! # }
+//! # #[cfg(not(any(feature = "default-resolver", feature = "ring-accelerated")))]
+//! # fn try_main() -> Result<(), ()> { Ok(()) }
+//! # try_main().unwrap();
+//! ```
+
+use crate::{Error, HandshakeState, StatelessTransportState, TransportState};
+use std::{fmt, marker::PhantomData};
+
+/// The turn of a [`Handshake`] whose next operation is writing a message.
+#[derive(Debug)]
+pub enum Writing {}
+
+/// The turn of a [`Handshake`] whose next operation is reading a message.
+#[derive(Debug)]
+pub enum Reading {}
+
+/// A handshake in progress, whose turn `T` is either [`Writing`] or [`Reading`].
+pub struct Handshake<T> {
+    state: Box<HandshakeState>,
+    turn:  PhantomData<T>,
+}
+
+/// What follows a handshake message: the handshake for the next turn, or the finished handshake.
+#[derive(Debug)]
+pub enum Next<T> {
+    /// More messages remain, and it's now turn `T`.
+    Continue(Handshake<T>),
+
+    /// That was the last handshake message.
+    Finished(Complete),
+}
+
+/// The state of a handshake at any point, for converting an existing [`HandshakeState`].
+#[derive(Debug)]
+pub enum Phase {
+    /// It's our turn to write.
+    Writing(Handshake<Writing>),
+
+    /// It's our turn to read.
+    Reading(Handshake<Reading>),
+
+    /// The handshake is finished.
+    Finished(Complete),
+}
+
+/// A failed handshake step, which leaves the handshake as it was before the step.
+///
+/// This converts into the underlying [`Error`], so `?` works when retrying isn't wanted.
+#[derive(Debug)]
+pub struct Failure<T> {
+    /// The reason the step failed.
+    pub error: Error,
+
+    /// The unchanged handshake, which can be retried (e.g. with a bigger buffer).
+    pub handshake: Handshake<T>,
+}
+
+/// A handshake that has processed all of its messages.
+#[derive(Debug)]
+pub struct Complete {
+    state: Box<HandshakeState>,
+}
+
+impl<T> Handshake<T> {
+    pub(crate) fn new(state: HandshakeState) -> Self {
+        Handshake { state: Box::new(state), turn: PhantomData }
+    }
+
+    /// The underlying handshake state, e.g. to look at the remote static key or handshake hash.
+    pub fn state(&self) -> &HandshakeState {
+        &self.state
+    }
+
+    /// Set the preshared key at the specified location. See [`HandshakeState::set_psk()`].
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Init(InitStage::ValidatePskPosition)` if the pattern has no
+    /// modifier for `location`, and `Error::Input` if the PSK is not the right length.
+    pub fn set_psk(&mut self, location: usize, key: &[u8]) -> Result<(), Error> {
+        self.state.set_psk(location, key)
+    }
+
+    /// Give up the compile-time turn tracking and get back the underlying handshake state.
+    pub fn into_inner(self) -> HandshakeState {
+        *self.state
+    }
+
+    fn next<U>(state: Box<HandshakeState>) -> Next<U> {
+        if state.is_handshake_finished() {
+            Next::Finished(Complete { state })
+        } else {
+            Next::Continue(Handshake { state, turn: PhantomData })
+        }
+    }
+}
+
+impl Handshake<Writing> {
+    /// Construct and send the next handshake message. See [`HandshakeState::write_message()`].
+    ///
+    /// Returns the size of the written message and what follows it.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the same errors as [`HandshakeState::write_message()`], handing the handshake
+    /// back unchanged.
+    pub fn write_message(
+        mut self,
+        payload: &[u8],
+        message: &mut [u8],
+    ) -> Result<(usize, Next<Reading>), Failure<Writing>> {
+        match self.state.write_message(payload, message) {
+            Ok(len) => Ok((len, Self::next(self.state))),
+            Err(error) => Err(Failure { error, handshake: self }),
+        }
+    }
+}
+
+impl Handshake<Reading> {
+    /// Read and process the next handshake message. See [`HandshakeState::read_message()`].
+    ///
+    /// Returns the size of the payload written to `payload` and what follows the message.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the same errors as [`HandshakeState::read_message()`], handing the handshake
+    /// back unchanged.
+    pub fn read_message(
+        mut self,
+        message: &[u8],
+        payload: &mut [u8],
+    ) -> Result<(usize, Next<Writing>), Failure<Reading>> {
+        match self.state.read_message(message, payload) {
+            Ok(len) => Ok((len, Self::next(self.state))),
+            Err(error) => Err(Failure { error, handshake: self }),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Handshake<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Handshake").field("state", &self.state).finish()
+    }
+}
+
+impl<T> Next<T> {
+    /// Whether that was the last handshake message.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Next::Finished(_))
+    }
+
+    /// The handshake for the next turn.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handshake is finished, which is known in advance for a given pattern.
+    pub fn expect_continue(self) -> Handshake<T> {
+        match self {
+            Next::Continue(handshake) => handshake,
+            Next::Finished(_) => panic!("handshake is already finished"),
+        }
+    }
+
+    /// The finished handshake.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more handshake messages remain, which is known in advance for a given pattern.
+    pub fn expect_finished(self) -> Complete {
+        match self {
+            Next::Finished(complete) => complete,
+            Next::Continue(_) => panic!("handshake is not finished"),
+        }
+    }
+}
+
+impl From<HandshakeState> for Phase {
+    fn from(state: HandshakeState) -> Self {
+        if state.is_handshake_finished() {
+            Phase::Finished(Complete { state: Box::new(state) })
+        } else if state.is_my_turn() {
+            Phase::Writing(Handshake::new(state))
+        } else {
+            Phase::Reading(Handshake::new(state))
+        }
+    }
+}
+
+impl<T> From<Failure<T>> for Error {
+    fn from(failure: Failure<T>) -> Self {
+        failure.error
+    }
+}
+
+impl Complete {
+    /// The underlying handshake state, e.g. to look at the remote static key or handshake hash.
+    pub fn state(&self) -> &HandshakeState {
+        &self.state
+    }
+
+    /// Convert into a `TransportState` with an internally stored nonce.
+    pub fn into_transport_mode(self) -> TransportState {
+        TransportState::new(*self.state).expect("handshake is finished")
+    }
+
+    /// Convert into a `StatelessTransportState` without an internally stored nonce.
+    pub fn into_stateless_transport_mode(self) -> StatelessTransportState {
+        StatelessTransportState::new(*self.state).expect("handshake is finished")
+    }
+}
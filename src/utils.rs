@@ -1,4 +1,12 @@
-use std::ops::{Deref, DerefMut};
+use crate::error::Error;
+#[cfg(not(feature = "forbid-unsafe"))]
+use crate::{constants::TAGLEN, padding};
+#[cfg(not(feature = "forbid-unsafe"))]
+use std::mem::MaybeUninit;
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 /// Toggle is similar to Option, except that even in the Off/"None" case, there is still
 /// an owned allocated inner object. This is useful for holding onto pre-allocated objects
@@ -21,6 +29,10 @@ impl<T> Toggle<T> {
         self.on = true;
     }
 
+    pub fn disable(&mut self) {
+        self.on = false;
+    }
+
     pub fn is_on(&self) -> bool {
         self.on
     }
@@ -47,3 +59,206 @@ impl<T> DerefMut for Toggle<T> {
         &mut self.inner
     }
 }
+
+/// Reject a `len`-byte message if it's longer than the receiver's `max_message_len`, which is
+/// checked before doing any work on the message.
+pub(crate) fn check_message_len(max_message_len: Option<usize>, len: usize) -> Result<(), Error> {
+    match max_message_len {
+        Some(max) if len > max => bail!(Error::MessageTooLarge { max, got: len }),
+        _ => Ok(()),
+    }
+}
+
+/// Counts the transport messages sent and received, and their bytes on the wire.
+///
+/// The counters are atomic so that `StatelessTransportState` can update them through `&self`.
+#[derive(Default)]
+pub(crate) struct Traffic {
+    bytes_sent:        AtomicU64,
+    bytes_received:    AtomicU64,
+    messages_sent:     AtomicU64,
+    messages_received: AtomicU64,
+}
+
+impl Traffic {
+    pub fn sent(&self, len: usize) {
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, len: usize) {
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received.load(Ordering::Relaxed)
+    }
+}
+
+/// Zero up to the first `len` bytes of `buf`, returning them as initialized bytes.
+///
+/// Only that prefix is touched, so the rest of a large buffer never has to be initialized.
+#[cfg(not(feature = "forbid-unsafe"))]
+pub(crate) fn init_prefix(buf: &mut [MaybeUninit<u8>], len: usize) -> &mut [u8] {
+    let len = len.min(buf.len());
+    let prefix = &mut buf[..len];
+    prefix.fill(MaybeUninit::new(0));
+    // SAFETY: every byte of `prefix` was just initialized.
+    unsafe { assume_init(prefix) }
+}
+
+/// Gather `payload` into a `plaintext_len`-byte plaintext at the front of `buf` with
+/// [`padding::gather_uninit()`], followed by zeroes where the tag goes, returning that much of
+/// `buf` as initialized bytes for the cipher to encrypt in place.
+///
+/// The plaintext is written straight into `buf`, so only the tag's bytes are zeroed first.
+///
+/// # Errors
+///
+/// Will result in `Error::BufferTooSmall` if `buf` can't hold the plaintext and tag.
+#[cfg(not(feature = "forbid-unsafe"))]
+pub(crate) fn init_message<'b>(
+    buf: &'b mut [MaybeUninit<u8>],
+    payload: &[u8],
+    plaintext_len: usize,
+    framed: bool,
+) -> Result<&'b mut [u8], Error> {
+    let needed = plaintext_len + TAGLEN;
+    if needed > buf.len() {
+        bail!(Error::BufferTooSmall { needed, got: buf.len() });
+    }
+    let message = &mut buf[..needed];
+    let (plaintext, tag) = message.split_at_mut(plaintext_len);
+    padding::gather_uninit(payload, plaintext, framed);
+    // The cipher writes the tag through a `&mut [u8]`, so it can't be left uninitialized.
+    tag.fill(MaybeUninit::new(0));
+    // SAFETY: gather_uninit() wrote every byte of the plaintext, and the tag was just zeroed.
+    Ok(unsafe { assume_init(message) })
+}
+
+/// # Safety
+///
+/// Every byte of `buf` has to be initialized.
+#[cfg(not(feature = "forbid-unsafe"))]
+unsafe fn assume_init(buf: &mut [MaybeUninit<u8>]) -> &mut [u8] {
+    // SAFETY: the caller guarantees `buf` is initialized, and `MaybeUninit<u8>` is guaranteed to
+    // have the same layout as `u8`.
+    &mut *(buf as *mut [MaybeUninit<u8>] as *mut [u8])
+}
+
+/// Encode `bytes` as lowercase hex.
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode hex in either case, returning `None` for anything else.
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `bytes` as standard (RFC 4648) base64, with `=` padding if `pad` is set.
+pub(crate) fn encode_base64(bytes: &[u8], pad: bool) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let n = u32::from(group[0]) << 16 | u32::from(group[1]) << 8 | u32::from(group[2]);
+        let chars = chunk.len() + 1;
+        for i in 0..4 {
+            if i < chars {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else if pad {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode standard (RFC 4648) base64, with or without `=` padding.
+///
+/// Returns `None` for anything else, including encodings with nonzero leftover bits, so each
+/// value has exactly one accepted (unpadded or padded) encoding.
+pub(crate) fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let unpadded = encoded.trim_end_matches('=');
+    let padded = encoded.len() != unpadded.len();
+    if (padded && encoded.len() & 3 != 0) || unpadded.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(unpadded.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in unpadded.bytes() {
+        let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+        acc = acc << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    if acc != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(encode_hex(&[0x00, 0xab, 0xff]), "00abff");
+        assert_eq!(decode_hex("00ABff"), Some(vec![0x00, 0xab, 0xff]));
+        for hex in &["0", "0g", "+1", "\u{e9}0"] {
+            assert_eq!(decode_hex(hex), None, "{}", hex);
+        }
+    }
+
+    #[test]
+    fn test_base64_rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (plain, encoded) in &vectors {
+            assert_eq!(encode_base64(plain.as_bytes(), true), *encoded);
+            let unpadded = encoded.trim_end_matches('=');
+            assert_eq!(encode_base64(plain.as_bytes(), false), unpadded);
+            assert_eq!(decode_base64(encoded).unwrap(), plain.as_bytes());
+            assert_eq!(decode_base64(unpadded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_base64_rejects_malformed() {
+        for encoded in &["Z", "Zg=", "Zh==", "Zm9v!", "Zm8==", "Z==="] {
+            assert_eq!(decode_base64(encoded), None, "{}", encoded);
+        }
+    }
+}
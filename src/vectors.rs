@@ -0,0 +1,380 @@
+//! Loading, running and generating Noise test vectors.
+//!
+//! The format is the JSON one shared by [cacophony](https://github.com/centromere/cacophony)
+//! and [noise-c](https://github.com/rweather/noise-c), so vectors can be exchanged with other
+//! implementations in both directions.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::vectors::{Vector, Vectors};
+//! let vector = Vector::generate("Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap()).unwrap();
+//! let json = Vectors { vectors: vec![vector] }.to_json();
+//!
+//! for vector in Vectors::from_json(&json).unwrap().vectors {
+//!     vector.run().unwrap();
+//! }
+//! ```
+
+use crate::{
+    error::{Error, Prerequisite},
+    params::{HandshakeModifier, HandshakeTokens, NoiseParams},
+    Builder, HandshakeState,
+};
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{convert::TryFrom, fmt, ops::Deref};
+
+/// The prologue used for generated vectors.
+const PROLOGUE: &[u8] = b"There is no right and wrong. There's only fun and boring.";
+
+/// The payload length used for generated vectors.
+const PAYLOAD_LEN: usize = 32;
+
+/// Bytes that are hex-encoded in the JSON format.
+#[derive(Clone, PartialEq, Default)]
+pub struct HexBytes(pub Vec<u8>);
+
+impl From<Vec<u8>> for HexBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        HexBytes(bytes)
+    }
+}
+
+impl Deref for HexBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for HexBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", hex::encode(&self.0))
+    }
+}
+
+impl Serialize for HexBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for HexBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(&s).map(HexBytes).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A single message of a vector, either a handshake or a transport message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Message {
+    /// The plaintext payload.
+    pub payload:    HexBytes,
+    /// The message on the wire.
+    pub ciphertext: HexBytes,
+}
+
+/// A single test vector: the inputs to both sides of a session and the messages they exchange.
+///
+/// The messages alternate between initiator and responder, except for the transport messages
+/// of one-way patterns, which are all sent by the initiator.
+#[allow(missing_docs)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vector {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    pub protocol_name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hybrid: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_pattern: Option<String>,
+
+    pub init_prologue: HexBytes,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_psks: Option<Vec<HexBytes>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_static: Option<HexBytes>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_ephemeral: Option<HexBytes>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_remote_static: Option<HexBytes>,
+
+    pub resp_prologue: HexBytes,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resp_psks: Option<Vec<HexBytes>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resp_static: Option<HexBytes>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resp_ephemeral: Option<HexBytes>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resp_remote_static: Option<HexBytes>,
+
+    pub messages: Vec<Message>,
+}
+
+/// A file of test vectors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vectors {
+    /// The vectors in the file.
+    pub vectors: Vec<Vector>,
+}
+
+impl Vectors {
+    /// Parse vectors from JSON.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Serialize these vectors to pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("vectors are always serializable")
+    }
+}
+
+/// Why a vector didn't run the way it says it should.
+#[derive(Debug)]
+pub enum VectorError {
+    /// The protocol name couldn't be parsed, or a session couldn't be built from the vector.
+    Build(Error),
+
+    /// Writing or reading a message failed.
+    Message {
+        /// The index of the message in the vector.
+        index: usize,
+        /// The error from writing or reading the message.
+        error: Error,
+    },
+
+    /// A message didn't match the one in the vector.
+    Mismatch {
+        /// The index of the message in the vector.
+        index:    usize,
+        /// The message in the vector.
+        expected: Message,
+        /// The message this implementation produced.
+        actual:   Message,
+    },
+}
+
+impl fmt::Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorError::Build(error) => write!(f, "couldn't build sessions: {}", error),
+            VectorError::Message { index, error } => write!(f, "message {}: {}", index, error),
+            VectorError::Mismatch { index, expected, actual } => write!(
+                f,
+                "message {} mismatch: expected {:?} for {:?}, got {:?} for {:?}",
+                index, expected.ciphertext, expected.payload, actual.ciphertext, actual.payload
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VectorError {}
+
+impl Vector {
+    /// Run a handshake and transport session between an initiator and a responder built from
+    /// this vector, checking every message against it.
+    pub fn run(&self) -> Result<(), VectorError> {
+        let params: NoiseParams = self.protocol_name.parse().map_err(VectorError::Build)?;
+        let (mut init, mut resp) = self.build_session_pair(&params).map_err(VectorError::Build)?;
+        let (mut sendbuf, mut recvbuf) = (vec![0u8; 65535], vec![0u8; 65535]);
+
+        let mut messages = self.messages.iter().enumerate();
+        while !init.is_handshake_finished() {
+            let (index, expected) = match messages.next() {
+                Some(message) => message,
+                None => return Ok(()),
+            };
+            let (send, recv) =
+                if index % 2 == 1 { (&mut resp, &mut init) } else { (&mut init, &mut resp) };
+
+            let error = |error| VectorError::Message { index, error };
+            let len = send.write_message(&expected.payload, &mut sendbuf).map_err(error)?;
+            let recv_len = recv.read_message(&sendbuf[..len], &mut recvbuf).map_err(error)?;
+            check_message(index, expected, &sendbuf[..len], &recvbuf[..recv_len])?;
+        }
+
+        let is_oneway = params.handshake.pattern.is_oneway();
+        let mut init = init.into_transport_mode().map_err(VectorError::Build)?;
+        let mut resp = resp.into_transport_mode().map_err(VectorError::Build)?;
+        for (index, expected) in messages {
+            let (send, recv) = if !is_oneway && index % 2 == 1 {
+                (&mut resp, &mut init)
+            } else {
+                (&mut init, &mut resp)
+            };
+
+            let error = |error| VectorError::Message { index, error };
+            let len = send.write_message(&expected.payload, &mut sendbuf).map_err(error)?;
+            let recv_len = recv.read_message(&sendbuf[..len], &mut recvbuf).map_err(error)?;
+            check_message(index, expected, &sendbuf[..len], &recvbuf[..recv_len])?;
+        }
+        Ok(())
+    }
+
+    /// Generate a new vector for `params`, with random keys, PSKs and payloads.
+    ///
+    /// The vector covers the whole handshake, followed by one transport message.
+    pub fn generate(params: NoiseParams) -> Result<Self, Error> {
+        let tokens = HandshakeTokens::try_from(&params.handshake)?;
+        let builder = Builder::new(params.clone());
+        let init_s = builder.generate_keypair()?;
+        let resp_s = builder.generate_keypair()?;
+
+        // Static keys may be signing keys, but ephemeral keys are always DH keys.
+        let builder = Builder::new(NoiseParams { sig: None, ..params.clone() });
+        let init_e = builder.generate_keypair()?;
+        let resp_e = builder.generate_keypair()?;
+
+        let psk_count = params
+            .handshake
+            .modifiers
+            .list
+            .iter()
+            .filter(|m| matches!(m, HandshakeModifier::Psk(_)))
+            .count();
+        let psks: Vec<HexBytes> = (0..psk_count).map(|_| random_bytes(32)).collect();
+
+        let known =
+            |needed: bool, key: &[u8]| if needed { Some(key.to_vec().into()) } else { None };
+        let mut vector = Vector {
+            name:               None,
+            protocol_name:      params.name.clone(),
+            hybrid:             None,
+            fail:               None,
+            fallback:           None,
+            fallback_pattern:   None,
+            init_prologue:      PROLOGUE.to_vec().into(),
+            init_psks:          Some(psks.clone()),
+            init_static:        known(tokens.needs_local_static(true), &init_s.private),
+            init_ephemeral:     Some(init_e.private.into()),
+            init_remote_static: known(tokens.needs_remote_static(true), &resp_s.public),
+            resp_prologue:      PROLOGUE.to_vec().into(),
+            resp_psks:          Some(psks),
+            resp_static:        known(tokens.needs_local_static(false), &resp_s.private),
+            resp_ephemeral:     Some(resp_e.private.into()),
+            resp_remote_static: known(tokens.needs_remote_static(false), &init_s.public),
+            messages:           vec![],
+        };
+
+        let (mut init, mut resp) = vector.build_session_pair(&params)?;
+        let (mut sendbuf, mut recvbuf) = (vec![0u8; 65535], vec![0u8; 65535]);
+
+        while !init.is_handshake_finished() {
+            let index = vector.messages.len();
+            let (send, recv) =
+                if index % 2 == 1 { (&mut resp, &mut init) } else { (&mut init, &mut resp) };
+            let payload = random_bytes(PAYLOAD_LEN);
+            let len = send.write_message(&payload, &mut sendbuf)?;
+            recv.read_message(&sendbuf[..len], &mut recvbuf)?;
+            vector.messages.push(Message { payload, ciphertext: sendbuf[..len].to_vec().into() });
+        }
+
+        // Transport messages keep alternating, unless the pattern is one-way.
+        let is_oneway = params.handshake.pattern.is_oneway();
+        let mut sender = if !is_oneway && vector.messages.len() % 2 == 1 {
+            resp.into_transport_mode()?
+        } else {
+            init.into_transport_mode()?
+        };
+        let payload = random_bytes(PAYLOAD_LEN);
+        let len = sender.write_message(&payload, &mut sendbuf)?;
+        vector.messages.push(Message { payload, ciphertext: sendbuf[..len].to_vec().into() });
+
+        Ok(vector)
+    }
+
+    fn build_session_pair(
+        &self,
+        params: &NoiseParams,
+    ) -> Result<(HandshakeState, HandshakeState), Error> {
+        let mut init_builder = Builder::new(params.clone());
+        let mut resp_builder = Builder::new(params.clone());
+
+        // PSKs are listed in the order of the modifiers that use them.
+        let locations = params.handshake.modifiers.list.iter().filter_map(|m| match m {
+            HandshakeModifier::Psk(n) => Some(*n),
+            _ => None,
+        });
+        for (i, location) in locations.enumerate() {
+            let init_psk = self.init_psks.as_ref().and_then(|psks| psks.get(i));
+            let resp_psk = self.resp_psks.as_ref().and_then(|psks| psks.get(i));
+            match (init_psk, resp_psk) {
+                (Some(init_psk), Some(resp_psk)) => {
+                    init_builder = init_builder.psk(location, init_psk);
+                    resp_builder = resp_builder.psk(location, resp_psk);
+                },
+                _ => bail!(Prerequisite::Psk(location)),
+            }
+        }
+
+        if let Some(init_s) = &self.init_static {
+            init_builder = init_builder.local_private_key(init_s);
+        }
+        if let Some(resp_s) = &self.resp_static {
+            resp_builder = resp_builder.local_private_key(resp_s);
+        }
+        if let Some(init_remote_static) = &self.init_remote_static {
+            init_builder = init_builder.remote_public_key(init_remote_static);
+        }
+        if let Some(resp_remote_static) = &self.resp_remote_static {
+            resp_builder = resp_builder.remote_public_key(resp_remote_static);
+        }
+        if let Some(init_e) = &self.init_ephemeral {
+            init_builder = init_builder.fixed_ephemeral_key_for_testing_only(init_e);
+        }
+        if let Some(resp_e) = &self.resp_ephemeral {
+            resp_builder = resp_builder.fixed_ephemeral_key_for_testing_only(resp_e);
+        }
+
+        let init = init_builder.prologue(&self.init_prologue).build_initiator()?;
+        let resp = resp_builder.prologue(&self.resp_prologue).build_responder()?;
+        Ok((init, resp))
+    }
+}
+
+fn check_message(
+    index: usize,
+    expected: &Message,
+    ciphertext: &[u8],
+    payload: &[u8],
+) -> Result<(), VectorError> {
+    if *expected.ciphertext != *ciphertext || *expected.payload != *payload {
+        return Err(VectorError::Mismatch {
+            index,
+            expected: expected.clone(),
+            actual: Message {
+                payload:    payload.to_vec().into(),
+                ciphertext: ciphertext.to_vec().into(),
+            },
+        });
+    }
+    Ok(())
+}
+
+fn random_bytes(len: usize) -> HexBytes {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.into()
+}
@@ -0,0 +1,239 @@
+//! Checking remote static keys against X.509 certificates, for deployments with an existing PKI.
+//!
+//! Here the static key itself is the subject key of a certificate, issued by a CA the other side
+//! trusts. The sender puts its certificate chain (leaf first, DER-encoded, see [`encode_chain()`])
+//! in the payload of the handshake message carrying its static key, like the evidence in the
+//! [`identity`](crate::identity) module. The receiver passes that payload to
+//! [`verify_payload()`], which hands the chain and the static key the handshake learned to a
+//! [`CertificateValidator`].
+//!
+//! With the "x509" feature, [`OpenSslValidator`] validates chains against an OpenSSL trust
+//! store; anything else (a platform verifier, a pinned set of certificates, ...) can be plugged
+//! in by implementing the trait.
+
+use crate::{
+    error::{Error, Prerequisite},
+    handshakestate::HandshakeState,
+};
+use std::convert::TryFrom;
+
+/// Decides whether a certificate chain vouches for a remote static key.
+pub trait CertificateValidator {
+    /// Accept `chain` (DER-encoded, leaf first) for `remote_static`, or fail with an error,
+    /// usually `Error::Sig`.
+    fn validate(&self, remote_static: &[u8], chain: &[&[u8]]) -> Result<(), Error>;
+}
+
+/// Encode a certificate chain (DER-encoded, leaf first) for a handshake payload: a one-byte
+/// certificate count, then each certificate with a big-endian `u16` length prefix.
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if there are more than 255 certificates or one is longer than
+/// 65535 bytes.
+pub fn encode_chain(chain: &[&[u8]]) -> Result<Vec<u8>, Error> {
+    let mut out = vec![u8::try_from(chain.len()).map_err(|_| Error::Input)?];
+    for cert in chain {
+        let len = u16::try_from(cert.len()).map_err(|_| Error::Input)?;
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(cert);
+    }
+    Ok(out)
+}
+
+/// Decode a certificate chain encoded by [`encode_chain()`].
+///
+/// # Errors
+///
+/// Will result in `Error::Input` if `payload` is malformed or has anything after the chain.
+pub fn decode_chain(payload: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let (&count, mut rest) = payload.split_first().ok_or(Error::Input)?;
+    let mut chain = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        if rest.len() < 2 {
+            bail!(Error::Input);
+        }
+        let len = usize::from(u16::from_be_bytes([rest[0], rest[1]]));
+        chain.push(rest.get(2..2 + len).ok_or(Error::Input)?);
+        rest = &rest[2 + len..];
+    }
+    if !rest.is_empty() {
+        bail!(Error::Input);
+    }
+    Ok(chain)
+}
+
+/// Decode the certificate chain in `payload` and check it with `validator` against the remote
+/// static key `handshake` has learned.
+///
+/// # Errors
+///
+/// Will result in `Error::Prereq(Prerequisite::RemotePublicKey)` if the remote static key isn't
+/// known yet, `Error::Input` if `payload` isn't a certificate chain, and whatever `validator`
+/// fails with if it doesn't accept it.
+pub fn verify_payload(
+    handshake: &HandshakeState,
+    payload: &[u8],
+    validator: &dyn CertificateValidator,
+) -> Result<(), Error> {
+    let remote_static = handshake.get_remote_static().ok_or(Prerequisite::RemotePublicKey)?;
+    validator.validate(remote_static, &decode_chain(payload)?)
+}
+
+/// A [`CertificateValidator`] that builds and checks the chain with OpenSSL, against a trust
+/// store of CA certificates, and requires the leaf's subject key to be the X25519 static key.
+///
+/// Any other checks (host names, purposes, revocation, ...) are configured on the store, with
+/// OpenSSL's verification parameters and flags.
+///
+/// The "x509" feature has to be enabled to use this validator.
+#[cfg(feature = "x509")]
+pub struct OpenSslValidator {
+    store: openssl::x509::store::X509Store,
+}
+
+#[cfg(feature = "x509")]
+impl OpenSslValidator {
+    /// Create a validator trusting the CAs in `store`.
+    pub fn new(store: openssl::x509::store::X509Store) -> Self {
+        OpenSslValidator { store }
+    }
+
+    /// Validate `chain` like [`validate()`](CertificateValidator::validate), returning the
+    /// leaf certificate, e.g. to look at its subject.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `Error::Input` if a certificate doesn't parse, and `Error::Sig` if the
+    /// chain doesn't lead to a trusted CA or the leaf isn't for `remote_static`.
+    pub fn validate_chain(
+        &self,
+        remote_static: &[u8],
+        chain: &[&[u8]],
+    ) -> Result<openssl::x509::X509, Error> {
+        use openssl::{
+            pkey::Id,
+            stack::Stack,
+            x509::{X509StoreContext, X509},
+        };
+
+        let (leaf, intermediates) = chain.split_first().ok_or(Error::Input)?;
+        let leaf = X509::from_der(leaf).map_err(|_| Error::Input)?;
+        let mut untrusted = Stack::new().map_err(|_| Error::Input)?;
+        for cert in intermediates {
+            untrusted
+                .push(X509::from_der(cert).map_err(|_| Error::Input)?)
+                .map_err(|_| Error::Input)?;
+        }
+
+        let mut context = X509StoreContext::new().map_err(|_| Error::Sig)?;
+        let valid = context
+            .init(&self.store, &leaf, &untrusted, |context| context.verify_cert())
+            .map_err(|_| Error::Sig)?;
+        if !valid {
+            bail!(Error::Sig);
+        }
+
+        let subject_key = leaf.public_key().map_err(|_| Error::Sig)?;
+        if subject_key.id() != Id::X25519
+            || subject_key.raw_public_key().map_err(|_| Error::Sig)? != remote_static
+        {
+            bail!(Error::Sig);
+        }
+        Ok(leaf)
+    }
+}
+
+#[cfg(feature = "x509")]
+impl CertificateValidator for OpenSslValidator {
+    fn validate(&self, remote_static: &[u8], chain: &[&[u8]]) -> Result<(), Error> {
+        self.validate_chain(remote_static, chain).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_encoding() {
+        let chain: [&[u8]; 2] = [b"leaf", b"intermediate"];
+        let encoded = encode_chain(&chain).unwrap();
+        assert_eq!(decode_chain(&encoded).unwrap(), chain);
+
+        for bad in &[&[][..], &encoded[..encoded.len() - 1], &[1, 0, 5, 0]] {
+            assert!(decode_chain(bad).is_err());
+        }
+        assert!(decode_chain(&[&encoded[..], &[0]].concat()).is_err());
+        assert!(encode_chain(&[&[0u8; 65536][..]]).is_err());
+    }
+
+    #[cfg(feature = "x509")]
+    mod openssl_validator {
+        use super::*;
+        use openssl::{
+            asn1::Asn1Time,
+            hash::MessageDigest,
+            pkey::{Id, PKey, Private, Public},
+            x509::{
+                extension::BasicConstraints, store::X509StoreBuilder, X509Builder, X509NameBuilder,
+                X509,
+            },
+        };
+
+        fn certificate(
+            name: &str,
+            subject_key: &PKey<Public>,
+            issuer: Option<&X509>,
+            issuer_key: &PKey<Private>,
+        ) -> X509 {
+            let mut subject = X509NameBuilder::new().unwrap();
+            subject.append_entry_by_text("CN", name).unwrap();
+            let subject = subject.build();
+            let mut builder = X509Builder::new().unwrap();
+            builder.set_version(2).unwrap();
+            builder.set_subject_name(&subject).unwrap();
+            builder
+                .set_issuer_name(issuer.map_or(&subject, |issuer| issuer.subject_name()))
+                .unwrap();
+            builder.set_pubkey(subject_key).unwrap();
+            if issuer.is_none() {
+                builder
+                    .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+            }
+            builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+            builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+            builder.sign(issuer_key, MessageDigest::null()).unwrap();
+            builder.build()
+        }
+
+        fn ca(name: &str) -> (X509, PKey<Private>) {
+            let key = PKey::generate_ed25519().unwrap();
+            let public = PKey::public_key_from_der(&key.public_key_to_der().unwrap()).unwrap();
+            (certificate(name, &public, None, &key), key)
+        }
+
+        #[test]
+        fn test_openssl_validator() {
+            let (ca_cert, ca_key) = ca("ca");
+            let (_, rogue_key) = ca("rogue");
+            let static_key = PKey::generate_x25519().unwrap();
+            let static_public = static_key.raw_public_key().unwrap();
+            let public = PKey::public_key_from_raw_bytes(&static_public, Id::X25519).unwrap();
+            let leaf = certificate("server", &public, Some(&ca_cert), &ca_key).to_der().unwrap();
+            let forged =
+                certificate("server", &public, Some(&ca_cert), &rogue_key).to_der().unwrap();
+
+            let mut store = X509StoreBuilder::new().unwrap();
+            store.add_cert(ca_cert).unwrap();
+            let validator = OpenSslValidator::new(store.build());
+
+            let leaf_cert = validator.validate_chain(&static_public, &[&leaf]).unwrap();
+            assert_eq!(leaf_cert.to_der().unwrap(), leaf);
+            assert!(matches!(validator.validate(&[0u8; 32], &[&leaf]), Err(Error::Sig)));
+            assert!(matches!(validator.validate(&static_public, &[&forged]), Err(Error::Sig)));
+            assert!(matches!(validator.validate(&static_public, &[b"junk"]), Err(Error::Input)));
+        }
+    }
+}
@@ -0,0 +1,186 @@
+#![cfg(feature = "ffi")]
+
+use snow::ffi::*;
+use std::ptr;
+
+fn builder(name: &[u8]) -> *mut ScreechBuilder {
+    let mut builder = ptr::null_mut();
+    assert_eq!(
+        unsafe { screech_builder_new(name.as_ptr() as *const _, &mut builder) },
+        ScreechError::Ok
+    );
+    builder
+}
+
+#[test]
+fn test_ffi_handshake_and_transport() {
+    unsafe {
+        let b_i = builder(b"Noise_XX_25519_ChaChaPoly_BLAKE2s\0");
+        let b_r = builder(b"Noise_XX_25519_ChaChaPoly_BLAKE2s\0");
+
+        let (mut private_i, mut public_i) = ([0u8; 32], [0u8; 32]);
+        let (mut private_len, mut public_len) = (0, 0);
+        let res = screech_builder_generate_keypair(
+            b_i,
+            private_i.as_mut_ptr(),
+            private_i.len(),
+            &mut private_len,
+            public_i.as_mut_ptr(),
+            public_i.len(),
+            &mut public_len,
+        );
+        assert_eq!(res, ScreechError::Ok);
+        assert_eq!((private_len, public_len), (32, 32));
+        let mut keypair_r = ([0u8; 32], [0u8; 32]);
+        let res = screech_builder_generate_keypair(
+            b_r,
+            keypair_r.0.as_mut_ptr(),
+            32,
+            &mut private_len,
+            keypair_r.1.as_mut_ptr(),
+            32,
+            &mut public_len,
+        );
+        assert_eq!(res, ScreechError::Ok);
+
+        assert_eq!(
+            screech_builder_local_private_key(b_i, private_i.as_ptr(), 32),
+            ScreechError::Ok
+        );
+        assert_eq!(
+            screech_builder_local_private_key(b_r, keypair_r.0.as_ptr(), 32),
+            ScreechError::Ok
+        );
+
+        let (mut h_i, mut h_r) = (ptr::null_mut(), ptr::null_mut());
+        assert_eq!(screech_builder_build_initiator(b_i, &mut h_i), ScreechError::Ok);
+        assert_eq!(screech_builder_build_responder(b_r, &mut h_r), ScreechError::Ok);
+        screech_builder_free(b_i);
+        screech_builder_free(b_r);
+
+        let mut msg = [0u8; 1024];
+        let mut buf = [0u8; 1024];
+        let mut len = 0;
+        let mut out_len = 0;
+        let mut sender = h_i;
+        let mut receiver = h_r;
+        while screech_handshake_is_finished(h_i) == 0 {
+            assert_eq!(screech_handshake_is_my_turn(sender), 1);
+            let res = screech_handshake_write_message(
+                sender,
+                ptr::null(),
+                0,
+                msg.as_mut_ptr(),
+                msg.len(),
+                &mut len,
+            );
+            assert_eq!(res, ScreechError::Ok);
+            let res = screech_handshake_read_message(
+                receiver,
+                msg.as_ptr(),
+                len,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut out_len,
+            );
+            assert_eq!(res, ScreechError::Ok);
+            std::mem::swap(&mut sender, &mut receiver);
+        }
+
+        let mut rs = [0u8; 32];
+        let res = screech_handshake_get_remote_static(h_r, rs.as_mut_ptr(), rs.len(), &mut len);
+        assert_eq!(res, ScreechError::Ok);
+        assert_eq!(&rs[..len], &public_i[..]);
+
+        let (mut t_i, mut t_r) = (ptr::null_mut(), ptr::null_mut());
+        assert_eq!(screech_handshake_into_transport(h_i, &mut t_i), ScreechError::Ok);
+        assert_eq!(screech_handshake_into_transport(h_r, &mut t_r), ScreechError::Ok);
+
+        let payload = b"hack the planet";
+        let res = screech_transport_write_message(
+            t_i,
+            payload.as_ptr(),
+            payload.len(),
+            msg.as_mut_ptr(),
+            4,
+            &mut len,
+        );
+        assert_eq!(res, ScreechError::BufferTooSmall);
+        assert_eq!(len, payload.len() + 16);
+
+        let res = screech_transport_write_message(
+            t_i,
+            payload.as_ptr(),
+            payload.len(),
+            msg.as_mut_ptr(),
+            len,
+            &mut len,
+        );
+        assert_eq!(res, ScreechError::Ok);
+        let res = screech_transport_read_message(
+            t_r,
+            msg.as_ptr(),
+            len,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut out_len,
+        );
+        assert_eq!(res, ScreechError::Ok);
+        assert_eq!(&buf[..out_len], payload);
+
+        msg[0] ^= 1;
+        let res = screech_transport_read_message(
+            t_r,
+            msg.as_ptr(),
+            len,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut out_len,
+        );
+        assert_eq!(res, ScreechError::Decrypt);
+
+        screech_transport_free(t_i);
+        screech_transport_free(t_r);
+    }
+}
+
+#[test]
+fn test_ffi_errors() {
+    unsafe {
+        let mut b = ptr::null_mut();
+        assert_eq!(screech_builder_new(ptr::null(), &mut b), ScreechError::NullPointer);
+        assert_eq!(
+            screech_builder_new(
+                b"Noise_ZZ_25519_ChaChaPoly_BLAKE2s\0".as_ptr() as *const _,
+                &mut b
+            ),
+            ScreechError::Pattern
+        );
+        assert!(b.is_null());
+
+        let b = builder(b"Noise_NK_25519_ChaChaPoly_BLAKE2s\0");
+        let mut h = ptr::null_mut();
+        assert_eq!(screech_builder_build_initiator(b, &mut h), ScreechError::Prereq);
+        assert_eq!(
+            screech_builder_remote_public_key(b, ptr::null(), 32),
+            ScreechError::NullPointer
+        );
+        assert_eq!(screech_builder_remote_public_key(b, [9u8; 32].as_ptr(), 32), ScreechError::Ok);
+        assert_eq!(screech_builder_build_initiator(b, &mut h), ScreechError::Ok);
+
+        let mut len = 0;
+        let res = screech_handshake_write_message(h, ptr::null(), 0, ptr::null_mut(), 0, &mut len);
+        assert_eq!(res, ScreechError::BufferTooSmall);
+        // Only the ephemeral key was reached before running out of room.
+        assert_eq!(len, 32);
+
+        let mut t = ptr::null_mut();
+        assert_eq!(screech_handshake_into_transport(h, &mut t), ScreechError::State);
+        assert!(t.is_null());
+
+        screech_builder_free(b);
+        screech_builder_free(ptr::null_mut());
+        screech_handshake_free(ptr::null_mut());
+        screech_transport_free(ptr::null_mut());
+    }
+}
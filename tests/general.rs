@@ -9,7 +9,8 @@ use snow::{
 };
 
 use rand_core::{impls, CryptoRng, RngCore};
-use snow::{params::*, types::*};
+use snow::{padding::PaddingPolicy, params::*, types::*};
+use std::io::IoSlice;
 use x25519_dalek as x25519;
 
 #[derive(Default)]
@@ -38,6 +39,31 @@ impl RngCore for CountingRng {
 impl CryptoRng for CountingRng {}
 impl Random for CountingRng {}
 
+/// An RNG whose entropy source has gone away.
+struct FailingRng;
+
+impl RngCore for FailingRng {
+    fn next_u32(&mut self) -> u32 {
+        panic!("entropy unavailable")
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        panic!("entropy unavailable")
+    }
+
+    fn fill_bytes(&mut self, _dest: &mut [u8]) {
+        panic!("entropy unavailable")
+    }
+
+    fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        let code = std::num::NonZeroU32::new(rand_core::Error::CUSTOM_START).unwrap();
+        Err(code.into())
+    }
+}
+
+impl CryptoRng for FailingRng {}
+impl Random for FailingRng {}
+
 fn get_inc_key(start: u8) -> [u8; 32] {
     let mut k = [0u8; 32];
     for i in 0..32 {
@@ -102,6 +128,38 @@ fn test_protocol_name() {
     }
 }
 
+#[test]
+fn test_builder_rng_is_reproducible() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let first_message = |seed| {
+        let mut h_i = Builder::new(params.clone())
+            .rng(Box::new(CountingRng(seed)))
+            .build_initiator()
+            .unwrap();
+        let mut buffer_msg = [0u8; 200];
+        let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+        buffer_msg[..len].to_vec()
+    };
+
+    assert_eq!(first_message(1), first_message(1));
+    assert_ne!(first_message(1), first_message(2));
+}
+
+#[test]
+fn test_rng_failure() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params).rng(Box::new(FailingRng)).build_initiator().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let res = h_i.write_message(&[], &mut buffer_msg);
+    assert!(matches!(
+        res,
+        Err(snow::Error::Handshake { message: 0, token: Token::E, source })
+            if matches!(*source, snow::Error::Rng)
+    ));
+    assert!(h_i.is_my_turn());
+}
+
 #[test]
 fn test_noise_state_change() {
     let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
@@ -253,7 +311,7 @@ fn test_Xpsk0_expected_value() {
 
 #[test]
 #[cfg(feature = "hfs")]
-#[cfg(feature = "pqclean_kyber1024")]
+#[cfg(feature = "pqclean_kyber")]
 fn test_NNhfs_sanity_session() {
     // Due to how PQClean is implemented, we cannot do deterministic testing of the protocol.
     // Instead, we will see if the protocol runs smoothly.
@@ -277,6 +335,213 @@ fn test_NNhfs_sanity_session() {
     assert_eq!(&buffer_out[..len], b"hack the planet");
 }
 
+#[test]
+#[cfg(any(feature = "pqclean_kyber", feature = "mlkem"))]
+fn test_NNhfs_sessions() {
+    let mut kems: Vec<&str> = vec![];
+    if cfg!(feature = "pqclean_kyber") {
+        kems.extend(&["Kyber512", "Kyber768", "Kyber1024"]);
+    }
+    if cfg!(feature = "mlkem") {
+        kems.extend(&["MLKEM512", "MLKEM768", "MLKEM1024"]);
+    }
+    for kem in kems {
+        let params: NoiseParams =
+            format!("Noise_NNhfs_25519+{}_ChaChaPoly_SHA256", kem).parse().unwrap();
+        let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+        let mut h_r = Builder::new(params).build_responder().unwrap();
+
+        let mut buffer_msg = [0u8; 4096];
+        let mut buffer_out = [0u8; 4096];
+        let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+        let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+        h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash(), "{}", kem);
+
+        let mut h_i = h_i.into_transport_mode().unwrap();
+        let mut h_r = h_r.into_transport_mode().unwrap();
+
+        let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+        let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        assert_eq!(&buffer_out[..len], b"hack the planet", "{}", kem);
+    }
+}
+
+/// An RNG that hands out fixed bytes, then counts like [`CountingRng`].
+#[cfg(feature = "mlkem")]
+struct ReplayRng(Vec<u8>, CountingRng);
+
+#[cfg(feature = "mlkem")]
+impl RngCore for ReplayRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.1.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if self.0.len() >= dest.len() {
+            dest.copy_from_slice(&self.0[..dest.len()]);
+            self.0.drain(..dest.len());
+        } else {
+            self.1.fill_bytes(dest);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mlkem")]
+impl CryptoRng for ReplayRng {}
+#[cfg(feature = "mlkem")]
+impl Random for ReplayRng {}
+
+/// The seed (d || z) and public key of ML-KEM-768 known-answer test 0.
+#[cfg(feature = "mlkem")]
+const MLKEM768_KAT_SEED: &str = "6dbbc4375136df3b07f7c70e639e223e177e7fd53b161b3f4d57791794f12624f696484048ec21f96cf50a56d0759c448f3779752f0383d37449690694cf7a68";
+#[cfg(feature = "mlkem")]
+const MLKEM768_KAT_PUBKEY: &str = "01f60af1dc8e6360ae78b59d4a5042eb9145a269046d6236b8304f305c2d9dcb189fe5a62df89b2f5a7bce3bbc753c1e78f730a99869f809aba856b676b707b26601d1d909bab32451494eb7d0a2153a6350b79789a9b115f83ea12037256562f06a1d5aba378da77039d3bdecaca8e6a22a49050a76300a0267cdb38b7ac77903c50ca53b99283cac6b95fba651b11a4d1a692e4072965060587669f253b1bb182e661446168ac60221894660020e9bb5f5b7124a0303e2543ea3ea6ce97a2482b255ca346fb27a847b33b93f3ab2d33064c6e6632d1a23f1144e907b246b479f4a5c928929a1e24150f5241258a5b67766a66f6a33846495907828ebe44ecc5b73124071ba479073910410a16d5d5696b48b194752979795772a91c348f502b37aa650983ebb89bf3c081ff273544129c9137a6e1834c8f2e7ce14c7870c53c05b9b94ecd38e6645911b0912336863ec168831f811881075cf38a59de4b5c738aa6ef03d779b295588cfb62491cc7b3e08b48473354f9ac8061c152a9e205997499b970b69bce66fe42bca2924ccdf0103d0a4c39193c2df25118d72b17aab26b0c60d4cd2c306ca4696c185de05035f4a09cf970aecc8cc93436f83b1aeaf452c41929a2eabc151938f74c93b858546df2264eeeab602e04a85c522f8fb1a5214afd8d4cae57a47b6f381a23126bd9917173128af917f1d483691c450d1151cfe9a1492d473ed862e27da92500c86a20019e9f975e4f54ad319ba2c5630c4014219d7ba235456fe530140193d662445e6a941d1e238567ba8d4d95ab1c7447d690821876d017270cfb169f2d792f03c800720697b410ab41c66f2b24585125655eb10aa1087ffcb7750cb887ad4467377500a6a7d3a82976b415a54469577b4138d919b03f4c9a4d3390bdcb6f1717a5fa4ab25a34f4ba5039bb22c7f3c234ea4427347aa7251464e631904d7cac4784f78b49d5f4a104a301809a779f6466131f9c62bb67147f4cd4973a6aa1c29ae6a8647b6268be089fe048ce990cd638743d285c889a707f581b63af41731f0246b054bc4b47aab01b6842a2709d02e8158ab90f48b69d136082b34cb0673b74aa3f54508ed029fb8f5045ee0639e150ee3b3c85f68a310ec0441980100b42abf2bad10d4a9e0c7b2bc5bbcaf73cbcdc49dc2c949111936779b178974a0392947745a47189bc3fa8a679c80af964a9f9b1b56577274a2a669d2da6704aa496af407fa1aa964cc3dc3140f5f959a7ea974bdb1b83e48a99c0a3e2d75b0669b5c1278962540609166266da18886fc237af30cefd569dbe399e6652e45f06a5dfc9a758a4987088ff8e38a3cf36b9d988f0e070b68d0b88f7bcc41306080d889780c7e238895ccaa4f3577225cca4c8a9330ce613e717798c9670924b271ac402b51538b8b5967ac490dcab5300e6c54d6a3632f3b973e4186ee1a7e2e85649185b26370c387235c4df28a9937a49d4078bf883f4e6346cb3251d9e13f1bda087b285afaa80e262641c5527b0a184b8bc84a62e577314658e2029d850064f7a7b81f253e7cc124a9c5b039dc9b179a80c2f6aee6ea0815172537331a57b505baa76ff5b4c1f0da754b6194f4b39a9b18730d3cdab925d691ed77a8db9927ea233ac2a12744fdc27e5d221b9369adb325d8";
+
+/// Builds an NNhfs initiator whose "e1" key pair is ML-KEM-768 known-answer test 0, and a
+/// responder encapsulating with counting randomness.
+#[cfg(feature = "mlkem")]
+fn mlkem_pair(name: &str, psk: Option<&[u8; 32]>) -> (snow::HandshakeState, snow::HandshakeState) {
+    let params: NoiseParams = name.parse().unwrap();
+    let (mut builder_i, mut builder_r) = (Builder::new(params.clone()), Builder::new(params));
+    if let Some(psk) = psk {
+        builder_i = builder_i.psk(0, psk);
+        builder_r = builder_r.psk(0, psk);
+    }
+    let seed = Vec::<u8>::from_hex(MLKEM768_KAT_SEED).unwrap();
+    let h_i = builder_i
+        .rng(Box::new(ReplayRng(seed, CountingRng::default())))
+        .fixed_ephemeral_key_for_testing_only(&get_inc_key(0))
+        .build_initiator()
+        .unwrap();
+    let h_r = builder_r
+        .rng(Box::new(CountingRng::default()))
+        .fixed_ephemeral_key_for_testing_only(&get_inc_key(1))
+        .build_responder()
+        .unwrap();
+    (h_i, h_r)
+}
+
+#[test]
+#[cfg(feature = "mlkem")]
+fn test_NNhfs_expected_value() {
+    let (mut h_i, mut h_r) = mlkem_pair("Noise_NNhfs_25519+MLKEM768_ChaChaPoly_SHA256", None);
+    let mut buf = [0u8; 4096];
+    let mut buf2 = [0u8; 4096];
+
+    // -> e, e1: nothing is encrypted yet, so e1 is the known-answer public key in the clear.
+    let len = h_i.write_message(b"abc", &mut buf).unwrap();
+    let e = Vec::<u8>::from_hex("8f40c5adb68f25624ae5b214ea767a6ec94d829d3d7b5e1ad1ba6f3e2138285f")
+        .unwrap();
+    let e1 = Vec::<u8>::from_hex(MLKEM768_KAT_PUBKEY).unwrap();
+    assert_eq!(&buf[..len], &[&e[..], &e1[..], b"abc"].concat()[..]);
+    let len = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+    assert_eq!(&buf2[..len], b"abc");
+
+    // <- e, ee, ekem1
+    let len = h_r.write_message(b"defg", &mut buf).unwrap();
+    assert_eq!(len, 32 + 1088 + 16 + 4 + 16);
+    let len = h_i.read_message(&buf[..len], &mut buf2).unwrap();
+    assert_eq!(&buf2[..len], b"defg");
+
+    let expected =
+        Vec::<u8>::from_hex("fc9c525c4a7595b755392eb056f0771708dd9092313367a0e3fb8ce604c6f02e")
+            .unwrap();
+    assert_eq!(h_i.get_handshake_hash(), &expected[..]);
+    assert_eq!(h_r.get_handshake_hash(), &expected[..]);
+}
+
+#[test]
+#[cfg(feature = "mlkem")]
+fn test_NNhfs_psk0_expected_value() {
+    // With psk0, "e" also mixes the ephemeral into the key, so "e1" already gets encrypted.
+    let name = "Noise_NNhfs+psk0_25519+MLKEM768_ChaChaPoly_SHA256";
+    let (mut h_i, mut h_r) = mlkem_pair(name, Some(&get_inc_key(4)));
+    let mut buf = [0u8; 4096];
+    let mut buf2 = [0u8; 4096];
+
+    // -> psk, e, e1
+    let len = h_i.write_message(b"abc", &mut buf).unwrap();
+    assert_eq!(len, 32 + 1184 + 16 + 3 + 16);
+    let len = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+    assert_eq!(&buf2[..len], b"abc");
+
+    // <- e, ee, ekem1
+    let len = h_r.write_message(b"defg", &mut buf).unwrap();
+    assert_eq!(len, 32 + 1088 + 16 + 4 + 16);
+    let len = h_i.read_message(&buf[..len], &mut buf2).unwrap();
+    assert_eq!(&buf2[..len], b"defg");
+
+    let expected =
+        Vec::<u8>::from_hex("2086c175388d4421adeacdf942bf790b8dae3bdab62e19fcbf1bd7ab6a17df73")
+            .unwrap();
+    assert_eq!(h_i.get_handshake_hash(), &expected[..]);
+    assert_eq!(h_r.get_handshake_hash(), &expected[..]);
+}
+
+#[test]
+#[cfg(feature = "pqclean_kyber")]
+fn test_hfs_psk_sessions() {
+    for name in &[
+        "Noise_XXhfs+psk2_25519+Kyber768_ChaChaPoly_SHA256",
+        "Noise_XXpsk0+hfs_25519+Kyber512_AESGCM_BLAKE2s",
+        "Noise_IKhfs+psk1_25519+Kyber1024_ChaChaPoly_BLAKE2b",
+        "Noise_NNhfs+psk0+psk2_25519+Kyber768_ChaChaPoly_SHA256",
+    ] {
+        let params: NoiseParams = name.parse().unwrap();
+        let pattern = params.handshake.pattern;
+        let keypair = Builder::new(params.clone()).generate_keypair().unwrap();
+        let psk = [7u8; 32];
+        let mut builder_i = Builder::new(params.clone());
+        let mut builder_r = Builder::new(params.clone());
+        for modifier in &params.handshake.modifiers.list {
+            if let HandshakeModifier::Psk(location) = *modifier {
+                builder_i = builder_i.psk(location, &psk);
+                builder_r = builder_r.psk(location, &psk);
+            }
+        }
+        if pattern.needs_local_static_key(true) {
+            builder_i = builder_i.local_private_key(&keypair.private);
+        }
+        if pattern.needs_local_static_key(false) {
+            builder_r = builder_r.local_private_key(&keypair.private);
+        }
+        if pattern.need_known_remote_pubkey(true) {
+            builder_i = builder_i.remote_public_key(&keypair.public);
+        }
+        let mut h_i = builder_i.build_initiator().unwrap();
+        let mut h_r = builder_r.build_responder().unwrap();
+
+        let mut buffer_msg = [0u8; 4096];
+        let mut buffer_out = [0u8; 4096];
+        let (mut sender, mut receiver) = (&mut h_i, &mut h_r);
+        while !(sender.is_handshake_finished() && receiver.is_handshake_finished()) {
+            let len = sender.write_message(b"abc", &mut buffer_msg).unwrap();
+            let len = receiver.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+            assert_eq!(&buffer_out[..len], b"abc", "{}", name);
+            std::mem::swap(&mut sender, &mut receiver);
+        }
+        assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash(), "{}", name);
+
+        let mut h_i = h_i.into_transport_mode().unwrap();
+        let mut h_r = h_r.into_transport_mode().unwrap();
+        let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+        let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        assert_eq!(&buffer_out[..len], b"hack the planet");
+    }
+}
+
 #[test]
 fn test_XXpsk0_expected_value() {
     let params: NoiseParams = "Noise_XXpsk0_25519_ChaChaPoly_SHA256".parse().unwrap();
@@ -427,6 +692,45 @@ fn test_rekey() {
     assert_eq!(&buffer_out[..len], b"hack the planet");
 }
 
+#[test]
+fn test_key_update() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    // the key update is the last message under the old key
+    let len = h_i.write_key_update(&mut buffer_msg).unwrap();
+    let update = buffer_msg[..len].to_vec();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    h_r.read_key_update(&update).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+
+    // the other direction keeps its key
+    let len = h_r.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+
+    // key updates and data messages can't pass for each other
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    assert!(matches!(h_r.read_key_update(&buffer_msg[..len]), Err(snow::Error::Decrypt)));
+    let len = h_i.write_key_update(&mut buffer_msg).unwrap();
+    assert!(matches!(
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out),
+        Err(snow::Error::Decrypt)
+    ));
+}
+
 #[test]
 fn test_rekey_manually() {
     let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
@@ -473,122 +777,605 @@ fn test_rekey_manually() {
 }
 
 #[test]
-fn test_handshake_message_exceeds_max_len() {
-    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut h_i = Builder::new(params).build_initiator().unwrap();
+fn test_transcript() {
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let static_i = Builder::new(params.clone()).generate_keypair().unwrap();
+    let static_r = Builder::new(params.clone()).generate_keypair().unwrap();
+    let mut h_i = Builder::new(params.clone())
+        .local_private_key(&static_i.private)
+        .record_transcript()
+        .build_initiator()
+        .unwrap();
+    let mut h_r = Builder::new(params.clone())
+        .local_private_key(&static_r.private)
+        .record_transcript()
+        .build_responder()
+        .unwrap();
+    let h_plain = Builder::new(params).local_private_key(&static_i.private).build_initiator();
+    assert!(h_plain.unwrap().transcript().is_none());
 
-    let mut buffer_out = [0u8; 65535 * 2];
-    assert!(h_i.write_message(&[0u8; 65530], &mut buffer_out).is_err());
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    buffer_msg[len - 1] ^= 1;
+    assert!(h_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+    buffer_msg[len - 1] ^= 1;
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let (t_i, t_r) = (h_i.transcript().unwrap(), h_r.transcript().unwrap());
+    assert!(t_i.initiator && !t_r.initiator);
+    assert_eq!(t_i.initial_hash, t_r.initial_hash);
+    assert_eq!(t_i.messages.len(), 4);
+    assert_eq!(t_r.messages.len(), 3);
+
+    // The failed read is recorded up to the payload, whose tag didn't verify.
+    let failed = &t_i.messages[1];
+    assert!(!failed.outgoing && failed.error.is_some() && failed.handshake_hash.is_none());
+    assert_eq!(failed.steps, t_i.messages[2].steps);
+
+    let succeeded = t_i.messages.iter().filter(|message| message.error.is_none());
+    for (sent, received) in succeeded.zip(&t_r.messages) {
+        assert_eq!(sent.outgoing, !received.outgoing);
+        assert_eq!(sent.steps, received.steps);
+        assert_eq!(sent.handshake_hash, received.handshake_hash);
+        assert_eq!(sent.message_len, received.message_len);
+        assert_eq!(sent.payload_len, received.payload_len);
+    }
+    let tokens: Vec<_> = t_r.messages.iter().flat_map(|m| &m.steps).map(|s| &s.token[..]).collect();
+    assert_eq!(tokens, ["e", "e", "ee", "s", "es", "s", "se"]);
+    assert_eq!(t_r.messages[0].payload_len, Some(3));
+    assert_eq!(t_r.messages[1].steps[2].public_key.as_deref(), Some(&static_r.public[..]));
+    assert_eq!(t_r.messages[2].steps[0].public_key.as_deref(), Some(&static_i.public[..]));
+    assert_eq!(t_r.messages[2].handshake_hash.as_deref(), Some(h_r.get_handshake_hash()));
+
+    // Starting over starts a new transcript.
+    h_r.reset(true, &[], Some(&static_i.public)).unwrap();
+    assert!(h_r.transcript().unwrap().initiator);
+    assert!(h_r.transcript().unwrap().messages.is_empty());
 }
 
 #[test]
-fn test_handshake_message_undersized_output_buffer() {
+fn test_derive_subsession() {
     let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut h_i = Builder::new(params).build_initiator().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params.clone()).build_responder().unwrap();
 
+    let mut buffer_msg = [0u8; 200];
     let mut buffer_out = [0u8; 200];
-    assert!(h_i.write_message(&[0u8; 400], &mut buffer_out).is_err());
-}
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let h_r = h_r.into_transport_mode().unwrap();
 
-#[test]
-fn test_transport_message_exceeds_max_len() {
-    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut noise = Builder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
+    // Rekeying the session doesn't change its sub-sessions.
+    h_i.rekey_outgoing();
+    let mut video_i = h_i.derive_subsession(b"video", Box::new(DefaultResolver)).unwrap();
+    let mut video_r = h_r.derive_subsession(b"video", Box::new(DefaultResolver)).unwrap();
+    let mut audio_r = h_r.derive_subsession(b"audio", Box::new(DefaultResolver)).unwrap();
+    assert!(video_i.is_initiator() && !video_r.is_initiator());
+
+    let len = video_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    assert!(audio_r.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+    let len = video_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+    let len = video_r.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = video_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
 
-    let mut buffer_out = [0u8; 65535 * 2];
-    noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
-    let mut noise = noise.into_transport_mode().unwrap();
-    assert!(noise.write_message(&[0u8; 65534], &mut buffer_out).is_err());
+    // Sub-sessions are independent of the session they came from.
+    let mut h_r = h_r;
+    assert_eq!(h_r.receiving_nonce(), 0);
+    let len = h_r.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    assert!(video_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+
+    // Exports don't carry what sub-sessions are derived from.
+    let imported = Builder::new(params).import_session(&h_r.export_session().unwrap()).unwrap();
+    assert!(matches!(
+        imported.derive_subsession(b"video", Box::new(DefaultResolver)),
+        Err(snow::Error::State(snow::error::StateProblem::MissingKeyMaterial))
+    ));
 }
 
 #[test]
-fn test_transport_message_undersized_output_buffer() {
-    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut noise = Builder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
+fn test_export_session() {
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let static_i = Builder::new(params.clone()).generate_keypair().unwrap();
+    let static_r = Builder::new(params.clone()).generate_keypair().unwrap();
+    let mut h_i = Builder::new(params.clone())
+        .local_private_key(&static_i.private)
+        .build_initiator()
+        .unwrap();
+    let mut h_r = Builder::new(params.clone())
+        .local_private_key(&static_r.private)
+        .build_responder()
+        .unwrap();
 
+    let mut buffer_msg = [0u8; 200];
     let mut buffer_out = [0u8; 200];
-    noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
-    let mut noise = noise.into_transport_mode().unwrap();
-    assert!(noise.write_message(&[0u8; 300], &mut buffer_out).is_err());
-}
-
-#[test]
-fn test_oneway_initiator_enforcements() {
-    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut noise = Builder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
-
-    let mut buffer_out = [0u8; 1024];
-    noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
-    let mut noise = noise.into_transport_mode().unwrap();
-    assert!(noise.read_message(&[0u8; 1024], &mut buffer_out).is_err());
-}
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
 
-#[test]
-fn test_oneway_responder_enforcements() {
-    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let resp_builder = Builder::new(params.clone());
-    let rpk = resp_builder.generate_keypair().unwrap();
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    h_i.rekey_outgoing();
+    h_r.rekey_incoming();
 
-    let mut resp = resp_builder.local_private_key(&rpk.private).build_responder().unwrap();
-    let mut init = Builder::new(params).remote_public_key(&rpk.public).build_initiator().unwrap();
+    // Pick the responder back up from its export, and carry on where it left off.
+    let exported = h_r.export_session().unwrap();
+    let mut h_r = Builder::new(params.clone()).import_session(&exported).unwrap();
+    assert!(!h_r.is_initiator());
+    assert_eq!(h_r.get_remote_static(), Some(&static_i.public[..]));
+    assert_eq!(h_r.receiving_nonce(), 1);
 
-    let mut buffer_resp = [0u8; 65535];
-    let mut buffer_init = [0u8; 65535];
-    let len = init.write_message(&[0u8; 0], &mut buffer_init).unwrap();
-    resp.read_message(&buffer_init[..len], &mut buffer_resp).unwrap();
-    let mut init = init.into_transport_mode().unwrap();
-    let mut resp = resp.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+    let len = h_r.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
 
-    assert!(init.read_message(&[0u8; 1024], &mut buffer_init).is_err());
-    assert!(resp.write_message(&[0u8; 1024], &mut buffer_resp).is_err());
+    let other: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    assert!(matches!(Builder::new(other).import_session(&exported), Err(snow::Error::Input)));
+    let truncated = &exported[..exported.len() - 1];
+    assert!(matches!(
+        Builder::new(params.clone()).import_session(truncated),
+        Err(snow::Error::Input)
+    ));
+    let mut bad_flag = exported;
+    let flag = 2 + params.name.len();
+    bad_flag[flag] = 2;
+    assert!(matches!(Builder::new(params).import_session(&bad_flag), Err(snow::Error::Input)));
 }
 
-#[test]
-fn test_buffer_issues() {
-    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
-    let mut h_r = Builder::new(params).build_responder().unwrap();
-
-    let mut buffer_msg = [0u8; 200];
-    let mut buffer_out = [0u8; 2];
-    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
-    let res = h_r.read_message(&buffer_msg[..len], &mut buffer_out);
+/// ChaChaPoly with a `rekey()` of its own, which doesn't hand back the new key.
+struct RekeyingCipher(Box<dyn Cipher>);
 
-    assert!(res.is_err());
-}
+impl Cipher for RekeyingCipher {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
 
-#[test]
-fn test_read_buffer_issues() {
-    let params: NoiseParams = "Noise_XK_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    fn set(&mut self, key: &[u8]) {
+        self.0.set(key)
+    }
 
-    let builder_r = snow::Builder::new(params.clone());
-    let keypair_r = builder_r.generate_keypair().unwrap();
-    let mut h_r = builder_r.local_private_key(&keypair_r.private).build_responder().unwrap();
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        self.0.encrypt(nonce, authtext, plaintext, out)
+    }
 
-    let builder_i = snow::Builder::new(params);
-    let key_i = builder_i.generate_keypair().unwrap().private;
-    let mut h_i = builder_i
-        .local_private_key(&key_i)
-        .remote_public_key(&keypair_r.public)
-        .build_initiator()
-        .unwrap();
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        self.0.decrypt(nonce, authtext, ciphertext, out)
+    }
 
-    let mut buffer_msg = [0u8; 65535];
-    let mut buffer_out = [0u8; 65535];
-    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
-    let res = h_r.read_message(&buffer_msg[..len], &mut buffer_out);
+    fn rekey(&mut self) {
+        self.0.set(&[7u8; 32]);
+    }
+}
 
-    assert!(res.is_ok());
+struct RekeyingResolver;
 
-    let len = h_r.write_message(b"abc", &mut buffer_msg).unwrap();
-    let res = h_i.read_message(&buffer_msg[..len], &mut buffer_out);
+impl CryptoResolver for RekeyingResolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        DefaultResolver.resolve_rng()
+    }
 
-    assert!(res.is_ok());
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        DefaultResolver.resolve_dh(choice)
+    }
 
-    let _len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
-    let res = h_r.read_message(&buffer_msg[..2], &mut buffer_out);
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        DefaultResolver.resolve_hash(choice)
+    }
 
-    assert!(res.is_err());
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        Some(Box::new(RekeyingCipher(DefaultResolver.resolve_cipher(choice)?)))
+    }
+}
+
+#[test]
+fn test_no_export_after_custom_rekey() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::with_resolver(params.clone(), Box::new(RekeyingResolver))
+        .build_initiator()
+        .unwrap();
+    let mut h_r =
+        Builder::with_resolver(params, Box::new(RekeyingResolver)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    assert!(h_i.export_session().is_ok());
+
+    // The cipher's own rekey is what both sides use...
+    h_i.rekey_outgoing();
+    h_r.rekey_incoming();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+
+    // ...but its key can't be tracked, so the session can't be exported any more.
+    for session in &[h_i, h_r] {
+        assert!(matches!(
+            session.export_session(),
+            Err(snow::Error::State(snow::error::StateProblem::UnexportableSession))
+        ));
+    }
+}
+
+#[test]
+fn test_reserve_nonces() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params.clone()).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    assert_eq!(h_i.reserved_nonces_left(), None);
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(h_i.reserve_nonces(3).unwrap(), 4);
+    let checkpoint = h_i.export_session().unwrap();
+
+    // Messages within the reservation go through as usual...
+    for _ in 0..3 {
+        let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    }
+    assert_eq!(h_i.reserved_nonces_left(), Some(0));
+    assert!(matches!(
+        h_i.write_message(b"hack the planet", &mut buffer_msg),
+        Err(snow::Error::State(snow::error::StateProblem::NonceReservationExhausted))
+    ));
+    assert!(h_i.write_message_vectored(&[IoSlice::new(b"abc")], &mut buffer_msg).is_err());
+
+    // ...and restoring the checkpoint picks up past all of them.
+    let restored = Builder::new(params.clone()).import_session(&checkpoint).unwrap();
+    assert_eq!(restored.sending_nonce(), 4);
+    assert_eq!(restored.reserved_nonces_left(), None);
+
+    assert_eq!(h_i.reserve_nonces(10).unwrap(), 14);
+    assert_eq!(h_i.reserved_nonces_left(), Some(10));
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert!(matches!(
+        h_i.reserve_nonces(u64::MAX),
+        Err(snow::Error::State(snow::error::StateProblem::NonceExhausted))
+    ));
+}
+
+#[test]
+#[cfg(feature = "argon2")]
+fn test_seal_session() {
+    use snow::psk::PassphraseCost;
+
+    let cheap = PassphraseCost { memory_kib: 64, iterations: 1, parallelism: 1 };
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params.clone()).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let h_r = h_r.into_transport_mode().unwrap();
+
+    let builder = Builder::new(params.clone());
+    let sealed = builder.seal_session(&h_r, b"hunter2", cheap).unwrap();
+    assert_ne!(sealed, builder.seal_session(&h_r, b"hunter2", cheap).unwrap());
+    let exported = h_r.export_session().unwrap();
+    assert!(!sealed.windows(exported.len()).any(|window| window == &exported[..]));
+
+    let wrong = Builder::new(params.clone()).unseal_session(&sealed, b"hunter3", cheap);
+    assert!(matches!(wrong, Err(snow::Error::Decrypt)));
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    let tampered = Builder::new(params.clone()).unseal_session(&tampered, b"hunter2", cheap);
+    assert!(matches!(tampered, Err(snow::Error::Decrypt)));
+    let other: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let other_protocol = Builder::new(other.clone()).unseal_session(&sealed, b"hunter2", cheap);
+    assert!(matches!(other_protocol, Err(snow::Error::Decrypt)));
+    assert!(matches!(
+        Builder::new(other).seal_session(&h_r, b"hunter2", cheap),
+        Err(snow::Error::Input)
+    ));
+
+    // The cost comes from the sealed session, so it's only spent up to the caller's limit.
+    let cheaper = PassphraseCost { memory_kib: 32, ..cheap };
+    let too_costly = Builder::new(params.clone()).unseal_session(&sealed, b"hunter2", cheaper);
+    assert!(matches!(too_costly, Err(snow::Error::Input)));
+    let mut inflated = sealed.clone();
+    inflated[1..5].copy_from_slice(&u32::MAX.to_be_bytes());
+    let inflated = Builder::new(params.clone()).unseal_session(&inflated, b"hunter2", cheap);
+    assert!(matches!(inflated, Err(snow::Error::Input)));
+
+    let mut h_r = Builder::new(params).unseal_session(&sealed, b"hunter2", cheap).unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+}
+
+#[test]
+fn test_handshake_message_exceeds_max_len() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params).build_initiator().unwrap();
+
+    let mut buffer_out = [0u8; 65535 * 2];
+    assert!(h_i.write_message(&[0u8; 65530], &mut buffer_out).is_err());
+}
+
+#[test]
+fn test_handshake_message_undersized_output_buffer() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params).build_initiator().unwrap();
+
+    let mut buffer_out = [0u8; 200];
+    assert!(h_i.write_message(&[0u8; 400], &mut buffer_out).is_err());
+}
+
+#[test]
+fn test_transport_message_exceeds_max_len() {
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut noise = Builder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
+
+    let mut buffer_out = [0u8; 65535 * 2];
+    noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
+    let mut noise = noise.into_transport_mode().unwrap();
+    assert!(noise.write_message(&[0u8; 65534], &mut buffer_out).is_err());
+}
+
+#[test]
+fn test_transport_message_undersized_output_buffer() {
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut noise = Builder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
+
+    let mut buffer_out = [0u8; 200];
+    noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
+    let mut noise = noise.into_transport_mode().unwrap();
+    assert!(noise.write_message(&[0u8; 300], &mut buffer_out).is_err());
+}
+
+#[test]
+fn test_oneway_initiator_enforcements() {
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut noise = Builder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
+
+    let mut buffer_out = [0u8; 1024];
+    noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
+    let mut noise = noise.into_transport_mode().unwrap();
+    assert!(matches!(
+        noise.read_message(&[0u8; 1024], &mut buffer_out),
+        Err(snow::Error::State(snow::error::StateProblem::OneWay))
+    ));
+}
+
+#[test]
+fn test_oneway_responder_enforcements() {
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let resp_builder = Builder::new(params.clone());
+    let rpk = resp_builder.generate_keypair().unwrap();
+
+    let mut resp = resp_builder.local_private_key(&rpk.private).build_responder().unwrap();
+    let mut init = Builder::new(params).remote_public_key(&rpk.public).build_initiator().unwrap();
+
+    let mut buffer_resp = [0u8; 65535];
+    let mut buffer_init = [0u8; 65535];
+    let len = init.write_message(&[0u8; 0], &mut buffer_init).unwrap();
+    resp.read_message(&buffer_init[..len], &mut buffer_resp).unwrap();
+    let mut init = init.into_transport_mode().unwrap();
+    let mut resp = resp.into_transport_mode().unwrap();
+
+    assert!(matches!(
+        init.read_message(&[0u8; 1024], &mut buffer_init),
+        Err(snow::Error::State(snow::error::StateProblem::OneWay))
+    ));
+    assert!(matches!(
+        resp.write_message(&[0u8; 1024], &mut buffer_resp),
+        Err(snow::Error::State(snow::error::StateProblem::OneWay))
+    ));
+}
+
+#[test]
+fn test_oneway_stateless_enforcements() {
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let resp_builder = Builder::new(params.clone());
+    let rpk = resp_builder.generate_keypair().unwrap();
+
+    let mut resp = resp_builder.local_private_key(&rpk.private).build_responder().unwrap();
+    let mut init = Builder::new(params).remote_public_key(&rpk.public).build_initiator().unwrap();
+
+    let mut buffer_resp = [0u8; 65535];
+    let mut buffer_init = [0u8; 65535];
+    let len = init.write_message(&[0u8; 0], &mut buffer_init).unwrap();
+    resp.read_message(&buffer_init[..len], &mut buffer_resp).unwrap();
+    let init = init.into_stateless_transport_mode().unwrap();
+    let resp = resp.into_stateless_transport_mode().unwrap();
+
+    assert!(matches!(
+        init.read_message(0, &[0u8; 1024], &mut buffer_init),
+        Err(snow::Error::State(snow::error::StateProblem::OneWay))
+    ));
+    assert!(matches!(
+        resp.write_message(0, &[0u8; 1024], &mut buffer_resp),
+        Err(snow::Error::State(snow::error::StateProblem::OneWay))
+    ));
+
+    let len = init.write_message(0, b"ping", &mut buffer_init).unwrap();
+    let len = resp.read_message(0, &buffer_init[..len], &mut buffer_resp).unwrap();
+    assert_eq!(&buffer_resp[..len], b"ping");
+}
+
+#[test]
+fn test_buffer_issues() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 2];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    let res = h_r.read_message(&buffer_msg[..len], &mut buffer_out);
+
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_handshake_progress() {
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
+    let mut h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    for position in 0..3 {
+        let (writer, reader) =
+            if position % 2 == 0 { (&mut h_i, &mut h_r) } else { (&mut h_r, &mut h_i) };
+        assert!(writer.is_my_turn() && !reader.is_my_turn());
+        assert_eq!(writer.pattern_position(), position);
+        assert_eq!(reader.messages_remaining(), 3 - position);
+        // Only the first message, `-> e`, has no key to encrypt its payload with.
+        assert_eq!(writer.next_message_will_encrypt_payload(), position > 0);
+        assert_eq!(reader.next_message_will_encrypt_payload(), position > 0);
+
+        let len = writer.write_message(&[], &mut buffer_msg).unwrap();
+        reader.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    }
+
+    assert_eq!(h_i.messages_remaining(), 0);
+    assert_eq!(h_r.pattern_position(), 3);
+    assert!(h_i.is_handshake_finished() && h_r.is_handshake_finished());
+}
+
+#[test]
+fn test_next_message_will_encrypt_payload() {
+    let params: NoiseParams = "Noise_NNpsk0_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let h_i = Builder::new(params).psk(0, &[1u8; 32]).build_initiator().unwrap();
+    assert!(h_i.next_message_will_encrypt_payload());
+
+    let params: NoiseParams = "Noise_NK_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let h_i = Builder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
+    assert!(h_i.next_message_will_encrypt_payload());
+    assert!(!h_i.was_write_payload_encrypted());
+}
+
+#[test]
+fn test_write_buffer_too_small() {
+    use std::error::Error as _;
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params).build_initiator().unwrap();
+
+    let err = h_i.write_message(b"abc", &mut [0u8; 40]).unwrap_err();
+    assert!(matches!(err, snow::Error::BufferTooSmall { needed: 51, got: 40 }));
+    assert_eq!(err.to_string(), "buffer too small: needed 51 bytes, got 40");
+    assert!(err.source().is_none());
+
+    let err = h_i.into_transport_mode().unwrap_err();
+    assert_eq!(err.to_string(), "state error: handshake not finished");
+    assert!(err.source().is_some());
+}
+
+#[test]
+fn test_truncated_vs_corrupted() {
+    use snow::Error;
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    let res = h_r.read_message(&buffer_msg[..10], &mut buffer_out);
+    assert!(matches!(res, Err(Error::TruncatedMessage { needed: 32, got: 10 })));
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    // <- e, ee: the payload tag is cut short, then corrupted.
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    let res = h_i.read_message(&buffer_msg[..len - 1], &mut buffer_out);
+    assert!(matches!(res, Err(Error::TruncatedMessage { needed: 48, got: 47 })));
+    buffer_msg[len - 1] ^= 1;
+    assert!(matches!(h_i.read_message(&buffer_msg[..len], &mut buffer_out), Err(Error::Decrypt)));
+    buffer_msg[len - 1] ^= 1;
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"hello", &mut buffer_msg).unwrap();
+    let res = h_r.read_message(&buffer_msg[..5], &mut buffer_out);
+    assert!(matches!(res, Err(Error::TruncatedMessage { needed: 16, got: 5 })));
+    let res = h_r.read_message(&buffer_msg[..len], &mut [0u8; 4]);
+    assert!(matches!(res, Err(Error::BufferTooSmall { needed: 5, got: 4 })));
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hello");
+}
+
+#[test]
+fn test_read_buffer_issues() {
+    let params: NoiseParams = "Noise_XK_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+
+    let builder_r = snow::Builder::new(params.clone());
+    let keypair_r = builder_r.generate_keypair().unwrap();
+    let mut h_r = builder_r.local_private_key(&keypair_r.private).build_responder().unwrap();
+
+    let builder_i = snow::Builder::new(params);
+    let key_i = builder_i.generate_keypair().unwrap().private;
+    let mut h_i = builder_i
+        .local_private_key(&key_i)
+        .remote_public_key(&keypair_r.public)
+        .build_initiator()
+        .unwrap();
+
+    let mut buffer_msg = [0u8; 65535];
+    let mut buffer_out = [0u8; 65535];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    let res = h_r.read_message(&buffer_msg[..len], &mut buffer_out);
+
+    assert!(res.is_ok());
+
+    let len = h_r.write_message(b"abc", &mut buffer_msg).unwrap();
+    let res = h_i.read_message(&buffer_msg[..len], &mut buffer_out);
+
+    assert!(res.is_ok());
+
+    let _len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    let res = h_r.read_message(&buffer_msg[..2], &mut buffer_out);
+
+    assert!(res.is_err());
 }
 
 #[test]
@@ -726,43 +1513,165 @@ fn test_get_remote_static() {
 }
 
 #[test]
-fn test_set_psk() {
-    let params: NoiseParams = "Noise_XXpsk3_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut h_i =
-        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
-    let mut h_r =
-        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+fn test_get_remote_ephemeral() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
 
     let mut buf = [0u8; 1024];
     let mut buf2 = [0u8; 1024];
+    assert!(h_r.get_remote_ephemeral().is_none());
 
-    let psk = get_inc_key(3);
-
-    // XX(s, rs):
     // -> e
     let len = h_i.write_message(&[], &mut buf).unwrap();
     let _ = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+    assert_eq!(h_r.get_remote_ephemeral().unwrap(), &buf[..32]);
+    assert!(h_i.get_remote_ephemeral().is_none());
 
-    // <- e, ee s, es
+    // <- e, ee
     let len = h_r.write_message(&[], &mut buf).unwrap();
     let _ = h_i.read_message(&buf[..len], &mut buf2).unwrap();
-
-    h_i.set_psk(3, &psk).unwrap();
-    h_r.set_psk(3, &psk).unwrap();
-
-    // -> s, se, psk
-    let len = h_i.write_message(&[], &mut buf).unwrap();
-    let _ = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+    assert_eq!(h_i.get_remote_ephemeral().unwrap(), &buf[..32]);
 }
 
 #[test]
-fn test_stateless_sanity_session() {
-    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
-    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+fn test_get_local_static() {
+    let params: NoiseParams = "Noise_XN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
     let mut h_r = Builder::new(params).build_responder().unwrap();
+    let static_i = x25519::x25519(get_inc_key(0), x25519::X25519_BASEPOINT_BYTES);
 
-    let mut buffer_msg = [0u8; 200];
-    let mut buffer_out = [0u8; 200];
+    assert_eq!(h_i.get_local_static().unwrap(), &static_i);
+    assert!(h_r.get_local_static().is_none());
+
+    let mut buf = [0u8; 1024];
+    let mut buf2 = [0u8; 1024];
+    let len = h_i.write_message(&[], &mut buf).unwrap();
+    let _ = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+    let len = h_r.write_message(&[], &mut buf).unwrap();
+    let _ = h_i.read_message(&buf[..len], &mut buf2).unwrap();
+    let len = h_i.write_message(&[], &mut buf).unwrap();
+    let _ = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+
+    let h_i = h_i.into_transport_mode().unwrap();
+    let h_r = h_r.into_stateless_transport_mode().unwrap();
+    assert_eq!(h_i.get_local_static().unwrap(), &static_i);
+    assert!(h_r.get_local_static().is_none());
+    assert_eq!(h_r.get_remote_static().unwrap(), &static_i);
+}
+
+#[test]
+fn test_traffic_accounting() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buf = [0u8; 1024];
+    let mut buf2 = [0u8; 1024];
+    let len = h_i.write_message(&[], &mut buf).unwrap();
+    let _ = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+    let len = h_r.write_message(&[], &mut buf).unwrap();
+    let _ = h_i.read_message(&buf[..len], &mut buf2).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let h_r = h_r.into_stateless_transport_mode().unwrap();
+    assert_eq!((h_i.bytes_sent(), h_i.messages_sent()), (0, 0));
+
+    for (nonce, payload_len) in [10usize, 0, 100].iter().enumerate() {
+        let len = h_i.write_message(&buf2[..*payload_len], &mut buf).unwrap();
+        let _ = h_r.read_message(nonce as u64, &buf[..len], &mut buf2).unwrap();
+    }
+    assert_eq!(h_i.bytes_sent(), 110 + 3 * 16);
+    assert_eq!(h_i.messages_sent(), 3);
+    assert_eq!(h_r.bytes_received(), 110 + 3 * 16);
+    assert_eq!(h_r.messages_received(), 3);
+
+    // Messages that don't decrypt aren't counted.
+    let len = h_i.write_message(b"tampered", &mut buf).unwrap();
+    buf[0] ^= 1;
+    assert!(h_r.read_message(3, &buf[..len], &mut buf2).is_err());
+    assert_eq!(h_r.messages_received(), 3);
+
+    let len = h_r.write_message(0, b"hi", &mut buf).unwrap();
+    let _ = h_i.read_message(&buf[..len], &mut buf2).unwrap();
+    assert_eq!((h_i.bytes_received(), h_i.messages_received()), (2 + 16, 1));
+    assert_eq!((h_r.bytes_sent(), h_r.messages_sent()), (2 + 16, 1));
+}
+
+#[test]
+fn test_max_message_len() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).max_message_len(100).build_responder().unwrap();
+
+    let mut buf = [0u8; 1024];
+    let mut buf2 = [0u8; 1024];
+    let len = h_i.write_message(&[0u8; 100], &mut buf).unwrap();
+    assert!(matches!(
+        h_r.read_message(&buf[..len], &mut buf2),
+        Err(snow::Error::MessageTooLarge { max: 100, got }) if got == len
+    ));
+    // Nothing was processed, so the handshake can carry on with a shorter message.
+    let mut h_i = Builder::new("Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap())
+        .build_initiator()
+        .unwrap();
+    let len = h_i.write_message(&[0u8; 10], &mut buf).unwrap();
+    h_r.read_message(&buf[..len], &mut buf2).unwrap();
+    let len = h_r.write_message(&[], &mut buf).unwrap();
+    h_i.read_message(&buf[..len], &mut buf2).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(&[0u8; 85], &mut buf).unwrap();
+    assert!(matches!(
+        h_r.read_message(&buf[..len], &mut buf2),
+        Err(snow::Error::MessageTooLarge { max: 100, got: 101 })
+    ));
+    assert_eq!(h_r.receiving_nonce(), 0);
+    h_r.set_receiving_nonce(1);
+    let len = h_i.write_message(&[0u8; 84], &mut buf).unwrap();
+    assert_eq!(h_r.read_message(&buf[..len], &mut buf2).unwrap(), 84);
+}
+
+#[test]
+fn test_set_psk() {
+    let params: NoiseParams = "Noise_XXpsk3_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
+    let mut h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+
+    let mut buf = [0u8; 1024];
+    let mut buf2 = [0u8; 1024];
+
+    let psk = get_inc_key(3);
+
+    // XX(s, rs):
+    // -> e
+    let len = h_i.write_message(&[], &mut buf).unwrap();
+    let _ = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+
+    // <- e, ee s, es
+    let len = h_r.write_message(&[], &mut buf).unwrap();
+    let _ = h_i.read_message(&buf[..len], &mut buf2).unwrap();
+
+    h_i.set_psk(3, &psk).unwrap();
+    h_r.set_psk(3, &psk).unwrap();
+
+    // -> s, se, psk
+    let len = h_i.write_message(&[], &mut buf).unwrap();
+    let _ = h_r.read_message(&buf[..len], &mut buf2).unwrap();
+}
+
+#[test]
+fn test_stateless_sanity_session() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
     let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
     h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
 
@@ -794,3 +1703,1654 @@ fn test_handshake_read_oob_error() {
     // This shouldn't panic, but it *should* return an error.
     let _ = h_i.read_message(&buffer_msg[..len], &mut buffer_out);
 }
+
+#[test]
+fn test_padded_session() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).padding(PaddingPolicy::Block(64)).build_initiator().unwrap();
+    let mut h_r = Builder::new(params)
+        .padding(PaddingPolicy::Buckets(vec![128, 1024]))
+        .build_responder()
+        .unwrap();
+
+    let mut buffer_msg = [0u8; 2048];
+    let mut buffer_out = [0u8; 2048];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    assert_eq!(len, 64 + 16);
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+
+    let len = h_r.write_message(&[7u8; 200], &mut buffer_msg).unwrap();
+    assert_eq!(len, 1024 + 16);
+    let len = h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], &[7u8; 200][..]);
+}
+
+#[test]
+fn test_padded_stateless_session() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).padding(PaddingPolicy::Random(32)).build_initiator().unwrap();
+    let mut h_r =
+        Builder::new(params).padding(PaddingPolicy::Random(32)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let h_i = h_i.into_stateless_transport_mode().unwrap();
+    let h_r = h_r.into_stateless_transport_mode().unwrap();
+
+    let len = h_i.write_message(1337, b"hack the planet", &mut buffer_msg).unwrap();
+    assert!((15 + 2 + 16..=15 + 2 + 32 + 16).contains(&len));
+    let len = h_r.read_message(1337, &buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+}
+
+#[test]
+fn test_noise_socket_retry() {
+    use snow::socket::{self, HandshakeFrame, Negotiation};
+
+    let offered: NoiseParams = "Noise_NN_25519_AESGCM_SHA256".parse().unwrap();
+    let retried: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut noise_msg = [0u8; 200];
+    let mut offer_buf = [0u8; 200];
+    let mut reply_buf = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+
+    // The initiator offers AESGCM.
+    let prologue = socket::initial_prologue(b"offer").unwrap();
+    let mut h_i = Builder::new(offered).prologue(&prologue).build_initiator().unwrap();
+    let len = h_i.write_message(&[], &mut noise_msg).unwrap();
+    let len = HandshakeFrame::new(b"offer", &noise_msg[..len]).write(&mut offer_buf).unwrap();
+
+    // The responder only speaks ChaChaPoly, so it asks for a retry.
+    let (offer, _) = HandshakeFrame::read(&offer_buf[..len]).unwrap();
+    let decision = Negotiation::Retry(retried.clone());
+    assert!(!decision.has_noise_message());
+    let len = HandshakeFrame::new(b"retry", &[]).write(&mut reply_buf).unwrap();
+    let retry_prologue = socket::retry_prologue(&offer, b"retry").unwrap();
+    let mut h_r =
+        Builder::new(retried.clone()).prologue(&retry_prologue).build_responder().unwrap();
+
+    // The initiator restarts with the protocol it was asked to use.
+    let (reply, _) = HandshakeFrame::read(&reply_buf[..len]).unwrap();
+    assert!(reply.noise_message.is_empty());
+    let (offer, _) = HandshakeFrame::read(&offer_buf).unwrap();
+    let prologue = socket::retry_prologue(&offer, reply.negotiation_data).unwrap();
+    let mut h_i = Builder::new(retried).prologue(&prologue).build_initiator().unwrap();
+
+    let len = h_i.write_message(&[], &mut noise_msg).unwrap();
+    let len = HandshakeFrame::new(&[], &noise_msg[..len]).write(&mut offer_buf).unwrap();
+    let (frame, _) = HandshakeFrame::read(&offer_buf[..len]).unwrap();
+    h_r.read_message(frame.noise_message, &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(&[], &mut noise_msg).unwrap();
+    h_i.read_message(&noise_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut noise_msg).unwrap();
+    let len = socket::write_transport_frame(&noise_msg[..len], &mut offer_buf).unwrap();
+    let (message, _) = socket::read_transport_frame(&offer_buf[..len]).unwrap();
+    let len = h_r.read_message(message, &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+}
+
+#[test]
+fn test_prologue_mismatch() {
+    use snow::{prologue::PrologueBuilder, Error};
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let ours = PrologueBuilder::new(b"test").protocol(&params.name).version(2).build();
+    let theirs = PrologueBuilder::new(b"test").protocol(&params.name).version(1).build();
+    let mut h_i = Builder::new(params.clone()).prologue(ours.as_bytes()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).prologue(theirs.as_bytes()).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    assert!(matches!(h_i.read_message(&buffer_msg[..len], &mut buffer_out), Err(Error::Decrypt)));
+    assert!(matches!(ours.verify(theirs.as_bytes()), Err(Error::Prologue)));
+}
+
+#[test]
+fn test_transcript_tampering() {
+    use snow::{prologue::Transcript, Error};
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut client = Transcript::new(b"test", true);
+    let mut server = Transcript::new(b"test", false);
+    client.sent(b"versions 1 2").received(b"version 1");
+    // The server saw a version list with the newest version stripped.
+    server.received(b"versions 1").sent(b"version 1");
+
+    let (ours, theirs) = (client.prologue(), server.prologue());
+    let mut h_i = Builder::new(params.clone()).prologue(ours.as_bytes()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).prologue(theirs.as_bytes()).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    assert!(matches!(h_i.read_message(&buffer_msg[..len], &mut buffer_out), Err(Error::Decrypt)));
+    assert!(matches!(ours.verify(theirs.as_bytes()), Err(Error::Prologue)));
+}
+
+#[test]
+fn test_sig_xx_session() {
+    let params: NoiseParams = "Noise_XXsig_25519+Ed25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let builder_i = Builder::new(params.clone());
+    let builder_r = Builder::new(params);
+    let static_i = builder_i.generate_keypair().unwrap();
+    let static_r = builder_r.generate_keypair().unwrap();
+    let mut h_i = builder_i.local_private_key(&static_i.private).build_initiator().unwrap();
+    let mut h_r = builder_r.local_private_key(&static_r.private).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 1024];
+    let mut buffer_out = [0u8; 1024];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_i.write_message(b"", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    assert_eq!(h_i.get_remote_static().unwrap(), &static_r.public[..]);
+    assert_eq!(h_r.get_remote_static().unwrap(), &static_i.public[..]);
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    assert_eq!(h_i.get_remote_static().unwrap(), &static_r.public[..]);
+
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+}
+
+#[test]
+fn test_sig_tampered_signature() {
+    let params: NoiseParams = "Noise_NXsig_25519+Ed25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let builder_r = Builder::new(params.clone());
+    let static_r = builder_r.generate_keypair().unwrap();
+    let mut h_i = Builder::new(params).build_initiator().unwrap();
+    let mut h_r = builder_r.local_private_key(&static_r.private).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 1024];
+    let mut buffer_out = [0u8; 1024];
+    let len = h_i.write_message(b"", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    // Flip a bit in the encrypted signature, which follows e (32) and the encrypted s (32 + 16).
+    let len = h_r.write_message(b"", &mut buffer_msg).unwrap();
+    buffer_msg[32 + 48] ^= 1;
+    assert!(h_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+}
+
+#[test]
+fn test_sig_unsupported_pattern() {
+    let params: NoiseParams = "Noise_IKsig_25519+Ed25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    assert!(Builder::new(params).build_initiator().is_err());
+}
+
+#[test]
+#[cfg(feature = "disco")]
+fn test_disco_session() {
+    let params: NoiseParams = "NoiseDisco_XX_25519_STROBEv1.0.2".parse().unwrap();
+    let builder_i = Builder::new(params.clone());
+    let builder_r = Builder::new(params.clone());
+    let static_i = builder_i.generate_keypair().unwrap();
+    let static_r = builder_r.generate_keypair().unwrap();
+    let mut h_i = builder_i.local_private_key(&static_i.private).build_initiator().unwrap();
+    let mut h_r = builder_r.local_private_key(&static_r.private).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 1024];
+    let mut buffer_out = [0u8; 1024];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    assert_eq!(len, 32 + 3);
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_i.write_message(b"", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    assert_eq!(len, 15 + 16);
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+
+    h_i.rekey_outgoing();
+    h_r.rekey_incoming();
+    let len = h_i.write_message(b"again", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"again");
+    let payload = [IoSlice::new(b"and "), IoSlice::new(b"again")];
+    let len = h_i.write_message_vectored(&payload, &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"and again");
+
+    // Replaying a message desynchronizes the duplex, so it fails to authenticate.
+    let len = h_r.write_message(b"once", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert!(h_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+}
+
+#[test]
+#[cfg(feature = "disco")]
+fn test_disco_no_stateless_transport() {
+    let params: NoiseParams = "NoiseDisco_NN_25519_STROBEv1.0.2".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(b"", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    assert!(h_i.into_stateless_transport_mode().is_err());
+}
+
+#[test]
+#[cfg(feature = "disco")]
+fn test_disco_no_export() {
+    let params: NoiseParams = "NoiseDisco_NN_25519_STROBEv1.0.2".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params.clone()).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(b"", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let h_i = h_i.into_transport_mode().unwrap();
+    assert!(matches!(
+        h_i.export_session(),
+        Err(snow::Error::State(snow::error::StateProblem::UnexportableSession))
+    ));
+    assert!(Builder::new(params).import_session(&[1]).is_err());
+}
+
+#[test]
+#[cfg(feature = "risky-chaining-key")]
+fn test_dangerously_get_chaining_key() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    // The protocol name fits in a hash, so it's the initial chaining key as is, while the
+    // handshake hash has already taken in the (empty) prologue.
+    assert_eq!(h_i.dangerously_get_chaining_key(), b"Noise_NN_25519_ChaChaPoly_SHA256");
+    assert_ne!(h_i.get_handshake_hash(), h_i.dangerously_get_chaining_key());
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    assert_eq!(h_i.dangerously_get_chaining_key().len(), 32);
+    assert_eq!(h_i.dangerously_get_chaining_key(), h_r.dangerously_get_chaining_key());
+    assert_ne!(h_i.dangerously_get_chaining_key(), b"Noise_NN_25519_ChaChaPoly_SHA256");
+}
+
+#[test]
+#[cfg(feature = "risky-set-nonce")]
+fn test_dangerously_set_nonce() {
+    let params: NoiseParams = "Noise_NNpsk0_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).psk(0, &[32u8; 32]).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).psk(0, &[32u8; 32]).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    assert_eq!(h_i.dangerously_get_nonce(), 1);
+
+    h_i.dangerously_set_nonce(7);
+    assert_eq!(h_i.dangerously_get_nonce(), 7);
+    h_i.dangerously_set_nonce(1);
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let len = h_r.write_message(b"", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    h_i.dangerously_set_sending_nonce(42);
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    assert_eq!(h_i.sending_nonce(), 43);
+    assert!(h_r.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+    h_r.set_receiving_nonce(42);
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+}
+
+#[test]
+fn test_static_dh_cache() {
+    use snow::dh_cache::StaticDhCache;
+    use std::sync::Arc;
+
+    let params: NoiseParams = "Noise_KK_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let pub_i = x25519::x25519(get_inc_key(0), x25519::X25519_BASEPOINT_BYTES);
+    let pub_r = x25519::x25519(get_inc_key(1), x25519::X25519_BASEPOINT_BYTES);
+    let cache = Arc::new(StaticDhCache::new(16));
+
+    let mut hashes = vec![];
+    for _ in 0..2 {
+        let mut h_i = Builder::new(params.clone())
+            .local_private_key(&get_inc_key(0))
+            .remote_public_key(&pub_r)
+            .static_dh_cache(cache.clone())
+            .build_initiator()
+            .unwrap();
+        let mut h_r = Builder::new(params.clone())
+            .local_private_key(&get_inc_key(1))
+            .remote_public_key(&pub_i)
+            .static_dh_cache(cache.clone())
+            .build_responder()
+            .unwrap();
+
+        let mut buffer_msg = [0u8; 200];
+        let mut buffer_out = [0u8; 200];
+        let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+        h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+        hashes.push(h_i.get_handshake_hash().to_vec());
+    }
+    assert_ne!(hashes[0], hashes[1]);
+    // One entry for each side's view of the pair of static keys.
+    assert_eq!(cache.len(), 2);
+    assert_eq!(format!("{:?}", cache), "StaticDhCache { len: 2, capacity: 16, .. }");
+    cache.forget(&pub_r);
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_precomputed_ss() {
+    let params: NoiseParams = "Noise_KK_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let pub_i = x25519::x25519(get_inc_key(0), x25519::X25519_BASEPOINT_BYTES);
+    let pub_r = x25519::x25519(get_inc_key(1), x25519::X25519_BASEPOINT_BYTES);
+    let ss = x25519::x25519(get_inc_key(0), pub_r);
+
+    for (ss, ok) in [(ss, true), ([7u8; 32], false)] {
+        let mut h_i = Builder::new(params.clone())
+            .local_private_key(&get_inc_key(0))
+            .remote_public_key(&pub_r)
+            .precomputed_ss(&ss)
+            .build_initiator()
+            .unwrap();
+        let mut h_r = Builder::new(params.clone())
+            .local_private_key(&get_inc_key(1))
+            .remote_public_key(&pub_i)
+            .build_responder()
+            .unwrap();
+
+        let mut buffer_msg = [0u8; 200];
+        let mut buffer_out = [0u8; 200];
+        let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+        assert_eq!(h_r.read_message(&buffer_msg[..len], &mut buffer_out).is_ok(), ok);
+    }
+
+    let err = Builder::new(params)
+        .local_private_key(&get_inc_key(0))
+        .remote_public_key(&pub_r)
+        .precomputed_ss(&[0u8; 16])
+        .build_initiator()
+        .unwrap_err();
+    assert!(matches!(err, snow::Error::Init(snow::error::InitStage::ValidateKeyLengths)));
+}
+
+#[test]
+fn test_private_key_lengths_checked() {
+    for name in
+        ["Noise_XX_25519_ChaChaPoly_BLAKE2s", "Noise_XXsig_25519+Ed25519_ChaChaPoly_BLAKE2s"]
+    {
+        let params: NoiseParams = name.parse().unwrap();
+        for len in [0, 31, 33, 64] {
+            let key = vec![1u8; len];
+            let err = Builder::new(params.clone()).local_private_key(&key).build_initiator();
+            assert!(matches!(
+                err,
+                Err(snow::Error::Init(snow::error::InitStage::ValidateKeyLengths))
+            ));
+            let err = Builder::new(params.clone())
+                .local_private_key(&[1u8; 32])
+                .fixed_ephemeral_key_for_testing_only(&key)
+                .build_initiator();
+            assert!(matches!(
+                err,
+                Err(snow::Error::Init(snow::error::InitStage::ValidateKeyLengths))
+            ));
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "legacy-psk")]
+fn test_legacy_psk() {
+    let params: NoiseParams = "NoisePSK_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let build = |psk: &[u8], initiator: bool| {
+        let builder = Builder::new(params.clone()).psk(0, psk);
+        if initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        }
+    };
+    let mut buf = [0u8; 1024];
+    let mut out = [0u8; 1024];
+
+    let mut h_i = build(&[1; 32], true).unwrap();
+    let mut h_r = build(&[1; 32], false).unwrap();
+    assert!(h_i.next_message_will_encrypt_payload());
+    let len = h_i.write_message(b"abc", &mut buf).unwrap();
+    assert_eq!(len, 32 + 3 + 16);
+    let len = h_r.read_message(&buf[..len], &mut out).unwrap();
+    assert_eq!(&out[..len], b"abc");
+    let len = h_r.write_message(b"defg", &mut buf).unwrap();
+    let len = h_i.read_message(&buf[..len], &mut out).unwrap();
+    assert_eq!(&out[..len], b"defg");
+    assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+
+    let mut h_i = build(&[1; 32], true).unwrap();
+    let mut h_r = build(&[2; 32], false).unwrap();
+    let len = h_i.write_message(b"abc", &mut buf).unwrap();
+    assert!(matches!(h_r.read_message(&buf[..len], &mut out), Err(snow::Error::Decrypt)));
+
+    assert!(matches!(
+        Builder::new(params.clone()).build_initiator(),
+        Err(snow::Error::Prereq(snow::error::Prerequisite::Psk(0)))
+    ));
+    assert!(matches!(
+        Builder::new(params.clone()).psk(1, &[1; 32]).build_initiator(),
+        Err(snow::Error::Init(snow::error::InitStage::ValidatePskPosition))
+    ));
+}
+
+#[test]
+fn test_handshake_reset() {
+    let params: NoiseParams = "Noise_XK_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let pub_r = x25519::x25519(get_inc_key(1), x25519::X25519_BASEPOINT_BYTES);
+    let mut h_i = Builder::new(params.clone())
+        .local_private_key(&get_inc_key(0))
+        .remote_public_key(&pub_r)
+        .build_initiator()
+        .unwrap();
+    let mut h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    // Abandon the first handshake partway through, then run two more on the same states.
+    let err = h_r.reset(true, b"prologue", None).unwrap_err();
+    assert!(matches!(
+        err,
+        snow::Error::PatternPrereq {
+            missing: snow::error::Prerequisite::RemotePublicKey,
+            initiator: true,
+            ..
+        }
+    ));
+    assert_eq!(h_r.pattern_position(), 1);
+    let mut hashes = vec![];
+    for _ in 0..2 {
+        h_i.reset(true, b"prologue", Some(&pub_r)).unwrap();
+        h_r.reset(false, b"prologue", None).unwrap();
+        assert!(h_i.is_my_turn() && !h_r.is_my_turn());
+        assert!(h_r.get_remote_static().is_none());
+
+        let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+        h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_i.write_message(b"hij", &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        assert!(h_i.is_handshake_finished() && h_r.is_handshake_finished());
+        assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+        hashes.push(h_i.get_handshake_hash().to_vec());
+    }
+    assert_ne!(hashes[0], hashes[1]);
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+}
+
+#[test]
+fn test_stateless_read_messages() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let h_i = h_i.into_stateless_transport_mode().unwrap();
+    let h_r = h_r.into_stateless_transport_mode().unwrap();
+
+    let mut sent = vec![];
+    for nonce in 0..8u64 {
+        let len = h_i.write_message(nonce, &[nonce as u8; 10], &mut buffer_msg).unwrap();
+        sent.push((nonce, buffer_msg[..len].to_vec()));
+    }
+    // Deliver the burst out of order, with one message replayed under the wrong nonce.
+    sent.reverse();
+    sent[3].0 = 42;
+    let messages: Vec<(u64, &[u8])> = sent.iter().map(|(n, m)| (*n, &m[..])).collect();
+    let mut outputs = vec![[0u8; 64]; messages.len()];
+    let mut output_refs: Vec<&mut [u8]> = outputs.iter_mut().map(|o| &mut o[..]).collect();
+
+    let results = h_r.read_messages(&messages, &mut output_refs).unwrap();
+    for (i, result) in results.into_iter().enumerate() {
+        if i == 3 {
+            assert!(matches!(result, Err(snow::Error::Decrypt)));
+        } else {
+            assert_eq!(result.unwrap(), 10);
+            assert_eq!(outputs[i][..10], [sent[i].0 as u8; 10]);
+        }
+    }
+
+    assert!(matches!(h_r.read_messages(&messages, &mut []), Err(snow::Error::Input)));
+}
+
+#[test]
+fn test_write_message_vectored() {
+    let payload = [IoSlice::new(b"hack "), IoSlice::new(b""), IoSlice::new(b"the planet")];
+    for cipher in ["ChaChaPoly", "AESGCM"] {
+        let params: NoiseParams = format!("Noise_NN_25519_{}_SHA256", cipher).parse().unwrap();
+        for padding in [None, Some(PaddingPolicy::Block(64))] {
+            let mut builder_i = Builder::new(params.clone());
+            let mut builder_r = Builder::new(params.clone());
+            if let Some(policy) = &padding {
+                builder_i = builder_i.padding(policy.clone());
+                builder_r = builder_r.padding(policy.clone());
+            }
+            let mut h_i = builder_i.build_initiator().unwrap();
+            let mut h_r = builder_r.build_responder().unwrap();
+
+            let mut buffer_msg = [0u8; 200];
+            let mut buffer_out = [0u8; 200];
+            let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+            h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+            let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+            h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+            let h_i = h_i.into_stateless_transport_mode().unwrap();
+            let h_r = h_r.into_stateless_transport_mode().unwrap();
+
+            let len = h_i.write_message_vectored(7, &payload, &mut buffer_msg).unwrap();
+            assert_eq!(len, if padding.is_some() { 64 + 16 } else { 15 + 16 });
+            let len = h_r.read_message(7, &buffer_msg[..len], &mut buffer_out).unwrap();
+            assert_eq!(&buffer_out[..len], b"hack the planet");
+
+            #[cfg(not(feature = "forbid-unsafe"))]
+            {
+                let mut uninit = [std::mem::MaybeUninit::uninit(); 200];
+                let sent = h_i.write_message_uninit(7, b"hack the planet", &mut uninit).unwrap();
+                let mut uninit_out = [std::mem::MaybeUninit::uninit(); 200];
+                let received = h_r.read_message_uninit(7, sent, &mut uninit_out).unwrap();
+                assert_eq!(received, b"hack the planet");
+            }
+
+            let mut in_place = [0u8; 200];
+            let len = h_i.write_message(9, b"hack the planet", &mut in_place).unwrap();
+            let received = h_r.read_message_in_place(9, &mut in_place[..len]).unwrap();
+            assert_eq!(&in_place[received], b"hack the planet");
+
+            if padding.is_none() {
+                let mut concatenated = [0u8; 200];
+                let len = h_i.write_message(7, b"hack the planet", &mut concatenated).unwrap();
+                assert_eq!(buffer_msg[..len], concatenated[..len]);
+            }
+            assert!(matches!(
+                h_i.write_message_vectored(8, &payload, &mut buffer_msg[..20]),
+                Err(snow::Error::BufferTooSmall { .. })
+            ));
+        }
+    }
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_bytes_transport() {
+    use bytes::{BufMut, BytesMut};
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    // Append after a length prefix, as a framed protocol would.
+    let mut frame = BytesMut::new();
+    frame.put_u16(15 + 16);
+    let len = h_i.write_message_bytes(b"hack the planet", &mut frame).unwrap();
+    assert_eq!((len, frame.len()), (15 + 16, 2 + 15 + 16));
+    let message = frame.split_off(2).freeze();
+    assert_eq!(&h_r.read_message_bytes(&message).unwrap()[..], b"hack the planet");
+
+    assert!(h_r.read_message_bytes(&message).is_err());
+    let mut frame = BytesMut::new();
+    assert!(h_r.write_message_bytes(&[0u8; 70000], &mut frame).is_err());
+    assert!(frame.is_empty());
+}
+
+#[test]
+#[cfg(not(feature = "forbid-unsafe"))]
+fn test_uninit_buffers() {
+    use std::mem::MaybeUninit;
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).padding(PaddingPolicy::Block(32)).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).padding(PaddingPolicy::Block(32)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    let mut message = vec![MaybeUninit::<u8>::uninit(); 65535];
+    let mut payload = vec![MaybeUninit::<u8>::uninit(); 65535];
+    let sent = h_i.write_message_uninit(b"hack the planet", &mut message).unwrap();
+    assert_eq!(sent.len(), 32 + 16);
+    let received = h_r.read_message_uninit(sent, &mut payload).unwrap();
+    assert_eq!(received, b"hack the planet");
+
+    let mut small = [MaybeUninit::<u8>::uninit(); 20];
+    assert!(matches!(
+        h_i.write_message_uninit(b"hack the planet", &mut small),
+        Err(snow::Error::BufferTooSmall { needed: 48, got: 20 })
+    ));
+}
+
+#[test]
+fn test_read_message_in_place() {
+    for cipher in ["ChaChaPoly", "AESGCM"] {
+        let params: NoiseParams = format!("Noise_NN_25519_{}_SHA256", cipher).parse().unwrap();
+        for padding in [None, Some(PaddingPolicy::Block(64))] {
+            let mut builder_i = Builder::new(params.clone());
+            let mut builder_r = Builder::new(params.clone());
+            if let Some(policy) = &padding {
+                builder_i = builder_i.padding(policy.clone());
+                builder_r = builder_r.padding(policy.clone());
+            }
+            let mut h_i = builder_i.build_initiator().unwrap();
+            let mut h_r = builder_r.build_responder().unwrap();
+
+            let mut buffer_msg = [0u8; 200];
+            let mut buffer_out = [0u8; 200];
+            let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+            h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+            let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+            h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+            let mut h_i = h_i.into_transport_mode().unwrap();
+            let mut h_r = h_r.into_transport_mode().unwrap();
+
+            let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+            let payload = h_r.read_message_in_place(&mut buffer_msg[..len]).unwrap();
+            assert_eq!(&buffer_msg[payload], b"hack the planet");
+
+            let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+            buffer_msg[0] ^= 1;
+            assert!(matches!(
+                h_r.read_message_in_place(&mut buffer_msg[..len]),
+                Err(snow::Error::Decrypt)
+            ));
+            assert!(matches!(
+                h_r.read_message_in_place(&mut buffer_msg[..10]),
+                Err(snow::Error::TruncatedMessage { needed: 16, got: 10 })
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_typestate_handshake() {
+    use snow::typestate::Phase;
+
+    let params: NoiseParams = "Noise_XK_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let pub_r = x25519::x25519(get_inc_key(1), x25519::X25519_BASEPOINT_BYTES);
+    let h_i = Builder::new(params.clone())
+        .local_private_key(&get_inc_key(0))
+        .remote_public_key(&pub_r)
+        .build_typed_initiator()
+        .unwrap();
+    let h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_typed_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+
+    // A failed step hands the handshake back untouched.
+    let failure = h_i.write_message(b"abc", &mut buffer_msg[..10]).unwrap_err();
+    assert!(matches!(failure.error, snow::Error::BufferTooSmall { .. }));
+    let (len, h_i) = failure.handshake.write_message(b"abc", &mut buffer_msg).unwrap();
+    let (_, h_r) = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let (len, h_r) = h_r.expect_continue().write_message(b"defg", &mut buffer_msg).unwrap();
+    let (_, h_i) = h_i.expect_continue().read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let (len, h_i) = h_i.expect_continue().write_message(b"hij", &mut buffer_msg).unwrap();
+    assert!(h_i.is_finished() && !h_r.is_finished());
+    let (len, h_r) =
+        h_r.expect_continue().read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hij");
+
+    let (h_i, h_r) = (h_i.expect_finished(), h_r.expect_finished());
+    assert_eq!(h_i.state().get_handshake_hash(), h_r.state().get_handshake_hash());
+    let mut h_i = h_i.into_transport_mode();
+    let mut h_r = h_r.into_transport_mode();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let h_r = Builder::new(params).build_responder().unwrap();
+    assert!(matches!(Phase::from(h_r), Phase::Reading(_)));
+}
+
+#[test]
+fn test_build_rejects_long_protocol_name() {
+    let mut params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    params.name = "x".repeat(256);
+    assert!(matches!(
+        Builder::new(params).build_initiator(),
+        Err(snow::Error::Pattern(snow::error::PatternProblem::ProtocolNameTooLong))
+    ));
+}
+
+#[test]
+fn test_resumption_psk() {
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
+    let mut h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert!(matches!(
+        h_r.resumption_psk(),
+        Err(snow::Error::State(snow::error::StateProblem::HandshakeNotFinished))
+    ));
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let psk = h_i.resumption_psk().unwrap();
+    assert_eq!(psk, h_r.resumption_psk().unwrap());
+    assert_ne!(&psk[..], &h_i.get_handshake_hash()[..32]);
+
+    let params: NoiseParams = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).psk(0, &psk).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).psk(0, &psk).build_responder().unwrap();
+    let len = h_i.write_message(b"resumed", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"resumed");
+}
+
+#[test]
+fn test_early_data() {
+    use snow::error::StateProblem;
+
+    let pub_r = x25519::x25519(get_inc_key(1), x25519::X25519_BASEPOINT_BYTES);
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    for (pattern, authenticated) in &[("IK", true), ("NK", false), ("NKpsk0", true)] {
+        let params: NoiseParams =
+            format!("Noise_{}_25519_ChaChaPoly_BLAKE2s", pattern).parse().unwrap();
+        let (psk, key_i, key_r) = ([3u8; 32], get_inc_key(0), get_inc_key(1));
+        let mut b_i =
+            Builder::new(params.clone()).local_private_key(&key_i).remote_public_key(&pub_r);
+        let mut b_r = Builder::new(params.clone()).local_private_key(&key_r);
+        if params.handshake.is_psk() {
+            b_i = b_i.psk(0, &psk);
+            b_r = b_r.psk(0, &psk);
+        }
+        let (mut h_i, mut h_r) = (b_i.build_initiator().unwrap(), b_r.build_responder().unwrap());
+
+        let len = h_i.write_early_data(b"GET /", &mut buffer_msg).unwrap();
+        let early = h_r.read_early_data(&buffer_msg[..len], &mut buffer_out).unwrap();
+        assert_eq!(&buffer_out[..early.len], b"GET /");
+        assert_eq!(early.sender_authenticated, *authenticated, "{}", pattern);
+
+        let err = h_r.write_early_data(b"nope", &mut buffer_msg).unwrap_err();
+        assert!(matches!(err, snow::Error::State(StateProblem::EarlyDataUnavailable)));
+    }
+
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
+    let mut h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+    let err = h_i.write_early_data(b"GET /", &mut buffer_msg).unwrap_err();
+    assert!(matches!(err, snow::Error::State(StateProblem::EarlyDataUnavailable)));
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    let err = h_r.read_early_data(&buffer_msg[..len], &mut buffer_out).unwrap_err();
+    assert!(matches!(err, snow::Error::State(StateProblem::EarlyDataUnavailable)));
+}
+
+#[test]
+fn test_message_with_ad() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    let len = h_i.write_message_with_ad(b"route 1", b"hack", &mut buffer_msg).unwrap();
+    assert!(matches!(
+        h_r.read_message_with_ad(b"route 2", &buffer_msg[..len], &mut buffer_out),
+        Err(snow::Error::Decrypt)
+    ));
+    h_r.set_receiving_nonce(0);
+    let len = h_r.read_message_with_ad(b"route 1", &buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack");
+
+    // An empty AD is the same as none at all.
+    let len = h_r.write_message_with_ad(&[], b"the planet", &mut buffer_msg).unwrap();
+    let len = h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"the planet");
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_events() {
+    use std::sync::{Arc, Mutex};
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    /// Collects the fields of every event as `name=value` strings.
+    #[derive(Default)]
+    struct Collector(Arc<Mutex<Vec<String>>>);
+
+    struct FieldVisitor(String);
+
+    impl Visit for FieldVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!("{}={:?} ", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut visitor = FieldVisitor(String::new());
+            event.record(&mut visitor);
+            self.0.lock().unwrap().push(visitor.0);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let collector = Collector(events.clone());
+    tracing::subscriber::with_default(collector, || {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+        let mut h_i = Builder::new(params.clone()).build_initiator().unwrap();
+        let mut h_r = Builder::new(params).build_responder().unwrap();
+        let mut buffer_msg = [0u8; 200];
+        let mut buffer_out = [0u8; 200];
+        h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..10], &mut buffer_out).unwrap_err();
+    });
+
+    let events = events.lock().unwrap();
+    assert!(events.iter().any(|e| e.contains("token=E")));
+    assert!(events.iter().any(|e| e.contains("message=handshake message written")));
+    assert!(events.iter().any(|e| e.contains("error=")));
+    // Nothing secret, like the payload, makes it into an event.
+    assert!(!events.iter().any(|e| e.contains("abc")));
+}
+
+#[test]
+fn test_debug_is_redacted() {
+    let params: NoiseParams = "Noise_XXpsk3_25519_ChaChaPoly_SHA256".parse().unwrap();
+    let static_i = Builder::new(params.clone()).generate_keypair().unwrap();
+    let static_r = Builder::new(params.clone()).generate_keypair().unwrap();
+    let psk = [42u8; 32];
+    let secrets = [format!("{:?}", static_i.private), format!("{:?}", &psk[..])];
+
+    let builder = Builder::new(params.clone()).local_private_key(&static_i.private).psk(3, &psk);
+    let debug = format!("{:?}", builder);
+    assert!(debug.contains("s: true") && debug.contains("psks: [3]"), "{}", debug);
+    let mut h_i = builder.build_initiator().unwrap();
+    let mut h_r = Builder::new(params)
+        .local_private_key(&static_r.private)
+        .psk(3, &psk)
+        .build_responder()
+        .unwrap();
+
+    let debug = format!("{:?}", h_i);
+    assert!(debug.contains("Noise_XXpsk3_25519_ChaChaPoly_SHA256"), "{}", debug);
+    assert!(debug.contains("pattern_position: 0") && debug.contains("rs: false"), "{}", debug);
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    h_i.write_message(b"hack", &mut buffer_msg).unwrap();
+    let debug = format!("{:?}", h_i);
+    assert!(debug.contains("sending_nonce: 1") && debug.contains("rs: true"), "{}", debug);
+    for debug in &[debug, format!("{:?}", h_r)] {
+        for secret in &secrets {
+            assert!(!debug.contains(&secret[1..secret.len() - 1]), "{}", debug);
+        }
+    }
+}
+
+#[test]
+fn test_session_observer() {
+    use snow::observer::{Direction, SessionObserver};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl SessionObserver for Recorder {
+        fn handshake_started(&self, params: &NoiseParams, initiator: bool) {
+            self.0.lock().unwrap().push(format!("started {} {}", params.name, initiator));
+        }
+
+        fn handshake_completed(&self, _params: &NoiseParams, initiator: bool) {
+            self.0.lock().unwrap().push(format!("completed {}", initiator));
+        }
+
+        fn message_encrypted(&self, len: usize) {
+            self.0.lock().unwrap().push(format!("encrypted {}", len));
+        }
+
+        fn message_decrypted(&self, len: usize) {
+            self.0.lock().unwrap().push(format!("decrypted {}", len));
+        }
+
+        fn decrypt_failed(&self) {
+            self.0.lock().unwrap().push("decrypt failed".into());
+        }
+
+        fn rekeyed(&self, direction: Direction) {
+            self.0.lock().unwrap().push(format!("rekeyed {:?}", direction));
+        }
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut h_i =
+        Builder::new(params.clone()).observer(recorder.clone()).build_initiator().unwrap();
+    let mut h_r = Builder::new(params).observer(recorder.clone()).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    buffer_msg[len - 1] ^= 1;
+    assert!(h_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+    buffer_msg[len - 1] ^= 1;
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    buffer_msg[0] ^= 1;
+    assert!(h_r.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+    h_i.rekey_outgoing();
+    h_r.rekey_initiator_manually(&[1u8; 32]);
+
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        vec![
+            "started Noise_NN_25519_ChaChaPoly_BLAKE2s true",
+            "started Noise_NN_25519_ChaChaPoly_BLAKE2s false",
+            "completed false",
+            "decrypt failed",
+            "completed true",
+            "encrypted 31",
+            "decrypted 31",
+            "encrypted 31",
+            "decrypt failed",
+            "rekeyed Outgoing",
+            "rekeyed Incoming",
+        ]
+    );
+}
+
+#[test]
+fn test_pinning() {
+    use snow::{
+        error::StateProblem,
+        pinning::{KnownHosts, PinPolicy, PinStore},
+        Error,
+    };
+    use std::sync::Arc;
+
+    let params: NoiseParams = "Noise_NX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let server_key = Builder::new(params.clone()).generate_keypair().unwrap();
+    let impostor_key = Builder::new(params.clone()).generate_keypair().unwrap();
+    let known_hosts = Arc::new(KnownHosts::new());
+
+    let handshake = |server_private: &[u8], policy: PinPolicy| -> Result<(), Error> {
+        let mut h_i = Builder::new(params.clone())
+            .pinning(known_hosts.clone(), "server", policy)
+            .build_initiator()?;
+        let mut h_r =
+            Builder::new(params.clone()).local_private_key(server_private).build_responder()?;
+        let mut buffer_msg = [0u8; 200];
+        let mut buffer_out = [0u8; 200];
+        let len = h_i.write_message(&[], &mut buffer_msg)?;
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out)?;
+        let len = h_r.write_message(&[], &mut buffer_msg)?;
+        h_i.read_message(&buffer_msg[..len], &mut buffer_out)?;
+        Ok(())
+    };
+
+    assert!(matches!(
+        handshake(&server_key.private, PinPolicy::Strict),
+        Err(Error::Handshake { token: Token::S, source, .. })
+            if matches!(*source, Error::State(StateProblem::NotPinned))
+    ));
+    assert!(known_hosts.is_empty());
+    handshake(&server_key.private, PinPolicy::TrustOnFirstUse).unwrap();
+    assert_eq!(known_hosts.pinned_key("server"), Some(server_key.public.clone()));
+    handshake(&server_key.private, PinPolicy::Strict).unwrap();
+    assert!(matches!(
+        handshake(&impostor_key.private, PinPolicy::TrustOnFirstUse),
+        Err(Error::Handshake { token: Token::S, source, .. })
+            if matches!(*source, Error::State(StateProblem::PinMismatch))
+    ));
+    assert_eq!(known_hosts.pinned_key("server"), Some(server_key.public));
+}
+
+#[test]
+fn test_fixed_handshake() {
+    use snow::{
+        error::Prerequisite,
+        fixed::{FixedHandshakeState, FixedKeys, FixedMessage},
+        Error,
+    };
+
+    let params: NoiseParams = "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let resolver = DefaultResolver;
+    let mut rng = resolver.resolve_rng().unwrap();
+    let static_i = Builder::new(params.clone()).generate_keypair().unwrap();
+    let static_r = Builder::new(params.clone()).generate_keypair().unwrap();
+    let psk = [7u8; 32];
+
+    let mut dh = |private: Option<&[u8]>| {
+        let mut dh = resolver.resolve_dh(&params.dh).unwrap();
+        match private {
+            Some(private) => dh.set(private),
+            None => dh.generate(&mut *rng).unwrap(),
+        }
+        dh
+    };
+    let keys = FixedKeys {
+        local_static:  Some(dh(Some(&static_i.private))),
+        ephemeral:     dh(None),
+        remote_static: None,
+    };
+    let cipher = || resolver.resolve_cipher(&params.cipher).unwrap();
+    let hash = || resolver.resolve_hash(&params.hash).unwrap();
+    assert!(matches!(
+        FixedHandshakeState::new(
+            &params,
+            true,
+            keys,
+            [cipher(), cipher(), cipher()],
+            hash(),
+            b"pro"
+        ),
+        Err(Error::PatternPrereq { missing: Prerequisite::RemotePublicKey, .. })
+    ));
+
+    let keys = FixedKeys {
+        local_static:  Some(dh(Some(&static_i.private))),
+        ephemeral:     dh(None),
+        remote_static: Some(&static_r.public),
+    };
+    let mut h_i = FixedHandshakeState::new(
+        &params,
+        true,
+        keys,
+        [cipher(), cipher(), cipher()],
+        hash(),
+        b"pro",
+    )
+    .unwrap();
+    let mut h_r = Builder::new(params.clone())
+        .local_private_key(&static_r.private)
+        .psk(2, &psk)
+        .prologue(b"pro")
+        .build_responder()
+        .unwrap();
+
+    assert!(matches!(h_i.write_fixed::<16>(b"abc"), Err(Error::BufferTooSmall { .. })));
+    let message: FixedMessage<128> = h_i.write_fixed(b"abc").unwrap();
+    let mut payload = [0u8; 128];
+    let len = h_r.read_message(&message, &mut payload).unwrap();
+    assert_eq!(&payload[..len], b"abc");
+
+    let mut buffer = [0u8; 128];
+    let len = h_r.write_message(b"defg", &mut buffer).unwrap();
+    assert!(matches!(
+        h_i.read_fixed::<128>(&buffer[..len]),
+        Err(Error::Handshake { message: 1, token: Token::Psk(2), source })
+            if matches!(*source, Error::Prereq(Prerequisite::Psk(2)))
+    ));
+    h_i.set_psk(2, &psk).unwrap();
+    let mut tampered = buffer;
+    tampered[len - 1] ^= 1;
+    assert!(matches!(h_i.read_fixed::<128>(&tampered[..len]), Err(Error::Decrypt)));
+    let received: FixedMessage<128> = h_i.read_fixed(&buffer[..len]).unwrap();
+    assert_eq!(&received[..], b"defg");
+    assert!(h_i.is_handshake_finished());
+    assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+    assert_eq!(h_i.get_remote_static(), Some(&static_r.public[..]));
+
+    let mut t_i = h_i.into_transport().unwrap();
+    let mut t_r = h_r.into_transport_mode().unwrap();
+    let message: FixedMessage<64> = t_i.write_fixed(b"hello").unwrap();
+    let len = t_r.read_message(&message, &mut payload).unwrap();
+    assert_eq!(&payload[..len], b"hello");
+    let len = t_r.write_message(b"world", &mut buffer).unwrap();
+    let payload: FixedMessage<64> = t_i.read_fixed(&buffer[..len]).unwrap();
+    assert_eq!(&payload[..], b"world");
+    assert_eq!((t_i.sending_nonce(), t_i.receiving_nonce()), (1, 1));
+}
+
+#[test]
+fn test_core_handshake() {
+    use snow::{CorePrimitives, Error};
+
+    let params: NoiseParams = "Noise_XX_25519_AESGCM_SHA256".parse().unwrap();
+    let resolver = DefaultResolver;
+    let primitives = || CorePrimitives {
+        local_static: resolver.resolve_dh(&params.dh).unwrap(),
+        ephemeral:    resolver.resolve_dh(&params.dh).unwrap(),
+        ciphers:      [(); 3].map(|_| resolver.resolve_cipher(&params.cipher).unwrap()),
+        hash:         resolver.resolve_hash(&params.hash).unwrap(),
+    };
+    let static_i = Builder::new(params.clone()).generate_keypair().unwrap();
+    let static_r = Builder::new(params.clone()).generate_keypair().unwrap();
+
+    let mut mismatched = primitives();
+    mismatched.hash = resolver.resolve_hash(&HashChoice::Blake2s).unwrap();
+    assert!(matches!(
+        Builder::new(params.clone()).build_core_initiator(mismatched),
+        Err(Error::Input)
+    ));
+
+    let mut h_i = Builder::new(params.clone())
+        .local_private_key(&static_i.private)
+        .build_core_initiator(primitives())
+        .unwrap();
+    let mut h_r = Builder::new(params.clone())
+        .local_private_key(&static_r.private)
+        .build_responder()
+        .unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(b"defg", &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(b"", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(h_i.get_remote_static(), Some(&static_r.public[..]));
+    assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+
+    let mut t_i = h_i.into_transport_core().unwrap();
+    let mut t_r = h_r.into_transport_mode().unwrap();
+    let len = t_i.write_message(b"hello", &mut buffer_msg).unwrap();
+    let len = t_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hello");
+    t_i.rekey_incoming();
+    t_r.rekey_outgoing();
+    let len = t_r.write_message(b"world", &mut buffer_msg).unwrap();
+    let len = t_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"world");
+}
+
+#[test]
+fn test_write_retry_after_rekeying_token() {
+    use snow::Error;
+
+    // The last XX message encrypts `s` and then mixes in `se`, so a payload that doesn't fit has
+    // to roll the handshake cipher back to its key and nonce from before the message.
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let static_i = Builder::new(params.clone()).generate_keypair().unwrap();
+    let static_r = Builder::new(params.clone()).generate_keypair().unwrap();
+    let mut h_i = Builder::new(params.clone())
+        .local_private_key(&static_i.private)
+        .build_initiator()
+        .unwrap();
+    let mut h_r = Builder::new(params.clone())
+        .local_private_key(&static_r.private)
+        .build_responder()
+        .unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert!(matches!(
+        h_i.write_message(&[0u8; 100], &mut buffer_msg[..100]),
+        Err(Error::BufferTooSmall { .. })
+    ));
+    let len = h_i.write_message(b"abc", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"abc");
+}
+
+#[test]
+fn test_handshake_tokens() {
+    use std::convert::TryFrom;
+
+    let params: NoiseParams = "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let tokens = HandshakeTokens::try_from(&params.handshake).unwrap();
+    assert!(tokens.premsg_pattern_i.is_empty());
+    assert_eq!(tokens.premsg_pattern_r, [Token::S]);
+    assert_eq!(
+        tokens.msg_patterns,
+        [
+            vec![Token::E, Token::Dh(DhToken::Es), Token::S, Token::Dh(DhToken::Ss)],
+            vec![Token::E, Token::Dh(DhToken::Ee), Token::Dh(DhToken::Se), Token::Psk(2)],
+        ]
+    );
+    let rendered: Vec<_> = tokens.msg_patterns[1].iter().map(Token::to_string).collect();
+    assert_eq!(rendered, ["e", "ee", "se", "psk2"]);
+}
+
+#[test]
+fn test_message_tokens() {
+    use snow::TokenView;
+
+    let params: NoiseParams = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut h_i = Builder::new(params.clone())
+        .local_private_key(&get_inc_key(0))
+        .psk(3, &get_inc_key(2))
+        .build_initiator()
+        .unwrap();
+    let mut h_r = Builder::new(params)
+        .local_private_key(&get_inc_key(1))
+        .psk(3, &get_inc_key(2))
+        .build_responder()
+        .unwrap();
+
+    let token = |token, encrypted| TokenView::Token { token, encrypted };
+    let messages = h_i.message_tokens();
+    assert_eq!(messages, h_r.message_tokens());
+    assert_eq!(messages[0], [token(Token::E, false), TokenView::Payload { encrypted: true }]);
+    assert_eq!(messages[1][2], token(Token::S, true));
+    assert_eq!(
+        messages[2],
+        [
+            token(Token::S, true),
+            token(Token::Dh(DhToken::Se), false),
+            token(Token::Psk(3), false),
+            TokenView::Payload { encrypted: true },
+        ]
+    );
+
+    // The schedule agrees with what the handshake actually does.
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    while !h_i.is_handshake_finished() {
+        let encrypted = matches!(
+            h_i.message_tokens()[h_i.pattern_position()].last(),
+            Some(TokenView::Payload { encrypted: true })
+        );
+        assert_eq!(encrypted, h_i.next_message_will_encrypt_payload());
+        let (sender, receiver) =
+            if h_i.is_my_turn() { (&mut h_i, &mut h_r) } else { (&mut h_r, &mut h_i) };
+        let len = sender.write_message(&[], &mut buffer_msg).unwrap();
+        receiver.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    }
+}
+
+#[test]
+fn test_local_key_selector() {
+    use snow::{error::Prerequisite, Keypair};
+    use std::sync::Arc;
+
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let keypair = |i| Keypair {
+        private: get_inc_key(i).to_vec(),
+        public:  x25519::x25519(get_inc_key(i), x25519::X25519_BASEPOINT_BYTES).to_vec(),
+    };
+    let selector = Arc::new(move |prologue: &[u8], payload: &[u8]| {
+        assert_eq!(prologue, b"selector");
+        match payload {
+            b"one" => Some(keypair(1)),
+            b"two" => Some(keypair(2)),
+            _ => None,
+        }
+    });
+    let responder = || {
+        Builder::new(params.clone())
+            .prologue(b"selector")
+            .local_key_selector(selector.clone())
+            .build_responder()
+            .unwrap()
+    };
+    let initiator = || {
+        Builder::new(params.clone())
+            .prologue(b"selector")
+            .local_private_key(&get_inc_key(0))
+            .build_initiator()
+            .unwrap()
+    };
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let (mut h_i, mut h_r) = (initiator(), responder());
+    assert!(h_r.get_local_static().is_none());
+    let len = h_i.write_message(b"two", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(h_i.get_remote_static(), Some(&keypair(2).public[..]));
+    assert_eq!(h_r.get_local_static(), Some(&keypair(2).public[..]));
+
+    // An unknown selector fails the first message.
+    let (mut h_i, mut h_r) = (initiator(), responder());
+    let len = h_i.write_message(b"three", &mut buffer_msg).unwrap();
+    assert!(matches!(
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out),
+        Err(snow::Error::Prereq(Prerequisite::LocalPrivateKey))
+    ));
+
+    // The responder's key can't wait when the initiator already knows it.
+    let result = Builder::new("Noise_IK_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+        .local_key_selector(selector.clone())
+        .build_responder();
+    assert!(matches!(
+        result,
+        Err(snow::Error::PatternPrereq { missing: Prerequisite::LocalPrivateKey, .. })
+    ));
+    let result =
+        Builder::new(params.clone()).local_key_selector(selector.clone()).build_initiator();
+    assert!(matches!(
+        result,
+        Err(snow::Error::PatternPrereq { missing: Prerequisite::LocalPrivateKey, .. })
+    ));
+
+    // A reset responder picks its key again, for the new handshake's payload.
+    let (mut h_i, mut h_r) = (initiator(), responder());
+    for (payload, selected) in &[(&b"one"[..], 1), (&b"two"[..], 2)] {
+        let len = h_i.write_message(payload, &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+        h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        assert_eq!(h_r.get_local_static(), Some(&keypair(*selected).public[..]));
+        assert_eq!(h_i.get_remote_static(), Some(&keypair(*selected).public[..]));
+
+        h_i.reset(true, b"selector", None).unwrap();
+        h_r.reset(false, b"selector", None).unwrap();
+        assert!(h_r.get_local_static().is_none());
+    }
+    // As an initiator, it has no key to use.
+    assert!(matches!(
+        h_r.reset(true, b"selector", None),
+        Err(snow::Error::PatternPrereq { missing: Prerequisite::LocalPrivateKey, .. })
+    ));
+}
+
+#[test]
+fn test_key_usage_tracker() {
+    use snow::{error::StateProblem, key_usage::KeyUsageTracker};
+    use std::sync::Arc;
+
+    let xx: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let ik: NoiseParams = "Noise_IK_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let key = get_inc_key(0);
+    let public = x25519::x25519(key, x25519::X25519_BASEPOINT_BYTES);
+    let tracker = Arc::new(KeyUsageTracker::new());
+    let builder = |params: &NoiseParams| {
+        Builder::new(params.clone()).local_private_key(&key).key_usage_tracker(tracker.clone())
+    };
+    let refused = |result: Result<snow::HandshakeState, snow::Error>| {
+        matches!(result, Err(snow::Error::State(StateProblem::KeyUsedInBothRoles)))
+    };
+
+    builder(&xx).build_responder().unwrap();
+    assert!(refused(builder(&xx).build_initiator()));
+    // Other protocols are tracked separately.
+    builder(&ik).remote_public_key(&public).build_initiator().unwrap();
+    assert!(refused(builder(&ik).build_responder()));
+    // Builders without the tracker aren't checked.
+    Builder::new(xx.clone()).local_private_key(&key).build_initiator().unwrap();
+
+    tracker.forget(&public);
+    builder(&xx).build_initiator().unwrap();
+    assert!(refused(builder(&xx).build_responder()));
+    tracker.allow_both_roles(&public);
+    builder(&xx).build_responder().unwrap();
+    builder(&xx).build_initiator().unwrap();
+}
+
+#[test]
+fn test_key_usage_tracker_reset() {
+    use snow::{error::StateProblem, key_usage::KeyUsageTracker};
+    use std::sync::Arc;
+
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let key = get_inc_key(0);
+    let tracker = Arc::new(KeyUsageTracker::new());
+    let mut h = Builder::new(params.clone())
+        .local_private_key(&key)
+        .key_usage_tracker(tracker.clone())
+        .build_responder()
+        .unwrap();
+
+    // Resetting into the same role is fine, but the other role is refused like a build would be.
+    h.reset(false, b"", None).unwrap();
+    assert!(matches!(
+        h.reset(true, b"", None),
+        Err(snow::Error::State(StateProblem::KeyUsedInBothRoles))
+    ));
+    assert!(!h.is_initiator());
+
+    // Without a tracker, the role can still be switched.
+    let mut h = Builder::new(params).local_private_key(&key).build_responder().unwrap();
+    h.reset(true, b"", None).unwrap();
+    assert!(h.is_initiator());
+}
+
+#[cfg(feature = "cipher-aegis")]
+#[test]
+fn test_aegis() {
+    for name in ["Noise_XX_25519_AEGIS128L_SHA256", "Noise_NN_25519_AEGIS256_BLAKE2s"] {
+        let params: NoiseParams = name.parse().unwrap();
+        assert_eq!(params.name, name);
+        let mut h_i = Builder::new(params.clone())
+            .local_private_key(&get_inc_key(0))
+            .build_initiator()
+            .unwrap();
+        let mut h_r = Builder::new(params.clone())
+            .local_private_key(&get_inc_key(1))
+            .build_responder()
+            .unwrap();
+
+        let mut buffer_msg = [0u8; 200];
+        let mut buffer_out = [0u8; 200];
+        while !(h_i.is_handshake_finished() && h_r.is_handshake_finished()) {
+            let (writer, reader) =
+                if h_i.is_my_turn() { (&mut h_i, &mut h_r) } else { (&mut h_r, &mut h_i) };
+            let len = writer.write_message(b"hello", &mut buffer_msg).unwrap();
+            assert_eq!(reader.read_message(&buffer_msg[..len], &mut buffer_out).unwrap(), 5);
+        }
+
+        let mut h_i = h_i.into_transport_mode().unwrap();
+        let mut h_r = h_r.into_transport_mode().unwrap();
+        let len = h_i.write_message(&[0x42; 100], &mut buffer_msg).unwrap();
+        assert_eq!(h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap(), 100);
+        assert_eq!(&buffer_out[..100], &[0x42; 100][..]);
+        let len = h_r.write_message(b"bye", &mut buffer_msg).unwrap();
+        buffer_msg[0] ^= 1;
+        assert!(matches!(
+            h_i.read_message(&buffer_msg[..len], &mut buffer_out),
+            Err(snow::Error::Decrypt)
+        ));
+    }
+}
+
+#[cfg(feature = "hash-ascon")]
+#[test]
+fn test_ascon_hash() {
+    let name = if cfg!(feature = "cipher-ascon") {
+        "Noise_XX_25519_Ascon128_AsconHash"
+    } else {
+        "Noise_XX_25519_ChaChaPoly_AsconHash"
+    };
+    let params: NoiseParams = name.parse().unwrap();
+    assert_eq!(params.name, name);
+    let mut h_i =
+        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
+    let mut h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    while !(h_i.is_handshake_finished() && h_r.is_handshake_finished()) {
+        let (writer, reader) =
+            if h_i.is_my_turn() { (&mut h_i, &mut h_r) } else { (&mut h_r, &mut h_i) };
+        let len = writer.write_message(b"hello", &mut buffer_msg).unwrap();
+        assert_eq!(reader.read_message(&buffer_msg[..len], &mut buffer_out).unwrap(), 5);
+    }
+    assert_eq!(h_i.get_handshake_hash(), h_r.get_handshake_hash());
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    assert_eq!(h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap(), 15);
+    assert_eq!(&buffer_out[..15], b"hack the planet");
+}
+
+#[cfg(feature = "cipher-ascon")]
+#[test]
+fn test_ascon() {
+    let params: NoiseParams = "Noise_XX_25519_Ascon128_SHA256".parse().unwrap();
+    assert_eq!(params.name, "Noise_XX_25519_Ascon128_SHA256");
+    let mut h_i =
+        Builder::new(params.clone()).local_private_key(&get_inc_key(0)).build_initiator().unwrap();
+    let mut h_r =
+        Builder::new(params).local_private_key(&get_inc_key(1)).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    while !(h_i.is_handshake_finished() && h_r.is_handshake_finished()) {
+        let (writer, reader) =
+            if h_i.is_my_turn() { (&mut h_i, &mut h_r) } else { (&mut h_r, &mut h_i) };
+        let len = writer.write_message(b"hello", &mut buffer_msg).unwrap();
+        assert_eq!(reader.read_message(&buffer_msg[..len], &mut buffer_out).unwrap(), 5);
+    }
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    // 13 bytes, so the last block is partial.
+    let len = h_i.write_message(b"hack the plan", &mut buffer_msg).unwrap();
+    assert_eq!(len, 13 + 16);
+    assert_eq!(h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap(), 13);
+    assert_eq!(&buffer_out[..13], b"hack the plan");
+    let len = h_r.write_message(b"bye", &mut buffer_msg).unwrap();
+    buffer_msg[len - 1] ^= 1;
+    assert!(matches!(
+        h_i.read_message(&buffer_msg[..len], &mut buffer_out),
+        Err(snow::Error::Decrypt)
+    ));
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "mobile")]
+
+use snow::mobile::*;
+
+#[test]
+fn test_mobile_handshake_and_transport() {
+    let params = "Noise_XX_25519_ChaChaPoly_BLAKE2s".to_string();
+    let keypair_i = NoiseBuilder::new(params.clone()).unwrap().generate_keypair().unwrap();
+    let keypair_r = NoiseBuilder::new(params.clone()).unwrap().generate_keypair().unwrap();
+
+    let h_i = NoiseBuilder::new(params.clone())
+        .unwrap()
+        .local_private_key(keypair_i.private)
+        .build_initiator()
+        .unwrap();
+    let h_r = NoiseBuilder::new(params)
+        .unwrap()
+        .local_private_key(keypair_r.private)
+        .build_responder()
+        .unwrap();
+
+    let msg = h_i.write_message(vec![]).unwrap();
+    h_r.read_message(msg).unwrap();
+    let msg = h_r.write_message(vec![]).unwrap();
+    h_i.read_message(msg).unwrap();
+    let msg = h_i.write_message(b"hi".to_vec()).unwrap();
+    assert_eq!(h_r.read_message(msg).unwrap(), b"hi");
+
+    assert!(h_i.is_handshake_finished());
+    assert_eq!(h_r.get_remote_static().unwrap(), Some(keypair_i.public));
+    assert_eq!(h_i.get_handshake_hash().unwrap(), h_r.get_handshake_hash().unwrap());
+
+    let t_i = h_i.into_transport_mode().unwrap();
+    let t_r = h_r.into_transport_mode().unwrap();
+    assert!(matches!(h_i.write_message(vec![]), Err(NoiseError::State(_))));
+
+    let msg = t_i.write_message(b"hack the planet".to_vec()).unwrap();
+    assert_eq!(t_r.read_message(msg.clone()).unwrap(), b"hack the planet");
+    assert!(matches!(t_r.read_message(msg), Err(NoiseError::Decrypt(_))));
+    assert_eq!(t_i.sending_nonce(), 1);
+}
+
+#[test]
+fn test_mobile_errors() {
+    assert!(matches!(NoiseBuilder::new("Noise_ZZ".to_string()), Err(NoiseError::Pattern(_))));
+
+    let builder = NoiseBuilder::new("Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s".to_string()).unwrap();
+    let err = builder.build_initiator().and_then(|h| h.write_message(vec![])).unwrap_err();
+    assert!(matches!(err, NoiseError::Prereq(_)));
+    assert_eq!(
+        err.to_string(),
+        "handshake message 0 failed at token psk0: missing prerequisite: psk0"
+    );
+
+    let h = builder.psk(0, vec![0u8; 32]).build_initiator().unwrap();
+    assert!(h.write_message(vec![]).is_ok());
+}